@@ -0,0 +1,62 @@
+// Embedding the simulator in another tokio program: build a small OSPF
+// ring, fail one of its links after a delay, wait for the network to
+// converge again and check that every router can still reach the others.
+//
+// Run with `cargo run --example embedded` (set RUST_LOG=info to see the
+// messages exchanged by the routers).
+
+use std::{collections::BTreeMap, net::Ipv4Addr, time::Duration};
+
+use network_simulator::prelude::*;
+
+const FAILURE_DELAY: Duration = Duration::from_millis(200);
+
+async fn build_ring(network: &mut Network) -> Result<(), NetworkError>{
+    for id in 1..=4{
+        network.add_router(&format!("r{}", id), id, 1)?;
+    }
+    // r1 -> r3 goes through r2 as long as the link r2-r3 is up
+    network.add_link("r1", 1, "r2", 1, 1).await?;
+    network.add_link("r2", 2, "r3", 1, 1).await?;
+    network.add_link("r3", 2, "r4", 1, 1).await?;
+    network.add_link("r4", 2, "r1", 2, 5).await?;
+    Ok(())
+}
+
+fn assert_fully_reachable(matrix: &BTreeMap<String, BTreeMap<String, bool>>){
+    for (from, destinations) in matrix{
+        for (to, reachable) in destinations{
+            assert!(reachable, "{} cannot reach {}", from, to);
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), NetworkError> {
+    let mut network = NetworkBuilder::new(Logger::start()).build();
+    build_ring(&mut network).await?;
+
+    let elapsed = network.wait_for_ospf(CONVERGENCE_TIMEOUT).await?;
+    println!("Converged in {:?}", elapsed);
+    assert_fully_reachable(&network.reachability().await?);
+    let before = network.traceroute("r1", Ipv4Addr::new(10, 0, 1, 3)).await?;
+    println!("r1 -> r3 before the failure: {:?}", before);
+
+    // scheduled failure of the link r2-r3
+    tokio::time::sleep(FAILURE_DELAY).await;
+    network.remove_link("r2", 2, "r3", 1).await?;
+
+    network.wait_for_ospf(CONVERGENCE_TIMEOUT).await?;
+    assert_fully_reachable(&network.reachability().await?);
+    let after = network.traceroute("r1", Ipv4Addr::new(10, 0, 1, 3)).await?;
+    println!("r1 -> r3 after the failure: {:?}", after);
+    assert_eq!(after, vec![Ipv4Addr::new(10, 0, 1, 4), Ipv4Addr::new(10, 0, 1, 3)]);
+
+    match network.ping("r1", Ipv4Addr::new(10, 0, 1, 3)).await{
+        Ok(rtt) => println!("Ping from r1 to r3: answer received in {:?}", rtt),
+        Err(e) => println!("Ping from r1 to r3 failed: {}", e),
+    }
+
+    network.quit().await;
+    Ok(())
+}
@@ -1 +1,35 @@
-pub mod network;
\ No newline at end of file
+pub mod network;
+
+// everything needed to build a network, query it and check its state
+pub mod prelude{
+    pub use crate::network::{
+        BgpLinkAttributes,
+        Network,
+        builder::NetworkBuilder,
+        capture::{Capture, CaptureRecord, Direction, Protocol},
+        config::ConfigFile,
+        error::{NetworkError, PingError},
+        events::{wait_for_event, NetworkEvent},
+        health::{DeviceHealth, InterfaceState, PortInfo, PortKind, RouterHealth, SessionHealth},
+        ip_prefix::IPPrefix,
+        limits::{Limits, MemoryStats},
+        link::{LinkImpairment, LinkRate, LinkStats, QueueDiscipline, RateLimit},
+        logger::{LogHandle, Logger, Source},
+        logparse::LogRecord,
+        output::OutputMode,
+        rib::{ForwardingHop, RIBEntry, RouteOrigin, StaticNexthop},
+        protocols::policy::{PolicyAction, PolicyRule, RoutePolicy},
+        protocols::ospf::{LsaInfo, Lsdb},
+        protocols::bgp::{BGPRoute, BGPSession, CommunityAction, MaxPrefixAction, Origin, RouteSource, SessionState, DEFAULT_LOCAL_PREF},
+        scenario::{compare_scenarios, load_config, run_to_convergence, BGP_CONVERGENCE, CONVERGENCE_TIMEOUT, IGP_CONVERGENCE},
+        state::{BGPTableEntry, Difference, NetworkState},
+        stats::{DeviceStats, MessageKind},
+        switch::PortState,
+        timers::TimerConfig,
+        topology::Topology,
+        traffic::{FlowStats, PingReport},
+        utils::MacAddress,
+    };
+}
+
+pub use prelude::*;
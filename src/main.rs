@@ -1,268 +1,212 @@
 
 pub mod network;
 
-use std::{collections::HashMap, env, fs, thread, time::Duration};
+use std::{io::{self, IsTerminal}, str::FromStr, sync::Arc, time::Duration};
 
-use network::logger::{Logger, Source};
-use strum::IntoEnumIterator;
+use clap::{Args, Parser, Subcommand};
+use log::LevelFilter;
+use network::{config::{ConfigFile, LogCfg}, error::NetworkError, examples, health::DeviceHealth, logger::{Logger, Source}, output::OutputMode, repl, scenario, schedule};
+use tokio::{sync::RwLock, time::Instant};
 
 use self::network::Network;
 
-use serde_yaml::{self, Value};
-
-fn generate_routers(network: &mut Network, config: &Value){
-    let routers = &config["network"]["routers"];
-
-    if routers.is_null(){
-        return;
-    }
-
-    for router in routers.as_sequence().expect("Invalid format, routers config should be a list"){
-        let name = router["name"].as_str().expect("name should be an string");
-        let id = &router["id"].as_u64().expect("id should be an integer");
-        let router_as = &router["AS"].as_u64().expect("AS should be an integer");
-        network.add_router(name, *id as u32, *router_as as u32);
-
-        println!("Added router {} with id {} in AS {}", name, id, router_as);
-    }
+#[derive(Parser)]
+#[command(name = "network-simulator", about = "Simulates the routers, switches and hosts of the network described by a configuration file")]
+struct Cli{
+    #[command(subcommand)]
+    command: Command
 }
 
-fn generate_switchs(network: &mut Network, config: &Value){
-    let switches = &config["network"]["switches"];
-
-    if switches.is_null(){
-        return;
-    }
-
-    for switch in switches.as_sequence().expect("Invalid format, switches config should be a list"){
-        let name = &switch["name"].as_str().expect("name should be an string");
-        let id = &switch["id"].as_u64().expect("id should be an integer");
-        network.add_switch(name, *id as u32);
-
-        println!("Added switch {} with id {}", name, id);
-    }
+#[derive(Subcommand)]
+enum Command{
+    /// Builds the network of a configuration file and runs its actions and events
+    Run(RunArgs),
+    /// Checks a configuration file and its topology without starting the devices
+    Validate{config: String},
+    /// Prints the DOT graph of the topology of a configuration file, without running its scenario
+    Graph{config: String},
+    /// Runs two scenarios until convergence and lists the differences between their final states
+    Compare{
+        a: String,
+        b: String,
+        /// Exit with a non-zero status when the states differ
+        #[arg(long)]
+        expect_identical: bool,
+    },
+    /// Prints the grammar of the configuration files
+    Schema,
+    /// Prints a ready-to-run scenario, or writes it in a file
+    Example{name: String, file: Option<String>},
 }
 
-async fn generate_links(network: &mut Network, config: &Value){
-    let links = &config["network"]["links"];
-
-    if links.is_null(){
-        return;
-    }
-
-    let mut highest_port = HashMap::new();
+#[derive(Args)]
+struct RunArgs{
+    config: String,
+    /// Sources of the logs, instead of the ones of the configuration (e.g. ospf,bgp)
+    #[arg(long, value_delimiter = ',', value_parser = Source::from_str)]
+    log: Option<Vec<Source>>,
+    /// Save the DOT graph of the network in this file once the events ran
+    #[arg(long)]
+    dot: Option<String>,
+    /// Skip the events after this time and keep the network running until then
+    #[arg(long)]
+    duration_ms: Option<u64>,
+    /// Seed of the faults of all the links, instead of the ones of the configuration
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Print neither the steps of the construction of the network nor the logs
+    #[arg(long)]
+    quiet: bool,
+    /// Render the tables as aligned tables, with colors on a terminal
+    #[arg(long)]
+    pretty: bool,
+    #[arg(long)]
+    no_color: bool,
+    /// Read commands from stdin once the events ran, until quit
+    #[arg(long)]
+    interactive: bool,
+    /// Serve the commands of the interactive mode over TCP on this address once the events ran, until Ctrl-C
+    #[arg(long, conflicts_with = "interactive")]
+    control: Option<String>,
+}
 
-    let internal = &links["internal"];
-    if ! internal.is_null(){
-        for link in internal.as_sequence().expect("Internal links should be a list"){
-            let l = link.as_sequence().expect("Error parsing the two routers/switches of the link");
-            let r1 = l[0].as_str().expect("Router/Switch name in link should be a string");
-            let r2 = l[1].as_str().expect("Router/Switch name in link should be a string");
-            let port1 = highest_port.entry(r1).or_insert(1);
-            let port1_saved = *port1;
-            *port1 += 1;
-            let port2 = highest_port.entry(r2).or_insert(1);
-            let port2_saved = *port2;
-            *port2 += 1;
-            
-            let cost = 
-                l.get(2)
-                .unwrap_or(&Value::Number(1.into()))
-                .as_u64()
-                .expect("Cost should be an int");
-    
-            println!("Link from {}:{} to {}:{} added with cost {}", r1, port1_saved, r2, port2_saved, cost);
-            network.add_link(r1, port1_saved, r2, port2_saved, cost as u32).await;
+async fn compare(a: &str, b: &str, expect_identical: bool) -> i32{
+    let configs = scenario::load_config(a).and_then(|config_a| Ok((config_a, scenario::load_config(b)?)));
+    let differences = match configs{
+        Ok((config_a, config_b)) => scenario::compare_scenarios(&config_a, &config_b).await,
+        Err(error) => Err(error),
+    };
+    let differences = match differences{
+        Ok(differences) => differences,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            return 2;
         }
+    };
+    if differences.is_empty(){
+        println!("Scenarios {} and {} converge to identical states", a, b);
+        return 0;
     }
-
-
-    let bgp = &links["bgp"];
-    if bgp.is_null(){
-        return;
+    println!("Differences between a={} and b={}:", a, b);
+    for difference in differences{
+        println!("  {}", difference);
     }
+    if expect_identical { 1 } else { 0 }
+}
 
-    let provider_customers = &bgp["provider-customer"];
-    if !provider_customers.is_null(){
-        for link in provider_customers.as_sequence().expect("BGP links should be a list"){
-            let provider = link["provider"].as_str().expect("Provider name in link should be a string");
-            let customer = link["customer"].as_str().expect("Customer name in link should be a string");
-            let port1 = highest_port.entry(provider).or_insert(1);
-            let port1_saved = *port1;
-            *port1 += 1;
-            let port2 = highest_port.entry(customer).or_insert(1);
-            let port2_saved = *port2;
-            *port2 += 1;
-            
-            let med = 
-                link.get("med")
-                .unwrap_or(&Value::Number(1.into()))
-                .as_u64()
-                .expect("MED should be an int");
-    
-            println!("BGP link from provider {}:{} to customer {}:{} added with med {}", provider, port1_saved, customer, port2_saved, med);
-            network.add_provider_customer_link(provider, port1_saved, customer, port2_saved, med as u32).await;
-        }
-    }
+fn example(name: &str, file: Option<&str>) -> i32{
+    let Some(yaml) = examples::example(name) else {
+        let names: Vec<&str> = examples::EXAMPLES.iter().map(|(name, _)| *name).collect();
+        eprintln!("Unknown example {}, available examples are [{}]", name, names.join(", "));
+        return 2;
+    };
+    match file{
+        Some(file) => {
+            std::fs::write(file, yaml).expect("Failed to write the example");
+            println!("Example {} written to {}", name, file);
+        },
+        None => print!("{}", yaml),
+    }
+    0
+}
 
-    let peers = &bgp["peer"];
-    if !peers.is_null(){
-        for link in peers.as_sequence().expect("BGP links should be a list"){
-            let l = link.as_sequence().expect("Error parsing the two routers/switches of the link");
-            let r1 = l[0].as_str().expect("Router/Switch name in link should be a string");
-            let r2 = l[1].as_str().expect("Router/Switch name in link should be a string");
-            let port1 = highest_port.entry(r1).or_insert(1);
-            let port1_saved = *port1;
-            *port1 += 1;
-            let port2 = highest_port.entry(r2).or_insert(1);
-            let port2_saved = *port2;
-            *port2 += 1;
-            
-            let med = 
-                l.get(2)
-                .unwrap_or(&Value::Number(1.into()))
-                .as_u64()
-                .expect("MED should be an int");
-    
-            println!("Peer link from {}:{} to {}:{} added with med {}", r1, port1_saved, r2, port2_saved, med);
-            network.add_peer_link(r1, port1_saved, r2, port2_saved, med as u32).await;
-        }
+fn get_logger(args: &RunArgs, config: &ConfigFile) -> Logger{
+    if args.quiet{
+        return Logger::start_with_level(LevelFilter::Off, vec![]);
     }
-
-    let ibgp = &bgp["ibgp"];
-    if !ibgp.is_null(){
-        for link in ibgp.as_sequence().expect("BGP links should be a list"){
-            let l = link.as_sequence().expect("Error parsing the two routers/switches of the ibgp session");
-            let r1 = l[0].as_str().expect("Router/Switch name in ibgp should be a string");
-            let r2 = l[1].as_str().expect("Router/Switch name in ibgp should be a string");
-    
-            println!("IBGP session added between {} and {}", r1, r2);
-            network.add_ibgp_connection(r1, r2).await;
-        }
+    match (&args.log, &config.network.config.log){
+        (Some(sources), _) | (None, Some(LogCfg(sources))) => Logger::start_with_filters(sources.clone()),
+        (None, None) => Logger::start(),
     }
 }
 
-async fn actions_first_round(network: &mut Network, config: &Value){
-    let actions = &config["network"]["actions"];
-    if actions.is_null(){
-        return;
+async fn run(args: &RunArgs) -> Result<(), NetworkError>{
+    let mut config = scenario::load_config(&args.config)?;
+    if let Some(seed) = args.seed{
+        config.set_impairment_seed(seed);
     }
-    let announces = &actions["announce_prefix"];
-    if !announces.is_null(){
-        for announce in announces.as_sequence().expect("Announce prefix should be a list"){
-            if announce.is_u64(){
-                let announce = announce.as_u64().unwrap();
-                network.announce_prefix_as(announce as u32).await;
-            }else if announce.is_string(){
-                let announce = announce.as_str().unwrap();
-                network.announce_prefix(announce).await;
-            }
-        }
-    }
-    let print_routing_tables = &actions["print_routing_tables"];
-    if !print_routing_tables.is_null(){
-        println!("Routing tables:");
-        network.print_routing_tables().await;
-        println!("");
-    }
-    let print_port_states = &actions["print_port_states"];
-    if !print_port_states.is_null(){
-        println!("Switch port states:");
-        network.print_switch_states().await;
-        println!("");
+    let mut events = schedule::load_events(&config)?;
+    let duration = args.duration_ms.map(Duration::from_millis);
+    if let Some(duration) = duration{
+        events.retain(|event| event.time <= duration);
     }
-}
 
-async fn actions_second_round(network: &mut Network, config: &Value){
-    let actions = &config["network"]["actions"];
-    if actions.is_null(){
-        return;
-    }
-    let print_bgp_tables = &actions["print_bgp_tables"];
-    if !print_bgp_tables.is_null(){
-        println!("BGP tables:");
-        network.print_bgp_tables().await;
-        println!("");
+    let logger = get_logger(args, &config);
+    let mut network = Network::new(logger.clone());
+    if args.pretty{
+        let color = !args.no_color && io::stdout().is_terminal();
+        network.set_output_mode(OutputMode::Pretty{color});
     }
-    let pings = &actions["ping"];
-    if !pings.is_null(){
-        let pings = pings.as_sequence().expect("Pings should be a list");
-        for ping in pings{
-            let from = ping["from"].as_str().expect("From should be a router name");
-            let to = ping["to"].as_str().expect("To should be an ip address");
-            network.ping(from, to.parse().expect("Failed to parse IP address")).await;
+    let start = Instant::now();
+    for step in scenario::build_network(&mut network, &config).await?{
+        if !args.quiet{
+            println!("{}", step);
         }
     }
-    let dot_graph_file = &actions["dot_graph_file"];
-    if !dot_graph_file.is_null(){
-        let filename = dot_graph_file.as_str().expect("Dot filename should be a string");
-        let dot_repr = network.dot_representation().await;
-        fs::write(filename, dot_repr).expect("Failed to write dot representation in file");
+    schedule::run_events(&mut network, &events).await?;
+    if let Some(duration) = duration{
+        tokio::time::sleep_until(start + duration).await;
+    }
+    if args.interactive{
+        let input = tokio::io::BufReader::new(tokio::io::stdin());
+        repl::run(&mut network, input, io::stdin().is_terminal()).await.expect("Failed to read the commands");
+    }
+    if let Some(file) = &args.dot{
+        let dot_repr = network.dot_representation().await?;
+        std::fs::write(file, dot_repr).map_err(|e| NetworkError::Io(format!("cannot write the DOT graph to {}: {}", file, e)))?;
+    }
+    if let Some(addr) = &args.control{
+        let shared = Arc::new(RwLock::new(network));
+        let addr = Network::serve_control(shared.clone(), addr).await.expect("Failed to start the control server");
+        println!("Control server listening on {}", addr);
+        tokio::signal::ctrl_c().await.expect("Failed to wait for Ctrl-C");
+        // the server keeps its handle on the network, the clients still connected are left with an empty one
+        network = std::mem::replace(&mut *shared.write().await, Network::new(logger));
+    }
+    for (device, health) in network.device_health().await{
+        if let DeviceHealth::Panicked(message) = health{
+            eprintln!("Device {} panicked: {}", device, message);
+        }
     }
+    network.quit().await;
+    Ok(())
 }
 
-fn get_logger(config: &Value) -> Logger{
-
-    let config = &config["network"]["config"];
-    if config.is_null(){
-        return Logger::start();
-    }
-    let logs = &config["log"];
-    if logs.is_null(){
-        return Logger::start();
-    }
-    env::set_var("RUST_LOG", "debug");
-    let mut logs_sources = vec![];
-    for source in logs.as_sequence().expect("Logs should be a list"){
-        let source = source.as_str().expect("Source should be a string");
-        let source = match source{
-            "OSPF" => Source::OSPF,
-            "SPT" => Source::SPT,
-            "PING" => Source::PING,
-            "DEBUG" => Source::DEBUG,
-            "IP" => Source::IP,
-            "BGP" => Source::BGP,
-            "ARP" => Source::ARP,
-            s => {
-                let sources: Vec<String> = Source::iter().map(|s| s.to_string()).collect();
-                panic!("Unknown source {}, supported sources are [{}]", s, sources.join(", "));
-            }
-        };
-        logs_sources.push(source);
-    }
-    Logger::start_with_filters(logs_sources)
+fn validate(file: &str) -> Result<(), NetworkError>{
+    let config = scenario::load_config(file)?;
+    scenario::validate(&config)?;
+    let network = &config.network;
+    let links = network.links.internal.len() + network.links.bgp.provider_customer.len() + network.links.bgp.peer.len();
+    println!("{} is valid: {} routers, {} switches, {} hosts and {} links",
+        file, network.routers.len(), network.switches.len(), network.hosts.len(), links);
+    Ok(())
 }
 
+// drawn from the config, the devices aren't started
+fn graph(file: &str) -> Result<(), NetworkError>{
+    let config = scenario::load_config(file)?;
+    scenario::validate(&config)?;
+    print!("{}", scenario::topology(&config)?.dot_string());
+    Ok(())
+}
 
 #[tokio::main]
 async fn main() -> Result<(), ()> {
-    
-    let file = std::env::args().nth(1).expect("Filename for configuration required");
-    let f = std::fs::File::open(file).expect("File doesn't exists");
-    let config: Value = serde_yaml::from_reader(f).expect("Error in yaml file");
-
-    let logger = get_logger(&config);
-    let mut network = Network::new(logger);
-
-    generate_routers(&mut network, &config);
-    generate_switchs(&mut network, &config);
-    generate_links(&mut network, &config).await;
-    
-    // wait for convergence of IGP
-    thread::sleep(Duration::from_millis(1000));
-
-    actions_first_round(&mut network, &config).await;
-
-    // wait for convergence of BGP
-    thread::sleep(Duration::from_millis(2000));
-    
-    actions_second_round(&mut network, &config).await;
-
-    // wait for pings
-    thread::sleep(Duration::from_millis(1000));
-
-    network.quit().await;
-
-    env::remove_var("RUST_LOG");
+    let cli = Cli::parse();
+    let (file, result) = match &cli.command{
+        Command::Run(args) => (&args.config, run(args).await),
+        Command::Validate{config} => (config, validate(config)),
+        Command::Graph{config} => (config, graph(config)),
+        Command::Compare{a, b, expect_identical} => std::process::exit(compare(a, b, *expect_identical).await),
+        Command::Schema => {
+            print!("{}", examples::schema());
+            return Ok(());
+        },
+        Command::Example{name, file} => std::process::exit(example(name, file.as_deref())),
+    };
+    if let Err(error) = result{
+        eprintln!("Error in {}: {}", file, error);
+        std::process::exit(1);
+    }
     Ok(())
 }
@@ -1,39 +1,143 @@
-pub mod communicators;
+pub mod builder;
+pub mod capture;
+pub mod config;
+pub mod control;
+pub(crate) mod communicators;
+pub mod error;
+pub mod events;
+pub mod examples;
+pub mod generator;
+pub mod health;
+pub(crate) mod host;
+pub mod limits;
+pub mod link;
 pub mod logger;
-pub mod messages;
-pub mod protocols;
-pub mod ip_trie;
-pub mod router;
-pub mod switch;
-pub mod utils;
+pub mod logparse;
+pub(crate) mod messages;
+pub mod output;
+pub mod repl;
+pub mod rib;
+pub mod scenario;
+pub mod schedule;
+pub mod state;
+pub mod stats;
+pub mod timers;
+pub mod topology;
+pub mod traffic;
+pub(crate) mod protocols;
+pub(crate) mod ip_trie;
+pub(crate) mod router;
+pub(crate) mod switch;
+pub(crate) mod utils;
 pub mod ip_prefix;
-pub mod graphviz;
-use graphviz::{EdgeOption, Graph, GraphOption, NodeOption};
+pub(crate) mod graphviz;
+use config::IbgpSessionCfg;
+use error::{NetworkError, PingError};
+use events::{EventSender, NetworkEvent};
+use health::{DeviceHealth, PortInfo, RouterHealth};
 use ip_prefix::IPPrefix;
-use logger::Logger;
-use protocols::bgp::BGPRoute;
+use ip_trie::IPTrie;
+use limits::{Limits, MemoryStats};
+use rib::{ForwardingHop, RIBEntry, StaticNexthop};
+use logger::{Logger, Source};
+use output::OutputMode;
+use state::{BGPTableEntry, NetworkState};
+use stats::DeviceStats;
+use timers::TimerConfig;
+use topology::Topology;
+use traffic::{FlowStats, PingReport, TrafficGen};
+use protocols::{bgp::{BGPRoute, BGPSession, CommunityAction, MaxPrefixAction, Origin, DEFAULT_LOCAL_PREF}, ospf::Lsdb, policy::RoutePolicy};
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     net::Ipv4Addr,
+    sync::Arc,
+    time::{Duration, Instant},
     vec,
 };
 use switch::PortState;
-use tokio::sync::mpsc::channel;
+use messages::Message;
+use capture::{Capture, CaptureSlot, CaptureWriter, Direction};
+use link::{carry_link, LinkControl, LinkImpairment, LinkSender, LinkStats, RateLimit};
+use utils::MacAddress;
+use tokio::{sync::{broadcast, mpsc::Receiver, Semaphore}, task::JoinHandle};
 
-use self::communicators::{RouterCommunicator, SwitchCommunicator};
-use self::router::Router;
+use self::communicators::{EqualCostPorts, HostCommunicator, MultipathRoutes, RouterCommunicator, SwitchCommunicator};
+use self::host::Host;
+use self::router::{Router, RouterConfig};
 use self::switch::Switch;
 
+type LinkChannel = (LinkSender, Receiver<Message>); // one direction of a link
+type BgpLink = (String, u32, String, u32, (u32, (Option<u32>, Option<u32>))); // ends, med and prefs of the routers
+
+// Settings of a BGP link besides its ends
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BgpLinkAttributes{
+    pub med: u32,
+    pub prefs: (Option<u32>, Option<u32>), // pref of the first router for the routes of the second, and the reverse
+    pub latency: Duration // of the messages in each direction
+}
+
+// pings of ping_many in flight at the same time
+const MAX_PARALLEL_PINGS: usize = 16;
+
+// time between two checks of the routers while waiting for their convergence
+const CONVERGENCE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// longest wait for the devices to stop once told to quit
+const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// a router stopped by crash_router, restarted on the same links by restart_router
+#[derive(Debug)]
+struct CrashedRouter{
+    id: u32,
+    router_as: u32,
+    internal_links: Vec<(u32, String, u32, u32)>,
+    provider_customer: Vec<BgpLink>,
+    peers: Vec<BgpLink>,
+    ibgp_sessions: Vec<IbgpSessionCfg>,
+}
+
+// egress links and ends of the paths towards a destination, highlighted in the DOT graph
+#[derive(Default)]
+struct ForwardingView{
+    egress: HashSet<(String, u32)>, // router and port the packets leave on
+    destinations: HashSet<String>,
+    unreachable: HashSet<String>,
+}
+
+// what the DOT graph shows on top of the topology
+enum Highlight{
+    Forwarding(ForwardingView),
+    SpanningTree{root: Option<String>}, // drawn from the port states given with it
+}
+
 #[derive(Debug)]
 pub struct Network {
-    switches: BTreeMap<String, SwitchCommunicator>,
+    switches: BTreeMap<String, (SwitchCommunicator, u32)>, // communicator, id
     routers: BTreeMap<String, (RouterCommunicator, Ipv4Addr)>,
+    hosts: BTreeMap<String, (HostCommunicator, Ipv4Addr, Ipv4Addr)>, // communicator, ip, gateway
     used_port: BTreeMap<String, HashSet<u32>>,
+    stale_ports: BTreeMap<String, HashSet<u32>>, // ports of the neighbors of stopped devices, cleared when they are reused
     internal_links: HashMap<String, Vec<(u32, String, u32, u32)>>,
-    provider_customer: Vec<(String, u32, String, u32, u32)>,
-    peers: Vec<(String, u32, String, u32, u32)>,
+    provider_customer: Vec<BgpLink>,
+    peers: Vec<BgpLink>,
+    ibgp_sessions: Vec<IbgpSessionCfg>,
     router_as: HashMap<u32, Vec<String>>,
     as_router: HashMap<String, u32>,
+    output_mode: OutputMode,
+    limits: Limits,
+    bpdu_max_age: Duration,
+    dead_interval: Duration,
+    timers: TimerConfig,
+    traffic: TrafficGen,
+    links: HashMap<(String, u32), [Arc<LinkControl>; 2]>, // both ends of a link -> its directions
+    ospf_disabled: HashSet<String>, // routers whose routes aren't expected to be learned by OSPF
+    crashed: BTreeMap<String, CrashedRouter>,
+    capture: CaptureSlot,
+    capture_task: Option<JoinHandle<()>>,
+    tasks: Vec<(String, JoinHandle<()>)>, // of the devices, awaited when the network quits
+    panicked: BTreeMap<String, String>,   // message of the devices whose task panicked
+    events: EventSender, // cloned by the routers and switches when they start
     logger: Logger,
 }
 
@@ -42,47 +146,344 @@ impl Network {
         Network {
             switches: BTreeMap::new(),
             routers: BTreeMap::new(),
+            hosts: BTreeMap::new(),
             used_port: BTreeMap::new(),
+            stale_ports: BTreeMap::new(),
             internal_links: HashMap::new(),
             provider_customer: vec![],
             peers: vec![],
+            ibgp_sessions: vec![],
             router_as: HashMap::new(),
             as_router: HashMap::new(),
+            output_mode: OutputMode::Plain,
+            limits: Limits::default(),
+            bpdu_max_age: switch::BPDU_MAX_AGE,
+            dead_interval: protocols::ospf::DEAD_INTERVAL,
+            timers: TimerConfig::default(),
+            traffic: TrafficGen::default(),
+            links: HashMap::new(),
+            ospf_disabled: HashSet::new(),
+            crashed: BTreeMap::new(),
+            capture: CaptureSlot::default(),
+            capture_task: None,
+            tasks: vec![],
+            panicked: BTreeMap::new(),
+            events: EventSender::new(events::EVENT_CAPACITY),
             logger,
         }
     }
 
     pub fn add_switch(&mut self, name: &str, id: u32) {
-        let communicator = Switch::start(name.to_string(), id, self.logger.clone());
-        self.switches.insert(name.to_string(), communicator);
+        self.add_switch_with_config(name, id, self.timers.clone());
+    }
+
+    pub fn add_switch_with_config(&mut self, name: &str, id: u32, timers: TimerConfig) {
+        let (communicator, task) = Switch::start(name.to_string(), id, self.bpdu_max_age, timers, self.events.clone(), self.logger.for_device(name));
+        self.switches.insert(name.to_string(), (communicator, id));
+        self.track_task(name, task);
         self.used_port.insert(name.to_string(), HashSet::new());
     }
 
-    pub fn add_router(&mut self, name: &str, id: u32, router_as: u32) {
-        let communicator = Router::start(name.to_string(), id, router_as, self.logger.clone());
+    pub fn add_router(&mut self, name: &str, id: u32, router_as: u32) -> Result<(), NetworkError> {
+        self.add_router_with_config(name, id, router_as, self.timers.clone())
+    }
+
+    pub fn add_router_with_config(&mut self, name: &str, id: u32, router_as: u32, timers: TimerConfig) -> Result<(), NetworkError> {
+        let ip = self.router_address(name, id, router_as)?;
+        let (communicator, task) = Router::start(name.to_string(), id, router_as, ip, RouterConfig{limits: self.limits.clone(), dead_interval: self.dead_interval, timers}, self.events.clone(), self.logger.for_device(name));
         self.used_port.insert(name.to_string(), HashSet::new());
-        self.routers.insert(
-            name.to_string(),
-            (
-                communicator,
-                Ipv4Addr::new(10, 0, router_as as u8, id as u8),
-            ),
-        );
+        self.ospf_disabled.remove(name);
+        // a router added under the name of a crashed one replaces it
+        self.crashed.remove(name);
+        self.routers.insert(name.to_string(), (communicator, ip));
+        self.track_task(name, task);
         self.router_as.entry(router_as).or_insert(vec![]).push(name.to_string());
         self.as_router.insert(name.to_string(), router_as);
+        Ok(())
+    }
+
+    // the host sends the packets for all the addresses to its gateway, a router of its LAN
+    pub fn add_host(&mut self, name: &str, ip: Ipv4Addr, gateway: Ipv4Addr) {
+        let (communicator, task) = Host::start(name.to_string(), ip, gateway, self.timers.clone(), self.logger.for_device(name));
+        self.used_port.insert(name.to_string(), HashSet::new());
+        self.hosts.insert(name.to_string(), (communicator, ip, gateway));
+        self.track_task(name, task);
+    }
+
+    // the task of a device replacing one of the same name, whose end doesn't matter anymore
+    fn track_task(&mut self, name: &str, task: JoinHandle<()>) {
+        self.tasks.retain(|(device, task)| device != name || !task.is_finished());
+        self.panicked.remove(name);
+        self.tasks.push((name.to_string(), task));
+    }
+
+    // the router id of AS x.y (x * 256 + y) has the address 10.x.y.id, e.g. 10.0.1.2 for router 2 of AS 1
+    fn router_address(&self, name: &str, id: u32, router_as: u32) -> Result<Ipv4Addr, NetworkError> {
+        if id > 255 || router_as > 65535{
+            return Err(NetworkError::AddressOutOfRange{device: name.to_string(), id, router_as});
+        }
+        let ip = Ipv4Addr::new(10, (router_as >> 8) as u8, router_as as u8, id as u8);
+        match self.routers.iter().find(|(_, (_, other_ip))| *other_ip == ip){
+            Some((other, _)) => Err(NetworkError::DuplicateAddress{device: name.to_string(), ip, other: other.clone()}),
+            None => Ok(ip),
+        }
+    }
+
+    // the events of the routers and switches emitted from now on, in addition to their logs
+    pub fn subscribe_events(&self) -> broadcast::Receiver<NetworkEvent>{
+        self.events.subscribe()
+    }
+
+    pub fn set_output_mode(&mut self, mode: OutputMode){
+        self.output_mode = mode;
+    }
+
+    // timers of the devices added afterwards with add_router or add_switch
+    pub fn set_timers(&mut self, timers: TimerConfig){
+        self.timers = timers;
+    }
+
+    // applies to the existing routers and to the ones added afterwards
+    pub async fn set_limits(&mut self, limits: Limits){
+        for (router, _) in self.routers.values(){
+            router.set_limits(limits.clone()).await;
+        }
+        self.limits = limits;
+    }
+
+    // applies to the existing routers and to the ones added afterwards
+    pub async fn set_dead_interval(&mut self, dead_interval: Duration){
+        for (router, _) in self.routers.values(){
+            router.set_dead_interval(dead_interval).await;
+        }
+        self.dead_interval = dead_interval;
+    }
+
+    // applies to the existing switches and to the ones added afterwards
+    pub async fn set_bpdu_max_age(&mut self, max_age: Duration){
+        for (switch, _) in self.switches.values(){
+            switch.set_max_age(max_age).await;
+        }
+        self.bpdu_max_age = max_age;
+    }
+
+    // only the messages of the sources and of the devices are logged from now on, empty lists log everything
+    pub async fn set_log_filters(&self, sources: Vec<Source>, devices: Vec<String>) -> Result<(), NetworkError> {
+        if let Some(device) = devices.iter().find(|device| !self.used_port.contains_key(*device)){
+            return Err(NetworkError::UnknownDevice(device.clone()));
+        }
+        self.logger.set_filters(sources).await;
+        self.logger.set_device_filters(devices).await;
+        Ok(())
+    }
+
+    pub async fn memory_stats(&self, router: &str) -> Result<MemoryStats, NetworkError> {
+        let src = &self.router(router)?.0;
+
+        src.get_memory_stats()
+            .await
+            .map_err(|_| NetworkError::DeviceDead(router.to_string()))
+    }
+
+    pub async fn health(&self, router: &str) -> Result<RouterHealth, NetworkError> {
+        let src = &self.router(router)?.0;
+
+        src.get_health()
+            .await
+            .map_err(|_| NetworkError::DeviceDead(router.to_string()))
+    }
+
+    // kind of link behind each port of the router, with its cost or the local pref of its routes
+    pub async fn get_router_ports(&self, router: &str) -> Result<BTreeMap<u32, PortInfo>, NetworkError> {
+        let src = &self.router(router)?.0;
+
+        src.get_ports()
+            .await
+            .map_err(|_| NetworkError::DeviceDead(router.to_string()))
+    }
+
+    // messages sent and received by a router or a switch, on each of its ports
+    pub async fn get_stats(&self, device: &str) -> Result<DeviceStats, NetworkError> {
+        let stats = match (self.routers.get(device), self.switches.get(device)){
+            (Some((router, _)), _) => router.get_stats().await,
+            (_, Some((switch, _))) => switch.get_stats().await,
+            _ if self.hosts.contains_key(device) => return Err(NetworkError::InvalidConfig(format!("only routers and switches count their messages, {} is a host", device))),
+            _ => return Err(NetworkError::UnknownDevice(device.to_string())),
+        };
+        stats.map_err(|_| NetworkError::DeviceDead(device.to_string()))
+    }
+
+    pub async fn print_stats(&self) -> Result<(), NetworkError> {
+        let mut stats = BTreeMap::new();
+        for device in self.routers.keys().chain(self.switches.keys()) {
+            stats.insert(device.clone(), self.get_stats(device).await?);
+        }
+        print!("{}", output::render_stats(&stats, self.output_mode));
+        Ok(())
     }
 
     pub fn routers(&self) -> Vec<String>{
         self.routers.keys().map(|r| r.clone()).into_iter().collect()
     }
 
-    pub fn check_port_not_used(&mut self, device: &str, port: u32){
-        let ports = self.used_port.get_mut(device).unwrap();
-        if ports.contains(&port){
-            panic!("Port {} is already used for device {}", port, device);
-        }else{
-            ports.insert(port);
+    fn router(&self, name: &str) -> Result<&(RouterCommunicator, Ipv4Addr), NetworkError>{
+        match self.routers.get(name){
+            Some(router) => Ok(router),
+            None if self.switches.contains_key(name) || self.hosts.contains_key(name) => Err(NetworkError::NotARouter(name.to_string())),
+            None => Err(NetworkError::UnknownDevice(name.to_string())),
+        }
+    }
+
+    // same as router, for the devices named in the bgp configuration
+    fn bgp_router(&self, name: &str) -> Result<&(RouterCommunicator, Ipv4Addr), NetworkError>{
+        self.router(name).map_err(|e| match e{
+            NetworkError::NotARouter(device) => NetworkError::SwitchInBGP(device),
+            e => e,
+        })
+    }
+
+    fn check_port_not_used(&mut self, device: &str, port: u32) -> Result<(), NetworkError>{
+        let ports = self.used_port.get_mut(device).ok_or(NetworkError::UnknownDevice(device.to_string()))?;
+        if !ports.insert(port){
+            return Err(NetworkError::PortInUse{device: device.to_string(), port});
+        }
+        Ok(())
+    }
+
+    fn release_port(&mut self, device: &str, port: u32){
+        if let Some(ports) = self.used_port.get_mut(device){
+            ports.remove(&port);
+        }
+        self.links.remove(&(device.to_string(), port));
+    }
+
+    async fn reserve_ports(&mut self, device1: &str, port1: u32, device2: &str, port2: u32) -> Result<(), NetworkError>{
+        self.check_port_not_used(device1, port1)?;
+        if let Err(e) = self.check_port_not_used(device2, port2){
+            self.release_port(device1, port1);
+            return Err(e);
+        }
+        // the device still holds the link to a stopped neighbor on this port
+        for (device, port) in [(device1, port1), (device2, port2)]{
+            if self.stale_ports.get_mut(device).is_some_and(|ports| ports.remove(&port)){
+                self.remove_port(device, port).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn next_free_port(&self, device: &str) -> Result<u32, NetworkError>{
+        self.next_free_port_except(device, &HashSet::new())
+    }
+
+    // lowest port of the device that is neither used nor kept for a link added later
+    pub fn next_free_port_except(&self, device: &str, reserved: &HashSet<u32>) -> Result<u32, NetworkError>{
+        let ports = self.used_port.get(device).ok_or(NetworkError::UnknownDevice(device.to_string()))?;
+        let mut port = 1;
+        while ports.contains(&port) || reserved.contains(&port){
+            port += 1;
+        }
+        Ok(port)
+    }
+
+    // both directions of a link, the messages are received latency after they were sent
+    fn link_channels(&mut self, device1: &str, port1: u32, device2: &str, port2: u32, latency: Duration) -> (LinkChannel, LinkChannel){
+        let (end1, end2) = ((device1.to_string(), port1), (device2.to_string(), port2));
+        let controls = [
+            Arc::new(LinkControl::new(end2.clone(), end1.clone(), Arc::clone(&self.capture), self.logger.clone())),
+            Arc::new(LinkControl::new(end1, end2, Arc::clone(&self.capture), self.logger.clone())),
+        ];
+        let channels = (carry_link(latency, Arc::clone(&controls[0])), carry_link(latency, Arc::clone(&controls[1])));
+        self.links.insert((device1.to_string(), port1), controls.clone());
+        self.links.insert((device2.to_string(), port2), controls);
+        channels
+    }
+
+    // the impairment applies to both directions of the link on the port of device, none removes it
+    pub fn set_link_impairment(&self, device: &str, port: u32, impairment: Option<LinkImpairment>) -> Result<(), NetworkError> {
+        let controls = self.links.get(&(device.to_string(), port)).ok_or(NetworkError::NoLinkOnPort{device: device.to_string(), port})?;
+        if let Some(impairment) = impairment.filter(|i| !i.is_valid()){
+            return Err(NetworkError::InvalidConfig(format!("probabilities of the impairment of {}:{} must be between 0 and 1, got {:?}", device, port, impairment)));
+        }
+        for (direction, control) in controls.iter().enumerate(){
+            // each direction draws its own faults
+            control.set_impairment(impairment.map(|i| LinkImpairment{seed: i.seed.wrapping_add(direction as u64), ..i}));
+        }
+        Ok(())
+    }
+
+    // record every message sent and received on the links in path, one json record per line, until the capture is stopped
+    pub async fn enable_capture(&mut self, path: &str) -> Result<(), NetworkError> {
+        self.stop_capture().await;
+        let (writer, task) = CaptureWriter::start(path)?;
+        *self.capture.lock().unwrap() = Some(writer);
+        self.capture_task = Some(task);
+        Ok(())
+    }
+
+    // the capture file is complete when this returns
+    pub async fn stop_capture(&mut self) {
+        // dropping the writer ends the task once it wrote the records received so far
+        self.capture.lock().unwrap().take();
+        if let Some(task) = self.capture_task.take(){
+            let _ = task.await;
+        }
+    }
+
+    // handle the messages device received in the capture, in order, without its neighbors sending them
+    // the ports of the capture without a link get one of cost 1 leading nowhere, device is usually freshly added
+    pub async fn replay(&mut self, capture: &Capture, device: &str) -> Result<(), NetworkError> {
+        if !self.routers.contains_key(device) && !self.switches.contains_key(device){
+            return Err(match self.hosts.contains_key(device){
+                true => NetworkError::InvalidConfig(format!("only the messages of routers and switches can be replayed, {} is a host", device)),
+                false => NetworkError::UnknownDevice(device.to_string()),
+            });
+        }
+        let messages: Vec<(u32, Message)> = capture.by_device(device).by_direction(Direction::Received).records.into_iter().map(|record| (record.port, record.message)).collect();
+        let ports: HashSet<u32> = messages.iter().map(|(port, _)| *port).collect();
+        for port in ports{
+            if self.used_port.get(device).is_some_and(|ports| ports.contains(&port)){
+                continue;
+            }
+            self.check_port_not_used(device, port)?;
+            let ((_, rx1), (tx2, _)) = self.link_channels(device, port, "replay", port, Duration::ZERO);
+            self.links.remove(&("replay".to_string(), port));
+            match self.switches.get(device){
+                Some((s, _)) => s.add_link(rx1, tx2, port, 1).await,
+                None => self.routers[device].0.add_link(rx1, tx2, port, 1).await,
+            }
+        }
+        match self.switches.get(device){
+            Some((s, _)) => s.replay(messages).await,
+            None => self.routers[device].0.replay(messages).await,
+        }.map_err(|_| NetworkError::DeviceDead(device.to_string()))
+    }
+
+    // the rate limit applies to both directions of the link on the port of device, none removes it
+    pub fn set_link_rate_limit(&self, device: &str, port: u32, rate_limit: Option<RateLimit>) -> Result<(), NetworkError> {
+        let controls = self.links.get(&(device.to_string(), port)).ok_or(NetworkError::NoLinkOnPort{device: device.to_string(), port})?;
+        if let Some(rate_limit) = rate_limit.filter(|r| !r.is_valid()){
+            return Err(NetworkError::InvalidConfig(format!("the rate and the queue depth of the link of {}:{} must be positive, got {:?}", device, port, rate_limit)));
         }
+        for control in controls.iter(){
+            control.set_rate_limit(rate_limit);
+        }
+        Ok(())
+    }
+
+    // messages altered by the impairment of the link on the port of device or dropped by its queues, in both directions
+    pub fn get_link_stats(&self, device: &str, port: u32) -> Result<LinkStats, NetworkError> {
+        let controls = self.links.get(&(device.to_string(), port)).ok_or(NetworkError::NoLinkOnPort{device: device.to_string(), port})?;
+        Ok(controls.iter().map(|c| c.stats()).fold(LinkStats::default(), |total, stats| LinkStats{
+            dropped: total.dropped + stats.dropped,
+            duplicated: total.duplicated + stats.duplicated,
+            reordered: total.reordered + stats.reordered,
+            tx_dropped: total.tx_dropped + stats.tx_dropped,
+            control_queued: total.control_queued + stats.control_queued,
+            data_queued: total.data_queued + stats.data_queued,
+            control_queue_dropped: total.control_queue_dropped + stats.control_queue_dropped,
+            data_queue_dropped: total.data_queue_dropped + stats.data_queue_dropped
+        }))
     }
 
     pub async fn add_peer_link(
@@ -92,23 +493,31 @@ impl Network {
         device2: &str,
         port2: u32,
         med: u32,
-    ) {
-        self.check_port_not_used(device1, port1);
-        self.check_port_not_used(device2, port2);
-        self.peers.push((device1.to_string(), port1, device2.to_string(), port2, med));
-        let (tx1, rx1) = channel(1024);
-        let (tx2, rx2) = channel(1024);
-
-        let (r1, ip1) = self
-            .routers
-            .get(&device1.to_string())
-            .expect(format!("Unknown device {}", device1).as_str());
-        let (r2, ip2) = self
-            .routers
-            .get(&device2.to_string())
-            .expect(format!("Unknown device {}", device1).as_str());
-        r1.add_peer_link(rx1, tx2, port1, med, *ip2).await;
-        r2.add_peer_link(rx2, tx1, port2, med, *ip1).await;
+        prefs: (Option<u32>, Option<u32>), // pref of device1 for the routes of device2 (100 by default), and the reverse
+    ) -> Result<(), NetworkError> {
+        self.add_peer_link_with_attributes(device1, port1, device2, port2, BgpLinkAttributes{med, prefs, latency: Duration::ZERO}).await
+    }
+
+    pub async fn add_peer_link_with_attributes(
+        &mut self,
+        device1: &str,
+        port1: u32,
+        device2: &str,
+        port2: u32,
+        attributes: BgpLinkAttributes,
+    ) -> Result<(), NetworkError> {
+        let BgpLinkAttributes{med, prefs, latency} = attributes;
+        self.bgp_router(device1)?;
+        self.bgp_router(device2)?;
+        self.reserve_ports(device1, port1, device2, port2).await?;
+        self.peers.push((device1.to_string(), port1, device2.to_string(), port2, (med, prefs)));
+        let ((tx1, rx1), (tx2, rx2)) = self.link_channels(device1, port1, device2, port2, latency);
+
+        let (r1, ip1) = self.router(device1)?;
+        let (r2, ip2) = self.router(device2)?;
+        r1.add_peer_link(rx1, tx2, port1, med, *ip2, prefs.0).await;
+        r2.add_peer_link(rx2, tx1, port2, med, *ip1, prefs.1).await;
+        Ok(())
     }
 
     pub async fn add_provider_customer_link(
@@ -118,28 +527,37 @@ impl Network {
         customer: &str,
         port2: u32,
         med: u32,
-    ) {
-        self.check_port_not_used(provider, port1);
-        self.check_port_not_used(customer, port2);
-        self.provider_customer.push((provider.to_string(), port1, customer.to_string(), port2, med));
-        let (tx1, rx1) = channel(1024);
-        let (tx2, rx2) = channel(1024);
-
-        let (provider, ip_provider) = self
-            .routers
-            .get(&provider.to_string())
-            .expect(format!("Unknown device {}", provider).as_str());
-        let (customer, ip_customer) = self
-            .routers
-            .get(&customer.to_string())
-            .expect(format!("Unknown device {}", customer).as_str());
+        prefs: (Option<u32>, Option<u32>), // pref of the provider for the routes of its customer (150 by default), and the reverse (50 by default)
+    ) -> Result<(), NetworkError> {
+        self.add_provider_customer_link_with_attributes(provider, port1, customer, port2, BgpLinkAttributes{med, prefs, latency: Duration::ZERO}).await
+    }
+
+    // the prefs of the attributes are the one of the provider for the routes of its customer, and the reverse
+    pub async fn add_provider_customer_link_with_attributes(
+        &mut self,
+        provider: &str,
+        port1: u32,
+        customer: &str,
+        port2: u32,
+        attributes: BgpLinkAttributes,
+    ) -> Result<(), NetworkError> {
+        let BgpLinkAttributes{med, prefs, latency} = attributes;
+        self.bgp_router(provider)?;
+        self.bgp_router(customer)?;
+        self.reserve_ports(provider, port1, customer, port2).await?;
+        self.provider_customer.push((provider.to_string(), port1, customer.to_string(), port2, (med, prefs)));
+        let ((tx1, rx1), (tx2, rx2)) = self.link_channels(provider, port1, customer, port2, latency);
+
+        let (provider, ip_provider) = self.router(provider)?;
+        let (customer, ip_customer) = self.router(customer)?;
 
         provider
-            .add_customer_link(rx1, tx2, port1, med, *ip_customer)
+            .add_customer_link(rx1, tx2, port1, med, *ip_customer, prefs.0)
             .await;
         customer
-            .add_provider_link(rx2, tx1, port2, med, *ip_provider)
+            .add_provider_link(rx2, tx1, port2, med, *ip_provider, prefs.1)
             .await;
+        Ok(())
     }
 
     pub async fn add_link(
@@ -149,635 +567,2385 @@ impl Network {
         device2: &str,
         port2: u32,
         cost: u32,
-    ) {
-        self.check_port_not_used(device1, port1);
-        self.check_port_not_used(device2, port2);
-        let (tx1, rx1) = channel(1024);
-        let (tx2, rx2) = channel(1024);
+    ) -> Result<(), NetworkError> {
+        self.add_link_with_latency(device1, port1, device2, port2, cost, Duration::ZERO).await
+    }
+
+    pub async fn add_link_with_latency(
+        &mut self,
+        device1: &str,
+        port1: u32,
+        device2: &str,
+        port2: u32,
+        cost: u32,
+        latency: Duration,
+    ) -> Result<(), NetworkError> {
+        // a host has a single interface
+        for device in [device1, device2]{
+            if self.hosts.contains_key(device) && self.used_port.get(device).is_some_and(|ports| !ports.is_empty()){
+                return Err(NetworkError::HostAlreadyLinked(device.to_string()));
+            }
+        }
+        self.reserve_ports(device1, port1, device2, port2).await?;
+        let ((tx1, rx1), (tx2, rx2)) = self.link_channels(device1, port1, device2, port2, latency);
         match self.switches.get(&device1.to_string()) {
-            Some(s) => s.add_link(rx1, tx2, port1, cost).await,
+            Some((s, _)) => s.add_link(rx1, tx2, port1, cost).await,
             None => match self.routers.get(&device1.to_string()) {
                 Some((r, _)) => r.add_link(rx1, tx2, port1, cost).await,
-                None => panic!("Missing device {}", device1),
+                None => match self.hosts.get(device1) {
+                    Some((h, _, _)) => h.add_link(rx1, tx2, port1, cost).await,
+                    None => return Err(NetworkError::UnknownDevice(device1.to_string())),
+                },
             },
         };
 
         match self.switches.get(&device2.to_string()) {
-            Some(s) => s.add_link(rx2, tx1, port2, cost).await,
+            Some((s, _)) => s.add_link(rx2, tx1, port2, cost).await,
             None => match self.routers.get(&device2.to_string()) {
                 Some((r, _)) => r.add_link(rx2, tx1, port2, cost).await,
-                None => panic!("Missing device {}", device2),
+                None => match self.hosts.get(device2) {
+                    Some((h, _, _)) => h.add_link(rx2, tx1, port2, cost).await,
+                    None => return Err(NetworkError::UnknownDevice(device2.to_string())),
+                },
             },
         };
 
         self.internal_links.entry(device1.to_string()).or_insert(vec![]).push((port1, device2.to_string(), port2, cost));
         self.internal_links.entry(device2.to_string()).or_insert(vec![]).push((port2, device1.to_string(), port1, cost));
+        Ok(())
     }
 
-    pub async fn add_ibgp_connection(
+    // link two routers over a subnet, the first router gets its first address and the second one the next
+    pub async fn add_link_with_subnet(
         &mut self,
-        device1: &str,
-        device2: &str,
-    ) {
-        let (d1, ip1) = self
-            .routers
-            .get(&device1.to_string())
-            .expect(format!("Unknown device {}", device1).as_str());
-        let (d2, ip2) = self
-            .routers
-            .get(&device2.to_string())
-            .expect(format!("Unknown device {}", device2).as_str());
-
-        d1.add_ibgp_connection(*ip2).await;
-        d2.add_ibgp_connection(*ip1).await;
+        router1: &str,
+        port1: u32,
+        router2: &str,
+        port2: u32,
+        cost: u32,
+        subnet: IPPrefix,
+    ) -> Result<(), NetworkError> {
+        self.router(router1)?;
+        self.router(router2)?;
+        if subnet.prefix_len > 30{
+            return Err(NetworkError::SubnetTooSmall(subnet.to_string()));
+        }
+        self.add_link(router1, port1, router2, port2, cost).await?;
+        self.set_interface_address(router1, port1, Ipv4Addr::from(u32::from(subnet.ip) + 1), subnet).await?;
+        self.set_interface_address(router2, port2, Ipv4Addr::from(u32::from(subnet.ip) + 2), subnet).await
     }
 
-    pub async fn ping(&self, from: &str, to: Ipv4Addr) {
-        let src = &self.routers.get(&from.to_string()).expect("Unknown router").0;
-
-        src.ping(to).await;
+    // the router answers for address on the link of port and advertises the subnet, e.g. as the gateway of the hosts of a LAN
+    pub async fn set_interface_address(&self, router: &str, port: u32, address: Ipv4Addr, subnet: IPPrefix) -> Result<(), NetworkError> {
+        let (src, _) = self.router(router)?;
+        if !subnet.contains(address){
+            return Err(NetworkError::AddressOutsideSubnet{address, subnet: subnet.to_string()});
+        }
+        src.set_interface_address(port, address, subnet).await;
+        Ok(())
     }
 
-    pub async fn announce_prefix(&self, router: &str) {
-        let router = &self.routers.get(router).expect("Unknown router").0;
-
-        router.announce_prefix().await;
+    async fn remove_port(&self, device: &str, port: u32) -> Result<(), NetworkError>{
+        match self.switches.get(device) {
+            Some((s, _)) => s.remove_link(port).await,
+            None => match self.routers.get(device) {
+                Some((r, _)) => r.remove_link(port).await,
+                None => match self.hosts.get(device) {
+                    Some((h, _, _)) => h.remove_link(port).await,
+                    None => return Err(NetworkError::UnknownDevice(device.to_string())),
+                },
+            },
+        };
+        Ok(())
     }
 
-    pub async fn announce_prefix_as(&self, announcing_as: u32) {
-        for router in self.router_as.get(&announcing_as).unwrap(){
-            self.announce_prefix(router).await;
+    pub async fn remove_link(
+        &mut self,
+        device1: &str,
+        port1: u32,
+        device2: &str,
+        port2: u32,
+    ) -> Result<(), NetworkError> {
+        let matches = |d1: &String, p1: &u32, d2: &String, p2: &u32| {
+            (d1 == device1 && *p1 == port1 && d2 == device2 && *p2 == port2) || 
+            (d1 == device2 && *p1 == port2 && d2 == device1 && *p2 == port1)
+        };
+        let internal = self.internal_links.get(device1)
+            .map(|links| links.iter().any(|(p1, d2, p2, _)| *p1 == port1 && d2 == device2 && *p2 == port2))
+            .unwrap_or(false);
+        let peer = self.peers.iter().any(|(d1, p1, d2, p2, _)| matches(d1, p1, d2, p2));
+        let provider_customer = self.provider_customer.iter().any(|(d1, p1, d2, p2, _)| matches(d1, p1, d2, p2));
+        if !internal && !peer && !provider_customer{
+            return Err(NetworkError::UnknownLink{device1: device1.to_string(), port1, device2: device2.to_string(), port2});
         }
-    }
-
-    pub async fn get_routing_table(&self, router: &str) -> HashMap<IPPrefix, (u32, u32)> {
-        let src = &self.routers.get(&router.to_string()).expect("Unknown router").0;
 
-        src.get_routing_table()
-            .await
-            .expect("Failed to retrieve routing table")
-    }
+        self.remove_port(device1, port1).await?;
+        self.remove_port(device2, port2).await?;
 
-    pub async fn get_bgp_routes(
-        &self,
-        router: &str,
-    ) -> HashMap<IPPrefix, (Option<BGPRoute>, HashSet<BGPRoute>)> {
-        let src = &self.routers.get(&router.to_string()).expect("Unknown router").0;
+        if let Some(links) = self.internal_links.get_mut(device1){
+            links.retain(|(p1, d2, p2, _)| !(*p1 == port1 && d2 == device2 && *p2 == port2));
+        }
+        if let Some(links) = self.internal_links.get_mut(device2){
+            links.retain(|(p2, d1, p1, _)| !(*p2 == port2 && d1 == device1 && *p1 == port1));
+        }
+        self.peers.retain(|(d1, p1, d2, p2, _)| !matches(d1, p1, d2, p2));
+        self.provider_customer.retain(|(d1, p1, d2, p2, _)| !matches(d1, p1, d2, p2));
 
-        src.get_bgp_routes()
-            .await
-            .expect("Failed to retrieve bgp routes")
+        self.release_port(device1, port1);
+        self.release_port(device2, port2);
+        Ok(())
     }
 
-    pub async fn quit(self) {
-        for (_, communicator) in self.switches {
-            communicator.quit().await;
+    // An administratively shut down port keeps its link, but the device neither sends nor receives on it
+    // and its protocols compute the spanning tree and the routes without it, until it is enabled again
+    pub async fn set_port_enabled(&self, device: &str, port: u32, enabled: bool) -> Result<(), NetworkError> {
+        if self.hosts.contains_key(device){
+            return Err(NetworkError::InvalidConfig(format!("only the ports of routers and switches can be shut down, {} is a host", device)));
         }
-
-        for (_, (communicator, _)) in self.routers {
-            communicator.quit().await;
+        if !self.routers.contains_key(device) && !self.switches.contains_key(device){
+            return Err(NetworkError::UnknownDevice(device.to_string()));
         }
-    }
-
-    pub async fn get_port_states(&self) -> BTreeMap<String, BTreeMap<u32, PortState>> {
-        let mut states = BTreeMap::new();
-        for (switch, communicator) in self.switches.iter() {
-            let ports_states = communicator
-                .get_port_state()
-                .await
-                .unwrap_or_else(|_| panic!("Failed to get port states of {}", switch));
-            states.insert(switch.clone(), ports_states);
+        if self.link_end(device, port).is_none(){
+            return Err(NetworkError::NoLinkOnPort{device: device.to_string(), port});
+        }
+        match self.switches.get(device){
+            Some((s, _)) => s.set_port_enabled(port, enabled).await,
+            None => self.routers[device].0.set_port_enabled(port, enabled).await,
         }
-        states
+        Ok(())
     }
 
-    pub async fn print_switch_states(&self) {
-        let states = self.get_port_states().await;
-        for (switch, ports) in states {
-            println!("{}", switch);
-            for (port, state) in ports {
-                println!("  {}: {:?}", port, state);
+    // both ends of the link use the new cost, the switches as the distance to the root and the routers as the OSPF cost
+    pub async fn set_link_cost(&mut self, device: &str, port: u32, cost: u32) -> Result<(), NetworkError> {
+        if self.hosts.contains_key(device){
+            return Err(NetworkError::InvalidConfig(format!("only the links of routers and switches have a cost, {} is a host", device)));
+        }
+        if !self.routers.contains_key(device) && !self.switches.contains_key(device){
+            return Err(NetworkError::UnknownDevice(device.to_string()));
+        }
+        let (other, other_port) = self.link_end(device, port).ok_or(NetworkError::NoLinkOnPort{device: device.to_string(), port})?;
+        let internal = self.internal_links.get(device).is_some_and(|links| links.iter().any(|(p, _, _, _)| *p == port));
+        if !internal{
+            return Err(NetworkError::InvalidConfig(format!("the link on port {} of {} is a BGP link, it has no cost", port, device)));
+        }
+        for (end, end_port) in [(device.to_string(), port), (other, other_port)]{
+            if let Some(link) = self.internal_links.get_mut(&end).and_then(|links| links.iter_mut().find(|(p, _, _, _)| *p == end_port)){
+                link.3 = cost;
+            }
+            match (self.switches.get(&end), self.routers.get(&end)){
+                (Some((s, _)), _) => s.set_link_cost(end_port, cost).await,
+                (_, Some((r, _))) => r.set_link_cost(end_port, cost).await,
+                _ => {}, // a host doesn't use the cost of its link
             }
         }
+        Ok(())
     }
 
-    pub async fn print_routing_table(&self, router: &str) {
-        let routing_tbale = self.get_routing_table(router).await;
-
-        println!("{}", router);
-
-        for (ip, (port, distance)) in routing_tbale {
-            println!("  {}: port={}, distance={}", ip, port, distance);
+    // ports of the first link found between device1 and device2
+    pub fn link_ports(&self, device1: &str, device2: &str) -> Option<(u32, u32)> {
+        if let Some(links) = self.internal_links.get(device1){
+            if let Some((p1, _, p2, _)) = links.iter().find(|(_, d2, _, _)| d2 == device2){
+                return Some((*p1, *p2));
+            }
+        }
+        for (d1, p1, d2, p2, _) in self.peers.iter().chain(self.provider_customer.iter()){
+            if d1 == device1 && d2 == device2{
+                return Some((*p1, *p2));
+            }
+            if d2 == device1 && d1 == device2{
+                return Some((*p2, *p1));
+            }
         }
+        None
     }
 
-    pub async fn print_routing_tables(&self) {
-        for router in self.routers.keys() {
-            self.print_routing_table(router).await;
+    // device and port at the other end of the link on this port
+    pub fn link_end(&self, device: &str, port: u32) -> Option<(String, u32)> {
+        if let Some(links) = self.internal_links.get(device){
+            if let Some((_, d2, p2, _)) = links.iter().find(|(p1, _, _, _)| *p1 == port){
+                return Some((d2.clone(), *p2));
+            }
+        }
+        for (d1, p1, d2, p2, _) in self.peers.iter().chain(self.provider_customer.iter()){
+            if d1 == device && *p1 == port{
+                return Some((d2.clone(), *p2));
+            }
+            if d2 == device && *p2 == port{
+                return Some((d1.clone(), *p1));
+            }
         }
+        None
     }
 
-    pub async fn print_bgp_table(&self, router: &str) {
-        let bgp_table = self.get_bgp_routes(router).await;
-
-        println!("{}", router);
-
-        for (prefix, (best_route, routes)) in bgp_table {
-            println!("  {}", prefix);
-            for route in routes {
-                if Some(route.clone()) == best_route {
-                    println!("   *{}", route)
-                } else {
-                    println!("    {}", route)
-                }
+    pub async fn remove_router(&mut self, name: &str) -> Result<(), NetworkError> {
+        if !self.routers.contains_key(name){
+            return Err(NetworkError::UnknownDevice(name.to_string()));
+        }
+        let mut links = vec![];
+        for (port, neighbor, neighbor_port, _) in self.internal_links.get(name).unwrap_or(&vec![]){
+            links.push((*port, neighbor.clone(), *neighbor_port));
+        }
+        for (d1, p1, d2, p2, _) in self.peers.iter().chain(self.provider_customer.iter()){
+            if d1 == name{
+                links.push((*p1, d2.clone(), *p2));
+            }else if d2 == name{
+                links.push((*p2, d1.clone(), *p1));
             }
         }
-    }
+        for (port, neighbor, neighbor_port) in links{
+            self.remove_link(name, port, &neighbor, neighbor_port).await?;
+        }
 
-    pub async fn print_bgp_tables(&self) {
-        for router in self.routers.keys() {
-            self.print_bgp_table(router).await;
+        let (communicator, _) = self.routers.remove(name).unwrap();
+        communicator.quit().await;
+        self.used_port.remove(name);
+        self.internal_links.remove(name);
+        self.remove_ibgp_sessions(name);
+        if let Some(router_as) = self.as_router.remove(name){
+            if let Some(routers) = self.router_as.get_mut(&router_as){
+                routers.retain(|r| r != name);
+            }
         }
+        Ok(())
     }
 
-    fn get_switch_as(&self) -> (HashMap<u32, Vec<String>>, Vec<String>){
-        let mut switch_as = HashMap::new();
-        let mut others = vec![];
-        for switch in self.switches.keys(){
-            let mut affiliation = None;
-            let mut inserted_other = false;
-            for (_, neighbor, _, _) in self.internal_links.get(switch).unwrap(){
-                if !self.routers.contains_key(neighbor) {
-                    continue;
-                }
-                let router_as = self.as_router.get(neighbor).unwrap();
-                match affiliation{
-                    Some(a) => {
-                        if a != router_as{
-                            others.push(switch.clone());
-                            inserted_other = true;
-                            break;
-                        }
-                    }
-                    None => affiliation = Some(router_as)
+    // stop a device as if it crashed: unlike remove_router, its neighbors aren't told and
+    // only notice it through their timeouts, their ports towards it stay in use
+    pub async fn stop_device(&mut self, name: &str) -> Result<(), NetworkError> {
+        if let Some((communicator, _)) = self.switches.remove(name){
+            communicator.quit().await;
+        }else if let Some((communicator, _)) = self.routers.remove(name){
+            communicator.quit().await;
+            self.remove_ibgp_sessions(name);
+            if let Some(router_as) = self.as_router.remove(name){
+                if let Some(routers) = self.router_as.get_mut(&router_as){
+                    routers.retain(|r| r != name);
                 }
             }
-            if !inserted_other{
-                if let Some(a) = affiliation{
-                    switch_as.entry(*a).or_insert(vec![]).push(switch.clone());
-                }else{
-                    others.push(switch.clone());
-                }
+        }else if let Some((communicator, _, _)) = self.hosts.remove(name){
+            communicator.quit().await;
+        }else{
+            return Err(NetworkError::UnknownDevice(name.to_string()));
+        }
+        self.used_port.remove(name);
+        self.stale_ports.remove(name);
+        self.links.retain(|(device, _), _| device != name);
+        // the neighbors only notice the failure with their timers, but their ports can be used by new links
+        let mut neighbor_ports: Vec<(String, u32)> = self.internal_links.remove(name).unwrap_or_default()
+            .into_iter()
+            .map(|(_, neighbor, port, _)| (neighbor, port))
+            .collect();
+        for (d1, p1, d2, p2, _) in self.peers.iter().chain(self.provider_customer.iter()){
+            if d1 == name{
+                neighbor_ports.push((d2.clone(), *p2));
+            }else if d2 == name{
+                neighbor_ports.push((d1.clone(), *p1));
             }
         }
-        (switch_as, others)
+        for (neighbor, port) in neighbor_ports{
+            self.release_port(&neighbor, port);
+            self.stale_ports.entry(neighbor).or_default().insert(port);
+        }
+        for links in self.internal_links.values_mut(){
+            links.retain(|(_, neighbor, _, _)| neighbor != name);
+        }
+        self.peers.retain(|(d1, _, d2, _, _)| d1 != name && d2 != name);
+        self.provider_customer.retain(|(d1, _, d2, _, _)| d1 != name && d2 != name);
+        Ok(())
     }
 
-    pub async fn dot_representation(&self) -> String {
+    // stop a router like stop_device, remembering its links and iBGP sessions for restart_router
+    pub async fn crash_router(&mut self, name: &str) -> Result<(), NetworkError> {
+        let ip = self.router(name)?.1;
+        let involved = |d1: &String, d2: &String| d1 == name || d2 == name;
+        let crashed = CrashedRouter{
+            id: ip.octets()[3] as u32,
+            router_as: self.as_router[name],
+            internal_links: self.internal_links.get(name).cloned().unwrap_or_default(),
+            provider_customer: self.provider_customer.iter().filter(|(d1, _, d2, _, _)| involved(d1, d2)).cloned().collect(),
+            peers: self.peers.iter().filter(|(d1, _, d2, _, _)| involved(d1, d2)).cloned().collect(),
+            ibgp_sessions: self.ibgp_sessions.iter().filter(|session| match session{
+                IbgpSessionCfg::Mesh(r1, r2) => involved(r1, r2),
+                IbgpSessionCfg::RouteReflector{rr, client} => involved(rr, client),
+            }).cloned().collect(),
+        };
+        self.stop_device(name).await?;
+        self.crashed.insert(name.to_string(), crashed);
+        Ok(())
+    }
 
-        let mut graph = Graph::new(vec![GraphOption::RankSep("1".to_string()), GraphOption::NodeSep("1".to_string())]);
-        
-        
-        let (switch_as, others) = self.get_switch_as();
-        for (as_id, routers) in self.router_as.iter(){
-            graph.add_group(&as_id.to_string(), &format!("AS {as_id}"));
-            for router in routers{
-                graph.add_node_group(router, &as_id.to_string(), vec![NodeOption::Shape("rect".to_string())]);
-            }
-            for switch in switch_as.get(&as_id).unwrap_or(&vec![]).iter(){
-                graph.add_node_group(switch, &as_id.to_string(), vec![NodeOption::Shape("diamond".to_string())]);
+    // start a crashed router again with an empty state, its links are added back on the same ports
+    // its neighbors drop their stale end of the links and find it again with their hellos and BGP sessions
+    // the links and sessions towards the devices stopped or removed since the crash are left out
+    pub async fn restart_router(&mut self, name: &str) -> Result<(), NetworkError> {
+        let crashed = self.crashed.remove(name).ok_or(NetworkError::NotCrashed(name.to_string()))?;
+        if let Err(error) = self.start_crashed(name, &crashed).await{
+            // crashed again with its record, so that the restart can be retried
+            if self.routers.contains_key(name){
+                self.stop_device(name).await?;
             }
+            self.crashed.insert(name.to_string(), crashed);
+            return Err(error);
         }
-        for switch in others{
-            graph.add_node(&switch, vec![NodeOption::Shape("diamond".to_string())])
-        }
+        Ok(())
+    }
 
-        
-        let states = self.get_port_states().await;
-        for (device1, neighbors) in self.internal_links.iter() {
-            for (p1, device2, p2, cost) in neighbors{
-                if device1 > device2{
-                    continue;
-                }
-                let mut options = vec![EdgeOption::Arrowhead("none".to_string()), EdgeOption::Label(cost.to_string())];
-                if self.switches.contains_key(device1) && self.switches.contains_key(device2){
-                    options.push(EdgeOption::Headlabel(format!("{} {}", p1,
-                        states.get(device1).unwrap().get(p1).unwrap().to_string())));
-                    options.push(EdgeOption::Taillabel(format!("{} {}", p2,
-                        states.get(device2).unwrap().get(p2).unwrap().to_string())));
-                }else{
-                    options.push(EdgeOption::Headlabel(format!("{}", p1)));
-                    options.push(EdgeOption::Taillabel(format!("{}", p2)));
-                }
-                graph.add_edge(device1, device2, options);
+    async fn start_crashed(&mut self, name: &str, crashed: &CrashedRouter) -> Result<(), NetworkError> {
+        let exists = |device: &String| self.routers.contains_key(device) || self.switches.contains_key(device) || self.hosts.contains_key(device);
+        let internal_links: Vec<_> = crashed.internal_links.iter().filter(|(_, neighbor, _, _)| exists(neighbor)).cloned().collect();
+        let bgp_link = |(d1, _, d2, _, _): &&BgpLink| (d1 == name || exists(d1)) && (d2 == name || exists(d2));
+        let provider_customer: Vec<BgpLink> = crashed.provider_customer.iter().filter(bgp_link).cloned().collect();
+        let peers: Vec<BgpLink> = crashed.peers.iter().filter(bgp_link).cloned().collect();
+        // the other routers of the sessions still have theirs
+        let other = |session: &IbgpSessionCfg| match session{
+            IbgpSessionCfg::Mesh(r1, r2) => if r1 == name { r2.clone() } else { r1.clone() },
+            IbgpSessionCfg::RouteReflector{rr, client} => if rr == name { client.clone() } else { rr.clone() },
+        };
+        let sessions: Vec<IbgpSessionCfg> = crashed.ibgp_sessions.iter().filter(|session| self.routers.contains_key(&other(session))).cloned().collect();
+
+        self.add_router(name, crashed.id, crashed.router_as)?;
+        for (port, neighbor, neighbor_port, cost) in internal_links{
+            self.add_link(name, port, &neighbor, neighbor_port, cost).await?;
+        }
+        for (provider, port1, customer, port2, (med, prefs)) in provider_customer{
+            self.add_provider_customer_link(&provider, port1, &customer, port2, med, prefs).await?;
+        }
+        for (device1, port1, device2, port2, (med, prefs)) in peers{
+            self.add_peer_link(&device1, port1, &device2, port2, med, prefs).await?;
+        }
+        let router = &self.router(name)?.0;
+        for session in sessions.iter(){
+            let address = self.router(&other(session))?.1;
+            match session{
+                IbgpSessionCfg::RouteReflector{rr, ..} if rr == name => router.add_ibgp_client(address).await,
+                _ => router.add_ibgp_connection(address).await,
             }
         }
+        self.ibgp_sessions.extend(sessions);
+        Ok(())
+    }
 
-        for (device1, p1, device2, p2, _) in self.provider_customer.iter(){
-            let options = vec![
-                EdgeOption::Label("$".to_string()), 
-                EdgeOption::Headlabel(format!("{}", p1)), 
-                EdgeOption::Taillabel(format!("{}", p2)),
-                EdgeOption::Color("red".to_string()),
-                EdgeOption::FontColor("red".to_string())
-            ];
-            graph.add_edge(&device1, &device2, options);
+    // state of the tasks of the devices of the network and of the crashed routers
+    // the queries to a panicked device fail with NetworkError::DeviceDead
+    pub async fn device_health(&mut self) -> BTreeMap<String, DeviceHealth> {
+        let (finished, running) = std::mem::take(&mut self.tasks).into_iter().partition(|(_, task)| task.is_finished());
+        self.tasks = running;
+        for (name, task) in finished{
+            if let Err(error) = task.await{
+                if error.is_panic(){
+                    let payload = error.into_panic();
+                    let message = payload.downcast_ref::<&str>().map(|m| m.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or("unknown panic".to_string());
+                    self.panicked.insert(name, message);
+                }
+            }
         }
-        for (device1, p1, device2, p2, _) in self.peers.iter(){
-            let options = vec![
-                EdgeOption::Arrowhead("none".to_string()),
-                EdgeOption::Label("=".to_string()), 
-                EdgeOption::Headlabel(format!("{}", p1)), 
-                EdgeOption::Taillabel(format!("{}", p2)),
-                EdgeOption::Color("blue".to_string()),
-                EdgeOption::FontColor("blue".to_string())
-            ];
-            graph.add_edge(&device1, &device2, options);
+        let devices = self.routers.keys().chain(self.switches.keys()).chain(self.hosts.keys());
+        let mut health: BTreeMap<String, DeviceHealth> = devices.map(|name| {
+            let state = match self.panicked.get(name){
+                Some(message) => DeviceHealth::Panicked(message.clone()),
+                None => DeviceHealth::Running,
+            };
+            (name.clone(), state)
+        }).collect();
+        for name in self.crashed.keys(){
+            health.insert(name.clone(), DeviceHealth::Stopped);
         }
+        health
+    }
 
-        format!("{}", graph)
+    fn remove_ibgp_sessions(&mut self, router: &str) {
+        self.ibgp_sessions.retain(|session| match session{
+            IbgpSessionCfg::Mesh(r1, r2) => r1 != router && r2 != router,
+            IbgpSessionCfg::RouteReflector{rr, client} => rr != router && client != router,
+        });
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use protocols::bgp::RouteSource;
-    use std::thread;
-    use std::time::Duration;
-    use PortState::*;
+    pub async fn add_ibgp_connection(
+        &mut self,
+        device1: &str,
+        device2: &str,
+    ) -> Result<(), NetworkError> {
+        let (d1, ip1) = self.bgp_router(device1)?;
+        let (d2, ip2) = self.bgp_router(device2)?;
 
-    #[tokio::test(flavor = "multi_thread", worker_threads = 6)]
-    async fn test_spanning_tree() {
-        for _ in 0..10 {
-            let logger = Logger::start_test();
-            let mut network = Network::new(logger);
-            network.add_switch("s1", 1);
-            network.add_switch("s2", 2);
-            network.add_switch("s3", 3);
-            network.add_switch("s4", 4);
-            network.add_switch("s6", 6);
-            network.add_switch("s9", 9);
+        d1.add_ibgp_connection(*ip2).await;
+        d2.add_ibgp_connection(*ip1).await;
+        self.ibgp_sessions.push(IbgpSessionCfg::Mesh(device1.to_string(), device2.to_string()));
+        Ok(())
+    }
 
-            network.add_link("s1", 1, "s2", 1, 1).await;
-            network.add_link("s1", 2, "s4", 1, 1).await;
-            network.add_link("s2", 2, "s9", 1, 1).await;
-            network.add_link("s4", 2, "s9", 2, 1).await;
-            network.add_link("s4", 3, "s3", 1, 1).await;
-            network.add_link("s9", 3, "s3", 2, 1).await;
-            network.add_link("s9", 4, "s6", 1, 1).await;
-            network.add_link("s3", 3, "s6", 2, 1).await;
+    // iBGP session between a route reflector and one of its clients
+    pub async fn add_ibgp_client(&mut self, rr: &str, client: &str) -> Result<(), NetworkError> {
+        let (d1, ip1) = self.bgp_router(rr)?;
+        let (d2, ip2) = self.bgp_router(client)?;
 
-            // wait for convergence
-            thread::sleep(Duration::from_millis(250));
+        d1.add_ibgp_client(*ip2).await;
+        d2.add_ibgp_connection(*ip1).await;
+        self.ibgp_sessions.push(IbgpSessionCfg::RouteReflector{rr: rr.to_string(), client: client.to_string()});
+        Ok(())
+    }
 
-            let switch_states = network.get_port_states().await;
+    pub async fn ping(&self, from: &str, to: Ipv4Addr) -> Result<Duration, NetworkError> {
+        if let Some((host, _, _)) = self.hosts.get(from){
+            return Network::ping_result(from, host.ping(to).await);
+        }
+        let src = &self.router(from)?.0;
 
-            let mut expected: BTreeMap<String, BTreeMap<u32, PortState>> = BTreeMap::new();
-            expected.insert(
-                "s1".into(),
-                [(1, Designated), (2, Designated)].into_iter().collect(),
-            );
-            expected.insert(
-                "s2".into(),
-                [(1, Root), (2, Designated)].into_iter().collect(),
-            );
-            expected.insert(
-                "s3".into(),
-                [(1, Root), (2, Designated), (3, Designated)]
-                    .into_iter()
-                    .collect(),
-            );
-            expected.insert(
-                "s4".into(),
-                [(1, Root), (2, Designated), (3, Designated)]
-                    .into_iter()
-                    .collect(),
-            );
-            expected.insert("s6".into(), [(1, Blocked), (2, Root)].into_iter().collect());
-            expected.insert(
-                "s9".into(),
-                [(1, Root), (2, Blocked), (3, Blocked), (4, Designated)]
-                    .into_iter()
-                    .collect(),
-            );
+        Network::ping_result(from, src.ping(to).await)
+    }
 
-            assert_eq!(expected, switch_states);
+    // routers crossed by a ping from the router to the destination, then by its answer back to the router
+    // both lists start with the router sending them, the answering device ends the first one and starts the second
+    pub async fn ping_recorded(&self, from: &str, to: Ipv4Addr) -> Result<(Vec<Ipv4Addr>, Vec<Ipv4Addr>), NetworkError> {
+        let src = &self.router(from)?.0;
 
-            network.quit().await;
-        }
+        Network::ping_result(from, src.ping_recorded(to).await)
     }
 
-    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
-    async fn test_ospf() {
-        for _ in 0..10 {
-            let logger = Logger::start_test();
-            let mut network = Network::new(logger);
-            network.add_router("r1", 1, 1);
-            network.add_router("r2", 2, 1);
-            network.add_router("r3", 3, 1);
-            network.add_router("r4", 4, 1);
+    // send count pings from the router, one every interval, like ping -c count -i interval
+    // the report is returned once the last ping was answered or timed out
+    pub async fn ping_n(&self, from: &str, to: Ipv4Addr, count: u32, interval: Duration) -> Result<PingReport, NetworkError> {
+        let src = &self.router(from)?.0;
 
-            network.add_link("r1", 1, "r2", 1, 1).await;
-            network.add_link("r1", 2, "r3", 1, 1).await;
-            network.add_link("r3", 3, "r4", 1, 1).await;
-            network.add_link("r2", 2, "r3", 2, 1).await;
+        src.ping_series(to, count, interval)
+            .await
+            .map_err(|_| NetworkError::DeviceDead(from.to_string()))
+    }
 
-            // wait for convergence
-            thread::sleep(Duration::from_millis(250));
+    // the data is sent from the address of the router, whether it was delivered is known from received_data
+    pub async fn send_data(&self, from: &str, to: Ipv4Addr, payload: &str) -> Result<(), NetworkError> {
+        let src = &self.router(from)?.0;
 
-            assert_eq!(
-                network.get_routing_table("r1").await,
-                [
-                    ("10.0.1.1/32".parse().unwrap(), (0, 0)),
-                    ("10.0.1.2/32".parse().unwrap(), (1, 1)),
-                    ("10.0.1.3/32".parse().unwrap(), (2, 1)),
-                    ("10.0.1.4/32".parse().unwrap(), (2, 2))
-                ]
-                .into_iter()
-                .collect()
-            );
+        src.send_data(to, payload.to_string()).await;
+        Ok(())
+    }
 
-            assert_eq!(
-                network.get_routing_table("r2").await,
-                [
-                    ("10.0.1.1/32".parse().unwrap(), (1, 1)),
-                    ("10.0.1.2/32".parse().unwrap(), (0, 0)),
-                    ("10.0.1.3/32".parse().unwrap(), (2, 1)),
-                    ("10.0.1.4/32".parse().unwrap(), (2, 2))
-                ]
-                .into_iter()
-                .collect()
-            );
+    // source and payload of the data received by the router, in the order of arrival
+    pub async fn received_data(&self, router: &str) -> Result<Vec<(Ipv4Addr, String)>, NetworkError> {
+        let src = &self.router(router)?.0;
 
-            assert_eq!(
-                network.get_routing_table("r3").await,
-                [
-                    ("10.0.1.1/32".parse().unwrap(), (1, 1)),
-                    ("10.0.1.2/32".parse().unwrap(), (2, 1)),
-                    ("10.0.1.3/32".parse().unwrap(), (0, 0)),
-                    ("10.0.1.4/32".parse().unwrap(), (3, 1))
-                ]
-                .into_iter()
-                .collect()
-            );
+        src.get_received_data()
+            .await
+            .map_err(|_| NetworkError::DeviceDead(router.to_string()))
+    }
 
-            assert_eq!(
-                network.get_routing_table("r4").await,
-                [
-                    ("10.0.1.1/32".parse().unwrap(), (1, 2)),
-                    ("10.0.1.2/32".parse().unwrap(), (1, 2)),
-                    ("10.0.1.3/32".parse().unwrap(), (1, 1)),
-                    ("10.0.1.4/32".parse().unwrap(), (0, 0))
-                ]
-                .into_iter()
-                .collect()
-            );
+    // send rate_pps probes per second during duration from the router, returns the id of the flow for flow_stats
+    pub fn start_flow(&mut self, from: &str, to: Ipv4Addr, rate_pps: u32, duration: Duration) -> Result<u64, NetworkError> {
+        let src = self.router(from)?.0.clone();
 
-            network.quit().await;
+        Ok(self.traffic.start(src, from, to, rate_pps, duration, self.logger.clone()))
+    }
+
+    // the probes are counted by the router which received them, wherever the flow was sent
+    pub async fn flow_stats(&self, flow: u64) -> Result<FlowStats, NetworkError> {
+        let (sent, finished) = self.traffic.sent(flow).ok_or(NetworkError::UnknownFlow(flow))?;
+        let (mut received, mut latency) = (0, Duration::ZERO);
+        for (router, (communicator, _)) in self.routers.iter(){
+            let (count, total) = communicator.get_received_probes(flow)
+                .await
+                .map_err(|_| NetworkError::DeviceDead(router.to_string()))?;
+            received += count;
+            latency += total;
         }
+        let average_latency = if received == 0 { Duration::ZERO } else { latency / received as u32 };
+        Ok(FlowStats{sent, received, lost: sent.saturating_sub(received), average_latency, finished})
     }
 
-    #[tokio::test(flavor = "multi_thread", worker_threads = 6)]
-    async fn test_mix_switches_routers() {
-        for _ in 0..10 {
-            let logger = Logger::start_test();
-            let mut network = Network::new(logger);
-            network.add_router("r1", 1, 1);
-            network.add_router("r2", 2, 1);
-            network.add_switch("s1", 11);
-            network.add_switch("s2", 12);
-            network.add_switch("s3", 13);
-            network.add_switch("s4", 14);
+    // ping the destination of each pair from its router concurrently, the results are in the order of the pairs
+    pub async fn ping_many(&self, pairs: &[(&str, Ipv4Addr)]) -> Vec<Result<Duration, NetworkError>> {
+        let permits = Arc::new(Semaphore::new(MAX_PARALLEL_PINGS));
+        let mut pings = vec![];
+        for (from, to) in pairs{
+            let ping = self.router(from).map(|(src, _)| {
+                let (src, from, to, permits) = (src.clone(), from.to_string(), *to, Arc::clone(&permits));
+                tokio::spawn(async move {
+                    let _permit = permits.acquire_owned().await.expect("Failed to acquire ping permit");
+                    Network::ping_result(&from, src.ping(to).await)
+                })
+            });
+            pings.push(ping);
+        }
+        let mut results = vec![];
+        for ping in pings{
+            results.push(match ping{
+                Ok(handle) => handle.await.expect("Failed to wait for ping"),
+                Err(e) => Err(e),
+            });
+        }
+        results
+    }
 
-            network.add_link("r1", 1, "s1", 1, 1).await;
-            network.add_link("s1", 2, "s2", 1, 1).await;
-            network.add_link("s2", 2, "s3", 1, 1).await;
-            network.add_link("s4", 1, "s3", 3, 1).await;
-            network.add_link("s4", 2, "s1", 3, 1).await;
-            network.add_link("s3", 2, "r2", 1, 1).await;
+    fn ping_result<T>(from: &str, result: Result<T, PingError>) -> Result<T, NetworkError> {
+        match result{
+            Ok(answer) => Ok(answer),
+            Err(PingError::RouterStopped) => Err(NetworkError::DeviceDead(from.to_string())),
+            Err(e) => Err(e.into()),
+        }
+    }
 
-            // wait for convergence
-            thread::sleep(Duration::from_millis(250));
+    // IPs of the routers crossed to reach to, the last one being the destination if it answered
+    pub async fn traceroute(&self, from: &str, to: Ipv4Addr) -> Result<Vec<Ipv4Addr>, NetworkError> {
+        let src = &self.router(from)?.0;
 
-            assert_eq!(
-                network.get_routing_table("r1").await,
-                [
-                    ("10.0.1.1/32".parse().unwrap(), (0, 0)),
-                    ("10.0.1.2/32".parse().unwrap(), (1, 1))
-                ]
-                .into_iter()
-                .collect()
-            );
+        src.traceroute(to)
+            .await
+            .map_err(|_| NetworkError::DeviceDead(from.to_string()))
+    }
 
-            assert_eq!(
-                network.get_routing_table("r2").await,
-                [
-                    ("10.0.1.1/32".parse().unwrap(), (1, 1)),
-                    ("10.0.1.2/32".parse().unwrap(), (0, 0))
-                ]
-                .into_iter()
-                .collect()
-            );
+    pub async fn announce_prefix(&self, router: &str) -> Result<(), NetworkError> {
+        self.announce_prefix_with_pref(router, DEFAULT_LOCAL_PREF).await
+    }
 
-            thread::sleep(Duration::from_millis(250));
+    pub async fn announce_prefix_with_pref(&self, router: &str, pref: u32) -> Result<(), NetworkError> {
+        let router = &self.bgp_router(router)?.0;
 
-            network.quit().await;
-        }
+        router.announce_prefix(pref).await;
+        Ok(())
     }
 
-    
-    #[tokio::test(flavor = "multi_thread", worker_threads = 16)]
-    async fn test_bgp() {
-        for _ in 0..5 {
-            let logger = Logger::start_test();
-            let mut network = Network::new(logger);
-            network.add_router("r1", 1, 1);
-            network.add_router("r2", 2, 2);
-            network.add_router("r3", 3, 3);
-            network.add_router("r4", 4, 4);
-
-            network
-                .add_provider_customer_link("r2", 1, "r1", 1, 0)
-                .await;
-            network
-                .add_provider_customer_link("r2", 2, "r4", 1, 0)
-                .await;
-            network
-                .add_provider_customer_link("r4", 3, "r3", 1, 0)
-                .await;
+    pub async fn announce_prefix_with_communities(&self, router: &str, communities: Vec<u32>) -> Result<(), NetworkError> {
+        let router = &self.bgp_router(router)?.0;
 
-            network
-                .add_peer_link("r1", 2, "r4", 2, 0)
-                .await;
+        router.announce_prefix_with_communities(communities).await;
+        Ok(())
+    }
 
-            network.announce_prefix("r1").await;
+    pub async fn announce_prefix_with_origin(&self, router: &str, origin: Origin) -> Result<(), NetworkError> {
+        let router = &self.bgp_router(router)?.0;
 
-            // wait for convergence
-            thread::sleep(Duration::from_millis(1000));
+        router.announce_prefix_with_origin(origin).await;
+        Ok(())
+    }
 
-            assert_eq!(
-                network.get_bgp_routes("r2").await,
-                [(
-                    "10.0.1.0/24".parse().unwrap(),
-                    (
-                        Some(BGPRoute {
-                            prefix: "10.0.1.0/24".parse().unwrap(),
-                            nexthop: "10.0.1.1".parse().unwrap(),
-                            as_path: vec![1],
-                            pref: 150,
-                            med: 0,
-                            router_id: 1,
-                            source: RouteSource::EBGP
-                        }),
-                        [BGPRoute {
-                            prefix: "10.0.1.0/24".parse().unwrap(),
-                            nexthop: "10.0.1.1".parse().unwrap(),
-                            as_path: vec![1],
-                            pref: 150,
-                            med: 0,
-                            router_id: 1,
-                            source: RouteSource::EBGP
-                        }]
-                        .into_iter()
-                        .collect()
-                    )
-                )]
-                .into_iter()
-                .collect()
-            );
+    // prefix announced by router in addition to the one of its AS, e.g. to simulate a hijack
+    pub async fn announce_custom_prefix(&self, router: &str, prefix: IPPrefix) -> Result<(), NetworkError> {
+        let router = &self.bgp_router(router)?.0;
 
-            assert_eq!(
-                network.get_bgp_routes("r3").await,
-                [(
-                    "10.0.1.0/24".parse().unwrap(),
-                    (
-                        Some(BGPRoute {
-                            prefix: "10.0.1.0/24".parse().unwrap(),
-                            nexthop: "10.0.4.4".parse().unwrap(),
-                            as_path: vec![4, 1],
-                            pref: 50,
-                            med: 0,
-                            router_id: 4,
-                            source: RouteSource::EBGP
-                        }),
-                        [BGPRoute {
-                            prefix: "10.0.1.0/24".parse().unwrap(),
-                            nexthop: "10.0.4.4".parse().unwrap(),
-                            as_path: vec![4, 1],
-                            pref: 50,
-                            med: 0,
-                            router_id: 4,
-                            source: RouteSource::EBGP
-                        }]
-                        .into_iter()
-                        .collect()
-                    )
-                )]
-                .into_iter()
-                .collect()
-            );
+        router.announce_custom_prefix(prefix).await;
+        Ok(())
+    }
 
-            assert_eq!(
-                network.get_bgp_routes("r4").await,
-                [(
-                    "10.0.1.0/24".parse().unwrap(),
-                    (
-                        Some(BGPRoute {
-                            prefix: "10.0.1.0/24".parse().unwrap(),
-                            nexthop: "10.0.1.1".parse().unwrap(),
-                            as_path: vec![1],
-                            pref: 100,
-                            med: 0,
-                            router_id: 1,
-                            source: RouteSource::EBGP
-                        }),
-                        [
-                            BGPRoute {
-                                prefix: "10.0.1.0/24".parse().unwrap(),
-                                nexthop: "10.0.1.1".parse().unwrap(),
-                                as_path: vec![1],
-                                pref: 100,
-                                med: 0,
-                                router_id: 1,
-                                source: RouteSource::EBGP
-                            },
-                            BGPRoute {
-                                prefix: "10.0.1.0/24".parse().unwrap(),
-                                nexthop: "10.0.2.2".parse().unwrap(),
-                                as_path: vec![2, 1],
-                                pref: 50,
-                                med: 0,
-                                router_id: 2,
-                                source: RouteSource::EBGP
-                            }
-                        ]
-                        .into_iter()
-                        .collect()
-                    )
-                )]
-                .into_iter()
-                .collect()
-            );
+    pub async fn withdraw_prefix(&self, router: &str) -> Result<(), NetworkError> {
+        let router = &self.bgp_router(router)?.0;
 
-            network.quit().await;
-        }
+        router.withdraw_prefix().await;
+        Ok(())
     }
 
-    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
-    pub async fn test_bgp_complex() {
-        let logger = Logger::start_test();
-        let mut network = Network::new(logger);
-        network.add_router("r1", 1, 1);
-        network.add_router("r2", 2, 2);
-        network.add_router("r3", 3, 3);
-        network.add_router("r4", 4, 4);
-        network.add_router("r5", 5, 5);
-        network.add_router("r6", 6, 6);
-        network.add_router("r7", 7, 7);
-        network.add_router("r8", 8, 8);
+    // flush the routes learned by the IGP of router, they are computed again from its LSPs
+    pub async fn clear_routes(&self, router: &str) -> Result<(), NetworkError> {
+        let router = &self.router(router)?.0;
 
-        network
-            .add_provider_customer_link("r3", 1, "r1", 1, 0)
-            .await;
-        network
-            .add_provider_customer_link("r1", 2, "r2", 1, 0)
-            .await;
-        network
-            .add_provider_customer_link("r4", 1, "r3", 3, 0)
-            .await;
-        network
-            .add_provider_customer_link("r5", 1, "r2", 3, 0)
-            .await;
-        network
-            .add_provider_customer_link("r7", 1, "r4", 3, 0)
-            .await;
-        network
-            .add_provider_customer_link("r6", 2, "r7", 2, 0)
-            .await;
-        network
-            .add_provider_customer_link("r8", 1, "r7", 3, 0)
-            .await;
+        router.clear_routes().await;
+        Ok(())
+    }
 
-        network
-            .add_peer_link("r2", 2, "r3", 2, 0)
-            .await;
-        network
-            .add_peer_link("r4", 2, "r5", 2, 0)
-            .await;
-        network
-            .add_peer_link("r5", 3, "r6", 1, 0)
-            .await;
-        network
-            .add_peer_link("r6", 3, "r8", 2, 0)
-            .await;
+    // flush the BGP routes of router, its neighbors are asked to advertise them again
+    pub async fn clear_bgp(&self, router: &str) -> Result<(), NetworkError> {
+        let router = &self.router(router)?.0;
 
-        network.announce_prefix("r2").await;
+        router.clear_bgp().await;
+        Ok(())
+    }
 
-        // wait for convergence
-        thread::sleep(Duration::from_millis(2000));
+    // routers of the AS, in the order they were added
+    pub fn routers_of_as(&self, router_as: u32) -> &[String] {
+        self.router_as.get(&router_as).map(|routers| routers.as_slice()).unwrap_or(&[])
+    }
 
-        let routes1 = [(
+    pub async fn announce_prefix_as(&self, announcing_as: u32) -> Result<(), NetworkError> {
+        for router in self.router_as.get(&announcing_as).unwrap_or(&vec![]){
+            self.announce_prefix(router).await?;
+        }
+        Ok(())
+    }
+
+    // port used by router for its BGP session with neighbor
+    fn session_port(&self, router: &str, neighbor: &str) -> Option<u32> {
+        for (d1, p1, d2, p2, _) in self.peers.iter().chain(self.provider_customer.iter()){
+            if d1 == router && d2 == neighbor{
+                return Some(*p1);
+            }
+            if d2 == router && d1 == neighbor{
+                return Some(*p2);
+            }
+        }
+        None
+    }
+
+    pub async fn add_conditional_advertisement(
+        &self,
+        router: &str,
+        prefix: IPPrefix,
+        unless_present: IPPrefix,
+        session: &str,
+    ) -> Result<(), NetworkError> {
+        let (communicator, _) = self.router(router)?;
+        let port = self.session_port(router, session).ok_or(NetworkError::UnknownSession{router: router.to_string(), neighbor: session.to_string()})?;
+        communicator.add_conditional_advertisement(prefix, unless_present, port).await;
+        Ok(())
+    }
+
+    // pref applied by router to the routes learned from session, the pref of the relationship is restored with None
+    pub async fn set_local_pref(&self, router: &str, session: &str, pref: Option<u32>) -> Result<(), NetworkError> {
+        let (communicator, _) = self.router(router)?;
+        let port = self.session_port(router, session).ok_or(NetworkError::UnknownSession{router: router.to_string(), neighbor: session.to_string()})?;
+        communicator.set_local_pref(port, pref).await;
+        Ok(())
+    }
+
+    // routers linked to router, by port
+    fn linked_routers(&self, router: &str) -> Vec<(u32, Ipv4Addr)> {
+        let mut linked = vec![];
+        if let Some(links) = self.internal_links.get(router){
+            linked.extend(links.iter().map(|(port, device, _, _)| (*port, device)));
+        }
+        for (d1, p1, d2, p2, _) in self.peers.iter().chain(self.provider_customer.iter()){
+            if d1 == router{
+                linked.push((*p1, d2));
+            }
+            if d2 == router{
+                linked.push((*p2, d1));
+            }
+        }
+        linked.into_iter().filter_map(|(port, device)| self.routers.get(device).map(|(_, ip)| (port, *ip))).collect()
+    }
+
+    // route to prefix installed on router, kept whatever OSPF computes
+    pub async fn add_static_route(&self, router: &str, prefix: IPPrefix, nexthop: StaticNexthop) -> Result<(), NetworkError> {
+        let (communicator, _) = self.router(router)?;
+        let (port, nexthop_ip) = self.linked_routers(router).into_iter()
+            .find(|(port, ip)| match nexthop{
+                StaticNexthop::Port(p) => *port == p,
+                StaticNexthop::Address(address) => *ip == address,
+            })
+            .ok_or(NetworkError::UnknownNexthop{router: router.to_string(), nexthop: nexthop.to_string()})?;
+        communicator.add_static_route(prefix, port, nexthop_ip).await;
+        Ok(())
+    }
+
+    // a router without OSPF only forwards along its static routes and the BGP ones
+    pub async fn set_ospf(&mut self, router: &str, enabled: bool) -> Result<(), NetworkError> {
+        let (communicator, _) = self.router(router)?;
+        communicator.set_ospf(enabled).await;
+        match enabled{
+            true => self.ospf_disabled.remove(router),
+            false => self.ospf_disabled.insert(router.to_string()),
+        };
+        Ok(())
+    }
+
+    // MED sent by router on session set to its IGP distance to the nexthop of each route, instead of the MED of the link
+    pub async fn set_igp_med(&self, router: &str, session: &str, enabled: bool) -> Result<(), NetworkError> {
+        let (communicator, _) = self.router(router)?;
+        let port = self.session_port(router, session).ok_or(NetworkError::UnknownSession{router: router.to_string(), neighbor: session.to_string()})?;
+        communicator.set_igp_med(port, enabled).await;
+        Ok(())
+    }
+
+    // router spreads the flows over all the BGP routes as good as the best one, the packets of a flow take the same route
+    pub async fn set_multipath(&self, router: &str, enabled: bool) -> Result<(), NetworkError> {
+        let (communicator, _) = self.bgp_router(router)?;
+        communicator.set_multipath(enabled).await;
+        Ok(())
+    }
+
+    // the flows of router are spread over the ports of the shortest OSPF paths when there are several ones
+    pub async fn set_ecmp(&self, router: &str, enabled: bool) -> Result<(), NetworkError> {
+        let (communicator, _) = self.router(router)?;
+        communicator.set_ecmp(enabled).await;
+        Ok(())
+    }
+
+    // router prepends its AS count more times to the paths it advertises on the BGP session of port
+    pub async fn set_prepend(&self, router: &str, port: u32, count: u32) -> Result<(), NetworkError> {
+        let (communicator, _) = self.bgp_router(router)?;
+        communicator.set_prepend(port, count).await;
+        Ok(())
+    }
+
+    // router applies action to the routes tagged with community, the routes it already knows included
+    pub async fn add_community_policy(&self, router: &str, community: u32, action: CommunityAction) -> Result<(), NetworkError> {
+        let (communicator, _) = self.bgp_router(router)?;
+        communicator.add_community_policy(community, action).await;
+        Ok(())
+    }
+
+    // router accepts at most limit prefixes from the neighbor of port, action decides what happens with the next ones
+    pub async fn set_max_prefixes(&self, router: &str, port: u32, limit: u32, action: MaxPrefixAction) -> Result<(), NetworkError> {
+        let (communicator, _) = self.bgp_router(router)?;
+        communicator.set_max_prefixes(port, limit, action).await;
+        Ok(())
+    }
+
+    // policies router applies to the routes it receives and sends on the BGP session of port
+    pub async fn set_import_policy(&self, router: &str, port: u32, policy: RoutePolicy) -> Result<(), NetworkError> {
+        let (communicator, _) = self.bgp_router(router)?;
+        communicator.set_import_policy(port, policy).await;
+        Ok(())
+    }
+
+    pub async fn set_export_policy(&self, router: &str, port: u32, policy: RoutePolicy) -> Result<(), NetworkError> {
+        let (communicator, _) = self.bgp_router(router)?;
+        communicator.set_export_policy(port, policy).await;
+        Ok(())
+    }
+
+    // secret router expects from neighbor, checked when their session is opened so it is set before adding their link
+    pub async fn set_session_secret(&self, router: &str, neighbor: &str, secret: Option<&str>) -> Result<(), NetworkError> {
+        let (communicator, _) = self.router(router)?;
+        let (_, neighbor_ip) = self.router(neighbor)?;
+        communicator.set_session_secret(*neighbor_ip, secret.map(|s| s.to_string())).await;
+        Ok(())
+    }
+
+    // eBGP sessions of router, by neighbor
+    pub async fn get_bgp_sessions(&self, router: &str) -> Result<BTreeMap<String, BGPSession>, NetworkError> {
+        let src = &self.router(router)?.0;
+
+        let sessions = src.get_bgp_sessions()
+            .await
+            .map_err(|_| NetworkError::DeviceDead(router.to_string()))?;
+        Ok(sessions.into_iter().map(|session| {
+            let neighbor = self.routers.iter()
+                .find(|(_, (_, ip))| *ip == session.neighbor)
+                .map_or(session.neighbor.to_string(), |(name, _)| name.clone());
+            (neighbor, session)
+        }).collect())
+    }
+
+    pub async fn print_bgp_sessions(&self) -> Result<(), NetworkError> {
+        let mut sessions = BTreeMap::new();
+        for router in self.routers.keys() {
+            sessions.insert(router.clone(), self.get_bgp_sessions(router).await?);
+        }
+        print!("{}", output::render_bgp_sessions(&sessions, self.output_mode));
+        Ok(())
+    }
+
+    pub async fn get_routing_table(&self, router: &str) -> Result<HashMap<IPPrefix, RIBEntry>, NetworkError> {
+        let src = &self.router(router)?.0;
+
+        src.get_routing_table()
+            .await
+            .map_err(|_| NetworkError::DeviceDead(router.to_string()))
+    }
+
+    pub async fn get_bgp_routes(
+        &self,
+        router: &str,
+    ) -> Result<HashMap<IPPrefix, (Option<BGPRoute>, HashSet<BGPRoute>)>, NetworkError> {
+        let src = &self.router(router)?.0;
+
+        src.get_bgp_routes()
+            .await
+            .map_err(|_| NetworkError::DeviceDead(router.to_string()))
+    }
+
+    // BGP routes of the router sorted by prefix, the routes of a prefix sorted by their representation
+    pub async fn get_bgp_table(&self, router: &str) -> Result<BTreeMap<IPPrefix, BGPTableEntry>, NetworkError> {
+        let mut bgp_table = BTreeMap::new();
+        for (prefix, (best, routes)) in self.get_bgp_routes(router).await? {
+            let mut routes: Vec<BGPRoute> = routes.into_iter().collect();
+            routes.sort_by_key(|r| r.to_string());
+            bgp_table.insert(prefix, BGPTableEntry{best, routes});
+        }
+        Ok(bgp_table)
+    }
+
+    // the ports of the shortest paths of the destinations the router reaches over several ones, the routing table has the lowest one
+    pub async fn get_equal_cost_ports(&self, router: &str) -> Result<EqualCostPorts, NetworkError> {
+        let src = &self.router(router)?.0;
+
+        src.get_equal_cost_ports()
+            .await
+            .map_err(|_| NetworkError::DeviceDead(router.to_string()))
+    }
+
+    // the newest lsp of each originator known by the router, with its age
+    pub async fn get_lsdb(&self, router: &str) -> Result<Lsdb, NetworkError> {
+        let src = &self.router(router)?.0;
+
+        src.get_lsdb()
+            .await
+            .map_err(|_| NetworkError::DeviceDead(router.to_string()))
+    }
+
+    // routes as good as the best one the router also uses in multipath mode, with the port of their nexthop
+    pub async fn get_multipath_routes(&self, router: &str) -> Result<MultipathRoutes, NetworkError> {
+        let src = &self.router(router)?.0;
+
+        src.get_multipath_routes()
+            .await
+            .map_err(|_| NetworkError::DeviceDead(router.to_string()))
+    }
+
+    // routers whose routes changed during the last hello intervals or with messages waiting to be handled
+    async fn unsettled_routers(&self) -> Result<BTreeSet<String>, NetworkError> {
+        let mut unsettled = BTreeSet::new();
+        for (router, (communicator, _)) in self.routers.iter() {
+            let converged = communicator.is_converged().await.map_err(|_| NetworkError::DeviceDead(router.clone()))?;
+            if !converged{
+                unsettled.insert(router.clone());
+            }
+        }
+        Ok(unsettled)
+    }
+
+    // addresses of the routers running OSPF reached by router through the links between routers and switches
+    fn ospf_neighborhood(&self, router: &str) -> Vec<IPPrefix> {
+        let mut visited = HashSet::from([router.to_string()]);
+        let mut to_visit = vec![router.to_string()];
+        let mut addresses = vec![];
+        while let Some(device) = to_visit.pop(){
+            for (_, neighbor, _, _) in self.internal_links.get(&device).unwrap_or(&vec![]){
+                let ospf_router = self.routers.get(neighbor).filter(|_| !self.ospf_disabled.contains(neighbor));
+                if (ospf_router.is_none() && !self.switches.contains_key(neighbor)) || !visited.insert(neighbor.clone()){
+                    continue;
+                }
+                if let Some((_, ip)) = ospf_router{
+                    addresses.push(IPPrefix{ip: *ip, prefix_len: 32});
+                }
+                to_visit.push(neighbor.clone());
+            }
+        }
+        addresses
+    }
+
+    // wait until every router has stable routes towards the routers it is linked to, returns the time it took
+    pub async fn wait_for_ospf(&self, timeout: Duration) -> Result<Duration, NetworkError> {
+        let start = Instant::now();
+        loop{
+            let mut unsettled = self.unsettled_routers().await?;
+            for router in self.routers.keys().filter(|router| !self.ospf_disabled.contains(*router)){
+                let routing_table = self.get_routing_table(router).await?;
+                if self.ospf_neighborhood(router).iter().any(|address| !routing_table.contains_key(address)){
+                    unsettled.insert(router.clone());
+                }
+            }
+            if unsettled.is_empty(){
+                return Ok(start.elapsed());
+            }
+            if start.elapsed() >= timeout{
+                return Err(NetworkError::NotConverged{timeout, routers: unsettled.into_iter().collect()});
+            }
+            tokio::time::sleep(CONVERGENCE_POLL_INTERVAL).await;
+        }
+    }
+
+    // wait until the routing tables and the BGP routes of every router stop changing, returns the time it took
+    pub async fn wait_for_bgp(&self, timeout: Duration) -> Result<Duration, NetworkError> {
+        let start = Instant::now();
+        loop{
+            let unsettled = self.unsettled_routers().await?;
+            if unsettled.is_empty(){
+                return Ok(start.elapsed());
+            }
+            if start.elapsed() >= timeout{
+                return Err(NetworkError::NotConverged{timeout, routers: unsettled.into_iter().collect()});
+            }
+            tokio::time::sleep(CONVERGENCE_POLL_INTERVAL).await;
+        }
+    }
+
+    // wait_for_ospf then wait_for_bgp, within timeout in total
+    pub async fn wait_for_convergence(&self, timeout: Duration) -> Result<Duration, NetworkError> {
+        let ospf = self.wait_for_ospf(timeout).await?;
+        let bgp = self.wait_for_bgp(timeout.saturating_sub(ospf)).await.map_err(|e| match e{
+            NetworkError::NotConverged{routers, ..} => NetworkError::NotConverged{timeout, routers},
+            e => e,
+        })?;
+        Ok(ospf + bgp)
+    }
+
+    pub async fn quit(mut self) {
+        self.traffic.stop();
+        self.stop_capture().await;
+        for (_, (communicator, _)) in self.switches {
+            communicator.quit().await;
+        }
+
+        for (_, (communicator, _)) in self.routers {
+            communicator.quit().await;
+        }
+
+        for (_, (communicator, _, _)) in self.hosts {
+            communicator.quit().await;
+        }
+
+        // the devices finish the command they were handling, their last messages are then logged
+        let tasks = self.tasks;
+        let finished = tokio::time::timeout(QUIT_TIMEOUT, async move {
+            for (_, task) in tasks{
+                let _ = task.await;
+            }
+        }).await;
+        if finished.is_err(){
+            self.logger.log(Source::DEBUG, format!("Devices still running {}ms after quitting", QUIT_TIMEOUT.as_millis())).await;
+        }
+        self.logger.shutdown().await;
+    }
+
+    pub async fn get_port_states(&self) -> Result<BTreeMap<String, BTreeMap<u32, PortState>>, NetworkError> {
+        let mut states = BTreeMap::new();
+        for (switch, (communicator, _)) in self.switches.iter() {
+            let ports_states = communicator
+                .get_port_state()
+                .await
+                .map_err(|_| NetworkError::DeviceDead(switch.clone()))?;
+            states.insert(switch.clone(), ports_states);
+        }
+        Ok(states)
+    }
+
+    // port of switch towards each mac address it learned
+    pub async fn get_mac_table(&self, switch: &str) -> Result<HashMap<MacAddress, u32>, NetworkError> {
+        let (communicator, _) = self.switches.get(switch).ok_or(NetworkError::UnknownDevice(switch.to_string()))?;
+
+        communicator.get_mac_table()
+            .await
+            .map_err(|_| NetworkError::DeviceDead(switch.to_string()))
+    }
+
+    pub async fn export_state(&self) -> Result<NetworkState, NetworkError> {
+        let mut state = NetworkState{port_states: self.get_port_states().await?, ..Default::default()};
+        for router in self.routers.keys() {
+            let routing_table = self.get_routing_table(router).await?;
+            state.routing_tables.insert(router.clone(), routing_table.into_iter().collect());
+
+            state.bgp_tables.insert(router.clone(), self.get_bgp_table(router).await?);
+        }
+        Ok(state)
+    }
+
+    pub async fn print_switch_states(&self) -> Result<(), NetworkError> {
+        let states = self.get_port_states().await?;
+        print!("{}", output::render_port_states(&states, self.output_mode));
+        Ok(())
+    }
+
+    pub async fn print_routing_table(&self, router: &str) -> Result<(), NetworkError> {
+        let routing_table = self.get_routing_table(router).await?;
+        let multipath = self.get_multipath_routes(router).await?;
+        let equal_cost = self.get_equal_cost_ports(router).await?;
+        print!("{}", output::render_routing_table(router, &routing_table, &multipath, &equal_cost, self.output_mode));
+        Ok(())
+    }
+
+    pub async fn print_routing_tables(&self) -> Result<(), NetworkError> {
+        for router in self.routers.keys() {
+            self.print_routing_table(router).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn print_bgp_table(&self, router: &str) -> Result<(), NetworkError> {
+        let bgp_table = self.get_bgp_routes(router).await?;
+        let multipath = self.get_multipath_routes(router).await?;
+        print!("{}", output::render_bgp_table(router, &bgp_table, &multipath, self.output_mode));
+        Ok(())
+    }
+
+    pub async fn print_bgp_tables(&self) -> Result<(), NetworkError> {
+        for router in self.routers.keys() {
+            self.print_bgp_table(router).await?;
+        }
+        Ok(())
+    }
+
+    // for each pair of routers, whether the forwarding table of the first one has a route towards the second one
+    pub async fn reachability(&self) -> Result<BTreeMap<String, BTreeMap<String, bool>>, NetworkError> {
+        let mut matrix = BTreeMap::new();
+        for router in self.routers.keys() {
+            let mut prefixes = IPTrie::new();
+            for prefix in self.get_routing_table(router).await?.keys() {
+                prefixes.insert(*prefix, *prefix);
+            }
+            let mut reachable = BTreeMap::new();
+            for (other, (_, ip)) in self.routers.iter() {
+                if other != router {
+                    reachable.insert(other.clone(), prefixes.longest_match(*ip).is_some());
+                }
+            }
+            matrix.insert(router.clone(), reachable);
+        }
+        Ok(matrix)
+    }
+
+    pub async fn print_reachability(&self) -> Result<(), NetworkError> {
+        let matrix = self.reachability().await?;
+        print!("{}", output::render_reachability(&matrix, self.output_mode));
+        Ok(())
+    }
+
+    // devices and links of the network, the ones its DOT graphs draw
+    pub fn topology(&self) -> Topology {
+        Topology{
+            router_as: self.router_as.clone(),
+            as_router: self.as_router.clone(),
+            switches: self.switches.keys().cloned().collect(),
+            hosts: self.hosts.keys().cloned().collect(),
+            used_port: self.used_port.clone(),
+            internal_links: self.internal_links.clone(),
+            provider_customer: self.provider_customer.clone(),
+            peers: self.peers.clone(),
+        }
+    }
+
+    // the ports of the switches are labelled with their states and the ones of the routers with their cost or pref
+    pub async fn dot_representation(&self) -> Result<String, NetworkError> {
+        let states = self.get_port_states().await?;
+        let mut router_ports = BTreeMap::new();
+        for router in self.routers.keys(){
+            router_ports.insert(router.clone(), self.get_router_ports(router).await?);
+        }
+        Ok(self.topology().dot_graph(Some(&states), Some(&router_ports), None))
+    }
+
+    // the same graph without the port states of the switches, the devices aren't queried
+    pub fn dot_string(&self) -> String {
+        self.topology().dot_graph(None, None, None)
+    }
+
+    // the topology with the links of the active spanning tree in green, the links with a blocked port dashed in light grey
+    // and the root bridge, the switch of the lowest id, as a double circle
+    pub async fn dot_spanning_tree(&self) -> Result<String, NetworkError> {
+        let states = self.get_port_states().await?;
+        let root = self.switches.iter().min_by_key(|(_, (_, id))| *id).map(|(switch, _)| switch.clone());
+        Ok(self.topology().dot_graph(Some(&states), None, Some(&Highlight::SpanningTree{root})))
+    }
+
+    // egress link of each router towards dest, taken from the longest prefix of its routing table like its packets
+    pub async fn forwarding(&self, dest: Ipv4Addr) -> Result<BTreeMap<String, ForwardingHop>, NetworkError> {
+        let mut hops = BTreeMap::new();
+        for router in self.routers.keys() {
+            let mut table = IPTrie::new();
+            for (prefix, entry) in self.get_routing_table(router).await? {
+                table.insert(prefix, entry);
+            }
+            let hop = match table.longest_match(dest) {
+                Some((0, _, _)) => ForwardingHop::Local,
+                Some((egress, _, _)) => match self.link_end(router, egress) {
+                    Some((device, port)) => ForwardingHop::Link{egress, device, port},
+                    None => ForwardingHop::Unreachable,
+                },
+                None => ForwardingHop::Unreachable,
+            };
+            hops.insert(router.clone(), hop);
+        }
+        Ok(hops)
+    }
+
+    // cycles of routers forwarding the packets towards dest to each other, found in their routing tables without sending packets
+    // each cycle starts with its smallest router, a walk stops at a switch or a host
+    pub async fn find_loops(&self, dest: Ipv4Addr) -> Result<Vec<Vec<String>>, NetworkError> {
+        let hops = self.forwarding(dest).await?;
+        let mut loops = BTreeSet::new();
+        for router in hops.keys() {
+            let mut path = vec![router.clone()];
+            while let Some(ForwardingHop::Link{device, ..}) = hops.get(path.last().unwrap()) {
+                if let Some(position) = path.iter().position(|hop| hop == device) {
+                    let mut cycle = path.split_off(position);
+                    let first = cycle.iter().enumerate().min_by_key(|(_, hop)| *hop).map(|(i, _)| i).unwrap();
+                    cycle.rotate_left(first);
+                    loops.insert(cycle);
+                    break;
+                }
+                path.push(device.clone());
+            }
+        }
+        Ok(loops.into_iter().collect())
+    }
+
+    // the topology with the egress link of each router towards dest drawn in green, the device owning dest filled
+    // and the routers without route towards it in grey
+    pub async fn dot_forwarding(&self, dest: Ipv4Addr) -> Result<String, NetworkError> {
+        let mut view = ForwardingView::default();
+        for (router, hop) in self.forwarding(dest).await? {
+            match hop {
+                ForwardingHop::Local => view.destinations.insert(router),
+                ForwardingHop::Link{egress, ..} => view.egress.insert((router, egress)),
+                ForwardingHop::Unreachable => view.unreachable.insert(router),
+            };
+        }
+        view.destinations.extend(self.hosts.iter().filter(|(_, (_, ip, _))| *ip == dest).map(|(host, _)| host.clone()));
+        Ok(self.topology().dot_graph(None, None, Some(&Highlight::Forwarding(view))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use health::{InterfaceState, PortKind, SessionHealth};
+    use capture::{Capture, CaptureRecord, Direction, Protocol};
+    use link::{LinkRate, QueueDiscipline};
+    use messages::bgp::BGPMessage;
+    use config::ConfigFile;
+    use events::wait_for_event;
+    use scenario::{BGP_CONVERGENCE, CONVERGENCE_TIMEOUT, IGP_CONVERGENCE};
+    use stats::MessageKind;
+    use protocols::{bgp::{RouteSource, SessionState, CUSTOMER_PREF, PEER_PREF, PROVIDER_PREF}, ospf::LsaInfo, policy::{PolicyAction, PolicyRule}};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use PortState::*;
+    use rib::RouteOrigin::*;
+
+    // longest wait for a message to reach the captured logs
+    const LOG_TIMEOUT: Duration = Duration::from_millis(500);
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 6)]
+    async fn test_spanning_tree() {
+        for _ in 0..10 {
+            let logger = Logger::start_test();
+            let mut network = Network::new(logger);
+            network.add_switch("s1", 1);
+            network.add_switch("s2", 2);
+            network.add_switch("s3", 3);
+            network.add_switch("s4", 4);
+            network.add_switch("s6", 6);
+            network.add_switch("s9", 9);
+
+            network.add_link("s1", 1, "s2", 1, 1).await.unwrap();
+            network.add_link("s1", 2, "s4", 1, 1).await.unwrap();
+            network.add_link("s2", 2, "s9", 1, 1).await.unwrap();
+            network.add_link("s4", 2, "s9", 2, 1).await.unwrap();
+            network.add_link("s4", 3, "s3", 1, 1).await.unwrap();
+            network.add_link("s9", 3, "s3", 2, 1).await.unwrap();
+            network.add_link("s9", 4, "s6", 1, 1).await.unwrap();
+            network.add_link("s3", 3, "s6", 2, 1).await.unwrap();
+
+            // wait for convergence
+            thread::sleep(Duration::from_millis(250));
+
+            let switch_states = network.get_port_states().await.unwrap();
+
+            let mut expected: BTreeMap<String, BTreeMap<u32, PortState>> = BTreeMap::new();
+            expected.insert(
+                "s1".into(),
+                [(1, Designated), (2, Designated)].into_iter().collect(),
+            );
+            expected.insert(
+                "s2".into(),
+                [(1, Root), (2, Designated)].into_iter().collect(),
+            );
+            expected.insert(
+                "s3".into(),
+                [(1, Root), (2, Designated), (3, Designated)]
+                    .into_iter()
+                    .collect(),
+            );
+            expected.insert(
+                "s4".into(),
+                [(1, Root), (2, Designated), (3, Designated)]
+                    .into_iter()
+                    .collect(),
+            );
+            expected.insert("s6".into(), [(1, Blocked), (2, Root)].into_iter().collect());
+            expected.insert(
+                "s9".into(),
+                [(1, Root), (2, Blocked), (3, Blocked), (4, Designated)]
+                    .into_iter()
+                    .collect(),
+            );
+
+            assert_eq!(expected, switch_states);
+
+            // the blocked ports still hear the bpdus of their designated neighbor but forward no frame
+            for (switch, port) in [("s6", 1), ("s9", 2), ("s9", 3)]{
+                let stats = network.get_stats(switch).await.unwrap();
+                assert!(stats.on_port(Direction::Received, port, MessageKind::Bpdu) > 0);
+                assert_eq!(stats.on_port(Direction::Sent, port, MessageKind::EthernetFrame), 0);
+            }
+
+            network.quit().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_spanning_tree_root_dies() {
+        for _ in 0..3 {
+            let logger = Logger::start_test();
+            let mut network = Network::new(logger);
+            network.add_switch("s1", 1);
+            network.add_switch("s2", 2);
+            network.add_switch("s3", 3);
+            network.add_switch("s4", 4);
+            network.add_switch("s6", 6);
+            network.add_switch("s9", 9);
+
+            network.add_link("s1", 1, "s2", 1, 1).await.unwrap();
+            network.add_link("s1", 2, "s4", 1, 1).await.unwrap();
+            network.add_link("s2", 2, "s9", 1, 1).await.unwrap();
+            network.add_link("s4", 2, "s9", 2, 1).await.unwrap();
+            network.add_link("s4", 3, "s3", 1, 1).await.unwrap();
+            network.add_link("s9", 3, "s3", 2, 1).await.unwrap();
+            network.add_link("s9", 4, "s6", 1, 1).await.unwrap();
+            network.add_link("s3", 3, "s6", 2, 1).await.unwrap();
+
+            // wait for convergence
+            thread::sleep(Duration::from_millis(250));
+
+            network.stop_device("s1").await.unwrap();
+
+            // wait for the bpdus of s1 to expire and for the new convergence
+            thread::sleep(Duration::from_millis(2000));
+
+            let switch_states = network.get_port_states().await.unwrap();
+
+            // s2 is the new root, the ports towards s1 are designated as nobody else claims them
+            let mut expected: BTreeMap<String, BTreeMap<u32, PortState>> = BTreeMap::new();
+            expected.insert(
+                "s2".into(),
+                [(1, Designated), (2, Designated)].into_iter().collect(),
+            );
+            expected.insert(
+                "s3".into(),
+                [(1, Designated), (2, Root), (3, Designated)]
+                    .into_iter()
+                    .collect(),
+            );
+            expected.insert(
+                "s4".into(),
+                [(1, Designated), (2, Root), (3, Blocked)]
+                    .into_iter()
+                    .collect(),
+            );
+            expected.insert("s6".into(), [(1, Root), (2, Blocked)].into_iter().collect());
+            expected.insert(
+                "s9".into(),
+                [(1, Root), (2, Designated), (3, Designated), (4, Designated)]
+                    .into_iter()
+                    .collect(),
+            );
+
+            assert_eq!(expected, switch_states);
+
+            network.quit().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_port_shutdown() {
+        let mut network = Network::new(Logger::start_test());
+        for id in [1, 2, 3, 4, 6, 9] {
+            network.add_switch(&format!("s{}", id), id);
+        }
+        network.add_link("s1", 1, "s2", 1, 1).await.unwrap();
+        network.add_link("s1", 2, "s4", 1, 1).await.unwrap();
+        network.add_link("s2", 2, "s9", 1, 1).await.unwrap();
+        network.add_link("s4", 2, "s9", 2, 1).await.unwrap();
+        network.add_link("s4", 3, "s3", 1, 1).await.unwrap();
+        network.add_link("s9", 3, "s3", 2, 1).await.unwrap();
+        network.add_link("s9", 4, "s6", 1, 1).await.unwrap();
+        network.add_link("s3", 3, "s6", 2, 1).await.unwrap();
+        thread::sleep(Duration::from_millis(250));
+        let tree = network.get_port_states().await.unwrap();
+        assert_eq!(tree["s9"], [(1, Root), (2, Blocked), (3, Blocked), (4, Designated)].into_iter().collect());
+
+        // s9 reaches the root through s4 instead of s2, the rest of the tree doesn't change
+        let mut events = network.subscribe_events();
+        network.set_port_enabled("s9", 1, false).await.unwrap();
+        let event = wait_for_event(&mut events, Duration::from_millis(250), |event| matches!(event, NetworkEvent::StpPortStateChanged{port: 2, ..})).await;
+        assert_eq!(event, Some(NetworkEvent::StpPortStateChanged{switch: "s9".into(), port: 2, old: Blocked, new: Root}));
+        let mut expected = tree.clone();
+        expected.insert("s9".into(), [(1, Disabled), (2, Root), (3, Blocked), (4, Designated)].into_iter().collect());
+        assert_eq!(network.get_port_states().await.unwrap(), expected);
+        let received = network.get_stats("s9").await.unwrap().on_port(Direction::Received, 1, MessageKind::Bpdu);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(network.get_stats("s9").await.unwrap().on_port(Direction::Received, 1, MessageKind::Bpdu), received);
+
+        network.set_port_enabled("s9", 1, true).await.unwrap();
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(network.get_port_states().await.unwrap(), tree);
+
+        assert_eq!(network.set_port_enabled("s9", 5, false).await, Err(NetworkError::NoLinkOnPort{device: "s9".into(), port: 5}));
+        assert_eq!(network.set_port_enabled("s5", 1, false).await, Err(NetworkError::UnknownDevice("s5".into())));
+        network.quit().await;
+
+        // a router shut down on a port routes around it, and through it again once enabled
+        let mut network = Network::new(Logger::start_test());
+        for id in 1..=3 {
+            network.add_router(&format!("r{}", id), id, 1).unwrap();
+        }
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r1", 2, "r3", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 2, 1).await.unwrap();
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        let r2 = "10.0.1.2/32".parse().unwrap();
+        assert_eq!(network.get_routing_table("r1").await.unwrap()[&r2], (1, 1, Ospf));
+
+        let mut events = network.subscribe_events();
+        network.set_port_enabled("r1", 1, false).await.unwrap();
+        assert_eq!(network.get_routing_table("r1").await.unwrap()[&r2], (2, 2, Ospf));
+        // r2 forgets r1 once its hellos went unanswered for the dead interval
+        let r1 = "10.0.1.1/32".parse().unwrap();
+        let rerouted = NetworkEvent::OspfRouteChanged{router: "r2".into(), prefix: r1};
+        let event = wait_for_event(&mut events, protocols::ospf::DEAD_INTERVAL + 4 * utils::HELLO_INTERVAL, |event| *event == rerouted).await;
+        assert_eq!(event, Some(rerouted));
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        assert_eq!(network.get_routing_table("r2").await.unwrap()[&r1], (2, 2, Ospf));
+
+        // the routes only change once the hellos found the neighbor again
+        network.set_port_enabled("r1", 1, true).await.unwrap();
+        thread::sleep(2 * utils::HELLO_INTERVAL);
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        assert_eq!(network.get_routing_table("r1").await.unwrap()[&r2], (1, 1, Ospf));
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_parallel_links() {
+        // two links between r1 and r2, the expensive one is a backup
+        let mut network = Network::new(Logger::start_test());
+        for id in 1..=3 {
+            network.add_router(&format!("r{}", id), id, 1).unwrap();
+        }
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r1", 2, "r2", 2, 5).await.unwrap();
+        network.add_link("r2", 3, "r3", 1, 1).await.unwrap();
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        let (r2, r3) = ("10.0.1.2/32".parse().unwrap(), "10.0.1.3/32".parse().unwrap());
+        let table = network.get_routing_table("r1").await.unwrap();
+        assert_eq!((table[&r2], table[&r3]), ((1, 1, Ospf), (1, 2, Ospf)));
+        assert_eq!(network.get_routing_table("r3").await.unwrap()[&"10.0.1.1/32".parse().unwrap()], (1, 2, Ospf));
+        let frames = |stats: &DeviceStats, port| stats.sent.get(&(port, MessageKind::EthernetFrame)).copied().unwrap_or(0);
+        let before = network.get_stats("r1").await.unwrap();
+        network.ping("r1", r3.ip).await.unwrap();
+        let after = network.get_stats("r1").await.unwrap();
+        assert!(frames(&after, 1) > frames(&before, 1));
+        assert_eq!(frames(&after, 2), frames(&before, 2));
+
+        // both links are drawn
+        let dot = network.dot_string();
+        assert_eq!(dot.lines().filter(|line| line.contains("r1 -> r2")).count(), 2);
+
+        // the traffic falls back to the other link once the first one is shut down
+        network.set_port_enabled("r1", 1, false).await.unwrap();
+        let table = network.get_routing_table("r1").await.unwrap();
+        assert_eq!((table[&r2], table[&r3]), ((2, 5, Ospf), (2, 6, Ospf)));
+        // r2 uses the other link back once r1 stopped answering its hellos on the first one
+        thread::sleep(protocols::ospf::DEAD_INTERVAL + 2 * utils::HELLO_INTERVAL);
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        assert_eq!(network.get_routing_table("r2").await.unwrap()[&"10.0.1.1/32".parse().unwrap()], (2, 5, Ospf));
+        network.ping("r1", r3.ip).await.unwrap();
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_gratuitous_arp() {
+        // the addresses are resolved again much later than the hellos, only the gratuitous replies tell them in time
+        let timers = TimerConfig{arp_refresh_interval: 10 * utils::HELLO_INTERVAL, ..TimerConfig::default()};
+        let mut network = Network::new(Logger::start_test());
+        network.add_router_with_config("r1", 1, 1, timers.clone()).unwrap();
+        network.add_router_with_config("r2", 2, 2, timers).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let start = Instant::now();
+        network.add_peer_link("r1", 1, "r2", 1, 0, (None, None)).await.unwrap();
+        let r2 = "10.0.2.2".parse().unwrap();
+        // the pings sent before the routers learned their addresses are never answered, a new one is sent instead
+        let mut answered = None;
+        while answered.is_none() && start.elapsed() < 2 * utils::HELLO_INTERVAL{
+            if let Ok(Ok(_)) = tokio::time::timeout(Duration::from_millis(10), network.ping("r1", r2)).await{
+                answered = Some(start.elapsed());
+            }
+        }
+        assert!(answered.is_some_and(|elapsed| elapsed < utils::HELLO_INTERVAL), "first ping answered after {:?}", answered);
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_link_cost() {
+        // s3 reaches the root s1 through s2 once its direct link costs more than the two hops
+        let mut network = Network::new(Logger::start_test());
+        for id in 1..=3 {
+            network.add_switch(&format!("s{}", id), id);
+        }
+        network.add_link("s1", 1, "s2", 1, 1).await.unwrap();
+        network.add_link("s1", 2, "s3", 1, 1).await.unwrap();
+        network.add_link("s2", 2, "s3", 2, 1).await.unwrap();
+        thread::sleep(Duration::from_millis(250));
+        let tree = network.get_port_states().await.unwrap();
+        assert_eq!(tree["s3"], [(1, Root), (2, Blocked)].into_iter().collect());
+
+        let mut events = network.subscribe_events();
+        network.set_link_cost("s3", 1, 5).await.unwrap();
+        let event = wait_for_event(&mut events, Duration::from_millis(250), |event| matches!(event, NetworkEvent::StpPortStateChanged{port: 1, ..})).await;
+        assert_eq!(event, Some(NetworkEvent::StpPortStateChanged{switch: "s3".into(), port: 1, old: Root, new: Blocked}));
+        thread::sleep(Duration::from_millis(100));
+        let mut expected = tree.clone();
+        expected.insert("s3".into(), [(1, Blocked), (2, Root)].into_iter().collect());
+        assert_eq!(network.get_port_states().await.unwrap(), expected);
+
+        network.set_link_cost("s1", 2, 1).await.unwrap();
+        thread::sleep(Duration::from_millis(250));
+        assert_eq!(network.get_port_states().await.unwrap(), tree);
+        assert_eq!(network.set_link_cost("s2", 3, 5).await, Err(NetworkError::NoLinkOnPort{device: "s2".into(), port: 3}));
+        assert_eq!(network.set_link_cost("s4", 1, 5).await, Err(NetworkError::UnknownDevice("s4".into())));
+        network.quit().await;
+
+        // the routers at both ends of the link route around it once it costs more than the other path
+        let mut network = Network::new(Logger::start_test());
+        for id in 1..=3 {
+            network.add_router(&format!("r{}", id), id, 1).unwrap();
+        }
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r1", 2, "r3", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 2, 1).await.unwrap();
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        let (r1, r2) = ("10.0.1.1/32".parse().unwrap(), "10.0.1.2/32".parse().unwrap());
+        assert_eq!(network.get_routing_table("r1").await.unwrap()[&r2], (1, 1, Ospf));
+
+        network.set_link_cost("r1", 1, 10).await.unwrap();
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        assert_eq!(network.get_routing_table("r1").await.unwrap()[&r2], (2, 2, Ospf));
+        assert_eq!(network.get_routing_table("r2").await.unwrap()[&r1], (2, 2, Ospf));
+        assert_eq!(network.get_routing_table("r3").await.unwrap()[&r2], (2, 1, Ospf));
+
+        network.set_link_cost("r2", 1, 1).await.unwrap();
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        assert_eq!(network.get_routing_table("r1").await.unwrap()[&r2], (1, 1, Ospf));
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_ospf() {
+        for _ in 0..10 {
+            let logger = Logger::start_test();
+            let mut network = Network::new(logger);
+            network.add_router("r1", 1, 1).unwrap();
+            network.add_router("r2", 2, 1).unwrap();
+            network.add_router("r3", 3, 1).unwrap();
+            network.add_router("r4", 4, 1).unwrap();
+
+            network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+            network.add_link("r1", 2, "r3", 1, 1).await.unwrap();
+            network.add_link("r3", 3, "r4", 1, 1).await.unwrap();
+            network.add_link("r2", 2, "r3", 2, 1).await.unwrap();
+
+            network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+            assert_eq!(
+                network.get_routing_table("r1").await.unwrap(),
+                [
+                    ("10.0.1.1/32".parse().unwrap(), (0, 0, Connected)),
+                    ("10.0.1.2/32".parse().unwrap(), (1, 1, Ospf)),
+                    ("10.0.1.3/32".parse().unwrap(), (2, 1, Ospf)),
+                    ("10.0.1.4/32".parse().unwrap(), (2, 2, Ospf))
+                ]
+                .into_iter()
+                .collect()
+            );
+
+            assert_eq!(
+                network.get_routing_table("r2").await.unwrap(),
+                [
+                    ("10.0.1.1/32".parse().unwrap(), (1, 1, Ospf)),
+                    ("10.0.1.2/32".parse().unwrap(), (0, 0, Connected)),
+                    ("10.0.1.3/32".parse().unwrap(), (2, 1, Ospf)),
+                    ("10.0.1.4/32".parse().unwrap(), (2, 2, Ospf))
+                ]
+                .into_iter()
+                .collect()
+            );
+
+            assert_eq!(
+                network.get_routing_table("r3").await.unwrap(),
+                [
+                    ("10.0.1.1/32".parse().unwrap(), (1, 1, Ospf)),
+                    ("10.0.1.2/32".parse().unwrap(), (2, 1, Ospf)),
+                    ("10.0.1.3/32".parse().unwrap(), (0, 0, Connected)),
+                    ("10.0.1.4/32".parse().unwrap(), (3, 1, Ospf))
+                ]
+                .into_iter()
+                .collect()
+            );
+
+            assert_eq!(
+                network.get_routing_table("r4").await.unwrap(),
+                [
+                    ("10.0.1.1/32".parse().unwrap(), (1, 2, Ospf)),
+                    ("10.0.1.2/32".parse().unwrap(), (1, 2, Ospf)),
+                    ("10.0.1.3/32".parse().unwrap(), (1, 1, Ospf)),
+                    ("10.0.1.4/32".parse().unwrap(), (0, 0, Connected))
+                ]
+                .into_iter()
+                .collect()
+            );
+
+            // each router received the lsps of the 3 others, flooded on its links
+            for router in ["r1", "r2", "r3", "r4"]{
+                let stats = network.get_stats(router).await.unwrap();
+                assert!(stats.total(Direction::Received, MessageKind::OspfLsp) >= 3, "{:?}", stats);
+                assert!(stats.total(Direction::Sent, MessageKind::OspfHello) > 0, "{:?}", stats);
+            }
+
+            network.quit().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_fast_hello_interval() {
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        let timers = TimerConfig{hello_interval: Duration::from_millis(20), ..TimerConfig::default()};
+        network.add_router_with_config("r1", 1, 1, timers.clone()).unwrap();
+        network.add_router_with_config("r2", 2, 1, timers.clone()).unwrap();
+        network.add_router_with_config("r3", 3, 1, timers.clone()).unwrap();
+        network.add_router_with_config("r4", 4, 1, timers).unwrap();
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r1", 2, "r3", 1, 1).await.unwrap();
+        network.add_link("r3", 3, "r4", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 2, 1).await.unwrap();
+
+        // wait for r1 to know the whole network
+        let start = std::time::Instant::now();
+        let mut table = network.get_routing_table("r1").await.unwrap();
+        while table.len() < 4 && start.elapsed() < Duration::from_secs(1){
+            thread::sleep(Duration::from_millis(5));
+            table = network.get_routing_table("r1").await.unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_millis(125), "converged in {:?}", start.elapsed());
+        assert_eq!(table[&"10.0.1.4/32".parse().unwrap()], (2, 2, Ospf));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_ospf_dead_router() {
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+        network.add_router("r4", 4, 1).unwrap();
+
+        // ring r1 - r2 - r3 - r4 - r1
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 1, 1).await.unwrap();
+        network.add_link("r3", 2, "r4", 1, 1).await.unwrap();
+        network.add_link("r4", 2, "r1", 2, 1).await.unwrap();
+
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let dead: IPPrefix = "10.0.1.2/32".parse().unwrap();
+        assert_eq!(network.get_routing_table("r1").await.unwrap()[&dead], (1, 1, Ospf));
+
+        network.stop_device("r2").await.unwrap();
+
+        // wait for the dead interval to elapse and for the new lsps to be flooded
+        thread::sleep(protocols::ospf::DEAD_INTERVAL + 2 * utils::HELLO_INTERVAL);
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        assert_eq!(
+            network.get_routing_table("r1").await.unwrap(),
+            [
+                ("10.0.1.1/32".parse().unwrap(), (0, 0, Connected)),
+                ("10.0.1.3/32".parse().unwrap(), (2, 2, Ospf)),
+                ("10.0.1.4/32".parse().unwrap(), (2, 1, Ospf))
+            ]
+            .into_iter()
+            .collect()
+        );
+        for router in ["r3", "r4"]{
+            let table = network.get_routing_table(router).await.unwrap();
+            assert!(!table.contains_key(&dead), "{} still has a route towards r2 : {:?}", router, table);
+        }
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_reuse_ports_of_stopped_device() {
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 2).unwrap();
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_provider_customer_link("r2", 2, "r3", 1, 0, (None, None)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        // r4 replaces r2 on the ports of its neighbors
+        network.stop_device("r2").await.unwrap();
+        network.add_router("r4", 4, 1).unwrap();
+        network.add_link("r1", 1, "r4", 1, 1).await.unwrap();
+        network.add_provider_customer_link("r4", 2, "r3", 1, 0, (None, None)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        assert_eq!(network.get_routing_table("r1").await.unwrap().get(&"10.0.1.4/32".parse().unwrap()), Some(&(1, 1, Ospf)));
+        let sessions = network.get_bgp_sessions("r3").await.unwrap();
+        assert_eq!(sessions["r4"].state, SessionState::Established);
+        assert_eq!(sessions.len(), 1);
+
+        network.remove_link("r1", 1, "r4", 1).await.unwrap();
+        assert!(network.add_link("r1", 1, "r3", 2, 1).await.is_ok());
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_ospf_restarted_router() {
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        for i in 1..=4{
+            network.add_router(&format!("r{}", i), i, 1).unwrap();
+        }
+        // r2 is the center of a star
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 1, 1).await.unwrap();
+        network.add_link("r2", 3, "r4", 1, 1).await.unwrap();
+
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.stop_device("r2").await.unwrap();
+        thread::sleep(protocols::ospf::DEAD_INTERVAL + 2 * utils::HELLO_INTERVAL);
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        // the new r2 numbers its lsps from 0 again and is no longer linked to r4, its lsps must replace the old ones
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_link("r1", 2, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 2, 1).await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let table = network.get_routing_table("r1").await.unwrap();
+        assert_eq!(table[&"10.0.1.3/32".parse().unwrap()], (2, 2, Ospf));
+        assert!(!table.contains_key(&"10.0.1.4/32".parse().unwrap()), "r1 still routes to r4 : {:?}", table);
+        network.ping("r1", "10.0.1.3".parse().unwrap()).await.unwrap();
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_crash_and_restart_router() {
+        let config = scenario::load_config("examples/ospf-example.yaml").unwrap();
+        let mut network = Network::new(Logger::start_test());
+        scenario::build_network(&mut network, &config).await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+        let r4: IPPrefix = "10.0.1.4/32".parse().unwrap();
+        network.ping("r1", r4.ip).await.unwrap();
+
+        // r4 is only reached through r3, r1 forgets it once r3 stopped answering its hellos
+        // until r2 also noticed the crash, r1 may route towards r4 through r2 and s1
+        let mut events = network.subscribe_events();
+        network.crash_router("r3").await.unwrap();
+        assert_eq!(network.restart_router("r1").await, Err(NetworkError::NotCrashed("r1".into())));
+        let lost = NetworkEvent::OspfRouteChanged{router: "r1".into(), prefix: r4};
+        let forgotten = tokio::time::timeout(2 * protocols::ospf::DEAD_INTERVAL + 4 * utils::HELLO_INTERVAL, async {
+            while network.get_routing_table("r1").await.unwrap().contains_key(&r4){
+                wait_for_event(&mut events, utils::HELLO_INTERVAL, |event| *event == lost).await;
+            }
+        }).await;
+        assert!(forgotten.is_ok(), "r1 still routes to r4");
+        assert!(network.ping("r1", r4.ip).await.is_err());
+
+        // the new r3 learns everything again on the same links
+        network.restart_router("r3").await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+        let (port, _) = network.link_ports("r1", "r3").unwrap();
+        assert_eq!(network.get_routing_table("r1").await.unwrap()[&r4], (port, 2, Ospf));
+        network.ping("r1", r4.ip).await.unwrap();
+        assert_eq!(network.link_ports("r3", "r4"), Some((2, 1)));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_restart_router_after_neighbor_changes() {
+        let config = scenario::load_config("examples/ospf-example.yaml").unwrap();
+        let mut network = Network::new(Logger::start_test());
+        scenario::build_network(&mut network, &config).await.unwrap();
+        let (r1_port, _) = network.link_ports("r1", "r3").unwrap();
+        network.crash_router("r3").await.unwrap();
+        network.stop_device("r4").await.unwrap();
+
+        // the port of r1 towards r3 was given to another link, r3 stays crashed and can be restarted later
+        let r2_port = network.next_free_port("r2").unwrap();
+        network.add_link("r1", r1_port, "r2", r2_port, 1).await.unwrap();
+        let in_use = Err(NetworkError::PortInUse{device: "r1".into(), port: r1_port});
+        assert_eq!(network.restart_router("r3").await, in_use);
+        assert!(network.router("r3").is_err());
+        assert_eq!(network.restart_router("r3").await, in_use);
+
+        // the link towards the stopped r4 is left out
+        network.remove_link("r1", r1_port, "r2", r2_port).await.unwrap();
+        network.restart_router("r3").await.unwrap();
+        assert_eq!(network.link_ports("r1", "r3").map(|(port, _)| port), Some(r1_port));
+        assert_eq!(network.link_ports("r3", "r4"), None);
+        assert!(network.link_ports("r3", "s1").is_some());
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+        network.ping("r1", "10.0.1.3".parse().unwrap()).await.unwrap();
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_panicked_device() {
+        let config = scenario::load_config("examples/ospf-example.yaml").unwrap();
+        let mut network = Network::new(Logger::start_test());
+        scenario::build_network(&mut network, &config).await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+        assert!(network.device_health().await.values().all(|health| *health == DeviceHealth::Running));
+
+        // the dead router doesn't answer anymore, the others keep working
+        network.router("r2").unwrap().0.panic().await;
+        assert_eq!(network.get_routing_table("r2").await, Err(NetworkError::DeviceDead("r2".into())));
+        network.crash_router("r3").await.unwrap();
+        // the task of r2 ends a bit after its channel closed
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let health = network.device_health().await;
+        assert_eq!(health["r2"], DeviceHealth::Panicked("Router r2 told to panic".into()));
+        assert_eq!(health["r3"], DeviceHealth::Stopped);
+        assert_eq!(health["r1"], DeviceHealth::Running);
+        assert!(network.get_routing_table("r1").await.is_ok());
+        assert!(network.ping("r1", "10.0.1.4".parse().unwrap()).await.is_err());
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_send_to_stopped_router() {
+        let config = scenario::load_config("examples/ospf-example.yaml").unwrap();
+        let mut network = Network::new(Logger::start_test());
+        scenario::build_network(&mut network, &config).await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+        let (port, _) = network.link_ports("r3", "r4").unwrap();
+        assert_eq!(network.get_stats("r3").await.unwrap().send_failures.get(&port), None);
+
+        // the hellos of r3 find the link towards r4 closed, they are dropped without stopping r3
+        network.stop_device("r4").await.unwrap();
+        thread::sleep(protocols::ospf::DEAD_INTERVAL + 2 * utils::HELLO_INTERVAL);
+        let stats = network.get_stats("r3").await.unwrap();
+        assert!(stats.send_failures.get(&port).is_some_and(|failures| *failures > 0), "{:?}", stats.send_failures);
+        assert!(network.device_health().await.values().all(|health| *health == DeviceHealth::Running));
+        network.ping("r1", network.router("r3").unwrap().1).await.unwrap();
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_ping_n() {
+        let mut network = Network::new(Logger::start_test());
+        // a few lost hellos in a row mustn't kill the neighbor
+        network.set_dead_interval(Duration::from_millis(2000)).await;
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+        let r2 = network.router("r2").unwrap().1;
+
+        let report = network.ping_n("r1", r2, 20, Duration::from_millis(10)).await.unwrap();
+        assert_eq!((report.sent, report.received, report.lost, report.duplicates), (20, 20, 0, 0), "{}", report);
+        assert!(report.min_rtt <= report.avg_rtt && report.avg_rtt <= report.max_rtt);
+        assert!(report.to_string().starts_with("20 packets transmitted, 20 received, 0% packet loss, rtt min/avg/max = "), "{}", report);
+
+        // the ping and its answer each cross the link once, 1 - 0.9 * 0.9 = 19% of the pings are lost
+        let impairment = LinkImpairment{loss_prob: 0.1, reorder_prob: 0.0, duplicate_prob: 0.05, seed: 7};
+        network.set_link_impairment("r1", 1, Some(impairment)).unwrap();
+        let report = network.ping_n("r1", r2, 300, Duration::from_millis(5)).await.unwrap();
+        assert_eq!(report.sent, 300);
+        assert_eq!(report.received + report.lost, report.sent);
+        assert!((10.0..30.0).contains(&report.loss_percent()), "{}", report);
+        assert!(report.duplicates > 0, "{}", report);
+
+        assert!(matches!(network.ping_n("s1", r2, 1, Duration::ZERO).await, Err(NetworkError::UnknownDevice(_))));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_chain_of_100_routers() {
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        for i in 1..=100{
+            network.add_router(&format!("r{}", i), i, 1).unwrap();
+        }
+        for i in 1..100{
+            network.add_link(&format!("r{}", i), 2, &format!("r{}", i+1), 1, 1).await.unwrap();
+        }
+
+        // wait for the first router to know the whole chain
+        let start = std::time::Instant::now();
+        let mut table = network.get_routing_table("r1").await.unwrap();
+        while table.len() < 100 && start.elapsed() < Duration::from_secs(10){
+            thread::sleep(Duration::from_millis(100));
+            table = network.get_routing_table("r1").await.unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_secs(5), "converged in {:?}", start.elapsed());
+        assert_eq!(table[&"10.0.1.100/32".parse().unwrap()], (2, 99, Ospf));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_grid_of_100_routers() {
+        let mut network = Network::new(Logger::start_test());
+        // the hellos of a busy router are answered late, the neighbors mustn't be considered dead meanwhile
+        network.set_dead_interval(Duration::from_secs(10)).await;
+        // grid of 10x10 routers
+        for i in 0..100{
+            network.add_router(&format!("r{}", i + 1), i + 1, 1).unwrap();
+        }
+        for i in 0..100{
+            if i % 10 != 9{
+                network.add_link(&format!("r{}", i + 1), 1, &format!("r{}", i + 2), 2, 1).await.unwrap();
+            }
+            if i < 90{
+                network.add_link(&format!("r{}", i + 1), 3, &format!("r{}", i + 11), 4, 1).await.unwrap();
+            }
+        }
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+        let table = network.get_routing_table("r1").await.unwrap();
+        assert_eq!(table.values().filter(|(_, _, origin)| *origin == Ospf).count(), 99);
+        assert_eq!(table[&"10.0.1.100/32".parse().unwrap()].1, 18);
+
+        // the lsps and hello replies received in a burst are handled by a single computation
+        for i in 1..=100{
+            let health = network.health(&format!("r{}", i)).await.unwrap();
+            assert!(health.spf_runs < 100, "r{} ran {} shortest path computations", i, health.spf_runs);
+        }
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_wait_for_convergence() {
+        let mut network = Network::new(Logger::start_test());
+        // grid of 5x5 routers
+        for i in 0..25{
+            network.add_router(&format!("r{}", i + 1), i + 1, 1).unwrap();
+        }
+        for i in 0..25{
+            if i % 5 != 4{
+                network.add_link(&format!("r{}", i + 1), 1, &format!("r{}", i + 2), 2, 1).await.unwrap();
+            }
+            if i < 20{
+                network.add_link(&format!("r{}", i + 1), 3, &format!("r{}", i + 6), 4, 1).await.unwrap();
+            }
+        }
+        let elapsed = network.wait_for_convergence(Duration::from_secs(10)).await.unwrap();
+        assert!(elapsed < IGP_CONVERGENCE + BGP_CONVERGENCE, "converged in {:?}", elapsed);
+        let table = network.get_routing_table("r1").await.unwrap();
+        assert_eq!(table[&"10.0.1.25/32".parse().unwrap()].1, 8);
+        network.quit().await;
+
+        // r3 never hears from the others, r1 - r2 -x- r3
+        let mut network = Network::new(Logger::start_test());
+        for i in 1..=3{
+            network.add_router(&format!("r{}", i), i, 1).unwrap();
+        }
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 1, 1).await.unwrap();
+        network.set_link_impairment("r2", 2, Some(LinkImpairment{loss_prob: 1.0, reorder_prob: 0.0, duplicate_prob: 0.0, seed: 0})).unwrap();
+        let timeout = Duration::from_millis(1500);
+        assert_eq!(
+            network.wait_for_ospf(timeout).await,
+            Err(NetworkError::NotConverged{timeout, routers: vec!["r1".into(), "r2".into(), "r3".into()]})
+        );
+        // without OSPF on r3, the others don't expect to reach it
+        network.set_ospf("r3", false).await.unwrap();
+        assert!(network.wait_for_ospf(Duration::from_secs(5)).await.is_ok());
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_requests() {
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let network = Arc::new(network);
+        let tasks: Vec<_> = ["r1", "r2"].into_iter().map(|router| {
+            let network = Arc::clone(&network);
+            tokio::spawn(async move {
+                network.get_routing_table(router).await.unwrap()
+            })
+        }).collect();
+        let mut tables = vec![];
+        for task in tasks{
+            tables.push(task.await.expect("Failed to join the task"));
+        }
+
+        assert_eq!(tables[0][&"10.0.1.2/32".parse().unwrap()], (1, 1, Ospf));
+        assert_eq!(tables[1][&"10.0.1.1/32".parse().unwrap()], (1, 1, Ospf));
+
+        Arc::try_unwrap(network).expect("Network still shared").quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_interleaved_queries() {
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 2).unwrap();
+        network.add_provider_customer_link("r2", 1, "r1", 1, 0, (None, None)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        network.announce_prefix("r1").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let network = Arc::new(network);
+        let neighbor: IPPrefix = "10.0.2.2/32".parse().unwrap();
+        let prefix: IPPrefix = "10.0.1.0/24".parse().unwrap();
+        let tasks: Vec<_> = (0..50).map(|i| {
+            let network = Arc::clone(&network);
+            tokio::spawn(async move {
+                if i % 2 == 0{
+                    assert!(network.get_routing_table("r1").await.unwrap().contains_key(&neighbor));
+                }else{
+                    assert!(network.get_bgp_routes("r1").await.unwrap()[&prefix].0.is_some());
+                }
+            })
+        }).collect();
+        for task in tasks{
+            task.await.expect("Failed to join the task");
+        }
+
+        Arc::try_unwrap(network).expect("Network still shared").quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_quit_writes_last_logs() {
+        for _ in 0..10 {
+            let (logger, handle) = Logger::start_capture();
+            let mut network = Network::new(logger.clone());
+            network.add_router("r1", 1, 1).unwrap();
+            // the router may still be handling the announce when the network quits
+            network.announce_prefix("r1").await.unwrap();
+            logger.log(Source::DEBUG, "Last message".into()).await;
+            network.quit().await;
+
+            let messages: Vec<String> = handle.messages().await.into_iter().map(|(_, message)| message).collect();
+            assert!(messages.iter().any(|message| message.starts_with("Router r1 announcing its prefix")));
+            assert!(messages.iter().any(|message| message == "Last message"));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_log_filters() {
+        let (logger, handle) = Logger::start_capture();
+        let mut network = Network::new(logger);
+        for i in 1..=3{
+            network.add_router(&format!("r{}", i), i, i).unwrap();
+        }
+        network.add_provider_customer_link("r1", 1, "r2", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r2", 2, "r3", 1, 0, (None, None)).await.unwrap();
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        assert_eq!(network.set_log_filters(vec![], vec!["r9".into()]).await, Err(NetworkError::UnknownDevice("r9".into())));
+        network.set_log_filters(vec![Source::BGP], vec!["r3".into()]).await.unwrap();
+        let before = handle.messages().await.len();
+        network.announce_prefix("r1").await.unwrap();
+        assert!(handle.wait_for_after(before, Source::BGP, "Router r3 received bgp update", LOG_TIMEOUT).await.is_some());
+        for (source, message) in &handle.messages().await[before..]{
+            assert!(*source == Source::BGP && message.starts_with("Router r3 "), "{} {}", source, message);
+        }
+
+        // everything is logged again without filters
+        network.set_log_filters(vec![], vec![]).await.unwrap();
+        let before = handle.messages().await.len();
+        network.announce_prefix("r3").await.unwrap();
+        assert!(handle.wait_for_after(before, Source::BGP, "Router r1 received bgp update", LOG_TIMEOUT).await.is_some());
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_mac_learning() {
+        let (logger, handle) = Logger::start_capture();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+        network.add_switch("s1", 11);
+        network.add_switch("s2", 12);
+        network.add_switch("s3", 13);
+
+        network.add_link("r1", 1, "s1", 1, 1).await.unwrap();
+        network.add_link("s1", 2, "s2", 1, 1).await.unwrap();
+        network.add_link("s2", 2, "s3", 1, 1).await.unwrap();
+        network.add_link("s3", 2, "r2", 1, 1).await.unwrap();
+        network.add_link("s2", 3, "r3", 1, 1).await.unwrap();
+
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.ping("r1", "10.0.1.2".parse().unwrap()).await.unwrap();
+        for switch in ["s1", "s2", "s3"]{
+            let table = network.get_mac_table(switch).await.unwrap();
+            assert_eq!(table.get(&MacAddress{id: 1}), Some(&1), "{}", switch);
+            assert_eq!(table.get(&MacAddress{id: 2}), Some(&2), "{}", switch);
+        }
+
+        // the frames between r1 and r2 are no longer flooded to r3
+        let frames_to_r3 = || async {
+            handle.messages().await.iter().filter(|(_, m)| m.starts_with("Router r3 received EthernetFrame")).count()
+        };
+        thread::sleep(Duration::from_millis(100));
+        let before = frames_to_r3().await;
+        // the first frame towards r2 was flooded, its address wasn't known yet
+        assert!(before > 0);
+        network.ping("r1", "10.0.1.2".parse().unwrap()).await.unwrap();
+        network.ping("r2", "10.0.1.1".parse().unwrap()).await.unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(frames_to_r3().await, before);
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_mac_table_flushed_on_tree_change() {
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        for i in 1..=4{
+            network.add_switch(&format!("s{}", i), 10 + i);
+        }
+
+        // s1 is the root, s4 reaches it through s2 and blocks its port towards s3
+        network.add_link("r1", 1, "s1", 1, 1).await.unwrap();
+        network.add_link("s1", 2, "s2", 1, 1).await.unwrap();
+        network.add_link("s1", 3, "s3", 1, 1).await.unwrap();
+        network.add_link("s2", 2, "s4", 1, 1).await.unwrap();
+        network.add_link("s3", 2, "s4", 2, 1).await.unwrap();
+        network.add_link("s4", 3, "r2", 1, 1).await.unwrap();
+
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.ping("r2", "10.0.1.1".parse().unwrap()).await.unwrap();
+        assert_eq!(network.get_mac_table("s4").await.unwrap().get(&MacAddress{id: 1}), Some(&1));
+
+        network.stop_device("s2").await.unwrap();
+        // wait for the bpdus of s2 to expire and for the new convergence
+        thread::sleep(Duration::from_millis(2000));
+
+        // r1 is now behind port 2, the frames towards it must not be sent to the dead s2
+        assert_ne!(network.get_mac_table("s4").await.unwrap().get(&MacAddress{id: 1}), Some(&1));
+        network.ping("r2", "10.0.1.1".parse().unwrap()).await.unwrap();
+        assert_eq!(network.get_mac_table("s4").await.unwrap().get(&MacAddress{id: 1}), Some(&2));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 6)]
+    async fn test_mix_switches_routers() {
+        for _ in 0..10 {
+            let logger = Logger::start_test();
+            let mut network = Network::new(logger);
+            network.add_router("r1", 1, 1).unwrap();
+            network.add_router("r2", 2, 1).unwrap();
+            network.add_switch("s1", 11);
+            network.add_switch("s2", 12);
+            network.add_switch("s3", 13);
+            network.add_switch("s4", 14);
+
+            network.add_link("r1", 1, "s1", 1, 1).await.unwrap();
+            network.add_link("s1", 2, "s2", 1, 1).await.unwrap();
+            network.add_link("s2", 2, "s3", 1, 1).await.unwrap();
+            network.add_link("s4", 1, "s3", 3, 1).await.unwrap();
+            network.add_link("s4", 2, "s1", 3, 1).await.unwrap();
+            network.add_link("s3", 2, "r2", 1, 1).await.unwrap();
+
+            network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+            assert_eq!(
+                network.get_routing_table("r1").await.unwrap(),
+                [
+                    ("10.0.1.1/32".parse().unwrap(), (0, 0, Connected)),
+                    ("10.0.1.2/32".parse().unwrap(), (1, 1, Ospf))
+                ]
+                .into_iter()
+                .collect()
+            );
+
+            assert_eq!(
+                network.get_routing_table("r2").await.unwrap(),
+                [
+                    ("10.0.1.1/32".parse().unwrap(), (1, 1, Ospf)),
+                    ("10.0.1.2/32".parse().unwrap(), (0, 0, Connected))
+                ]
+                .into_iter()
+                .collect()
+            );
+
+            thread::sleep(Duration::from_millis(250));
+
+            // the frames of the ping cross the loop of switches on the spanning tree only
+            network.ping("r1", "10.0.1.2".parse().unwrap()).await.unwrap();
+            let states = network.get_port_states().await.unwrap();
+            let blocked: Vec<(&String, u32)> = states.iter().flat_map(|(switch, ports)| ports.iter().filter(|(_, state)| **state == Blocked).map(move |(port, _)| (switch, *port))).collect();
+            assert_eq!(blocked.len(), 1);
+            for (switch, port) in blocked{
+                let stats = network.get_stats(switch).await.unwrap();
+                assert_eq!(stats.on_port(Direction::Sent, port, MessageKind::EthernetFrame), 0, "{:?}", stats);
+            }
+            assert!(network.get_stats("s1").await.unwrap().total(Direction::Sent, MessageKind::EthernetFrame) > 0);
+            assert_eq!(network.get_stats("h1").await, Err(NetworkError::UnknownDevice("h1".into())));
+
+            network.quit().await;
+        }
+    }
+
+    
+    #[tokio::test(flavor = "multi_thread", worker_threads = 16)]
+    async fn test_bgp() {
+        for _ in 0..5 {
+            let logger = Logger::start_test();
+            let mut network = Network::new(logger);
+            network.add_router("r1", 1, 1).unwrap();
+            network.add_router("r2", 2, 2).unwrap();
+            network.add_router("r3", 3, 3).unwrap();
+            network.add_router("r4", 4, 4).unwrap();
+
+            network
+                .add_provider_customer_link("r2", 1, "r1", 1, 0, (None, None))
+                .await.unwrap();
+            network
+                .add_provider_customer_link("r2", 2, "r4", 1, 0, (None, None))
+                .await.unwrap();
+            network
+                .add_provider_customer_link("r4", 3, "r3", 1, 0, (None, None))
+                .await.unwrap();
+
+            network
+                .add_peer_link("r1", 2, "r4", 2, 0, (None, None))
+                .await.unwrap();
+
+            let mut events = network.subscribe_events();
+            network.announce_prefix("r1").await.unwrap();
+
+            // r4 prefers the route of its peer r1, and r3 the one it gets from r4 then
+            for (router, as_path) in [("r4", vec![1]), ("r3", vec![4, 1])]{
+                let event = wait_for_event(&mut events, Duration::from_millis(1000), |event| matches!(event,
+                    NetworkEvent::BgpBestChanged{router: r, new: Some(best), ..} if r == router && best.as_path == as_path)).await;
+                assert!(event.is_some(), "no best route through {:?} on {}", as_path, router);
+            }
+            network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+            assert_eq!(
+                network.get_bgp_routes("r2").await.unwrap(),
+                [(
+                    "10.0.1.0/24".parse().unwrap(),
+                    (
+                        Some(BGPRoute {
+                            prefix: "10.0.1.0/24".parse().unwrap(),
+                            nexthop: "10.0.1.1".parse().unwrap(),
+                            as_path: vec![1],
+                            pref: 150,
+                            med: 0,
+                            router_id: 1,
+                            source: RouteSource::Ebgp,
+                            communities: vec![],
+                            origin: Origin::Igp,
+                            learned_from: Some(1)
+                        }),
+                        [BGPRoute {
+                            prefix: "10.0.1.0/24".parse().unwrap(),
+                            nexthop: "10.0.1.1".parse().unwrap(),
+                            as_path: vec![1],
+                            pref: 150,
+                            med: 0,
+                            router_id: 1,
+                            source: RouteSource::Ebgp,
+                            communities: vec![],
+                            origin: Origin::Igp,
+                            learned_from: Some(1)
+                        }]
+                        .into_iter()
+                        .collect()
+                    )
+                )]
+                .into_iter()
+                .collect()
+            );
+
+            assert_eq!(
+                network.get_bgp_routes("r3").await.unwrap(),
+                [(
+                    "10.0.1.0/24".parse().unwrap(),
+                    (
+                        Some(BGPRoute {
+                            prefix: "10.0.1.0/24".parse().unwrap(),
+                            nexthop: "10.0.4.4".parse().unwrap(),
+                            as_path: vec![4, 1],
+                            pref: 50,
+                            med: 0,
+                            router_id: 4,
+                            source: RouteSource::Ebgp,
+                            communities: vec![],
+                            origin: Origin::Igp,
+                            learned_from: Some(1)
+                        }),
+                        [BGPRoute {
+                            prefix: "10.0.1.0/24".parse().unwrap(),
+                            nexthop: "10.0.4.4".parse().unwrap(),
+                            as_path: vec![4, 1],
+                            pref: 50,
+                            med: 0,
+                            router_id: 4,
+                            source: RouteSource::Ebgp,
+                            communities: vec![],
+                            origin: Origin::Igp,
+                            learned_from: Some(1)
+                        }]
+                        .into_iter()
+                        .collect()
+                    )
+                )]
+                .into_iter()
+                .collect()
+            );
+
+            assert_eq!(
+                network.get_bgp_routes("r4").await.unwrap(),
+                [(
+                    "10.0.1.0/24".parse().unwrap(),
+                    (
+                        Some(BGPRoute {
+                            prefix: "10.0.1.0/24".parse().unwrap(),
+                            nexthop: "10.0.1.1".parse().unwrap(),
+                            as_path: vec![1],
+                            pref: 100,
+                            med: 0,
+                            router_id: 1,
+                            source: RouteSource::Ebgp,
+                            communities: vec![],
+                            origin: Origin::Igp,
+                            learned_from: Some(2)
+                        }),
+                        [
+                            BGPRoute {
+                                prefix: "10.0.1.0/24".parse().unwrap(),
+                                nexthop: "10.0.1.1".parse().unwrap(),
+                                as_path: vec![1],
+                                pref: 100,
+                                med: 0,
+                                router_id: 1,
+                                source: RouteSource::Ebgp,
+                                communities: vec![],
+                                origin: Origin::Igp,
+                                learned_from: Some(2)
+                            },
+                            BGPRoute {
+                                prefix: "10.0.1.0/24".parse().unwrap(),
+                                nexthop: "10.0.2.2".parse().unwrap(),
+                                as_path: vec![2, 1],
+                                pref: 50,
+                                med: 0,
+                                router_id: 2,
+                                source: RouteSource::Ebgp,
+                                communities: vec![],
+                                origin: Origin::Igp,
+                                learned_from: Some(1)
+                            }
+                        ]
+                        .into_iter()
+                        .collect()
+                    )
+                )]
+                .into_iter()
+                .collect()
+            );
+
+            // every session was opened and confirmed by a keepalive, the neighbors sent their AS
+            for (router, neighbors) in [("r1", [2, 4].as_slice()), ("r2", &[1, 4]), ("r3", &[4]), ("r4", &[1, 2, 3])]{
+                let sessions = network.get_bgp_sessions(router).await.unwrap();
+                assert_eq!(sessions.len(), neighbors.len());
+                for neighbor in neighbors{
+                    let session = &sessions[&format!("r{}", neighbor)];
+                    assert_eq!(session.state, SessionState::Established);
+                    assert_eq!(session.neighbor_as, Some(*neighbor));
+                    assert!(session.uptime().unwrap() < Duration::from_millis(1100));
+                }
+            }
+
+            // r2 is the provider of r4, r1 its peer and r3 its customer
+            let ports = network.get_router_ports("r4").await.unwrap();
+            let ports: Vec<(u32, PortKind, u32, Option<Ipv4Addr>)> = ports.into_iter()
+                .map(|(port, info)| (port, info.kind, info.cost_or_pref, info.neighbor_ip))
+                .collect();
+            assert_eq!(ports, vec![
+                (1, PortKind::Provider, PROVIDER_PREF, Some(network.router("r2").unwrap().1)),
+                (2, PortKind::Peer, PEER_PREF, Some(network.router("r1").unwrap().1)),
+                (3, PortKind::Customer, CUSTOMER_PREF, Some(network.router("r3").unwrap().1)),
+            ]);
+
+            // the route of the peer r1 is the best one, the one of the provider r2 comes after it
+            let table = output::render_bgp_table("r4", &network.get_bgp_routes("r4").await.unwrap(),
+                &network.get_multipath_routes("r4").await.unwrap(), OutputMode::Plain);
+            assert_eq!(table, concat!(
+                "r4\n",
+                "   Network            Next Hop         Pref   MED  Port  Path\n",
+                " > 10.0.1.0/24        10.0.1.1          100     0     2  1 i\n",
+                "                      10.0.2.2           50     0     1  2 1 i\n"
+            ));
+
+            network.quit().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    pub async fn test_bgp_complex() {
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 2).unwrap();
+        network.add_router("r3", 3, 3).unwrap();
+        network.add_router("r4", 4, 4).unwrap();
+        network.add_router("r5", 5, 5).unwrap();
+        network.add_router("r6", 6, 6).unwrap();
+        network.add_router("r7", 7, 7).unwrap();
+        network.add_router("r8", 8, 8).unwrap();
+
+        network
+            .add_provider_customer_link("r3", 1, "r1", 1, 0, (None, None))
+            .await.unwrap();
+        network
+            .add_provider_customer_link("r1", 2, "r2", 1, 0, (None, None))
+            .await.unwrap();
+        network
+            .add_provider_customer_link("r4", 1, "r3", 3, 0, (None, None))
+            .await.unwrap();
+        network
+            .add_provider_customer_link("r5", 1, "r2", 3, 0, (None, None))
+            .await.unwrap();
+        network
+            .add_provider_customer_link("r7", 1, "r4", 3, 0, (None, None))
+            .await.unwrap();
+        network
+            .add_provider_customer_link("r6", 2, "r7", 2, 0, (None, None))
+            .await.unwrap();
+        network
+            .add_provider_customer_link("r8", 1, "r7", 3, 0, (None, None))
+            .await.unwrap();
+
+        network
+            .add_peer_link("r2", 2, "r3", 2, 0, (None, None))
+            .await.unwrap();
+        network
+            .add_peer_link("r4", 2, "r5", 2, 0, (None, None))
+            .await.unwrap();
+        network
+            .add_peer_link("r5", 3, "r6", 1, 0, (None, None))
+            .await.unwrap();
+        network
+            .add_peer_link("r6", 3, "r8", 2, 0, (None, None))
+            .await.unwrap();
+
+        network.announce_prefix("r2").await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let routes1 = [(
             "10.0.2.0/24".parse().unwrap(),
             (
                 Some(BGPRoute {
@@ -787,7 +2955,10 @@ mod tests {
                     pref: 150,
                     med: 0,
                     router_id: 2,
-                    source: RouteSource::EBGP,
+                    source: RouteSource::Ebgp,
+                    communities: vec![],
+                    origin: Origin::Igp,
+                    learned_from: Some(2),
                 }),
                 [BGPRoute {
                     prefix: "10.0.2.0/24".parse().unwrap(),
@@ -796,104 +2967,1885 @@ mod tests {
                     pref: 150,
                     med: 0,
                     router_id: 2,
-                    source: RouteSource::EBGP,
+                    source: RouteSource::Ebgp,
+                    communities: vec![],
+                    origin: Origin::Igp,
+                    learned_from: Some(2),
                 }]
                 .into_iter()
                 .collect(),
             ),
         )]
             .into_iter()
-            .collect();
+            .collect();
+
+        assert_eq!(network.get_bgp_routes("r1").await.unwrap(), routes1);
+
+        // r6 prefers the route of its customer r7 and the ping only goes down towards r2, while r2 only
+        // has routes from its providers and answers through r5, which learned r6's prefix from its peer
+        network.announce_prefix("r6").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        let (forward, backward) = network.ping_recorded("r6", "10.0.2.2".parse().unwrap()).await.unwrap();
+        let ases = |route: &Vec<Ipv4Addr>| -> Vec<u8> { route.iter().map(|ip| ip.octets()[2]).collect() };
+        assert_eq!((ases(&forward), ases(&backward)), (vec![6, 7, 4, 3, 1, 2], vec![2, 5, 6]));
+
+        // r1 crashes, its neighbors notice the missing keepalives and withdraw its routes
+        network.stop_device("r1").await.unwrap();
+        thread::sleep(Duration::from_millis(1000));
+
+        let prefix: IPPrefix = "10.0.2.0/24".parse().unwrap();
+        // r1 is no longer part of the network, its session is named after its address
+        let sessions = network.get_bgp_sessions("r3").await.unwrap();
+        assert_eq!(sessions["10.0.1.1"].state, SessionState::Idle);
+        let routes3 = network.get_bgp_routes("r3").await.unwrap();
+        assert_eq!(routes3[&prefix].0.clone().unwrap().as_path, vec![2]);
+        // r3 no longer exports the route of its peer r2 to its provider r4, which uses its own peer
+        assert_eq!(network.get_bgp_routes("r4").await.unwrap()[&prefix].0.clone().unwrap().as_path, vec![5, 2]);
+        for router in ["r2", "r3", "r4"]{
+            let routes = network.get_bgp_routes(router).await.unwrap();
+            assert!(!routes[&prefix].1.iter().any(|r| r.router_id == 1 || r.as_path.contains(&1)), "{}", router);
+        }
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 6)]
+    async fn test_config_round_trip() {
+        // the topology of test_bgp_complex
+        let mut network = Network::new(Logger::start_test());
+        for id in 1..=8{
+            network.add_router(&format!("r{}", id), id, id).unwrap();
+        }
+        let provider_customer = [("r3", 1, "r1", 1), ("r1", 2, "r2", 1), ("r4", 1, "r3", 3), ("r5", 1, "r2", 3), ("r7", 1, "r4", 3), ("r6", 2, "r7", 2), ("r8", 1, "r7", 3)];
+        for (provider, port1, customer, port2) in provider_customer{
+            network.add_provider_customer_link(provider, port1, customer, port2, 0, (None, None)).await.unwrap();
+        }
+        for (r1, port1, r2, port2) in [("r2", 2, "r3", 2), ("r4", 2, "r5", 2), ("r5", 3, "r6", 1), ("r6", 3, "r8", 2)]{
+            network.add_peer_link(r1, port1, r2, port2, 0, (None, None)).await.unwrap();
+        }
+
+        let config = network.to_config();
+        assert_eq!(config.network.routers.len(), 8);
+        assert_eq!(config.network.links.bgp.provider_customer.len(), 7);
+        assert_eq!(config.network.links.bgp.peer[3].peers, ("r6".to_string(), "r8".to_string()));
+        let yaml = config.to_yaml_string();
+        let other = Network::from_config(&yaml.parse().unwrap(), Logger::start_test()).await.unwrap();
+        assert_eq!(other.to_config(), config);
+
+        network.announce_prefix("r2").await.unwrap();
+        other.announce_prefix("r2").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        other.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        // the ports of the bgp links aren't part of the configuration, the routes are compared without the one they came from
+        let without_ports = |table: BTreeMap<IPPrefix, BGPTableEntry>| table.into_values().map(|entry| {
+            let forget = |route: BGPRoute| BGPRoute{learned_from: None, ..route};
+            (entry.best.map(forget), entry.routes.into_iter().map(forget).collect::<HashSet<BGPRoute>>())
+        }).collect::<Vec<_>>();
+        for router in network.routers(){
+            assert_eq!(without_ports(network.get_bgp_table(&router).await.unwrap()), without_ports(other.get_bgp_table(&router).await.unwrap()), "{}", router);
+        }
+        network.quit().await;
+        other.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_config_of_internal_links() {
+        let mut network = Network::new(Logger::start_test());
+        for id in 1..=3{
+            network.add_router(&format!("r{}", id), id, 1).unwrap();
+        }
+        network.add_switch("s1", 1);
+        network.add_host("h1", "10.1.0.2".parse().unwrap(), "10.1.0.1".parse().unwrap());
+        network.add_link("r2", 3, "r1", 2, 4).await.unwrap();
+        network.add_link("r3", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("s1", 1, "r1", 1, 1).await.unwrap();
+        network.add_link("h1", 1, "s1", 2, 1).await.unwrap();
+        network.add_ibgp_client("r1", "r2").await.unwrap();
+        network.add_ibgp_connection("r1", "r3").await.unwrap();
+        network.set_ospf("r3", false).await.unwrap();
+
+        let yaml = network.to_config().to_yaml_string();
+        let config: ConfigFile = yaml.parse().unwrap();
+        let links: Vec<(String, String, u32)> = config.network.links.internal.iter().map(|link| (link.ends.0.to_string(), link.ends.1.to_string(), link.cost)).collect();
+        assert_eq!(links, [("h1:1".to_string(), "s1:2".to_string(), 1), ("r1:1".into(), "s1:1".into(), 1), ("r1:2".into(), "r2:3".into(), 4), ("r2:1".into(), "r3:1".into(), 1)]);
+        assert_eq!(config.network.links.bgp.ibgp, [IbgpSessionCfg::RouteReflector{rr: "r1".into(), client: "r2".into()}, IbgpSessionCfg::Mesh("r1".into(), "r3".into())]);
+        assert!(!config.network.routers[2].ospf);
+        assert_eq!(config.network.hosts[0].gateway, Ipv4Addr::new(10, 1, 0, 1));
+
+        // the sessions of a removed router are forgotten
+        network.remove_router("r3").await.unwrap();
+        assert_eq!(network.to_config().network.links.bgp.ibgp.len(), 1);
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+    async fn test_ibgp(){
+        for _ in 0..5{
+            let logger = Logger::start_test();
+            let mut network = Network::new(logger);
+            network.add_router("r1", 1, 1).unwrap();
+            network.add_router("r2", 2, 1).unwrap();
+            network.add_router("r3", 3, 1).unwrap();
+            network.add_router("r4", 4, 2).unwrap();
+            network.add_router("r5", 5, 3).unwrap();
+        
+            network
+                .add_provider_customer_link("r4", 1, "r1", 1, 0, (None, None))
+                .await.unwrap();
+        
+            network
+                .add_provider_customer_link("r3", 3, "r5", 3, 0, (None, None))
+                .await.unwrap();
+        
+            network
+                .add_link("r1", 2, "r2", 1, 0)
+                .await.unwrap();
+            network
+                .add_link("r2", 2, "r3", 1, 0)
+                .await.unwrap();
+            network
+                .add_link("r1", 3, "r3", 2, 0)
+                .await.unwrap();
+        
+            let routers = ["r1", "r2", "r3"];
+            for i in 0..routers.len(){
+                for j in i+1..routers.len(){
+                    network.add_ibgp_connection(routers[i].into(), routers[j].into()).await.unwrap();
+                }
+            }
+        
+            network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        
+            network.announce_prefix("r4").await.unwrap();
+            network.announce_prefix("r5").await.unwrap();
+        
+            network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        
+            let bgp_table = network.get_bgp_routes("r2").await.unwrap();
+            let mut expected_table = HashMap::new();
+            expected_table.insert("10.0.2.0/24".parse().unwrap(), (Some(BGPRoute{
+                prefix: "10.0.2.0/24".parse().unwrap(),
+                nexthop: "10.0.1.1".parse().unwrap(),
+                as_path: vec![2],
+                pref: 50,
+                med: 0,
+                router_id: 1,
+                source: RouteSource::Ibgp,
+                communities: vec![],
+                origin: Origin::Igp,
+                learned_from: None,
+            }), [BGPRoute{
+                prefix: "10.0.2.0/24".parse().unwrap(),
+                nexthop: "10.0.1.1".parse().unwrap(),
+                as_path: vec![2],
+                pref: 50,
+                med: 0,
+                router_id: 1,
+                source: RouteSource::Ibgp,
+                communities: vec![],
+                origin: Origin::Igp,
+                learned_from: None,
+            }].into_iter().collect()));
+
+            expected_table.insert("10.0.3.0/24".parse().unwrap(), (Some(BGPRoute{
+                prefix: "10.0.3.0/24".parse().unwrap(),
+                nexthop: "10.0.1.3".parse().unwrap(),
+                as_path: vec![3],
+                pref: 150,
+                med: 0,
+                router_id: 3,
+                source: RouteSource::Ibgp,
+                communities: vec![],
+                origin: Origin::Igp,
+                learned_from: None,
+            }), [BGPRoute{
+                prefix: "10.0.3.0/24".parse().unwrap(),
+                nexthop: "10.0.1.3".parse().unwrap(),
+                as_path: vec![3],
+                pref: 150,
+                med: 0,
+                router_id: 3,
+                source: RouteSource::Ibgp,
+                communities: vec![],
+                origin: Origin::Igp,
+                learned_from: None,
+            }].into_iter().collect()));
+            assert_eq!(bgp_table, expected_table);
+
+        
+            network.quit().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+    async fn test_unreachable_nexthop(){
+        let (logger, handle) = Logger::start_capture();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+        network.add_router("r4", 4, 4).unwrap();
+        network.add_router("r5", 5, 5).unwrap();
+
+        // r4 is a customer of r1 and of r5, the provider of r3
+        network.add_provider_customer_link("r1", 1, "r4", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r5", 1, "r4", 2, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r5", 2, "r3", 1, 0, (None, None)).await.unwrap();
+        network.add_link("r1", 2, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 2, 1).await.unwrap();
+        for (a, b) in [("r1", "r2"), ("r1", "r3"), ("r2", "r3")]{
+            network.add_ibgp_connection(a, b).await.unwrap();
+        }
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.announce_prefix("r4").await.unwrap();
+        network.announce_prefix("r3").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        // r3 prefers the route of the customer of r1 to the one of its provider
+        let prefix: IPPrefix = "10.0.4.0/24".parse().unwrap();
+        let best = network.get_bgp_routes("r3").await.unwrap()[&prefix].0.clone().unwrap();
+        assert_eq!((best.source, best.nexthop), (RouteSource::Ibgp, "10.0.1.1".parse().unwrap()));
+
+        // the IGP no longer reaches r1, its route is unusable
+        network.remove_link("r1", 2, "r2", 1).await.unwrap();
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let (best, routes) = network.get_bgp_routes("r3").await.unwrap()[&prefix].clone();
+        assert!(routes.iter().any(|r| r.source == RouteSource::Ibgp));
+        assert_eq!(best.unwrap().as_path, vec![5, 4]);
+        network.ping("r3", "10.0.4.4".parse().unwrap()).await.unwrap();
+        assert!(handle.wait_for(Source::PING, "Router r3 received ping back from 10.0.4.4", LOG_TIMEOUT).await.is_some());
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+    async fn test_link_local_pref(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 2).unwrap();
+        network.add_router("r3", 3, 3).unwrap();
+        network.add_router("r4", 4, 4).unwrap();
+        network.add_router("r5", 5, 5).unwrap();
+
+        // r4 is a customer of r2 and r3, r2 is a customer of r1, r3 and r5 are peers of r1
+        network.add_provider_customer_link("r1", 1, "r2", 1, 0, (None, None)).await.unwrap();
+        network.add_peer_link("r1", 2, "r3", 1, 0, (Some(200), None)).await.unwrap();
+        network.add_peer_link("r1", 3, "r5", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r2", 2, "r4", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r3", 2, "r4", 2, 0, (None, None)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.announce_prefix("r4").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        // the pref of r1 for the routes of its peer r3 beats the one of its customer
+        let prefix: IPPrefix = "10.0.4.0/24".parse().unwrap();
+        let (best, routes) = network.get_bgp_routes("r1").await.unwrap()[&prefix].clone();
+        assert_eq!(routes.len(), 2);
+        let best = best.unwrap();
+        assert_eq!((best.as_path, best.pref), (vec![3, 4], 200));
+
+        // the route is still exported as a peer route, r5 doesn't keep it
+        let routes = network.get_bgp_routes("r5").await.unwrap();
+        assert!(routes.get(&prefix).is_none_or(|(best, _)| best.is_none()));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+    async fn test_route_reflector(){
+        let (logger, handle) = Logger::start_capture();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap(); // route reflector
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+        network.add_router("r4", 4, 1).unwrap();
+        network.add_router("r5", 5, 2).unwrap();
+
+        network.add_provider_customer_link("r5", 1, "r2", 1, 0, (None, None)).await.unwrap();
+        network.add_link("r2", 2, "r1", 1, 1).await.unwrap();
+        network.add_link("r1", 2, "r3", 1, 1).await.unwrap();
+        network.add_link("r1", 3, "r4", 1, 1).await.unwrap();
+        // no full mesh, the clients only have a session with r1
+        for client in ["r2", "r3", "r4"]{
+            network.add_ibgp_client("r1", client).await.unwrap();
+        }
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.announce_prefix("r5").await.unwrap();
+        network.announce_prefix("r2").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let prefix: IPPrefix = "10.0.2.0/24".parse().unwrap();
+        for router in ["r1", "r3", "r4"]{
+            let best = network.get_bgp_routes(router).await.unwrap()[&prefix].0.clone().unwrap();
+            assert_eq!(best.source, RouteSource::Ibgp, "{}", router);
+            assert_eq!(best.nexthop, "10.0.1.2".parse::<Ipv4Addr>().unwrap(), "{}", router);
+            assert_eq!(best.as_path, vec![2], "{}", router);
+        }
+        // the ping is answered by r5, through r2
+        network.ping("r4", "10.0.2.5".parse().unwrap()).await.unwrap();
+        assert!(handle.wait_for(Source::PING, "Router r5 received ping from 10.0.1.4", LOG_TIMEOUT).await.is_some());
+        assert!(handle.wait_for(Source::PING, "Router r4 received ping back from 10.0.2.5", LOG_TIMEOUT).await.is_some());
+
+        // the withdrawal is reflected too
+        network.withdraw_prefix("r5").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        for router in ["r1", "r2", "r3", "r4"]{
+            assert!(network.get_bgp_routes(router).await.unwrap().get(&prefix).is_none_or(|(best, _)| best.is_none()), "{}", router);
+        }
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_remove_link_releases_ports(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        assert_eq!(
+            network.add_link("r1", 1, "r2", 2, 1).await,
+            Err(NetworkError::PortInUse{device: "r1".into(), port: 1})
+        );
+        // a failed link must not keep the port of the other end reserved
+        assert_eq!(network.next_free_port("r2"), Ok(2));
+
+        network.remove_link("r1", 1, "r2", 1).await.unwrap();
+        assert_eq!(network.next_free_port("r1"), Ok(1));
+        assert_eq!(network.next_free_port("r2"), Ok(1));
+        assert_eq!(
+            network.remove_link("r1", 1, "r2", 1).await,
+            Err(NetworkError::UnknownLink{device1: "r1".into(), port1: 1, device2: "r2".into(), port2: 1})
+        );
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_remove_router_releases_ports(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 2).unwrap();
+        network.add_switch("s1", 4);
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r1", 2, "s1", 1, 1).await.unwrap();
+        network.add_peer_link("r1", 3, "r3", 1, 0, (None, None)).await.unwrap();
+
+        network.remove_router("r1").await.unwrap();
+
+        assert_eq!(network.routers(), vec!["r2".to_string(), "r3".to_string()]);
+        assert_eq!(network.next_free_port("r1"), Err(NetworkError::UnknownDevice("r1".into())));
+        assert_eq!(network.next_free_port("r2"), Ok(1));
+        assert_eq!(network.next_free_port("s1"), Ok(1));
+        assert_eq!(network.next_free_port("r3"), Ok(1));
+
+        network.add_link("r2", 1, "s1", 1, 1).await.unwrap();
+        network.add_peer_link("r2", 2, "r3", 1, 0, (None, None)).await.unwrap();
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_unknown_devices(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_switch("s1", 2);
+
+        assert_eq!(network.add_link("r1", 1, "r9", 1, 1).await, Err(NetworkError::UnknownDevice("r9".into())));
+        assert_eq!(network.add_peer_link("r1", 1, "s1", 1, 0, (None, None)).await, Err(NetworkError::SwitchInBGP("s1".into())));
+        assert_eq!(network.add_ibgp_connection("r1", "r9").await, Err(NetworkError::UnknownDevice("r9".into())));
+        assert_eq!(network.announce_prefix("s1").await, Err(NetworkError::SwitchInBGP("s1".into())));
+        assert_eq!(network.get_routing_table("r9").await, Err(NetworkError::UnknownDevice("r9".into())));
+        assert_eq!(network.ping("s1", "10.0.1.1".parse().unwrap()).await, Err(NetworkError::NotARouter("s1".into())));
+        // failed links must not reserve any port
+        assert_eq!(network.next_free_port("r1"), Ok(1));
+        assert_eq!(network.next_free_port("s1"), Ok(1));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_router_addresses(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 300).unwrap();
+        network.add_router("r2", 2, 300).unwrap();
+        network.add_router("r3", 1, 44).unwrap();
+
+        assert_eq!(
+            network.add_router("r4", 2, 300),
+            Err(NetworkError::DuplicateAddress{device: "r4".into(), ip: "10.1.44.2".parse().unwrap(), other: "r2".into()})
+        );
+        assert_eq!(network.add_router("r5", 256, 1), Err(NetworkError::AddressOutOfRange{device: "r5".into(), id: 256, router_as: 1}));
+        assert_eq!(network.add_router("r6", 1, 65536), Err(NetworkError::AddressOutOfRange{device: "r6".into(), id: 1, router_as: 65536}));
+        assert!(network.get_routing_table("r4").await.is_err());
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 1, 1).await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        // AS 300 doesn't wrap to AS 44
+        let table = network.get_routing_table("r1").await.unwrap();
+        assert_eq!(table[&"10.1.44.2/32".parse().unwrap()], (1, 1, Ospf));
+        assert_eq!(table[&"10.0.44.1/32".parse().unwrap()], (1, 2, Ospf));
+        network.ping("r1", "10.0.44.1".parse().unwrap()).await.unwrap();
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_remove_link_spanning_tree(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_switch("s1", 1);
+        network.add_switch("s2", 2);
+        network.add_switch("s3", 3);
+
+        network.add_link("s1", 1, "s2", 1, 1).await.unwrap();
+        network.add_link("s1", 2, "s3", 1, 1).await.unwrap();
+        network.add_link("s2", 2, "s3", 2, 1).await.unwrap();
+
+        // wait for convergence
+        thread::sleep(Duration::from_millis(250));
+
+        let mut expected: BTreeMap<String, BTreeMap<u32, PortState>> = BTreeMap::new();
+        expected.insert("s1".into(), [(1, Designated), (2, Designated)].into_iter().collect());
+        expected.insert("s2".into(), [(1, Root), (2, Designated)].into_iter().collect());
+        expected.insert("s3".into(), [(1, Root), (2, Blocked)].into_iter().collect());
+        assert_eq!(expected, network.get_port_states().await.unwrap());
+
+        // remove the root port of s2, s2 must now reach the root through s3
+        network.remove_link("s1", 1, "s2", 1).await.unwrap();
+
+        thread::sleep(Duration::from_millis(600));
+
+        let mut expected: BTreeMap<String, BTreeMap<u32, PortState>> = BTreeMap::new();
+        expected.insert("s1".into(), [(2, Designated)].into_iter().collect());
+        expected.insert("s2".into(), [(2, Root)].into_iter().collect());
+        expected.insert("s3".into(), [(1, Root), (2, Designated)].into_iter().collect());
+        assert_eq!(expected, network.get_port_states().await.unwrap());
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_remove_link_ospf(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 1, 1).await.unwrap();
+        network.add_link("r1", 2, "r3", 2, 10).await.unwrap();
+
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        assert_eq!(
+            network.get_routing_table("r1").await.unwrap(),
+            [
+                ("10.0.1.1/32".parse().unwrap(), (0, 0, Connected)),
+                ("10.0.1.2/32".parse().unwrap(), (1, 1, Ospf)),
+                ("10.0.1.3/32".parse().unwrap(), (1, 2, Ospf))
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        network.remove_link("r1", 1, "r2", 1).await.unwrap();
+
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        assert_eq!(
+            network.get_routing_table("r1").await.unwrap(),
+            [
+                ("10.0.1.1/32".parse().unwrap(), (0, 0, Connected)),
+                ("10.0.1.2/32".parse().unwrap(), (2, 11, Ospf)),
+                ("10.0.1.3/32".parse().unwrap(), (2, 10, Ospf))
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        assert_eq!(
+            network.get_routing_table("r2").await.unwrap(),
+            [
+                ("10.0.1.1/32".parse().unwrap(), (2, 11, Ospf)),
+                ("10.0.1.2/32".parse().unwrap(), (0, 0, Connected)),
+                ("10.0.1.3/32".parse().unwrap(), (2, 1, Ospf))
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        assert_eq!(
+            network.get_routing_table("r3").await.unwrap(),
+            [
+                ("10.0.1.1/32".parse().unwrap(), (2, 10, Ospf)),
+                ("10.0.1.2/32".parse().unwrap(), (1, 1, Ospf)),
+                ("10.0.1.3/32".parse().unwrap(), (0, 0, Connected))
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_ping(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 1, 1).await.unwrap();
+
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        assert!(network.ping("r1", "10.0.1.3".parse().unwrap()).await.is_ok());
+        assert!(network.ping("r3", "10.0.1.1".parse().unwrap()).await.is_ok());
+        let path: Vec<Ipv4Addr> = ["10.0.1.1", "10.0.1.2", "10.0.1.3"].iter().map(|ip| ip.parse().unwrap()).collect();
+        let back: Vec<Ipv4Addr> = path.iter().rev().copied().collect();
+        assert_eq!(network.ping_recorded("r1", "10.0.1.3".parse().unwrap()).await, Ok((path, back)));
+        assert_eq!(
+            network.ping("r1", "10.0.1.9".parse().unwrap()).await,
+            Err(NetworkError::Ping(PingError::Timeout("10.0.1.9".parse().unwrap())))
+        );
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_link_subnets(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+        network.add_router("r4", 4, 1).unwrap();
+        network.add_switch("s1", 5);
+
+        network.add_link_with_subnet("r1", 1, "r2", 1, 1, "10.1.12.0/30".parse().unwrap()).await.unwrap();
+        network.add_link_with_subnet("r2", 2, "r3", 1, 1, "10.1.23.0/30".parse().unwrap()).await.unwrap();
+        network.add_link_with_subnet("r3", 2, "r4", 1, 1, "10.1.34.0/30".parse().unwrap()).await.unwrap();
+        assert_eq!(
+            network.add_link_with_subnet("r1", 2, "r4", 2, 1, "10.1.14.0/31".parse().unwrap()).await,
+            Err(NetworkError::SubnetTooSmall("10.1.14.0/31".into()))
+        );
+        assert_eq!(
+            network.add_link_with_subnet("r1", 2, "s1", 1, 1, "10.1.15.0/30".parse().unwrap()).await,
+            Err(NetworkError::NotARouter("s1".into()))
+        );
+
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let routing_table = network.get_routing_table("r1").await.unwrap();
+        assert_eq!(routing_table.get(&"10.1.12.0/30".parse().unwrap()), Some(&(1, 0, Connected)));
+        assert_eq!(routing_table.get(&"10.1.34.0/30".parse().unwrap()), Some(&(1, 3, Ospf)));
+        assert!(network.ping("r1", "10.1.12.2".parse().unwrap()).await.is_ok());
+        assert!(network.ping("r1", "10.1.34.2".parse().unwrap()).await.is_ok());
+        assert!(network.ping("r4", "10.1.12.1".parse().unwrap()).await.is_ok());
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_link_latency(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        // two paths of equal cost from r1 to r4, the one through r2 is slower
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+        network.add_router("r4", 4, 1).unwrap();
+
+        network.add_link_with_latency("r1", 1, "r2", 1, 1, Duration::from_millis(100)).await.unwrap();
+        network.add_link("r1", 2, "r3", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r4", 1, 1).await.unwrap();
+        network.add_link("r3", 2, "r4", 2, 1).await.unwrap();
+
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        assert!(network.ping("r1", "10.0.1.2".parse().unwrap()).await.unwrap() >= Duration::from_millis(200));
+        assert!(network.ping("r1", "10.0.1.3".parse().unwrap()).await.unwrap() < Duration::from_millis(100));
+        // the pong takes the path chosen by r4 back to r1, the ping the one chosen by r1
+        let (port_r1, _, _) = network.get_routing_table("r1").await.unwrap()[&"10.0.1.4/32".parse().unwrap()];
+        let (port_r4, _, _) = network.get_routing_table("r4").await.unwrap()[&"10.0.1.1/32".parse().unwrap()];
+        let slow_paths = (port_r1 == 1) as u32 + (port_r4 == 1) as u32;
+        let rtt = network.ping("r1", "10.0.1.4".parse().unwrap()).await.unwrap();
+        assert!(rtt >= Duration::from_millis(100) * slow_paths && rtt < Duration::from_millis(100) * (slow_paths + 1), "rtt {:?}", rtt);
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_lossy_links(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        // a few lost hellos in a row mustn't kill a neighbor
+        network.set_dead_interval(Duration::from_millis(2000)).await;
+        for id in 1..=4{
+            network.add_router(&format!("r{}", id), id, 1).unwrap();
+        }
+        // ring r1 - r2 - r3 - r4 - r1, each link losing 20% of its messages
+        for id in 1..=4{
+            let next = id % 4 + 1;
+            network.add_link(&format!("r{}", id), 1, &format!("r{}", next), 2, 1).await.unwrap();
+            let impairment = LinkImpairment{loss_prob: 0.2, reorder_prob: 0.1, duplicate_prob: 0.1, seed: id as u64};
+            network.set_link_impairment(&format!("r{}", id), 1, Some(impairment)).unwrap();
+        }
+
+        // the lsps lost on a link are flooded again when a neighbor is found or lost
+        let distance = |from: u32, to: u32| u32::min((to + 4 - from) % 4, (from + 4 - to) % 4);
+        let mut converged = false;
+        for _ in 0..50{
+            thread::sleep(Duration::from_millis(100));
+            let mut all = true;
+            for from in 1..=4{
+                let table = network.get_routing_table(&format!("r{}", from)).await.unwrap();
+                for to in (1..=4).filter(|to| *to != from){
+                    let prefix = IPPrefix{ip: Ipv4Addr::new(10, 0, 1, to as u8), prefix_len: 32};
+                    all &= table.get(&prefix).is_some_and(|(_, d, _)| *d == distance(from, to));
+                }
+            }
+            if all{
+                converged = true;
+                break;
+            }
+        }
+        assert!(converged, "the routing tables didn't converge despite the losses");
+
+        // a few more hellos on each link
+        thread::sleep(Duration::from_millis(1000));
+        for id in 1..=4{
+            let stats = network.get_link_stats(&format!("r{}", id), 1).unwrap();
+            assert!(stats.dropped > 0, "{:?}", stats);
+            // both ends of a link report the same counters
+            assert_eq!(network.get_link_stats(&format!("r{}", id % 4 + 1), 2).unwrap().dropped, stats.dropped);
+        }
+        assert_eq!(network.get_link_stats("r1", 3), Err(NetworkError::NoLinkOnPort{device: "r1".to_string(), port: 3}));
+        let invalid = LinkImpairment{loss_prob: 1.5, reorder_prob: 0.0, duplicate_prob: 0.0, seed: 0};
+        assert!(matches!(network.set_link_impairment("r1", 1, Some(invalid)), Err(NetworkError::InvalidConfig(_))));
+
+        network.remove_link("r1", 1, "r2", 2).await.unwrap();
+        assert!(network.get_link_stats("r1", 1).is_err());
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_rate_limited_link(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        // a flow of 1000 probes per second on a link sending 10 messages per second
+        let rate_limit = RateLimit{rate: LinkRate::MessagesPerSec(10), queue_depth: 8, discipline: QueueDiscipline::Fifo};
+        network.set_link_rate_limit("r1", 1, Some(rate_limit)).unwrap();
+        let flow = network.start_flow("r1", "10.0.1.2".parse().unwrap(), 1000, Duration::from_secs(1)).unwrap();
+        thread::sleep(Duration::from_millis(1500));
+
+        let stats = network.flow_stats(flow).await.unwrap();
+        assert!(stats.finished);
+        assert!(stats.received > 0 && stats.received <= 40, "flow {}", stats);
+        let link = network.get_link_stats("r1", 1).unwrap();
+        assert!(link.tx_dropped > 0, "{:?}, flow {}", link, stats);
+        assert_eq!(network.get_link_stats("r2", 1).unwrap(), link);
+
+        // the link sends everything again once the limit is removed
+        network.set_link_rate_limit("r1", 1, None).unwrap();
+        thread::sleep(Duration::from_millis(1000));
+        network.ping("r1", "10.0.1.2".parse().unwrap()).await.unwrap();
+        let invalid = RateLimit{rate: LinkRate::BytesPerSec(0), queue_depth: 8, discipline: QueueDiscipline::Fifo};
+        assert!(matches!(network.set_link_rate_limit("r1", 1, Some(invalid)), Err(NetworkError::InvalidConfig(_))));
+
+        network.quit().await;
+    }
+
+    // saturates a link of 50 messages per second with a flow of 500 probes per second, returns whether r2
+    // considered r1 dead and the stats of the link in the middle of the flow
+    async fn saturate_link(discipline: QueueDiscipline) -> (bool, LinkStats){
+        let (logger, handle) = Logger::start_capture();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let rate_limit = RateLimit{rate: LinkRate::MessagesPerSec(50), queue_depth: 32, discipline};
+        network.set_link_rate_limit("r1", 1, Some(rate_limit)).unwrap();
+        let skipped = handle.messages().await.len();
+        network.start_flow("r1", "10.0.1.2".parse().unwrap(), 500, Duration::from_secs(2)).unwrap();
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        let stats = network.get_link_stats("r1", 1).unwrap();
+        let dead = handle.wait_for_after(skipped, Source::OSPF, "neighbor considered dead", Duration::from_millis(1500)).await;
+        network.quit().await;
+        (dead.is_some(), stats)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_priority_queueing(){
+        // the hellos wait behind the probes or are dropped with them, the dead interval expires
+        let (dead, stats) = saturate_link(QueueDiscipline::Fifo).await;
+        assert!(dead, "{:?}", stats);
+        assert!(stats.data_queued > 0 && stats.data_queue_dropped > 0, "{:?}", stats);
+
+        // the hellos overtake the probes, the adjacency stays up while the probes are still dropped
+        let (dead, stats) = saturate_link(QueueDiscipline::Priority).await;
+        assert!(!dead, "{:?}", stats);
+        assert!(stats.data_queued > 0 && stats.data_queue_dropped > 0, "{:?}", stats);
+        assert_eq!(stats.control_queue_dropped, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_capture(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 2).unwrap();
+        network.add_provider_customer_link("r2", 1, "r1", 1, 0, (None, None)).await.unwrap();
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let path = std::env::temp_dir().join(format!("capture-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        network.enable_capture(path).await.unwrap();
+        network.announce_prefix("r1").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        network.stop_capture().await;
+
+        let capture = Capture::load(path).unwrap();
+        // a single update crossed the link, seen on both of its ends
+        let prefix: IPPrefix = "10.0.1.0/24".parse().unwrap();
+        let is_update = |record: &CaptureRecord| matches!(&record.message, Message::Bgp(BGPMessage::Update(p, ..)) if *p == prefix);
+        let received = capture.by_device("r2").by_protocol(Protocol::BGP).by_direction(Direction::Received);
+        assert_eq!(received.records.iter().filter(|r| is_update(r)).count(), 1);
+        let sent = capture.by_device("r1").by_direction(Direction::Sent);
+        let update = sent.records.iter().find(|r| is_update(r)).expect("r1 didn't send its update");
+        assert_eq!((update.port, update.protocol()), (1, Protocol::BGP));
+        // the keepalives too
+        assert!(capture.by_protocol(Protocol::BGP).len() > received.len());
+
+        // nothing is recorded once the capture is stopped
+        thread::sleep(Duration::from_millis(250));
+        assert_eq!(Capture::load(path).unwrap().len(), capture.len());
+        std::fs::remove_file(path).unwrap();
+        assert!(matches!(Capture::load(path), Err(NetworkError::Capture(_))));
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_replay(){
+        let mut network = Network::new(Logger::start_test());
+        let path = std::env::temp_dir().join(format!("replay-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        network.enable_capture(path).await.unwrap();
+        // r1 - r2 - r3 and a loop of switches, s3 blocks one of its ports
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 1, 1).await.unwrap();
+        network.add_switch("s1", 1);
+        network.add_switch("s2", 2);
+        network.add_switch("s3", 3);
+        network.add_link("s1", 1, "s2", 1, 1).await.unwrap();
+        network.add_link("s1", 2, "s3", 1, 1).await.unwrap();
+        network.add_link("s2", 2, "s3", 2, 1).await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+        network.stop_capture().await;
+        let routing_table = network.get_routing_table("r1").await.unwrap();
+        let ports_states = network.get_port_states().await.unwrap().remove("s3").unwrap();
+        network.quit().await;
+
+        // alone, the devices reach the same state from the messages they received
+        let capture = Capture::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let mut network = Network::new(Logger::start_test());
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_switch("s3", 3);
+        network.replay(&capture, "r1").await.unwrap();
+        network.replay(&capture, "s3").await.unwrap();
+        assert_eq!(network.get_routing_table("r1").await.unwrap(), routing_table);
+        assert_eq!(network.get_port_states().await.unwrap()["s3"], ports_states);
+        assert_eq!(network.replay(&capture, "r4").await, Err(NetworkError::UnknownDevice("r4".into())));
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_hosts(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        // h1 - s1 - r1 - r2 - s2 - h2, each host only knows the router of its LAN
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_switch("s1", 11);
+        network.add_switch("s2", 12);
+        network.add_host("h1", "10.1.1.2".parse().unwrap(), "10.1.1.1".parse().unwrap());
+        network.add_host("h2", "10.1.2.2".parse().unwrap(), "10.1.2.1".parse().unwrap());
+
+        network.add_link("h1", 1, "s1", 1, 1).await.unwrap();
+        network.add_link("s1", 2, "r1", 1, 1).await.unwrap();
+        network.add_link("r1", 2, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "s2", 1, 1).await.unwrap();
+        network.add_link("s2", 2, "h2", 1, 1).await.unwrap();
+        network.set_interface_address("r1", 1, "10.1.1.1".parse().unwrap(), "10.1.1.0/24".parse().unwrap()).await.unwrap();
+        network.set_interface_address("r2", 2, "10.1.2.1".parse().unwrap(), "10.1.2.0/24".parse().unwrap()).await.unwrap();
+        assert_eq!(network.add_link("h1", 2, "s1", 3, 1).await, Err(NetworkError::HostAlreadyLinked("h1".into())));
+        assert_eq!(
+            network.set_interface_address("r1", 1, "10.1.3.1".parse().unwrap(), "10.1.1.0/24".parse().unwrap()).await,
+            Err(NetworkError::AddressOutsideSubnet{address: "10.1.3.1".parse().unwrap(), subnet: "10.1.1.0/24".into()})
+        );
+
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        assert!(network.ping("h1", "10.1.2.2".parse().unwrap()).await.is_ok());
+        assert!(network.ping("h2", "10.1.1.2".parse().unwrap()).await.is_ok());
+        assert!(network.ping("h1", "10.0.1.2".parse().unwrap()).await.is_ok());
+        assert!(network.ping("r2", "10.1.1.2".parse().unwrap()).await.is_ok());
+        assert_eq!(network.get_routing_table("h1").await, Err(NetworkError::NotARouter("h1".into())));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_ping_many(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        // AS1 is made of r1, r2 and r3 connected by a ring of switches, AS2 and AS3 are its customers
+        // and peer with each other, AS4 is a customer of both and AS5 a customer of AS4
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+        network.add_router("r4", 4, 2).unwrap();
+        network.add_router("r5", 5, 3).unwrap();
+        network.add_router("r6", 6, 4).unwrap();
+        network.add_router("r7", 7, 4).unwrap();
+        network.add_router("r8", 8, 5).unwrap();
+        network.add_switch("s1", 11);
+        network.add_switch("s2", 12);
+        network.add_switch("s3", 13);
+
+        network.add_link("s1", 1, "s2", 1, 1).await.unwrap();
+        network.add_link("s2", 2, "s3", 1, 1).await.unwrap();
+        network.add_link("s3", 2, "s1", 2, 1).await.unwrap();
+        network.add_link("r1", 1, "s1", 3, 1).await.unwrap();
+        network.add_link("r2", 1, "s2", 3, 1).await.unwrap();
+        network.add_link("r3", 1, "s3", 3, 1).await.unwrap();
+        network.add_link("r6", 1, "r7", 1, 1).await.unwrap();
+        network.add_ibgp_connection("r1", "r2").await.unwrap();
+        network.add_ibgp_connection("r1", "r3").await.unwrap();
+        network.add_ibgp_connection("r2", "r3").await.unwrap();
+        network.add_ibgp_connection("r6", "r7").await.unwrap();
+
+        network.add_provider_customer_link("r1", 2, "r4", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r2", 2, "r5", 1, 0, (None, None)).await.unwrap();
+        network.add_peer_link("r4", 2, "r5", 2, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r4", 3, "r6", 2, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r5", 3, "r7", 2, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r7", 3, "r8", 1, 0, (None, None)).await.unwrap();
+
+        // wait for the igp convergence, the iBGP messages are routed by OSPF
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        for router_as in 1..=5{
+            network.announce_prefix_as(router_as).await.unwrap();
+        }
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        // each router has several pings in flight towards different destinations
+        let routers: Vec<(String, Ipv4Addr)> = network.routers.iter().map(|(name, (_, ip))| (name.clone(), *ip)).collect();
+        let mut pairs = vec![];
+        for (from, _) in routers.iter(){
+            for (to, ip) in routers.iter(){
+                if from != to && pairs.len() < 50{
+                    pairs.push((from.as_str(), *ip));
+                }
+            }
+        }
+        let results = network.ping_many(&pairs).await;
+
+        assert_eq!(results.len(), 50);
+        for ((from, to), result) in pairs.iter().zip(results){
+            assert!(result.is_ok(), "ping from {} to {} failed: {:?}", from, to, result);
+        }
+        // a single unreachable destination doesn't affect the others
+        let results = network.ping_many(&[("r1", "10.0.5.8".parse().unwrap()), ("r1", "10.0.1.42".parse().unwrap()), ("r8", "10.0.1.3".parse().unwrap())]).await;
+        assert!(results[0].is_ok());
+        assert_eq!(results[1], Err(NetworkError::Ping(PingError::Timeout("10.0.1.42".parse().unwrap()))));
+        assert!(results[2].is_ok());
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_withdraw_prefix(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 2).unwrap();
+        network.add_router("r3", 3, 3).unwrap();
+
+        network.add_provider_customer_link("r2", 1, "r1", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r2", 2, "r3", 1, 0, (None, None)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.announce_prefix("r1").await.unwrap();
+        network.announce_prefix("r3").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let prefix: IPPrefix = "10.0.1.0/24".parse().unwrap();
+        for router in ["r2", "r3"]{
+            assert!(network.get_bgp_routes(router).await.unwrap()[&prefix].0.is_some());
+        }
+        assert!(network.ping("r3", "10.0.1.1".parse().unwrap()).await.is_ok());
+
+        network.withdraw_prefix("r1").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        for router in ["r2", "r3"]{
+            assert!(!network.get_bgp_routes(router).await.unwrap().contains_key(&prefix));
+            // the packets to the prefix are no longer forwarded towards r1
+            assert!(!network.get_routing_table(router).await.unwrap().contains_key(&prefix));
+        }
+        assert_eq!(
+            network.ping("r3", "10.0.1.1".parse().unwrap()).await,
+            Err(NetworkError::Ping(PingError::Timeout("10.0.1.1".parse().unwrap())))
+        );
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+    async fn test_set_local_pref(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 2).unwrap();
+        network.add_router("r3", 3, 3).unwrap();
+        network.add_router("r4", 4, 4).unwrap();
+
+        network.add_provider_customer_link("r2", 1, "r1", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r3", 1, "r1", 2, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r2", 2, "r4", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r3", 2, "r4", 2, 0, (None, None)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.announce_prefix("r4").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let prefix: IPPrefix = "10.0.4.0/24".parse().unwrap();
+        let best = network.get_bgp_routes("r1").await.unwrap()[&prefix].0.clone().unwrap();
+        assert_eq!((best.nexthop, best.pref), ("10.0.2.2".parse().unwrap(), 50));
+        assert_eq!(network.get_routing_table("r1").await.unwrap()[&prefix].0, 1);
+
+        // drain the session with r2, the routes of r3 become the best ones
+        network.set_local_pref("r1", "r2", Some(10)).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let (best, routes) = network.get_bgp_routes("r1").await.unwrap()[&prefix].clone();
+        assert_eq!(best.unwrap().nexthop, "10.0.3.3".parse::<Ipv4Addr>().unwrap());
+        assert!(routes.iter().any(|r| r.nexthop == "10.0.2.2".parse::<Ipv4Addr>().unwrap() && r.pref == 10));
+        assert_eq!(network.get_routing_table("r1").await.unwrap()[&prefix].0, 2);
+
+        network.set_local_pref("r1", "r2", None).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let best = network.get_bgp_routes("r1").await.unwrap()[&prefix].0.clone().unwrap();
+        assert_eq!((best.nexthop, best.pref), ("10.0.2.2".parse().unwrap(), 50));
+        assert_eq!(network.get_routing_table("r1").await.unwrap()[&prefix].0, 1);
+
+        assert_eq!(
+            network.set_local_pref("r1", "r4", Some(10)).await,
+            Err(NetworkError::UnknownSession{router: "r1".into(), neighbor: "r4".into()})
+        );
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+    async fn test_restore_configured_local_pref(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 2).unwrap();
+        network.add_router("r3", 3, 3).unwrap();
+        network.add_router("r4", 4, 4).unwrap();
+
+        // r1 prefers its provider r2, as configured on their link
+        network.add_provider_customer_link("r2", 1, "r1", 1, 0, (None, Some(70))).await.unwrap();
+        network.add_provider_customer_link("r3", 1, "r1", 2, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r2", 2, "r4", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r3", 2, "r4", 2, 0, (None, None)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.announce_prefix("r4").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let prefix: IPPrefix = "10.0.4.0/24".parse().unwrap();
+        let best = network.get_bgp_routes("r1").await.unwrap()[&prefix].0.clone().unwrap();
+        assert_eq!((best.nexthop, best.pref), ("10.0.2.2".parse().unwrap(), 70));
+
+        network.set_local_pref("r1", "r2", Some(10)).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        let best = network.get_bgp_routes("r1").await.unwrap()[&prefix].0.clone().unwrap();
+        assert_eq!((best.nexthop, best.pref), ("10.0.3.3".parse().unwrap(), 50));
+
+        // the pref of the link applies again, not the one of the relationship
+        network.set_local_pref("r1", "r2", None).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        let best = network.get_bgp_routes("r1").await.unwrap()[&prefix].0.clone().unwrap();
+        assert_eq!((best.nexthop, best.pref), ("10.0.2.2".parse().unwrap(), 70));
+        let sessions = network.get_bgp_sessions("r1").await.unwrap();
+        assert_eq!(sessions["r2"].configured_pref, Some(70));
+        assert_eq!(sessions["r3"].configured_pref, None);
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+    async fn test_as_path_prepend(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 2).unwrap();
+        network.add_router("r3", 3, 3).unwrap();
+        network.add_router("r4", 4, 4).unwrap();
+
+        // r1 reaches r4 through r2 or r3, with paths of the same length
+        network.add_provider_customer_link("r2", 1, "r1", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r3", 1, "r1", 2, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r2", 2, "r4", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r3", 2, "r4", 2, 0, (None, None)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.announce_prefix("r4").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let prefix: IPPrefix = "10.0.4.0/24".parse().unwrap();
+        let best = network.get_bgp_routes("r1").await.unwrap()[&prefix].0.clone().unwrap();
+        assert_eq!((best.nexthop, best.as_path), ("10.0.2.2".parse().unwrap(), vec![2, 4]));
+
+        // r4 makes its path through r2 less attractive
+        network.set_prepend("r4", 1, 2).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let best = network.get_bgp_routes("r2").await.unwrap()[&prefix].0.clone().unwrap();
+        assert_eq!(best.as_path, vec![4, 4, 4]);
+        let (best, routes) = network.get_bgp_routes("r1").await.unwrap()[&prefix].clone();
+        assert_eq!((best.unwrap().nexthop, routes.len()), ("10.0.3.3".parse().unwrap(), 2));
+        assert!(routes.iter().any(|r| r.as_path == vec![2, 4, 4, 4]));
+        assert_eq!(network.get_routing_table("r1").await.unwrap()[&prefix].0, 2);
+
+        // back to a single occurrence of its AS
+        network.set_prepend("r4", 1, 0).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let best = network.get_bgp_routes("r1").await.unwrap()[&prefix].0.clone().unwrap();
+        assert_eq!((best.nexthop, best.as_path), ("10.0.2.2".parse().unwrap(), vec![2, 4]));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+    async fn test_community_policies(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        for i in 1..=5{
+            network.add_router(&format!("r{}", i), i, i).unwrap();
+        }
+
+        // r1 is a customer of r2, whose peer is r3, provider is r4 and other customer is r5
+        network.add_provider_customer_link("r2", 1, "r1", 1, 0, (None, None)).await.unwrap();
+        network.add_peer_link("r2", 2, "r3", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r4", 1, "r2", 3, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r2", 4, "r5", 1, 0, (None, None)).await.unwrap();
+        network.add_community_policy("r2", 100, CommunityAction::NoExportToPeers).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.announce_prefix_with_communities("r1", vec![100]).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let prefix: IPPrefix = "10.0.1.0/24".parse().unwrap();
+        let best = network.get_bgp_routes("r2").await.unwrap()[&prefix].0.clone().unwrap();
+        assert_eq!(best.communities, vec![100]);
+        for router in ["r3", "r4"]{
+            assert!(!network.get_bgp_routes(router).await.unwrap().contains_key(&prefix), "{} learned the prefix", router);
+        }
+        let best = network.get_bgp_routes("r5").await.unwrap()[&prefix].0.clone().unwrap();
+        assert_eq!((best.as_path, best.communities, best.pref), (vec![2, 1], vec![100], 50));
+
+        // the policies also apply to the routes already received
+        network.add_community_policy("r5", 100, CommunityAction::SetPref(20)).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        assert_eq!(network.get_bgp_routes("r5").await.unwrap()[&prefix].0.clone().unwrap().pref, 20);
+
+        network.add_community_policy("r5", 100, CommunityAction::Drop).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        assert!(network.get_bgp_routes("r5").await.unwrap().get(&prefix).is_none_or(|(best, _)| best.is_none()));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+    async fn test_route_policies(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        for i in 1..=4{
+            network.add_router(&format!("r{}", i), i, i).unwrap();
+        }
+
+        // r1 is a customer of r2 and r3, r4 is a customer of r3
+        network.add_provider_customer_link("r2", 1, "r1", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r3", 1, "r1", 2, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r3", 2, "r4", 1, 0, (None, None)).await.unwrap();
+        let rule = |prefix: &str, le, action| PolicyRule{prefix: prefix.parse().unwrap(), le, action};
+        network.set_import_policy("r2", 1, RoutePolicy::new(vec![rule("10.0.1.0/24", None, PolicyAction::Deny)])).await.unwrap();
+        network.set_export_policy("r3", 2, RoutePolicy::new(vec![rule("10.0.0.0/16", Some(24), PolicyAction::SetMed(7))])).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.announce_prefix("r1").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let prefix: IPPrefix = "10.0.1.0/24".parse().unwrap();
+        assert!(!network.get_bgp_routes("r2").await.unwrap().contains_key(&prefix));
+        assert!(network.get_bgp_routes("r3").await.unwrap()[&prefix].0.is_some());
+        let best = network.get_bgp_routes("r4").await.unwrap()[&prefix].0.clone().unwrap();
+        assert_eq!((best.as_path, best.med), (vec![3, 1], 7));
+
+        // the routes already received are filtered again with the new policy
+        network.set_import_policy("r2", 1, RoutePolicy::default()).await.unwrap();
+        network.set_export_policy("r3", 2, RoutePolicy::new(vec![rule("10.0.1.0/24", None, PolicyAction::Deny)])).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        assert!(network.get_bgp_routes("r2").await.unwrap()[&prefix].0.is_some());
+        assert!(network.get_bgp_routes("r4").await.unwrap().get(&prefix).is_none_or(|(best, _)| best.is_none()));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_bgp_multipath(){
+        let (logger, handle) = Logger::start_capture();
+        let mut network = Network::new(logger);
+        for i in 1..=8{
+            network.add_router(&format!("r{}", i), i, i).unwrap();
+        }
+
+        // r1 reaches r4 through two equally good providers, r5 to r8 are customers of r1
+        network.add_provider_customer_link("r2", 1, "r1", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r3", 1, "r1", 2, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r2", 2, "r4", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r3", 2, "r4", 2, 0, (None, None)).await.unwrap();
+        for i in 5..=8{
+            network.add_provider_customer_link("r1", i - 2, &format!("r{}", i), 1, 0, (None, None)).await.unwrap();
+        }
+        network.set_multipath("r1", true).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.announce_prefix_as(4).await.unwrap();
+        for i in 5..=8{
+            network.announce_prefix_as(i).await.unwrap();
+        }
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let prefix: IPPrefix = "10.0.4.0/24".parse().unwrap();
+        let multipath = network.get_multipath_routes("r1").await.unwrap();
+        let ports: Vec<u32> = multipath[&prefix].iter().map(|(_, port)| *port).collect();
+        assert_eq!(ports, vec![1, 2]);
+
+        // each flow leaves r1 through one of its providers, the flows of the customers use both
+        let mut providers = HashSet::new();
+        for i in 5..=8{
+            let hops = network.traceroute(&format!("r{}", i), "10.0.4.4".parse().unwrap()).await.unwrap();
+            assert_eq!(hops.len(), 3);
+            providers.insert(hops[1]);
+            assert!(network.ping(&format!("r{}", i), "10.0.4.4".parse().unwrap()).await.is_ok());
+            let answered = format!("Router r4 received ping from 10.0.{}.{}", i, i);
+            assert!(handle.wait_for(Source::PING, &answered, LOG_TIMEOUT).await.is_some(), "{}", answered);
+        }
+        assert_eq!(providers, ["10.0.2.2".parse().unwrap(), "10.0.3.3".parse().unwrap()].into_iter().collect());
+
+        // a single route is used again without multipath
+        network.set_multipath("r1", false).await.unwrap();
+        assert!(network.get_multipath_routes("r1").await.unwrap().is_empty());
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_ospf_ecmp(){
+        // the same routes are computed whatever order the paths of the diamond are found in
+        for _ in 0..20{
+            let mut network = Network::new(Logger::start_test());
+            for i in 1..=4{
+                network.add_router(&format!("r{}", i), i, 1).unwrap();
+            }
+            network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+            network.add_link("r1", 2, "r3", 1, 1).await.unwrap();
+            network.add_link("r2", 2, "r4", 1, 1).await.unwrap();
+            network.add_link("r3", 2, "r4", 2, 1).await.unwrap();
+            network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+            let r4: IPPrefix = "10.0.1.4/32".parse().unwrap();
+            assert_eq!(network.get_routing_table("r1").await.unwrap()[&r4], (1, 2, Ospf));
+            assert_eq!(network.get_equal_cost_ports("r1").await.unwrap(), [(r4, vec![1, 2])].into_iter().collect());
+            // r2 reaches r3 through r1 or r4
+            let r3: IPPrefix = "10.0.1.3/32".parse().unwrap();
+            assert_eq!(network.get_equal_cost_ports("r2").await.unwrap(), [(r3, vec![1, 2])].into_iter().collect());
+
+            // a flow keeps its path through r2 or r3
+            network.set_ecmp("r1", true).await.unwrap();
+            let hops = network.traceroute("r1", r4.ip).await.unwrap();
+            assert!(["10.0.1.2", "10.0.1.3"].map(|ip| ip.parse().unwrap()).contains(&hops[0]), "{:?}", hops);
+            assert_eq!(network.traceroute("r1", r4.ip).await.unwrap(), hops);
+
+            network.quit().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_max_prefixes(){
+        let (logger, handle) = Logger::start_capture();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 2).unwrap();
+        network.add_router("r3", 3, 3).unwrap();
+        network.add_peer_link("r1", 1, "r2", 1, 0, (None, None)).await.unwrap();
+        network.add_peer_link("r1", 2, "r3", 1, 0, (None, None)).await.unwrap();
+        network.set_max_prefixes("r1", 1, 2, MaxPrefixAction::Drop).await.unwrap();
+        network.set_max_prefixes("r1", 2, 2, MaxPrefixAction::Close).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        for router in ["r2", "r3"]{
+            for i in 1..=3{
+                let prefix = format!("192.0.{}.0/24", 10 * i + router[1..].parse::<u32>().unwrap());
+                network.announce_custom_prefix(router, prefix.parse().unwrap()).await.unwrap();
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        // the third prefix of r2 is ignored, the session with r3 is closed with its routes
+        let routes = network.get_bgp_routes("r1").await.unwrap();
+        let learned = |as_path: u32| routes.values().filter(|(best, _)| best.as_ref().is_some_and(|r| r.as_path == vec![as_path])).count();
+        assert_eq!(learned(2), 2);
+        assert_eq!(learned(3), 0);
+        assert_eq!(network.get_bgp_sessions("r1").await.unwrap()["r3"].state, SessionState::Idle);
+        let messages = handle.messages().await;
+        assert!(messages.iter().any(|(_, m)| m == "Warning: router r1 received more than 2 prefixes on port 1, update for prefix 192.0.32.0/24 dropped"));
+        assert!(messages.iter().any(|(_, m)| m == "Warning: router r1 received more than 2 prefixes on port 2, session closed"));
 
-        assert_eq!(network.get_bgp_routes("r1").await, routes1);
         network.quit().await;
     }
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
-    async fn test_ibgp(){
-        for _ in 0..5{
-            let logger = Logger::start_test();
-            let mut network = Network::new(logger);
-            network.add_router("r1", 1, 1);
-            network.add_router("r2", 2, 1);
-            network.add_router("r3", 3, 1);
-            network.add_router("r4", 4, 2);
-            network.add_router("r5", 5, 3);
-        
-            network
-                .add_provider_customer_link("r4", 1, "r1", 1, 0)
-                .await;
-        
-            network
-                .add_provider_customer_link("r3", 3, "r5", 3, 0)
-                .await;
-        
-            network
-                .add_link("r1", 2, "r2", 1, 0)
-                .await;
-            network
-                .add_link("r2", 2, "r3", 1, 0)
-                .await;
-            network
-                .add_link("r1", 3, "r3", 2, 0)
-                .await;
-        
-            let routers = ["r1", "r2", "r3"];
-            for i in 0..routers.len(){
-                for j in i+1..routers.len(){
-                    network.add_ibgp_connection(routers[i].into(), routers[j].into()).await;
-                }
+    async fn test_clear_bgp(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 2).unwrap();
+        network.add_router("r3", 3, 3).unwrap();
+        network.add_router("r4", 4, 4).unwrap();
+
+        network.add_provider_customer_link("r2", 1, "r1", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r2", 2, "r3", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r4", 1, "r2", 3, 0, (None, None)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        for router in ["r1", "r2", "r3", "r4"]{
+            network.announce_prefix(router).await.unwrap();
+        }
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        let before = network.export_state().await.unwrap();
+        assert_eq!(before.bgp_tables["r1"].len(), 4);
+
+        network.clear_bgp("r2").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        assert_eq!(before.diff(&network.export_state().await.unwrap()), vec![]);
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_clear_routes(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 1, 3).await.unwrap();
+
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+        let before = network.get_routing_table("r1").await.unwrap();
+        assert_eq!(before[&"10.0.1.3/32".parse().unwrap()], (1, 4, Ospf));
+
+        network.clear_routes("r1").await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        assert_eq!(network.get_routing_table("r1").await.unwrap(), before);
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_traceroute(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+        network.add_router("r4", 4, 1).unwrap();
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r1", 2, "r3", 1, 1).await.unwrap();
+        network.add_link("r3", 3, "r4", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 2, 1).await.unwrap();
+
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        assert_eq!(
+            network.traceroute("r1", "10.0.1.4".parse().unwrap()).await.unwrap(),
+            vec!["10.0.1.3".parse::<Ipv4Addr>().unwrap(), "10.0.1.4".parse().unwrap()]
+        );
+        assert_eq!(
+            network.traceroute("r4", "10.0.1.2".parse().unwrap()).await.unwrap(),
+            vec!["10.0.1.3".parse::<Ipv4Addr>().unwrap(), "10.0.1.2".parse().unwrap()]
+        );
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_health(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 2).unwrap();
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_provider_customer_link("r3", 1, "r1", 2, 0, (None, None)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        network.announce_prefix("r3").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let health = network.health("r1").await.unwrap();
+        assert_eq!(health.interfaces, [(1, InterfaceState::Up), (2, InterfaceState::Up)].into_iter().collect());
+        assert_eq!(health.ospf_neighbors.keys().collect::<Vec<_>>(), vec![&"10.0.1.2".parse::<Ipv4Addr>().unwrap()]);
+        assert_eq!(
+            health.bgp_sessions,
+            [("10.0.2.3".parse().unwrap(), SessionHealth{state: SessionState::Established, prefixes: 1})].into_iter().collect()
+        );
+        assert!(health.anomalies.is_empty());
+
+        let output = health.to_string();
+        assert!(output.contains("ospf neighbors: 1"), "{}", output);
+        assert!(output.contains("bgp sessions: 1 (10.0.2.3: Established, 1 prefixes)"), "{}", output);
+        assert_eq!(network.health("s1").await, Err(NetworkError::UnknownDevice("s1".into())));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_bgp_route_survives_ospf_reconvergence(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 2).unwrap();
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_provider_customer_link("r3", 1, "r1", 2, 0, (None, None)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        network.announce_prefix("r3").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let prefix: IPPrefix = "10.0.2.0/24".parse().unwrap();
+        let table = network.get_routing_table("r1").await.unwrap();
+        assert_eq!(table[&prefix], (2, 0, Bgp));
+        assert_eq!(table[&"10.0.2.3/32".parse().unwrap()], (2, 1, Connected));
+        assert_eq!(table[&"10.0.1.2/32".parse().unwrap()], (1, 1, Ospf));
+
+        // the routes learned by OSPF are computed again, and a new router joins the AS
+        network.clear_routes("r1").await.unwrap();
+        network.add_router("r4", 4, 1).unwrap();
+        network.add_link("r2", 2, "r4", 1, 1).await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let table = network.get_routing_table("r1").await.unwrap();
+        assert_eq!(table[&"10.0.1.4/32".parse().unwrap()], (1, 2, Ospf));
+        assert_eq!(table[&prefix], (2, 0, Bgp));
+        assert_eq!(table[&"10.0.2.3/32".parse().unwrap()], (2, 1, Connected));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+    async fn test_igp_med(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("a1", 1, 1).unwrap();
+        network.add_router("b1", 2, 1).unwrap();
+        network.add_router("b2", 3, 1).unwrap();
+        network.add_router("n", 4, 2).unwrap();
+        network.add_router("o", 5, 3).unwrap();
+
+        network.add_link("a1", 1, "b1", 1, 1).await.unwrap();
+        network.add_link("a1", 2, "b2", 1, 5).await.unwrap();
+        network.add_provider_customer_link("a1", 3, "o", 1, 0, (None, None)).await.unwrap();
+        network.add_peer_link("b1", 2, "n", 1, 0, (None, None)).await.unwrap();
+        network.add_peer_link("b2", 2, "n", 2, 0, (None, None)).await.unwrap();
+        network.set_igp_med("b1", "n", true).await.unwrap();
+        network.set_igp_med("b2", "n", true).await.unwrap();
+        network.add_ibgp_connection("a1", "b1").await.unwrap();
+        network.add_ibgp_connection("a1", "b2").await.unwrap();
+        network.add_ibgp_connection("b1", "b2").await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        network.announce_prefix("o").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        // the exit closer to a1, which learned the prefix from o, has the lowest MED
+        let prefix: IPPrefix = "10.0.3.0/24".parse().unwrap();
+        let (best, routes) = network.get_bgp_routes("n").await.unwrap().remove(&prefix).unwrap();
+        let meds: BTreeMap<Ipv4Addr, u32> = routes.iter().map(|r| (r.nexthop, r.med)).collect();
+        assert_eq!(meds, [("10.0.1.2".parse().unwrap(), 1), ("10.0.1.3".parse().unwrap(), 5)].into_iter().collect());
+        assert_eq!(best.unwrap().nexthop, "10.0.1.2".parse::<Ipv4Addr>().unwrap());
+
+        // b1 now reaches a1 with a cost of 10
+        network.remove_link("a1", 1, "b1", 1).await.unwrap();
+        network.add_link("a1", 1, "b1", 1, 10).await.unwrap();
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let (best, routes) = network.get_bgp_routes("n").await.unwrap().remove(&prefix).unwrap();
+        let meds: BTreeMap<Ipv4Addr, u32> = routes.iter().map(|r| (r.nexthop, r.med)).collect();
+        assert_eq!(meds, [("10.0.1.2".parse().unwrap(), 10), ("10.0.1.3".parse().unwrap(), 5)].into_iter().collect());
+        assert_eq!(best.unwrap().nexthop, "10.0.1.3".parse::<Ipv4Addr>().unwrap());
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_static_routes(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+        network.add_router("r4", 4, 1).unwrap();
+        for router in ["r1", "r2", "r3", "r4"]{
+            network.set_ospf(router, false).await.unwrap();
+        }
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 1, 1).await.unwrap();
+        network.add_link("r3", 2, "r4", 1, 1).await.unwrap();
+
+        let default: IPPrefix = "0.0.0.0/0".parse().unwrap();
+        let r1: IPPrefix = "10.0.1.1/32".parse().unwrap();
+        let r4: IPPrefix = "10.0.1.4/32".parse().unwrap();
+        network.add_static_route("r1", default, StaticNexthop::Address("10.0.1.2".parse().unwrap())).await.unwrap();
+        network.add_static_route("r2", r4, StaticNexthop::Address("10.0.1.3".parse().unwrap())).await.unwrap();
+        network.add_static_route("r2", r1, StaticNexthop::Port(1)).await.unwrap();
+        network.add_static_route("r3", r4, StaticNexthop::Port(2)).await.unwrap();
+        network.add_static_route("r3", default, StaticNexthop::Port(1)).await.unwrap();
+        network.add_static_route("r4", default, StaticNexthop::Port(1)).await.unwrap();
+
+        // wait for the nexthops to be resolved
+        thread::sleep(Duration::from_millis(500));
+
+        network.ping("r1", "10.0.1.4".parse().unwrap()).await.unwrap();
+        let table = network.get_routing_table("r2").await.unwrap();
+        assert_eq!(table.len(), 3);
+        assert_eq!(table[&r4], (2, 1, Static));
+        assert_eq!(table[&r1], (1, 1, Static));
+
+        assert_eq!(
+            network.add_static_route("r1", default, StaticNexthop::Address("10.0.1.3".parse().unwrap())).await,
+            Err(NetworkError::UnknownNexthop{router: "r1".into(), nexthop: "10.0.1.3".into()})
+        );
+        assert_eq!(
+            network.add_static_route("r1", default, StaticNexthop::Port(2)).await,
+            Err(NetworkError::UnknownNexthop{router: "r1".into(), nexthop: "port 2".into()})
+        );
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_forwarding_loop(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+        for router in ["r1", "r2", "r3"]{
+            network.set_ospf(router, false).await.unwrap();
+        }
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 1, 1).await.unwrap();
+
+        // r1 and r2 send the packets of the prefix to each other, r3 goes through r2
+        let prefix: IPPrefix = "10.99.0.0/16".parse().unwrap();
+        network.add_static_route("r1", prefix, StaticNexthop::Port(1)).await.unwrap();
+        network.add_static_route("r2", prefix, StaticNexthop::Port(1)).await.unwrap();
+        network.add_static_route("r3", prefix, StaticNexthop::Port(1)).await.unwrap();
+        let dest = Ipv4Addr::new(10, 99, 0, 1);
+        assert_eq!(network.find_loops(dest).await.unwrap(), vec![vec!["r1".to_string(), "r2".to_string()]]);
+        assert!(network.find_loops(Ipv4Addr::new(10, 98, 0, 1)).await.unwrap().is_empty());
+
+        let mut events = network.subscribe_events();
+        network.send_data("r3", dest, "looping").await.unwrap();
+        let event = wait_for_event(&mut events, Duration::from_secs(1), |event| matches!(event, NetworkEvent::ForwardingLoop{..})).await;
+        let Some(NetworkEvent::ForwardingLoop{router, path}) = event else { panic!("no forwarding loop detected") };
+        // the packet is dropped by the first router reached a second time
+        assert_eq!(router, "r2");
+        assert_eq!(path, vec![Ipv4Addr::new(10, 0, 1, 2), Ipv4Addr::new(10, 0, 1, 1)]);
+        assert_eq!(network.get_stats("r2").await.unwrap().loops_detected, 1);
+        assert_eq!(network.get_stats("r1").await.unwrap().loops_detected, 0);
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_static_route_survives_ospf(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r1", 2, "r3", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 2, 1).await.unwrap();
+        network.add_static_route("r1", "10.0.1.2/32".parse().unwrap(), StaticNexthop::Port(2)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        network.clear_routes("r1").await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let table = network.get_routing_table("r1").await.unwrap();
+        assert_eq!(table[&"10.0.1.2/32".parse().unwrap()], (2, 1, Static));
+        assert_eq!(table[&"10.0.1.3/32".parse().unwrap()], (2, 1, Ospf));
+        assert_eq!(network.traceroute("r1", "10.0.1.2".parse().unwrap()).await.unwrap().len(), 2);
+
+        network.quit().await;
+    }
+
+    async fn provider_of_two_customers() -> Network{
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 2).unwrap();
+        network.add_router("r3", 3, 3).unwrap();
+
+        network.add_provider_customer_link("r3", 1, "r1", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r3", 2, "r2", 1, 0, (None, None)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        network
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_announce_custom_prefix(){
+        let network = provider_of_two_customers().await;
+        let custom: IPPrefix = "192.0.2.0/24".parse().unwrap();
+        network.announce_prefix("r1").await.unwrap();
+        network.announce_prefix("r2").await.unwrap();
+        network.announce_custom_prefix("r1", custom).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let routes = network.get_bgp_routes("r3").await.unwrap();
+        for prefix in ["10.0.1.0/24".parse().unwrap(), custom]{
+            let best = routes[&prefix].0.clone().unwrap();
+            assert_eq!(best.nexthop, "10.0.1.1".parse::<Ipv4Addr>().unwrap());
+            assert_eq!(best.as_path, vec![1]);
+        }
+        assert!(network.get_bgp_routes("r2").await.unwrap().contains_key(&custom));
+        // r1 answers for the addresses of its custom prefix
+        network.ping("r2", "192.0.2.10".parse().unwrap()).await.unwrap();
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_more_specific_prefix_hijack(){
+        let network = provider_of_two_customers().await;
+        network.announce_prefix("r1").await.unwrap();
+        network.announce_custom_prefix("r2", "10.0.1.0/25".parse().unwrap()).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let routes = network.get_bgp_routes("r3").await.unwrap();
+        assert_eq!(routes[&"10.0.1.0/24".parse().unwrap()].0.clone().unwrap().as_path, vec![1]);
+        assert_eq!(routes[&"10.0.1.0/25".parse().unwrap()].0.clone().unwrap().as_path, vec![2]);
+
+        // the longest prefix wins, r2 answers for the addresses of the /25
+        network.ping("r3", "10.0.1.100".parse().unwrap()).await.unwrap();
+        assert_eq!(
+            network.ping("r3", "10.0.1.200".parse().unwrap()).await,
+            Err(NetworkError::Ping(PingError::Timeout("10.0.1.200".parse().unwrap())))
+        );
+
+        network.quit().await;
+    }
+
+    // ping to from router from and return the name of the router that answered it
+    async fn ping_receiver(network: &Network, handle: &logger::LogHandle, from: &str, to: Ipv4Addr) -> String{
+        let before = handle.messages().await.len();
+        network.ping(from, to).await.unwrap();
+        let src = network.routers[from].1;
+        let suffix = format!(" received ping from {}", src);
+        handle.wait_for_after(before, Source::PING, &suffix, LOG_TIMEOUT).await
+            .as_deref()
+            .and_then(|m| m.strip_suffix(&suffix).and_then(|m| m.strip_prefix("Router ")))
+            .expect("Failed to find the receiver of the ping")
+            .to_string()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_bgp_hijack(){
+        let (logger, handle) = Logger::start_capture();
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap(); // owner of 10.0.1.0/24
+        network.add_router("r2", 2, 2).unwrap(); // hijacker
+        network.add_router("r3", 3, 3).unwrap();
+        network.add_peer_link("r3", 1, "r1", 1, 0, (None, None)).await.unwrap();
+        network.add_provider_customer_link("r3", 2, "r2", 1, 0, (None, None)).await.unwrap();
+
+        network.wait_for_convergence(CONVERGENCE_TIMEOUT).await.unwrap();
+        for router in ["r1", "r2", "r3"]{
+            network.announce_prefix(router).await.unwrap();
+        }
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        let victim: Ipv4Addr = "10.0.1.1".parse().unwrap();
+        assert_eq!(ping_receiver(&network, &handle, "r3", victim).await, "r1");
+
+        // r3 prefers the route of its customer to the one of its peer
+        let prefix: IPPrefix = "10.0.1.0/24".parse().unwrap();
+        network.announce_custom_prefix("r2", prefix).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        assert_eq!(network.get_bgp_routes("r3").await.unwrap()[&prefix].0.clone().unwrap().as_path, vec![2]);
+        assert_eq!(ping_receiver(&network, &handle, "r3", victim).await, "r2");
+
+        // the more specific prefixes of the owner win over the hijacked one
+        for half in ["10.0.1.0/25", "10.0.1.128/25"]{
+            network.announce_custom_prefix("r1", half.parse().unwrap()).await.unwrap();
+        }
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        assert_eq!(ping_receiver(&network, &handle, "r3", victim).await, "r1");
+        assert_eq!(ping_receiver(&network, &handle, "r3", "10.0.1.200".parse().unwrap()).await, "r1");
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_received_lsp_limit(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        // room for the lsps of r1 and of one other router
+        network.set_limits(Limits{max_received_lsp: 2, ..Limits::default()}).await;
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 1).unwrap();
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_link("r2", 2, "r3", 1, 1).await.unwrap();
+        for _ in 0..3{
+            thread::sleep(Duration::from_millis(250));
+            network.remove_link("r2", 2, "r3", 1).await.unwrap();
+            thread::sleep(Duration::from_millis(250));
+            network.add_link("r2", 2, "r3", 1, 1).await.unwrap();
+        }
+        thread::sleep(Duration::from_millis(500));
+
+        let stats = network.memory_stats("r1").await.unwrap();
+        assert!(stats.received_lsp <= 2);
+        assert!(stats.lsp_evictions > 0);
+        // the lsp of r1 is never forgotten, its neighbors stay reachable
+        assert!(network.get_lsdb("r1").await.unwrap().originators.contains_key(&"10.0.1.1".parse().unwrap()));
+        assert_eq!(network.get_routing_table("r1").await.unwrap().get(&"10.0.1.2/32".parse().unwrap()), Some(&(1, 1, Ospf)));
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_lsdb_aging(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        network.set_timers(TimerConfig{lsp_refresh_interval: Duration::from_millis(100), ..TimerConfig::default()});
+        for i in 1..=4{
+            network.add_router(&format!("r{}", i), i, 1).unwrap();
+        }
+        let links = [("r1", "r2"), ("r2", "r3"), ("r3", "r4"), ("r1", "r3")];
+        for (port, (r1, r2)) in links.iter().enumerate(){
+            network.add_link(r1, port as u32 + 1, r2, port as u32 + 1, 1).await.unwrap();
+        }
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        // each originator advertises the routers it is linked to
+        let router_neighbors = |lsa: &LsaInfo| -> BTreeSet<Ipv4Addr> {
+            lsa.neighbors.iter().filter(|(_, prefix)| prefix.prefix_len == 32).map(|(_, prefix)| prefix.ip).collect()
+        };
+        let lsdb = network.get_lsdb("r1").await.unwrap();
+        assert_eq!(lsdb.originators.len(), 4);
+        for i in 1..=4{
+            let router = format!("r{}", i);
+            let adjacent: BTreeSet<Ipv4Addr> = links.iter()
+                .filter_map(|(r1, r2)| if *r1 == router { Some(r2) } else if *r2 == router { Some(r1) } else { None })
+                .map(|neighbor| network.router(neighbor).unwrap().1)
+                .collect();
+            let origin = network.router(&router).unwrap().1;
+            assert_eq!(router_neighbors(&lsdb.originators[&origin]), adjacent, "lsp of {}", router);
+        }
+
+        // r4 dies without telling r3, its lsp isn't refreshed anymore
+        let r4 = network.router("r4").unwrap().1;
+        network.stop_device("r4").await.unwrap();
+        let r3 = network.router("r3").unwrap().1;
+        let aged = |lsdb: &Lsdb, table: &HashMap<IPPrefix, RIBEntry>| !lsdb.originators.contains_key(&r4)
+            && lsdb.originators.get(&r3).is_some_and(|lsa| !router_neighbors(lsa).contains(&r4))
+            && !table.contains_key(&IPPrefix{ip: r4, prefix_len: 32});
+        let start = std::time::Instant::now();
+        loop{
+            let lsdb = network.get_lsdb("r1").await.unwrap();
+            let table = network.get_routing_table("r1").await.unwrap();
+            if aged(&lsdb, &table) || start.elapsed() > Duration::from_secs(5){
+                assert!(!lsdb.originators.contains_key(&r4));
+                assert_eq!(lsdb.originators.len(), 3);
+                assert!(!router_neighbors(&lsdb.originators[&r3]).contains(&r4));
+                assert!(!table.contains_key(&IPPrefix{ip: r4, prefix_len: 32}));
+                break;
             }
-        
-            // wait for convergence
-            thread::sleep(Duration::from_millis(1000));
-        
-            network.announce_prefix("r4").await;
-            network.announce_prefix("r5").await;
-        
-            thread::sleep(Duration::from_millis(1000));
-        
-            let bgp_table = network.get_bgp_routes("r2").await;
-            let mut expected_table = HashMap::new();
-            expected_table.insert("10.0.2.0/24".parse().unwrap(), (Some(BGPRoute{
-                prefix: "10.0.2.0/24".parse().unwrap(),
-                nexthop: "10.0.1.1".parse().unwrap(),
-                as_path: vec![2],
-                pref: 50,
-                med: 0,
-                router_id: 1,
-                source: RouteSource::IBGP,
-            }), [BGPRoute{
-                prefix: "10.0.2.0/24".parse().unwrap(),
-                nexthop: "10.0.1.1".parse().unwrap(),
-                as_path: vec![2],
-                pref: 50,
-                med: 0,
-                router_id: 1,
-                source: RouteSource::IBGP,
-            }].into_iter().collect()));
+            thread::sleep(Duration::from_millis(100));
+        }
 
-            expected_table.insert("10.0.3.0/24".parse().unwrap(), (Some(BGPRoute{
-                prefix: "10.0.3.0/24".parse().unwrap(),
-                nexthop: "10.0.1.3".parse().unwrap(),
-                as_path: vec![3],
-                pref: 150,
-                med: 0,
-                router_id: 3,
-                source: RouteSource::IBGP,
-            }), [BGPRoute{
-                prefix: "10.0.3.0/24".parse().unwrap(),
-                nexthop: "10.0.1.3".parse().unwrap(),
-                as_path: vec![3],
-                pref: 150,
-                med: 0,
-                router_id: 3,
-                source: RouteSource::IBGP,
-            }].into_iter().collect()));
-            assert_eq!(bgp_table, expected_table);
+        network.quit().await;
+    }
 
-        
-            network.quit().await;
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_network_builder(){
+        let logger = Logger::start_test();
+        let timers = TimerConfig{keepalive_interval: Duration::from_millis(50), ..TimerConfig::default()};
+        let network = builder::NetworkBuilder::new(logger)
+            .output_mode(OutputMode::Pretty{color: false})
+            .limits(Limits{max_received_lsp: 4, ..Limits::default()})
+            .timers(timers.clone())
+            .dead_interval(Duration::from_millis(300))
+            .build();
+
+        assert_eq!(network.output_mode, OutputMode::Pretty{color: false});
+        assert_eq!(network.limits.max_received_lsp, 4);
+        assert_eq!(network.timers, timers);
+        assert_eq!(network.dead_interval, Duration::from_millis(300));
+        // not set, kept to its default
+        assert_eq!(network.bpdu_max_age, switch::BPDU_MAX_AGE);
+
+        network.quit().await;
+    }
+
+    // churns the network for a minute, run with `cargo test -- --ignored`
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore]
+    async fn test_soak_bounded_memory(){
+        let logger = Logger::start_test();
+        let mut network = Network::new(logger);
+        let limits = Limits{max_received_lsp: 32, max_routes_per_prefix: 8, ..Limits::default()};
+        network.set_limits(limits.clone()).await;
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_router("r3", 3, 2).unwrap();
+        network.add_router("r4", 4, 3).unwrap();
+
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.add_provider_customer_link("r3", 1, "r1", 2, 0, (None, None)).await.unwrap();
+        network.add_peer_link("r3", 2, "r4", 1, 0, (None, None)).await.unwrap();
+        network.add_ibgp_connection("r1", "r2").await.unwrap();
+
+        let start = std::time::Instant::now();
+        let mut pref = 0;
+        while start.elapsed() < Duration::from_secs(60){
+            pref = (pref + 1) % 20;
+            network.announce_prefix_with_pref("r4", pref).await.unwrap();
+            network.remove_link("r1", 1, "r2", 1).await.unwrap();
+            thread::sleep(Duration::from_millis(100));
+            network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        for router in network.routers(){
+            let stats = network.memory_stats(&router).await.unwrap();
+            assert!(stats.received_lsp <= limits.max_received_lsp, "{} remembers {} lsps", router, stats.received_lsp);
+            assert!(stats.bgp_routes <= limits.max_routes_per_prefix * 3, "{} has {} bgp routes", router, stats.bgp_routes);
+        }
+
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_forwarding_graph(){
+        let config = scenario::load_config("examples/ospf-example.yaml").unwrap();
+        let mut network = Network::new(Logger::start_test());
+        scenario::build_network(&mut network, &config).await.unwrap();
+        network.wait_for_ospf(scenario::CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let dest = Ipv4Addr::new(10, 0, 1, 1);
+        let hops = network.forwarding(dest).await.unwrap();
+        assert_eq!(hops["r1"], ForwardingHop::Local);
+        assert_eq!(hops["r2"], ForwardingHop::Link{egress: 1, device: "r1".into(), port: 1});
+        // the egress links lead every router to r1 without loop, they form a tree rooted at it
+        for router in hops.keys(){
+            let mut current = router.clone();
+            let mut visited = HashSet::new();
+            while let ForwardingHop::Link{device, ..} = &hops[&current]{
+                assert!(visited.insert(current.clone()), "{} loops through {}", router, current);
+                current = device.clone();
+            }
+            assert_eq!(current, "r1");
         }
+        let dot = network.dot_forwarding(dest).await.unwrap();
+        assert_eq!(dot.lines().filter(|line| line.contains("color=darkgreen")).count(), hops.len() - 1);
+        assert!(dot.contains("r1[shape=rect,style=filled,fillcolor=palegreen];"));
+        assert!(dot.contains("r1 -> r2[label=\"1\",headlabel=\"1\",taillabel=\"1\",color=darkgreen,penwidth=3,dir=back];"), "{}", dot);
+
+        // a router without route towards the destination
+        network.add_router("r5", 5, 1).unwrap();
+        assert_eq!(network.forwarding(dest).await.unwrap()["r5"], ForwardingHop::Unreachable);
+        assert!(network.dot_forwarding(dest).await.unwrap().contains("r5[shape=rect,color=grey,fontcolor=grey];"));
+        network.quit().await;
     }
 }
@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use super::{limits::Limits, logger::Logger, output::OutputMode, timers::TimerConfig, Network};
+
+// Configuration of a network set before its first device is added
+#[derive(Debug)]
+pub struct NetworkBuilder{
+    logger: Logger,
+    output_mode: OutputMode,
+    limits: Limits,
+    timers: TimerConfig,
+    dead_interval: Option<Duration>,
+    bpdu_max_age: Option<Duration>
+}
+
+impl NetworkBuilder{
+    pub fn new(logger: Logger) -> NetworkBuilder{
+        NetworkBuilder{
+            logger,
+            output_mode: OutputMode::Plain,
+            limits: Limits::default(),
+            timers: TimerConfig::default(),
+            dead_interval: None,
+            bpdu_max_age: None
+        }
+    }
+
+    pub fn output_mode(mut self, mode: OutputMode) -> NetworkBuilder{
+        self.output_mode = mode;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> NetworkBuilder{
+        self.limits = limits;
+        self
+    }
+
+    pub fn timers(mut self, timers: TimerConfig) -> NetworkBuilder{
+        self.timers = timers;
+        self
+    }
+
+    pub fn dead_interval(mut self, dead_interval: Duration) -> NetworkBuilder{
+        self.dead_interval = Some(dead_interval);
+        self
+    }
+
+    pub fn bpdu_max_age(mut self, max_age: Duration) -> NetworkBuilder{
+        self.bpdu_max_age = Some(max_age);
+        self
+    }
+
+    pub fn build(self) -> Network{
+        let mut network = Network::new(self.logger);
+        network.output_mode = self.output_mode;
+        network.limits = self.limits;
+        network.timers = self.timers;
+        if let Some(dead_interval) = self.dead_interval{
+            network.dead_interval = dead_interval;
+        }
+        if let Some(max_age) = self.bpdu_max_age{
+            network.bpdu_max_age = max_age;
+        }
+        network
+    }
+}
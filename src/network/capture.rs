@@ -0,0 +1,123 @@
+use std::{fs::File, io::{BufRead, BufReader, BufWriter, Write}, sync::{Arc, Mutex}, time::{SystemTime, UNIX_EPOCH}};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc::{unbounded_channel, UnboundedSender}, task::JoinHandle};
+
+use super::{error::NetworkError, messages::{ip::Content, Message}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction{
+    Sent,
+    Received
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol{
+    STP,
+    OSPF,
+    IP,
+    BGP,
+    IBGP, // carried by IP between the routers of an AS
+    ARP
+}
+
+// A message seen by a device on one of its ports, one line of a capture file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRecord{
+    pub timestamp_us: u64, // since the unix epoch
+    pub device: String,
+    pub port: u32,
+    pub direction: Direction,
+    pub message: Message
+}
+
+impl CaptureRecord{
+    pub fn protocol(&self) -> Protocol{
+        match &self.message{
+            Message::Bpdu(_) => Protocol::STP,
+            Message::Ospf(_) => Protocol::OSPF,
+            Message::EthernetFrame(_, _, ip) if matches!(ip.content, Content::Ibgp(_)) => Protocol::IBGP,
+            Message::EthernetFrame(_, _, _) => Protocol::IP,
+            Message::Bgp(_) => Protocol::BGP,
+            Message::Arp(_) => Protocol::ARP,
+        }
+    }
+}
+
+// Records of a capture file, in the order they were written
+#[derive(Debug, Clone, Default)]
+pub struct Capture{
+    pub records: Vec<CaptureRecord>
+}
+
+impl Capture{
+    pub fn load(path: &str) -> Result<Capture, NetworkError>{
+        let f = File::open(path).map_err(|e| NetworkError::Capture(format!("cannot open {}: {}", path, e)))?;
+        let mut records = vec![];
+        for (number, line) in BufReader::new(f).lines().enumerate(){
+            let line = line.map_err(|e| NetworkError::Capture(format!("cannot read {}: {}", path, e)))?;
+            let record = serde_json::from_str(&line).map_err(|e| NetworkError::Capture(format!("line {} of {} is not a record: {}", number + 1, path, e)))?;
+            records.push(record);
+        }
+        Ok(Capture{records})
+    }
+
+    pub fn by_device(&self, device: &str) -> Capture{
+        self.filter(|record| record.device == device)
+    }
+
+    pub fn by_protocol(&self, protocol: Protocol) -> Capture{
+        self.filter(|record| record.protocol() == protocol)
+    }
+
+    pub fn by_direction(&self, direction: Direction) -> Capture{
+        self.filter(|record| record.direction == direction)
+    }
+
+    pub fn len(&self) -> usize{
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.records.is_empty()
+    }
+
+    fn filter(&self, keep: impl Fn(&CaptureRecord) -> bool) -> Capture{
+        Capture{records: self.records.iter().filter(|record| keep(record)).cloned().collect()}
+    }
+}
+
+// writer of the capture of a network shared by all its links, none when the messages aren't captured
+pub(crate) type CaptureSlot = Arc<Mutex<Option<CaptureWriter>>>;
+
+// Sending end of the task writing a capture file, one json record per line
+#[derive(Debug, Clone)]
+pub(crate) struct CaptureWriter{
+    sender: UnboundedSender<CaptureRecord>
+}
+
+impl CaptureWriter{
+    // the file is complete once every writer was dropped and the task returned
+    pub fn start(path: &str) -> Result<(CaptureWriter, JoinHandle<()>), NetworkError>{
+        let f = File::create(path).map_err(|e| NetworkError::Capture(format!("cannot create {}: {}", path, e)))?;
+        let (sender, mut receiver) = unbounded_channel::<CaptureRecord>();
+        let handle = tokio::spawn(async move {
+            let mut writer = BufWriter::new(f);
+            while let Some(record) = receiver.recv().await{
+                // a capture is a debugging aid, a failed write mustn't stop the simulation
+                let _ = serde_json::to_writer(&mut writer, &record).map_err(std::io::Error::from).and_then(|_| writer.write_all(b"\n"));
+                if receiver.is_empty(){
+                    let _ = writer.flush();
+                }
+            }
+            let _ = writer.flush();
+        });
+        Ok((CaptureWriter{sender}, handle))
+    }
+
+    pub fn record(&self, device: &str, port: u32, direction: Direction, message: &Message){
+        let timestamp_us = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        // the capture may have been stopped while the message was carried
+        let _ = self.sender.send(CaptureRecord{timestamp_us, device: device.to_string(), port, direction, message: message.clone()});
+    }
+}
@@ -1,113 +1,401 @@
 use crate::network::PortState;
-use crate::network::messages::Message;
-use std::{cell::RefCell, collections::{BTreeMap, HashMap, HashSet}, net::Ipv4Addr, rc::Rc};
-use tokio::sync::mpsc::{Receiver, Sender};
+use crate::network::messages::{ip::RecordedRoute, Message};
+use std::{collections::{BTreeMap, HashMap, HashSet}, net::Ipv4Addr, time::Duration};
+use tokio::sync::{mpsc::{Receiver, Sender}, oneshot};
 
-use super::{ip_prefix::IPPrefix, protocols::bgp::BGPRoute};
+use super::{rib::RIBEntry, link::LinkSender, utils::MacAddress, error::PingError, health::{PortInfo, RouterHealth}, ip_prefix::IPPrefix, limits::{Limits, MemoryStats}, stats::DeviceStats, traffic::PingReport, protocols::{bgp::{BGPRoute, BGPSession, CommunityAction, MaxPrefixAction, Origin}, ospf::Lsdb, policy::RoutePolicy}};
 
+pub type RoutingTable = HashMap<IPPrefix, RIBEntry>;
+pub type BGPRoutes = HashMap<IPPrefix, (Option<BGPRoute>, HashSet<BGPRoute>)>;
+pub type EqualCostPorts = HashMap<IPPrefix, Vec<u32>>; // ports of the shortest OSPF paths for the destinations with several ones
+pub type MultipathRoutes = HashMap<IPPrefix, Vec<(BGPRoute, u32)>>; // routes used for the prefixes with several ones, with the port of their nexthop
+
+// longest wait for the answer of a device to a query, a dead or stuck device never answers
+pub const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+// a dead device ignores the commands, Network::device_health tells why it died
+async fn send(sender: &Sender<Command>, command: Command){
+    let _ = sender.send(command).await;
+}
+
+async fn query<T>(sender: &Sender<Command>, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> Result<T, ()>{
+    let (tx, rx) = oneshot::channel();
+    let answer = async {
+        sender.send(command(tx)).await.map_err(|_| ())?;
+        rx.await.map_err(|_| ())
+    };
+    tokio::time::timeout(QUERY_TIMEOUT, answer).await.unwrap_or(Err(()))
+}
+
+// the queries carry the channel their answer is sent on
 pub enum Command{
-    StatePorts,
-    RoutingTable,
-    BGPRoutes,
-    AddLink(Receiver<Message>, Sender<Message>, u32, u32),
-    AddPeerLink(Receiver<Message>, Sender<Message>, u32, u32, Ipv4Addr),
-    AddProvider(Receiver<Message>, Sender<Message>, u32, u32, Ipv4Addr),
-    AddCustomer(Receiver<Message>, Sender<Message>, u32, u32, Ipv4Addr),
+    StatePorts(oneshot::Sender<BTreeMap<u32, PortState>>),
+    MacTable(oneshot::Sender<HashMap<MacAddress, u32>>),
+    RoutingTable(oneshot::Sender<RoutingTable>),
+    BGPRoutes(oneshot::Sender<BGPRoutes>),
+    MultipathRoutes(oneshot::Sender<MultipathRoutes>),
+    EqualCostPorts(oneshot::Sender<EqualCostPorts>),
+    Lsdb(oneshot::Sender<Lsdb>),
+    BGPSessions(oneshot::Sender<Vec<BGPSession>>),
+    AddLink(Receiver<Message>, LinkSender, u32, u32),
+    SetInterfaceAddress(u32, Ipv4Addr, IPPrefix), // port, address of the router on the subnet of the link, subnet
+    AddPeerLink(Receiver<Message>, LinkSender, u32, u32, Ipv4Addr, Option<u32>), // the last field overrides the pref of the relationship
+    AddProvider(Receiver<Message>, LinkSender, u32, u32, Ipv4Addr, Option<u32>),
+    AddCustomer(Receiver<Message>, LinkSender, u32, u32, Ipv4Addr, Option<u32>),
     AddIBGP(Ipv4Addr),
-    Ping(Ipv4Addr),
-    AnnouncePrefix,
+    AddIBGPClient(Ipv4Addr),
+    RemoveLink(u32),
+    SetPortEnabled(u32, bool), // a disabled port keeps its link but neither sends nor receives
+    SetLinkCost(u32, u32), // port, new cost of its link
+    Ping(Ipv4Addr, oneshot::Sender<Result<Duration, PingError>>),
+    Traceroute(Ipv4Addr, oneshot::Sender<Vec<Ipv4Addr>>),
+    PingRecorded(Ipv4Addr, oneshot::Sender<Result<RecordedRoute, PingError>>), // ping recording the routers it crosses in both directions
+    PingSeries(Ipv4Addr, u32, Duration, oneshot::Sender<PingReport>), // count, interval, answered once the last ping was answered or timed out
+    SendData(Ipv4Addr, String),
+    ReceivedData(oneshot::Sender<Vec<(Ipv4Addr, String)>>), // source and payload of the data received, in order
+    SendProbe(Ipv4Addr, u64), // destination, flow
+    ReceivedProbes(u64, oneshot::Sender<(u64, Duration)>), // probes of the flow received and sum of their latencies
+    AnnouncePrefix(u32),
+    AnnouncePrefixCustom(IPPrefix),
+    AnnouncePrefixWithCommunities(Vec<u32>),
+    AnnouncePrefixWithOrigin(Origin),
+    WithdrawPrefix,
+    ClearRoutes,
+    ClearBGP,
+    AddConditionalAdvertisement(IPPrefix, IPPrefix, u32),
+    SetLocalPref(u32, Option<u32>),
+    SetIGPMed(u32, bool),
+    SetMultipath(bool),
+    SetEcmp(bool),
+    SetPrepend(u32, u32), // port, extra times the AS is prepended
+    AddCommunityPolicy(u32, CommunityAction),
+    SetMaxPrefixes(u32, u32, MaxPrefixAction), // port, most prefixes accepted, action above
+    SetImportPolicy(u32, RoutePolicy),
+    SetExportPolicy(u32, RoutePolicy),
+    AddStaticRoute(IPPrefix, u32, Ipv4Addr),
+    SetOSPF(bool),
+    SetSessionSecret(Ipv4Addr, Option<String>),
+    SetLimits(Limits),
+    SetMaxAge(Duration),
+    SetDeadInterval(Duration),
+    MemoryStats(oneshot::Sender<MemoryStats>),
+    Health(oneshot::Sender<RouterHealth>),
+    RouterPorts(oneshot::Sender<BTreeMap<u32, PortInfo>>),
+    Stats(oneshot::Sender<DeviceStats>), // messages sent and received by the device
+    ConvergenceState(oneshot::Sender<bool>), // whether the routes of the router stopped changing
+    Replay(Vec<(u32, Message)>, oneshot::Sender<()>), // messages handled as if received on their port, answered once all were
+    #[cfg(test)]
+    Panic, // the device dies, to check that the network copes with it
     Quit
 }
 
-pub enum Response{
-    StatePorts(BTreeMap<u32, PortState>),
-    RoutingTable(HashMap<IPPrefix, (u32, u32)>),
-    BGPRoutes(HashMap<IPPrefix, (Option<BGPRoute>, HashSet<BGPRoute>)>)
-}
-
 #[derive(Debug)]
 pub struct SwitchCommunicator{
-    pub command_sender: Sender<Command>, 
-    pub response_receiver: Rc<RefCell<Receiver<Response>>>
+    pub command_sender: Sender<Command>
 }
 
 impl SwitchCommunicator {
 
-    pub async fn add_link(&self, receiver: Receiver<Message>, sender: Sender<Message>, port: u32, cost: u32) {
-        self.command_sender.send(Command::AddLink(receiver, sender, port, cost)).await.expect("Failed to send add link command");
+    pub async fn add_link(&self, receiver: Receiver<Message>, sender: LinkSender, port: u32, cost: u32) {
+        send(&self.command_sender, Command::AddLink(receiver, sender, port, cost)).await;
+    }
+
+    pub async fn remove_link(&self, port: u32) {
+        send(&self.command_sender, Command::RemoveLink(port)).await;
+    }
+
+    pub async fn set_port_enabled(&self, port: u32, enabled: bool) {
+        send(&self.command_sender, Command::SetPortEnabled(port, enabled)).await;
+    }
+
+    pub async fn set_link_cost(&self, port: u32, cost: u32) {
+        send(&self.command_sender, Command::SetLinkCost(port, cost)).await;
+    }
+
+    pub async fn set_max_age(&self, max_age: Duration) {
+        send(&self.command_sender, Command::SetMaxAge(max_age)).await;
+    }
+
+    pub async fn replay(&self, messages: Vec<(u32, Message)>) -> Result<(), ()>{
+        let (tx, rx) = oneshot::channel();
+        send(&self.command_sender, Command::Replay(messages, tx)).await;
+        rx.await.map_err(|_| ())
     }
 
     pub async fn quit(self){
-        self.command_sender.send(Command::Quit).await.expect("Failed to send quit message");
+        send(&self.command_sender, Command::Quit).await;
     }
 
     pub async fn get_port_state(&self) -> Result<BTreeMap<u32, PortState>, ()>{
-        self.command_sender.send(Command::StatePorts).await.expect("Failed to send StatePorts message");
-        match self.response_receiver.borrow_mut().recv().await{
-            Some(Response::StatePorts(ports)) => Ok(ports),
-            Some(Response::RoutingTable(_)) => panic!("Unexpected answer"),
-            Some(Response::BGPRoutes(_)) => panic!("Unexpected answer"),
-            None => Err(()),
-        }
+        query(&self.command_sender, Command::StatePorts).await
+    }
+
+    pub async fn get_mac_table(&self) -> Result<HashMap<MacAddress, u32>, ()>{
+        query(&self.command_sender, Command::MacTable).await
+    }
+
+    pub async fn get_stats(&self) -> Result<DeviceStats, ()>{
+        query(&self.command_sender, Command::Stats).await
     }
 }
 
 #[derive(Debug)]
+pub struct HostCommunicator{
+    pub command_sender: Sender<Command>
+}
+
+impl HostCommunicator {
+
+    pub async fn add_link(&self, receiver: Receiver<Message>, sender: LinkSender, port: u32, cost: u32) {
+        send(&self.command_sender, Command::AddLink(receiver, sender, port, cost)).await;
+    }
+
+    pub async fn remove_link(&self, port: u32) {
+        send(&self.command_sender, Command::RemoveLink(port)).await;
+    }
+
+    pub async fn ping(&self, ip: Ipv4Addr) -> Result<Duration, PingError>{
+        let (tx, rx) = oneshot::channel();
+        send(&self.command_sender, Command::Ping(ip, tx)).await;
+        rx.await.unwrap_or(Err(PingError::RouterStopped))
+    }
+
+    pub async fn quit(self){
+        send(&self.command_sender, Command::Quit).await;
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct RouterCommunicator{
-    pub command_sender: Sender<Command>, 
-    pub response_receiver: Rc<RefCell<Receiver<Response>>>
+    pub command_sender: Sender<Command>
 }
 
 impl RouterCommunicator {
-    pub async fn add_link(&self, receiver: Receiver<Message>, sender: Sender<Message>, port: u32, cost: u32) {
-        self.command_sender.send(Command::AddLink(receiver, sender, port, cost)).await.expect("Failed to send add link command");
+    pub async fn add_link(&self, receiver: Receiver<Message>, sender: LinkSender, port: u32, cost: u32) {
+        send(&self.command_sender, Command::AddLink(receiver, sender, port, cost)).await;
     }
 
-    pub async fn add_peer_link(&self, receiver: Receiver<Message>, sender: Sender<Message>, port: u32, med: u32, other_ip: Ipv4Addr) {
-        self.command_sender.send(Command::AddPeerLink(receiver, sender, port, med, other_ip)).await.expect("Failed to send add peer link command");
+    pub async fn set_interface_address(&self, port: u32, address: Ipv4Addr, subnet: IPPrefix) {
+        send(&self.command_sender, Command::SetInterfaceAddress(port, address, subnet)).await;
     }
 
-    pub async fn add_customer_link(&self, receiver: Receiver<Message>, sender: Sender<Message>, port: u32, med: u32, other_ip: Ipv4Addr) {
-        self.command_sender.send(Command::AddCustomer(receiver, sender, port, med, other_ip)).await.expect("Failed to send add customer link command");
+    pub async fn add_peer_link(&self, receiver: Receiver<Message>, sender: LinkSender, port: u32, med: u32, other_ip: Ipv4Addr, pref: Option<u32>) {
+        send(&self.command_sender, Command::AddPeerLink(receiver, sender, port, med, other_ip, pref)).await;
     }
 
-    pub async fn add_provider_link(&self, receiver: Receiver<Message>, sender: Sender<Message>, port: u32, med: u32, other_ip: Ipv4Addr) {
-        self.command_sender.send(Command::AddProvider(receiver, sender, port, med, other_ip)).await.expect("Failed to send add provider link command");
+    pub async fn add_customer_link(&self, receiver: Receiver<Message>, sender: LinkSender, port: u32, med: u32, other_ip: Ipv4Addr, pref: Option<u32>) {
+        send(&self.command_sender, Command::AddCustomer(receiver, sender, port, med, other_ip, pref)).await;
+    }
+
+    pub async fn add_provider_link(&self, receiver: Receiver<Message>, sender: LinkSender, port: u32, med: u32, other_ip: Ipv4Addr, pref: Option<u32>) {
+        send(&self.command_sender, Command::AddProvider(receiver, sender, port, med, other_ip, pref)).await;
     }
 
     pub async fn add_ibgp_connection(&self, other_ip: Ipv4Addr) {
-        self.command_sender.send(Command::AddIBGP(other_ip)).await.expect("Failed to send add ibgp command");
+        send(&self.command_sender, Command::AddIBGP(other_ip)).await;
+    }
+
+    // the routes are reflected to the client, which doesn't need to be connected to the other peers
+    pub async fn add_ibgp_client(&self, client_ip: Ipv4Addr) {
+        send(&self.command_sender, Command::AddIBGPClient(client_ip)).await;
+    }
+
+    pub async fn remove_link(&self, port: u32) {
+        send(&self.command_sender, Command::RemoveLink(port)).await;
+    }
+
+    pub async fn set_port_enabled(&self, port: u32, enabled: bool) {
+        send(&self.command_sender, Command::SetPortEnabled(port, enabled)).await;
+    }
+
+    pub async fn set_link_cost(&self, port: u32, cost: u32) {
+        send(&self.command_sender, Command::SetLinkCost(port, cost)).await;
+    }
+
+    pub async fn ping(&self, ip: Ipv4Addr) -> Result<Duration, PingError>{
+        let (tx, rx) = oneshot::channel();
+        send(&self.command_sender, Command::Ping(ip, tx)).await;
+        rx.await.unwrap_or(Err(PingError::RouterStopped))
+    }
+
+    pub async fn ping_series(&self, ip: Ipv4Addr, count: u32, interval: Duration) -> Result<PingReport, ()>{
+        let (tx, rx) = oneshot::channel();
+        send(&self.command_sender, Command::PingSeries(ip, count, interval, tx)).await;
+        rx.await.map_err(|_| ())
+    }
+
+    pub async fn ping_recorded(&self, ip: Ipv4Addr) -> Result<RecordedRoute, PingError>{
+        let (tx, rx) = oneshot::channel();
+        send(&self.command_sender, Command::PingRecorded(ip, tx)).await;
+        rx.await.unwrap_or(Err(PingError::RouterStopped))
+    }
+
+    pub async fn traceroute(&self, ip: Ipv4Addr) -> Result<Vec<Ipv4Addr>, ()>{
+        let (tx, rx) = oneshot::channel();
+        send(&self.command_sender, Command::Traceroute(ip, tx)).await;
+        rx.await.map_err(|_| ())
+    }
+
+    pub async fn send_data(&self, ip: Ipv4Addr, payload: String){
+        send(&self.command_sender, Command::SendData(ip, payload)).await;
+    }
+
+    pub async fn get_received_data(&self) -> Result<Vec<(Ipv4Addr, String)>, ()>{
+        query(&self.command_sender, Command::ReceivedData).await
+    }
+
+    pub async fn send_probe(&self, ip: Ipv4Addr, flow: u64){
+        send(&self.command_sender, Command::SendProbe(ip, flow)).await;
+    }
+
+    pub async fn get_received_probes(&self, flow: u64) -> Result<(u64, Duration), ()>{
+        query(&self.command_sender, |tx| Command::ReceivedProbes(flow, tx)).await
+    }
+
+    pub async fn set_limits(&self, limits: Limits){
+        send(&self.command_sender, Command::SetLimits(limits)).await;
+    }
+
+    pub async fn set_dead_interval(&self, dead_interval: Duration){
+        send(&self.command_sender, Command::SetDeadInterval(dead_interval)).await;
+    }
+
+    pub async fn get_memory_stats(&self) -> Result<MemoryStats, ()>{
+        query(&self.command_sender, Command::MemoryStats).await
+    }
+
+    pub async fn get_health(&self) -> Result<RouterHealth, ()>{
+        query(&self.command_sender, Command::Health).await
+    }
+
+    pub async fn get_stats(&self) -> Result<DeviceStats, ()>{
+        query(&self.command_sender, Command::Stats).await
+    }
+
+    pub async fn get_ports(&self) -> Result<BTreeMap<u32, PortInfo>, ()>{
+        query(&self.command_sender, Command::RouterPorts).await
+    }
+
+    pub async fn announce_prefix(&self, pref: u32){
+        send(&self.command_sender, Command::AnnouncePrefix(pref)).await;
+    }
+
+    pub async fn announce_prefix_with_communities(&self, communities: Vec<u32>){
+        send(&self.command_sender, Command::AnnouncePrefixWithCommunities(communities)).await;
+    }
+
+    pub async fn announce_prefix_with_origin(&self, origin: Origin){
+        send(&self.command_sender, Command::AnnouncePrefixWithOrigin(origin)).await;
+    }
+
+    pub async fn announce_custom_prefix(&self, prefix: IPPrefix){
+        send(&self.command_sender, Command::AnnouncePrefixCustom(prefix)).await;
+    }
+
+    pub async fn withdraw_prefix(&self){
+        send(&self.command_sender, Command::WithdrawPrefix).await;
+    }
+
+    pub async fn clear_routes(&self){
+        send(&self.command_sender, Command::ClearRoutes).await;
+    }
+
+    pub async fn clear_bgp(&self){
+        send(&self.command_sender, Command::ClearBGP).await;
+    }
+
+    pub async fn add_conditional_advertisement(&self, prefix: IPPrefix, unless_present: IPPrefix, port: u32){
+        send(&self.command_sender, Command::AddConditionalAdvertisement(prefix, unless_present, port)).await;
+    }
+
+    pub async fn set_local_pref(&self, port: u32, pref: Option<u32>){
+        send(&self.command_sender, Command::SetLocalPref(port, pref)).await;
+    }
+
+    pub async fn add_static_route(&self, prefix: IPPrefix, port: u32, nexthop: Ipv4Addr){
+        send(&self.command_sender, Command::AddStaticRoute(prefix, port, nexthop)).await;
     }
 
-    pub async fn ping(&self, ip: Ipv4Addr){
-        self.command_sender.send(Command::Ping(ip)).await.expect("Failed to send ping command");
+    pub async fn set_ospf(&self, enabled: bool){
+        send(&self.command_sender, Command::SetOSPF(enabled)).await;
     }
 
-    pub async fn announce_prefix(&self){
-        self.command_sender.send(Command::AnnouncePrefix).await.expect("Failed to send announce prefix command");
+    pub async fn set_igp_med(&self, port: u32, enabled: bool){
+        send(&self.command_sender, Command::SetIGPMed(port, enabled)).await;
     }
 
-    pub async fn get_routing_table(&self) -> Result<HashMap<IPPrefix, (u32, u32)>, ()>{
-        self.command_sender.send(Command::RoutingTable).await.expect("Failed to send RoutingTable message");
-        match self.response_receiver.borrow_mut().recv().await{
-            Some(Response::StatePorts(_)) => panic!("Unexpected answer"),
-            Some(Response::BGPRoutes(_)) => panic!("Unexpected answer"),
-            Some(Response::RoutingTable(table)) => Ok(table),
-            None => Err(()),
-        }
+    pub async fn set_prepend(&self, port: u32, count: u32){
+        send(&self.command_sender, Command::SetPrepend(port, count)).await;
     }
 
-    pub async fn get_bgp_routes(&self) -> Result<HashMap<IPPrefix, (Option<BGPRoute>, HashSet<BGPRoute>)>, ()>{
-        self.command_sender.send(Command::BGPRoutes).await.expect("Failed to send BGPRoutes message");
-        match self.response_receiver.borrow_mut().recv().await{
-            Some(Response::StatePorts(_)) => panic!("Unexpected answer"),
-            Some(Response::BGPRoutes(routes)) => Ok(routes),
-            Some(Response::RoutingTable(_)) => panic!("Unexpected answer"),
-            None => Err(()),
-        }
+    pub async fn add_community_policy(&self, community: u32, action: CommunityAction){
+        send(&self.command_sender, Command::AddCommunityPolicy(community, action)).await;
+    }
+
+    pub async fn set_max_prefixes(&self, port: u32, limit: u32, action: MaxPrefixAction){
+        send(&self.command_sender, Command::SetMaxPrefixes(port, limit, action)).await;
+    }
+
+    pub async fn set_import_policy(&self, port: u32, policy: RoutePolicy){
+        send(&self.command_sender, Command::SetImportPolicy(port, policy)).await;
+    }
+
+    pub async fn set_export_policy(&self, port: u32, policy: RoutePolicy){
+        send(&self.command_sender, Command::SetExportPolicy(port, policy)).await;
+    }
+
+    pub async fn set_session_secret(&self, neighbor: Ipv4Addr, secret: Option<String>){
+        send(&self.command_sender, Command::SetSessionSecret(neighbor, secret)).await;
+    }
+
+    pub async fn get_bgp_sessions(&self) -> Result<Vec<BGPSession>, ()>{
+        query(&self.command_sender, Command::BGPSessions).await
+    }
+
+    pub async fn get_routing_table(&self) -> Result<RoutingTable, ()>{
+        query(&self.command_sender, Command::RoutingTable).await
+    }
+
+    pub async fn get_bgp_routes(&self) -> Result<BGPRoutes, ()>{
+        query(&self.command_sender, Command::BGPRoutes).await
+    }
+
+    pub async fn get_multipath_routes(&self) -> Result<MultipathRoutes, ()>{
+        query(&self.command_sender, Command::MultipathRoutes).await
+    }
+
+    pub async fn replay(&self, messages: Vec<(u32, Message)>) -> Result<(), ()>{
+        let (tx, rx) = oneshot::channel();
+        send(&self.command_sender, Command::Replay(messages, tx)).await;
+        rx.await.map_err(|_| ())
+    }
+
+    pub async fn is_converged(&self) -> Result<bool, ()>{
+        query(&self.command_sender, Command::ConvergenceState).await
+    }
+
+    pub async fn set_multipath(&self, enabled: bool){
+        send(&self.command_sender, Command::SetMultipath(enabled)).await;
+    }
+
+    pub async fn get_equal_cost_ports(&self) -> Result<EqualCostPorts, ()>{
+        query(&self.command_sender, Command::EqualCostPorts).await
+    }
+
+    pub async fn get_lsdb(&self) -> Result<Lsdb, ()>{
+        query(&self.command_sender, Command::Lsdb).await
+    }
+
+    #[cfg(test)]
+    pub async fn panic(&self){
+        send(&self.command_sender, Command::Panic).await;
+    }
+
+    pub async fn set_ecmp(&self, enabled: bool){
+        send(&self.command_sender, Command::SetEcmp(enabled)).await;
     }
 
     pub async fn quit(self){
-        self.command_sender.send(Command::Quit).await.expect("Failed to send quit command");
+        send(&self.command_sender, Command::Quit).await;
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,1128 @@
+use std::{collections::BTreeMap, fmt::{self, Display}, fs, marker::PhantomData, net::Ipv4Addr, str::FromStr, time::Duration};
+
+use serde::{de::{self, value::MapAccessDeserializer, DeserializeOwned, MapAccess, SeqAccess, Visitor}, Deserialize, Deserializer, Serialize, Serializer};
+use serde_yaml::Mapping;
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use super::{error::NetworkError, ip_prefix::IPPrefix, link::{LinkImpairment, LinkRate, QueueDiscipline, RateLimit, DEFAULT_QUEUE_DEPTH}, logger::Source, protocols::{bgp::MaxPrefixAction, policy::{PolicyAction, PolicyRule, RoutePolicy}}, rib::StaticNexthop, timers::TimerConfig};
+
+// values of the config when they are not given
+pub(super) const DEFAULT_COST: u32 = 1;
+pub(super) const DEFAULT_MED: u32 = 1;
+pub(super) const DEFAULT_PING_INTERVAL_MS: u64 = 100;
+// MED derived from the IGP distance to the nexthop of each route
+pub(super) const IGP_MED: &str = "igp";
+// actions of the rules of the route policies
+pub(super) const POLICY_ACTIONS: [&str; 4] = ["accept", "deny", "set_med", "set_pref"];
+
+// A configuration file, whose grammar is printed by `network-simulator schema`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile{
+    pub network: NetworkCfg
+}
+
+impl ConfigFile{
+    pub fn load(path: &str) -> Result<ConfigFile, NetworkError>{
+        let yaml = fs::read_to_string(path).map_err(|e| NetworkError::InvalidConfig(format!("cannot open {}: {}", path, e)))?;
+        yaml.parse().map_err(|e| match e{
+            NetworkError::InvalidConfig(reason) => NetworkError::InvalidConfig(format!("{} is not a valid config: {}", path, reason)),
+            e => e,
+        })
+    }
+
+    // the same seed for the faults of all the links, instead of the ones of the config
+    pub fn set_impairment_seed(&mut self, seed: u64){
+        let links = &mut self.network.links;
+        let impairments = links.internal.iter_mut().map(|link| &mut link.impairment)
+            .chain(links.bgp.provider_customer.iter_mut().map(|link| &mut link.impairment))
+            .chain(links.bgp.peer.iter_mut().map(|link| &mut link.impairment));
+        for impairment in impairments.flatten(){
+            impairment.seed = seed;
+        }
+    }
+
+    // the topology of the config: its devices, internal and BGP links and iBGP sessions,
+    // the settings of the links and of the routers, the actions and the events aren't written
+    pub fn to_yaml_string(&self) -> String{
+        serde_yaml::to_string(self).expect("Failed to serialize the config")
+    }
+}
+
+// the errors name the field at fault and its position, e.g. "network.routers[0]: missing field `id` at line 3 column 7"
+impl FromStr for ConfigFile{
+    type Err = NetworkError;
+
+    fn from_str(yaml: &str) -> Result<Self, Self::Err> {
+        serde_yaml::from_str(yaml).map_err(|e| NetworkError::InvalidConfig(e.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkCfg{
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub routers: Vec<RouterCfg>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub switches: Vec<SwitchCfg>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<HostCfg>,
+    pub links: LinksCfg,
+    #[serde(skip_serializing)]
+    pub config: SettingsCfg,
+    #[serde(skip_serializing)]
+    pub actions: Option<ActionsCfg>,
+    #[serde(skip_serializing)]
+    pub events: Vec<Mapping>, // parsed by the schedule, each one has a time_ms and actions
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouterCfg{
+    pub name: String,
+    pub id: u32,
+    #[serde(rename = "AS")]
+    pub router_as: u32,
+    #[serde(default, deserialize_with = "one_or_many", skip_serializing)]
+    pub advertise: Vec<AdvertiseCfg>,
+    #[serde(default, skip_serializing)]
+    pub secrets: BTreeMap<String, String>, // BGP neighbor -> secret
+    #[serde(default = "enabled", skip_serializing_if = "is_enabled")]
+    pub ospf: bool,
+    #[serde(default, skip_serializing)]
+    pub static_routes: Vec<StaticRouteCfg>,
+}
+
+impl RouterCfg{
+    pub fn new(name: &str, id: u32, router_as: u32) -> RouterCfg{
+        RouterCfg{name: name.to_string(), id, router_as, advertise: vec![], secrets: BTreeMap::new(), ospf: true, static_routes: vec![]}
+    }
+}
+
+fn enabled() -> bool{
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool{
+    *enabled
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdvertiseCfg{
+    pub prefix: IPPrefix,
+    pub unless_present: IPPrefix,
+    pub session: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "StaticRouteConf")]
+pub struct StaticRouteCfg{
+    pub prefix: IPPrefix,
+    pub nexthop: StaticNexthop,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StaticRouteConf{
+    prefix: IPPrefix,
+    nexthop: Option<Ipv4Addr>,
+    port: Option<u32>,
+}
+
+impl TryFrom<StaticRouteConf> for StaticRouteCfg{
+    type Error = String;
+
+    fn try_from(route: StaticRouteConf) -> Result<Self, Self::Error> {
+        let nexthop = match (route.port, route.nexthop){
+            (Some(port), None) => StaticNexthop::Port(port),
+            (None, Some(address)) => StaticNexthop::Address(address),
+            _ => return Err(format!("the static route to {} should have either a nexthop or a port", route.prefix)),
+        };
+        Ok(StaticRouteCfg{prefix: route.prefix, nexthop})
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SwitchCfg{
+    pub name: String,
+    pub id: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostCfg{
+    pub name: String,
+    pub ip: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinksCfg{
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub internal: Vec<LinkCfg>,
+    pub bgp: BgpLinksCfg,
+}
+
+// internal link, either [device1, device2, cost, address] or a mapping
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LinkCfg{
+    #[serde(rename = "devices")]
+    pub ends: (LinkEnd, LinkEnd),
+    pub cost: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<LinkAddress>, // of the first device
+    #[serde(skip_serializing)]
+    pub latency: Duration,
+    #[serde(skip_serializing)]
+    pub impairment: Option<LinkImpairment>,
+    #[serde(skip_serializing)]
+    pub rate_limit: Option<RateLimit>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LinkMapCfg{
+    devices: Option<(LinkEnd, LinkEnd)>,
+    a: Option<String>,
+    a_port: Option<u32>,
+    b: Option<String>,
+    b_port: Option<u32>,
+    #[serde(default = "default_cost")]
+    cost: u32,
+    address: Option<LinkAddress>,
+    #[serde(default, rename = "latency_ms", deserialize_with = "millis")]
+    latency: Duration,
+    #[serde(default, deserialize_with = "impairment")]
+    impairment: Option<LinkImpairment>,
+    #[serde(default, deserialize_with = "rate_limit")]
+    rate_limit: Option<RateLimit>,
+}
+
+fn default_cost() -> u32{
+    DEFAULT_COST
+}
+
+impl LinkCfg{
+    pub fn new(ends: (LinkEnd, LinkEnd), cost: u32) -> LinkCfg{
+        LinkCfg{ends, cost, address: None, latency: Duration::ZERO, impairment: None, rate_limit: None}
+    }
+}
+
+impl SeqOrMap for LinkCfg{
+    type Map = LinkMapCfg;
+    const EXPECTED: &'static str = "a list [device1, device2, cost, address] or a mapping with devices";
+
+    fn from_seq<'de, A: SeqAccess<'de>>(mut seq: A) -> Result<Self, A::Error>{
+        let ends = (next(&mut seq, 0, Self::EXPECTED)?, next(&mut seq, 1, Self::EXPECTED)?);
+        let cost = seq.next_element()?.unwrap_or(DEFAULT_COST);
+        let address = seq.next_element()?;
+        Ok(LinkCfg{address, ..LinkCfg::new(ends, cost)})
+    }
+
+    fn from_map(link: LinkMapCfg) -> Result<Self, String>{
+        let ends = match (link.devices, link.a, link.b){
+            (Some(devices), None, None) if link.a_port.is_none() && link.b_port.is_none() => devices,
+            (None, Some(a), Some(b)) => (LinkEnd{device: a, port: link.a_port}, LinkEnd{device: b, port: link.b_port}),
+            _ => return Err("an internal link should have either devices or a and b with their optional ports".into()),
+        };
+        Ok(LinkCfg{ends, cost: link.cost, address: link.address, latency: link.latency, impairment: link.impairment, rate_limit: link.rate_limit})
+    }
+}
+
+// device at one end of an internal link, e.g. "r1:2" for its port 2, the port is allocated when the link is added if it is not given
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct LinkEnd{
+    pub device: String,
+    pub port: Option<u32>,
+}
+
+impl TryFrom<String> for LinkEnd{
+    type Error = String;
+
+    fn try_from(end: String) -> Result<Self, Self::Error> {
+        match end.split_once(':'){
+            Some((device, port)) => {
+                let port = port.parse().map_err(|_| format!("port {} of {} should be an integer", port, device))?;
+                Ok(LinkEnd{device: device.to_string(), port: Some(port)})
+            },
+            None => Ok(LinkEnd{device: end, port: None}),
+        }
+    }
+}
+
+impl From<&str> for LinkEnd{
+    fn from(device: &str) -> Self {
+        LinkEnd{device: device.to_string(), port: None}
+    }
+}
+
+impl Display for LinkEnd{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port{
+            Some(port) => write!(f, "{}:{}", self.device, port),
+            None => write!(f, "{}", self.device),
+        }
+    }
+}
+
+impl Serialize for LinkEnd{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// address of a router on a link with the length of its subnet, e.g. 10.1.1.1/24
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct LinkAddress{
+    pub ip: Ipv4Addr,
+    pub subnet: IPPrefix,
+}
+
+impl TryFrom<String> for LinkAddress{
+    type Error = String;
+
+    fn try_from(address: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("link address {} is invalid, expected an address and the length of its subnet", address);
+        let subnet: IPPrefix = address.parse().map_err(|_| invalid())?;
+        let ip = address.split('/').next().unwrap().parse().map_err(|_| invalid())?;
+        Ok(LinkAddress{ip, subnet})
+    }
+}
+
+impl Display for LinkAddress{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.ip, self.subnet.prefix_len)
+    }
+}
+
+impl Serialize for LinkAddress{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LinkImpairmentConf{
+    #[serde(default)]
+    loss_prob: f64,
+    #[serde(default)]
+    reorder_prob: f64,
+    #[serde(default)]
+    duplicate_prob: f64,
+    #[serde(default)]
+    seed: u64,
+}
+
+fn impairment<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<LinkImpairment>, D::Error>{
+    let impairment = Option::<LinkImpairmentConf>::deserialize(deserializer)?;
+    Ok(impairment.map(|i| LinkImpairment{loss_prob: i.loss_prob, reorder_prob: i.reorder_prob, duplicate_prob: i.duplicate_prob, seed: i.seed}))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RateLimitConf{
+    messages_per_sec: Option<u64>,
+    bytes_per_sec: Option<u64>,
+    #[serde(default = "default_queue_depth")]
+    queue_depth: usize,
+    #[serde(default)]
+    discipline: QueueDiscipline,
+}
+
+fn default_queue_depth() -> usize{
+    DEFAULT_QUEUE_DEPTH
+}
+
+fn rate_limit<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<RateLimit>, D::Error>{
+    let Some(rate_limit) = Option::<RateLimitConf>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let rate = match (rate_limit.messages_per_sec, rate_limit.bytes_per_sec){
+        (Some(rate), None) => LinkRate::MessagesPerSec(rate),
+        (None, Some(rate)) => LinkRate::BytesPerSec(rate),
+        _ => return Err(de::Error::custom("a rate limit should have either messages_per_sec or bytes_per_sec")),
+    };
+    Ok(Some(RateLimit{rate, queue_depth: rate_limit.queue_depth, discipline: rate_limit.discipline}))
+}
+
+fn millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error>{
+    Ok(Duration::from_millis(Option::<u64>::deserialize(deserializer)?.unwrap_or_default()))
+}
+
+// MED sent on a BGP link, or derived from the IGP distance to the nexthop of each route
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BgpMed{
+    Value(u32),
+    Igp
+}
+
+impl BgpMed{
+    // MED of the link and whether it is derived from the IGP
+    pub fn med(&self) -> (u32, bool){
+        match self{
+            BgpMed::Value(med) => (*med, false),
+            BgpMed::Igp => (0, true),
+        }
+    }
+}
+
+impl Default for BgpMed{
+    fn default() -> Self {
+        BgpMed::Value(DEFAULT_MED)
+    }
+}
+
+impl Display for BgpMed{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            BgpMed::Value(med) => write!(f, "{}", med),
+            BgpMed::Igp => write!(f, "{}", IGP_MED),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BgpMed{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MedVisitor;
+
+        impl Visitor<'_> for MedVisitor{
+            type Value = BgpMed;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an integer or \"{}\"", IGP_MED)
+            }
+
+            fn visit_u64<E: de::Error>(self, med: u64) -> Result<BgpMed, E> {
+                u32::try_from(med).map(BgpMed::Value).map_err(|_| E::invalid_value(de::Unexpected::Unsigned(med), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, med: &str) -> Result<BgpMed, E> {
+                match med{
+                    IGP_MED => Ok(BgpMed::Igp),
+                    med => Err(E::invalid_value(de::Unexpected::Str(med), &self)),
+                }
+            }
+        }
+        deserializer.deserialize_any(MedVisitor)
+    }
+}
+
+impl Serialize for BgpMed{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self{
+            BgpMed::Value(med) => serializer.serialize_u32(*med),
+            BgpMed::Igp => serializer.serialize_str(IGP_MED),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BgpLinksCfg{
+    #[serde(rename = "provider-customer", skip_serializing_if = "Vec::is_empty")]
+    pub provider_customer: Vec<BgpLinkCfg>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub peer: Vec<PeerLinkCfg>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ibgp: Vec<IbgpSessionCfg>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ibgp_full_mesh: Vec<u32>, // ASes whose routers all have sessions with each other
+}
+
+// values of the provider and of the customer of a link
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RolesCfg<T>{
+    pub provider: T,
+    pub customer: T,
+}
+
+impl<T> RolesCfg<T>{
+    pub fn pair(self) -> (T, T){
+        (self.provider, self.customer)
+    }
+}
+
+impl<T: Default + PartialEq> RolesCfg<T>{
+    fn is_default(&self) -> bool{
+        self.provider == T::default() && self.customer == T::default()
+    }
+}
+
+// provider-customer link
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BgpLinkCfg{
+    pub provider: String,
+    pub customer: String,
+    #[serde(default)]
+    pub med: BgpMed,
+    #[serde(default, skip_serializing_if = "RolesCfg::is_default")]
+    pub local_pref: RolesCfg<Option<u32>>,
+    #[serde(default, skip_serializing)]
+    pub prepend: RolesCfg<u32>,
+    #[serde(default, skip_serializing)]
+    pub import: RolesCfg<Option<PolicyCfg>>,
+    #[serde(default, skip_serializing)]
+    pub export: RolesCfg<Option<PolicyCfg>>,
+    #[serde(default, skip_serializing)]
+    pub max_prefixes: RolesCfg<Option<MaxPrefixesCfg>>,
+    #[serde(default, rename = "latency_ms", deserialize_with = "millis", skip_serializing)]
+    pub latency: Duration,
+    #[serde(default, deserialize_with = "impairment", skip_serializing)]
+    pub impairment: Option<LinkImpairment>,
+    #[serde(default, deserialize_with = "rate_limit", skip_serializing)]
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl BgpLinkCfg{
+    pub fn new(provider: &str, customer: &str, med: BgpMed) -> BgpLinkCfg{
+        BgpLinkCfg{
+            provider: provider.to_string(),
+            customer: customer.to_string(),
+            med,
+            local_pref: RolesCfg::default(),
+            prepend: RolesCfg::default(),
+            import: RolesCfg::default(),
+            export: RolesCfg::default(),
+            max_prefixes: RolesCfg::default(),
+            latency: Duration::ZERO,
+            impairment: None,
+            rate_limit: None,
+        }
+    }
+}
+
+// peer link, either [router1, router2, med, pref1, pref2] or a mapping, the values of the pairs are those of router1 then router2
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PeerLinkCfg{
+    pub peers: (String, String),
+    pub med: BgpMed,
+    #[serde(skip_serializing_if = "no_prefs")]
+    pub local_pref: (Option<u32>, Option<u32>),
+    #[serde(skip_serializing)]
+    pub prepend: (u32, u32),
+    #[serde(skip_serializing)]
+    pub import: (Option<PolicyCfg>, Option<PolicyCfg>),
+    #[serde(skip_serializing)]
+    pub export: (Option<PolicyCfg>, Option<PolicyCfg>),
+    #[serde(skip_serializing)]
+    pub max_prefixes: (Option<MaxPrefixesCfg>, Option<MaxPrefixesCfg>),
+    #[serde(skip_serializing)]
+    pub latency: Duration,
+    #[serde(skip_serializing)]
+    pub impairment: Option<LinkImpairment>,
+    #[serde(skip_serializing)]
+    pub rate_limit: Option<RateLimit>,
+}
+
+fn no_prefs(prefs: &(Option<u32>, Option<u32>)) -> bool{
+    *prefs == (None, None)
+}
+
+impl PeerLinkCfg{
+    pub fn new(router1: &str, router2: &str, med: BgpMed) -> PeerLinkCfg{
+        PeerLinkCfg{
+            peers: (router1.to_string(), router2.to_string()),
+            med,
+            local_pref: (None, None),
+            prepend: (0, 0),
+            import: (None, None),
+            export: (None, None),
+            max_prefixes: (None, None),
+            latency: Duration::ZERO,
+            impairment: None,
+            rate_limit: None
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PeerLinkMapCfg{
+    peers: (String, String),
+    #[serde(default)]
+    med: BgpMed,
+    #[serde(default, deserialize_with = "pair")]
+    local_pref: (Option<u32>, Option<u32>),
+    #[serde(default, deserialize_with = "pair")]
+    prepend: (u32, u32),
+    #[serde(default, deserialize_with = "pair")]
+    import: (Option<PolicyCfg>, Option<PolicyCfg>),
+    #[serde(default, deserialize_with = "pair")]
+    export: (Option<PolicyCfg>, Option<PolicyCfg>),
+    #[serde(default, deserialize_with = "pair")]
+    max_prefixes: (Option<MaxPrefixesCfg>, Option<MaxPrefixesCfg>),
+    #[serde(default, rename = "latency_ms", deserialize_with = "millis")]
+    latency: Duration,
+    #[serde(default, deserialize_with = "impairment")]
+    impairment: Option<LinkImpairment>,
+    #[serde(default, deserialize_with = "rate_limit")]
+    rate_limit: Option<RateLimit>,
+}
+
+impl SeqOrMap for PeerLinkCfg{
+    type Map = PeerLinkMapCfg;
+    const EXPECTED: &'static str = "a list [router1, router2, med, pref1, pref2] or a mapping with peers";
+
+    fn from_seq<'de, A: SeqAccess<'de>>(mut seq: A) -> Result<Self, A::Error>{
+        let (router1, router2): (String, String) = (next(&mut seq, 0, Self::EXPECTED)?, next(&mut seq, 1, Self::EXPECTED)?);
+        let med = seq.next_element()?.unwrap_or_default();
+        let local_pref = (seq.next_element()?.flatten(), seq.next_element()?.flatten());
+        Ok(PeerLinkCfg{local_pref, ..PeerLinkCfg::new(&router1, &router2, med)})
+    }
+
+    fn from_map(link: PeerLinkMapCfg) -> Result<Self, String>{
+        Ok(PeerLinkCfg{
+            peers: link.peers,
+            med: link.med,
+            local_pref: link.local_pref,
+            prepend: link.prepend,
+            import: link.import,
+            export: link.export,
+            max_prefixes: link.max_prefixes,
+            latency: link.latency,
+            impairment: link.impairment,
+            rate_limit: link.rate_limit
+        })
+    }
+}
+
+// values of the two routers of a peer link, the missing ones have their default
+fn pair<'de, D: Deserializer<'de>, T: Deserialize<'de> + Default>(deserializer: D) -> Result<(T, T), D::Error>{
+    let mut values = Option::<Vec<T>>::deserialize(deserializer)?.unwrap_or_default().into_iter();
+    if values.len() > 2{
+        return Err(de::Error::invalid_length(values.len(), &"a value for each router of the link"));
+    }
+    Ok((values.next().unwrap_or_default(), values.next().unwrap_or_default()))
+}
+
+// iBGP session, either [router1, router2] in a full mesh or a route reflector and one of its clients
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum IbgpSessionCfg{
+    Mesh(String, String),
+    RouteReflector{rr: String, client: String}
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IbgpClientCfg{
+    rr: String,
+    client: String,
+}
+
+impl SeqOrMap for IbgpSessionCfg{
+    type Map = IbgpClientCfg;
+    const EXPECTED: &'static str = "a list [router1, router2] or a mapping with rr and client";
+
+    fn from_seq<'de, A: SeqAccess<'de>>(mut seq: A) -> Result<Self, A::Error>{
+        Ok(IbgpSessionCfg::Mesh(next(&mut seq, 0, Self::EXPECTED)?, next(&mut seq, 1, Self::EXPECTED)?))
+    }
+
+    fn from_map(session: IbgpClientCfg) -> Result<Self, String>{
+        Ok(IbgpSessionCfg::RouteReflector{rr: session.rr, client: session.client})
+    }
+}
+
+// rules applied by a router to the routes of a BGP session
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "Vec<PolicyRuleConf>")]
+pub struct PolicyCfg(pub RoutePolicy);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PolicyActionName{
+    Accept,
+    Deny,
+    SetMed,
+    SetPref
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyRuleConf{
+    prefix: IPPrefix,
+    le: Option<u32>,
+    action: PolicyActionName,
+    value: Option<u32>, // of the set_med and set_pref actions
+}
+
+impl TryFrom<Vec<PolicyRuleConf>> for PolicyCfg{
+    type Error = String;
+
+    fn try_from(rules: Vec<PolicyRuleConf>) -> Result<Self, Self::Error> {
+        let mut policy = RoutePolicy::default();
+        for rule in rules{
+            let value = |action| rule.value.ok_or(format!("the {} action of the rule for {} needs a value", action, rule.prefix));
+            let action = match rule.action{
+                PolicyActionName::Accept => PolicyAction::Accept,
+                PolicyActionName::Deny => PolicyAction::Deny,
+                PolicyActionName::SetMed => PolicyAction::SetMed(value("set_med")?),
+                PolicyActionName::SetPref => PolicyAction::SetPref(value("set_pref")?),
+            };
+            policy.rules.push(PolicyRule{prefix: rule.prefix, le: rule.le, action});
+        }
+        Ok(PolicyCfg(policy))
+    }
+}
+
+// most prefixes a router accepts on a BGP session and what it does above
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaxPrefixesCfg{
+    pub limit: u32,
+    #[serde(default = "drop_prefixes")]
+    pub action: MaxPrefixAction,
+}
+
+fn drop_prefixes() -> MaxPrefixAction{
+    MaxPrefixAction::Drop
+}
+
+// the "config" section of the file
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SettingsCfg{
+    pub log: Option<LogCfg>,
+    pub timers: TimersCfg,
+}
+
+// sources of the messages logged, nothing is logged without them
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogCfg(pub Vec<Source>);
+
+impl<'de> Deserialize<'de> for LogCfg{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut sources = vec![];
+        for name in Vec::<String>::deserialize(deserializer)?{
+            let Some(source) = Source::iter().find(|s| s.to_string() == name) else {
+                let sources: Vec<String> = Source::iter().map(|s| s.to_string()).collect();
+                return Err(de::Error::custom(format!("unknown log source {}, supported sources are [{}]", name, sources.join(", "))));
+            };
+            sources.push(source);
+        }
+        Ok(LogCfg(sources))
+    }
+}
+
+// timers of all the devices, the missing ones keep their default period
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimersCfg{
+    pub hello_interval_ms: Option<u64>,
+    pub bpdu_interval_ms: Option<u64>,
+    pub arp_refresh_interval_ms: Option<u64>,
+    pub keepalive_interval_ms: Option<u64>,
+    pub lsp_refresh_interval_ms: Option<u64>,
+    pub dead_interval_ms: Option<u64>, // silence of an OSPF neighbor before it is considered dead
+}
+
+impl TimersCfg{
+    pub fn timer_config(&self) -> TimerConfig{
+        let default = TimerConfig::default();
+        let interval = |ms: Option<u64>, default| ms.map(Duration::from_millis).unwrap_or(default);
+        TimerConfig{
+            hello_interval: interval(self.hello_interval_ms, default.hello_interval),
+            bpdu_interval: interval(self.bpdu_interval_ms, default.bpdu_interval),
+            arp_refresh_interval: interval(self.arp_refresh_interval_ms, default.arp_refresh_interval),
+            keepalive_interval: interval(self.keepalive_interval_ms, default.keepalive_interval),
+            lsp_refresh_interval: interval(self.lsp_refresh_interval_ms, default.lsp_refresh_interval),
+        }
+    }
+}
+
+// the former actions, run once the IGP and then BGP converged
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ActionsCfg{
+    pub announce_prefix: Vec<AnnounceCfg>,
+    pub withdraw_prefix: Vec<String>,
+    pub clear_routes: Vec<String>,
+    pub clear_bgp: Vec<String>,
+    pub ping: Vec<PingCfg>,
+    pub print_routing_tables: bool,
+    pub print_port_states: bool,
+    pub print_spanning_tree_graph: bool,
+    pub print_bgp_tables: bool,
+    pub print_reachability: bool,
+    pub print_forwarding_graph: Option<ForwardingGraphCfg>,
+    pub dot_graph_file: Option<String>,
+    pub state_file: Option<String>,
+    pub assert_routing_table: Vec<RoutingTableAssertCfg>,
+    pub assert_bgp_best: Vec<BgpBestAssertCfg>,
+    pub assert_ping: Vec<PingAssertCfg>,
+}
+
+// DOT graph of the links the routers use towards an address
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardingGraphCfg{
+    pub dest: Ipv4Addr,
+}
+
+// port of a device shut down or enabled again by an event
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortCfg{
+    pub device: String,
+    pub port: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkCostCfg{
+    pub device: String,
+    pub port: u32,
+    pub cost: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PingCfg{
+    pub from: String,
+    pub to: Ipv4Addr,
+    pub count: Option<u32>, // pings of a series, a single ping without it
+    pub interval_ms: Option<u64>,
+}
+
+// routes expected in the routing table of a router, the other routes of the table are not checked
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingTableAssertCfg{
+    pub router: String,
+    pub entries: BTreeMap<IPPrefix, RouteAssertCfg>,
+}
+
+// port and distance of a route, only the given ones are checked
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteAssertCfg{
+    pub port: Option<u32>,
+    pub distance: Option<u32>,
+}
+
+impl RouteAssertCfg{
+    pub fn matches(&self, port: u32, distance: u32) -> bool{
+        self.port.is_none_or(|p| p == port) && self.distance.is_none_or(|d| d == distance)
+    }
+}
+
+impl Display for RouteAssertCfg{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<String> = [("port", self.port), ("distance", self.distance)].into_iter()
+            .filter_map(|(name, value)| value.map(|value| format!("{}={}", name, value)))
+            .collect();
+        match fields.is_empty(){
+            true => write!(f, "a route"),
+            false => write!(f, "{}", fields.join(", ")),
+        }
+    }
+}
+
+// AS path of the best BGP route of a router towards a prefix
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BgpBestAssertCfg{
+    pub router: String,
+    pub prefix: IPPrefix,
+    pub as_path: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PingAssertCfg{
+    pub from: String,
+    pub to: Ipv4Addr,
+    #[serde(default)]
+    pub expect: PingOutcome,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, EnumIter)]
+#[serde(rename_all = "lowercase")]
+pub enum PingOutcome{
+    #[default]
+    Success,
+    Failure
+}
+
+impl Display for PingOutcome{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            PingOutcome::Success => write!(f, "success"),
+            PingOutcome::Failure => write!(f, "failure"),
+        }
+    }
+}
+
+// prefix announced by all the routers of an AS, by a router, with the pref of its own route or instead of the prefix of its AS
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnnounceCfg{
+    As(u32),
+    Router(String),
+    WithPref{router: String, pref: u32},
+    Prefix{router: String, prefix: IPPrefix}
+}
+
+impl Display for AnnounceCfg{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            AnnounceCfg::As(router_as) => write!(f, "{}", router_as),
+            AnnounceCfg::Router(router) => write!(f, "{}", router),
+            AnnounceCfg::WithPref{router, pref} => write!(f, "{} with pref {}", router, pref),
+            AnnounceCfg::Prefix{router, prefix} => write!(f, "{} prefix {}", router, prefix),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnnounceConf{
+    router: String,
+    pref: Option<u32>,
+    prefix: Option<IPPrefix>,
+}
+
+impl<'de> Deserialize<'de> for AnnounceCfg{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AnnounceVisitor;
+
+        impl<'de> Visitor<'de> for AnnounceVisitor{
+            type Value = AnnounceCfg;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an AS, a router name or a mapping with router and pref or prefix")
+            }
+
+            fn visit_u64<E: de::Error>(self, router_as: u64) -> Result<AnnounceCfg, E> {
+                u32::try_from(router_as).map(AnnounceCfg::As).map_err(|_| E::invalid_value(de::Unexpected::Unsigned(router_as), &self))
+            }
+
+            fn visit_str<E: de::Error>(self, router: &str) -> Result<AnnounceCfg, E> {
+                Ok(AnnounceCfg::Router(router.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<AnnounceCfg, A::Error> {
+                let announce = AnnounceConf::deserialize(MapAccessDeserializer::new(map))?;
+                match (announce.prefix, announce.pref){
+                    (Some(prefix), _) => Ok(AnnounceCfg::Prefix{router: announce.router, prefix}),
+                    (None, Some(pref)) => Ok(AnnounceCfg::WithPref{router: announce.router, pref}),
+                    (None, None) => Err(de::Error::missing_field("pref")),
+                }
+            }
+        }
+        deserializer.deserialize_any(AnnounceVisitor)
+    }
+}
+
+// a value given either as the list of its first fields in order or as a mapping
+trait SeqOrMap: Sized{
+    type Map: DeserializeOwned;
+    const EXPECTED: &'static str;
+
+    fn from_seq<'de, A: SeqAccess<'de>>(seq: A) -> Result<Self, A::Error>;
+    fn from_map(map: Self::Map) -> Result<Self, String>;
+}
+
+struct SeqOrMapVisitor<T>(PhantomData<T>);
+
+impl<'de, T: SeqOrMap> Visitor<'de> for SeqOrMapVisitor<T>{
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(T::EXPECTED)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<T, A::Error> {
+        T::from_seq(seq)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<T, A::Error> {
+        T::from_map(T::Map::deserialize(MapAccessDeserializer::new(map))?).map_err(de::Error::custom)
+    }
+}
+
+macro_rules! deserialize_seq_or_map {
+    ($($t:ty),*) => {$(
+        impl<'de> Deserialize<'de> for $t{
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserializer.deserialize_any(SeqOrMapVisitor(PhantomData))
+            }
+        }
+    )*};
+}
+
+deserialize_seq_or_map!(LinkCfg, PeerLinkCfg, IbgpSessionCfg);
+
+// element of a list that can't be omitted
+fn next<'de, A: SeqAccess<'de>, T: Deserialize<'de>>(seq: &mut A, index: usize, expected: &'static str) -> Result<T, A::Error>{
+    seq.next_element()?.ok_or_else(|| de::Error::invalid_length(index, &expected))
+}
+
+// either a single value or a list of them
+fn one_or_many<'de, D: Deserializer<'de>, T: DeserializeOwned>(deserializer: D) -> Result<Vec<T>, D::Error>{
+    struct OneOrMany<T>(PhantomData<T>);
+
+    impl<'de, T: DeserializeOwned> Visitor<'de> for OneOrMany<T>{
+        type Value = Vec<T>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a mapping or a list of them")
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Vec<T>, E> {
+            Ok(vec![])
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<T>, A::Error> {
+            let mut values = vec![];
+            while let Some(value) = seq.next_element()?{
+                values.push(value);
+            }
+            Ok(values)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Vec<T>, A::Error> {
+            Ok(vec![T::deserialize(MapAccessDeserializer::new(map))?])
+        }
+    }
+    deserializer.deserialize_any(OneOrMany(PhantomData))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(yaml: &str) -> String{
+        match yaml.parse::<ConfigFile>(){
+            Err(NetworkError::InvalidConfig(reason)) => reason,
+            result => panic!("{} should not parse, got {:?}", yaml, result),
+        }
+    }
+
+    #[test]
+    fn test_parse_examples() {
+        for entry in fs::read_dir("examples").unwrap(){
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "yaml"){
+                if let Err(e) = ConfigFile::load(path.to_str().unwrap()){
+                    panic!("{} doesn't parse: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config: ConfigFile = "
+network:
+  routers:
+    - {name: r1, id: 1, AS: 1, static_routes: [{prefix: 0.0.0.0/0, port: 1}]}
+    - name: r2
+      id: 2
+      AS: 2
+      advertise: {prefix: 10.9.0.0/24, unless_present: 10.0.1.0/24, session: r1}
+  links:
+    internal:
+      - [r1, r2, 3, 10.1.1.1/24]
+      - {devices: [r2, r1], latency_ms: 20, rate_limit: {messages_per_sec: 5, discipline: priority}}
+      - [r1:4, r2, 2]
+      - {a: r2, b: r1, b_port: 3}
+    bgp:
+      provider-customer:
+        - {provider: r1, customer: r2, med: igp, prepend: {customer: 2}, max_prefixes: {provider: {limit: 3, action: close}}}
+      peer:
+        - [r1, r2, 5, null, 150]
+        - {peers: [r1, r2], export: [[{prefix: 10.0.0.0/16, le: 24, action: set_med, value: 4}]]}
+      ibgp:
+        - [r1, r2]
+        - {rr: r1, client: r2}
+      ibgp_full_mesh: [1, 2]
+  config:
+    log: [OSPF, BGP]
+    timers: {hello_interval_ms: 20, lsp_refresh_interval_ms: 1000, dead_interval_ms: 900}
+  actions:
+    announce_prefix: [r1, 2, {router: r2, pref: 50}, {router: r1, prefix: 192.0.2.0/24}]
+    print_bgp_tables: true
+    assert_routing_table: [{router: r1, entries: {10.0.1.2/32: {port: 1}}}]
+    assert_bgp_best: [{router: r2, prefix: 10.0.1.0/24, as_path: [1]}]
+    assert_ping: [{from: r2, to: 10.0.1.1}, {from: r1, to: 10.0.9.9, expect: failure}]
+".parse().unwrap();
+        let network = &config.network;
+        assert_eq!(network.routers[0].static_routes[0].nexthop, StaticNexthop::Port(1));
+        assert!(network.routers[0].ospf);
+        assert_eq!(network.routers[1].advertise.len(), 1);
+
+        let internal = &network.links.internal;
+        assert_eq!((internal[0].cost, internal[0].address.unwrap().ip), (3, "10.1.1.1".parse().unwrap()));
+        assert_eq!((internal[1].cost, internal[1].latency), (DEFAULT_COST, Duration::from_millis(20)));
+        assert_eq!(internal[1].rate_limit, Some(RateLimit{rate: LinkRate::MessagesPerSec(5), queue_depth: DEFAULT_QUEUE_DEPTH, discipline: QueueDiscipline::Priority}));
+        assert_eq!(internal[2].ends, (LinkEnd{device: "r1".into(), port: Some(4)}, LinkEnd{device: "r2".into(), port: None}));
+        assert_eq!(internal[3].ends, (LinkEnd{device: "r2".into(), port: None}, LinkEnd{device: "r1".into(), port: Some(3)}));
+
+        let bgp = &network.links.bgp;
+        assert_eq!(bgp.provider_customer[0].med.med(), (0, true));
+        assert_eq!(bgp.provider_customer[0].prepend.clone().pair(), (0, 2));
+        assert_eq!(bgp.provider_customer[0].max_prefixes.provider.unwrap().action, MaxPrefixAction::Close);
+        assert_eq!((bgp.peer[0].med, bgp.peer[0].local_pref), (BgpMed::Value(5), (None, Some(150))));
+        assert_eq!(bgp.peer[1].med, BgpMed::Value(DEFAULT_MED));
+        assert_eq!(bgp.peer[1].export.0.as_ref().unwrap().0.rules[0].action, PolicyAction::SetMed(4));
+        assert!(bgp.peer[1].export.1.is_none());
+        assert_eq!(bgp.ibgp[1], IbgpSessionCfg::RouteReflector{rr: "r1".into(), client: "r2".into()});
+        assert_eq!(bgp.ibgp_full_mesh, vec![1, 2]);
+
+        assert_eq!(network.config.log, Some(LogCfg(vec![Source::OSPF, Source::BGP])));
+        assert_eq!(network.config.timers.timer_config().hello_interval, Duration::from_millis(20));
+        assert_eq!(network.config.timers.timer_config().lsp_refresh_interval, Duration::from_millis(1000));
+        assert_eq!(network.config.timers.dead_interval_ms, Some(900));
+        let actions = network.actions.as_ref().unwrap();
+        let announces: Vec<String> = actions.announce_prefix.iter().map(|a| a.to_string()).collect();
+        assert_eq!(announces, vec!["r1", "2", "r2 with pref 50", "r1 prefix 192.0.2.0/24"]);
+        assert!(actions.print_bgp_tables && !actions.print_routing_tables);
+        let entry = actions.assert_routing_table[0].entries[&"10.0.1.2/32".parse().unwrap()];
+        assert_eq!((entry.to_string(), entry.matches(1, 5), entry.matches(2, 1)), ("port=1".to_string(), true, false));
+        assert_eq!(actions.assert_bgp_best[0].as_path, vec![1]);
+        assert_eq!((actions.assert_ping[0].expect, actions.assert_ping[1].expect), (PingOutcome::Success, PingOutcome::Failure));
+
+        let mut seeded = config.clone();
+        seeded.set_impairment_seed(9);
+        assert_eq!(seeded.network.links, config.network.links);
+        let mut seeded: ConfigFile = "network: {links: {internal: [{devices: [r1, r2], impairment: {loss_prob: 0.5, seed: 3}}, [r2, r3]]}}".parse().unwrap();
+        seeded.set_impairment_seed(9);
+        assert_eq!((seeded.network.links.internal[0].impairment.unwrap().seed, seeded.network.links.internal[1].impairment), (9, None));
+
+        // the sections can be empty
+        assert_eq!("network:\n  routers:\n  links:\n".parse::<ConfigFile>(), Ok(ConfigFile::default()));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert_eq!(error("network:\n  routers:\n    - {name: r1, AS: 1}\n"), "network.routers[0]: missing field `id` at line 3 column 7");
+        assert!(error("network: {routers: [{name: r1, id: one, AS: 1}]}").starts_with("network.routers[0].id: invalid type: string \"one\", expected u32"));
+        assert!(error("network: {switches: [{name: s1, id: 1, AS: 1}]}").starts_with("network.switches[0]: unknown field `AS`"));
+        assert!(error("network: {links: {internal: [[r1]]}}").starts_with("network.links.internal[0]: invalid length 1, expected a list [device1, device2, cost, address]"));
+        assert!(error("network: {links: {internal: [[r1:x, r2]]}}").contains("port x of r1 should be an integer"));
+        assert!(error("network: {links: {internal: [{devices: [r1, r2], a_port: 1}]}}").contains("an internal link should have either devices or a and b"));
+        assert!(error("network: {links: {bgp: {peer: [[r1, r2, high]]}}}").contains("invalid value: string \"high\", expected an integer or \"igp\""));
+        assert!(error("network: {links: {bgp: {peer: [{peers: [r1, r2], prepend: [1, 2, 3]}]}}}").contains("invalid length 3, expected a value for each router of the link"));
+        assert!(error("network: {links: {bgp: {peer: [{peers: [r1, r2], import: [[{prefix: 10.0.1.0/24, action: drop}]]}]}}}")
+            .contains("unknown variant `drop`, expected one of `accept`, `deny`, `set_med`, `set_pref`"));
+        assert!(error("network: {links: {bgp: {peer: [{peers: [r1, r2], import: [[{prefix: 10.0.1.0/24, action: set_pref}]]}]}}}")
+            .contains("the set_pref action of the rule for 10.0.1.0/24 needs a value"));
+        assert!(error("network: {links: {internal: [{devices: [r1, r2], rate_limit: {messages_per_sec: 1, bytes_per_sec: 2}}]}}")
+            .contains("a rate limit should have either messages_per_sec or bytes_per_sec"));
+        assert!(error("network: {routers: [{name: r1, id: 1, AS: 1, static_routes: [{prefix: 0.0.0.0/0}]}]}")
+            .contains("the static route to 0.0.0.0/0 should have either a nexthop or a port"));
+        assert!(error("network: {actions: {announce_prefix: [{router: r1}]}}").starts_with("network.actions.announce_prefix[0]: missing field `pref`"));
+        assert!(error("network: {config: {log: [OSPF, TCP]}}").contains("unknown log source TCP, supported sources are [OSPF, SPT"));
+        assert!(error("network: {actions: {assert_ping: [{from: r1, to: 10.0.1.1, expect: timeout}]}}").contains("unknown variant `timeout`, expected `success` or `failure`"));
+        assert!(matches!(ConfigFile::load("examples/missing.yaml"), Err(NetworkError::InvalidConfig(_))));
+    }
+}
@@ -0,0 +1,111 @@
+use std::{collections::BTreeMap, io, net::SocketAddr, sync::Arc};
+
+use serde::Serialize;
+use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader}, net::{TcpListener, TcpStream}, sync::RwLock};
+
+use super::{config::AnnounceCfg, error::NetworkError, ip_prefix::IPPrefix, repl::{self, ReplCommand, COMMANDS}, rib::RIBEntry, scenario::{self, CONVERGENCE_TIMEOUT}, state::BGPTableEntry, Network};
+
+// answer to a request of a client, sent as a JSON document on one line
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlResponse{
+    RoutingTable(BTreeMap<IPPrefix, RIBEntry>),
+    #[serde(rename = "bgp_table")]
+    BGPTable(BTreeMap<IPPrefix, BGPTableEntry>),
+    Ping{rtt_us: u128},
+    Converged{elapsed_ms: u128},
+    Dot(String),
+    Commands(Vec<String>),
+    Done(String),
+    Error(String),
+}
+
+// the queries only lock the network for reading, so that the clients querying different routers are answered concurrently
+async fn answer(network: &RwLock<Network>, command: ReplCommand) -> Result<ControlResponse, NetworkError>{
+    let response = match command{
+        ReplCommand::Ping(from, to) => {
+            let rtt = network.read().await.ping(&from, to).await?;
+            ControlResponse::Ping{rtt_us: rtt.as_micros()}
+        },
+        ReplCommand::ShowRoute(router) => {
+            let routing_table = network.read().await.get_routing_table(&router).await?;
+            ControlResponse::RoutingTable(routing_table.into_iter().collect())
+        },
+        ReplCommand::ShowBGP(router) => ControlResponse::BGPTable(network.read().await.get_bgp_table(&router).await?),
+        ReplCommand::Announce(router) => {
+            scenario::announce(&*network.read().await, &AnnounceCfg::Router(router.clone())).await?;
+            ControlResponse::Done(format!("Prefix of {} announced", router))
+        },
+        ReplCommand::Withdraw(router) => {
+            network.read().await.withdraw_prefix(&router).await?;
+            ControlResponse::Done(format!("Prefix of {} withdrawn", router))
+        },
+        ReplCommand::FailLink(device, port) => {
+            let (other, other_port) = repl::fail_link(&mut *network.write().await, &device, port).await?;
+            ControlResponse::Done(format!("Link from {}:{} to {}:{} removed", device, port, other, other_port))
+        },
+        ReplCommand::AddLink(device1, device2, cost) => {
+            let (port1, port2) = repl::add_link(&mut *network.write().await, &device1, &device2, cost).await?;
+            ControlResponse::Done(format!("Link from {}:{} to {}:{} added with cost {}", device1, port1, device2, port2, cost))
+        },
+        ReplCommand::Wait(Some(duration)) => {
+            tokio::time::sleep(duration).await;
+            ControlResponse::Done(format!("Waited {}ms", duration.as_millis()))
+        },
+        ReplCommand::Wait(None) => {
+            let elapsed = network.read().await.wait_for_convergence(CONVERGENCE_TIMEOUT).await?;
+            ControlResponse::Converged{elapsed_ms: elapsed.as_millis()}
+        },
+        ReplCommand::Dot => ControlResponse::Dot(network.read().await.dot_representation().await?),
+        ReplCommand::Log(sources, devices) => {
+            network.read().await.set_log_filters(sources.clone(), devices.clone()).await?;
+            ControlResponse::Done(repl::log_filters_description(&sources, &devices))
+        },
+        ReplCommand::Help => ControlResponse::Commands(COMMANDS.iter().map(|(usage, _)| usage.to_string()).collect()),
+        ReplCommand::Quit => ControlResponse::Done("Bye".into()),
+    };
+    Ok(response)
+}
+
+// Answers the requests of a client, one command of the REPL per line, until quit or the end of the connection
+async fn serve_client(network: Arc<RwLock<Network>>, stream: TcpStream) -> io::Result<()>{
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await?{
+        if line.trim().is_empty(){
+            continue;
+        }
+        let command = line.parse();
+        let quit = command == Ok(ReplCommand::Quit);
+        let response = match command{
+            Ok(command) => answer(&network, command).await.unwrap_or_else(|error| ControlResponse::Error(error.to_string())),
+            Err(error) => ControlResponse::Error(error.to_string()),
+        };
+        let mut json = serde_json::to_string(&response).expect("Failed to serialize control response");
+        json.push('\n');
+        writer.write_all(json.as_bytes()).await?;
+        if quit{
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+impl Network{
+    // Starts a control server on addr, each client is served by its own task
+    // Returns the address the server listens on, for when the system chooses its port
+    pub async fn serve_control(network: Arc<RwLock<Network>>, addr: &str) -> io::Result<SocketAddr>{
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await{
+                let network = network.clone();
+                tokio::spawn(async move {
+                    // a client closing its connection only ends its own task
+                    let _ = serve_client(network, stream).await;
+                });
+            }
+        });
+        Ok(local_addr)
+    }
+}
@@ -0,0 +1,87 @@
+use std::{fmt::Display, net::Ipv4Addr, time::Duration};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum NetworkError{
+    UnknownDevice(String),
+    PortInUse{device: String, port: u32},
+    UnknownLink{device1: String, port1: u32, device2: String, port2: u32},
+    UnknownSession{router: String, neighbor: String},
+    UnknownNexthop{router: String, nexthop: String},
+    NotARouter(String),
+    SwitchInBGP(String),
+    DeviceDead(String),
+    Ping(PingError),
+    ExpectationFailed(String),
+    AssertionsFailed(Vec<String>), // differences between the expected and the actual state, one per failed assertion
+    InvalidConfig(String),
+    AddressOutOfRange{device: String, id: u32, router_as: u32},
+    DuplicateAddress{device: String, ip: Ipv4Addr, other: String},
+    SubnetTooSmall(String),
+    AddressOutsideSubnet{address: Ipv4Addr, subnet: String},
+    HostAlreadyLinked(String),
+    NotCrashed(String),
+    UnknownFlow(u64),
+    NoLinkOnPort{device: String, port: u32},
+    Capture(String),
+    Log(String),
+    Io(String),
+    NotConverged{timeout: Duration, routers: Vec<String>},
+    InvalidCommand(String),
+}
+
+impl Display for NetworkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            NetworkError::UnknownDevice(device) => write!(f, "Unknown device {}", device),
+            NetworkError::PortInUse{device, port} => write!(f, "Port {} is already used for device {}", port, device),
+            NetworkError::UnknownLink{device1, port1, device2, port2} => write!(f, "No link between {}:{} and {}:{}", device1, port1, device2, port2),
+            NetworkError::UnknownSession{router, neighbor} => write!(f, "No BGP session between {} and {}", router, neighbor),
+            NetworkError::UnknownNexthop{router, nexthop} => write!(f, "No router linked to {} at {}", router, nexthop),
+            NetworkError::NotARouter(device) => write!(f, "Device {} is not a router", device),
+            NetworkError::SwitchInBGP(device) => write!(f, "Device '{}' is a switch and cannot participate in BGP links", device),
+            NetworkError::DeviceDead(device) => write!(f, "Device {} stopped answering", device),
+            NetworkError::Ping(error) => write!(f, "{}", error),
+            NetworkError::ExpectationFailed(reason) => write!(f, "Expectation failed: {}", reason),
+            NetworkError::AssertionsFailed(failures) => write!(f, "{} assertions failed:\n{}", failures.len(), failures.join("\n")),
+            NetworkError::InvalidConfig(reason) => write!(f, "Invalid configuration: {}", reason),
+            NetworkError::AddressOutOfRange{device, id, router_as} => write!(f, "No address for router {} with id {} in AS {}, the id must be at most 255 and the AS at most 65535", device, id, router_as),
+            NetworkError::DuplicateAddress{device, ip, other} => write!(f, "Address {} of router {} is already used by router {}", ip, device, other),
+            NetworkError::SubnetTooSmall(subnet) => write!(f, "Subnet {} has no room for the two ends of a link", subnet),
+            NetworkError::AddressOutsideSubnet{address, subnet} => write!(f, "Address {} is not in subnet {}", address, subnet),
+            NetworkError::HostAlreadyLinked(host) => write!(f, "Host {} already has a link", host),
+            NetworkError::NotCrashed(router) => write!(f, "Router {} didn't crash, it can't be restarted", router),
+            NetworkError::UnknownFlow(flow) => write!(f, "Unknown flow {}", flow),
+            NetworkError::NoLinkOnPort{device, port} => write!(f, "No link on port {} of device {}", port, device),
+            NetworkError::Capture(reason) => write!(f, "Capture failed: {}", reason),
+            NetworkError::Log(reason) => write!(f, "Log failed: {}", reason),
+            NetworkError::Io(reason) => write!(f, "I/O failed: {}", reason),
+            NetworkError::NotConverged{timeout, routers} => write!(f, "Routers {} didn't converge within {}ms", routers.join(", "), timeout.as_millis()),
+            NetworkError::InvalidCommand(reason) => write!(f, "Invalid command: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+impl From<PingError> for NetworkError {
+    fn from(error: PingError) -> Self {
+        NetworkError::Ping(error)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PingError{
+    Timeout(Ipv4Addr),
+    RouterStopped,
+}
+
+impl Display for PingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            PingError::Timeout(dest) => write!(f, "No answer from {} before timeout", dest),
+            PingError::RouterStopped => write!(f, "Router stopped before answering"),
+        }
+    }
+}
+
+impl std::error::Error for PingError {}
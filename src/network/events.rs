@@ -0,0 +1,39 @@
+use std::{net::Ipv4Addr, time::Duration};
+
+use tokio::sync::broadcast::{self, error::RecvError, Receiver};
+
+use super::{ip_prefix::IPPrefix, protocols::bgp::BGPRoute, switch::PortState};
+
+// events kept for a subscriber that doesn't read them, the oldest ones are then lost
+pub const EVENT_CAPACITY: usize = 1024;
+
+// A change in a device, emitted in addition to its textual logs
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkEvent{
+    StpPortStateChanged{switch: String, port: u32, old: PortState, new: PortState},
+    OspfRouteChanged{router: String, prefix: IPPrefix}, // the route was added, changed or removed
+    BgpBestChanged{router: String, prefix: IPPrefix, old: Option<BGPRoute>, new: Option<BGPRoute>},
+    ForwardingLoop{router: String, path: Vec<Ipv4Addr>} // routers crossed by the packet since it last left the router, which dropped it
+}
+
+// given to the devices when they start, sending without any subscriber does nothing
+pub(crate) type EventSender = broadcast::Sender<NetworkEvent>;
+
+pub(crate) fn emit(events: &EventSender, event: NetworkEvent){
+    // nobody may be subscribed
+    let _ = events.send(event);
+}
+
+// Waits for the first event accepted by matches, None if it didn't come before the timeout
+pub async fn wait_for_event<F: Fn(&NetworkEvent) -> bool>(receiver: &mut Receiver<NetworkEvent>, timeout: Duration, matches: F) -> Option<NetworkEvent>{
+    let wait = async {
+        loop{
+            match receiver.recv().await{
+                Ok(event) if matches(&event) => return Some(event),
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    };
+    tokio::time::timeout(timeout, wait).await.ok().flatten()
+}
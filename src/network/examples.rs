@@ -0,0 +1,392 @@
+use std::fmt::Display;
+
+use strum::IntoEnumIterator;
+
+use super::{config::{PingOutcome, DEFAULT_COST, DEFAULT_MED, DEFAULT_PING_INTERVAL_MS, IGP_MED, POLICY_ACTIONS}, link::{QueueDiscipline, DEFAULT_QUEUE_DEPTH, REORDER_DELAY}, logger::Source, protocols::{bgp::{SessionState, CUSTOMER_PREF, DEFAULT_LOCAL_PREF, PEER_PREF, PROVIDER_PREF}, ospf::DEAD_INTERVAL}, timers::TimerConfig};
+
+// ready-to-run scenarios, printed by `network-simulator example <name>` and run by the integration tests
+pub const EXAMPLES: [(&str, &str); 4] = [
+    ("ospf-square", include_str!("../../examples/ospf-square.yaml")),
+    ("bgp-diamond", include_str!("../../examples/bgp-diamond.yaml")),
+    ("stp-classic", include_str!("../../examples/stp-classic.yaml")),
+    ("ibgp-as", include_str!("../../examples/ibgp-as.yaml")),
+];
+
+pub fn example(name: &str) -> Option<&'static str>{
+    EXAMPLES.iter().find(|(n, _)| *n == name).map(|(_, yaml)| *yaml)
+}
+
+// rule of the grammar of the configuration files, either the keys of a mapping or the forms a value can take
+struct Rule{
+    name: &'static str,
+    comment: &'static str,
+    body: Body
+}
+
+enum Body{
+    Fields(Vec<(&'static str, String, String)>), // key, type, comment
+    Forms(Vec<(String, String)>)                 // form, comment
+}
+
+fn fields(name: &'static str, fields: &[(&'static str, &str, &str)]) -> Rule{
+    Rule{name, comment: "", body: Body::Fields(fields.iter().map(|(key, kind, comment)| (*key, kind.to_string(), comment.to_string())).collect())}
+}
+
+fn forms(name: &'static str, forms: &[(&str, &str)]) -> Rule{
+    Rule{name, comment: "", body: Body::Forms(forms.iter().map(|(form, comment)| (form.to_string(), comment.to_string())).collect())}
+}
+
+// string values accepted by the parser, e.g. the names of the log sources
+fn names<T: Display>(values: impl Iterator<Item = T>) -> Body{
+    Body::Forms(values.map(|v| (format!("\"{}\"", v), String::new())).collect())
+}
+
+fn rules() -> Vec<Rule>{
+    let timers = TimerConfig::default();
+    let states: Vec<String> = SessionState::iter().map(|s| format!("\"{}\"", s)).collect();
+    let states = match states.split_last(){
+        Some((last, first)) => format!("{} or {}", first.join(", "), last),
+        None => String::new(),
+    };
+    vec![
+        fields("Network", &[("network", "NetworkConf", "")]),
+        fields("NetworkConf", &[
+            ("routers", "List[RouterDef]", ""),
+            ("switches", "List[SwitchDef]", ""),
+            ("hosts", "List[HostDef]", ""),
+            ("links", "Links", ""),
+            ("config", "Config", ""),
+            ("actions", "Actions", ""),
+            ("events", "List[Event]", ""),
+        ]),
+        fields("RouterDef", &[
+            ("name", "str", ""),
+            ("id", "uint", ""),
+            ("AS", "uint", ""),
+            ("advertise", "List[ConditionalAdvertisement] | ConditionalAdvertisement", "optional"),
+            ("secrets", "Map[str, str]", "optional, secret expected from each BGP neighbor (router name), the session stays Idle if they differ"),
+            ("ospf", "bool", "optional, false to disable OSPF on the router (true by default)"),
+            ("static_routes", "List[StaticRouteConf]", "optional, routes kept whatever OSPF computes"),
+        ]),
+        fields("StaticRouteConf", &[
+            ("prefix", "str", "e.g. \"10.0.1.4/32\", or \"0.0.0.0/0\" for a default route"),
+            ("nexthop", "str", "address of a router linked to this one, or"),
+            ("port", "uint", "port of the link towards the nexthop"),
+        ]),
+        fields("ConditionalAdvertisement", &[
+            ("prefix", "str", "prefix advertised, e.g. \"10.9.0.0/24\""),
+            ("unless_present", "str", "the prefix is only advertised while this one has no route in the BGP table"),
+            ("session", "str", "BGP neighbor (router name) receiving the advertisement"),
+        ]),
+        fields("SwitchDef", &[
+            ("name", "str", ""),
+            ("id", "uint", ""),
+        ]),
+        fields("HostDef", &[
+            ("name", "str", ""),
+            ("ip", "str", "e.g. \"10.1.1.2\", a host has a single link"),
+            ("gateway", "str", "address of a router of the LAN of the host, which receives all its packets"),
+        ]),
+        fields("Links", &[
+            ("internal", "List[InternalLinkConf]", ""),
+            ("bgp", "BGPLinks", ""),
+        ]),
+        forms("InternalLinkConf", &[
+            ("[device1 (LinkEnd), device2 (LinkEnd), cost (uint)]", ""),
+            ("[device1 (LinkEnd), device2 (LinkEnd)]", &format!("cost of {} by default", DEFAULT_COST)),
+            ("[device1 (LinkEnd), device2 (LinkEnd), cost (uint), address (str)]", "address of device1, a router, on the link and length of the subnet it advertises, e.g. \"10.1.1.1/24\""),
+            ("InternalLinkMapConf", ""),
+        ]),
+        forms("LinkEnd", &[
+            ("str", "name of the device, the link gets its lowest port not given to another link"),
+            ("str:uint", "name of the device and port of the link, e.g. \"r1:2\""),
+        ]),
+        fields("InternalLinkMapConf", &[
+            ("devices", "[LinkEnd, LinkEnd]", "or"),
+            ("a", "str", "first device and"),
+            ("a_port", "uint", "optional, its port on the link"),
+            ("b", "str", "second device"),
+            ("b_port", "uint", "optional, its port on the link"),
+            ("cost", "uint", &format!("optional, {} by default", DEFAULT_COST)),
+            ("address", "str", "optional, address of the first device on the link, as above"),
+            ("latency_ms", "uint", "optional, delay of the messages of the link in each direction (none by default)"),
+            ("impairment", "LinkImpairmentConf", "optional, faults of the link, none by default"),
+            ("rate_limit", "RateLimitConf", "optional, unlimited by default"),
+        ]),
+        fields("LinkImpairmentConf", &[
+            ("loss_prob", "float", "optional, probability that a message is lost (default 0)"),
+            ("reorder_prob", "float", &format!("optional, probability that a message is delayed by {}ms more, the next ones overtaking it (default 0)", REORDER_DELAY.as_millis())),
+            ("duplicate_prob", "float", "optional, probability that a message is received twice (default 0)"),
+            ("seed", "uint", "optional, the same seed loses, reorders and duplicates the same messages of a run (default 0)"),
+        ]),
+        fields("RateLimitConf", &[
+            ("messages_per_sec", "uint", "messages sent by the link in each direction, or"),
+            ("bytes_per_sec", "uint", "bytes sent by the link in each direction, as the messages are printed in the logs"),
+            ("queue_depth", "uint", &format!("optional, messages waiting to be sent, the next ones are dropped (default {})", DEFAULT_QUEUE_DEPTH)),
+            ("discipline", "QueueDiscipline", &format!("optional, {} by default, priority sends the control messages (hellos, LSPs, BGP, ARP, BPDUs) before the traffic, each class having its own queue", QueueDiscipline::default())),
+        ]),
+        Rule{name: "QueueDiscipline", comment: "", body: names(QueueDiscipline::iter())},
+        fields("BGPLinks", &[
+            ("provider-customer", "List[ProviderCustomerLinkConf]", ""),
+            ("peer", "List[PeerLinkConf]", ""),
+            ("ibgp", "List[IBGPConnectionConf]", ""),
+            ("ibgp_full_mesh", "List[uint]", "ASes whose routers all have iBGP sessions with each other, besides the ibgp ones"),
+        ]),
+        fields("ProviderCustomerLinkConf", &[
+            ("provider", "str", ""),
+            ("customer", "str", ""),
+            ("med", "BGPMed", "optional"),
+            ("local_pref", "LinkLocalPrefConf", "optional"),
+            ("prepend", "LinkPrependConf", "optional"),
+            ("import", "LinkPolicyConf", "optional, rules applied by each router to the routes it receives from the other"),
+            ("export", "LinkPolicyConf", "optional, rules applied by each router to the routes it sends to the other"),
+            ("max_prefixes", "LinkMaxPrefixesConf", "optional"),
+            ("latency_ms", "uint", "optional, delay of the messages of the link in each direction (none by default)"),
+            ("impairment", "LinkImpairmentConf", "optional, faults of the link, none by default"),
+            ("rate_limit", "RateLimitConf", "optional, unlimited by default"),
+        ]),
+        fields("LinkLocalPrefConf", &[
+            ("provider", "uint", &format!("optional, pref of the provider for the routes of its customer (default {})", CUSTOMER_PREF)),
+            ("customer", "uint", &format!("optional, pref of the customer for the routes of its provider (default {})", PROVIDER_PREF)),
+        ]),
+        fields("LinkPrependConf", &[
+            ("provider", "uint", "optional, extra times the provider prepends its AS to the paths it advertises to its customer (none by default)"),
+            ("customer", "uint", "optional, extra times the customer prepends its AS to the paths it advertises to its provider (none by default)"),
+        ]),
+        fields("LinkPolicyConf", &[
+            ("provider", "List[PolicyRuleConf]", "optional, no rule by default"),
+            ("customer", "List[PolicyRuleConf]", "optional, no rule by default"),
+        ]),
+        fields("PolicyRuleConf", &[
+            ("prefix", "str", "e.g. \"10.0.1.0/24\", the first rule matching the prefix of a route applies, the routes matched by no rule are accepted"),
+            ("le", "uint", "optional, longest prefix length matched inside prefix (only the length of prefix by default)"),
+            ("action", "PolicyAction", ""),
+            ("value", "uint", "MED or pref set by the set_med and set_pref actions, set_pref is ignored on export"),
+        ]),
+        Rule{name: "PolicyAction", comment: "", body: names(POLICY_ACTIONS.iter())},
+        fields("LinkMaxPrefixesConf", &[
+            ("provider", "MaxPrefixesConf", "optional, unlimited by default"),
+            ("customer", "MaxPrefixesConf", "optional, unlimited by default"),
+        ]),
+        fields("MaxPrefixesConf", &[
+            ("limit", "uint", "most prefixes accepted from the neighbor"),
+            ("action", "MaxPrefixAction", "optional, what happens to the updates above the limit (\"drop\" by default)"),
+        ]),
+        forms("MaxPrefixAction", &[
+            ("\"drop\"", "the updates for new prefixes are ignored"),
+            ("\"close\"", "the session is closed and the routes of the neighbor are withdrawn"),
+        ]),
+        forms("PeerLinkConf", &[
+            ("[str, str]", ""),
+            ("[str, str, BGPMed]", ""),
+            ("[str, str, BGPMed, LinkPref]", "pref of the first router for the routes of the second"),
+            ("[str, str, BGPMed, LinkPref, LinkPref]", "and pref of the second router for the routes of the first"),
+            ("PeerLinkMapConf", ""),
+        ]),
+        fields("PeerLinkMapConf", &[
+            ("peers", "[str, str]", ""),
+            ("med", "BGPMed", "optional"),
+            ("local_pref", "[LinkPref, LinkPref]", "optional, pref of each router for the routes of the other"),
+            ("prepend", "[uint, uint]", "optional, extra times each router prepends its AS to the paths it advertises to the other (none by default)"),
+            ("import", "[List[PolicyRuleConf], List[PolicyRuleConf]]", "optional, rules applied by each router to the routes it receives from the other"),
+            ("export", "[List[PolicyRuleConf], List[PolicyRuleConf]]", "optional, rules applied by each router to the routes it sends to the other"),
+            ("max_prefixes", "[MaxPrefixesConf, MaxPrefixesConf]", "optional, most prefixes each router accepts from the other (unlimited by default)"),
+            ("latency_ms", "uint", "optional, delay of the messages of the link in each direction (none by default)"),
+            ("impairment", "LinkImpairmentConf", "optional, faults of the link, none by default"),
+            ("rate_limit", "RateLimitConf", "optional, unlimited by default"),
+        ]),
+        forms("LinkPref", &[
+            ("uint", "local pref applied to the routes learned on the session, the relationship still decides where they are exported"),
+            ("null", &format!("pref of the relationship (default {} for a peer)", PEER_PREF)),
+        ]),
+        forms("BGPMed", &[
+            ("uint", &format!("MED sent by both routers on the session (default {})", DEFAULT_MED)),
+            (&format!("\"{}\"", IGP_MED), "MED set to the IGP distance to the nexthop of each route (0 for their own prefix), sent again when it changes"),
+        ]),
+        forms("IBGPConnectionConf", &[
+            ("[str, str]", "iBGP session between two routers of the same AS, the sessions should form a full mesh"),
+            ("IBGPClientConf", ""),
+        ]),
+        fields("IBGPClientConf", &[
+            ("rr", "str", "route reflector, advertises the routes of its clients to all its peers and the other routes to its clients"),
+            ("client", "str", "router only connected to its route reflectors"),
+        ]),
+        fields("Config", &[
+            ("log", "List[LogSource]", ""),
+            ("timers", "TimersConf", "optional"),
+        ]),
+        fields("TimersConf", &[
+            ("hello_interval_ms", "uint", &format!("period of the OSPF hellos of the routers (default {})", timers.hello_interval.as_millis())),
+            ("bpdu_interval_ms", "uint", &format!("period of the BPDUs of the switches (default {})", timers.bpdu_interval.as_millis())),
+            ("arp_refresh_interval_ms", "uint", &format!("period of the ARP resolutions of the neighbors of the routers (default {})", timers.arp_refresh_interval.as_millis())),
+            ("keepalive_interval_ms", "uint", &format!("period of the BGP keepalives, a session is closed and its routes withdrawn after three periods without them (default {})", timers.keepalive_interval.as_millis())),
+            ("lsp_refresh_interval_ms", "uint", &format!("period of the LSPs flooded again by the routers, the LSPs not refreshed for three periods are forgotten (default {})", timers.lsp_refresh_interval.as_millis())),
+            ("dead_interval_ms", "uint", &format!("silence after which a router considers an OSPF neighbor dead (default {})", DEAD_INTERVAL.as_millis())),
+        ]),
+        Rule{name: "LogSource", comment: "", body: names(Source::iter())},
+        fields("Actions", &[
+            ("announce_prefix", "List[ToAnnounce]", ""),
+            ("withdraw_prefix", "List[str]", "routers withdrawing their prefix once BGP converged, before the tables are printed"),
+            ("clear_routes", "List[str]", "routers flushing the routes learned by OSPF, at the same time as the withdrawals"),
+            ("clear_bgp", "List[str]", "routers flushing their BGP routes and asking their neighbors to send them again"),
+            ("ping", "List[PingConf]", ""),
+            ("print_bgp_tables", "bool", "print the bgp tables"),
+            ("print_routing_tables", "bool", "print the routing tables"),
+            ("print_reachability", "bool", "print which routers have a route towards each other router"),
+            ("print_spanning_tree_graph", "bool", "print the DOT graph of the spanning tree of the switches, with their port states"),
+            ("print_forwarding_graph", "ForwardingGraphConf", "print the DOT graph of the links the routers use towards an address"),
+            ("dot_graph_file", "str", "save the representation of network in file"),
+            ("state_file", "str", "save the routing tables, BGP tables and port states as JSON in file"),
+            ("assert_routing_table", "List[RoutingTableAssertion]", "checked once everything else ran, the scenario fails after printing the differences with the expected state"),
+            ("assert_bgp_best", "List[BGPBestAssertion]", ""),
+            ("assert_ping", "List[PingAssertion]", ""),
+        ]),
+        forms("ToAnnounce", &[
+            ("str", "single router announce its prefix"),
+            ("uint", "AS announce its prefix"),
+            ("AnnounceConf", ""),
+        ]),
+        fields("AnnounceConf", &[
+            ("router", "str", "router that will announce its prefix"),
+            ("pref", "uint", &format!("local preference of its own route (default {}), the prefix is only advertised while this route is the best", DEFAULT_LOCAL_PREF)),
+            ("prefix", "str", "optional, prefix announced instead of the one of the AS (e.g. \"192.0.2.0/24\"), the router answers the pings to its addresses"),
+        ]),
+        fields("PingConf", &[
+            ("from", "str", "router or host that will generate the ping"),
+            ("to", "str", "IP address to ping"),
+            ("count", "uint", "optional, pings sent from a router like ping -c, their loss and round trip times are printed"),
+            ("interval_ms", "uint", &format!("optional, time between the pings of a count (default {})", DEFAULT_PING_INTERVAL_MS)),
+        ]),
+        fields("ForwardingGraphConf", &[
+            ("dest", "str", "IP address, e.g. \"10.0.1.1\", the links towards it are green and the routers without route grey"),
+        ]),
+        fields("PortConf", &[
+            ("device", "str", "router or switch"),
+            ("port", "uint", ""),
+        ]),
+        fields("LinkCostConf", &[
+            ("device", "str", "router or switch, the link has the same cost at both ends"),
+            ("port", "uint", ""),
+            ("cost", "uint", ""),
+        ]),
+        fields("LocalPrefConf", &[
+            ("router", "str", "router changing the pref of the routes it learns from a session"),
+            ("session", "str", "BGP neighbor of router"),
+            ("value", "uint", "pref applied to the routes of the session, instead of the one of its relationship"),
+        ]),
+        fields("SessionExpectation", &[
+            ("router", "str", ""),
+            ("session", "str", "BGP neighbor of router"),
+            ("state", "str", &states),
+        ]),
+        fields("RouteExpectation", &[
+            ("router", "str", ""),
+            ("prefix", "str", "e.g. \"10.0.1.0/24\""),
+            ("learned", "bool", "whether router has a best BGP route to prefix, true by default"),
+        ]),
+        fields("RoutingTableAssertion", &[
+            ("router", "str", ""),
+            ("entries", "Map[str, RouteAssertion]", "expected routes of the prefixes, e.g. \"10.0.1.4/32\", the other routes of the table aren't checked"),
+        ]),
+        fields("RouteAssertion", &[
+            ("port", "uint", "optional"),
+            ("distance", "uint", "optional"),
+        ]),
+        fields("BGPBestAssertion", &[
+            ("router", "str", ""),
+            ("prefix", "str", ""),
+            ("as_path", "List[uint]", "AS path of the best BGP route of router towards prefix"),
+        ]),
+        fields("PingAssertion", &[
+            ("from", "str", ""),
+            ("to", "str", ""),
+            ("expect", "PingOutcome", &format!("optional, {} by default", PingOutcome::default())),
+        ]),
+        Rule{name: "PingOutcome", comment: "", body: names(PingOutcome::iter())},
+        Rule{name: "Event", comment: "a time_ms and one of the actions below", body: fields("", &[
+            ("time_ms", "uint", "time since the network was built, the time of the previous event by default"),
+            ("announce_prefix", "ToAnnounce", ""),
+            ("withdraw_prefix", "str", ""),
+            ("clear_routes", "str", ""),
+            ("clear_bgp", "str", ""),
+            ("remove_link", "[str, str]", "the two devices of the link"),
+            ("disable_port", "PortConf", "shut the port down, it keeps its link but neither sends nor receives"),
+            ("enable_port", "PortConf", ""),
+            ("set_link_cost", "LinkCostConf", "the switches compute the spanning tree and the routers their routes again"),
+            ("set_local_pref", "LocalPrefConf", ""),
+            ("restore_local_pref", "LocalPrefConf", "the pref configured on the link, or else the one of the relationship, applies again, value is not needed"),
+            ("ping", "PingConf", ""),
+            ("print_routing_tables", "bool", ""),
+            ("print_port_states", "bool", ""),
+            ("print_bgp_tables", "bool", ""),
+            ("print_reachability", "bool", ""),
+            ("print_bgp_sessions", "bool", "print the state, neighbor AS and uptime of the eBGP sessions of each router"),
+            ("print_health", "str", "print the interfaces, neighbors, table and prefix trie sizes, sessions and anomalies of a router"),
+            ("print_lsdb", "str", "print the newest LSP of each originator known by a router, with its sequence number and age"),
+            ("print_stats", "bool", "print the messages sent and received by each router and switch, by port and kind"),
+            ("print_spanning_tree_graph", "bool", ""),
+            ("print_forwarding_graph", "ForwardingGraphConf", ""),
+            ("expect_session", "SessionExpectation", "the scenario stops with an error if the expectation doesn't hold"),
+            ("expect_route", "RouteExpectation", ""),
+            ("assert_routing_table", "RoutingTableAssertion", "the failed assertions don't stop the scenario, it fails once all the events ran"),
+            ("assert_bgp_best", "BGPBestAssertion", ""),
+            ("assert_ping", "PingAssertion", ""),
+            ("dot_graph_file", "str", ""),
+            ("state_file", "str", ""),
+            ("wait", "uint", "delay the next events by this number of milliseconds"),
+            ("wait_for_convergence", "uint", "delay the next events until the routes of the routers stop changing, failing after this number of milliseconds"),
+        ]).body},
+    ]
+}
+
+// the comments of a rule are aligned after its longest line
+fn render(rule: &Rule) -> String{
+    let lines: Vec<(String, &str)> = match &rule.body{
+        Body::Fields(fields) => fields.iter().map(|(key, kind, comment)| (format!("    {}: {}", key, kind), comment.as_str())).collect(),
+        Body::Forms(forms) => forms.iter().enumerate().map(|(i, (form, comment))| {
+            (format!("{}{}", if i == 0 { "    ::= " } else { "      | " }, form), comment.as_str())
+        }).collect(),
+    };
+    let width = lines.iter().filter(|(_, comment)| !comment.is_empty()).map(|(line, _)| line.len()).max().unwrap_or(0);
+    let comment = |line: &str, comment: &str| match comment{
+        "" => line.to_string(),
+        comment => format!("{:width$} // {}", line, comment, width = width),
+    };
+    let header = match rule.body{
+        Body::Fields(_) => comment(&format!("{} ::=", rule.name), rule.comment),
+        Body::Forms(_) => rule.name.to_string(),
+    };
+    let mut text = header + "\n";
+    for (line, c) in lines.iter(){
+        text += &comment(line, c);
+        text += "\n";
+    }
+    text
+}
+
+// grammar of the configuration files, built from the defaults and the values accepted by the parsers
+pub fn schema() -> String{
+    rules().iter().map(render).collect::<Vec<String>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema() {
+        let schema = schema();
+        assert!(schema.starts_with("Network ::=\n    network: NetworkConf\n"));
+        assert!(schema.contains("      | \"igp\""));
+        assert!(schema.contains("\"Idle\", \"OpenSent\" or \"Established\""));
+        assert!(schema.contains("(default 200)"));
+        // the README documents the same grammar
+        assert!(include_str!("../../README.md").contains(&schema), "the grammar of the README differs from:\n{}", schema);
+    }
+
+    #[test]
+    fn test_example() {
+        assert!(example("bgp-diamond").unwrap().contains("provider-customer"));
+        assert_eq!(example("unknown"), None);
+    }
+}
@@ -0,0 +1,204 @@
+use std::{collections::BTreeSet, f64::consts::PI};
+
+use super::{config::{BgpLinkCfg, BgpMed, ConfigFile, LinkCfg, PeerLinkCfg, RouterCfg, DEFAULT_COST}, error::NetworkError, utils::Rng};
+
+// the id of a router is the last byte of its address, an AS has at most 255 routers
+const MAX_ROUTERS: usize = 255;
+// ASes of the top tier of an AS hierarchy, each tier below has twice as many
+const TOP_TIER_SIZE: usize = 3;
+// the AS numbers of the 3 * (2^14 - 1) ASes of the tiers fit in the second and third bytes of the addresses
+const MAX_TIERS: usize = 14;
+// the hellos of a large network wait behind its LSPs while it converges, its neighbors are given a longer silence
+const GENERATED_DEAD_INTERVAL_MS: u64 = 2000;
+// chance for an AS below the top tier to peer with another AS of its tier
+const PEERING_PROB: f64 = 0.25;
+
+fn generated_config() -> ConfigFile{
+    let mut config = ConfigFile::default();
+    config.network.config.timers.dead_interval_ms = Some(GENERATED_DEAD_INTERVAL_MS);
+    config
+}
+
+// Routers of AS 1 placed at random in a unit square, two routers are linked when they are close enough to have
+// avg_degree neighbors on average. The closest routers of the parts left apart are linked too, so that every
+// router reaches the others. The cost of a link grows with its length
+pub fn random_geometric(n_routers: usize, avg_degree: f64, seed: u64) -> Result<ConfigFile, NetworkError>{
+    if n_routers == 0 || n_routers > MAX_ROUTERS{
+        return Err(NetworkError::InvalidConfig(format!("a random geometric topology has between 1 and {} routers, not {}", MAX_ROUTERS, n_routers)));
+    }
+    let mut rng = Rng(seed);
+    let points: Vec<(f64, f64)> = (0..n_routers).map(|_| (rng.next_f64(), rng.next_f64())).collect();
+    let radius = (avg_degree / (PI * n_routers as f64)).sqrt();
+
+    let mut pairs = vec![];
+    for i in 0..n_routers{
+        for j in i + 1..n_routers{
+            let ((x1, y1), (x2, y2)) = (points[i], points[j]);
+            pairs.push(((x1 - x2).hypot(y1 - y2), i, j));
+        }
+    }
+    pairs.sort_by(|(d1, _, _), (d2, _, _)| d1.total_cmp(d2));
+
+    let mut config = generated_config();
+    let network = &mut config.network;
+    network.routers = (1..=n_routers).map(|id| RouterCfg::new(&format!("r{}", id), id as u32, 1)).collect();
+    // the part of each router, merged when a link joins two parts
+    let mut parts: Vec<usize> = (0..n_routers).collect();
+    for (distance, i, j) in pairs{
+        let (part_i, part_j) = (parts[i], parts[j]);
+        if distance > radius && part_i == part_j{
+            continue;
+        }
+        for part in parts.iter_mut().filter(|part| **part == part_j){
+            *part = part_i;
+        }
+        let cost = ((distance * 100.0).round() as u32).max(DEFAULT_COST);
+        let ends = (network.routers[i].name.as_str().into(), network.routers[j].name.as_str().into());
+        network.links.internal.push(LinkCfg::new(ends, cost));
+    }
+    Ok(config)
+}
+
+// Fat tree of routers of AS 1 with k ports each: k pods of k/2 edge and k/2 aggregation routers, linked to each
+// other inside their pod, and (k/2)^2 core routers, each aggregation router being linked to k/2 of them
+pub fn fat_tree(k: usize) -> Result<ConfigFile, NetworkError>{
+    let half = k / 2;
+    let n_routers = half * half + k * k;
+    if k == 0 || k % 2 == 1 || n_routers > MAX_ROUTERS{
+        return Err(NetworkError::InvalidConfig(format!("a fat tree has an even number of ports and at most {} routers, not {} ports", MAX_ROUTERS, k)));
+    }
+    let core = |i: usize| format!("core{}", i + 1);
+    let aggregation = |pod: usize, i: usize| format!("agg{}_{}", pod + 1, i + 1);
+    let edge = |pod: usize, i: usize| format!("edge{}_{}", pod + 1, i + 1);
+
+    let mut names: Vec<String> = (0..half * half).map(core).collect();
+    for pod in 0..k{
+        names.extend((0..half).map(|i| aggregation(pod, i)));
+        names.extend((0..half).map(|i| edge(pod, i)));
+    }
+    let mut config = generated_config();
+    let network = &mut config.network;
+    network.routers = names.iter().enumerate().map(|(i, name)| RouterCfg::new(name, i as u32 + 1, 1)).collect();
+    for pod in 0..k{
+        for i in 0..half{
+            for j in 0..half{
+                let ends = (edge(pod, i).as_str().into(), aggregation(pod, j).as_str().into());
+                network.links.internal.push(LinkCfg::new(ends, DEFAULT_COST));
+            }
+        }
+        for i in 0..half{
+            for j in 0..half{
+                let ends = (aggregation(pod, i).as_str().into(), core(i * half + j).as_str().into());
+                network.links.internal.push(LinkCfg::new(ends, DEFAULT_COST));
+            }
+        }
+    }
+    Ok(config)
+}
+
+// Hierarchy of ASes of one router each, the ASes of the top tier are the peers of each other and each tier below has
+// twice as many ASes as the one above. Following the Gao-Rexford model, the providers of an AS are one or two ASes of the
+// tier above, so that no AS is an indirect customer of itself, and the peers of an AS are in its tier
+pub fn as_hierarchy(n_tiers: usize, seed: u64) -> Result<ConfigFile, NetworkError>{
+    if n_tiers == 0 || n_tiers > MAX_TIERS{
+        return Err(NetworkError::InvalidConfig(format!("an AS hierarchy has between 1 and {} tiers, not {}", MAX_TIERS, n_tiers)));
+    }
+    let mut rng = Rng(seed);
+    let mut config = generated_config();
+    let network = &mut config.network;
+    // the AS numbers of each tier, the ASes are numbered from the top
+    let mut tiers: Vec<Vec<u32>> = vec![];
+    for tier in 0..n_tiers{
+        let first = tiers.last().and_then(|ases| ases.last()).map_or(1, |last| last + 1);
+        tiers.push((first..first + (TOP_TIER_SIZE << tier) as u32).collect());
+    }
+    let name = |router_as: u32| format!("r{}", router_as);
+    network.routers = tiers.iter().flatten().map(|router_as| RouterCfg::new(&name(*router_as), 1, *router_as)).collect();
+
+    let bgp = &mut network.links.bgp;
+    for (i, r1) in tiers[0].iter().enumerate(){
+        for r2 in &tiers[0][i + 1..]{
+            bgp.peer.push(PeerLinkCfg::new(&name(*r1), &name(*r2), BgpMed::default()));
+        }
+    }
+    for (above, ases) in tiers.iter().zip(&tiers[1..]){
+        let mut peers = BTreeSet::new();
+        for customer in ases{
+            let first = above[rng.below(above.len())];
+            let second = above[rng.below(above.len())];
+            for provider in BTreeSet::from([first, second]){
+                bgp.provider_customer.push(BgpLinkCfg::new(&name(provider), &name(*customer), BgpMed::default()));
+            }
+            let peer = ases[rng.below(ases.len())];
+            if rng.next_f64() < PEERING_PROB && peer != *customer && peers.insert((peer.min(*customer), peer.max(*customer))){
+                bgp.peer.push(PeerLinkCfg::new(&name(*customer), &name(peer), BgpMed::default()));
+            }
+        }
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{logger::Logger, scenario::{self, CONVERGENCE_TIMEOUT}, Network};
+
+    #[test]
+    fn test_random_geometric() {
+        let config = random_geometric(50, 4.0, 7).unwrap();
+        assert_eq!(config, random_geometric(50, 4.0, 7).unwrap());
+        assert_ne!(config, random_geometric(50, 4.0, 8).unwrap());
+        scenario::validate(&config).unwrap();
+        assert_eq!(config.network.routers.len(), 50);
+        let links = config.network.links.internal.len();
+        assert!((75..=150).contains(&links), "{} links", links);
+
+        // the links join all the routers
+        let mut reached = BTreeSet::from(["r1".to_string()]);
+        while let Some(link) = config.network.links.internal.iter().find(|link| reached.contains(&link.ends.0.device) != reached.contains(&link.ends.1.device)){
+            reached.extend([link.ends.0.device.clone(), link.ends.1.device.clone()]);
+        }
+        assert_eq!(reached.len(), 50);
+        assert!(random_geometric(256, 4.0, 7).is_err());
+    }
+
+    #[test]
+    fn test_fat_tree() {
+        let config = fat_tree(4).unwrap();
+        scenario::validate(&config).unwrap();
+        assert_eq!(config.network.routers.len(), 20);
+        assert_eq!(config.network.links.internal.len(), 32);
+        // the edge routers keep k/2 ports for the hosts
+        let degree = |router: &str| config.network.links.internal.iter().filter(|link| link.ends.0.device == router || link.ends.1.device == router).count();
+        assert_eq!((degree("edge1_1"), degree("agg2_2"), degree("core4")), (2, 4, 4));
+        assert!(fat_tree(3).is_err());
+        assert!(fat_tree(16).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_as_hierarchy() {
+        let config = as_hierarchy(3, 1).unwrap();
+        assert_eq!(config, as_hierarchy(3, 1).unwrap());
+        assert_eq!(config.network.routers.len(), 21);
+        let bgp = &config.network.links.bgp;
+        let tier = |router: &str| match router[1..].parse::<u32>().unwrap(){
+            1..=3 => 0,
+            4..=9 => 1,
+            _ => 2,
+        };
+        assert!(bgp.provider_customer.iter().all(|link| tier(&link.provider) + 1 == tier(&link.customer)));
+        assert!(bgp.peer.iter().all(|link| tier(&link.peers.0) == tier(&link.peers.1)));
+        assert!(as_hierarchy(0, 1).is_err());
+
+        // the prefix of a stub AS reaches every AS, through the top tier
+        let network = Network::from_config(&config, Logger::start_test()).await.unwrap();
+        network.announce_prefix("r21").await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+        let prefix = "10.0.21.0/24".parse().unwrap();
+        for router in network.routers().iter().filter(|router| *router != "r21"){
+            let routes = network.get_bgp_routes(router).await.unwrap();
+            assert!(routes.get(&prefix).is_some_and(|(best, _)| best.is_some()), "{}", router);
+        }
+        network.quit().await;
+    }
+}
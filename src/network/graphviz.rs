@@ -1,4 +1,4 @@
-use std::{collections::HashMap, fmt::Display};
+use std::fmt::Display;
 
 pub enum EdgeOption{
     Color(String),
@@ -6,7 +6,10 @@ pub enum EdgeOption{
     Label(String),
     Arrowhead(String),
     Headlabel(String),
-    Taillabel(String)
+    Taillabel(String),
+    Style(String),
+    PenWidth(String),
+    Dir(String)
 }
 
 impl Display for EdgeOption {
@@ -18,18 +21,29 @@ impl Display for EdgeOption {
             EdgeOption::Arrowhead(t) => write!(f, "arrowhead={}", t),
             EdgeOption::Headlabel(l) => write!(f, "headlabel=\"{}\"", l),
             EdgeOption::Taillabel(l) => write!(f, "taillabel=\"{}\"", l),
+            EdgeOption::Style(s) => write!(f, "style={}", s),
+            EdgeOption::PenWidth(w) => write!(f, "penwidth={}", w),
+            EdgeOption::Dir(d) => write!(f, "dir={}", d),
         }
     }
 }
 
 pub enum NodeOption{
     Shape(String),
+    Style(String),
+    Color(String),
+    FontColor(String),
+    FillColor(String),
 }
 
 impl Display for NodeOption {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             NodeOption::Shape(shape) => write!(f, "shape={}", shape),
+            NodeOption::Style(style) => write!(f, "style={}", style),
+            NodeOption::Color(color) => write!(f, "color={}", color),
+            NodeOption::FontColor(color) => write!(f, "fontcolor={}", color),
+            NodeOption::FillColor(color) => write!(f, "fillcolor={}", color),
         }
     }
 }
@@ -52,14 +66,14 @@ type GroupNodes = Vec<(String, Vec<NodeOption>)>; // name, nodeOptions
 
 pub struct Graph{
     nodes: Vec<(String, Vec<NodeOption>)>,
-    groups: HashMap<String, (String, GroupNodes)>,
+    groups: Vec<(String, String, GroupNodes)>, // name, label, nodes, in the order they were added
     edges: Vec<(String, String, Vec<EdgeOption>)>,
     graph_options: Vec<GraphOption>
 }
 
 impl Graph{
     pub fn new(options: Vec<GraphOption>) -> Graph{
-        Graph{nodes: vec![], groups: HashMap::new(), edges: vec![], graph_options: options}
+        Graph{nodes: vec![], groups: vec![], edges: vec![], graph_options: options}
     }
 
     pub fn add_node(&mut self, name: &str, options: Vec<NodeOption>){
@@ -67,11 +81,12 @@ impl Graph{
     }
 
     pub fn add_group(&mut self, name: &str, label: &str){
-        self.groups.insert(name.to_string(), (label.to_string(), vec![]));
+        self.groups.push((name.to_string(), label.to_string(), vec![]));
     }
 
     pub fn add_node_group(&mut self, name: &str, group: &str, options: Vec<NodeOption>){
-        self.groups.get_mut(group).unwrap().1.push((name.to_string(), options));
+        let (_, _, nodes) = self.groups.iter_mut().find(|(group_name, _, _)| group_name == group).unwrap();
+        nodes.push((name.to_string(), options));
     }
 
     pub fn add_edge(&mut self, node1: &str, node2: &str, options: Vec<EdgeOption>){
@@ -91,7 +106,7 @@ impl Display for Graph{
                 &format!("  {}[{}];\n", node, node_options.iter().map(|o| format!("{}", o)).collect::<Vec<String>>().join(",")));
         }
 
-        for (group, (group_label, nodes)) in self.groups.iter(){
+        for (group, group_label, nodes) in self.groups.iter(){
             string.push_str(
                 &format!("  subgraph cluster_{} {{\n", group));
             string.push_str(
@@ -109,7 +124,7 @@ impl Display for Graph{
                 &format!("  {} -> {}[{}];\n", node1, node2, options.iter().map(|o| format!("{}", o)).collect::<Vec<String>>().join(",")));
         }
 
-        string.push_str("}");
+        string.push_str("}\n");
         write!(f, "{}", string)
     }
 }
\ No newline at end of file
@@ -0,0 +1,111 @@
+use std::{collections::BTreeMap, fmt::Display, net::Ipv4Addr};
+
+use serde::Serialize;
+
+use super::protocols::bgp::SessionState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum InterfaceState{
+    Up,      // a neighbor was found behind the port
+    Waiting  // no neighbor answered on the port yet
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SessionHealth{
+    pub state: SessionState,
+    pub prefixes: usize // prefixes learned from the neighbor
+}
+
+// Vital signs of a router, assembled by the router itself from its protocol states
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RouterHealth{
+    pub name: String,
+    pub interfaces: BTreeMap<u32, InterfaceState>,
+    pub ospf_neighbors: BTreeMap<Ipv4Addr, u64>, // milliseconds since the last hello reply of each neighbor
+    pub lsp_database: usize,                     // routers whose neighbors are known from their lsp
+    pub spf_runs: u64,                           // shortest path computations since the router started
+    pub routing_table: usize,
+    pub ospf_trie_nodes: usize,                  // nodes of the prefix tries, to observe their growth
+    pub bgp_trie_nodes: usize,
+    pub bgp_sessions: BTreeMap<Ipv4Addr, SessionHealth>,
+    pub command_queue: usize,
+    pub link_queue: usize,
+    pub anomalies: Vec<String>
+}
+
+impl Display for RouterHealth{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Health of router {}", self.name)?;
+        let interfaces: Vec<String> = self.interfaces.iter().map(|(port, state)| format!("{}: {:?}", port, state)).collect();
+        writeln!(f, "  interfaces: {} ({})", self.interfaces.len(), interfaces.join(", "))?;
+        let neighbors: Vec<String> = self.ospf_neighbors.iter().map(|(ip, ms)| format!("{} seen {}ms ago", ip, ms)).collect();
+        writeln!(f, "  ospf neighbors: {} ({})", self.ospf_neighbors.len(), neighbors.join(", "))?;
+        writeln!(f, "  lsp database: {} routers", self.lsp_database)?;
+        writeln!(f, "  shortest path runs: {}", self.spf_runs)?;
+        writeln!(f, "  routing table: {} entries", self.routing_table)?;
+        writeln!(f, "  prefix tries: {} ospf nodes, {} bgp nodes", self.ospf_trie_nodes, self.bgp_trie_nodes)?;
+        let sessions: Vec<String> = self.bgp_sessions.iter().map(|(ip, s)| format!("{}: {}, {} prefixes", ip, s.state, s.prefixes)).collect();
+        writeln!(f, "  bgp sessions: {} ({})", self.bgp_sessions.len(), sessions.join(", "))?;
+        writeln!(f, "  queues: {} commands, {} link messages", self.command_queue, self.link_queue)?;
+        if self.anomalies.is_empty(){
+            write!(f, "  anomalies: none")
+        }else{
+            write!(f, "  anomalies: {}", self.anomalies.join(", "))
+        }
+    }
+}
+
+// state of the task of a device, a panicked device doesn't answer the queries anymore
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum DeviceHealth{
+    Running,
+    Stopped,         // crashed with crash_router, until it's restarted
+    Panicked(String) // message of the panic
+}
+
+impl Display for DeviceHealth{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            DeviceHealth::Running => write!(f, "running"),
+            DeviceHealth::Stopped => write!(f, "stopped"),
+            DeviceHealth::Panicked(message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+// what the link of a router port leads to, the customers, providers and peers are routers of other ASes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PortKind{
+    Igp,
+    Customer,
+    Provider,
+    Peer
+}
+
+impl Display for PortKind{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self{
+            PortKind::Igp => "igp",
+            PortKind::Customer => "customer",
+            PortKind::Provider => "provider",
+            PortKind::Peer => "peer",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortInfo{
+    pub kind: PortKind,
+    pub cost_or_pref: u32,            // cost of an igp link, local pref of the routes learned on a bgp link
+    pub neighbor_ip: Option<Ipv4Addr> // None until a neighbor answered behind an igp port
+}
+
+impl Display for PortInfo{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind{
+            PortKind::Igp => write!(f, "cost {}", self.cost_or_pref),
+            kind => write!(f, "{} pref {}", kind, self.cost_or_pref),
+        }
+    }
+}
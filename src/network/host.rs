@@ -0,0 +1,176 @@
+use std::{collections::HashMap, net::Ipv4Addr, time::{Duration, SystemTime}};
+use tokio::{sync::{mpsc::{channel, Receiver, Sender}, oneshot}, task::JoinHandle};
+
+use super::{error::PingError, link::LinkSender, logger::{Logger, Source}, messages::{arp::ARPMessage, ip::{Content, DEFAULT_TTL, IP}, Message}, router::PING_TIMEOUT, timers::TimerConfig, utils::{forward_link, LinkMessage, MacAddress}};
+use super::communicators::{Command, HostCommunicator};
+
+type PendingPing = (SystemTime, oneshot::Sender<Result<Duration, PingError>>); // time sent, replier
+
+// End station with a single link, usually towards a switch. It sends all its packets to its
+// gateway, a router of its LAN, and only answers the pings and ARP requests for its own address
+#[derive(Debug)]
+pub struct Host{
+    pub name: String,
+    pub ip: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub mac_address: MacAddress,
+    pub link: Option<(u32, u64, LinkSender)>, // port, id of the link, sender
+    pub arp_mapping: HashMap<Ipv4Addr, MacAddress>,
+    pub pending_pings: HashMap<(Ipv4Addr, u32), PendingPing>, // (destination, echo identifier) -> ping waiting for an answer
+    pub next_echo_id: u32,
+    pub command_receiver: Receiver<Command>,
+    pub link_sender: Sender<LinkMessage>,
+    pub link_receiver: Receiver<LinkMessage>,
+    pub next_link: u64,
+    pub timers: TimerConfig,
+    pub logger: Logger
+}
+
+impl Host{
+
+    // the mac address of a host is derived from its ip, it can't collide with the ones of the routers
+    pub fn start(name: String, ip: Ipv4Addr, gateway: Ipv4Addr, timers: TimerConfig, logger: Logger) -> (HostCommunicator, JoinHandle<()>){
+        let (tx_command, rx_command) = channel(1024);
+        let (link_sender, link_receiver) = channel(1024);
+        let mut host = Host{
+            name,
+            ip,
+            gateway,
+            mac_address: MacAddress{id: u32::from(ip)},
+            link: None,
+            arp_mapping: HashMap::new(),
+            pending_pings: HashMap::new(),
+            next_echo_id: 0,
+            command_receiver: rx_command,
+            link_sender,
+            link_receiver,
+            next_link: 0,
+            timers,
+            logger
+        };
+        let task = tokio::spawn(async move {
+            host.run().await;
+        });
+        (HostCommunicator{command_sender: tx_command}, task)
+    }
+
+    pub async fn run(&mut self){
+        let mut arp_refresh = tokio::time::interval(self.timers.arp_refresh_interval);
+        loop{
+            tokio::select!{
+                command = self.command_receiver.recv() => match command{
+                    Some(command) => if self.process_command(command).await{
+                        return;
+                    },
+                    None => return,
+                },
+                Some((port, link, message)) = self.link_receiver.recv() => self.receive_message(port, link, message).await,
+                // announce our address with a gratuitous reply, so that the routers can reach us, and resolve the gateway
+                _ = arp_refresh.tick() => {
+                    self.send(Message::Arp(ARPMessage::Reply(self.ip, self.mac_address.clone()))).await;
+                    self.send(Message::Arp(ARPMessage::Request(self.gateway))).await;
+                },
+            }
+            self.check_ping_timeouts().await;
+        }
+    }
+
+    pub async fn process_command(&mut self, command: Command) -> bool{
+        match command{
+            Command::AddLink(receiver, sender, port, _) => {
+                let link = self.next_link;
+                self.next_link += 1;
+                forward_link(receiver, port, link, self.link_sender.clone());
+                self.logger.log(Source::DEBUG, format!("Host {} received adding link", self.name)).await;
+                self.link = Some((port, link, sender));
+                false
+            },
+            Command::RemoveLink(port) => {
+                self.logger.log(Source::DEBUG, format!("Host {} removing link on port {}", self.name, port)).await;
+                if self.link.as_ref().is_some_and(|(p, _, _)| *p == port){
+                    self.link = None;
+                    self.arp_mapping.clear();
+                }
+                false
+            },
+            Command::Ping(dest, replier) => {
+                self.send_ping(dest, replier).await;
+                false
+            },
+            Command::Quit => true,
+            _ => panic!("Command not supported on host"),
+        }
+    }
+
+    pub async fn receive_message(&mut self, port: u32, link: u64, message: Message){
+        if self.link.as_ref().is_none_or(|(p, l, _)| *p != port || *l != link){
+            // the link was removed
+            return;
+        }
+        match message{
+            Message::EthernetFrame(_, mac, ip) if mac == self.mac_address && ip.dest == self.ip => self.process_ip(ip).await,
+            Message::Arp(ARPMessage::Request(ip)) if ip == self.ip => {
+                self.send(Message::Arp(ARPMessage::Reply(self.ip, self.mac_address.clone()))).await;
+            },
+            Message::Arp(ARPMessage::Reply(ip, mac)) => {
+                self.arp_mapping.insert(ip, mac);
+            },
+            // the other frames and the messages of the routing protocols are for other devices of the LAN
+            _ => (),
+        }
+    }
+
+    async fn process_ip(&mut self, ip_packet: IP){
+        match ip_packet.content{
+            Content::Ping(id, seq, route) => {
+                self.logger.log(Source::PING, format!("Host {} received ping from {}", self.name, ip_packet.src)).await;
+                let route = route.map(|mut forward| {
+                    forward.push(self.ip);
+                    (forward, vec![self.ip])
+                });
+                self.send_ip(IP{src: self.ip, dest: ip_packet.src, ttl: DEFAULT_TTL, content: Content::Pong(id, seq, route), visited: vec![]}).await;
+            },
+            Content::Pong(id, _, _) => {
+                self.logger.log(Source::PING, format!("Host {} received ping back from {}", self.name, ip_packet.src)).await;
+                if let Some((sent, replier)) = self.pending_pings.remove(&(ip_packet.src, id)){
+                    // the caller may have stopped waiting for the answer
+                    let _ = replier.send(Ok(sent.elapsed().unwrap_or_default()));
+                }
+            },
+            _ => (),
+        }
+    }
+
+    async fn send_ping(&mut self, dest: Ipv4Addr, replier: oneshot::Sender<Result<Duration, PingError>>){
+        let id = self.next_echo_id;
+        self.next_echo_id += 1;
+        self.pending_pings.insert((dest, id), (SystemTime::now(), replier));
+        self.logger.log(Source::PING, format!("Host {} sending ping message to {}", self.name, dest)).await;
+        self.send_ip(IP{src: self.ip, dest, ttl: DEFAULT_TTL, content: Content::Ping(id, 0, None), visited: vec![]}).await;
+    }
+
+    // every packet goes through the gateway, it is dropped while the gateway isn't resolved
+    async fn send_ip(&self, packet: IP){
+        if let Some(mac) = self.arp_mapping.get(&self.gateway){
+            self.send(Message::EthernetFrame(self.mac_address.clone(), mac.clone(), packet)).await;
+        }
+    }
+
+    async fn send(&self, message: Message){
+        if let Some((_, _, sender)) = &self.link{
+            sender.send(message).await;
+        }
+    }
+
+    async fn check_ping_timeouts(&mut self){
+        let expired: Vec<(Ipv4Addr, u32)> = self.pending_pings.iter()
+            .filter(|(_, (sent, _))| sent.elapsed().unwrap_or_default() > PING_TIMEOUT)
+            .map(|(key, _)| *key)
+            .collect();
+        for (dest, id) in expired{
+            let (_, replier) = self.pending_pings.remove(&(dest, id)).unwrap();
+            self.logger.log(Source::PING, format!("Ping to {} timed out", dest)).await;
+            let _ = replier.send(Err(PingError::Timeout(dest)));
+        }
+    }
+}
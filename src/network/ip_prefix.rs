@@ -1,17 +1,57 @@
 use std::{fmt::{Display, Error}, net::Ipv4Addr, str::FromStr};
 
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
 #[derive(Debug, PartialEq, Clone, Eq, Hash, Copy, Ord, PartialOrd)]
 pub struct IPPrefix{
     pub ip: Ipv4Addr,
     pub prefix_len: u32,
 }
 
+impl IPPrefix{
+    // the host bits of ip are cleared, "10.0.0.7/24" and "10.0.0.0/24" are the same prefix
+    pub fn new(ip: Ipv4Addr, prefix_len: u32) -> IPPrefix{
+        IPPrefix{ip: Ipv4Addr::from(u32::from(ip) & Self::mask(prefix_len)), prefix_len}
+    }
+
+    fn mask(prefix_len: u32) -> u32{
+        u32::MAX.checked_shl(32 - prefix_len).unwrap_or(0)
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool{
+        let mask = Self::mask(self.prefix_len);
+        u32::from(ip) & mask == u32::from(self.ip) & mask
+    }
+
+    // other is this prefix or one of its more specific prefixes
+    pub fn contains_prefix(&self, other: &IPPrefix) -> bool{
+        self.prefix_len <= other.prefix_len && self.contains(other.ip)
+    }
+
+    pub fn overlaps(&self, other: &IPPrefix) -> bool{
+        self.contains_prefix(other) || other.contains_prefix(self)
+    }
+}
+
 impl Display for IPPrefix{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}/{}", self.ip, self.prefix_len)
     }
 }
 
+impl Serialize for IPPrefix{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IPPrefix{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| de::Error::custom(format!("invalid prefix {}", s)))
+    }
+}
+
 impl FromStr for IPPrefix{
     type Err = Error;
 
@@ -39,6 +79,67 @@ impl FromStr for IPPrefix{
             return Err(Error);
         }
 
-        Ok(IPPrefix{ip, prefix_len})
+        Ok(IPPrefix::new(ip, prefix_len))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::IPPrefix;
+
+    #[test]
+    fn test_host_bits_cleared() {
+        let prefix: IPPrefix = "10.0.0.7/24".parse().unwrap();
+        assert_eq!(prefix, "10.0.0.0/24".parse().unwrap());
+        assert_eq!(prefix.to_string(), "10.0.0.0/24");
+        assert_eq!(IPPrefix::new("10.0.0.7".parse().unwrap(), 24), prefix);
+
+        assert_eq!("10.0.0.7/0".parse::<IPPrefix>().unwrap().ip, Ipv4Addr::new(0, 0, 0, 0));
+        assert_eq!("200.0.0.7/1".parse::<IPPrefix>().unwrap().ip, Ipv4Addr::new(128, 0, 0, 0));
+        assert_eq!("10.0.0.7/31".parse::<IPPrefix>().unwrap().ip, Ipv4Addr::new(10, 0, 0, 6));
+        assert_eq!("10.0.0.7/32".parse::<IPPrefix>().unwrap().ip, Ipv4Addr::new(10, 0, 0, 7));
+        assert!("10.0.0.7/33".parse::<IPPrefix>().is_err());
+    }
+
+    #[test]
+    fn test_contains() {
+        let prefix: IPPrefix = "192.0.2.0/25".parse().unwrap();
+        assert!(prefix.contains("192.0.2.0".parse().unwrap()));
+        assert!(prefix.contains("192.0.2.127".parse().unwrap()));
+        assert!(!prefix.contains("192.0.2.128".parse().unwrap()));
+        assert!(!prefix.contains("10.0.2.1".parse().unwrap()));
+
+        let default: IPPrefix = "0.0.0.0/0".parse().unwrap();
+        assert!(default.contains("10.0.2.1".parse().unwrap()));
+
+        let host: IPPrefix = "192.0.2.1/32".parse().unwrap();
+        assert!(host.contains("192.0.2.1".parse().unwrap()));
+        assert!(!host.contains("192.0.2.0".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_contains_prefix() {
+        let prefix: IPPrefix = "192.0.2.0/24".parse().unwrap();
+        assert!(prefix.contains_prefix(&prefix));
+        assert!(prefix.contains_prefix(&"192.0.2.128/25".parse().unwrap()));
+        assert!(prefix.contains_prefix(&"192.0.2.3/32".parse().unwrap()));
+        assert!(!prefix.contains_prefix(&"192.0.0.0/16".parse().unwrap()));
+        assert!(!prefix.contains_prefix(&"192.0.3.0/24".parse().unwrap()));
+
+        let default: IPPrefix = "0.0.0.0/0".parse().unwrap();
+        assert!(default.contains_prefix(&prefix));
+        assert!(!prefix.contains_prefix(&default));
+        assert!("128.0.0.0/1".parse::<IPPrefix>().unwrap().contains_prefix(&prefix));
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let prefix: IPPrefix = "192.0.2.0/24".parse().unwrap();
+        assert!(prefix.overlaps(&"192.0.0.0/16".parse().unwrap()));
+        assert!(prefix.overlaps(&"192.0.2.64/26".parse().unwrap()));
+        assert!(prefix.overlaps(&"0.0.0.0/0".parse().unwrap()));
+        assert!(!prefix.overlaps(&"192.0.3.0/24".parse().unwrap()));
+        assert!(!"192.0.2.0/31".parse::<IPPrefix>().unwrap().overlaps(&"192.0.2.2/31".parse().unwrap()));
     }
-}
\ No newline at end of file
+}
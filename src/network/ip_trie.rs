@@ -5,141 +5,204 @@ use super::ip_prefix::IPPrefix;
 
 type Child<K> = Arc<IPTrieNode<K>>;
 
+// a node only exists where a prefix is stored or where the paths of two prefixes split,
+// the bits skipped between a node and its child are the ones of the child's key
 #[derive(Debug)]
 struct IPTrieNode<K: Clone> {
+    key: u32,      // bits of the prefix, the host bits are cleared
+    len: u32,      // length of the prefix
     data: Option<K>,
     left: Option<Child<K>>,
     right: Option<Child<K>>,
 }
 
+impl<K: Clone> IPTrieNode<K> {
+    fn leaf(key: u32, len: u32, data: Option<K>) -> Child<K> {
+        Arc::new(IPTrieNode { key, len, data, left: None, right: None })
+    }
+
+    fn contains(&self, key: u32, len: u32) -> bool {
+        self.len <= len && (key & mask(self.len)) == self.key
+    }
+
+    fn child(&self, key: u32) -> Option<&Child<K>> {
+        if bit(key, self.len) { self.right.as_ref() } else { self.left.as_ref() }
+    }
+
+    // copy of the node with child as the branch taken by key
+    fn with_child(&self, key: u32, child: Option<Child<K>>) -> Child<K> {
+        let (left, right) = if bit(key, self.len) {
+            (self.left.clone(), child)
+        } else {
+            (child, self.right.clone())
+        };
+        Arc::new(IPTrieNode { key: self.key, len: self.len, data: self.data.clone(), left, right })
+    }
+
+    fn with_data(&self, data: Option<K>) -> Child<K> {
+        Arc::new(IPTrieNode { key: self.key, len: self.len, data, left: self.left.clone(), right: self.right.clone() })
+    }
+}
+
+fn mask(len: u32) -> u32 {
+    if len == 0 { 0 } else { u32::MAX << (32 - len) }
+}
+
+// bit idx of key, from the most significant one
+fn bit(key: u32, idx: u32) -> bool {
+    (key >> (31 - idx)) & 1 == 1
+}
+
 #[derive(Debug)]
 pub struct IPTrie<K: Clone> {
-    root: Option<Child<K>>,
+    root: Child<K>, // the node of 0.0.0.0/0, always present
+    removals: usize, // removals since the trie was last rebuilt
 }
 
 impl<K: Clone> IPTrie<K> {
     pub fn new() -> IPTrie<K> {
-        IPTrie { root: Some(Arc::new(IPTrieNode{data: None, left: None, right: None})) }
+        IPTrie { root: IPTrieNode::leaf(0, 0, None), removals: 0 }
     }
 
-    fn bits(&self, ip: Ipv4Addr) -> Vec<bool> {
-        let mut bits = vec![];
-        for byte in ip.octets() {
-            let mut mask = 1 << 7;
-            while mask > 0 {
-                bits.push((byte & mask) != 0);
-                mask = mask >> 1;
+    pub fn insert(&mut self, prefix: IPPrefix, data: K) {
+        let key = u32::from(prefix.ip) & mask(prefix.prefix_len);
+
+        self.root = Self::insert_node(&self.root, key, prefix.prefix_len, data);
+    }
+
+    // node contains the prefix, the nodes on the path to it are copied
+    fn insert_node(node: &Child<K>, key: u32, len: u32, data: K) -> Child<K> {
+        if node.len == len {
+            return node.with_data(Some(data));
+        }
+        let child = match node.child(key) {
+            None => IPTrieNode::leaf(key, len, Some(data)),
+            Some(c) if c.contains(key, len) => Self::insert_node(c, key, len, data),
+            Some(c) => {
+                // the prefix and the child split after their common bits
+                let common = u32::min((key ^ c.key).leading_zeros(), u32::min(len, c.len));
+                let split = IPTrieNode::leaf(key & mask(common), common, None);
+                let split = split.with_child(c.key, Some(c.clone()));
+                if common == len {
+                    split.with_data(Some(data))
+                } else {
+                    split.with_child(key, Some(IPTrieNode::leaf(key, len, Some(data))))
+                }
             }
+        };
+        node.with_child(key, Some(child))
+    }
+
+    // the nodes of a removed prefix are kept, they are only freed when the trie is compacted
+    pub fn remove(&mut self, prefix: IPPrefix) -> Option<K> {
+        let key = u32::from(prefix.ip) & mask(prefix.prefix_len);
+        let (root, data) = Self::remove_node(&self.root, key, prefix.prefix_len);
+        if data.is_some(){
+            self.root = root;
+            self.removals += 1;
         }
-        bits
+        data
     }
 
-    pub fn insert(&mut self, prefix: IPPrefix, data: K) {
-        let bits = self.bits(prefix.ip);
-
-        self.root = Self::insert_node(self.root.clone(), bits, 0, prefix.prefix_len, data);
-    }
-
-    fn insert_node(
-        node: Option<Child<K>>,
-        bits: Vec<bool>,
-        idx: u32,
-        prefix_len: u32,
-        data: K,
-    ) -> Option<Child<K>> {
-        if idx == prefix_len{
-            match node {
-                Some(n) => Some(Arc::new(IPTrieNode {
-                    data: Some(data),
-                    left: n.left.clone(),
-                    right: n.right.clone(),
-                })),
-                None => Some(Arc::new(IPTrieNode {
-                    data: Some(data),
-                    left: None,
-                    right: None,
-                })),
+    fn remove_node(node: &Child<K>, key: u32, len: u32) -> (Child<K>, Option<K>) {
+        if node.len == len {
+            return (node.with_data(None), node.data.clone());
+        }
+        match node.child(key) {
+            Some(c) if c.contains(key, len) => {
+                let (child, data) = Self::remove_node(c, key, len);
+                (node.with_child(key, Some(child)), data)
             }
-        } else {
-            match node {
-                Some(n) => {
-                    if bits[idx as usize] {
-                        Some(Arc::new(IPTrieNode {
-                            data: n.data.clone(),
-                            left: n.left.clone(),
-                            right: Self::insert_node(
-                                n.right.clone(),
-                                bits,
-                                idx + 1,
-                                prefix_len,
-                                data,
-                            ),
-                        }))
-                    } else {
-                        Some(Arc::new(IPTrieNode {
-                            data: n.data.clone(),
-                            left: Self::insert_node(
-                                n.left.clone(),
-                                bits,
-                                idx + 1,
-                                prefix_len,
-                                data,
-                            ),
-                            right: n.right.clone(),
-                        }))
-                    }
-                }
-                None => {
-                    if bits[idx as usize] {
-                        Some(Arc::new(IPTrieNode {
-                            data: None,
-                            left: None,
-                            right: Self::insert_node(None, bits, idx + 1, prefix_len, data),
-                        }))
-                    } else {
-                        Some(Arc::new(IPTrieNode {
-                            data: None,
-                            left: Self::insert_node(None, bits, idx + 1, prefix_len, data),
-                            right: None,
-                        }))
-                    }
-                }
+            _ => (node.clone(), None),
+        }
+    }
+
+    // rebuild the trie from its prefixes, dropping the empty nodes left by the removals
+    pub fn compact(&mut self) {
+        let mut trie = IPTrie::new();
+        for (prefix, data) in self.iter() {
+            trie.insert(prefix, data.clone());
+        }
+        self.root = trie.root;
+        self.removals = 0;
+    }
+
+    // compact the trie once enough prefixes were removed since the last compaction
+    pub fn compact_after(&mut self, removals: usize) -> bool {
+        if self.removals < removals{
+            return false;
+        }
+        self.compact();
+        true
+    }
+
+    // the prefixes in order, a prefix comes before the more specific ones it contains
+    pub fn iter(&self) -> impl Iterator<Item = (IPPrefix, &K)> {
+        let mut entries = vec![];
+        let mut stack = vec![&self.root];
+        while let Some(n) = stack.pop() {
+            if let Some(data) = &n.data {
+                entries.push((IPPrefix::new(Ipv4Addr::from(n.key), n.len), data));
             }
+            stack.extend(n.right.as_ref());
+            stack.extend(n.left.as_ref());
         }
+        entries.into_iter()
     }
 
-    pub fn longest_match(&self, ip: Ipv4Addr) -> Option<K> {
-        let bits = self.bits(ip);
-        let mut data = None;
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack = vec![&self.root];
+        while let Some(n) = stack.pop() {
+            count += 1;
+            stack.extend(n.left.as_ref());
+            stack.extend(n.right.as_ref());
+        }
+        count
+    }
 
-        let mut curr = self.root.clone(); // clone a rc, cheap
+    pub fn longest_match(&self, ip: Ipv4Addr) -> Option<K> {
+        self.longest_match_entry(ip).map(|(_, data)| data)
+    }
 
-        let mut idx = 0;
-        while curr.is_some(){
-            let n = curr.unwrap();
+    // like longest_match, with the prefix that matched ip
+    pub fn longest_match_entry(&self, ip: Ipv4Addr) -> Option<(IPPrefix, K)> {
+        let key = u32::from(ip);
+        let mut entry = None;
 
+        let mut curr = Some(&self.root);
+        while let Some(n) = curr.filter(|n| n.contains(key, 32)) {
             if let Some(p) = &n.data {
-                data = Some(p.clone());
+                entry = Some((n, p));
             }
+            curr = if n.len == 32 { None } else { n.child(key) };
+        }
+        entry.map(|(n, data)| (IPPrefix::new(Ipv4Addr::from(n.key), n.len), data.clone()))
+    }
 
-            if idx == 32{
-                break;
-            }
+    // data of every prefix containing ip, from the longest prefix to the shortest
+    pub fn matches(&self, ip: Ipv4Addr) -> Vec<K> {
+        let key = u32::from(ip);
+        let mut data = vec![];
 
-            if bits[idx] {
-                curr = n.right.clone();
-            } else {
-                curr = n.left.clone();
+        let mut curr = Some(&self.root);
+        while let Some(n) = curr.filter(|n| n.contains(key, 32)) {
+            if let Some(p) = &n.data {
+                data.push(p.clone());
             }
-
-            idx += 1;
+            curr = if n.len == 32 { None } else { n.child(key) };
         }
+        data.reverse();
         data
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::net::Ipv4Addr;
+
+    use crate::network::ip_prefix::IPPrefix;
+
     use super::IPTrie;
 
     #[test]
@@ -179,4 +242,167 @@ mod tests {
         assert_eq!(trie.longest_match("11.0.0.64".parse().unwrap()), Some(5));
         assert_eq!(trie.longest_match("47.0.0.64".parse().unwrap()), Some(5));
     }
+
+    #[test]
+    fn test_matches() {
+
+        let mut trie = IPTrie::new();
+
+        trie.insert("10.0.0.0/8".parse().unwrap(), 1);
+        trie.insert("10.0.1.0/24".parse().unwrap(), 2);
+        trie.insert("10.0.1.0/25".parse().unwrap(), 3);
+
+        assert_eq!(trie.matches("10.0.1.1".parse().unwrap()), vec![3, 2, 1]);
+        assert_eq!(trie.matches("10.0.1.200".parse().unwrap()), vec![2, 1]);
+        assert!(trie.matches("11.0.1.1".parse().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_remove() {
+
+        let mut trie = IPTrie::new();
+
+        trie.insert("10.0.0.0/24".parse().unwrap(), 1);
+        trie.insert("10.0.0.128/25".parse().unwrap(), 2);
+
+        assert_eq!(trie.remove("10.0.0.128/25".parse().unwrap()), Some(2));
+        assert_eq!(trie.remove("10.0.0.128/25".parse().unwrap()), None);
+        assert_eq!(trie.longest_match("10.0.0.164".parse().unwrap()), Some(1));
+        assert_eq!(trie.iter().collect::<Vec<_>>(), vec![("10.0.0.0/24".parse().unwrap(), &1)]);
+    }
+
+    #[test]
+    fn test_path_compression() {
+
+        let mut trie = IPTrie::new();
+
+        trie.insert("10.0.0.1/32".parse().unwrap(), 1);
+        assert_eq!(trie.node_count(), 2);
+        // the paths split after 10.0.0.0/31
+        trie.insert("10.0.0.0/32".parse().unwrap(), 2);
+        assert_eq!(trie.node_count(), 4);
+        trie.insert("10.0.0.0/31".parse().unwrap(), 3);
+        assert_eq!(trie.node_count(), 4);
+
+        assert_eq!(trie.matches("10.0.0.1".parse().unwrap()), vec![1, 3]);
+        assert_eq!(trie.matches("10.0.0.0".parse().unwrap()), vec![2, 3]);
+        assert!(trie.matches("10.0.0.2".parse().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_remove_keeps_more_specific() {
+
+        let mut trie = IPTrie::new();
+
+        trie.insert("10.0.0.0/8".parse().unwrap(), 1);
+        trie.insert("10.0.0.0/16".parse().unwrap(), 2);
+        trie.insert("10.0.1.0/24".parse().unwrap(), 3);
+
+        assert_eq!(trie.remove("10.0.0.0/16".parse().unwrap()), Some(2));
+        assert_eq!(trie.longest_match("10.0.1.1".parse().unwrap()), Some(3));
+        // the addresses of the removed prefix fall back to the covering one
+        assert_eq!(trie.longest_match("10.0.2.1".parse().unwrap()), Some(1));
+        assert_eq!(trie.longest_match_entry("10.0.2.1".parse().unwrap()), Some(("10.0.0.0/8".parse().unwrap(), 1)));
+
+        assert_eq!(trie.remove("10.0.0.0/8".parse().unwrap()), Some(1));
+        assert_eq!(trie.longest_match("10.0.1.1".parse().unwrap()), Some(3));
+        assert_eq!(trie.longest_match("10.0.2.1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_iter() {
+
+        let mut trie = IPTrie::new();
+
+        trie.insert("10.0.1.0/24".parse().unwrap(), 3);
+        trie.insert("192.168.0.0/16".parse().unwrap(), 4);
+        trie.insert("10.0.0.0/8".parse().unwrap(), 1);
+        trie.insert("0.0.0.0/0".parse().unwrap(), 0);
+        trie.insert("10.0.0.0/24".parse().unwrap(), 2);
+
+        let prefixes: Vec<(IPPrefix, &i32)> = trie.iter().collect();
+        assert_eq!(prefixes, vec![
+            ("0.0.0.0/0".parse().unwrap(), &0),
+            ("10.0.0.0/8".parse().unwrap(), &1),
+            ("10.0.0.0/24".parse().unwrap(), &2),
+            ("10.0.1.0/24".parse().unwrap(), &3),
+            ("192.168.0.0/16".parse().unwrap(), &4),
+        ]);
+        for (i, (prefix, _)) in prefixes.iter().enumerate() {
+            assert!(prefixes[i + 1..].iter().all(|(other, _)| !other.contains_prefix(prefix) || other == prefix));
+        }
+        assert!(IPTrie::<u32>::new().iter().next().is_none());
+    }
+
+    #[test]
+    fn test_longest_match_entry() {
+
+        let mut trie = IPTrie::new();
+
+        trie.insert("0.0.0.0/0".parse().unwrap(), 0);
+        trie.insert("10.0.0.128/25".parse().unwrap(), 1);
+        trie.insert("10.0.0.5/32".parse().unwrap(), 2);
+
+        assert_eq!(trie.longest_match_entry("10.0.0.200".parse().unwrap()), Some(("10.0.0.128/25".parse().unwrap(), 1)));
+        assert_eq!(trie.longest_match_entry("10.0.0.5".parse().unwrap()), Some(("10.0.0.5/32".parse().unwrap(), 2)));
+        assert_eq!(trie.longest_match_entry("11.0.0.1".parse().unwrap()), Some(("0.0.0.0/0".parse().unwrap(), 0)));
+        assert_eq!(IPTrie::<u32>::new().longest_match_entry("11.0.0.1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_compact_after_churn() {
+
+        let mut trie = IPTrie::new();
+        trie.insert("10.0.0.0/24".parse().unwrap(), 0);
+        trie.insert("0.0.0.0/0".parse().unwrap(), 0);
+        let baseline = trie.node_count();
+
+        let mut compactions = 0;
+        for i in 0..10_000u32 {
+            let prefix = IPPrefix{ip: Ipv4Addr::from(0x0b00_0000 + (i << 8)), prefix_len: 24};
+            trie.insert(prefix, i);
+            assert_eq!(trie.remove(prefix), Some(i));
+            if trie.compact_after(1024) {
+                compactions += 1;
+            }
+        }
+        assert_eq!(compactions, 9);
+        assert!(trie.node_count() > baseline);
+
+        trie.compact();
+        assert_eq!(trie.node_count(), baseline);
+        assert_eq!(trie.longest_match("10.0.0.1".parse().unwrap()), Some(0));
+        assert_eq!(trie.longest_match("11.0.0.1".parse().unwrap()), Some(0));
+    }
+
+    // 10k random /24s and 1M lookups, run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn test_lookup_benchmark() {
+        // xorshift, the prefixes are the same on every run
+        let mut state = 0x2545_f491u32;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+
+        let mut trie = IPTrie::new();
+        let start = std::time::Instant::now();
+        for i in 0..10_000u32 {
+            trie.insert(IPPrefix{ip: Ipv4Addr::from(random() & 0xffff_ff00), prefix_len: 24}, i);
+        }
+        let inserted = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let mut found = 0;
+        for _ in 0..1_000_000 {
+            found += trie.longest_match(Ipv4Addr::from(random())).is_some() as u32;
+        }
+        let looked_up = start.elapsed();
+        println!("{} nodes, inserts in {:?}, lookups in {:?}, {} matches", trie.node_count(), inserted, looked_up, found);
+        // at most one node per prefix and one where two paths split, against up to 24 per prefix without compression
+        assert!(trie.node_count() < 2 * 10_000 + 1);
+    }
 }
@@ -0,0 +1,24 @@
+// Bounds on the collections that grow during a run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits{
+    pub max_received_lsp: usize,      // originators whose newest LSP a router remembers
+    pub max_routes_per_prefix: usize, // BGP routes kept for a single prefix
+    pub trie_compact_after: usize     // prefixes removed from a prefix trie before it is rebuilt
+}
+
+impl Default for Limits{
+    fn default() -> Self {
+        Limits{max_received_lsp: 1024, max_routes_per_prefix: 64, trie_compact_after: 1024}
+    }
+}
+
+// Size of the bounded collections of a router, and how many entries were evicted to respect the limits
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MemoryStats{
+    pub received_lsp: usize,
+    pub bgp_routes: usize,
+    pub lsp_evictions: u64,
+    pub route_evictions: u64,
+    pub ospf_trie_nodes: usize,
+    pub bgp_trie_nodes: usize
+}
@@ -0,0 +1,400 @@
+use std::{collections::VecDeque, fmt::Display, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc, Mutex}, time::Duration};
+use tokio::{sync::mpsc::{channel, error::TrySendError, Receiver, Sender}, time::Instant};
+
+use serde::Deserialize;
+use strum_macros::EnumIter;
+
+use super::{capture::{CaptureSlot, Direction}, logger::{Logger, Source}, messages::{Message, TrafficClass}, stats::SharedStats, utils::Rng};
+
+// messages waiting for the tokens of a rate limited link when the config doesn't give its queue depth
+pub const DEFAULT_QUEUE_DEPTH: usize = 64;
+
+// extra delay of a reordered message, the messages sent after it overtake it
+pub const REORDER_DELAY: Duration = Duration::from_millis(10);
+
+// Faults of a link, drawn independently for each message. The draws only depend on the seed,
+// so that a run with the same messages loses the same ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkImpairment{
+    pub loss_prob: f64,
+    pub reorder_prob: f64,
+    pub duplicate_prob: f64,
+    pub seed: u64
+}
+
+impl LinkImpairment{
+    pub fn is_valid(&self) -> bool{
+        [self.loss_prob, self.reorder_prob, self.duplicate_prob].iter().all(|p| (0.0..=1.0).contains(p))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkRate{
+    MessagesPerSec(u64),
+    BytesPerSec(u64) // of the messages as they are printed in the logs
+}
+
+// Order in which the messages waiting for the tokens of a link are sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, EnumIter)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueDiscipline{
+    #[default]
+    Fifo, // one queue, in the order the messages were sent
+    Priority // one queue per traffic class, the control messages go before the data ones
+}
+
+impl Display for QueueDiscipline{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            QueueDiscipline::Fifo => write!(f, "fifo"),
+            QueueDiscipline::Priority => write!(f, "priority"),
+        }
+    }
+}
+
+// Token bucket of a link, holding one second of traffic. The messages waiting for tokens
+// above queue_depth are dropped, in each class of traffic with the priority discipline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit{
+    pub rate: LinkRate,
+    pub queue_depth: usize,
+    pub discipline: QueueDiscipline
+}
+
+impl RateLimit{
+    pub fn is_valid(&self) -> bool{
+        self.capacity() > 0.0 && self.queue_depth > 0
+    }
+
+    fn capacity(&self) -> f64{
+        match self.rate{
+            LinkRate::MessagesPerSec(rate) | LinkRate::BytesPerSec(rate) => rate as f64,
+        }
+    }
+
+    fn cost(&self, message: &Message) -> f64{
+        match self.rate{
+            LinkRate::MessagesPerSec(_) => 1.0,
+            LinkRate::BytesPerSec(_) => message.size() as f64,
+        }
+    }
+
+    fn has_room(&self, queue: &VecDeque<Message>, message: &Message) -> bool{
+        match self.discipline{
+            QueueDiscipline::Fifo => queue.len() < self.queue_depth,
+            QueueDiscipline::Priority => queue.iter().filter(|queued| queued.class() == message.class()).count() < self.queue_depth,
+        }
+    }
+}
+
+// next message of the queue to send, without rate limit the queue is emptied in order
+fn dequeue(rate_limit: Option<RateLimit>, queue: &mut VecDeque<Message>) -> Option<Message>{
+    match rate_limit.map(|r| r.discipline){
+        Some(QueueDiscipline::Priority) => {
+            let position = queue.iter().position(|message| message.class() == TrafficClass::Control).unwrap_or(0);
+            queue.remove(position)
+        },
+        Some(QueueDiscipline::Fifo) | None => queue.pop_front(),
+    }
+}
+
+// Messages of a link altered by its impairment or dropped by its queue, in both directions
+// the messages of each class waiting for the tokens of the link and dropped as their queue was full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkStats{
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub tx_dropped: u64,
+    pub control_queued: u64,
+    pub data_queued: u64,
+    pub control_queue_dropped: u64,
+    pub data_queue_dropped: u64
+}
+
+enum Fate{
+    Dropped,
+    Delivered{copies: usize, delay: Duration}
+}
+
+// State of one direction of a link, shared between the network, the device sending on it and the task carrying its messages
+#[derive(Debug)]
+pub(crate) struct LinkControl{
+    from: (String, u32), // device and port sending on this direction
+    to: (String, u32),
+    impairment: Mutex<Option<(LinkImpairment, Rng)>>,
+    rate_limit: Mutex<Option<RateLimit>>,
+    dropped: AtomicU64,
+    duplicated: AtomicU64,
+    reordered: AtomicU64,
+    tx_dropped: AtomicU64,
+    queued: [AtomicU64; 2], // control, data
+    queue_dropped: [AtomicU64; 2],
+    shut_down: AtomicBool, // the port of the sending device was shut down by the administrator
+    capture: CaptureSlot,
+    logger: Logger
+}
+
+impl LinkControl{
+    pub fn new(from: (String, u32), to: (String, u32), capture: CaptureSlot, logger: Logger) -> LinkControl{
+        LinkControl{
+            from,
+            to,
+            impairment: Mutex::new(None),
+            rate_limit: Mutex::new(None),
+            dropped: AtomicU64::new(0),
+            duplicated: AtomicU64::new(0),
+            reordered: AtomicU64::new(0),
+            tx_dropped: AtomicU64::new(0),
+            queued: Default::default(),
+            queue_dropped: Default::default(),
+            shut_down: AtomicBool::new(false),
+            capture,
+            logger
+        }
+    }
+
+    // none sends the messages as soon as they are received, the queued ones included
+    pub fn set_rate_limit(&self, rate_limit: Option<RateLimit>){
+        *self.rate_limit.lock().unwrap() = rate_limit;
+    }
+
+    // the messages are recorded as sent by the device at the start of the link and received by the one at its end
+    fn capture(&self, direction: Direction, message: &Message){
+        if let Some(capture) = self.capture.lock().unwrap().as_ref(){
+            let (device, port) = match direction{
+                Direction::Sent => &self.from,
+                Direction::Received => &self.to,
+            };
+            capture.record(device, *port, direction, message);
+        }
+    }
+
+    fn rate_limit(&self) -> Option<RateLimit>{
+        *self.rate_limit.lock().unwrap()
+    }
+
+    fn set_queued(&self, queue: &VecDeque<Message>){
+        let mut queued = [0; 2];
+        for message in queue{
+            queued[class_index(message.class())] += 1;
+        }
+        for (counter, count) in self.queued.iter().zip(queued){
+            counter.store(count, Ordering::Relaxed);
+        }
+    }
+
+    async fn queue_drop(&self, message: &Message){
+        self.queue_dropped[class_index(message.class())].fetch_add(1, Ordering::Relaxed);
+        self.tx_drop("its queue is full").await;
+    }
+
+    async fn tx_drop(&self, reason: &str){
+        self.tx_dropped.fetch_add(1, Ordering::Relaxed);
+        self.logger.log_from(Source::LINK, &self.from.0, format!("Link {}:{} -> {}:{} dropped a message, {}", self.from.0, self.from.1, self.to.0, self.to.1, reason)).await;
+    }
+
+    // the draws start again from the seed, none removes the faults
+    pub fn set_impairment(&self, impairment: Option<LinkImpairment>){
+        *self.impairment.lock().unwrap() = impairment.map(|i| (i, Rng(i.seed)));
+    }
+
+    pub fn stats(&self) -> LinkStats{
+        LinkStats{
+            dropped: self.dropped.load(Ordering::Relaxed),
+            duplicated: self.duplicated.load(Ordering::Relaxed),
+            reordered: self.reordered.load(Ordering::Relaxed),
+            tx_dropped: self.tx_dropped.load(Ordering::Relaxed),
+            control_queued: self.queued[0].load(Ordering::Relaxed),
+            data_queued: self.queued[1].load(Ordering::Relaxed),
+            control_queue_dropped: self.queue_dropped[0].load(Ordering::Relaxed),
+            data_queue_dropped: self.queue_dropped[1].load(Ordering::Relaxed)
+        }
+    }
+
+    fn draw(&self) -> Fate{
+        let mut impairment = self.impairment.lock().unwrap();
+        let Some((impairment, rng)) = impairment.as_mut() else {
+            return Fate::Delivered{copies: 1, delay: Duration::ZERO};
+        };
+        // always three draws, so that the fate of a message doesn't shift the ones of the next messages
+        let (lost, reordered, duplicated) = (rng.next_f64() < impairment.loss_prob, rng.next_f64() < impairment.reorder_prob, rng.next_f64() < impairment.duplicate_prob);
+        if lost{
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Fate::Dropped;
+        }
+        if reordered{
+            self.reordered.fetch_add(1, Ordering::Relaxed);
+        }
+        if duplicated{
+            self.duplicated.fetch_add(1, Ordering::Relaxed);
+        }
+        Fate::Delivered{copies: if duplicated { 2 } else { 1 }, delay: if reordered { REORDER_DELAY } else { Duration::ZERO }}
+    }
+}
+
+fn class_index(class: TrafficClass) -> usize{
+    match class{
+        TrafficClass::Control => 0,
+        TrafficClass::Data => 1,
+    }
+}
+
+// Sending end of a link. It never waits for the link: the messages that don't fit in its
+// queue are dropped and counted, like on a congested interface
+#[derive(Debug, Clone)]
+pub(crate) struct LinkSender{
+    sender: Sender<Message>,
+    control: Arc<LinkControl>,
+    stats: Option<(u32, SharedStats)> // port and counters of the device sending on the link
+}
+
+impl LinkSender{
+    pub fn new(sender: Sender<Message>, control: Arc<LinkControl>) -> LinkSender{
+        LinkSender{sender, control, stats: None}
+    }
+
+    // the messages sent are counted by the device owning the sender, whichever of its protocols sends them
+    pub fn counted(self, port: u32, stats: &SharedStats) -> LinkSender{
+        LinkSender{stats: Some((port, Arc::clone(stats))), ..self}
+    }
+
+    // a port shut down keeps its link, the messages sent on it are discarded until it is enabled again
+    pub fn set_enabled(&self, enabled: bool){
+        self.control.shut_down.store(!enabled, Ordering::Relaxed);
+    }
+
+    pub async fn send(&self, message: Message){
+        if self.control.shut_down.load(Ordering::Relaxed){
+            return;
+        }
+        if let Some((port, stats)) = &self.stats{
+            stats.lock().unwrap().count(Direction::Sent, *port, &message);
+        }
+        self.control.capture(Direction::Sent, &message);
+        let reason = match self.sender.try_send(message){
+            Ok(()) => return,
+            Err(TrySendError::Full(_)) => "its channel is full",
+            Err(TrySendError::Closed(_)) => "the link is closed",
+        };
+        if let Some((port, stats)) = &self.stats{
+            *stats.lock().unwrap().send_failures.entry(*port).or_default() += 1;
+        }
+        self.control.tx_drop(reason).await;
+    }
+}
+
+// Carry the messages of one direction of a link, received latency after they were sent unless
+// the impairment of the link drops, duplicates or delays them. Each message only waits for its
+// own latency, so the messages sent while others are in flight aren't slowed down. With a rate
+// limit, the messages wait in a queue for the tokens of the link before being sent
+pub(crate) fn carry_link(latency: Duration, control: Arc<LinkControl>) -> (LinkSender, Receiver<Message>){
+    let (link_sender, mut receiver) = channel(1024);
+    let (sender, delivered) = channel(1024);
+    let link_sender = LinkSender::new(link_sender, Arc::clone(&control));
+    tokio::spawn(async move {
+        // waiting for tokens, then in flight sorted by delivery time
+        let mut queue: VecDeque<Message> = VecDeque::new();
+        let mut in_flight: VecDeque<(Instant, Message)> = VecDeque::new();
+        let (mut tokens, mut refilled) = (0.0, Instant::now());
+        let mut limited = false;
+        loop{
+            let rate_limit = control.rate_limit();
+            if let Some(rate_limit) = rate_limit{
+                if !limited{
+                    // a new limit starts with a full bucket
+                    (tokens, limited) = (rate_limit.capacity(), true);
+                }
+                tokens = f64::min(rate_limit.capacity(), tokens + refilled.elapsed().as_secs_f64() * rate_limit.capacity());
+            }else{
+                limited = false;
+            }
+            refilled = Instant::now();
+            // a message is sent as long as the bucket isn't empty, a big message takes the tokens of the next ones
+            while rate_limit.is_none() || tokens > 0.0{
+                let Some(message) = dequeue(rate_limit, &mut queue) else { break };
+                if let Some(rate_limit) = rate_limit{
+                    tokens -= rate_limit.cost(&message);
+                }
+                transmit(&control, message, latency, &mut in_flight);
+            }
+            control.set_queued(&queue);
+            let next = in_flight.front().map(|(deadline, _)| *deadline);
+            // time until the bucket has a token again
+            let next_token = rate_limit.filter(|_| !queue.is_empty()).map(|rate_limit| refilled + Duration::from_secs_f64(-tokens / rate_limit.capacity()));
+            tokio::select!{
+                message = receiver.recv() => match message{
+                    Some(message) => {
+                        if rate_limit.is_none() && in_flight.is_empty() && latency.is_zero(){
+                            // nothing to wait for, the message is sent right away
+                            transmit(&control, message, latency, &mut in_flight);
+                            while in_flight.front().is_some_and(|(deadline, _)| *deadline <= Instant::now()){
+                                let (_, message) = in_flight.pop_front().unwrap();
+                                control.capture(Direction::Received, &message);
+                                if sender.send(message).await.is_err(){
+                                    return;
+                                }
+                            }
+                            continue;
+                        }
+                        match rate_limit{
+                            Some(rate_limit) if !rate_limit.has_room(&queue, &message) => control.queue_drop(&message).await,
+                            _ => queue.push_back(message),
+                        }
+                    },
+                    // the link was removed, the messages in flight are dropped
+                    None => return,
+                },
+                _ = tokio::time::sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                    let (_, message) = in_flight.pop_front().unwrap();
+                    control.capture(Direction::Received, &message);
+                    // like forward_link, the link is closed once the other end stopped reading it
+                    if sender.send(message).await.is_err(){
+                        return;
+                    }
+                },
+                _ = tokio::time::sleep_until(next_token.unwrap_or_else(Instant::now)), if next_token.is_some() => (),
+            }
+        }
+    });
+    (link_sender, delivered)
+}
+
+// put a message on the wire, where the impairment of the link applies
+fn transmit(control: &LinkControl, message: Message, latency: Duration, in_flight: &mut VecDeque<(Instant, Message)>){
+    let Fate::Delivered{copies, delay} = control.draw() else {
+        return;
+    };
+    let deadline = Instant::now() + latency + delay;
+    for _ in 0..copies{
+        let position = in_flight.partition_point(|(d, _)| *d <= deadline);
+        in_flight.insert(position, (deadline, message.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[tokio::test]
+    async fn test_same_seed_same_draws(){
+        let impairment = LinkImpairment{loss_prob: 0.2, reorder_prob: 0.1, duplicate_prob: 0.1, seed: 42};
+        let draws = || {
+            let control = LinkControl::new(("r1".to_string(), 1), ("r2".to_string(), 1), CaptureSlot::default(), Logger::start_test());
+            control.set_impairment(Some(impairment));
+            (0..1000).map(|_| match control.draw(){
+                Fate::Dropped => None,
+                Fate::Delivered{copies, delay} => Some((copies, delay)),
+            }).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(), draws());
+
+        let control = LinkControl::new(("r1".to_string(), 1), ("r2".to_string(), 1), CaptureSlot::default(), Logger::start_test());
+        control.set_impairment(Some(impairment));
+        for _ in 0..10000{
+            control.draw();
+        }
+        let dropped = control.stats().dropped;
+        assert!((1700..2300).contains(&dropped), "{} messages dropped out of 10000", dropped);
+    }
+}
@@ -1,10 +1,14 @@
-use std::{fmt::Display, sync::Arc};
+use std::{collections::HashSet, fmt::Display, io::{BufWriter, Write}, str::FromStr, sync::Arc, time::{Duration, SystemTime, UNIX_EPOCH}};
 
-use log::info;
+use log::{Level, LevelFilter, Log, Record};
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
-use tokio::sync::{mpsc::{channel, Receiver, Sender}, Mutex};
+use tokio::sync::{mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender}, oneshot, Mutex, RwLock};
 
-#[derive(EnumIter, PartialEq, Eq, Clone)]
+use super::logparse::LogRecord;
+
+#[derive(EnumIter, Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub enum Source{
     OSPF,
     SPT,
@@ -12,7 +16,8 @@ pub enum Source{
     DEBUG,
     IP,
     BGP,
-    ARP
+    ARP,
+    LINK
 }
 
 impl Display for Source {
@@ -25,62 +30,249 @@ impl Display for Source {
             Source::IP => "IP",
             Source::BGP => "BGP",
             Source::ARP => "ARP",
+            Source::LINK => "LINK",
         };
         write!(f, "{}", str)
     }
 }
 
+// the name of a source in any case, e.g. bgp
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Source::iter().find(|source| source.to_string().eq_ignore_ascii_case(name)).ok_or_else(|| {
+            let sources: Vec<String> = Source::iter().map(|s| s.to_string().to_lowercase()).collect();
+            format!("unknown log source {}, supported sources are [{}]", name, sources.join(", "))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum LogEntry{
+    Message(Source, Option<String>, String), // device that logged the message, if any
+    Shutdown(oneshot::Sender<()>), // answered once the messages logged before were written
+}
+
+// messages written by the logger, an empty set lets everything through
+// the messages of no device are dropped as soon as some devices are chosen
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LogFilter{
+    pub sources: HashSet<Source>,
+    pub devices: HashSet<String>,
+}
+
+impl LogFilter{
+    pub fn allows(&self, source: &Source, device: Option<&str>) -> bool{
+        let source_allowed = self.sources.is_empty() || self.sources.contains(source);
+        let device_allowed = self.devices.is_empty() || device.is_some_and(|device| self.devices.contains(device));
+        source_allowed && device_allowed
+    }
+}
+
+// the filter is shared by all the clones of a logger, it can be changed while the network runs
+type SharedFilter = Arc<RwLock<LogFilter>>;
+
 #[derive(Debug)]
 pub struct Logger{
-    sender: Arc<Mutex<Sender<(Source, String)>>>,
+    sender: UnboundedSender<LogEntry>,
+    filter: SharedFilter,
+    device: Option<String>, // attached to the messages logged with log
+}
+
+type Messages = Arc<Mutex<Vec<(Source, String)>>>;
+
+type JsonWriter = BufWriter<Box<dyn Write + Send>>;
+
+// where the task of a logger writes the messages it lets through
+pub enum LogOutput{
+    Print(env_logger::Logger), // formatted by env_logger, at the level of the logger
+    Json(JsonWriter),
+}
+
+impl LogOutput{
+    // the env_logger isn't installed as the global logger, several loggers can print in the same process
+    // without level, the one of RUST_LOG is used
+    fn print(level: Option<LevelFilter>) -> LogOutput{
+        let printer = match level{
+            Some(level) => env_logger::Builder::new().filter_level(level).build(),
+            None => env_logger::Builder::from_default_env().build(),
+        };
+        LogOutput::Print(printer)
+    }
+}
+
+// time between two looks at the captured messages while waiting for one
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// messages logged by a logger created with Logger::start_capture, in the order they were written
+// the logs are written by their own task, a message logged by a device is seen a bit later by the handle
+#[derive(Debug, Clone)]
+pub struct LogHandle{
+    messages: Messages
+}
+
+impl LogHandle{
+    pub async fn messages(&self) -> Vec<(Source, String)>{
+        self.messages.lock().await.clone()
+    }
+
+    // first message of the source containing the substring, None if none was logged before the timeout
+    pub async fn wait_for(&self, source: Source, substring: &str, timeout: Duration) -> Option<String>{
+        self.wait_for_after(0, source, substring, timeout).await
+    }
+
+    // same as wait_for, ignoring the first skipped messages, e.g. the messages().len() of before an action
+    pub async fn wait_for_after(&self, skipped: usize, source: Source, substring: &str, timeout: Duration) -> Option<String>{
+        let found = tokio::time::timeout(timeout, async {
+            loop{
+                let message = self.messages.lock().await.iter().skip(skipped)
+                    .find(|(src, msg)| *src == source && msg.contains(substring))
+                    .map(|(_, msg)| msg.clone());
+                if message.is_some(){
+                    return message;
+                }
+                tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+            }
+        }).await;
+        found.ok().flatten()
+    }
 }
 
 impl Logger{
-    pub fn start_test() -> Logger{
-        let (tx, rx) = channel(1024);
+    fn spawn(sources: Vec<Source>, capture: Option<Messages>, output: LogOutput) -> Logger{
+        let (tx, rx) = unbounded_channel();
+        let filter = Arc::new(RwLock::new(LogFilter{sources: sources.into_iter().collect(), devices: HashSet::new()}));
+        let loop_filter = Arc::clone(&filter);
         tokio::spawn(async move{
-            Self::write_loop(rx, vec![]).await
+            Self::write_loop(rx, loop_filter, capture, output).await
         });
-        Logger{sender: Arc::new(Mutex::new(tx))}
+        Logger{sender: tx, filter, device: None}
     }
 
+    pub fn start_test() -> Logger{
+        Self::spawn(vec![], None, LogOutput::print(None))
+    }
+
+    pub fn start_capture() -> (Logger, LogHandle){
+        let handle = LogHandle{messages: Arc::new(Mutex::new(vec![]))};
+        let logger = Self::spawn(vec![], Some(Arc::clone(&handle.messages)), LogOutput::print(None));
+        (logger, handle)
+    }
+
+    // the messages are printed when RUST_LOG enables the info level
     pub fn start() -> Logger{
-        env_logger::init();
-        let (tx, rx) = channel(1024);
-        tokio::spawn(async move{
-            Self::write_loop(rx, vec![]).await
-        });
-        Logger{sender: Arc::new(Mutex::new(tx))}
+        Self::spawn(vec![], None, LogOutput::print(None))
+    }
+
+    // the messages are logged at the info level, printed when the level allows it whatever RUST_LOG is
+    pub fn start_with_level(level: LevelFilter, filters: Vec<Source>) -> Logger{
+        Self::spawn(filters, None, LogOutput::print(Some(level)))
     }
 
+    // the messages of the sources are printed whatever RUST_LOG is
     pub fn start_with_filters(filters: Vec<Source>) -> Logger{
-        env_logger::init();
-        let (tx, rx) = channel(1024);
-        tokio::spawn(async move{
-            Self::write_loop(rx, filters).await
-        });
-        Logger{sender: Arc::new(Mutex::new(tx))}
+        Self::start_with_level(LevelFilter::Info, filters)
+    }
+
+    // writes one json LogRecord per line instead of going through the log crate, e.g. to File::create(path)
+    // the writer is flushed once the logger is idle and when it shuts down
+    pub fn start_json<W: Write + Send + 'static>(writer: W) -> Logger{
+        Self::spawn(vec![], None, LogOutput::Json(BufWriter::new(Box::new(writer))))
     }
 
-    pub async fn write_loop(mut receiver: Receiver<(Source, String)>, filters: Vec<Source>){
+    pub async fn write_loop(mut receiver: UnboundedReceiver<LogEntry>, filter: SharedFilter, capture: Option<Messages>, mut output: LogOutput){
+        let mut seq = 0;
+        let mut shutdowns = vec![];
         loop{
             match receiver.recv().await{
-                Some((src, msg)) => {
-                    if filters.len() > 0 && !filters.contains(&src){
+                Some(LogEntry::Shutdown(replier)) => {
+                    // the messages already sent are still written, the later ones are refused
+                    receiver.close();
+                    shutdowns.push(replier);
+                },
+                Some(LogEntry::Message(src, device, msg)) => {
+                    if !filter.read().await.allows(&src, device.as_deref()){
                         continue;
                     }
-                    info!("{}", msg);
+                    match &mut output{
+                        LogOutput::Json(writer) => {
+                            let wall_time_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+                            let record = LogRecord{seq, wall_time_ms, source: src.clone(), device, message: msg.clone()};
+                            seq += 1;
+                            // like the capture, a failed write mustn't stop the simulation
+                            let _ = serde_json::to_writer(&mut *writer, &record).map_err(std::io::Error::from).and_then(|_| writer.write_all(b"\n"));
+                            if receiver.is_empty(){
+                                let _ = writer.flush();
+                            }
+                        },
+                        LogOutput::Print(printer) => printer.log(&Record::builder().args(format_args!("{}", msg)).level(Level::Info).target(module_path!()).build()),
+                    }
+                    if let Some(messages) = &capture{
+                        messages.lock().await.push((src, msg));
+                    }
                 },
                 None => break,
             }
         }
+        match &mut output{
+            LogOutput::Json(writer) => {
+                let _ = writer.flush();
+            },
+            LogOutput::Print(printer) => printer.flush(),
+        }
+        for replier in shutdowns{
+            let _ = replier.send(());
+        }
     }
 
+    // never blocks, a busy logger shouldn't slow down the devices
+    // the messages logged after the shutdown are dropped
+    // the message is attached to the device of the logger, see for_device
     pub async fn log(&self, src: Source, msg: String){
-        self.sender.lock().await.send((src, msg)).await.expect("Failed to log");
+        let _ = self.sender.send(LogEntry::Message(src, self.device.clone(), msg));
+    }
+
+    pub async fn log_from(&self, src: Source, device: &str, msg: String){
+        let _ = self.sender.send(LogEntry::Message(src, Some(device.to_string()), msg));
+    }
+
+    // clone of the logger attaching the device to the messages it logs
+    pub fn for_device(&self, device: &str) -> Logger{
+        Logger{sender: self.sender.clone(), filter: Arc::clone(&self.filter), device: Some(device.to_string())}
+    }
+
+    pub async fn filter(&self) -> LogFilter{
+        self.filter.read().await.clone()
+    }
+
+    // only the messages of the sources are written, all of them without sources
+    pub async fn set_filters(&self, sources: Vec<Source>){
+        self.filter.write().await.sources = sources.into_iter().collect();
+    }
+
+    pub async fn add_filter(&self, source: Source){
+        self.filter.write().await.sources.insert(source);
+    }
+
+    pub async fn remove_filter(&self, source: Source){
+        self.filter.write().await.sources.remove(&source);
+    }
+
+    // only the messages of the devices are written, the ones of all the devices and of the network without devices
+    pub async fn set_device_filters(&self, devices: Vec<String>){
+        self.filter.write().await.devices = devices.into_iter().collect();
+    }
+
+    // stops the logger of all the clones, once the messages logged before were written
+    pub async fn shutdown(&self){
+        let (tx, rx) = oneshot::channel();
+        if self.sender.send(LogEntry::Shutdown(tx)).is_ok(){
+            let _ = rx.await;
+        }
     }
 
     pub fn clone(&self) -> Logger{
-        Logger{sender: Arc::clone(&self.sender)}
+        Logger{sender: self.sender.clone(), filter: Arc::clone(&self.filter), device: self.device.clone()}
     }
 }
\ No newline at end of file
@@ -0,0 +1,27 @@
+use std::{fs::File, io::{BufRead, BufReader}};
+
+use serde::{Deserialize, Serialize};
+
+use super::{error::NetworkError, logger::Source};
+
+// A message written by a logger started with Logger::start_json, one line of the log file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogRecord{
+    pub seq: u64, // position among the written messages, from 0
+    pub wall_time_ms: u64, // since the unix epoch
+    pub source: Source,
+    pub device: Option<String>, // none for the messages of the network itself
+    pub message: String,
+}
+
+// Records of a json log file, in the order they were written
+pub fn load(path: &str) -> Result<Vec<LogRecord>, NetworkError>{
+    let f = File::open(path).map_err(|e| NetworkError::Log(format!("cannot open {}: {}", path, e)))?;
+    let mut records = vec![];
+    for (number, line) in BufReader::new(f).lines().enumerate(){
+        let line = line.map_err(|e| NetworkError::Log(format!("cannot read {}: {}", path, e)))?;
+        let record = serde_json::from_str(&line).map_err(|e| NetworkError::Log(format!("line {} of {} is not a record: {}", number + 1, path, e)))?;
+        records.push(record);
+    }
+    Ok(records)
+}
@@ -1,8 +1,10 @@
 use std::net::Ipv4Addr;
 
+use serde::{Deserialize, Serialize};
+
 use crate::network::utils::MacAddress;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ARPMessage{
     Request(Ipv4Addr),
     Reply(Ipv4Addr, MacAddress)
@@ -1,41 +1,69 @@
 use std::{fmt::Display, net::Ipv4Addr};
 
-use crate::network::ip_prefix::IPPrefix;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+use crate::network::{ip_prefix::IPPrefix, protocols::bgp::Origin};
+
+// the communities are only shown when the route carries some
+fn write_communities(f: &mut std::fmt::Formatter<'_>, communities: &[u32]) -> std::fmt::Result{
+    if communities.is_empty(){
+        return Ok(());
+    }
+    write!(f, ", communities={:?}", communities)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum BGPMessage{
-    Update(IPPrefix, Ipv4Addr, Vec<u32>, u32, u32), // prefix, nexthop, as-path, med, router_id
-    Withdraw(IPPrefix, Ipv4Addr, Vec<u32>, u32)     // prefix, nexthop, as-path, router_id
+    Open(u32, u32, Option<String>),                 // as, router_id, secret configured for the session, compared by the neighbor
+    Keepalive,                                      // the open of the neighbor was accepted
+    Update(IPPrefix, Ipv4Addr, Vec<u32>, u32, u32, Vec<u32>, Origin), // prefix, nexthop, as-path, med, router_id, communities, origin
+    Withdraw(IPPrefix, Ipv4Addr, Vec<u32>, u32),    // prefix, nexthop, as-path, router_id
+    RouteRefresh                                    // ask the neighbor to advertise its routes again
 }
 
 impl Display for BGPMessage{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self{
-            BGPMessage::Update(prefix, nexthop, as_path, med, router_id) => 
-                write!(f, "UPDATE(prefix={}, nexthop={}, as_path={}, med={}, router_id={})", 
-                    prefix, nexthop, as_path.iter().map(|a| format!("AS{}", a)).collect::<Vec<String>>().join(":"), med, router_id),
+            BGPMessage::Open(router_as, router_id, _) => write!(f, "OPEN(as=AS{}, router_id={})", router_as, router_id),
+            BGPMessage::Keepalive => write!(f, "KEEPALIVE"),
+            BGPMessage::Update(prefix, nexthop, as_path, med, router_id, communities, origin) => {
+                write!(f, "UPDATE(prefix={}, nexthop={}, as_path={}, origin={}, med={}, router_id={}", 
+                    prefix, nexthop, as_path.iter().map(|a| format!("AS{}", a)).collect::<Vec<String>>().join(":"), origin, med, router_id)?;
+                write_communities(f, communities)?;
+                write!(f, ")")
+            },
             BGPMessage::Withdraw(prefix, nexthop, as_path, router_id) =>                 
                 write!(f, "WITHDRAW(prefix={}, nexthop={}, as_path={}, router_id={})", 
-                    prefix, nexthop, as_path.iter().map(|a| format!("AS{}", a)).collect::<Vec<String>>().join(":"), router_id)
+                    prefix, nexthop, as_path.iter().map(|a| format!("AS{}", a)).collect::<Vec<String>>().join(":"), router_id),
+            BGPMessage::RouteRefresh => write!(f, "ROUTE-REFRESH")
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum IBGPMessage{
-    Update(IPPrefix, Ipv4Addr, Vec<u32>, u32, u32, u32), // prefix, nexthop, as-path, pref, med, router_id
-    Withdraw(IPPrefix, Ipv4Addr, Vec<u32>, u32)     // prefix, nexthop, as-path, router_id
+    Update(IPPrefix, Ipv4Addr, Vec<u32>, u32, u32, u32, u32, Vec<u32>, Vec<u32>, Origin), // prefix, nexthop, as-path, pref, med, router_id, originator_id, cluster_list, communities, origin
+    Withdraw(IPPrefix, Ipv4Addr, Vec<u32>, u32),    // prefix, nexthop, as-path, router_id
+    RouteRefresh(Ipv4Addr)                          // peer asking for the routes again
 }
 
 impl Display for IBGPMessage{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self{
-            IBGPMessage::Update(prefix, nexthop, as_path, pref, med, router_id) => 
-                write!(f, "UPDATE(prefix={}, nexthop={}, as_path={}, pref={}, med={}, router_id={})", 
-                    prefix, nexthop, as_path.iter().map(|a| format!("AS{}", a)).collect::<Vec<String>>().join(":"), pref, med, router_id),
+            IBGPMessage::Update(prefix, nexthop, as_path, pref, med, router_id, originator_id, cluster_list, communities, origin) => {
+                write!(f, "UPDATE(prefix={}, nexthop={}, as_path={}, origin={}, pref={}, med={}, router_id={}", 
+                    prefix, nexthop, as_path.iter().map(|a| format!("AS{}", a)).collect::<Vec<String>>().join(":"), origin, pref, med, router_id)?;
+                if !cluster_list.is_empty(){
+                    // reflected by a route reflector
+                    write!(f, ", originator_id={}, cluster_list={:?}", originator_id, cluster_list)?;
+                }
+                write_communities(f, communities)?;
+                write!(f, ")")
+            },
             IBGPMessage::Withdraw(prefix, nexthop, as_path, router_id) =>                 
                 write!(f, "WITHDRAW(prefix={}, nexthop={}, as_path={}, router_id={})", 
-                    prefix, nexthop, as_path.iter().map(|a| format!("AS{}", a)).collect::<Vec<String>>().join(":"), router_id)
+                    prefix, nexthop, as_path.iter().map(|a| format!("AS{}", a)).collect::<Vec<String>>().join(":"), router_id),
+            IBGPMessage::RouteRefresh(peer) => write!(f, "ROUTE-REFRESH(peer={})", peer)
         }
     }
 }
\ No newline at end of file
@@ -1,13 +1,36 @@
-#[derive(Debug, PartialEq, PartialOrd, Clone)]
-pub struct BPDU{
+use std::{cmp::Ordering, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bpdu{
     pub root: u32,
     pub distance: u32,
     pub switch: u32,
-    pub port: u32
+    pub port: u32,
+    pub age: Duration // time since the root sent the information, ignored when comparing bpdus
+}
+
+impl Bpdu{
+    fn key(&self) -> (u32, u32, u32, u32){
+        (self.root, self.distance, self.switch, self.port)
+    }
+}
+
+impl PartialEq for Bpdu{
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl PartialOrd for Bpdu{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.key().partial_cmp(&other.key())
+    }
 }
 
-impl ToString for BPDU{
+impl ToString for Bpdu{
     fn to_string(&self) -> String{
         format!("<{},{},{},{}>", self.root, self.distance, self.switch, self.port)
     }
-}
\ No newline at end of file
+}
@@ -1,18 +1,40 @@
-use std::net::Ipv4Addr;
+use std::{net::Ipv4Addr, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
 
 use super::bgp::IBGPMessage;
 
-#[derive(Debug, Clone)]
+pub const DEFAULT_TTL: u8 = 64;
+
+// routers crossed by a ping from its origin to the device answering it, then by the answer on its way back
+pub type RecordedRoute = (Vec<Ipv4Addr>, Vec<Ipv4Addr>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Content{
-    Ping,
-    Pong,
-    Data(String),
-    IBGP(IBGPMessage)
+    Ping(u32, u32, Option<Vec<Ipv4Addr>>), // echo identifier, sequence number, routers crossed when the route is recorded
+    Pong(u32, u32, Option<RecordedRoute>),
+    Ibgp(IBGPMessage),
+    Data(String), // payload of the application traffic
+    Probe(u64, SystemTime), // flow of the traffic generator, time sent
+    TimeExceeded(Box<IP>) // packet dropped because its ttl expired
+}
+
+impl Content{
+    // a router forwarding a ping or its answer adds itself to the route they record
+    pub fn record_hop(&mut self, router: Ipv4Addr){
+        match self{
+            Content::Ping(_, _, Some(forward)) => forward.push(router),
+            Content::Pong(_, _, Some((_, backward))) => backward.push(router),
+            _ => (),
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IP{
     pub src: Ipv4Addr, 
     pub dest: Ipv4Addr,
-    pub content: Content
+    pub ttl: u8,
+    pub content: Content,
+    pub visited: Vec<Ipv4Addr> // routers that forwarded the packet, one of them seeing it again found a forwarding loop
 }
\ No newline at end of file
@@ -5,19 +5,44 @@ pub mod bgp;
 pub mod arp;
 
 use arp::ARPMessage;
-use bpdu::BPDU;
+use bpdu::Bpdu;
 use ospf::OSPFMessage;
-use ip::IP;
+use ip::{Content, IP};
 use bgp::BGPMessage;
 
+use serde::{Deserialize, Serialize};
+
 use super::utils::MacAddress;
 
 
-#[derive(Debug, Clone)]
+// Control messages keep the protocols running, the data ones are the traffic of the devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrafficClass{
+    Control,
+    Data
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Message{
-    BPDU(BPDU),
-    OSPF(OSPFMessage),
-    EthernetFrame(MacAddress, IP),
-    BGP(BGPMessage),
-    ARP(ARPMessage)
+    Bpdu(Bpdu),
+    Ospf(OSPFMessage),
+    EthernetFrame(MacAddress, MacAddress, IP), // source, destination
+    Bgp(BGPMessage),
+    Arp(ARPMessage)
+}
+
+impl Message{
+    // bytes taken on a link, the messages aren't encoded so their debug representation stands for them
+    pub fn size(&self) -> usize{
+        format!("{:?}", self).len()
+    }
+
+    // the iBGP messages are carried in ip packets, like the traffic
+    pub fn class(&self) -> TrafficClass{
+        match self{
+            Message::EthernetFrame(_, _, IP{content: Content::Ibgp(_), ..}) => TrafficClass::Control,
+            Message::EthernetFrame(_, _, _) => TrafficClass::Data,
+            Message::Bpdu(_) | Message::Ospf(_) | Message::Bgp(_) | Message::Arp(_) => TrafficClass::Control,
+        }
+    }
 }
\ No newline at end of file
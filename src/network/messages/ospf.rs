@@ -1,11 +1,13 @@
 use std::{collections::HashSet, net::Ipv4Addr};
 
+use serde::{Deserialize, Serialize};
+
 use crate::network::ip_prefix::IPPrefix;
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum OSPFMessage{
     Hello,
-    LSP(Ipv4Addr, u32, HashSet<(u32, IPPrefix)>),
-    HelloReply(IPPrefix)
+    Lsp(Ipv4Addr, u32, HashSet<(u32, IPPrefix)>),
+    HelloReply(IPPrefix, Option<Ipv4Addr>) // address of the router, and its address on the subnet of the link if it has one
 }
\ No newline at end of file
@@ -0,0 +1,484 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::{communicators::{EqualCostPorts, MultipathRoutes}, ip_prefix::IPPrefix, protocols::bgp::{BGPRoute, BGPSession, Origin, SessionState}, rib::RIBEntry, stats::DeviceStats, switch::PortState};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputMode{
+    Plain,
+    Pretty{color: bool}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style{
+    Bold,
+    Red,
+    Green,
+    Yellow
+}
+
+impl Style{
+    fn code(&self) -> &'static str{
+        match self{
+            Style::Bold => "\x1b[1m",
+            Style::Red => "\x1b[31m",
+            Style::Green => "\x1b[32m",
+            Style::Yellow => "\x1b[33m",
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+
+pub struct Cell{
+    text: String,
+    style: Option<Style>
+}
+
+impl Cell{
+    pub fn new(text: impl ToString) -> Cell{
+        Cell{text: text.to_string(), style: None}
+    }
+
+    pub fn styled(text: impl ToString, style: Style) -> Cell{
+        Cell{text: text.to_string(), style: Some(style)}
+    }
+}
+
+fn paint(text: &str, style: Option<Style>, color: bool) -> String{
+    match style{
+        Some(style) if color => format!("{}{}{}", style.code(), text, RESET),
+        _ => text.to_string(),
+    }
+}
+
+fn border(widths: &[usize], left: char, middle: char, right: char) -> String{
+    let segments: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+    format!("{}{}{}", left, segments.join(&middle.to_string()), right)
+}
+
+pub fn render_table(title: &str, headers: &[&str], rows: &[Vec<Cell>], color: bool) -> String{
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows{
+        for (i, cell) in row.iter().enumerate(){
+            widths[i] = usize::max(widths[i], cell.text.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<(&str, Option<Style>)>| {
+        let cells: Vec<String> = cells.iter().zip(widths.iter()).map(|((text, style), width)| {
+            let padding = " ".repeat(width - text.chars().count());
+            format!(" {}{} ", paint(text, *style, color), padding)
+        }).collect();
+        format!("│{}│", cells.join("│"))
+    };
+
+    let mut lines = vec![paint(title, Some(Style::Bold), color)];
+    lines.push(border(&widths, '┌', '┬', '┐'));
+    lines.push(line(headers.iter().map(|h| (*h, Some(Style::Bold))).collect()));
+    lines.push(border(&widths, '├', '┼', '┤'));
+    for row in rows{
+        lines.push(line(row.iter().map(|c| (c.text.as_str(), c.style)).collect()));
+    }
+    lines.push(border(&widths, '└', '┴', '┘'));
+    lines.join("\n") + "\n"
+}
+
+// the ports of all the routes used for a prefix in multipath mode, of its equal cost OSPF paths, or the one of its entry
+fn ports(prefix: &IPPrefix, port: u32, multipath: &MultipathRoutes, equal_cost: &EqualCostPorts) -> String{
+    match (multipath.get(prefix), equal_cost.get(prefix)){
+        (Some(routes), _) => routes.iter().map(|(_, port)| port.to_string()).collect::<Vec<String>>().join(","),
+        (None, Some(ports)) => ports.iter().map(|port| port.to_string()).collect::<Vec<String>>().join(","),
+        (None, None) => port.to_string(),
+    }
+}
+
+pub fn render_routing_table(router: &str, table: &HashMap<IPPrefix, RIBEntry>, multipath: &MultipathRoutes, equal_cost: &EqualCostPorts, mode: OutputMode) -> String{
+    let mut entries: Vec<(&IPPrefix, &RIBEntry)> = table.iter().collect();
+    entries.sort();
+    match mode{
+        OutputMode::Plain => {
+            let mut output = format!("{}\n", router);
+            for (ip, (port, distance, origin)) in entries{
+                output.push_str(&format!("  {}: port={}, distance={}, origin={}\n", ip, ports(ip, *port, multipath, equal_cost), distance, origin));
+            }
+            output
+        },
+        OutputMode::Pretty{color} => {
+            let rows: Vec<Vec<Cell>> = entries.iter().map(|(ip, (port, distance, origin))| {
+                vec![Cell::new(ip), Cell::new(ports(ip, *port, multipath, equal_cost)), Cell::new(distance), Cell::new(origin)]
+            }).collect();
+            render_table(router, &["Prefix", "Port", "Distance", "Origin"], &rows, color)
+        }
+    }
+}
+
+// the best route first, then by as path, the other attributes only break the ties
+fn sorted_bgp_routes(best: &Option<BGPRoute>, routes: &HashSet<BGPRoute>) -> Vec<(bool, BGPRoute)>{
+    let mut routes: Vec<(bool, BGPRoute)> = routes.iter().map(|r| (Some(r) == best.as_ref(), r.clone())).collect();
+    routes.sort_by_key(|(is_best, route)| (!is_best, route.as_path.clone(), route.to_string()));
+    routes
+}
+
+// ">" for the best route, "+" for the other routes used in multipath mode
+fn route_marker(is_best: bool, prefix: &IPPrefix, route: &BGPRoute, multipath: &MultipathRoutes) -> &'static str{
+    if is_best{
+        ">"
+    }else if multipath.get(prefix).is_some_and(|routes| routes.iter().any(|(r, _)| r == route)){
+        "+"
+    }else{
+        " "
+    }
+}
+
+// port of the session the route was learned on, "-" for the routes of the router and the ones of its ibgp peers
+fn learned_from(route: &BGPRoute) -> String{
+    route.learned_from.map_or("-".to_string(), |port| port.to_string())
+}
+
+// the as path followed by the code of the origin, i for IGP, e for EGP and ? when incomplete
+fn bgp_path(route: &BGPRoute) -> String{
+    let code = match route.origin{
+        Origin::Igp => "i",
+        Origin::Egp => "e",
+        Origin::Incomplete => "?",
+    };
+    route.as_path.iter().map(|a| a.to_string()).chain([code.to_string()]).collect::<Vec<String>>().join(" ")
+}
+
+pub fn render_bgp_table(router: &str, table: &HashMap<IPPrefix, (Option<BGPRoute>, HashSet<BGPRoute>)>, multipath: &MultipathRoutes, mode: OutputMode) -> String{
+    let mut prefixes: Vec<&IPPrefix> = table.keys().collect();
+    prefixes.sort();
+    match mode{
+        // fixed width columns like show ip bgp, the prefix is only written on the line of its first route
+        OutputMode::Plain => {
+            let mut output = format!("{}\n", router);
+            output.push_str(&format!("   {:<18} {:<15} {:>5} {:>5} {:>5}  {}\n", "Network", "Next Hop", "Pref", "MED", "Port", "Path"));
+            for prefix in prefixes{
+                let (best, routes) = &table[prefix];
+                for (i, (is_best, route)) in sorted_bgp_routes(best, routes).into_iter().enumerate(){
+                    let marker = route_marker(is_best, prefix, &route, multipath);
+                    let network = if i == 0 { prefix.to_string() } else { String::new() };
+                    output.push_str(&format!(" {} {:<18} {:<15} {:>5} {:>5} {:>5}  {}\n", marker, network, route.nexthop, route.pref, route.med,
+                        learned_from(&route), bgp_path(&route)));
+                }
+            }
+            output
+        },
+        OutputMode::Pretty{color} => {
+            let mut rows = vec![];
+            for prefix in prefixes{
+                let (best, routes) = &table[prefix];
+                for (is_best, route) in sorted_bgp_routes(best, routes){
+                    let path = route.as_path.iter().map(|a| a.to_string()).collect::<Vec<String>>().join(" ");
+                    let cells = [
+                        route_marker(is_best, prefix, &route, multipath).trim().to_string(), prefix.to_string(), route.nexthop.to_string(),
+                        route.pref.to_string(), route.med.to_string(), learned_from(&route), path, route.origin.to_string()
+                    ];
+                    rows.push(cells.into_iter().map(|text| {
+                        if is_best { Cell::styled(text, Style::Green) } else { Cell::new(text) }
+                    }).collect());
+                }
+            }
+            render_table(router, &["", "Prefix", "Nexthop", "Pref", "MED", "Port", "AS path", "Origin"], &rows, color)
+        }
+    }
+}
+
+fn port_state_style(state: &PortState) -> Style{
+    match state{
+        PortState::Blocked | PortState::Disabled => Style::Red,
+        PortState::Designated => Style::Yellow,
+        PortState::Root => Style::Green,
+    }
+}
+
+pub fn render_port_states(states: &BTreeMap<String, BTreeMap<u32, PortState>>, mode: OutputMode) -> String{
+    match mode{
+        OutputMode::Plain => {
+            let mut output = String::new();
+            for (switch, ports) in states{
+                output.push_str(&format!("{}\n", switch));
+                for (port, state) in ports{
+                    output.push_str(&format!("  {}: {:?}\n", port, state));
+                }
+            }
+            output
+        },
+        OutputMode::Pretty{color} => {
+            let mut rows = vec![];
+            for (switch, ports) in states{
+                for (port, state) in ports{
+                    rows.push(vec![Cell::new(switch), Cell::new(port), Cell::styled(format!("{:?}", state), port_state_style(state))]);
+                }
+            }
+            render_table("Switch port states", &["Switch", "Port", "State"], &rows, color)
+        }
+    }
+}
+
+pub fn render_bgp_sessions(sessions: &BTreeMap<String, BTreeMap<String, BGPSession>>, mode: OutputMode) -> String{
+    match mode{
+        OutputMode::Plain => {
+            let mut output = String::new();
+            for (router, neighbors) in sessions{
+                output.push_str(&format!("{}\n", router));
+                for (neighbor, session) in neighbors{
+                    let neighbor_as = session.neighbor_as.map(|a| format!(", AS {}", a)).unwrap_or_default();
+                    let uptime = session.uptime().map(|t| format!(", up {}ms", t.as_millis())).unwrap_or_default();
+                    output.push_str(&format!("  {} (port {}{}): {}{}\n", neighbor, session.port, neighbor_as, session, uptime));
+                }
+            }
+            output
+        },
+        OutputMode::Pretty{color} => {
+            let mut rows = vec![];
+            for (router, neighbors) in sessions{
+                for (neighbor, session) in neighbors{
+                    let style = if session.state == SessionState::Established { Style::Green } else { Style::Red };
+                    let neighbor_as = session.neighbor_as.map_or("-".to_string(), |a| a.to_string());
+                    let uptime = session.uptime().map_or("-".to_string(), |t| format!("{}ms", t.as_millis()));
+                    rows.push(vec![Cell::new(router), Cell::new(neighbor), Cell::new(session.port), Cell::new(neighbor_as), Cell::styled(session, style), Cell::new(uptime)]);
+                }
+            }
+            render_table("BGP sessions", &["Router", "Neighbor", "Port", "AS", "State", "Uptime"], &rows, color)
+        }
+    }
+}
+
+pub fn render_reachability(matrix: &BTreeMap<String, BTreeMap<String, bool>>, mode: OutputMode) -> String{
+    match mode{
+        OutputMode::Plain => {
+            let mut output = String::new();
+            for (from, destinations) in matrix{
+                for (to, reachable) in destinations{
+                    output.push_str(&format!("  {} -> {}: {}\n", from, to, if *reachable { "reachable" } else { "unreachable" }));
+                }
+            }
+            output
+        },
+        OutputMode::Pretty{color} => {
+            let destinations: Vec<&String> = matrix.values().flat_map(|d| d.keys()).collect::<std::collections::BTreeSet<_>>().into_iter().collect();
+            let mut headers = vec!["from \\ to"];
+            headers.extend(destinations.iter().map(|d| d.as_str()));
+            let rows: Vec<Vec<Cell>> = matrix.iter().map(|(from, reachable)| {
+                let mut row = vec![Cell::styled(from, Style::Bold)];
+                for to in destinations.iter(){
+                    row.push(match reachable.get(*to){
+                        Some(true) => Cell::styled("✓", Style::Green),
+                        Some(false) => Cell::styled("✗", Style::Red),
+                        None => Cell::new("-"),
+                    });
+                }
+                row
+            }).collect();
+            render_table("Reachability", &headers, &rows, color)
+        }
+    }
+}
+
+pub fn render_stats(stats: &BTreeMap<String, DeviceStats>, mode: OutputMode) -> String{
+    match mode{
+        OutputMode::Plain => {
+            let mut output = String::new();
+            for (device, counters) in stats{
+                output.push_str(&format!("{}\n", device));
+                for (port, kind, sent, received) in counters.rows(){
+                    output.push_str(&format!("  {} {}: {} sent, {} received\n", port, kind, sent, received));
+                }
+                for (port, failures) in counters.send_failures.iter(){
+                    output.push_str(&format!("  {}: {} failed sends\n", port, failures));
+                }
+                if counters.loops_detected > 0{
+                    output.push_str(&format!("  {} forwarding loops detected\n", counters.loops_detected));
+                }
+            }
+            output
+        },
+        OutputMode::Pretty{color} => {
+            let mut rows = vec![];
+            for (device, counters) in stats{
+                for (port, kind, sent, received) in counters.rows(){
+                    rows.push(vec![Cell::new(device), Cell::new(port), Cell::new(kind), Cell::new(sent), Cell::new(received)]);
+                }
+                for (port, failures) in counters.send_failures.iter(){
+                    rows.push(vec![Cell::new(device), Cell::new(port), Cell::new("failed sends"), Cell::new(failures), Cell::new("-")]);
+                }
+                if counters.loops_detected > 0{
+                    rows.push(vec![Cell::new(device), Cell::new("-"), Cell::new("forwarding loops"), Cell::new(counters.loops_detected), Cell::new("-")]);
+                }
+            }
+            render_table("Message counters", &["Device", "Port", "Message", "Sent", "Received"], &rows, color)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::rib::RouteOrigin;
+    use crate::network::protocols::bgp::{Origin, RouteSource};
+    use std::time::Instant;
+
+    fn routing_table() -> HashMap<IPPrefix, RIBEntry>{
+        [
+            ("10.0.1.1/32".parse().unwrap(), (0, 0, RouteOrigin::Connected)),
+            ("10.0.1.2/32".parse().unwrap(), (1, 1, RouteOrigin::Ospf)),
+            ("10.0.1.4/32".parse().unwrap(), (12, 10, RouteOrigin::Ospf)),
+        ].into_iter().collect()
+    }
+
+    fn assert_aligned(output: &str){
+        let lines: Vec<&str> = output.lines().skip(1).collect();
+        let width = lines[0].chars().count();
+        for line in lines{
+            assert_eq!(line.chars().count(), width, "misaligned line {:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_plain_routing_table() {
+        let output = render_routing_table("r1", &routing_table(), &HashMap::new(), &HashMap::new(), OutputMode::Plain);
+        assert_eq!(output, "r1\n  10.0.1.1/32: port=0, distance=0, origin=connected\n  10.0.1.2/32: port=1, distance=1, origin=ospf\n  10.0.1.4/32: port=12, distance=10, origin=ospf\n");
+    }
+
+    #[test]
+    fn test_pretty_routing_table() {
+        let output = render_routing_table("r1", &routing_table(), &HashMap::new(), &HashMap::new(), OutputMode::Pretty{color: false});
+        assert_aligned(&output);
+        assert!(!output.contains('\x1b'));
+        assert_eq!(output.lines().nth(5).unwrap(), "│ 10.0.1.2/32 │ 1    │ 1        │ ospf      │");
+
+        let colored = render_routing_table("r1", &routing_table(), &HashMap::new(), &HashMap::new(), OutputMode::Pretty{color: true});
+        assert!(colored.contains("\x1b[1mr1\x1b[0m"));
+    }
+
+    #[test]
+    fn test_bgp_table_best_first() {
+        let route = |nexthop: &str, as_path: Vec<u32>, pref| BGPRoute{
+            prefix: "10.0.1.0/24".parse().unwrap(),
+            nexthop: nexthop.parse().unwrap(),
+            as_path,
+            pref,
+            med: 0,
+            router_id: 1,
+            source: RouteSource::Ebgp,
+            communities: vec![],
+            origin: Origin::Igp,
+            learned_from: Some(1)
+        };
+        let best = route("10.0.2.2", vec![2, 1], 150);
+        let table = [(
+            "10.0.1.0/24".parse().unwrap(),
+            (Some(best.clone()), [route("10.0.1.1", vec![1], 50), best].into_iter().collect())
+        )].into_iter().collect();
+
+        let plain = render_bgp_table("r4", &table, &HashMap::new(), OutputMode::Plain);
+        assert_eq!(plain, concat!(
+            "r4\n",
+            "   Network            Next Hop         Pref   MED  Port  Path\n",
+            " > 10.0.1.0/24        10.0.2.2          150     0     1  2 1 i\n",
+            "                      10.0.1.1           50     0     1  1 i\n"
+        ));
+
+        let pretty = render_bgp_table("r4", &table, &HashMap::new(), OutputMode::Pretty{color: false});
+        assert_aligned(&pretty);
+        assert_eq!(pretty.lines().nth(4).unwrap(), "│ > │ 10.0.1.0/24 │ 10.0.2.2 │ 150  │ 0   │ 1    │ 2 1     │ IGP    │");
+
+        let colored = render_bgp_table("r4", &table, &HashMap::new(), OutputMode::Pretty{color: true});
+        assert!(colored.lines().nth(4).unwrap().contains("\x1b[32m10.0.2.2\x1b[0m"));
+        assert!(!colored.lines().nth(5).unwrap().contains("\x1b[32m"));
+    }
+
+    #[test]
+    fn test_multipath_routes() {
+        let route = |nexthop: &str, as_path: Vec<u32>, router_id| BGPRoute{
+            prefix: "10.0.4.0/24".parse().unwrap(),
+            nexthop: nexthop.parse().unwrap(),
+            as_path,
+            pref: 50,
+            med: 0,
+            router_id,
+            source: RouteSource::Ebgp,
+            communities: vec![],
+            origin: Origin::Igp,
+            learned_from: Some(1)
+        };
+        let (best, other) = (route("10.0.2.2", vec![2, 4], 2), route("10.0.3.3", vec![3, 4], 3));
+        let prefix: IPPrefix = "10.0.4.0/24".parse().unwrap();
+        let multipath = [(prefix, vec![(best.clone(), 1), (other.clone(), 2)])].into_iter().collect();
+        let table = [(prefix, (Some(best.clone()), [best, other].into_iter().collect()))].into_iter().collect();
+        let plain = render_bgp_table("r1", &table, &multipath, OutputMode::Plain);
+        assert_eq!(plain, concat!(
+            "r1\n",
+            "   Network            Next Hop         Pref   MED  Port  Path\n",
+            " > 10.0.4.0/24        10.0.2.2           50     0     1  2 4 i\n",
+            " +                    10.0.3.3           50     0     1  3 4 i\n"
+        ));
+
+        let routing_table = [(prefix, (1, 0, RouteOrigin::Bgp))].into_iter().collect();
+        let plain = render_routing_table("r1", &routing_table, &multipath, &HashMap::new(), OutputMode::Plain);
+        assert_eq!(plain, "r1\n  10.0.4.0/24: port=1,2, distance=0, origin=bgp\n");
+    }
+
+    #[test]
+    fn test_equal_cost_ports() {
+        let prefix: IPPrefix = "10.0.1.4/32".parse().unwrap();
+        let equal_cost = [(prefix, vec![1, 12])].into_iter().collect();
+        let plain = render_routing_table("r1", &routing_table(), &HashMap::new(), &equal_cost, OutputMode::Plain);
+        assert!(plain.contains("  10.0.1.4/32: port=1,12, distance=10, origin=ospf\n"), "{}", plain);
+    }
+
+    #[test]
+    fn test_bgp_sessions() {
+        let session = |port, state, neighbor_as: Option<u32>, established_at| BGPSession{
+            port, neighbor: "10.0.2.2".parse().unwrap(), state, secret_mismatch: neighbor_as.is_none(), link: (150, 0), configured_pref: None, neighbor_as, established_at, last_keepalive: established_at
+        };
+        let sessions = [("r1".to_string(), [
+            ("r2".to_string(), session(1, SessionState::Idle, None, None)),
+            ("r3".to_string(), session(2, SessionState::Established, Some(3), Some(Instant::now()))),
+        ].into_iter().collect())].into_iter().collect();
+        let plain = render_bgp_sessions(&sessions, OutputMode::Plain);
+        assert!(plain.starts_with("r1\n  r2 (port 1): Idle (secret mismatch)\n  r3 (port 2, AS 3): Established, up "));
+
+        let pretty = render_bgp_sessions(&sessions, OutputMode::Pretty{color: false});
+        assert_aligned(&pretty);
+        assert!(pretty.contains("│ r1     │ r2       │ 1    │ -  │ Idle (secret mismatch) │ -      │"));
+    }
+
+    #[test]
+    fn test_port_states_and_reachability() {
+        let states = [("s1".to_string(), [(1, PortState::Root), (2, PortState::Blocked)].into_iter().collect())].into_iter().collect();
+        assert_eq!(render_port_states(&states, OutputMode::Plain), "s1\n  1: Root\n  2: Blocked\n");
+        let pretty = render_port_states(&states, OutputMode::Pretty{color: false});
+        assert_aligned(&pretty);
+        assert!(pretty.contains("│ s1     │ 2    │ Blocked │"));
+
+        let matrix = [
+            ("r1".to_string(), [("r2".to_string(), true)].into_iter().collect()),
+            ("r2".to_string(), [("r1".to_string(), false)].into_iter().collect()),
+        ].into_iter().collect();
+        let pretty = render_reachability(&matrix, OutputMode::Pretty{color: false});
+        assert_aligned(&pretty);
+        assert!(pretty.contains("│ r1        │ -  │ ✓  │"));
+        assert!(pretty.contains("│ r2        │ ✗  │ -  │"));
+        assert_eq!(render_reachability(&matrix, OutputMode::Plain), "  r1 -> r2: reachable\n  r2 -> r1: unreachable\n");
+    }
+
+    #[test]
+    fn test_stats() {
+        use crate::network::stats::MessageKind;
+        let counters = DeviceStats{
+            sent: [((1, MessageKind::OspfLsp), 3), ((2, MessageKind::Bgp), 1)].into_iter().collect(),
+            received: [((1, MessageKind::OspfLsp), 5)].into_iter().collect(),
+            send_failures: [(2, 4)].into_iter().collect(),
+            loops_detected: 1,
+        };
+        let stats = [("r1".to_string(), counters)].into_iter().collect();
+        assert_eq!(render_stats(&stats, OutputMode::Plain), "r1\n  1 OSPF LSP: 3 sent, 5 received\n  2 BGP: 1 sent, 0 received\n  2: 4 failed sends\n  1 forwarding loops detected\n");
+        let pretty = render_stats(&stats, OutputMode::Pretty{color: false});
+        assert_aligned(&pretty);
+        assert!(pretty.contains("│ r1     │ 2    │ BGP              │ 1    │ 0        │"));
+        assert!(pretty.contains("│ r1     │ 2    │ failed sends     │ 4    │ -        │"));
+        assert!(pretty.contains("│ r1     │ -    │ forwarding loops │ 1    │ -        │"));
+    }
+}
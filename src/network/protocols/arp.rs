@@ -1,39 +1,54 @@
-use std::{collections::HashMap, net::Ipv4Addr};
+use std::{collections::{HashMap, HashSet}, net::Ipv4Addr};
 
 use crate::network::{logger::{Logger, Source}, messages::{arp::ARPMessage, Message}, router::RouterInfo, utils::{MacAddress, SharedState}};
 
 #[derive(Debug)]
 pub struct ArpState{
     pub mapping: HashMap<Ipv4Addr, MacAddress>,
+    pub requested: HashSet<Ipv4Addr>, // addresses resolved and not answered yet, the other replies are gratuitous
     pub router_info: SharedState<RouterInfo>,
     pub logger: Logger
 }
 
 impl ArpState{
     pub fn new(router_info: SharedState<RouterInfo>, logger: Logger) -> ArpState{
-        ArpState{mapping: HashMap::new(), router_info, logger}
+        ArpState{mapping: HashMap::new(), requested: HashSet::new(), router_info, logger}
     }
 
-    pub async fn resolve(&self, ip: Ipv4Addr, port: u32){
+    pub async fn resolve(&mut self, ip: Ipv4Addr, port: u32){
+        self.requested.insert(ip);
         self.logger.log(Source::ARP, format!("Router {} sending resolving request for {}", self.router_info.lock().await.name, ip)).await;
         let info = self.router_info.lock().await;
         if let Some((_, sender)) = info.neighbors_links.get(&port){
-            sender.send(Message::ARP(ARPMessage::Request(ip))).await.expect("Failed to send arp message");
+            sender.send(Message::Arp(ARPMessage::Request(ip))).await;
         }
     }
 
     pub async fn process_request(&mut self, ip: Ipv4Addr, port: u32){
         self.logger.log(Source::ARP, format!("Router {} received request for mapping of ip {}", self.router_info.lock().await.name, ip)).await;
         let info = self.router_info.lock().await;
-        if info.ip != ip{
+        // the router answers for its address on the subnet of the link too
+        if info.ip != ip && info.interface_addresses.get(&port).is_none_or(|(address, _)| *address != ip){
             return;
         }
         if let Some((_, sender)) = info.neighbors_links.get(&port){
-            sender.send(Message::ARP(ARPMessage::Reply(ip, info.mac_address.clone()))).await.expect("Failed to send arp message");
+            sender.send(Message::Arp(ARPMessage::Reply(ip, info.mac_address.clone()))).await;
+        }
+    }
+
+    // a gratuitous reply tells the address of the router at the other end of a new link
+    pub async fn announce(&self, port: u32){
+        let info = self.router_info.lock().await;
+        if let Some((_, sender)) = info.neighbors_links.get(&port){
+            self.logger.log(Source::ARP, format!("Router {} sending gratuitous reply for {} on port {}", info.name, info.ip, port)).await;
+            sender.send(Message::Arp(ARPMessage::Reply(info.ip, info.mac_address.clone()))).await;
         }
     }
 
     pub async fn process_reply(&mut self, ip: Ipv4Addr, mac_address: MacAddress){
+        if !self.requested.remove(&ip){
+            self.logger.log(Source::ARP, format!("Router {} received gratuitous reply for {} : {:?}", self.router_info.lock().await.name, ip, mac_address)).await;
+        }
         self.mapping.insert(ip, mac_address);
         self.logger.log(Source::ARP, format!("Router {} has mappings : {:?}", self.router_info.lock().await.name, self.mapping)).await;
     }
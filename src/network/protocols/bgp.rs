@@ -1,18 +1,53 @@
-use std::{borrow::Borrow, collections::{hash_map::Entry, HashMap, HashSet}, fmt::Display, net::Ipv4Addr};
+use std::{borrow::Borrow, collections::{hash_map::{DefaultHasher, Entry}, HashMap, HashSet}, fmt::Display, hash::{Hash, Hasher}, net::Ipv4Addr, sync::Arc, time::{Duration, Instant}};
+
+use serde::{Deserialize, Serialize};
+use strum_macros::EnumIter;
 
 use crate::network::{
-    ip_prefix::IPPrefix, ip_trie::IPTrie, logger::{Logger, Source}, messages::{bgp::{BGPMessage, IBGPMessage}, ip::{Content, IP}, Message}, router::RouterInfo, utils::SharedState
+    events::{self, EventSender, NetworkEvent}, ip_prefix::IPPrefix, ip_trie::IPTrie, logger::{Logger, Source}, messages::{bgp::{BGPMessage, IBGPMessage}, ip::{Content, DEFAULT_TTL, IP}, Message}, rib::RouteOrigin, router::RouterInfo, utils::{SharedState, HELLO_INTERVAL}
 };
 
-use super::ospf::OSPFState;
+use super::{ospf::OSPFState, policy::{PolicyAction, RoutePolicy}};
 
-#[derive(Debug, PartialEq, Clone, Eq, Hash)]
+#[derive(Debug, PartialEq, Clone, Eq, Hash, Serialize)]
 pub enum RouteSource{
-    IBGP,
-    EBGP
+    #[serde(rename="IBGP")]
+    Ibgp,
+    #[serde(rename="EBGP")]
+    Ebgp,
+    Local
+}
+
+// how the prefix was learned by the router originating it, the lowest is preferred
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Origin{
+    #[serde(rename="IGP")]
+    Igp,
+    #[serde(rename="EGP")]
+    Egp,
+    #[serde(rename="incomplete")]
+    Incomplete
+}
+
+impl Display for Origin{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            Origin::Igp => write!(f, "IGP"),
+            Origin::Egp => write!(f, "EGP"),
+            Origin::Incomplete => write!(f, "incomplete"),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Clone, Eq, Hash)]
+// preference given to a locally originated route, unless configured otherwise
+pub const DEFAULT_LOCAL_PREF: u32 = 1000;
+
+// preference given to the routes learned on a session, according to the relationship with the neighbor
+pub const CUSTOMER_PREF: u32 = 150;
+pub const PEER_PREF: u32 = 100;
+pub const PROVIDER_PREF: u32 = 50;
+
+#[derive(Debug, PartialEq, Clone, Eq, Hash, Serialize)]
 pub struct BGPRoute{
     pub prefix: IPPrefix,
     pub nexthop: Ipv4Addr,
@@ -20,13 +55,117 @@ pub struct BGPRoute{
     pub pref: u32,
     pub med: u32,
     pub router_id: u32,
-    pub source: RouteSource
+    pub source: RouteSource,
+    pub communities: Vec<u32>,
+    pub origin: Origin,
+    pub learned_from: Option<u32> // port of the eBGP session the route was received on
+}
+
+// Attributes of a path received in an update, an eBGP path gets its pref from its session
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathAttributes{
+    pub as_path: Vec<u32>,
+    pub med: u32,
+    pub router_id: u32,
+    pub communities: Vec<u32>,
+    pub origin: Origin
+}
+
+impl BGPRoute{
+    // the routes of a path only differ by their attributes
+    fn same_path(&self, other: &BGPRoute) -> bool{
+        self.source == other.source && self.nexthop == other.nexthop && self.router_id == other.router_id && self.as_path == other.as_path
+    }
 }
 
 impl Display for BGPRoute{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let path = self.as_path.iter().map(|v| format!("AS{}", v)).collect::<Vec<String>>().join(":");
-        write!(f, "nexthop={}, AS path={}, pref={}, med={}", self.nexthop, path, self.pref, self.med)
+        write!(f, "nexthop={}, AS path={}, origin={}, pref={}, med={}", self.nexthop, path, self.origin, self.pref, self.med)?;
+        if !self.communities.is_empty(){
+            write!(f, ", communities={:?}", self.communities)?;
+        }
+        Ok(())
+    }
+}
+
+// applied by a router to the routes tagged with a community
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Serialize)]
+pub enum CommunityAction{
+    Drop,            // the routes are ignored when received
+    SetPref(u32),    // the routes are received with this pref instead of the one of the session
+    NoExportToPeers  // the routes are only exported to customers, like the routes of peers and providers
+}
+
+// advertise prefix on the session of port only while unless_present has no route in the RIB
+#[derive(Debug, Clone)]
+pub struct ConditionalAdvertisement{
+    pub prefix: IPPrefix,
+    pub unless_present: IPPrefix,
+    pub port: u32,
+    pub advertised: bool
+}
+
+// what a router does with the updates of a neighbor once it sent more prefixes than the limit of their session
+#[derive(Debug, PartialEq, Clone, Copy, Eq, EnumIter, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaxPrefixAction{
+    Drop,  // the updates for new prefixes are ignored
+    Close  // the session is closed and the routes of the neighbor withdrawn
+}
+
+impl Display for MaxPrefixAction{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            MaxPrefixAction::Drop => write!(f, "drop"),
+            MaxPrefixAction::Close => write!(f, "close"),
+        }
+    }
+}
+
+// three keepalive periods, like the OSPF dead interval
+pub const HOLD_TIME: Duration = HELLO_INTERVAL.saturating_mul(3);
+
+// routes are only exchanged on the established sessions
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Serialize, EnumIter)]
+pub enum SessionState{
+    Idle,
+    OpenSent,
+    Established
+}
+
+impl Display for SessionState{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct BGPSession{
+    pub port: u32,
+    pub neighbor: Ipv4Addr,
+    pub state: SessionState,
+    pub secret_mismatch: bool,        // the neighbor sent another secret than the one configured
+    pub link: (u32, u32),             // (pref, med) of the relationship, used once the session is established
+    pub configured_pref: Option<u32>, // pref configured on the link instead of the one of the relationship
+    pub neighbor_as: Option<u32>,     // AS sent in the open of the neighbor, once accepted
+    pub established_at: Option<Instant>,
+    pub last_keepalive: Option<Instant>
+}
+
+impl BGPSession{
+    pub fn uptime(&self) -> Option<Duration>{
+        self.established_at.map(|t| t.elapsed())
+    }
+}
+
+impl Display for BGPSession{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.state)?;
+        if self.secret_mismatch{
+            write!(f, " (secret mismatch)")?;
+        }
+        Ok(())
     }
 }
 
@@ -36,7 +175,38 @@ pub struct BGPState {
     pub igp_info: SharedState<OSPFState>,
     pub logger: Logger,
     pub routes: HashMap<IPPrefix, HashSet<BGPRoute>>,
-    pub prefixes: IPTrie<IPPrefix>
+    pub prefixes: IPTrie<IPPrefix>,
+    pub conditional_advertisements: Vec<ConditionalAdvertisement>,
+    // (pref of the relationship, pref applied to its routes) of the sessions whose pref was changed, by neighbor address
+    pub local_prefs: HashMap<Ipv4Addr, (u32, u32)>,
+    // AS path advertised for each prefix on the session of each port
+    pub adj_rib_out: HashMap<u32, HashMap<IPPrefix, Vec<u32>>>,
+    // secret expected from each neighbor, by neighbor address
+    pub secrets: HashMap<Ipv4Addr, String>,
+    // prefixes announced in addition to the one of our AS
+    pub custom_prefixes: HashSet<IPPrefix>,
+    // MED advertised for each prefix on the sessions deriving it from the IGP distance to the nexthop, by port
+    pub igp_meds: HashMap<u32, HashMap<IPPrefix, u32>>,
+    // extra times our AS is prepended to the paths advertised on the session of each port
+    pub prepends: HashMap<u32, u32>,
+    // action applied to the routes carrying each community
+    pub community_policies: HashMap<u32, CommunityAction>,
+    // policies applied to the routes received and sent on the session of each port
+    pub import_policies: HashMap<u32, RoutePolicy>,
+    pub export_policies: HashMap<u32, RoutePolicy>,
+    // most prefixes accepted from the neighbor of each port, and what happens above it
+    pub max_prefixes: HashMap<u32, (u32, MaxPrefixAction)>,
+    pub sessions: HashMap<u32, BGPSession>,
+    // (peer it was received from, originator_id, cluster_list) of the routes learned over iBGP, by prefix and nexthop
+    pub ibgp_origins: HashMap<(IPPrefix, Ipv4Addr), (Ipv4Addr, u32, Vec<u32>)>,
+    // route reflected for each prefix and the peers it was reflected to, when the router is a route reflector
+    pub reflected: HashMap<IPPrefix, (BGPRoute, Vec<Ipv4Addr>)>,
+    // whether the IGP reaches each nexthop of the routes, updated when the IGP routes change
+    pub nexthops: HashMap<Ipv4Addr, bool>,
+    pub hold_time: Duration, // an established session is closed after this long without keepalive
+    pub multipath: bool,     // the packets are spread over all the routes as good as the best one
+    pub route_evictions: u64,
+    pub events: EventSender
 }
 
 impl BGPState {
@@ -46,60 +216,353 @@ impl BGPState {
             igp_info,
             logger,
             routes: HashMap::new(),
-            prefixes: IPTrie::new()
+            prefixes: IPTrie::new(),
+            conditional_advertisements: vec![],
+            local_prefs: HashMap::new(),
+            adj_rib_out: HashMap::new(),
+            secrets: HashMap::new(),
+            custom_prefixes: HashSet::new(),
+            igp_meds: HashMap::new(),
+            prepends: HashMap::new(),
+            community_policies: HashMap::new(),
+            import_policies: HashMap::new(),
+            export_policies: HashMap::new(),
+            max_prefixes: HashMap::new(),
+            sessions: HashMap::new(),
+            ibgp_origins: HashMap::new(),
+            reflected: HashMap::new(),
+            nexthops: HashMap::new(),
+            hold_time: HOLD_TIME,
+            multipath: false,
+            route_evictions: 0,
+            events: EventSender::new(events::EVENT_CAPACITY)
         }
     }
 
     pub async fn process_bgp_message(&mut self, port:u32, message: BGPMessage) {
+        if !matches!(message, BGPMessage::Open(..) | BGPMessage::Keepalive) && !self.router_info.lock().await.bgp_links.contains_key(&port){
+            // the session isn't established
+            return;
+        }
         match message {
-            BGPMessage::Update(prefix, nexthop, as_path, med, router_id) => {
-                self.process_update(port, prefix, nexthop, as_path, med, router_id).await
+            BGPMessage::Open(router_as, _, secret) => self.process_open(port, router_as, secret).await,
+            BGPMessage::Keepalive => self.process_keepalive(port).await,
+            BGPMessage::Update(prefix, nexthop, as_path, med, router_id, communities, origin) => {
+                self.process_update(port, prefix, nexthop, PathAttributes{as_path, med, router_id, communities, origin}).await;
+                self.reflect(prefix).await;
             }
             BGPMessage::Withdraw(prefix, nexthop, as_path, router_id) => {
-                self.process_withdraw(port, prefix, nexthop, as_path, router_id).await
+                self.process_withdraw(port, prefix, nexthop, as_path, router_id).await;
+                self.reflect(prefix).await;
             }
+            BGPMessage::RouteRefresh => self.refresh_session(port).await
         }
+        self.evaluate_conditional_advertisements().await;
     }
 
-    pub async fn process_ibgp_message(&mut self, port:u32, message: IBGPMessage) {
+    pub async fn process_ibgp_message(&mut self, port:u32, from: Ipv4Addr, message: IBGPMessage) {
         match message {
-            IBGPMessage::Update(prefix, nexthop, as_path, pref, med, router_id) => {
-                self.process_update_ibgp(port, prefix, nexthop, as_path, pref, med, router_id).await
+            IBGPMessage::Update(prefix, nexthop, as_path, pref, med, router_id, originator_id, cluster_list, communities, origin) => {
+                let info = self.router_info.lock().await;
+                let (name, id) = (info.name.clone(), info.id);
+                drop(info);
+                if originator_id == id || cluster_list.contains(&id){
+                    // our own route reflected back to us
+                    self.logger.borrow().log(Source::BGP, format!("Router {} ignored the reflected ibgp update for prefix {} from {}, it went through it", name, prefix, from)).await;
+                    return;
+                }
+                self.ibgp_origins.insert((prefix, nexthop), (from, originator_id, cluster_list));
+                self.process_update_ibgp(port, prefix, nexthop, pref, PathAttributes{as_path, med, router_id, communities, origin}).await;
+                self.reflect(prefix).await;
             }
             IBGPMessage::Withdraw(prefix, nexthop, as_path, router_id) => {
-                self.process_withdraw_ibgp(port, prefix, nexthop, as_path, router_id).await
+                self.process_withdraw_ibgp(port, prefix, nexthop, as_path, router_id).await;
+                if !self.routes.get(&prefix).is_some_and(|routes| routes.iter().any(|r| r.source == RouteSource::Ibgp && r.nexthop == nexthop)){
+                    self.ibgp_origins.remove(&(prefix, nexthop));
+                }
+                self.reflect(prefix).await;
+            }
+            IBGPMessage::RouteRefresh(peer) => self.refresh_ibgp_peer(peer).await
+        }
+        self.evaluate_conditional_advertisements().await;
+    }
+
+    // a route reflector advertises its best iBGP route to its clients if it was learned from a non-client,
+    // to all its peers if it was learned from a client, the peers don't readvertise iBGP routes otherwise
+    async fn reflect(&mut self, prefix: IPPrefix){
+        let info = self.router_info.lock().await;
+        let (clients, peers, self_ip, self_id, name) = (info.rr_clients.clone(), info.ibgp_peers.clone(), info.ip, info.id, info.name.clone());
+        drop(info);
+        if clients.is_empty() && !self.reflected.contains_key(&prefix){
+            return;
+        }
+        let best = self.decision_process(prefix).await.filter(|best| best.source == RouteSource::Ibgp);
+        let reflection = best.and_then(|best| {
+            let (from, originator_id, cluster_list) = self.ibgp_origins.get(&(prefix, best.nexthop))?.clone();
+            let targets: Vec<Ipv4Addr> = peers.iter()
+                .filter(|peer| **peer != from && **peer != best.nexthop && (clients.contains(&from) || clients.contains(peer)))
+                .cloned()
+                .collect();
+            Some((best, targets, originator_id, cluster_list))
+        });
+        if self.reflected.get(&prefix).map(|(route, _)| route) == reflection.as_ref().map(|(route, ..)| route){
+            return;
+        }
+
+        let mut messages = vec![];
+        if let Some((previous, targets)) = self.reflected.remove(&prefix){
+            for peer in targets{
+                messages.push((peer, IBGPMessage::Withdraw(prefix, previous.nexthop, previous.as_path.clone(), previous.router_id)));
+            }
+        }
+        if let Some((best, targets, originator_id, mut cluster_list)) = reflection{
+            // our router id is used as cluster id
+            cluster_list.push(self_id);
+            for peer in targets.iter(){
+                messages.push((*peer, IBGPMessage::Update(prefix, best.nexthop, best.as_path.clone(), best.pref, best.med, best.router_id, originator_id, cluster_list.clone(), best.communities.clone(), best.origin)));
+            }
+            self.reflected.insert(prefix, (best, targets));
+        }
+        let igp_state = self.igp_info.lock().await;
+        for (peer, ibgp_message) in messages{
+            self.logger.borrow().log(Source::BGP, format!("Router {} has reflected iBGP message {} to peer {}", name, ibgp_message, peer)).await;
+            let message = IP{src: self_ip, dest: peer, ttl: DEFAULT_TTL, content: Content::Ibgp(ibgp_message), visited: vec![]};
+            igp_state.send_message(peer, message).await;
+        }
+    }
+
+    // the secret of neighbor is checked when its session is opened
+    pub fn set_secret(&mut self, neighbor: Ipv4Addr, secret: Option<String>){
+        match secret{
+            Some(secret) => self.secrets.insert(neighbor, secret),
+            None => self.secrets.remove(&neighbor),
+        };
+    }
+
+    // send our AS and secret to the neighbor of port, the session is established once the neighbor accepted them
+    // pref replaces the pref of the relationship for the routes learned on the session, the relationship still decides the exports
+    pub async fn open_session(&mut self, port: u32, neighbor: Ipv4Addr, link: (u32, u32), pref: Option<u32>){
+        if let Some(pref) = pref{
+            self.local_prefs.insert(neighbor, (link.0, pref));
+        }
+        let info = self.router_info.lock().await;
+        let message = BGPMessage::Open(info.router_as, info.id, self.secrets.get(&neighbor).cloned());
+        drop(info);
+        self.send_session_message(port, message).await;
+        self.sessions.insert(port, BGPSession{port, neighbor, state: SessionState::OpenSent, secret_mismatch: false, link, configured_pref: pref, neighbor_as: None, established_at: None, last_keepalive: None});
+    }
+
+    async fn send_session_message(&self, port: u32, message: BGPMessage){
+        let info = self.router_info.lock().await;
+        let Some((_, sender)) = info.neighbors_links.get(&port) else {
+            return;
+        };
+        self.logger.borrow().log(Source::BGP, format!("Router {} has sent {} on port {}", info.name, message, port)).await;
+        sender.send(Message::Bgp(message)).await;
+    }
+
+    async fn process_open(&mut self, port: u32, router_as: u32, secret: Option<String>){
+        let name = self.router_info.lock().await.name.clone();
+        let session = match self.sessions.get_mut(&port){
+            Some(session) if session.state == SessionState::OpenSent && session.neighbor_as.is_none() => session,
+            _ => return,
+        };
+        if self.secrets.get(&session.neighbor) != secret.as_ref(){
+            session.state = SessionState::Idle;
+            session.secret_mismatch = true;
+            self.logger.borrow().log(Source::BGP, format!("Router {} refused the BGP session with {} on port {}, the secrets don't match", name, session.neighbor, port)).await;
+            return;
+        }
+        session.neighbor_as = Some(router_as);
+        self.send_session_message(port, BGPMessage::Keepalive).await;
+    }
+
+    // the neighbor accepted our open, routes can be exchanged if we accepted its own
+    async fn process_keepalive(&mut self, port: u32){
+        let name = self.router_info.lock().await.name.clone();
+        let session = match self.sessions.get_mut(&port){
+            Some(session) if session.neighbor_as.is_some() => session,
+            _ => return,
+        };
+        session.last_keepalive = Some(Instant::now());
+        if session.state != SessionState::OpenSent{
+            return;
+        }
+        session.state = SessionState::Established;
+        session.established_at = session.last_keepalive;
+        let (link, neighbor) = (session.link, session.neighbor);
+        self.router_info.lock().await.bgp_links.insert(port, link);
+        self.logger.borrow().log(Source::BGP, format!("Router {} established its BGP session with {} on port {}", name, neighbor, port)).await;
+        self.refresh_session(port).await;
+    }
+
+    // tell the neighbors of the established sessions that we are alive, not logged as they are periodic
+    pub async fn send_keepalives(&self){
+        let info = self.router_info.lock().await;
+        for session in self.sessions.values().filter(|s| s.state == SessionState::Established){
+            if let Some((_, sender)) = info.neighbors_links.get(&session.port){
+                sender.send(Message::Bgp(BGPMessage::Keepalive)).await;
             }
         }
     }
 
+    // close the established sessions whose neighbor stopped sending keepalives
+    pub async fn expire_sessions(&mut self){
+        let expired: Vec<u32> = self.sessions.values()
+            .filter(|s| s.state == SessionState::Established && s.last_keepalive.is_some_and(|t| t.elapsed() > self.hold_time))
+            .map(|s| s.port)
+            .collect();
+        for port in expired{
+            let name = self.router_info.lock().await.name.clone();
+            let neighbor = self.sessions[&port].neighbor;
+            self.logger.borrow().log(Source::BGP, format!("Router {} didn't receive keepalives from {} on port {} for {:?}, session closed", name, neighbor, port, self.hold_time)).await;
+            self.close_session(port).await;
+        }
+    }
+
+    // the session of port goes back to Idle, the routes learned from its neighbor are withdrawn
+    pub async fn close_session(&mut self, port: u32){
+        let (neighbor, relationship, configured_pref) = match self.sessions.get_mut(&port){
+            Some(session) => {
+                session.state = SessionState::Idle;
+                session.established_at = None;
+                (session.neighbor, session.link.0, session.configured_pref)
+            },
+            None => return,
+        };
+        // a pref changed while the session was up doesn't outlive it
+        Self::restore_local_pref(&mut self.local_prefs, neighbor, relationship, configured_pref);
+        self.router_info.lock().await.bgp_links.remove(&port);
+        self.adj_rib_out.remove(&port);
+        self.igp_meds.remove(&port);
+        let learned: Vec<BGPRoute> = self.routes.values()
+            .flatten()
+            .filter(|r| r.source == RouteSource::Ebgp && r.nexthop == neighbor)
+            .cloned()
+            .collect();
+        for route in learned{
+            self.remove_route(route.prefix, route.nexthop, route.as_path, route.router_id).await;
+            self.reflect(route.prefix).await;
+        }
+        self.evaluate_conditional_advertisements().await;
+    }
+
     pub async fn install_route(&self, route: BGPRoute){
+        if route.source == RouteSource::Local{
+            // the prefix is owned by this router, nothing to forward
+            return;
+        }
         let mut igp_state = self.igp_info.lock().await;
-        let port = igp_state.get_port(route.nexthop).await.unwrap().clone();
-        igp_state.routing_table.insert(route.prefix, (port, 0));
-    }
-
-    pub async fn process_update(
-        &mut self,
-        port: u32,
-        prefix: IPPrefix,
-        nexthop: Ipv4Addr,
-        as_path: Vec<u32>,
-        med: u32,
-        router_id: u32
-    ) {
-        
+        if let Some(port) = igp_state.get_port(route.nexthop).await{
+            igp_state.install(route.prefix, port, 0, RouteOrigin::Bgp);
+        }
+    }
+
+    // remember whether the IGP reaches a new nexthop, until the next change of the IGP routes
+    async fn resolve_nexthop(&mut self, nexthop: Ipv4Addr){
+        if !self.nexthops.contains_key(&nexthop){
+            let reachable = self.distance_nexthop(nexthop).await != u32::MAX;
+            self.nexthops.insert(nexthop, reachable);
+        }
+    }
+
+    // the IGP routes changed, the best routes whose nexthop became unreachable (or reachable again) are replaced
+    pub async fn revalidate_nexthops(&mut self){
+        let prefixes: Vec<IPPrefix> = self.routes.keys().cloned().collect();
+        let mut previous_bests = vec![];
+        for prefix in prefixes.iter(){
+            previous_bests.push(self.decision_process(*prefix).await);
+        }
+        // forget the nexthops of the routes that are gone
+        let used: HashSet<Ipv4Addr> = self.routes.values().flatten().map(|r| r.nexthop).collect();
+        self.nexthops.retain(|nexthop, _| used.contains(nexthop));
+        let mut changed = false;
+        let nexthops: Vec<Ipv4Addr> = self.nexthops.keys().cloned().collect();
+        for nexthop in nexthops{
+            let reachable = self.distance_nexthop(nexthop).await != u32::MAX;
+            changed |= self.nexthops.insert(nexthop, reachable) != Some(reachable);
+        }
+        if !changed{
+            return;
+        }
+        let name = self.router_info.lock().await.name.clone();
+        for (prefix, previous_best) in prefixes.into_iter().zip(previous_bests){
+            let best = self.decision_process(prefix).await;
+            if best == previous_best{
+                continue;
+            }
+            self.logger.borrow().log(Source::BGP, format!("Router {} reevaluates its routes to {}, the reachability of their nexthop changed", name, prefix)).await;
+            self.advertise_best(prefix, previous_best, best).await;
+            self.reflect(prefix).await;
+        }
+        self.evaluate_conditional_advertisements().await;
+    }
+
+    // advertise the new best route of prefix instead of the previous one, and forward the packets with it
+    async fn advertise_best(&mut self, prefix: IPPrefix, previous_best: Option<BGPRoute>, best: Option<BGPRoute>){
+        let info = self.router_info.lock().await;
+        let (name, ip) = (info.name.clone(), info.ip);
+        drop(info);
+        events::emit(&self.events, NetworkEvent::BgpBestChanged{router: name.clone(), prefix, old: previous_best.clone(), new: best.clone()});
+        if let Some(previous_best_route) = previous_best{
+            self.send_withdraw(prefix, ip, previous_best_route.as_path.clone()).await;
+            if previous_best_route.source == RouteSource::Ebgp{
+                self.send_ibgp_withdraw(prefix, previous_best_route.as_path).await;
+            }
+        }
+        match best{
+            Some(best) => {
+                self.logger.borrow().log(Source::BGP, format!("Router {} has new best route ({}) to reach prefix {}", name, best, best.prefix)).await;
+                self.install_route(best.clone()).await;
+                self.send_update(prefix, ip, best.as_path.clone(), &best.communities, best.origin, self.export_pref(&best)).await;
+                if best.source == RouteSource::Ebgp{
+                    self.send_ibgp_update(prefix, best.as_path, best.pref, best.med, &best.communities, best.origin).await;
+                }
+            },
+            None => self.igp_info.lock().await.uninstall(prefix, RouteOrigin::Bgp),
+        }
+    }
+
+    pub async fn install_best_routes(&self){
+        for prefix in self.routes.keys(){
+            if let Some(best) = self.decision_process(*prefix).await{
+                self.install_route(best).await;
+            }
+        }
+    }
+
+    pub async fn process_update(&mut self, port: u32, prefix: IPPrefix, nexthop: Ipv4Addr, attributes: PathAttributes) {
+        let PathAttributes{as_path, med, router_id, communities, origin} = attributes;
         let info = self.router_info.lock().await;
         let name = info.name.clone();
-        let ip = info.ip;
         let pref = info.bgp_links.get(&port).unwrap().0;
+        let pref = self.local_prefs.get(&nexthop).map_or(pref, |(_, pref)| *pref);
         let current_as = info.router_as;
         drop(info);
         if as_path.contains(&current_as){
             return;
         }
-        self.prefixes.insert(prefix, prefix);
         self.logger.borrow().log(Source::BGP, format!("Router {} received bgp update on port {} for prefix {} with nexthop = {}, AS path = {:?}, med = {}", name, port, prefix, nexthop, as_path, med)).await;
-        let route = BGPRoute{prefix, nexthop, as_path, pref, med, source: RouteSource::EBGP, router_id};
+        // the import policy of the session goes before the policies of the communities
+        let action = Self::policy_action(&self.import_policies, port, prefix);
+        let pref = match (action, self.community_pref(&communities, pref)){
+            (PolicyAction::Deny, _) | (_, None) => {
+                self.drop_route(&name, prefix, nexthop, as_path, router_id).await;
+                return;
+            },
+            (PolicyAction::SetPref(pref), _) => pref,
+            (_, Some(pref)) => pref,
+        };
+        let med = match action{
+            PolicyAction::SetMed(med) => med,
+            _ => med,
+        };
+        if self.exceeds_max_prefixes(&name, port, prefix, nexthop).await{
+            return;
+        }
+        self.prefixes.insert(prefix, prefix);
+        self.resolve_nexthop(nexthop).await;
+        let route = BGPRoute{prefix, nexthop, as_path, pref, med, source: RouteSource::Ebgp, router_id, communities, origin, learned_from: Some(port)};
 
         let previous_best = self.decision_process(prefix).await;
 
@@ -108,22 +571,15 @@ impl BGPState {
             Entry::Vacant(v) => v.insert(HashSet::new()),
         };
 
+        // an update of a known path replaces it, e.g. when its MED changed
+        routes.retain(|r| !r.same_path(&route));
         routes.insert(route);
+        self.enforce_route_limit(prefix).await;
 
         let best = self.decision_process(prefix).await;
 
         if previous_best != best{
-            if let Some(previous_best_route) = previous_best{
-                self.send_withdraw(previous_best_route.prefix, ip, previous_best_route.as_path.clone()).await;
-                if previous_best_route.source != RouteSource::IBGP{
-                    self.send_ibgp_withdraw(previous_best_route.prefix, previous_best_route.as_path).await;
-                }
-            }
-            let best = best.unwrap();
-            self.logger.borrow().log(Source::BGP, format!("Router {} has new best route ({}) to reach prefix {}", name, best, best.prefix)).await;
-            self.install_route(best.clone()).await;
-            self.send_update(best.prefix, ip, best.as_path.clone(), best.pref).await;
-            self.send_ibgp_update(best.prefix, best.as_path, best.pref, best.med).await;
+            self.advertise_best(prefix, previous_best, best).await;
         }
     }
 
@@ -131,22 +587,22 @@ impl BGPState {
         let info = self.router_info.lock().await;
         let name = info.name.clone();
         let current_as = info.router_as;
-        let ip = info.ip;
         drop(info);
         if as_path.contains(&current_as){
             return;
         }
         self.logger.borrow().log(Source::BGP, format!("Router {} received bgp withdraw on port {} for prefix {} with nexthop = {}, AS path = {:?}", name, port, prefix, nexthop, as_path)).await;
-    
-        let previous_best = self.decision_process(prefix).await;
-
-        let routes = self.routes.get(&prefix);
+        self.remove_route(prefix, nexthop, as_path, router_id).await;
+    }
 
-        if let None = routes{
-            return;
-        }
+    // forget the route learned from a neighbor and advertise the new best route
+    async fn remove_route(&mut self, prefix: IPPrefix, nexthop: Ipv4Addr, as_path: Vec<u32>, router_id: u32){
+        let previous_best = self.decision_process(prefix).await;
 
-        let routes = routes.unwrap();
+        let routes = match self.routes.get(&prefix){
+            Some(routes) => routes,
+            None => return,
+        };
 
         let mut new_routes = HashSet::new();
         let mut best_removed = false;
@@ -161,44 +617,30 @@ impl BGPState {
         }
         
         self.routes.insert(prefix, new_routes);
+        self.forget_prefix(prefix).await;
 
         if best_removed{
-            let previous_best = previous_best.unwrap();
-            self.send_withdraw(prefix, ip, previous_best.as_path.clone()).await;
-            if previous_best.source == RouteSource::EBGP{
-                self.send_ibgp_withdraw(prefix, previous_best.as_path).await;
-            }
-
             let new_best = self.decision_process(prefix).await;
-            if let Some(new_best_route) = new_best{
-                self.logger.borrow().log(Source::BGP, format!("Router {} has new best route ({}) to reach prefix {}", name, new_best_route, new_best_route.prefix)).await;
-                self.install_route(new_best_route.clone()).await;
-                self.send_update(prefix, ip, new_best_route.as_path.clone(), new_best_route.pref).await;
-                if new_best_route.source != RouteSource::IBGP{
-                    self.send_ibgp_update(new_best_route.prefix, new_best_route.as_path, new_best_route.pref, new_best_route.med).await;
-                }
-            }
+            self.advertise_best(prefix, previous_best, new_best).await;
         }
         
     }
 
-    pub async fn process_update_ibgp(
-        &mut self,
-        port: u32,
-        prefix: IPPrefix,
-        nexthop: Ipv4Addr,
-        as_path: Vec<u32>,
-        pref: u32,
-        med: u32,
-        router_id: u32
-    ){
-        let info = self.router_info.lock().await;
-        let name = info.name.clone();
-        let ip = info.ip;
-        drop(info);
-        self.prefixes.insert(prefix, prefix);
+    pub async fn process_update_ibgp(&mut self, port: u32, prefix: IPPrefix, nexthop: Ipv4Addr, pref: u32, attributes: PathAttributes){
+        let PathAttributes{as_path, med, router_id, communities, origin} = attributes;
+        let name = self.router_info.lock().await.name.clone();
         self.logger.borrow().log(Source::BGP, format!("Router {} received ibgp update on port {} for prefix {} with nexthop = {}, AS path = {:?}, med = {}", name, port, prefix, nexthop, as_path, med)).await;
-        let route = BGPRoute{prefix, nexthop, as_path, pref, med, source: RouteSource::IBGP, router_id};
+        let pref = match self.community_pref(&communities, pref){
+            Some(pref) => pref,
+            None => {
+                self.drop_route(&name, prefix, nexthop, as_path, router_id).await;
+                return;
+            },
+        };
+        self.prefixes.insert(prefix, prefix);
+        self.resolve_nexthop(nexthop).await;
+        // the ibgp updates are routed, the port they came in on doesn't tell the peer
+        let route = BGPRoute{prefix, nexthop, as_path, pref, med, source: RouteSource::Ibgp, router_id, communities, origin, learned_from: None};
 
         let previous_best = self.decision_process(prefix).await;
 
@@ -207,178 +649,221 @@ impl BGPState {
             Entry::Vacant(v) => v.insert(HashSet::new()),
         };
 
+        // an update of a known path replaces it, e.g. when its MED changed
+        routes.retain(|r| !r.same_path(&route));
         routes.insert(route);
+        self.enforce_route_limit(prefix).await;
 
         let best = self.decision_process(prefix).await;
 
         if previous_best != best{
-            if let Some(previous_best_route) = previous_best{
-                self.send_withdraw(previous_best_route.prefix, ip, previous_best_route.as_path.clone()).await;
-                if previous_best_route.source != RouteSource::IBGP{
-                    self.send_ibgp_withdraw(previous_best_route.prefix, previous_best_route.as_path).await;
-                }
-            }
-            let best = best.unwrap();
-            self.logger.borrow().log(Source::BGP, format!("Router {} has new best route ({}) to reach prefix {}", name, best, best.prefix)).await;
-            self.install_route(best.clone()).await;
-            self.send_update(best.prefix, ip, best.as_path.clone(), best.pref).await;
-            // suppose fullmesh, no need to readvertise new best to other ibgp peers
+            // the route reflectors readvertise the new best to the other peers, the full mesh doesn't need it
+            self.advertise_best(prefix, previous_best, best).await;
         }
     }
 
     pub async fn process_withdraw_ibgp(&mut self, port: u32, prefix: IPPrefix, nexthop: Ipv4Addr, as_path: Vec<u32>, router_id: u32) {
-        let info = self.router_info.lock().await;
-        let name = info.name.clone();
-        let ip = info.ip;
-        drop(info);
+        let name = self.router_info.lock().await.name.clone();
         self.logger.borrow().log(Source::BGP, format!("Router {} received ibgp withdraw on port {} for prefix {} with nexthop = {}, AS path = {:?}", name, port, prefix, nexthop, as_path)).await;
-    
-        let previous_best = self.decision_process(prefix).await;
+        self.remove_route(prefix, nexthop, as_path, router_id).await;
+    }
 
-        let routes = self.routes.get(&prefix);
+    // pref of a received route once the policies of its communities are applied, None if it must be dropped
+    fn community_pref(&self, communities: &[u32], pref: u32) -> Option<u32>{
+        let mut pref = pref;
+        for community in communities{
+            match self.community_policies.get(community){
+                Some(CommunityAction::Drop) => return None,
+                Some(CommunityAction::SetPref(community_pref)) => pref = *community_pref,
+                _ => {},
+            }
+        }
+        Some(pref)
+    }
 
-        if let None = routes{
-            return;
+    // a new prefix above the limit of the session of port is dropped, or closes the session
+    async fn exceeds_max_prefixes(&mut self, name: &str, port: u32, prefix: IPPrefix, neighbor: Ipv4Addr) -> bool{
+        let (limit, action) = match self.max_prefixes.get(&port){
+            Some(max_prefixes) => *max_prefixes,
+            None => return false,
+        };
+        let learned: HashSet<IPPrefix> = self.routes.iter()
+            .filter(|(_, routes)| routes.iter().any(|r| r.source == RouteSource::Ebgp && r.nexthop == neighbor))
+            .map(|(prefix, _)| *prefix)
+            .collect();
+        if learned.contains(&prefix) || (learned.len() as u32) < limit{
+            return false;
         }
+        match action{
+            MaxPrefixAction::Drop => {
+                self.logger.borrow().log(Source::BGP, format!("Warning: router {} received more than {} prefixes on port {}, update for prefix {} dropped", name, limit, port, prefix)).await;
+            },
+            MaxPrefixAction::Close => {
+                self.logger.borrow().log(Source::BGP, format!("Warning: router {} received more than {} prefixes on port {}, session closed", name, limit, port)).await;
+                self.close_session(port).await;
+            },
+        }
+        true
+    }
 
-        let routes = routes.unwrap();
+    // the prefixes already learned above a new limit are kept
+    pub async fn set_max_prefixes(&mut self, port: u32, limit: u32, action: MaxPrefixAction){
+        let name = self.router_info.lock().await.name.clone();
+        self.logger.borrow().log(Source::BGP, format!("Router {} accepts at most {} prefixes on port {} ({} above)", name, limit, port, action)).await;
+        self.max_prefixes.insert(port, (limit, action));
+    }
 
-        let mut new_routes = HashSet::new();
-        let mut best_removed = false;
-        for route in routes{
-            if route.nexthop == nexthop && route.router_id == router_id && route.as_path == as_path{
-                if let Some(r) = &previous_best{
-                    best_removed = best_removed || route.nexthop == r.nexthop && route.router_id == r.router_id && route.as_path == r.as_path ; 
-                }
-            }else{
-                new_routes.insert(route.clone());
-            }
-        }
-        
-        self.routes.insert(prefix, new_routes);
+    fn policy_action(policies: &HashMap<u32, RoutePolicy>, port: u32, prefix: IPPrefix) -> PolicyAction{
+        policies.get(&port).map_or(PolicyAction::Accept, |policy| policy.action(prefix))
+    }
 
-        if best_removed{
-            let previous_best = previous_best.unwrap();
-            self.send_withdraw(prefix, ip, previous_best.as_path.clone()).await;
-            if previous_best.source == RouteSource::EBGP{
-                self.send_ibgp_withdraw(prefix, previous_best.as_path).await;
-            }
+    // a dropped update also withdraws the route previously received for the same path
+    async fn drop_route(&mut self, name: &str, prefix: IPPrefix, nexthop: Ipv4Addr, as_path: Vec<u32>, router_id: u32){
+        self.logger.borrow().log(Source::BGP, format!("Router {} dropped the update for prefix {} with nexthop = {}, it is filtered by the policies of the router", name, prefix, nexthop)).await;
+        self.remove_route(prefix, nexthop, as_path, router_id).await;
+    }
 
-            let new_best = self.decision_process(prefix).await;
-            if let Some(new_best_route) = new_best{
-                self.logger.borrow().log(Source::BGP, format!("Router {} has new best route ({}) to reach prefix {}", name, new_best_route, new_best_route.prefix)).await;
-                self.install_route(new_best_route.clone()).await;
-                self.send_update(prefix, ip, new_best_route.as_path.clone(), new_best_route.pref).await;
-                if new_best_route.source != RouteSource::IBGP{
-                    self.send_ibgp_update(new_best_route.prefix, new_best_route.as_path, new_best_route.pref, new_best_route.med).await;
-                }
+    // a prefix without any route is no longer matched when looking for a nexthop
+    async fn forget_prefix(&mut self, prefix: IPPrefix){
+        if self.routes.get(&prefix).is_some_and(|routes| !routes.is_empty()){
+            return;
+        }
+        self.routes.remove(&prefix);
+        self.prefixes.remove(prefix);
+        self.igp_info.lock().await.uninstall(prefix, RouteOrigin::Bgp);
+        let compact_after = self.router_info.lock().await.limits.trie_compact_after;
+        self.prefixes.compact_after(compact_after);
+    }
+
+    // drop the least preferred routes of prefix above the limit, never the best one
+    async fn enforce_route_limit(&mut self, prefix: IPPrefix){
+        let info = self.router_info.lock().await;
+        let limit = info.limits.max_routes_per_prefix;
+        let name = info.name.clone();
+        drop(info);
+        let first_eviction = self.route_evictions == 0;
+        while self.routes.get(&prefix).map_or(0, |routes| routes.len()) > limit{
+            let best = self.decision_process(prefix).await;
+            let routes = self.routes.get_mut(&prefix).unwrap();
+            let worst = routes.iter()
+                .filter(|route| Some(*route) != best.as_ref())
+                .min_by_key(|route| (route.pref, std::cmp::Reverse(route.as_path.len())))
+                .cloned();
+            match worst{
+                Some(worst) => {
+                    routes.remove(&worst);
+                    self.route_evictions += 1;
+                },
+                None => break,
             }
         }
+        if first_eviction && self.route_evictions > 0{
+            self.logger.borrow().log(Source::BGP, format!("Router {} reached its limit of {} routes for prefix {}, evicting the least preferred ones", name, limit, prefix)).await;
+        }
     }
 
     pub async fn distance_nexthop(&self, nexthop: Ipv4Addr) -> u32{
         let igp_info = &self.igp_info.lock().await;
-        let prefix = igp_info.prefixes.longest_match(nexthop);
-        if prefix.is_none(){
-            return u32::max_value();
-        }
-        let prefix = prefix.unwrap();
+        let prefix = match igp_info.prefixes.longest_match_entry(nexthop){
+            Some((prefix, _)) => prefix,
+            None => return u32::MAX,
+        };
         match igp_info.routing_table.get(&prefix){
-            Some((_, distance)) => *distance,
+            Some((_, distance, _)) => *distance,
             None => u32::max_value(),
         }
     }
 
     pub async fn decision_process(&self, prefix: IPPrefix) -> Option<BGPRoute>{
-        let routes = self.routes.get(&prefix);
+        // the routes whose nexthop isn't reached by the IGP can't be used
+        let routes: HashSet<BGPRoute> = self.routes.get(&prefix)?.iter().filter(|r| self.nexthops.get(&r.nexthop) != Some(&false)).cloned().collect();
 
-        if routes.is_none(){
-            return None;
-        }
-
-        let routes = routes.unwrap();
-
-        if routes.is_empty(){
-            return None;
+        // the IGP is only asked for the distances the decision may need
+        let mut distances = HashMap::new();
+        for route in routes.iter().filter(|r| r.source == RouteSource::Ibgp){
+            distances.insert(route.nexthop, self.distance_nexthop(route.nexthop).await);
         }
+        select_best(&routes, |nexthop| distances.get(&nexthop).copied().unwrap_or(u32::MAX))
+    }
 
-        let mut best_pref = 0;
-        let mut best_path_len = usize::max_value();
-        for route in routes{
-            if best_pref != route.pref{
-                if route.pref > best_pref{
-                    best_pref = route.pref;
-                    best_path_len = route.as_path.len();
-                }
-            }else{
-                best_path_len = usize::min(route.as_path.len(), best_path_len);
-            }
+    // the best route of prefix followed, in multipath mode, by the routes it only won against on the router id
+    pub async fn multipath_routes(&self, prefix: IPPrefix) -> Vec<BGPRoute>{
+        let best = match self.decision_process(prefix).await{
+            Some(best) => best,
+            None => return vec![],
+        };
+        if !self.multipath || best.source == RouteSource::Local{
+            return vec![best];
         }
-
-        let mut map = HashMap::new();
-        for route in routes{
-            if route.pref != best_pref || route.as_path.len() != best_path_len{
+        let best_distance = self.distance_nexthop(best.nexthop).await;
+        let mut routes = vec![];
+        for route in self.routes[&prefix].iter().filter(|r| **r != best && self.nexthops.get(&r.nexthop) != Some(&false)){
+            if route.source != best.source || route.pref != best.pref || route.as_path.len() != best.as_path.len() || route.origin != best.origin || route.med != best.med{
                 continue;
             }
-            let map_entry = match map.entry(route.as_path[0]) {
-                Entry::Occupied(o) => o.into_mut(),
-                Entry::Vacant(v) => v.insert(vec![]),
-            };
-
-            if map_entry.len() == 0{
-                map_entry.push(route);
-            }else if map_entry[0].med > route.med{
-                map_entry.clear();
-                map_entry.push(route);
-            }else if map_entry[0].med == route.med{
-                map_entry.push(route);
+            if route.source == RouteSource::Ibgp && self.distance_nexthop(route.nexthop).await != best_distance{
+                continue;
             }
+            routes.push(route.clone());
         }
+        routes.sort_by_key(|r| (r.router_id, r.nexthop));
+        routes.insert(0, best);
+        routes
+    }
 
-        let mut routes: Vec<&BGPRoute> = vec![];
-        for route_vec in map.values(){
-            routes.extend(route_vec.iter());
-        }
-
-        let mut best_route = routes[0];
-        
-        for route in routes{
-            if best_route.source != route.source{
-                if best_route.source == RouteSource::IBGP && route.source == RouteSource::EBGP{
-                    best_route = route;
-                }
+    // the prefixes with several routes used in multipath mode, with the port towards the nexthop of each route
+    pub async fn multipath_table(&self) -> HashMap<IPPrefix, Vec<(BGPRoute, u32)>>{
+        let mut table = HashMap::new();
+        for prefix in self.routes.keys(){
+            let routes = self.multipath_routes(*prefix).await;
+            if routes.len() < 2{
+                continue;
             }
-            else if best_route.source == RouteSource::IBGP && self.distance_nexthop(route.nexthop).await != self.distance_nexthop(best_route.nexthop).await{
-                if self.distance_nexthop(route.nexthop).await < self.distance_nexthop(best_route.nexthop).await{
-                    best_route = route;
+            let igp_state = self.igp_info.lock().await;
+            let mut entries = vec![];
+            for route in routes{
+                if let Some(port) = igp_state.get_port(route.nexthop).await{
+                    entries.push((route, port));
                 }
-            }else if route.router_id < best_route.router_id{
-                    best_route = route;
             }
+            table.insert(*prefix, entries);
         }
+        table
+    }
 
-        Some(best_route.clone())
+    pub async fn set_multipath(&mut self, enabled: bool){
+        let name = self.router_info.lock().await.name.clone();
+        self.logger.borrow().log(Source::BGP, format!("Router {} {} BGP multipath", name, if enabled { "enabled" } else { "disabled" })).await;
+        self.multipath = enabled;
     }
 
-    pub async fn send_update(&self, prefix: IPPrefix, nexthop: Ipv4Addr, mut as_path: Vec<u32>, pref_from: u32) {
+    pub async fn send_update(&mut self, prefix: IPPrefix, nexthop: Ipv4Addr, as_path: Vec<u32>, communities: &[u32], origin: Origin, pref_from: u32) {
+        let igp_med = match self.decision_process(prefix).await{
+            Some(best) if !self.igp_meds.is_empty() => self.igp_med(&best).await,
+            _ => 0,
+        };
         let info = self.router_info.lock().await;
-        as_path.insert(0, info.router_as);
         for (port, (pref, med)) in info.bgp_links.iter() {
             let (_, sender) = info.neighbors_links.get(port).unwrap();
-            if pref_from != 150 && *pref != 150{
+            if pref_from != CUSTOMER_PREF && *pref != CUSTOMER_PREF{
                 // send routes from peer/providers only to customers
                 continue;
             }
-            let message = BGPMessage::Update(prefix.clone(), nexthop, as_path.clone(), *med, info.id);
+            let export_med = match Self::policy_action(&self.export_policies, *port, prefix){
+                PolicyAction::Deny => continue,
+                PolicyAction::SetMed(med) => Some(med),
+                _ => None,
+            };
+            let as_path = Self::advertised_path(&self.prepends, *port, info.router_as, &as_path);
+            self.adj_rib_out.entry(*port).or_default().insert(prefix, as_path.clone());
+            let med = export_med.unwrap_or(Self::session_med(&mut self.igp_meds, *port, *med, prefix, igp_med));
+            let message = BGPMessage::Update(prefix.clone(), nexthop, as_path.clone(), med, info.id, communities.to_vec(), origin);
             self.logger.borrow().log(Source::BGP, format!("Router {} has sent {} on port {}", info.name, message, port)).await;
-            sender
-                .send(Message::BGP(message))
-                .await
-                .expect("Failed to send bgp message");
+            sender.send(Message::Bgp(message)).await;
         }
     }
 
-    pub async fn send_ibgp_update(&self, prefix: IPPrefix, as_path: Vec<u32>, pref_from: u32, med: u32) {
+    pub async fn send_ibgp_update(&self, prefix: IPPrefix, as_path: Vec<u32>, pref_from: u32, med: u32, communities: &[u32], origin: Origin) {
         let igp_state = self.igp_info.lock().await;
         let info =  self.router_info.lock().await;
         let peers = info.ibgp_peers.clone();
@@ -387,28 +872,35 @@ impl BGPState {
         let name = info.name.clone();
         drop(info);
         for peer_addr in peers {
-            let ibgp_message = IBGPMessage::Update(prefix.clone(), self_ip, as_path.clone(), pref_from, med, self_id);
+            let ibgp_message = IBGPMessage::Update(prefix.clone(), self_ip, as_path.clone(), pref_from, med, self_id, self_id, vec![], communities.to_vec(), origin);
             self.logger.borrow().log(Source::BGP, format!("Router {} has sent iBGP message {} to peer {}", name, ibgp_message, peer_addr)).await;
             let message = IP{
                 src: self_ip, 
                 dest: peer_addr.clone(), 
-                content: Content::IBGP(ibgp_message)
+                ttl: DEFAULT_TTL,
+                content: Content::Ibgp(ibgp_message),
+                visited: vec![]
             };
             igp_state.send_message(peer_addr.clone(), message).await;
         }
     }
 
-    pub async fn send_withdraw(&self, prefix: IPPrefix, nexthop: Ipv4Addr, mut as_path: Vec<u32>) {
+    // only withdraw the route on the sessions it was advertised on
+    pub async fn send_withdraw(&mut self, prefix: IPPrefix, nexthop: Ipv4Addr, as_path: Vec<u32>) {
         let info = self.router_info.lock().await;
-        as_path.insert(0, info.router_as);
         for (port, _) in info.bgp_links.iter() {
+            let as_path = Self::advertised_path(&self.prepends, *port, info.router_as, &as_path);
+            if self.adj_rib_out.get(port).and_then(|rib| rib.get(&prefix)) != Some(&as_path){
+                continue;
+            }
+            self.adj_rib_out.get_mut(port).unwrap().remove(&prefix);
+            if let Some(meds) = self.igp_meds.get_mut(port){
+                meds.remove(&prefix);
+            }
             let (_, sender) = info.neighbors_links.get(port).unwrap();
             let message = BGPMessage::Withdraw(prefix.clone(), nexthop, as_path.clone(), info.id);
             self.logger.borrow().log(Source::BGP, format!("Router {} has sent {} on port {}", info.name, message, port)).await;
-            sender
-                .send(Message::BGP(message))
-                .await
-                .expect("Failed to send bgp message");
+            sender.send(Message::Bgp(message)).await;
         }
     }
 
@@ -426,26 +918,804 @@ impl BGPState {
             let message = IP{
                 src: self_ip, 
                 dest: peer_addr.clone(), 
-                content: Content::IBGP(ibgp_message)
+                ttl: DEFAULT_TTL,
+                content: Content::Ibgp(ibgp_message),
+                visited: vec![]
             };
             igp_state.send_message(peer_addr.clone(), message).await;
         }
     }
 
 
-    pub async fn announce_prefix(&self) {
+    // local routes are exported to every neighbor, like routes learned from customers
+    // routes of a session with a changed pref are exported according to the relationship of the session
+    // routes carrying a community not exported to peers are exported like the routes of peers
+    fn export_pref(&self, route: &BGPRoute) -> u32{
+        if route.communities.iter().any(|c| self.community_policies.get(c) == Some(&CommunityAction::NoExportToPeers)){
+            return PEER_PREF;
+        }
+        match route.source{
+            RouteSource::Local => CUSTOMER_PREF,
+            RouteSource::Ebgp => self.local_prefs.get(&route.nexthop).map_or(route.pref, |(relationship, _)| *relationship),
+            RouteSource::Ibgp => route.pref,
+        }
+    }
+
+    // prefix originated by the router of ip
+    fn own_prefix(ip: Ipv4Addr) -> IPPrefix{
+        IPPrefix::new(ip, 24)
+    }
+
+    pub async fn announce_prefix(&mut self, pref: u32) {
+        let info = self.router_info.lock().await;
+        self.logger.borrow().log(Source::BGP, format!("Router {} announcing its prefix {} with pref {}", info.name, info.ip, pref)).await;
+        let ip = info.ip;
+        drop(info);
+        self.announce(Self::own_prefix(ip), pref, vec![], Origin::Igp).await;
+    }
+
+    // the communities are carried by the route up to the routers applying policies to them
+    pub async fn announce_prefix_with_communities(&mut self, communities: Vec<u32>) {
+        let info = self.router_info.lock().await;
+        self.logger.borrow().log(Source::BGP, format!("Router {} announcing its prefix {} with communities {:?}", info.name, info.ip, communities)).await;
+        let ip = info.ip;
+        drop(info);
+        self.announce(Self::own_prefix(ip), DEFAULT_LOCAL_PREF, communities, Origin::Igp).await;
+    }
+
+    // e.g. a prefix redistributed into BGP is announced with an incomplete origin
+    pub async fn announce_prefix_with_origin(&mut self, origin: Origin) {
+        let info = self.router_info.lock().await;
+        self.logger.borrow().log(Source::BGP, format!("Router {} announcing its prefix {} with origin {}", info.name, info.ip, origin)).await;
+        let ip = info.ip;
+        drop(info);
+        self.announce(Self::own_prefix(ip), DEFAULT_LOCAL_PREF, vec![], origin).await;
+    }
+
+    // announce a prefix other than the one of our AS, the router answers for its addresses
+    pub async fn announce_custom_prefix(&mut self, prefix: IPPrefix) {
+        let name = self.router_info.lock().await.name.clone();
+        self.logger.borrow().log(Source::BGP, format!("Router {} announcing the custom prefix {}", name, prefix)).await;
+        self.custom_prefixes.insert(prefix);
+        self.announce(prefix, DEFAULT_LOCAL_PREF, vec![], Origin::Igp).await;
+    }
+
+    pub fn answers_for(&self, dest: Ipv4Addr) -> bool{
+        self.custom_prefixes.iter().any(|prefix| prefix.contains(dest))
+    }
+
+    async fn announce(&mut self, prefix: IPPrefix, pref: u32, communities: Vec<u32>, origin: Origin) {
+        let info = self.router_info.lock().await;
+        let ip = info.ip;
+        let router_id = info.id;
+        drop(info);
+        let route = BGPRoute{prefix, nexthop: ip, as_path: vec![], pref, med: 0, router_id, source: RouteSource::Local, communities, origin, learned_from: None};
+
+        let previous_best = self.decision_process(prefix).await;
+        if let Some(routes) = self.routes.get_mut(&prefix){
+            // a new announcement replaces the previous local route
+            routes.retain(|r| r.source != RouteSource::Local);
+        }
+        self.prefixes.insert(prefix, prefix);
+        self.routes.entry(prefix).or_default().insert(route);
+        let best = self.decision_process(prefix).await.unwrap();
+
+        if previous_best.as_ref() != Some(&best){
+            if let Some(previous_best_route) = previous_best{
+                self.send_withdraw(prefix, ip, previous_best_route.as_path.clone()).await;
+                if previous_best_route.source == RouteSource::Ebgp{
+                    self.send_ibgp_withdraw(prefix, previous_best_route.as_path).await;
+                }
+            }
+            if best.source == RouteSource::Local{
+                self.send_update(prefix, ip, vec![], &best.communities, best.origin, self.export_pref(&best)).await;
+            }else{
+                // the local route is less preferred than an already known route, keep advertising the latter
+                self.install_route(best.clone()).await;
+                self.send_update(prefix, ip, best.as_path.clone(), &best.communities, best.origin, self.export_pref(&best)).await;
+                if best.source == RouteSource::Ebgp{
+                    self.send_ibgp_update(prefix, best.as_path, best.pref, best.med, &best.communities, best.origin).await;
+                }
+            }
+        }
+        self.evaluate_conditional_advertisements().await;
+    }
+
+    pub async fn withdraw_prefix(&mut self) {
+        let info = self.router_info.lock().await;
+        let name = info.name.clone();
+        let ip = info.ip;
+        drop(info);
+        let prefix = Self::own_prefix(ip);
+
+        let previous_best = self.decision_process(prefix).await;
+        let routes = match self.routes.get_mut(&prefix){
+            Some(routes) if routes.iter().any(|r| r.source == RouteSource::Local) => routes,
+            _ => return,
+        };
+        self.logger.borrow().log(Source::BGP, format!("Router {} withdrawing its prefix {}", name, prefix)).await;
+        routes.retain(|r| r.source != RouteSource::Local);
+        self.forget_prefix(prefix).await;
+
+        if previous_best.as_ref().is_some_and(|r| r.source == RouteSource::Local){
+            let new_best = self.decision_process(prefix).await;
+            self.advertise_best(prefix, previous_best, new_best).await;
+        }
+        self.evaluate_conditional_advertisements().await;
+    }
+
+    // forget the routes learned from the neighbors and ask them to advertise their routes again
+    pub async fn clear_routes(&mut self) {
         let info = self.router_info.lock().await;
-        self.logger.borrow().log(Source::BGP, format!("Router {} announcing its prefix {}", info.name, info.ip)).await;
+        let name = info.name.clone();
         let ip = info.ip;
+        let ports: Vec<u32> = info.bgp_links.keys().cloned().collect();
+        let peers = info.ibgp_peers.clone();
+        drop(info);
+        self.logger.borrow().log(Source::BGP, format!("Router {} clearing its BGP routes", name)).await;
+
+        let prefixes: Vec<IPPrefix> = self.routes.keys().cloned().collect();
+        for prefix in prefixes{
+            let previous_best = self.decision_process(prefix).await;
+            self.routes.get_mut(&prefix).unwrap().retain(|r| r.source == RouteSource::Local);
+            let best = self.decision_process(prefix).await;
+            self.forget_prefix(prefix).await;
+            if previous_best == best{
+                continue;
+            }
+            if let Some(previous_best_route) = previous_best{
+                self.send_withdraw(prefix, ip, previous_best_route.as_path.clone()).await;
+                if previous_best_route.source == RouteSource::Ebgp{
+                    self.send_ibgp_withdraw(prefix, previous_best_route.as_path).await;
+                }
+            }
+            if let Some(best) = best{
+                self.send_update(prefix, ip, vec![], &best.communities, best.origin, self.export_pref(&best)).await;
+            }
+        }
+        self.evaluate_conditional_advertisements().await;
+
+        let info = self.router_info.lock().await;
+        for port in ports{
+            let (_, sender) = info.neighbors_links.get(&port).unwrap();
+            self.logger.borrow().log(Source::BGP, format!("Router {} has sent {} on port {}", name, BGPMessage::RouteRefresh, port)).await;
+            sender.send(Message::Bgp(BGPMessage::RouteRefresh)).await;
+        }
+        drop(info);
+        let igp_state = self.igp_info.lock().await;
+        for peer_addr in peers{
+            let ibgp_message = IBGPMessage::RouteRefresh(ip);
+            self.logger.borrow().log(Source::BGP, format!("Router {} has sent iBGP message {} to peer {}", name, ibgp_message, peer_addr)).await;
+            let message = IP{src: ip, dest: peer_addr, ttl: DEFAULT_TTL, content: Content::Ibgp(ibgp_message), visited: vec![]};
+            igp_state.send_message(peer_addr, message).await;
+        }
+    }
+
+    // advertise again on the session of port every best route exported to it
+    async fn refresh_session(&mut self, port: u32){
+        let mut updates = vec![];
+        for prefix in self.routes.keys(){
+            if let Some(best) = self.decision_process(*prefix).await{
+                let igp_med = self.igp_med(&best).await;
+                updates.push((best, igp_med));
+            }
+        }
+        let info = self.router_info.lock().await;
+        let (pref, med) = match info.bgp_links.get(&port){
+            Some(link) => *link,
+            None => return,
+        };
+        let (_, sender) = info.neighbors_links.get(&port).unwrap();
+        let mut messages = vec![];
+        for (best, igp_med) in updates{
+            if self.export_pref(&best) != CUSTOMER_PREF && pref != CUSTOMER_PREF{
+                continue;
+            }
+            let export_med = match Self::policy_action(&self.export_policies, port, best.prefix){
+                PolicyAction::Deny => continue,
+                PolicyAction::SetMed(med) => Some(med),
+                _ => None,
+            };
+            let as_path = Self::advertised_path(&self.prepends, port, info.router_as, &best.as_path);
+            self.adj_rib_out.entry(port).or_default().insert(best.prefix, as_path.clone());
+            let med = export_med.unwrap_or(Self::session_med(&mut self.igp_meds, port, med, best.prefix, igp_med));
+            messages.push(BGPMessage::Update(best.prefix, info.ip, as_path, med, info.id, best.communities, best.origin));
+        }
+        for rule in self.conditional_advertisements.iter().filter(|r| r.advertised && r.port == port){
+            // conditional prefixes are our own, their IGP MED is 0
+            let med = Self::session_med(&mut self.igp_meds, port, med, rule.prefix, 0);
+            messages.push(BGPMessage::Update(rule.prefix, info.ip, Self::advertised_path(&self.prepends, port, info.router_as, &[]), med, info.id, vec![], Origin::Igp));
+        }
+        for message in messages{
+            self.logger.borrow().log(Source::BGP, format!("Router {} has sent {} on port {}", info.name, message, port)).await;
+            sender.send(Message::Bgp(message)).await;
+        }
+    }
+
+    // path advertised on the session of port, with our AS prepended once plus the count configured on the session
+    fn advertised_path(prepends: &HashMap<u32, u32>, port: u32, router_as: u32, as_path: &[u32]) -> Vec<u32>{
+        let count = 1 + prepends.get(&port).copied().unwrap_or(0);
+        let mut path = vec![router_as; count as usize];
+        path.extend_from_slice(as_path);
+        path
+    }
+
+    pub async fn set_prepend(&mut self, port: u32, count: u32){
+        if self.prepends.get(&port).copied().unwrap_or(0) == count{
+            return;
+        }
+        // the neighbor keeps the paths advertised with the previous count until they are withdrawn
+        let info = self.router_info.lock().await;
+        let mut messages = vec![];
+        if let Some(rib) = self.adj_rib_out.remove(&port){
+            messages.extend(rib.into_iter().map(|(prefix, as_path)| BGPMessage::Withdraw(prefix, info.ip, as_path, info.id)));
+        }
+        for rule in self.conditional_advertisements.iter().filter(|r| r.advertised && r.port == port){
+            messages.push(BGPMessage::Withdraw(rule.prefix, info.ip, Self::advertised_path(&self.prepends, port, info.router_as, &[]), info.id));
+        }
+        let name = info.name.clone();
         drop(info);
-        let octets = ip.octets();
-        let prefix = IPPrefix{ip: Ipv4Addr::new(octets[0], octets[1], octets[2], 0), prefix_len: 24};
-        self.send_update(prefix, ip, vec![], 150).await;
+        if count == 0{
+            self.prepends.remove(&port);
+        }else{
+            self.prepends.insert(port, count);
+        }
+        self.logger.borrow().log(Source::BGP, format!("Router {} prepends its AS {} more times on port {}", name, count, port)).await;
+        if self.sessions.get(&port).is_none_or(|s| s.state != SessionState::Established){
+            // advertised with the new count once the session is established
+            return;
+        }
+        for message in messages{
+            self.send_session_message(port, message).await;
+        }
+        self.refresh_session(port).await;
+    }
+
+    // the neighbor of port sends its routes again to apply the policy to them
+    pub async fn set_import_policy(&mut self, port: u32, policy: RoutePolicy){
+        let name = self.router_info.lock().await.name.clone();
+        self.logger.borrow().log(Source::BGP, format!("Router {} applies the import policy {} on port {}", name, policy, port)).await;
+        self.import_policies.insert(port, policy);
+        if self.sessions.get(&port).is_some_and(|s| s.state == SessionState::Established){
+            self.send_session_message(port, BGPMessage::RouteRefresh).await;
+        }
+    }
+
+    // the prefixes denied by the policy are withdrawn from the neighbor of port, the others are advertised again
+    pub async fn set_export_policy(&mut self, port: u32, policy: RoutePolicy){
+        let info = self.router_info.lock().await;
+        let (name, ip, id) = (info.name.clone(), info.ip, info.id);
+        drop(info);
+        self.logger.borrow().log(Source::BGP, format!("Router {} applies the export policy {} on port {}", name, policy, port)).await;
+        let mut messages = vec![];
+        if let Some(rib) = self.adj_rib_out.get_mut(&port){
+            let denied: Vec<IPPrefix> = rib.keys().filter(|prefix| policy.action(**prefix) == PolicyAction::Deny).cloned().collect();
+            for prefix in denied{
+                let as_path = rib.remove(&prefix).unwrap();
+                messages.push(BGPMessage::Withdraw(prefix, ip, as_path, id));
+            }
+        }
+        self.export_policies.insert(port, policy);
+        if self.sessions.get(&port).is_none_or(|s| s.state != SessionState::Established){
+            return;
+        }
+        for message in messages{
+            self.send_session_message(port, message).await;
+        }
+        self.refresh_session(port).await;
+    }
+
+    // MED sent for prefix on the session of port, the IGP one is recorded to advertise the prefix again when it changes
+    fn session_med(igp_meds: &mut HashMap<u32, HashMap<IPPrefix, u32>>, port: u32, link_med: u32, prefix: IPPrefix, igp_med: u32) -> u32{
+        match igp_meds.get_mut(&port){
+            Some(meds) => {
+                meds.insert(prefix, igp_med);
+                igp_med
+            },
+            None => link_med,
+        }
     }
 
-    pub async fn get_nexthop(&self, dest: Ipv4Addr) -> Option<Ipv4Addr>{
-        let prefix = self.prefixes.longest_match(dest)?;
-        let best_route = self.decision_process(prefix).await?;
+    // MED of best on the sessions deriving it from the IGP, the distance to its nexthop or 0 for our own prefix
+    async fn igp_med(&self, best: &BGPRoute) -> u32{
+        if best.source == RouteSource::Local{
+            return 0;
+        }
+        self.distance_nexthop(best.nexthop).await
+    }
+
+    // derive the MED sent on the session of port from the IGP distance to the nexthop, or use the one of the link again
+    pub async fn set_igp_med(&mut self, port: u32, enabled: bool){
+        if enabled == self.igp_meds.contains_key(&port){
+            return;
+        }
+        if enabled{
+            self.igp_meds.insert(port, HashMap::new());
+        }else{
+            self.igp_meds.remove(&port);
+        }
+        self.refresh_session(port).await;
+    }
+
+    // advertise again on the sessions deriving their MED from the IGP the routes whose distance to their nexthop changed
+    pub async fn refresh_igp_meds(&mut self){
+        let mut changed = vec![];
+        for (port, meds) in self.igp_meds.iter(){
+            for (prefix, med) in meds.iter(){
+                let igp_med = match self.decision_process(*prefix).await{
+                    Some(best) => self.igp_med(&best).await,
+                    None => continue,
+                };
+                if igp_med != *med{
+                    changed.push(*port);
+                    break;
+                }
+            }
+        }
+        for port in changed{
+            let name = self.router_info.lock().await.name.clone();
+            self.logger.borrow().log(Source::BGP, format!("Router {} advertises again its routes on port {}, their IGP distance changed", name, port)).await;
+            self.refresh_session(port).await;
+        }
+    }
+
+    // advertise again to an iBGP peer the best routes learned over eBGP
+    async fn refresh_ibgp_peer(&self, peer_addr: Ipv4Addr){
+        let info = self.router_info.lock().await;
+        let (self_ip, self_id, name) = (info.ip, info.id, info.name.clone());
+        drop(info);
+        let mut messages = vec![];
+        for prefix in self.routes.keys(){
+            if let Some(best) = self.decision_process(*prefix).await{
+                if best.source == RouteSource::Ebgp{
+                    messages.push(IBGPMessage::Update(best.prefix, self_ip, best.as_path, best.pref, best.med, self_id, self_id, vec![], best.communities, best.origin));
+                }
+            }
+        }
+        for (route, targets) in self.reflected.values(){
+            if let (true, Some((_, originator_id, cluster_list))) = (targets.contains(&peer_addr), self.ibgp_origins.get(&(route.prefix, route.nexthop))){
+                let (originator_id, mut cluster_list) = (*originator_id, cluster_list.clone());
+                cluster_list.push(self_id);
+                messages.push(IBGPMessage::Update(route.prefix, route.nexthop, route.as_path.clone(), route.pref, route.med, route.router_id, originator_id, cluster_list, route.communities.clone(), route.origin));
+            }
+        }
+        let igp_state = self.igp_info.lock().await;
+        for ibgp_message in messages{
+            self.logger.borrow().log(Source::BGP, format!("Router {} has sent iBGP message {} to peer {}", name, ibgp_message, peer_addr)).await;
+            let message = IP{src: self_ip, dest: peer_addr, ttl: DEFAULT_TTL, content: Content::Ibgp(ibgp_message), visited: vec![]};
+            igp_state.send_message(peer_addr, message).await;
+        }
+    }
+
+    // the pref configured on the link of neighbor applies again, or the one of its relationship
+    fn restore_local_pref(local_prefs: &mut HashMap<Ipv4Addr, (u32, u32)>, neighbor: Ipv4Addr, relationship: u32, configured_pref: Option<u32>) -> u32{
+        match configured_pref{
+            Some(pref) => {
+                local_prefs.insert(neighbor, (relationship, pref));
+                pref
+            },
+            None => {
+                local_prefs.remove(&neighbor);
+                relationship
+            },
+        }
+    }
+
+    // apply pref to the routes learned on the session of port instead of the pref of its relationship, or restore the configured one if None
+    pub async fn set_local_pref(&mut self, port: u32, pref: Option<u32>){
+        let info = self.router_info.lock().await;
+        let name = info.name.clone();
+        let relationship = match info.bgp_links.get(&port){
+            Some((relationship, _)) => *relationship,
+            None => return,
+        };
+        drop(info);
+        let neighbor = self.igp_info.lock().await.ebgp_neighbors.get(&port).copied();
+        let neighbor = match neighbor{
+            Some(neighbor) => neighbor,
+            None => return,
+        };
+        let pref = match pref{
+            Some(pref) => {
+                self.local_prefs.insert(neighbor, (relationship, pref));
+                pref
+            },
+            None => {
+                let configured_pref = self.sessions.get(&port).and_then(|s| s.configured_pref);
+                Self::restore_local_pref(&mut self.local_prefs, neighbor, relationship, configured_pref)
+            },
+        };
+        self.logger.borrow().log(Source::BGP, format!("Router {} applies pref {} to the routes of its session on port {}", name, pref, port)).await;
+
+        let prefixes: Vec<IPPrefix> = self.routes.keys().cloned().collect();
+        for prefix in prefixes{
+            let previous_best = self.decision_process(prefix).await;
+            let routes = self.routes.get_mut(&prefix).unwrap();
+            let learned: Vec<BGPRoute> = routes.iter().filter(|r| r.source == RouteSource::Ebgp && r.nexthop == neighbor && r.pref != pref).cloned().collect();
+            if learned.is_empty(){
+                continue;
+            }
+            for route in learned{
+                routes.remove(&route);
+                routes.insert(BGPRoute{pref, ..route});
+            }
+            let best = self.decision_process(prefix).await;
+            if previous_best != best{
+                self.advertise_best(prefix, previous_best, best).await;
+            }
+        }
+        self.evaluate_conditional_advertisements().await;
+    }
+
+    // the routes are received again from the neighbors to apply the policy to them
+    pub async fn add_community_policy(&mut self, community: u32, action: CommunityAction){
+        let name = self.router_info.lock().await.name.clone();
+        self.logger.borrow().log(Source::BGP, format!("Router {} applies {:?} to the routes with community {}", name, action, community)).await;
+        self.community_policies.insert(community, action);
+        self.clear_routes().await;
+    }
+
+    pub async fn add_conditional_advertisement(&mut self, prefix: IPPrefix, unless_present: IPPrefix, port: u32){
+        self.conditional_advertisements.push(ConditionalAdvertisement{prefix, unless_present, port, advertised: false});
+        self.evaluate_conditional_advertisements().await;
+    }
+
+    // announce or withdraw the conditional prefixes whose watched prefix appeared or disappeared from the RIB
+    async fn evaluate_conditional_advertisements(&mut self){
+        let mut changed = vec![];
+        for (i, rule) in self.conditional_advertisements.iter().enumerate(){
+            let present = self.decision_process(rule.unless_present).await.is_some();
+            if present == rule.advertised{
+                changed.push(i);
+            }
+        }
+        if changed.is_empty(){
+            return;
+        }
+        let router_info = Arc::clone(&self.router_info);
+        let info = router_info.lock().await;
+        for i in changed{
+            let rule = &mut self.conditional_advertisements[i];
+            rule.advertised = !rule.advertised;
+            let (_, sender) = match info.neighbors_links.get(&rule.port){
+                Some(neighbor) if info.bgp_links.contains_key(&rule.port) => neighbor,
+                _ => continue, // advertised once the session is established
+            };
+            let message = if rule.advertised{
+                let med = info.bgp_links.get(&rule.port).map(|(_, med)| *med).unwrap_or(0);
+                // conditional prefixes are our own, their IGP MED is 0
+                let med = Self::session_med(&mut self.igp_meds, rule.port, med, rule.prefix, 0);
+                BGPMessage::Update(rule.prefix, info.ip, Self::advertised_path(&self.prepends, rule.port, info.router_as, &[]), med, info.id, vec![], Origin::Igp)
+            }else{
+                if let Some(meds) = self.igp_meds.get_mut(&rule.port){
+                    meds.remove(&rule.prefix);
+                }
+                BGPMessage::Withdraw(rule.prefix, info.ip, Self::advertised_path(&self.prepends, rule.port, info.router_as, &[]), info.id)
+            };
+            self.logger.borrow().log(Source::BGP, format!("Router {} has sent conditional {} on port {}", info.name, message, rule.port)).await;
+            sender.send(Message::Bgp(message)).await;
+        }
+    }
+
+    // the packets of a flow (src, dest) always take the same route when several ones are used in multipath mode
+    pub async fn get_nexthop(&self, src: Ipv4Addr, dest: Ipv4Addr) -> Option<Ipv4Addr>{
+        // the longest prefix with a best route wins, a more specific prefix left without route doesn't hide the others
+        let mut routes = vec![];
+        for prefix in self.prefixes.matches(dest){
+            routes = self.multipath_routes(prefix).await;
+            if !routes.is_empty(){
+                break;
+            }
+        }
+        let mut hasher = DefaultHasher::new();
+        (src, dest).hash(&mut hasher);
+        let best_route = routes.get((hasher.finish() % routes.len().max(1) as u64) as usize)?;
+        if best_route.source == RouteSource::Local{
+            // destinations inside our own prefix are reached through the IGP
+            return None;
+        }
         Some(best_route.nexthop)
     }
 }
+
+// the BGP decision process: highest pref, shortest AS path, lowest origin, lowest MED among the routes of the same neighboring AS,
+// local then eBGP then iBGP routes, closest iBGP nexthop, lowest router id and lowest nexthop
+pub fn select_best(routes: &HashSet<BGPRoute>, igp_distance: impl Fn(Ipv4Addr) -> u32) -> Option<BGPRoute>{
+    let best_pref = routes.iter().map(|r| r.pref).max()?;
+    let routes: Vec<&BGPRoute> = routes.iter().filter(|r| r.pref == best_pref).collect();
+    let best_path_len = routes.iter().map(|r| r.as_path.len()).min()?;
+    let routes: Vec<&BGPRoute> = routes.into_iter().filter(|r| r.as_path.len() == best_path_len).collect();
+    let best_origin = routes.iter().map(|r| r.origin).min()?;
+    let routes: Vec<&BGPRoute> = routes.into_iter().filter(|r| r.origin == best_origin).collect();
+    // locally originated routes have an empty AS path
+    let routes: Vec<&BGPRoute> = routes.iter()
+        .filter(|r| !routes.iter().any(|other| other.as_path.first() == r.as_path.first() && other.med < r.med))
+        .copied()
+        .collect();
+    routes.into_iter().min_by_key(|r| {
+        let (source, distance) = match r.source{
+            RouteSource::Local => (0, 0),
+            RouteSource::Ebgp => (1, 0),
+            RouteSource::Ibgp => (2, igp_distance(r.nexthop)),
+        };
+        (source, distance, r.router_id, r.nexthop)
+    }).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::{mpsc::{channel, Receiver}, Mutex};
+
+    use super::*;
+    use crate::network::{capture::CaptureSlot, limits::Limits, link::{LinkControl, LinkSender}, protocols::arp::ArpState, utils::MacAddress};
+
+    // path of an update without med nor communities
+    fn path(as_path: Vec<u32>, router_id: u32) -> PathAttributes{
+        PathAttributes{as_path, med: 0, router_id, communities: vec![], origin: Origin::Igp}
+    }
+
+    // router r1 of AS 1 with a customer on port 1 and a peer on port 2
+    async fn bgp_state_with_neighbors(logger: Logger) -> (BGPState, Receiver<Message>, Receiver<Message>){
+        let ip = Ipv4Addr::new(10, 0, 1, 1);
+        let (tx_customer, rx_customer) = channel(1024);
+        let (tx_peer, rx_peer) = channel(1024);
+        let router_info = Arc::new(Mutex::new(RouterInfo{
+            name: "r1".into(),
+            id: 1,
+            router_as: 1,
+            ip,
+            mac_address: MacAddress{id: 1},
+            neighbors_links: [
+                (1, (0, LinkSender::new(tx_customer, Arc::new(LinkControl::new(("r1".into(), 1), ("customer".into(), 1), CaptureSlot::default(), logger.clone()))))),
+                (2, (1, LinkSender::new(tx_peer, Arc::new(LinkControl::new(("r1".into(), 2), ("peer".into(), 1), CaptureSlot::default(), logger.clone())))))
+            ].into_iter().collect(),
+            igp_links: HashMap::new(),
+            bgp_links: [(1, (150, 0)), (2, (100, 0))].into_iter().collect(),
+            ibgp_peers: vec![],
+            rr_clients: vec![],
+            interface_addresses: HashMap::new(),
+            limits: Limits::default()
+        }));
+        let arp_state = Arc::new(Mutex::new(ArpState::new(Arc::clone(&router_info), logger.clone())));
+        let mut igp_state = OSPFState::new(ip, logger.clone(), Arc::clone(&router_info), arp_state);
+        let customer: IPPrefix = "10.0.2.2/32".parse().unwrap();
+        igp_state.install(customer, 1, 1, RouteOrigin::Connected);
+        igp_state.prefixes.insert(customer, ());
+        (BGPState::new(router_info, Arc::new(Mutex::new(igp_state)), logger), rx_customer, rx_peer)
+    }
+
+    fn next_bgp_message(receiver: &mut Receiver<Message>) -> BGPMessage{
+        match receiver.try_recv().expect("No message received"){
+            Message::Bgp(message) => message,
+            message => panic!("Unexpected message {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_low_pref_origination_withdrawn_while_learned_route_is_best() {
+        let logger = Logger::start_test();
+        let (mut bgp_state, mut customer, _peer) = bgp_state_with_neighbors(logger).await;
+        let prefix: IPPrefix = "10.0.1.0/24".parse().unwrap();
+        let own_ip = Ipv4Addr::new(10, 0, 1, 1);
+        let customer_ip = Ipv4Addr::new(10, 0, 2, 2);
+
+        bgp_state.announce_prefix(10).await;
+        assert_eq!(next_bgp_message(&mut customer), BGPMessage::Update(prefix, own_ip, vec![1], 0, 1, vec![], Origin::Igp));
+        assert!(customer.try_recv().is_err());
+
+        bgp_state.process_update(1, prefix, customer_ip, path(vec![2], 2)).await;
+        assert_eq!(next_bgp_message(&mut customer), BGPMessage::Withdraw(prefix, own_ip, vec![1], 1));
+        assert_eq!(bgp_state.decision_process(prefix).await.unwrap().source, RouteSource::Ebgp);
+        while customer.try_recv().is_ok(){}
+
+        bgp_state.process_withdraw(1, prefix, customer_ip, vec![2], 2).await;
+        assert_eq!(next_bgp_message(&mut customer), BGPMessage::Withdraw(prefix, own_ip, vec![1, 2], 1));
+        assert_eq!(next_bgp_message(&mut customer), BGPMessage::Update(prefix, own_ip, vec![1], 0, 1, vec![], Origin::Igp));
+        assert_eq!(bgp_state.decision_process(prefix).await.unwrap().source, RouteSource::Local);
+    }
+
+    fn received_bgp_messages(receiver: &mut Receiver<Message>) -> Vec<BGPMessage>{
+        let mut messages = vec![];
+        while let Ok(message) = receiver.try_recv(){
+            if let Message::Bgp(message) = message{
+                messages.push(message);
+            }
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_conditional_advertisement() {
+        let logger = Logger::start_test();
+        let (mut bgp_state, _customer, mut peer) = bgp_state_with_neighbors(logger).await;
+        let backup: IPPrefix = "10.9.0.0/24".parse().unwrap();
+        let watched: IPPrefix = "10.0.2.0/24".parse().unwrap();
+        let own_ip = Ipv4Addr::new(10, 0, 1, 1);
+        let customer_ip = Ipv4Addr::new(10, 0, 2, 2);
+
+        // the watched prefix is absent, the backup is advertised right away
+        bgp_state.add_conditional_advertisement(backup, watched, 2).await;
+        assert_eq!(received_bgp_messages(&mut peer), vec![BGPMessage::Update(backup, own_ip, vec![1], 0, 1, vec![], Origin::Igp)]);
+
+        bgp_state.process_bgp_message(1, BGPMessage::Update(watched, customer_ip, vec![2], 0, 2, vec![], Origin::Igp)).await;
+        let messages = received_bgp_messages(&mut peer);
+        assert!(messages.contains(&BGPMessage::Withdraw(backup, own_ip, vec![1], 1)));
+        assert!(!messages.contains(&BGPMessage::Update(backup, own_ip, vec![1], 0, 1, vec![], Origin::Igp)));
+
+        bgp_state.process_bgp_message(1, BGPMessage::Withdraw(watched, customer_ip, vec![2], 2)).await;
+        assert!(received_bgp_messages(&mut peer).contains(&BGPMessage::Update(backup, own_ip, vec![1], 0, 1, vec![], Origin::Igp)));
+
+        bgp_state.process_bgp_message(1, BGPMessage::Update(watched, customer_ip, vec![2], 0, 2, vec![], Origin::Igp)).await;
+        assert!(received_bgp_messages(&mut peer).contains(&BGPMessage::Withdraw(backup, own_ip, vec![1], 1)));
+    }
+
+    #[tokio::test]
+    async fn test_conditional_advertisement_igp_med() {
+        let logger = Logger::start_test();
+        let (mut bgp_state, _customer, mut peer) = bgp_state_with_neighbors(logger).await;
+        bgp_state.router_info.lock().await.bgp_links.insert(2, (100, 7));
+        let backup: IPPrefix = "10.9.0.0/24".parse().unwrap();
+        let other: IPPrefix = "10.8.0.0/24".parse().unwrap();
+        let watched: IPPrefix = "10.0.2.0/24".parse().unwrap();
+        let own_ip = Ipv4Addr::new(10, 0, 1, 1);
+
+        bgp_state.add_conditional_advertisement(backup, watched, 2).await;
+        assert_eq!(received_bgp_messages(&mut peer), vec![BGPMessage::Update(backup, own_ip, vec![1], 7, 1, vec![], Origin::Igp)]);
+
+        // the session derives its MED from the IGP, like the other exports our own prefixes carry 0
+        bgp_state.set_igp_med(2, true).await;
+        assert_eq!(received_bgp_messages(&mut peer), vec![BGPMessage::Update(backup, own_ip, vec![1], 0, 1, vec![], Origin::Igp)]);
+        bgp_state.add_conditional_advertisement(other, watched, 2).await;
+        assert_eq!(received_bgp_messages(&mut peer), vec![BGPMessage::Update(other, own_ip, vec![1], 0, 1, vec![], Origin::Igp)]);
+        assert_eq!(bgp_state.igp_meds[&2].get(&other), Some(&0));
+    }
+
+    #[tokio::test]
+    async fn test_own_prefix_is_best_route() {
+        let logger = Logger::start_test();
+        let (mut bgp_state, _customer, _peer) = bgp_state_with_neighbors(logger).await;
+        let prefix: IPPrefix = "10.0.1.0/24".parse().unwrap();
+        let customer_ip = Ipv4Addr::new(10, 0, 2, 2);
+
+        bgp_state.announce_prefix(150).await;
+        // a customer route with the same preference, the empty AS path of the own route must win
+        bgp_state.process_update(1, prefix, customer_ip, path(vec![2], 2)).await;
+
+        let routes = &bgp_state.routes[&prefix];
+        assert_eq!(routes.len(), 2);
+        assert!(routes.iter().any(|route| route.source == RouteSource::Local && route.as_path.is_empty()));
+        let best = bgp_state.decision_process(prefix).await.unwrap();
+        assert_eq!(best.source, RouteSource::Local);
+        assert_eq!(best.nexthop, Ipv4Addr::new(10, 0, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_only_where_advertised() {
+        let logger = Logger::start_test();
+        let (mut bgp_state, mut customer, mut peer) = bgp_state_with_neighbors(logger).await;
+        let peer_prefix: IPPrefix = "10.0.3.3/32".parse().unwrap();
+        let mut igp_state = bgp_state.igp_info.lock().await;
+        igp_state.install(peer_prefix, 2, 1, RouteOrigin::Connected);
+        igp_state.prefixes.insert(peer_prefix, ());
+        drop(igp_state);
+        let prefix: IPPrefix = "10.0.5.0/24".parse().unwrap();
+        let own_ip = Ipv4Addr::new(10, 0, 1, 1);
+        let peer_ip = Ipv4Addr::new(10, 0, 3, 3);
+
+        // a route learned from a peer is only exported to the customer
+        bgp_state.process_update(2, prefix, peer_ip, path(vec![3, 5], 3)).await;
+        assert_eq!(received_bgp_messages(&mut customer), vec![BGPMessage::Update(prefix, own_ip, vec![1, 3, 5], 0, 1, vec![], Origin::Igp)]);
+        assert_eq!(received_bgp_messages(&mut peer), vec![]);
+
+        bgp_state.process_withdraw(2, prefix, peer_ip, vec![3, 5], 3).await;
+        assert_eq!(received_bgp_messages(&mut customer), vec![BGPMessage::Withdraw(prefix, own_ip, vec![1, 3, 5], 1)]);
+        assert_eq!(received_bgp_messages(&mut peer), vec![]);
+        assert!(bgp_state.adj_rib_out.values().all(|rib| rib.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_routes_per_prefix_limit() {
+        let logger = Logger::start_test();
+        let (mut bgp_state, _customer, _peer) = bgp_state_with_neighbors(logger).await;
+        bgp_state.router_info.lock().await.limits.max_routes_per_prefix = 2;
+        let prefix: IPPrefix = "10.0.5.0/24".parse().unwrap();
+        let customer_ip = Ipv4Addr::new(10, 0, 2, 2);
+
+        bgp_state.process_update(1, prefix, customer_ip, path(vec![2, 5], 2)).await;
+        bgp_state.process_update(1, prefix, customer_ip, path(vec![2, 3, 4, 5], 2)).await;
+        bgp_state.process_update(1, prefix, customer_ip, path(vec![2, 4, 5], 2)).await;
+
+        let routes = &bgp_state.routes[&prefix];
+        assert_eq!(routes.len(), 2);
+        assert!(routes.iter().all(|route| route.as_path != vec![2, 3, 4, 5]));
+        assert_eq!(bgp_state.route_evictions, 1);
+        assert_eq!(bgp_state.decision_process(prefix).await.unwrap().as_path, vec![2, 5]);
+    }
+
+    fn decision_route(nexthop: &str, as_path: Vec<u32>, pref: u32, med: u32, source: RouteSource) -> BGPRoute{
+        let router_id = nexthop.split('.').last().unwrap().parse().unwrap();
+        BGPRoute{prefix: "10.0.9.0/24".parse().unwrap(), nexthop: nexthop.parse().unwrap(), as_path, pref, med, router_id, source, communities: vec![], origin: Origin::Igp, learned_from: None}
+    }
+
+    fn best_of(routes: &[BGPRoute], distances: &[(Ipv4Addr, u32)]) -> BGPRoute{
+        let routes: HashSet<BGPRoute> = routes.iter().cloned().collect();
+        let distance = |nexthop| distances.iter().find(|(n, _)| *n == nexthop).map(|(_, d)| *d).unwrap_or(u32::MAX);
+        select_best(&routes, distance).unwrap()
+    }
+
+    #[test]
+    fn test_select_best_empty() {
+        assert_eq!(select_best(&HashSet::new(), |_| 0), None);
+    }
+
+    #[test]
+    fn test_select_best_pref() {
+        let customer = decision_route("10.0.2.2", vec![2, 8, 9], CUSTOMER_PREF, 50, RouteSource::Ebgp);
+        let peer = decision_route("10.0.3.3", vec![9], PEER_PREF, 0, RouteSource::Ebgp);
+        let local = decision_route("10.0.1.1", vec![], PEER_PREF, 0, RouteSource::Local);
+        // the longer path and higher MED of the customer route don't matter
+        assert_eq!(best_of(&[peer.clone(), customer.clone()], &[]), customer);
+        assert_eq!(best_of(&[peer, customer.clone(), local], &[]), customer);
+    }
+
+    #[test]
+    fn test_select_best_path_length() {
+        let peer = decision_route("10.0.3.3", vec![3, 9], PEER_PREF, 10, RouteSource::Ebgp);
+        let long = decision_route("10.0.4.4", vec![4, 8, 9], PEER_PREF, 0, RouteSource::Ebgp);
+        assert_eq!(best_of(&[long.clone(), peer.clone()], &[]), peer);
+
+        // a shorter path from a less preferred group doesn't change the path length of the best one
+        let provider = decision_route("10.0.2.2", vec![9], PROVIDER_PREF, 0, RouteSource::Ebgp);
+        assert_eq!(best_of(&[provider, long, peer.clone()], &[]), peer);
+    }
+
+    #[test]
+    fn test_select_best_origin() {
+        let incomplete = BGPRoute{origin: Origin::Incomplete, ..decision_route("10.0.4.4", vec![4], PEER_PREF, 0, RouteSource::Ebgp)};
+        let egp = BGPRoute{origin: Origin::Egp, ..decision_route("10.0.5.5", vec![5], PEER_PREF, 10, RouteSource::Ebgp)};
+        let igp = decision_route("10.0.6.6", vec![6], PEER_PREF, 20, RouteSource::Ibgp);
+        // the origin goes before the MED and the kind of session
+        assert_eq!(best_of(&[incomplete.clone(), egp.clone()], &[]), egp);
+        assert_eq!(best_of(&[incomplete, egp, igp.clone()], &[]), igp);
+    }
+
+    #[test]
+    fn test_select_best_med_per_neighbor_as() {
+        let as4_low = decision_route("10.0.4.5", vec![4, 9], PEER_PREF, 5, RouteSource::Ebgp);
+        let as4_high = decision_route("10.0.4.4", vec![4, 9], PEER_PREF, 50, RouteSource::Ebgp);
+        let as3 = decision_route("10.0.3.3", vec![3, 9], PEER_PREF, 100, RouteSource::Ebgp);
+        assert_eq!(best_of(&[as4_low.clone(), as4_high.clone()], &[]), as4_low);
+        // the MED of AS4 doesn't rule out the route of AS3, the router id decides between them
+        assert_eq!(best_of(&[as4_low, as4_high, as3.clone()], &[]), as3);
+    }
+
+    #[test]
+    fn test_select_best_ebgp_over_ibgp() {
+        let ibgp = decision_route("10.0.1.2", vec![9], PEER_PREF, 0, RouteSource::Ibgp);
+        let ebgp = decision_route("10.0.3.3", vec![9], PEER_PREF, 0, RouteSource::Ebgp);
+        let local = decision_route("10.0.1.4", vec![], PEER_PREF, 0, RouteSource::Local);
+        assert_eq!(best_of(&[ibgp.clone(), ebgp.clone()], &[(ibgp.nexthop, 0)]), ebgp);
+        // a prefix of our own AS learned from an iBGP peer
+        let own_ibgp = BGPRoute{as_path: vec![], ..ibgp};
+        assert_eq!(best_of(&[own_ibgp, local.clone()], &[]), local);
+    }
+
+    #[test]
+    fn test_select_best_igp_distance() {
+        let close = decision_route("10.0.1.2", vec![9], PEER_PREF, 0, RouteSource::Ibgp);
+        let far = decision_route("10.0.1.3", vec![9], PEER_PREF, 0, RouteSource::Ibgp);
+        assert_eq!(best_of(&[close.clone(), far.clone()], &[(close.nexthop, 20), (far.nexthop, 10)]), far);
+        assert_eq!(best_of(&[close.clone(), far.clone()], &[(close.nexthop, 10), (far.nexthop, 20)]), close);
+    }
+
+    #[test]
+    fn test_select_best_router_id() {
+        let low = decision_route("10.0.3.3", vec![9], PEER_PREF, 0, RouteSource::Ebgp);
+        let high = decision_route("10.0.5.5", vec![9], PEER_PREF, 0, RouteSource::Ebgp);
+        assert_eq!(best_of(&[high.clone(), low.clone()], &[]), low);
+
+        // the same router reached through two links is decided by the nexthop
+        let twin = BGPRoute{router_id: low.router_id, ..decision_route("10.0.3.4", vec![9], PEER_PREF, 0, RouteSource::Ebgp)};
+        assert_eq!(best_of(&[twin, high, low.clone()], &[]), low);
+    }
+}
@@ -1,4 +1,5 @@
 
 pub mod ospf;
 pub mod bgp;
-pub mod arp;
\ No newline at end of file
+pub mod arp;
+pub mod policy;
\ No newline at end of file
@@ -1,21 +1,61 @@
-use std::{collections::{hash_map::Entry, BinaryHeap, HashMap, HashSet}, net::Ipv4Addr};
+use std::{collections::{hash_map::{DefaultHasher, Entry}, BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet}, fmt::Display, hash::{Hash, Hasher}, net::Ipv4Addr, time::{Duration, Instant}};
 
-use tokio::sync::mpsc::Sender;
+use serde::Serialize;
 
-use crate::network::{ip_prefix::IPPrefix, ip_trie::IPTrie, logger::{Logger, Source}, messages::{ip::IP, ospf::OSPFMessage::{self, *}, Message}, router::RouterInfo, utils::{MacAddress, SharedState}};
+use crate::network::{events::{self, EventSender, NetworkEvent}, ip_prefix::IPPrefix, ip_trie::IPTrie, link::LinkSender, logger::{Logger, Source}, messages::{ip::IP, ospf::OSPFMessage::{self, *}, Message}, rib::{RIBEntry, RouteOrigin}, router::RouterInfo, utils::{MacAddress, SharedState, HELLO_INTERVAL}};
 
 use super::arp::ArpState;
 
-#[derive(Ord, PartialEq, Eq, Hash, Clone)]
+// a neighbor that didn't answer our hellos for this long is considered dead
+pub const DEAD_INTERVAL: Duration = HELLO_INTERVAL.saturating_mul(3);
+
+// a router floods its lsp again after this long, the lsps not refreshed for LSP_MAX_AGE_INTERVALS periods are forgotten
+pub const LSP_REFRESH_INTERVAL: Duration = HELLO_INTERVAL.saturating_mul(25);
+pub const LSP_MAX_AGE_INTERVALS: u32 = 3;
+
+#[derive(PartialEq, Eq, Hash, Clone)]
 pub struct Node{
     distance: u32,
     ip: IPPrefix,
     port: u32
 }
 
+// reversed on the distance, so that the binary heap pops the closest node first
+impl Ord for Node{
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.distance.cmp(&self.distance)
+    }
+}
+
 impl PartialOrd for Node{
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        other.distance.partial_cmp(&self.distance)
+        Some(self.cmp(other))
+    }
+}
+
+// the newest lsp of an originator known by a router
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LsaInfo{
+    pub seq: u32,
+    pub age_ms: u64, // since it was received, or flooded for our own
+    pub neighbors: BTreeSet<(u32, IPPrefix)>, // cost, neighbor, ours lists the neighbors found by our hellos
+}
+
+// link-state database of a router, the topology its shortest paths are computed on
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Lsdb{
+    pub router: String,
+    pub originators: BTreeMap<Ipv4Addr, LsaInfo>,
+}
+
+impl Display for Lsdb{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "LSDB of router {}", self.router)?;
+        for (originator, lsa) in self.originators.iter(){
+            let neighbors: Vec<String> = lsa.neighbors.iter().map(|(cost, prefix)| format!("{} cost {}", prefix, cost)).collect();
+            writeln!(f, "  {} seq {}, {}ms old: {}", originator, lsa.seq, lsa.age_ms, neighbors.join(", "))?;
+        }
+        Ok(())
     }
 }
 
@@ -23,12 +63,27 @@ impl PartialOrd for Node{
 pub struct OSPFState{
     pub topo: HashMap<Ipv4Addr, HashSet<(u32, IPPrefix)>>,
     pub direct_neighbors: HashSet<(u32, u32, IPPrefix)>,
-    pub routing_table: HashMap<IPPrefix, (u32, u32)>,  // (port, distance)
-    pub prefixes: IPTrie<IPPrefix>,
-    pub received_lsp: HashSet<(Ipv4Addr, u32)>,
+    pub ebgp_neighbors: HashMap<u32, Ipv4Addr>, // port -> router of another AS, reached directly but never advertised in our lsp
+    pub neighbor_addresses: HashMap<u32, Ipv4Addr>, // port -> address of the neighbor on the subnet of the link
+    pub last_hello: HashMap<(u32, IPPrefix), Instant>, // last hello reply of each neighbor found by the hellos
+    pub dead_interval: Duration,
+    pub routing_table: HashMap<IPPrefix, RIBEntry>,
+    pub equal_cost_ports: HashMap<IPPrefix, Vec<u32>>, // ports of the shortest paths towards the destinations reached over several ones, lowest first
+    pub ecmp: bool, // the flows are spread over the equal cost ports instead of all taking the lowest one
+    pub igp_routes: HashSet<IPPrefix>, // destinations reached by the last shortest path computation
+    pub static_routes: HashMap<IPPrefix, (u32, Ipv4Addr)>, // port, nexthop
+    pub enabled: bool, // hellos and lsps are neither sent nor processed while disabled
+    pub prefixes: IPTrie<()>,
+    pub received_lsp: HashMap<Ipv4Addr, (u32, Instant)>, // newest seq of each originator and when it was received
     pub lsp_seq: u32,
+    pub lsp_evictions: u64,
+    pub topo_changed: bool,  // lsps were received since the last shortest path computation
+    pub lsp_pending: bool,   // neighbors were found since our last lsp, it is flooded with the next computation
+    pub spf_runs: u64,       // shortest path computations since the router started
+    pub routes_changed: bool, // routes were computed since BGP last compared the distances to its nexthops
     pub router_info: SharedState<RouterInfo>,
     pub arp_state: SharedState<ArpState>,
+    pub events: EventSender,
     pub logger: Logger
 }
 
@@ -36,74 +91,149 @@ impl OSPFState{
     pub fn new(ip: Ipv4Addr, logger: Logger, router_info: SharedState<RouterInfo>, arp_state: SharedState<ArpState>) -> OSPFState{
         let prefix = IPPrefix{ip, prefix_len: 32};
         let mut prefixes = IPTrie::new();
-        prefixes.insert(prefix, prefix);
+        prefixes.insert(prefix, ());
         OSPFState{
             topo: HashMap::new(),
             direct_neighbors: HashSet::new(),
-            routing_table: [(prefix, (0, 0))].into_iter().collect(),
+            ebgp_neighbors: HashMap::new(),
+            neighbor_addresses: HashMap::new(),
+            last_hello: HashMap::new(),
+            dead_interval: DEAD_INTERVAL,
+            routing_table: [(prefix, (0, 0, RouteOrigin::Connected))].into_iter().collect(),
+            equal_cost_ports: HashMap::new(),
+            ecmp: false,
+            igp_routes: HashSet::new(),
+            static_routes: HashMap::new(),
+            enabled: true,
             prefixes,
-            received_lsp: HashSet::new(),
+            received_lsp: HashMap::new(),
             lsp_seq: 0,
+            lsp_evictions: 0,
+            topo_changed: false,
+            lsp_pending: false,
+            spf_runs: 0,
+            routes_changed: false,
             router_info,
             arp_state,
+            events: EventSender::new(events::EVENT_CAPACITY),
             logger
         }
     }
 
     pub async fn send_message(&self, nexthop: Ipv4Addr, content: IP){
-        if let Some((port, mac)) = self.get_port_mac(nexthop).await{
+        if let Some((port, mac)) = self.get_port_mac(nexthop, Some((content.src, content.dest))).await{
             let info_router = self.router_info.lock().await;
-            let (_, sender) = info_router.neighbors_links.get(&port).unwrap();
-            sender.send(Message::EthernetFrame(mac, content)).await.expect("Failed to send ethernet frame");
+            // the link of the port may have been removed since the route was computed
+            let Some((_, sender)) = info_router.neighbors_links.get(&port) else {
+                return;
+            };
+            sender.send(Message::EthernetFrame(info_router.mac_address.clone(), mac, content)).await;
         }
     }
 
-    pub async fn get_port_mac(&self, ip: Ipv4Addr) -> Option<(u32, MacAddress)>{
-        let prefix = self.prefixes.longest_match(ip)?;
-        let (port, _) = self.routing_table.get(&prefix)?;
-        for (_, p, prefix) in self.direct_neighbors.iter(){
-            if p == port{
-                let arp_state = self.arp_state.lock().await;
-                let mac_address = arp_state.mapping.get(&prefix.ip);
-                if mac_address.is_some(){
-                    return Some((*p, mac_address.unwrap().clone()));
-                }
+    // with ecmp, the packets of a flow (src, dest) always leave on the same port among the equal cost ones
+    pub async fn get_port_mac(&self, ip: Ipv4Addr, flow: Option<(Ipv4Addr, Ipv4Addr)>) -> Option<(u32, MacAddress)>{
+        let (prefix, _) = self.prefixes.longest_match_entry(ip)?;
+        let (_, _, origin) = self.routing_table.get(&prefix)?;
+        let ports = self.get_ports(ip).await;
+        let port = match flow{
+            Some(flow) if self.ecmp && ports.len() > 1 => {
+                let mut hasher = DefaultHasher::new();
+                flow.hash(&mut hasher);
+                &ports[(hasher.finish() % ports.len() as u64) as usize]
+            },
+            _ => ports.first()?,
+        };
+        let mut neighbors: Vec<Ipv4Addr> = self.direct_neighbors.iter().filter(|(_, p, _)| p == port).map(|(_, p, prefix)| self.neighbor_address(*p, prefix.ip)).collect();
+        neighbors.extend(self.ebgp_neighbors.get(port));
+        if let Some((_, nexthop)) = self.static_routes.get(&prefix){
+            neighbors.insert(0, *nexthop);
+        }
+        if *origin == RouteOrigin::Connected{
+            // the destination is on the link, e.g. a host of the LAN of the port
+            neighbors.push(ip);
+        }
+        // several routers may be behind the port of a LAN, the destination itself is preferred
+        neighbors.sort_by_key(|neighbor| *neighbor != ip);
+        let arp_state = self.arp_state.lock().await;
+        for neighbor in neighbors{
+            if let Some(mac_address) = arp_state.mapping.get(&neighbor){
+                return Some((*port, mac_address.clone()));
             }
         }
         None
     }
 
+    // a neighbor linked by a subnet is reached at its address on the subnet, the others at their own address
+    pub fn neighbor_address(&self, port: u32, ip: Ipv4Addr) -> Ipv4Addr{
+        self.neighbor_addresses.get(&port).copied().unwrap_or(ip)
+    }
+
     pub async fn get_port(&self, ip: Ipv4Addr) -> Option<u32>{
-        let prefix = self.prefixes.longest_match(ip)?;
-        let (port, _) = self.routing_table.get(&prefix)?;
+        let (prefix, _) = self.prefixes.longest_match_entry(ip)?;
+        let (port, _, _) = self.routing_table.get(&prefix)?;
         Some(*port)
     }
 
+    // the ports of all the shortest paths towards ip, the one of get_port first
+    pub async fn get_ports(&self, ip: Ipv4Addr) -> Vec<u32>{
+        let Some((prefix, _)) = self.prefixes.longest_match_entry(ip) else {
+            return vec![];
+        };
+        match (self.routing_table.get(&prefix), self.equal_cost_ports.get(&prefix)){
+            (Some((_, _, RouteOrigin::Ospf)), Some(ports)) => ports.clone(),
+            (Some((port, _, _)), _) => vec![*port],
+            (None, _) => vec![],
+        }
+    }
+
     pub async fn process_ospf(&mut self, ospf: OSPFMessage, port: u32){
+        if !self.enabled{
+            return;
+        }
         match ospf{
             Hello => self.send_hello_reply(port).await,
-            LSP(from, seq, neighbors) => self.process_lsp(from, seq, neighbors).await,
-            HelloReply(ip) => self.process_hello_reply(ip, port).await,
+            Lsp(from, seq, neighbors) => self.process_lsp(from, seq, neighbors, port).await,
+            HelloReply(ip, address) => self.process_hello_reply(ip, address, port).await,
         }
     }
 
     pub async fn shortest_path(&mut self){
-        let mut visited = HashSet::new();
+        self.topo_changed = false;
+        self.routes_changed = true;
+        self.spf_runs += 1;
+        let previous = self.routing_table.clone();
+        let previous_ports = std::mem::take(&mut self.equal_cost_ports);
+        // distance and first ports of the shortest paths towards each destination
+        let mut reached: HashMap<Ipv4Addr, (IPPrefix, u32, Vec<u32>)> = HashMap::new();
         let mut pq = BinaryHeap::new();
 
-        visited.insert(self.get_ip().await);
+        let own_ip = self.get_ip().await;
+        // every destination known by the igp, to forget the ones that became unreachable
+        let mut known: HashSet<IPPrefix> = self.topo.values().flatten().map(|(_, prefix)| *prefix).collect();
+        // a dead router may not appear in any lsp anymore
+        known.extend(self.igp_routes.drain());
         for (cost, port, ip) in self.direct_neighbors.iter(){
             pq.push(Node{distance: *cost, ip: ip.clone(), port: *port});
         }
 
-        while !pq.is_empty(){
-            let p = pq.pop().unwrap();
-            if visited.contains(&p.ip.ip){
+        while let Some(p) = pq.pop(){
+            if p.ip.ip == own_ip{
                 continue;
             }
-            self.routing_table.insert(p.ip, (p.port, p.distance));
-            self.prefixes.insert(p.ip, p.ip);
-            visited.insert(p.ip.ip);
+            match reached.entry(p.ip.ip){
+                // a path as short as the first one, its port also leads to the destinations behind
+                Entry::Occupied(mut o) => {
+                    let (_, distance, ports) = o.get_mut();
+                    if p.distance != *distance || ports.contains(&p.port){
+                        continue;
+                    }
+                    ports.push(p.port);
+                },
+                Entry::Vacant(v) => {
+                    v.insert((p.ip, p.distance, vec![p.port]));
+                },
+            }
             let neighs = self.topo.get(&p.ip.ip);
             if let Some(n) = neighs{
                 for (cost, neigh) in n{
@@ -111,37 +241,154 @@ impl OSPFState{
                 }
             }
         }
-        self.logger.log(Source::OSPF, format!("Router {} has updated its routing table : {:?}", self.get_name().await, self.routing_table)).await;
+        // the lowest port is installed whatever order the paths were found in
+        for (prefix, distance, ports) in reached.values_mut(){
+            ports.sort();
+            if self.install(*prefix, ports[0], *distance, RouteOrigin::Ospf) && ports.len() > 1{
+                self.equal_cost_ports.insert(*prefix, ports.clone());
+            }
+            self.prefixes.insert(*prefix, ());
+            self.igp_routes.insert(*prefix);
+        }
+        for prefix in known{
+            if !reached.contains_key(&prefix.ip) && self.routing_table.get(&prefix).is_some_and(|(_, _, origin)| *origin == RouteOrigin::Ospf){
+                self.forget_prefix(prefix).await;
+            }
+        }
+        let name = self.get_name().await;
+        let changed = previous.keys().chain(self.routing_table.keys())
+            .filter(|prefix| previous.get(prefix) != self.routing_table.get(prefix) || previous_ports.get(prefix) != self.equal_cost_ports.get(prefix));
+        for prefix in changed.collect::<HashSet<_>>(){
+            events::emit(&self.events, NetworkEvent::OspfRouteChanged{router: name.clone(), prefix: *prefix});
+        }
+        self.logger.log(Source::OSPF, format!("Router {} has updated its routing table : {:?}", name, self.routing_table)).await;
+        if !self.equal_cost_ports.is_empty(){
+            self.logger.log(Source::OSPF, format!("Router {} has equal cost paths : {:?}", name, self.equal_cost_ports)).await;
+        }
+    }
+
+    pub async fn set_ecmp(&mut self, enabled: bool){
+        self.ecmp = enabled;
+        self.logger.log(Source::OSPF, format!("Router {} {} ECMP", self.get_name().await, if enabled { "enabled" } else { "disabled" })).await;
+    }
+
+    // the route isn't removed by OSPF, only when the link of its port is removed
+    pub async fn add_static_route(&mut self, prefix: IPPrefix, port: u32, nexthop: Ipv4Addr){
+        self.static_routes.insert(prefix, (port, nexthop));
+        self.install(prefix, port, 1, RouteOrigin::Static);
+        self.prefixes.insert(prefix, ());
+        self.logger.log(Source::OSPF, format!("Router {} added static route to {} via {} on port {}", self.get_name().await, prefix, nexthop, port)).await;
+    }
+
+    // the subnet of the link of port is advertised to the other routers
+    pub async fn add_connected_subnet(&mut self, subnet: IPPrefix, port: u32){
+        self.install(subnet, port, 0, RouteOrigin::Connected);
+        self.prefixes.insert(subnet, ());
+        self.logger.log(Source::OSPF, format!("Router {} is connected to subnet {} on port {}", self.get_name().await, subnet, port)).await;
+        self.flood_neighbors().await;
     }
 
-    pub async fn process_lsp(&mut self, from: Ipv4Addr, seq: u32, neighbors: HashSet<(u32, IPPrefix)>){
-        if self.received_lsp.contains(&(from, seq)){
+    // a disabled router forgets the neighbors and routes it learned with OSPF
+    pub async fn set_enabled(&mut self, enabled: bool){
+        if self.enabled == enabled{
             return;
         }
-        self.received_lsp.insert((from, seq));
-        let values = match self.topo.entry(from) {
-            Entry::Occupied(o) => o.into_mut(),
-            Entry::Vacant(v) => v.insert(HashSet::new()),
-        };
+        self.enabled = enabled;
+        if !enabled{
+            let learned: Vec<(u32, IPPrefix)> = self.last_hello.drain().map(|(neighbor, _)| neighbor).collect();
+            self.direct_neighbors.retain(|(_, port, prefix)| !learned.contains(&(*port, *prefix)));
+            self.topo.clear();
+            self.received_lsp.clear();
+            self.shortest_path().await;
+        }
+        self.logger.log(Source::OSPF, format!("Router {} {} OSPF", self.get_name().await, if enabled { "enabled" } else { "disabled" })).await;
+    }
 
-        values.extend(neighbors.iter());
-        self.shortest_path().await;
+    async fn forget_prefix(&mut self, prefix: IPPrefix){
+        self.routing_table.remove(&prefix);
+        self.prefixes.remove(prefix);
+        let compact_after = self.router_info.lock().await.limits.trie_compact_after;
+        self.prefixes.compact_after(compact_after);
+    }
+
+    // the route is only installed if no more trusted protocol installed one for the prefix
+    pub fn install(&mut self, prefix: IPPrefix, port: u32, distance: u32, origin: RouteOrigin) -> bool{
+        if self.routing_table.get(&prefix).is_some_and(|(_, _, current)| !origin.replaces(current)){
+            return false;
+        }
+        self.routing_table.insert(prefix, (port, distance, origin));
+        true
+    }
+
+    // remove the route of prefix if origin installed it
+    pub fn uninstall(&mut self, prefix: IPPrefix, origin: RouteOrigin){
+        if self.routing_table.get(&prefix).is_some_and(|(_, _, current)| *current == origin){
+            self.routing_table.remove(&prefix);
+        }
+    }
+
+    pub async fn process_lsp(&mut self, from: Ipv4Addr, seq: u32, neighbors: HashSet<(u32, IPPrefix)>, port: u32){
+        if from == self.get_ip().await{
+            // a lsp of ours numbered after our last one was sent before we restarted, ours must be numbered after it
+            if seq >= self.lsp_seq{
+                self.lsp_seq = seq + 1;
+                self.flood_neighbors().await;
+            }
+            return;
+        }
+        let newest = self.received_lsp.get(&from).map(|(newest, _)| *newest);
+        if let Some(newest) = newest.filter(|newest| seq < *newest){
+            // superseded by a lsp already flooded
+            // the sender gets the newest one back, it may be the origin numbering its lsps from 0 again after a restart
+            if let Some(lsp_neighbors) = self.topo.get(&from){
+                let lsp = OSPFMessage::Lsp(from, newest, lsp_neighbors.clone());
+                if let Some((sender, _)) = self.get_igp_neighbors().await.get(&port){
+                    self.logger.log(Source::OSPF, format!("Router {} sending back {:?} on port {}, it is newer than the received one", self.get_name().await, lsp, port)).await;
+                    sender.send(Message::Ospf(lsp)).await;
+                }
+            }
+            return;
+        }
+        if newest == Some(seq){
+            return;
+        }
+        self.remember_lsp(from, seq).await;
+        // a lsp describes all the neighbors of its sender, older ones are outdated
+        // a refresh of the same neighbors doesn't change the shortest paths
+        if self.topo.get(&from) != Some(&neighbors){
+            self.topo.insert(from, neighbors.clone());
+            self.topo_changed = true;
+        }
 
-        self.send_lsp(OSPFMessage::LSP(from, seq, neighbors)).await; // flood
+        // flood, the routers behind the port of the lsp already received it
+        self.send_lsp(OSPFMessage::Lsp(from, seq, neighbors), Some(port)).await;
     }
 
-    pub async fn process_hello_reply(&mut self, ip: IPPrefix, port: u32){
+    // compute the routes once for all the lsps and neighbors received since the last computation
+    pub async fn update_routes(&mut self){
+        if self.topo_changed{
+            self.shortest_path().await;
+        }
+        if std::mem::take(&mut self.lsp_pending){
+            self.flood_neighbors().await;
+        }
+    }
+
+    pub async fn process_hello_reply(&mut self, ip: IPPrefix, address: Option<Ipv4Addr>, port: u32){
         if self.get_ip().await == ip.ip{
             return;
         }
         let map = self.get_igp_neighbors().await;
         let (_, cost) = map.get(&port).unwrap();
+        self.last_hello.insert((port, ip), Instant::now());
+        if let Some(address) = address{
+            self.neighbor_addresses.insert(port, address);
+        }
         if self.direct_neighbors.contains(&(*cost, port, ip)){
             return;
         }
         self.direct_neighbors.insert((*cost, port, ip));
         self.logger.log(Source::OSPF, format!("Router {} has neighbors : {:?}", self.get_name().await, self.direct_neighbors)).await;
-        self.routing_table.insert(ip, (port, *cost));
 
         let values = match self.topo.entry(self.get_ip().await) {
             Entry::Occupied(o) => o.into_mut(),
@@ -151,37 +398,205 @@ impl OSPFState{
         values.insert((*cost, ip));
         
         self.logger.log(Source::OSPF, format!("Router {} received prefix {} from neighbor on port {}", self.get_name().await, ip, port)).await;
+        // the lsps received before this neighbor was known may now lead somewhere
+        // the neighbors found in a burst of hello replies are advertised by a single lsp
+        self.topo_changed = true;
+        self.lsp_pending = true;
+    }
+
+    // forget the routes learned from the other routers and compute them again from the received LSPs
+    pub async fn clear_routes(&mut self){
+        let connected: HashSet<IPPrefix> = self.direct_neighbors.iter().map(|(_, _, prefix)| *prefix).collect();
+        self.routing_table.retain(|prefix, (_, _, origin)| *origin != RouteOrigin::Ospf || connected.contains(prefix));
+        self.logger.log(Source::OSPF, format!("Router {} cleared its routing table", self.get_name().await)).await;
+        self.shortest_path().await;
+        self.flood_neighbors().await;
+    }
+
+    pub async fn remove_neighbor(&mut self, port: u32){
+        self.direct_neighbors.retain(|(_, p, _)| *p != port);
+        self.neighbor_addresses.remove(&port);
+        self.ebgp_neighbors.remove(&port);
+        self.last_hello.retain(|(p, _), _| *p != port);
+        self.static_routes.retain(|_, (p, _)| *p != port);
+        let lost: Vec<IPPrefix> = self.routing_table.iter().filter(|(_, (p, _, _))| *p == port).map(|(prefix, _)| *prefix).collect();
+        for prefix in lost{
+            self.forget_prefix(prefix).await;
+        }
+        let neighbors = self.direct_neighbors.iter().map(|(cost, _, n)| (*cost, *n)).collect();
+        let ip = self.get_ip().await;
+        self.topo.insert(ip, neighbors);
+        self.logger.log(Source::OSPF, format!("Router {} lost its neighbor on port {}, neighbors : {:?}", self.get_name().await, port, self.direct_neighbors)).await;
+        self.shortest_path().await;
+        self.flood_neighbors().await;
+    }
+
+    // the neighbors found behind a port shut down are lost, the hellos find them again once it is enabled
+    pub async fn shut_down_port(&mut self, port: u32){
+        let lost: Vec<(u32, IPPrefix)> = self.last_hello.keys().filter(|(p, _)| *p == port).copied().collect();
+        for (port, prefix) in lost{
+            self.last_hello.remove(&(port, prefix));
+            self.direct_neighbors.retain(|(_, p, n)| !(*p == port && *n == prefix));
+        }
+        self.logger.log(Source::OSPF, format!("Router {} shut down port {}, neighbors : {:?}", self.get_name().await, port, self.direct_neighbors)).await;
+        let neighbors = self.direct_neighbors.iter().map(|(cost, _, n)| (*cost, *n)).collect();
+        let ip = self.get_ip().await;
+        self.topo.insert(ip, neighbors);
+        self.shortest_path().await;
+        self.flood_neighbors().await;
+    }
+
+    // the neighbors behind the port are reached at the new cost, our lsp tells the other routers
+    pub async fn set_link_cost(&mut self, port: u32, cost: u32){
+        let neighbors: Vec<(u32, u32, IPPrefix)> = self.direct_neighbors.iter().filter(|(_, p, _)| *p == port).copied().collect();
+        for (old, port, prefix) in neighbors{
+            self.direct_neighbors.remove(&(old, port, prefix));
+            self.direct_neighbors.insert((cost, port, prefix));
+        }
+        self.logger.log(Source::OSPF, format!("Router {} set the cost of port {} to {}, neighbors : {:?}", self.get_name().await, port, cost, self.direct_neighbors)).await;
+        let neighbors = self.direct_neighbors.iter().map(|(cost, _, n)| (*cost, *n)).collect();
+        let ip = self.get_ip().await;
+        self.topo.insert(ip, neighbors);
+        self.shortest_path().await;
+        self.flood_neighbors().await;
+    }
+
+    // forget the neighbors that stopped answering our hellos, their router or the link to them may be dead
+    pub async fn expire_neighbors(&mut self){
+        let dead_interval = self.dead_interval;
+        let dead: Vec<(u32, IPPrefix)> = self.last_hello.iter()
+            .filter(|(_, last)| last.elapsed() > dead_interval)
+            .map(|(neighbor, _)| *neighbor)
+            .collect();
+        if dead.is_empty(){
+            return;
+        }
+        for (port, prefix) in dead{
+            self.last_hello.remove(&(port, prefix));
+            self.direct_neighbors.retain(|(_, p, n)| !(*p == port && *n == prefix));
+            self.logger.log(Source::OSPF, format!("Router {} didn't receive hellos from {} on port {} for {:?}, neighbor considered dead", self.get_name().await, prefix, port, dead_interval)).await;
+        }
+        let neighbors = self.direct_neighbors.iter().map(|(cost, _, n)| (*cost, *n)).collect();
+        let ip = self.get_ip().await;
+        self.topo.insert(ip, neighbors);
+        self.shortest_path().await;
+        self.flood_neighbors().await;
+    }
+
+    // advertise our current neighbors to the rest of the network
+    async fn flood_neighbors(&mut self){
+        self.lsp_pending = false;
         let seq = self.lsp_seq;
         self.lsp_seq+=1;
         let mut neighs = HashSet::new();
         for (cost, _port, n) in self.direct_neighbors.iter(){
             neighs.insert((*cost, n.clone()));
         }
+        // the subnets of our links are reached through us
+        let info = self.router_info.lock().await;
+        for (port, (_, subnet)) in info.interface_addresses.iter(){
+            neighs.insert((info.igp_links.get(port).copied().unwrap_or(1), *subnet));
+        }
+        drop(info);
         let ip = self.get_ip().await;
-        self.send_lsp(OSPFMessage::LSP(ip, seq, neighs)).await;
+        // our own lsp is part of the database, it is never aged out
+        self.remember_lsp(ip, seq).await;
+        self.send_lsp(OSPFMessage::Lsp(ip, seq, neighs), None).await;
     }
 
-    pub async fn send_lsp(&mut self, lsp: OSPFMessage){
-        for (port, (sender, _)) in self.get_igp_neighbors().await.iter() {
+    // record the newest lsp of an originator, forgetting the originators refreshed the longest ago above the limit
+    async fn remember_lsp(&mut self, from: Ipv4Addr, seq: u32){
+        self.received_lsp.insert(from, (seq, Instant::now()));
+        let limit = self.router_info.lock().await.limits.max_received_lsp;
+        if self.received_lsp.len() <= limit{
+            return;
+        }
+        // our own lsp is never forgotten
+        let ip = self.get_ip().await;
+        let mut evictable: Vec<(Instant, Ipv4Addr)> = self.received_lsp.iter()
+            .filter(|(origin, _)| **origin != ip)
+            .map(|(origin, (_, received))| (*received, *origin))
+            .collect();
+        evictable.sort();
+        let excess = self.received_lsp.len() - limit;
+        let first_eviction = self.lsp_evictions == 0;
+        for (_, origin) in evictable.into_iter().take(excess){
+            self.forget_originator(origin);
+            self.lsp_evictions += 1;
+        }
+        if first_eviction && self.lsp_evictions > 0{
+            self.logger.log(Source::OSPF, format!("Router {} reached its limit of {} received LSPs, forgetting the oldest ones", self.get_name().await, limit)).await;
+        }
+    }
+
+    fn forget_originator(&mut self, origin: Ipv4Addr){
+        self.received_lsp.remove(&origin);
+        if self.topo.remove(&origin).is_some(){
+            self.topo_changed = true;
+        }
+    }
+
+    // forget the lsps that weren't refreshed for max_age, their originator may be dead
+    // the routes are computed again without them with the next update_routes
+    pub async fn expire_lsps(&mut self, max_age: Duration){
+        let ip = self.get_ip().await;
+        let stale: Vec<Ipv4Addr> = self.received_lsp.iter()
+            .filter(|(origin, (_, received))| **origin != ip && received.elapsed() > max_age)
+            .map(|(origin, _)| *origin)
+            .collect();
+        for origin in stale{
+            self.forget_originator(origin);
+            self.logger.log(Source::OSPF, format!("Router {} didn't receive a LSP from {} for {:?}, forgetting it", self.get_name().await, origin, max_age)).await;
+        }
+    }
+
+    // flood our lsp again, before the other routers age it out
+    pub async fn refresh_lsp(&mut self){
+        if self.enabled{
+            self.flood_neighbors().await;
+        }
+    }
+
+    pub async fn lsdb(&self) -> Lsdb{
+        let originators = self.received_lsp.iter().map(|(origin, (seq, received))| {
+            let neighbors = self.topo.get(origin).map(|neighbors| neighbors.iter().copied().collect()).unwrap_or_default();
+            (*origin, LsaInfo{seq: *seq, age_ms: received.elapsed().as_millis() as u64, neighbors})
+        }).collect();
+        Lsdb{router: self.get_name().await, originators}
+    }
+
+    pub async fn send_lsp(&mut self, lsp: OSPFMessage, received_on: Option<u32>){
+        if !self.enabled{
+            return;
+        }
+        for (port, (sender, _)) in self.get_igp_neighbors().await.iter().filter(|(port, _)| Some(**port) != received_on) {
             self.logger.log(Source::OSPF, format!("Router {} sending {:?} on port {}", self.get_name().await, lsp, port)).await;
-            sender.send(Message::OSPF(lsp.clone())).await.unwrap();
+            sender.send(Message::Ospf(lsp.clone())).await;
         }
     }
 
     pub async fn send_hello(&self){
+        if !self.enabled{
+            return;
+        }
         for (port, (sender, _)) in self.get_igp_neighbors().await.iter() {
-            let msg = Message::OSPF(Hello);
+            let msg = Message::Ospf(Hello);
             self.logger.log(Source::OSPF, format!("Router {} sending Hello on port {}", self.get_name().await, port)).await;
-            sender.send(msg).await.unwrap();
+            sender.send(msg).await;
         }
     }
 
     pub async fn send_hello_reply(&self, port: u32){
         let map = self.get_igp_neighbors().await;
-        let (sender, _) = map.get(&port).unwrap();
+        let Some((sender, _)) = map.get(&port) else {
+            return;
+        };
         self.logger.log(Source::OSPF, format!("Router {} sending hello reply on port {}", self.get_name().await, port)).await;
-        let prefix = IPPrefix{ip: self.get_ip().await, prefix_len: 32};
-        sender.send(Message::OSPF(OSPFMessage::HelloReply(prefix))).await.expect("Failed to send Hello reply");
+        let info = self.router_info.lock().await;
+        let prefix = IPPrefix{ip: info.ip, prefix_len: 32};
+        let address = info.interface_addresses.get(&port).map(|(address, _)| *address);
+        drop(info);
+        sender.send(Message::Ospf(OSPFMessage::HelloReply(prefix, address))).await;
     }
 
     pub async fn get_ip(&self) -> Ipv4Addr{
@@ -192,12 +607,13 @@ impl OSPFState{
         self.router_info.lock().await.name.clone()
     }
 
-    pub async fn get_igp_neighbors(&self) -> HashMap<u32, (Sender<Message>, u32)>{
+    pub async fn get_igp_neighbors(&self) -> HashMap<u32, (LinkSender, u32)>{
         let mut map = HashMap::new();
         let info = self.router_info.lock().await;
         for (port, cost) in info.igp_links.iter(){
-            let (_, sender) = info.neighbors_links.get(&port).unwrap();
-            map.insert(*port, (sender.clone(), *cost));
+            if let Some((_, sender)) = info.neighbors_links.get(port){
+                map.insert(*port, (sender.clone(), *cost));
+            }
         }
         map
     }
@@ -0,0 +1,79 @@
+use std::fmt::Display;
+
+use crate::network::ip_prefix::IPPrefix;
+
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum PolicyAction{
+    Accept,
+    Deny,
+    SetMed(u32),
+    SetPref(u32) // only used on import, the pref isn't sent to the neighbors
+}
+
+// matches the prefixes inside prefix, whose length is between the one of prefix and le
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct PolicyRule{
+    pub prefix: IPPrefix,
+    pub le: Option<u32>, // only the exact length of prefix without it
+    pub action: PolicyAction
+}
+
+impl PolicyRule{
+    fn matches(&self, prefix: IPPrefix) -> bool{
+        let max_len = self.le.unwrap_or(self.prefix.prefix_len);
+        self.prefix.contains_prefix(&prefix) && prefix.prefix_len <= max_len
+    }
+}
+
+impl Display for PolicyRule{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "match {}", self.prefix)?;
+        if let Some(le) = self.le{
+            write!(f, " le {}", le)?;
+        }
+        write!(f, " {:?}", self.action)
+    }
+}
+
+// the first rule matching a prefix decides what happens to its routes, the routes matched by no rule are accepted
+#[derive(Debug, PartialEq, Clone, Eq, Default)]
+pub struct RoutePolicy{
+    pub rules: Vec<PolicyRule>
+}
+
+impl RoutePolicy{
+    pub fn new(rules: Vec<PolicyRule>) -> RoutePolicy{
+        RoutePolicy{rules}
+    }
+
+    pub fn action(&self, prefix: IPPrefix) -> PolicyAction{
+        self.rules.iter().find(|rule| rule.matches(prefix)).map_or(PolicyAction::Accept, |rule| rule.action)
+    }
+}
+
+impl Display for RoutePolicy{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}]", self.rules.iter().map(|rule| rule.to_string()).collect::<Vec<String>>().join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_matching_rule() {
+        let rule = |prefix: &str, le, action| PolicyRule{prefix: prefix.parse().unwrap(), le, action};
+        let policy = RoutePolicy::new(vec![
+            rule("10.0.1.0/24", None, PolicyAction::Deny),
+            rule("10.0.0.0/16", Some(24), PolicyAction::SetMed(5)),
+            rule("10.0.0.0/8", Some(32), PolicyAction::Deny),
+        ]);
+        assert_eq!(policy.action("10.0.1.0/24".parse().unwrap()), PolicyAction::Deny);
+        assert_eq!(policy.action("10.0.2.0/24".parse().unwrap()), PolicyAction::SetMed(5));
+        assert_eq!(policy.action("10.0.2.128/25".parse().unwrap()), PolicyAction::Deny);
+        assert_eq!(policy.action("10.0.0.0/8".parse().unwrap()), PolicyAction::Deny);
+        assert_eq!(policy.action("192.0.2.0/24".parse().unwrap()), PolicyAction::Accept);
+        assert_eq!(RoutePolicy::default().action("10.0.1.0/24".parse().unwrap()), PolicyAction::Accept);
+    }
+}
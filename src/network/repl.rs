@@ -0,0 +1,206 @@
+use std::{io::{self, Write}, net::Ipv4Addr, str::FromStr, time::Duration};
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use super::{config::{AnnounceCfg, DEFAULT_COST}, error::NetworkError, logger::Source, scenario::CONVERGENCE_TIMEOUT, schedule::{self, Action}, Network};
+
+// usage and description of each command, printed by help
+pub(super) const COMMANDS: [(&str, &str); 12] = [
+    ("ping <router> <ip>", "send a ping and wait for the answer"),
+    ("show route <router>", "print the routing table of a router"),
+    ("show bgp <router>", "print the BGP table of a router"),
+    ("announce <router>", "announce the prefix of a router"),
+    ("withdraw <router>", "withdraw the prefix of a router"),
+    ("fail-link <device> <port>", "remove the link on a port of a device"),
+    ("add-link <device> <device> [cost]", "link two devices on their first free ports"),
+    ("wait [ms]", "wait for the convergence of the routers, or for some time"),
+    ("dot", "print the DOT graph of the network"),
+    ("log <source,...|all> [device ...]", "only log the messages of the sources, and of the devices"),
+    ("help", "print this list"),
+    ("quit", "stop the simulation"),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum ReplCommand{
+    Ping(String, Ipv4Addr),
+    ShowRoute(String),
+    ShowBGP(String),
+    Announce(String),
+    Withdraw(String),
+    FailLink(String, u32),
+    AddLink(String, String, u32), // cost
+    Wait(Option<Duration>), // None waits for the convergence
+    Dot,
+    Log(Vec<Source>, Vec<String>), // empty lists log everything
+    Help,
+    Quit,
+}
+
+fn parse_arg<T: FromStr>(value: &str, what: &str) -> Result<T, NetworkError>{
+    value.parse().map_err(|_| NetworkError::InvalidCommand(format!("{} is not a valid {}", value, what)))
+}
+
+impl FromStr for ReplCommand{
+    type Err = NetworkError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice(){
+            ["ping", from, to] => Ok(ReplCommand::Ping(from.to_string(), parse_arg(to, "IP address")?)),
+            ["show", "route", router] => Ok(ReplCommand::ShowRoute(router.to_string())),
+            ["show", "bgp", router] => Ok(ReplCommand::ShowBGP(router.to_string())),
+            ["announce", router] => Ok(ReplCommand::Announce(router.to_string())),
+            ["withdraw", router] => Ok(ReplCommand::Withdraw(router.to_string())),
+            ["fail-link", device, port] => Ok(ReplCommand::FailLink(device.to_string(), parse_arg(port, "port")?)),
+            ["add-link", device1, device2] => Ok(ReplCommand::AddLink(device1.to_string(), device2.to_string(), DEFAULT_COST)),
+            ["add-link", device1, device2, cost] => Ok(ReplCommand::AddLink(device1.to_string(), device2.to_string(), parse_arg(cost, "cost")?)),
+            ["wait"] => Ok(ReplCommand::Wait(None)),
+            ["wait", ms] => Ok(ReplCommand::Wait(Some(Duration::from_millis(parse_arg(ms, "duration")?)))),
+            ["dot"] => Ok(ReplCommand::Dot),
+            ["log", "all", devices @ ..] => Ok(ReplCommand::Log(vec![], devices.iter().map(|device| device.to_string()).collect())),
+            ["log", sources, devices @ ..] => {
+                let sources = sources.split(',').map(|source| source.parse().map_err(NetworkError::InvalidCommand)).collect::<Result<_, _>>()?;
+                Ok(ReplCommand::Log(sources, devices.iter().map(|device| device.to_string()).collect()))
+            },
+            ["help"] => Ok(ReplCommand::Help),
+            ["quit"] | ["exit"] => Ok(ReplCommand::Quit),
+            [name, ..] => {
+                let usages: Vec<&str> = COMMANDS.iter().map(|(usage, _)| *usage).filter(|usage| usage.split(' ').next() == Some(name)).collect();
+                if usages.is_empty(){
+                    return Err(NetworkError::InvalidCommand(format!("unknown command {}, type help for the list of commands", name)));
+                }
+                Err(NetworkError::InvalidCommand(format!("usage: {}", usages.join(" or "))))
+            },
+            [] => Err(NetworkError::InvalidCommand("empty command".into())),
+        }
+    }
+}
+
+// links the devices on their first free ports, which are returned
+pub(super) async fn add_link(network: &mut Network, device1: &str, device2: &str, cost: u32) -> Result<(u32, u32), NetworkError>{
+    let (port1, port2) = (network.next_free_port(device1)?, network.next_free_port(device2)?);
+    network.add_link(device1, port1, device2, port2, cost).await?;
+    Ok((port1, port2))
+}
+
+// answer to the log command
+pub(super) fn log_filters_description(sources: &[Source], devices: &[String]) -> String{
+    let sources = if sources.is_empty() { "all sources".to_string() } else { sources.iter().map(|source| source.to_string()).collect::<Vec<_>>().join(", ") };
+    let devices = if devices.is_empty() { "all devices".to_string() } else { devices.join(", ") };
+    format!("Logging the messages of {} from {}", sources, devices)
+}
+
+// removes the link on the port of the device and returns its other end
+pub(super) async fn fail_link(network: &mut Network, device: &str, port: u32) -> Result<(String, u32), NetworkError>{
+    let (other, other_port) = network.link_end(device, port).ok_or(NetworkError::NoLinkOnPort{device: device.to_string(), port})?;
+    network.remove_link(device, port, &other, other_port).await?;
+    Ok((other, other_port))
+}
+
+async fn execute(network: &mut Network, command: &ReplCommand) -> Result<(), NetworkError>{
+    match command{
+        ReplCommand::Ping(from, to) => schedule::execute(network, &Action::Ping(from.clone(), *to)).await?,
+        ReplCommand::ShowRoute(router) => network.print_routing_table(router).await?,
+        ReplCommand::ShowBGP(router) => network.print_bgp_table(router).await?,
+        ReplCommand::Announce(router) => {
+            schedule::execute(network, &Action::AnnouncePrefix(AnnounceCfg::Router(router.clone()))).await?;
+            println!("Prefix of {} announced", router);
+        },
+        ReplCommand::Withdraw(router) => {
+            schedule::execute(network, &Action::WithdrawPrefix(router.clone())).await?;
+            println!("Prefix of {} withdrawn", router);
+        },
+        ReplCommand::FailLink(device, port) => {
+            let (other, other_port) = fail_link(network, device, *port).await?;
+            println!("Link from {}:{} to {}:{} removed", device, port, other, other_port);
+        },
+        ReplCommand::AddLink(device1, device2, cost) => {
+            let (port1, port2) = add_link(network, device1, device2, *cost).await?;
+            println!("Link from {}:{} to {}:{} added with cost {}", device1, port1, device2, port2, cost);
+        },
+        ReplCommand::Wait(Some(duration)) => tokio::time::sleep(*duration).await,
+        ReplCommand::Wait(None) => {
+            let elapsed = network.wait_for_convergence(CONVERGENCE_TIMEOUT).await?;
+            println!("Network converged in {}ms", elapsed.as_millis());
+        },
+        ReplCommand::Dot => print!("{}", network.dot_representation().await?),
+        ReplCommand::Log(sources, devices) => {
+            network.set_log_filters(sources.clone(), devices.clone()).await?;
+            println!("{}", log_filters_description(sources, devices));
+        },
+        ReplCommand::Help => {
+            for (usage, description) in COMMANDS{
+                println!("  {:<35}{}", usage, description);
+            }
+        },
+        ReplCommand::Quit => {},
+    }
+    Ok(())
+}
+
+// Reads the commands line by line until quit or the end of the input
+// An invalid or failed command is reported and the session goes on
+pub async fn run<R: AsyncBufRead + Unpin>(network: &mut Network, input: R, prompt: bool) -> io::Result<()>{
+    let mut lines = input.lines();
+    loop{
+        if prompt{
+            print!("> ");
+            io::stdout().flush()?;
+        }
+        let Some(line) = lines.next_line().await? else {
+            return Ok(());
+        };
+        if line.trim().is_empty(){
+            continue;
+        }
+        match line.parse(){
+            Ok(ReplCommand::Quit) => return Ok(()),
+            Ok(command) => if let Err(error) = execute(network, &command).await{
+                println!("Error: {}", error);
+            },
+            Err(error) => println!("Error: {}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::logger::Logger;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!("ping r1 10.0.3.3".parse(), Ok(ReplCommand::Ping("r1".into(), Ipv4Addr::new(10, 0, 3, 3))));
+        assert_eq!("  show   bgp r4 ".parse(), Ok(ReplCommand::ShowBGP("r4".into())));
+        assert_eq!("fail-link r1 2".parse(), Ok(ReplCommand::FailLink("r1".into(), 2)));
+        assert_eq!("add-link r1 r2".parse(), Ok(ReplCommand::AddLink("r1".into(), "r2".into(), 1)));
+        assert_eq!("wait".parse(), Ok(ReplCommand::Wait(None)));
+        assert_eq!("wait 200".parse(), Ok(ReplCommand::Wait(Some(Duration::from_millis(200)))));
+        assert_eq!("log bgp,ospf r3".parse(), Ok(ReplCommand::Log(vec![Source::BGP, Source::OSPF], vec!["r3".into()])));
+        assert_eq!("log all".parse(), Ok(ReplCommand::Log(vec![], vec![])));
+
+        let error = |reason: &str| Err::<ReplCommand, _>(NetworkError::InvalidCommand(reason.into()));
+        assert_eq!("ping r1 10.0.3".parse(), error("10.0.3 is not a valid IP address"));
+        assert_eq!("fail-link r1 two".parse(), error("two is not a valid port"));
+        assert_eq!("traceroute r1".parse(), error("unknown command traceroute, type help for the list of commands"));
+        assert_eq!("show r1".parse(), error("usage: show route <router> or show bgp <router>"));
+        assert_eq!("log tcp".parse(), error("unknown log source tcp, supported sources are [ospf, spt, ping, debug, ip, bgp, arp, link]"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_session_goes_on_after_errors() {
+        let mut network = Network::new(Logger::start_test());
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+
+        assert_eq!(network.link_end("r2", 1), Some(("r1".into(), 1)));
+        let input = "show route r9\nping r1\nfail-link r1 3\n\nfail-link r1 1\nquit\n";
+        run(&mut network, input.as_bytes(), false).await.unwrap();
+        assert_eq!(network.link_ports("r1", "r2"), None);
+
+        run(&mut network, "add-link r2 r1 4\nadd-link r2 r3\n".as_bytes(), false).await.unwrap();
+        assert_eq!(network.link_ports("r1", "r2"), Some((1, 1)));
+        network.quit().await;
+    }
+}
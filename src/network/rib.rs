@@ -0,0 +1,81 @@
+use std::{fmt::Display, net::Ipv4Addr};
+
+use serde::Serialize;
+
+// protocol that installed a route in the routing table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub enum RouteOrigin{
+    Connected,
+    Static,
+    Bgp,
+    Ospf
+}
+
+pub type RIBEntry = (u32, u32, RouteOrigin); // port, distance, origin
+
+// where a router sends the packets towards a destination, by the longest prefix of its routing table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForwardingHop{
+    Local, // the destination is one of the addresses of the router
+    Link{egress: u32, device: String, port: u32}, // port the packets leave on, device and port at the other end of its link
+    Unreachable
+}
+
+// where the packets of a static route are sent, through a port or to the address of a router linked to ours
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StaticNexthop{
+    Port(u32),
+    Address(Ipv4Addr)
+}
+
+impl RouteOrigin{
+    // administrative distance, the route of the lowest one is kept for a prefix
+    pub fn admin_distance(&self) -> u32{
+        match self{
+            RouteOrigin::Connected => 0,
+            RouteOrigin::Static => 1,
+            RouteOrigin::Bgp => 20,
+            RouteOrigin::Ospf => 110,
+        }
+    }
+
+    // a protocol always replaces its own routes, and the ones of the less trusted protocols
+    pub fn replaces(&self, current: &RouteOrigin) -> bool{
+        self == current || self.admin_distance() < current.admin_distance()
+    }
+}
+
+impl Display for RouteOrigin{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            RouteOrigin::Connected => write!(f, "connected"),
+            RouteOrigin::Static => write!(f, "static"),
+            RouteOrigin::Bgp => write!(f, "bgp"),
+            RouteOrigin::Ospf => write!(f, "ospf"),
+        }
+    }
+}
+
+impl Display for StaticNexthop{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            StaticNexthop::Port(port) => write!(f, "port {}", port),
+            StaticNexthop::Address(ip) => write!(f, "{}", ip),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admin_distance() {
+        assert!(RouteOrigin::Ospf.replaces(&RouteOrigin::Ospf));
+        assert!(!RouteOrigin::Ospf.replaces(&RouteOrigin::Bgp));
+        assert!(!RouteOrigin::Ospf.replaces(&RouteOrigin::Connected));
+        assert!(RouteOrigin::Bgp.replaces(&RouteOrigin::Ospf));
+        assert!(!RouteOrigin::Bgp.replaces(&RouteOrigin::Static));
+        assert!(RouteOrigin::Connected.replaces(&RouteOrigin::Bgp));
+    }
+}
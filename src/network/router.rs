@@ -1,16 +1,104 @@
-use std::{cell::RefCell, collections::HashMap, net::Ipv4Addr, rc::Rc, sync::Arc, time::SystemTime};
-use tokio::sync::{mpsc::{channel, Receiver, Sender}, Mutex};
+use std::{collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet}, hash::{Hash, Hasher}, net::Ipv4Addr, sync::Arc, time::{Duration, Instant, SystemTime}};
+use tokio::{sync::{mpsc::{channel, Receiver, Sender}, oneshot, Mutex}, task::JoinHandle};
 
-use super::{ip_prefix::IPPrefix, logger::{Logger, Source}, messages::{ip::{Content, IP}, Message}, protocols::{arp::ArpState, bgp::BGPState}, utils::{MacAddress, SharedState}};
-use super::communicators::{RouterCommunicator, Command, Response};
-use super::protocols::ospf::OSPFState;
+use super::{capture::Direction, error::PingError, rib::RouteOrigin, health::{InterfaceState, PortInfo, PortKind, RouterHealth, SessionHealth}, ip_prefix::IPPrefix, events::{emit, EventSender, NetworkEvent}, limits::{Limits, MemoryStats}, link::LinkSender, logger::{Logger, Source}, messages::{ip::{Content, RecordedRoute, DEFAULT_TTL, IP}, Message}, protocols::{arp::ArpState, bgp::{BGPState, CUSTOMER_PREF, PEER_PREF, PROVIDER_PREF}}, stats::SharedStats, timers::TimerConfig, traffic::PingReport, utils::{forward_link, LinkMessage, MacAddress, SharedState}};
+use super::communicators::{RouterCommunicator, Command};
+use super::protocols::ospf::{OSPFState, LSP_MAX_AGE_INTERVALS};
 
-type Neighbor = (SharedState<Receiver<Message>>, Sender<Message>); // receiver, sender
+type Neighbor = (u64, LinkSender); // id of the link, sender
 
 type BGPNeighbor = (u32, u32); // pref, med
 
 type IGPNeighbor = u32;  // cost
 
+type PendingPing = (u32, SystemTime, PingReplier); // sequence number, time sent, replier
+
+// what the caller of a ping waits for
+#[derive(Debug)]
+pub enum PingReplier{
+    Rtt(oneshot::Sender<Result<Duration, PingError>>),
+    Route(oneshot::Sender<Result<RecordedRoute, PingError>>) // the ping records the routers it crosses
+}
+
+impl PingReplier{
+    // the caller may have stopped waiting for the answer
+    fn answer(self, rtt: Duration, route: Option<RecordedRoute>){
+        match self{
+            PingReplier::Rtt(replier) => { let _ = replier.send(Ok(rtt)); },
+            PingReplier::Route(replier) => { let _ = replier.send(Ok(route.unwrap_or_default())); },
+        }
+    }
+
+    fn fail(self, error: PingError){
+        match self{
+            PingReplier::Rtt(replier) => { let _ = replier.send(Err(error)); },
+            PingReplier::Route(replier) => { let _ = replier.send(Err(error)); },
+        }
+    }
+}
+
+// time after which a ping without answer is considered lost
+pub const PING_TIMEOUT: Duration = Duration::from_millis(1000);
+
+pub const TRACEROUTE_MAX_HOPS: u8 = 30;
+
+// hello intervals without any change of its routes after which a router is converged
+pub const CONVERGENCE_INTERVALS: u32 = 3;
+
+#[derive(Debug)]
+pub struct Traceroute{
+    pub dest: Ipv4Addr,
+    pub id: u32, // echo identifier of the probes
+    pub seq: u32,  // sequence number of the probe in flight
+    pub ttl: u8,
+    pub sent: SystemTime,
+    pub hops: Vec<Ipv4Addr>,
+    pub replier: oneshot::Sender<Vec<Ipv4Addr>>
+}
+
+// pings sent every interval with the same echo identifier and increasing sequence numbers
+#[derive(Debug)]
+pub struct PingSeries{
+    pub dest: Ipv4Addr,
+    pub count: u32,
+    pub interval: Duration,
+    pub started: Instant,
+    pub sent: Vec<SystemTime>,            // time each sequence number was sent
+    pub answered: BTreeMap<u32, Duration>, // sequence number -> round trip time
+    pub duplicates: u32,
+    pub reordered: u32,
+    pub replier: oneshot::Sender<PingReport>
+}
+
+impl PingSeries{
+    // next ping to send, or the time the last one times out
+    fn deadline(&self) -> Instant{
+        match self.sent.len() as u32{
+            sent if sent < self.count => self.started + self.interval * sent,
+            _ => self.started + self.interval * self.count.saturating_sub(1) + PING_TIMEOUT,
+        }
+    }
+
+    fn report(self) -> PingReport{
+        let sent = self.sent.len() as u32;
+        let received = self.answered.len() as u32;
+        let rtts = self.answered.values();
+        let avg_rtt = if received == 0 { Duration::ZERO } else { rtts.clone().sum::<Duration>() / received };
+        let report = PingReport{
+            sent,
+            received,
+            lost: sent - received,
+            duplicates: self.duplicates,
+            reordered: self.reordered,
+            min_rtt: rtts.clone().min().copied().unwrap_or_default(),
+            avg_rtt,
+            max_rtt: rtts.max().copied().unwrap_or_default(),
+        };
+        let _ = self.replier.send(report.clone());
+        report
+    }
+}
+
 #[derive(Debug)]
 pub struct RouterInfo{
     pub name: String,
@@ -21,26 +109,50 @@ pub struct RouterInfo{
     pub neighbors_links: HashMap<u32, Neighbor>,
     pub igp_links: HashMap<u32, IGPNeighbor>,
     pub bgp_links: HashMap<u32, BGPNeighbor>,
-    pub ibgp_peers: Vec<Ipv4Addr>
+    pub ibgp_peers: Vec<Ipv4Addr>,
+    pub rr_clients: Vec<Ipv4Addr>, // iBGP peers whose routes are reflected, when the router is a route reflector
+    pub interface_addresses: HashMap<u32, (Ipv4Addr, IPPrefix)>, // port -> address of the router on the subnet of the link, subnet
+    pub limits: Limits
+}
+
+// settings of a router when it starts, its limits and dead interval can be changed while it runs
+#[derive(Debug, Clone)]
+pub struct RouterConfig{
+    pub limits: Limits,
+    pub dead_interval: Duration, // of its OSPF neighbors
+    pub timers: TimerConfig
 }
 
 #[derive(Debug)]
 pub struct Router{
     pub router_info: SharedState<RouterInfo>,
     pub command_receiver: Receiver<Command>,
-    pub command_replier: Sender<Response>,
     pub igp_state: SharedState<OSPFState>,
     pub arp_state: SharedState<ArpState>,
     pub bgp_state: SharedState<BGPState>,
+    pub link_sender: Sender<LinkMessage>,
+    pub link_receiver: Receiver<LinkMessage>, // messages of all the links
+    pub next_link: u64,
+    pub disabled_ports: HashSet<u32>, // shut down by the administrator, their messages are ignored
+    pub pending_pings: HashMap<(Ipv4Addr, u32), PendingPing>, // (destination, echo identifier) -> ping waiting for an answer
+    pub next_echo_id: u32,
+    pub traceroute: Option<Traceroute>,
+    pub ping_series: HashMap<u32, PingSeries>, // echo identifier -> series
+    pub received_data: Vec<(Ipv4Addr, String)>, // source and payload of the data received, in order
+    pub received_probes: HashMap<u64, (u64, Duration)>, // flow -> probes received, sum of their latencies
+    pub routes_fingerprint: (u64, Instant), // hash of the routes when last asked if converged, time they last changed
+    pub stats: SharedStats,
+    pub timers: TimerConfig,
+    pub events: EventSender,
     pub logger: Logger
 }
 
 impl Router{
 
-    pub fn start(name: String, id: u32, router_as: u32, logger: Logger) -> RouterCommunicator{
+    pub fn start(name: String, id: u32, router_as: u32, ip: Ipv4Addr, config: RouterConfig, events: EventSender, logger: Logger) -> (RouterCommunicator, JoinHandle<()>){
+        let RouterConfig{limits, dead_interval, timers} = config;
         let (tx_command, rx_command) = channel(1024);
-        let (tx_response, rx_response) = channel(1024);
-        let ip = Ipv4Addr::new(10, 0, router_as as u8, id as u8);
+        let (link_sender, link_receiver) = channel(1024);
         let router_info = Arc::new(Mutex::new(RouterInfo{
             name, 
             ip,
@@ -50,213 +162,809 @@ impl Router{
             neighbors_links: HashMap::new(), 
             igp_links: HashMap::new(),
             bgp_links: HashMap::new(),
-            ibgp_peers: vec![]
+            ibgp_peers: vec![],
+            rr_clients: vec![],
+            interface_addresses: HashMap::new(),
+            limits
         }));
         let arp_state = Arc::new(Mutex::new(ArpState::new(Arc::clone(&router_info), logger.clone())));
-        let igp_state = Arc::new(Mutex::new(OSPFState::new(ip, logger.clone(), Arc::clone(&router_info), Arc::clone(&arp_state))));
+        let mut igp_state = OSPFState::new(ip, logger.clone(), Arc::clone(&router_info), Arc::clone(&arp_state));
+        igp_state.dead_interval = dead_interval;
+        igp_state.events = events.clone();
+        let igp_state = Arc::new(Mutex::new(igp_state));
+        let mut bgp_state = BGPState::new(Arc::clone(&router_info), Arc::clone(&igp_state), logger.clone());
+        bgp_state.hold_time = timers.keepalive_interval.saturating_mul(3);
+        bgp_state.events = events.clone();
         let mut router = Router{
             router_info: Arc::clone(&router_info),
             command_receiver: rx_command,
-            command_replier: tx_response,
             igp_state: Arc::clone(&igp_state) ,
             arp_state,
-            bgp_state: Arc::new(Mutex::new(BGPState::new(router_info, igp_state, logger.clone()))),
+            bgp_state: Arc::new(Mutex::new(bgp_state)),
+            link_sender,
+            link_receiver,
+            next_link: 0,
+            disabled_ports: HashSet::new(),
+            pending_pings: HashMap::new(),
+            next_echo_id: 0,
+            traceroute: None,
+            ping_series: HashMap::new(),
+            received_data: vec![],
+            received_probes: HashMap::new(),
+            routes_fingerprint: (0, Instant::now()),
+            stats: Arc::default(),
+            timers,
+            events,
             logger
         };
-        tokio::spawn(async move {
+        let task = tokio::spawn(async move {
             router.run().await;
         });
-        RouterCommunicator{command_sender: tx_command, response_receiver: Rc::new(RefCell::new(rx_response))}
+        (RouterCommunicator{command_sender: tx_command}, task)
     }
 
     pub async fn run(&mut self){
-        let mut time = SystemTime::now();
+        let mut hello = tokio::time::interval(self.timers.hello_interval);
+        let mut arp_refresh = tokio::time::interval(self.timers.arp_refresh_interval);
+        let mut keepalive = tokio::time::interval(self.timers.keepalive_interval);
+        let mut lsp_refresh = tokio::time::interval(self.timers.lsp_refresh_interval);
         loop{
-            if self.receive_command().await{
-                return;
+            let next_ping = self.ping_series.values().map(|series| series.deadline()).min().map(tokio::time::Instant::from_std);
+            tokio::select!{
+                command = self.command_receiver.recv() => match command{
+                    Some(command) => if self.process_command(command).await{
+                        return;
+                    },
+                    None => return,
+                },
+                Some((port, link, message)) = self.link_receiver.recv() => self.receive_message(port, link, message).await,
+                _ = hello.tick() => {
+                    // forget the silent neighbors and send an hello message
+                    let mut igp_state = self.igp_state.lock().await;
+                    igp_state.expire_neighbors().await;
+                    igp_state.send_hello().await;
+                    let routes_changed = std::mem::take(&mut igp_state.routes_changed);
+                    drop(igp_state);
+                    if routes_changed{
+                        // like a MRAI timer, the BGP routes follow the IGP routes at most once per hello interval
+                        let mut bgp_state = self.bgp_state.lock().await;
+                        bgp_state.revalidate_nexthops().await;
+                        bgp_state.refresh_igp_meds().await;
+                    }
+                },
+                _ = tokio::time::sleep_until(next_ping.unwrap_or_else(tokio::time::Instant::now)), if next_ping.is_some() => self.advance_ping_series().await,
+                _ = lsp_refresh.tick() => {
+                    // like the BGP sessions, the lsps not refreshed for a few periods are aged out
+                    let mut igp_state = self.igp_state.lock().await;
+                    igp_state.expire_lsps(self.timers.lsp_refresh_interval * LSP_MAX_AGE_INTERVALS).await;
+                    igp_state.refresh_lsp().await;
+                },
+                _ = keepalive.tick() => {
+                    let mut bgp_state = self.bgp_state.lock().await;
+                    bgp_state.expire_sessions().await;
+                    bgp_state.send_keepalives().await;
+                },
+                _ = arp_refresh.tick() => {
+                    let igp_state = self.igp_state.lock().await;
+                    let mut arp_state = self.arp_state.lock().await;
+                    for (_, port, ip) in igp_state.direct_neighbors.iter(){
+                        arp_state.resolve(igp_state.neighbor_address(*port, ip.ip), *port).await;
+                    }
+                    for (port, ip) in igp_state.ebgp_neighbors.iter(){
+                        arp_state.resolve(*ip, *port).await;
+                    }
+                    for (port, nexthop) in igp_state.static_routes.values(){
+                        arp_state.resolve(*nexthop, *port).await;
+                    }
+                },
             }
-            self.receive_messages().await;
-            if time.elapsed().unwrap().as_millis() > 200{
-                // every 200ms, send an hello message, and refresh arp state
-                time = SystemTime::now();
-                let igp_state = self.igp_state.lock().await;
-                igp_state.send_hello().await;
-                let arp_state = self.arp_state.lock().await;
-                for (_, port, ip) in igp_state.direct_neighbors.iter(){
-                    arp_state.resolve(ip.ip, *port).await;
-                }
+            if self.link_receiver.is_empty(){
+                self.igp_state.lock().await.update_routes().await;
             }
+            self.check_ping_timeouts().await;
         }
     }
 
-    pub async fn receive_messages(&mut self){
-        let mut received_messages = vec![];
+    // read the link in its own task, its messages are received with those of the other links
+    fn add_link(&mut self, receiver: Receiver<Message>, port: u32) -> u64{
+        let link = self.next_link;
+        self.next_link += 1;
+        forward_link(receiver, port, link, self.link_sender.clone());
+        link
+    }
+
+    // link towards a router of another AS, the BGP session is opened with the (pref, med) of the relationship
+    async fn add_bgp_link(&mut self, receiver: Receiver<Message>, sender: LinkSender, port: u32, relationship: (u32, u32), other_ip: Ipv4Addr, pref: Option<u32>){
+        let link = self.add_link(receiver, port);
+        let mut info = self.router_info.lock().await;
+        let kind = match relationship.0{
+            CUSTOMER_PREF => "customer",
+            PEER_PREF => "peer",
+            _ => "provider",
+        };
+        self.logger.log(Source::DEBUG, format!("Router {} received adding {} link", info.name, kind)).await;
+        info.neighbors_links.insert(port, (link, sender.counted(port, &self.stats)));
+        let prefix = IPPrefix{ip: other_ip, prefix_len: 32};
+        let mut igp_state = self.igp_state.lock().await;
+        igp_state.install(prefix, port, 1, RouteOrigin::Connected);
+        igp_state.prefixes.insert(prefix, ());
+        // the router of the other AS isn't part of our igp, it is left out of our lsp
+        igp_state.ebgp_neighbors.insert(port, other_ip);
+        drop(igp_state);
+        drop(info);
+        self.arp_state.lock().await.announce(port).await;
+        self.bgp_state.lock().await.open_session(port, other_ip, relationship, pref).await;
+    }
+
+    pub async fn receive_message(&mut self, port: u32, link: u64, message: Message){
         let info = self.router_info.lock().await;
-        for (port, (receiver, _)) in info.neighbors_links.iter(){
-            let mut receiver = receiver.lock().await;
-            if let Ok(message) = receiver.try_recv(){
-                received_messages.push((message, *port));
-            }
+        if info.neighbors_links.get(&port).is_none_or(|(current, _)| *current != link) || self.disabled_ports.contains(&port){
+            // the link was removed or its port shut down
+            return;
         }
-        let name = info.name.clone();
         drop(info);
-        for (message, port) in received_messages{
-            self.logger.log(Source::DEBUG, format!("Router {} received {:?}", name, message)).await;
-            
-            match message{
-                Message::BPDU(_) => (), // don't care about bdpus
-                Message::OSPF(ospf) => self.igp_state.lock().await.process_ospf(ospf, port).await,
-                Message::EthernetFrame(mac, ip) => self.process_frame(port, mac, ip).await,
-                Message::BGP(bgp_message) => self.bgp_state.lock().await.process_bgp_message(port, bgp_message).await,
-                Message::ARP(arp_message) => self.arp_state.lock().await.process_arp_message(arp_message, port).await,
-            }
+        self.stats.lock().unwrap().count(Direction::Received, port, &message);
+        self.dispatch_message(port, message).await;
+    }
+
+    // handle a message received on port, whichever link carried it
+    pub async fn dispatch_message(&mut self, port: u32, message: Message){
+        let name = self.router_info.lock().await.name.clone();
+        self.logger.log(Source::DEBUG, format!("Router {} received {:?}", name, message)).await;
+
+        match message{
+            Message::Bpdu(_) => (), // don't care about bdpus
+            Message::Ospf(ospf) => self.igp_state.lock().await.process_ospf(ospf, port).await,
+            Message::EthernetFrame(_, mac, ip) => self.process_frame(port, mac, ip).await,
+            Message::Bgp(bgp_message) => self.bgp_state.lock().await.process_bgp_message(port, bgp_message).await,
+            Message::Arp(arp_message) => self.arp_state.lock().await.process_arp_message(arp_message, port).await,
         }
     }
 
-    pub async fn process_frame(&self,port: u32, mac: MacAddress, content: IP){
+    pub async fn process_frame(&mut self,port: u32, mac: MacAddress, content: IP){
         let self_mac = self.router_info.lock().await.mac_address.clone();
         if self_mac == mac{
             self.process_ip(port, content).await;
         }
     }
 
-    pub async fn process_ip(&self, port: u32, ip_packet: IP){
+    pub async fn process_ip(&mut self, port: u32, mut ip_packet: IP){
         let info = self.router_info.lock().await;
         let ip = info.ip.clone();
-        self.logger.log(Source::IP, format!("Router {} received ip packet {:?}", info.name, ip_packet)).await;
+        let name = info.name.clone();
+        let own_address = ip_packet.dest == ip || info.interface_addresses.values().any(|(address, _)| *address == ip_packet.dest);
+        self.logger.log(Source::IP, format!("Router {} received ip packet {:?}", name, ip_packet)).await;
         drop(info);
-        if ip_packet.dest == ip{
+        if own_address || self.bgp_state.lock().await.answers_for(ip_packet.dest){
             self.process_ip_content(port, ip_packet).await;
+        }else if let Some(position) = ip_packet.visited.iter().position(|hop| *hop == ip){
+            // the packet would cross the same routers until its ttl expires
+            let path = ip_packet.visited.split_off(position);
+            let hops: Vec<String> = path.iter().map(|hop| hop.to_string()).collect();
+            self.logger.log(Source::IP, format!("Router {} dropped packet from {} to {}, forwarding loop through {}", name, ip_packet.src, ip_packet.dest, hops.join(" -> "))).await;
+            self.stats.lock().unwrap().loops_detected += 1;
+            emit(&self.events, NetworkEvent::ForwardingLoop{router: name, path});
+        }else if ip_packet.ttl <= 1{
+            self.logger.log(Source::IP, format!("Router {} dropped packet from {} to {}, ttl exceeded", name, ip_packet.src, ip_packet.dest)).await;
+            if !matches!(ip_packet.content, Content::TimeExceeded(_)){
+                let src = ip_packet.src;
+                self.send_message(src, IP{src: ip, dest: src, ttl: DEFAULT_TTL, content: Content::TimeExceeded(Box::new(ip_packet)), visited: vec![]}).await;
+            }
         }else{
+            ip_packet.ttl -= 1;
+            ip_packet.visited.push(ip);
+            ip_packet.content.record_hop(ip);
             self.send_message(ip_packet.dest, ip_packet).await;
         }
     }
 
-    pub async fn process_ip_content(&self, port: u32, ip_packet: IP){
+    pub async fn process_ip_content(&mut self, port: u32, ip_packet: IP){
         let info = self.router_info.lock().await;
-        let ip = info.ip.clone();
-        let name = info.name.clone();
+        let (name, ip) = (info.name.clone(), info.ip);
         drop(info);
         match ip_packet.content{
-            Content::Ping => {
+            Content::Ping(id, seq, route) => {
                 self.logger.log(Source::PING, format!("Router {} received ping from {}", name, ip_packet.src)).await;
-                self.send_message(ip_packet.src, IP{src: ip, dest: ip_packet.src, content: Content::Pong}).await;
+                let route = route.map(|mut forward| {
+                    forward.push(ip);
+                    (forward, vec![ip])
+                });
+                // answer from the pinged address, it may belong to a custom prefix
+                self.send_message(ip_packet.src, IP{src: ip_packet.dest, dest: ip_packet.src, ttl: DEFAULT_TTL, content: Content::Pong(id, seq, route), visited: vec![]}).await;
             },
-            Content::Pong => {
+            Content::Pong(id, seq, route) => {
                 self.logger.log(Source::PING, format!("Router {} received ping back from {}", name, ip_packet.src)).await;
+                let key = (ip_packet.src, id);
+                if self.pending_pings.get(&key).is_some_and(|(pending_seq, _, _)| *pending_seq == seq){
+                    let (_, sent, replier) = self.pending_pings.remove(&key).unwrap();
+                    let route = route.map(|(forward, mut backward)| {
+                        backward.push(ip);
+                        (forward, backward)
+                    });
+                    replier.answer(sent.elapsed().unwrap_or_default(), route);
+                }else if let Some(series) = self.ping_series.get_mut(&id).filter(|s| s.dest == ip_packet.src && (seq as usize) < s.sent.len()){
+                    if series.answered.contains_key(&seq){
+                        series.duplicates += 1;
+                    }else{
+                        if series.answered.range(seq..).next().is_some(){
+                            series.reordered += 1;
+                        }
+                        series.answered.insert(seq, series.sent[seq as usize].elapsed().unwrap_or_default());
+                    }
+                    if series.answered.len() as u32 == series.count{
+                        self.finish_ping_series(id).await;
+                    }
+                }else if self.traceroute.as_ref().is_some_and(|t| t.dest == ip_packet.src && t.id == id && t.seq == seq){
+                    // the destination answered, the trace is complete
+                    self.traceroute.as_mut().unwrap().hops.push(ip_packet.src);
+                    self.finish_traceroute().await;
+                }
+            },
+            Content::TimeExceeded(original) => {
+                self.logger.log(Source::PING, format!("Router {} received time exceeded from {}", name, ip_packet.src)).await;
+                if let Content::Ping(id, seq, _) = original.content{
+                    if let Some(traceroute) = self.traceroute.as_mut().filter(|t| t.id == id && t.seq == seq){
+                        traceroute.hops.push(ip_packet.src);
+                        if traceroute.ttl >= TRACEROUTE_MAX_HOPS{
+                            self.finish_traceroute().await;
+                        }else{
+                            self.send_probe().await;
+                        }
+                    }
+                }
+            },
+            Content::Ibgp(ibgp_message) => {
+                self.bgp_state.lock().await.process_ibgp_message(port, ip_packet.src, ibgp_message).await
             },
-            Content::Data(data) => {
-                self.logger.log(Source::IP, format!("Router {} received data {} from {}", name, data, ip_packet.src)).await;
+            Content::Data(payload) => {
+                self.logger.log(Source::IP, format!("Router {} received data {:?} from {}", name, payload, ip_packet.src)).await;
+                self.received_data.push((ip_packet.src, payload));
             },
-            Content::IBGP(ibgp_message) => {
-                self.bgp_state.lock().await.process_ibgp_message(port, ibgp_message).await
+            Content::Probe(flow, sent) => {
+                let (count, latency) = self.received_probes.entry(flow).or_default();
+                *count += 1;
+                *latency += sent.elapsed().unwrap_or_default();
             },
         }
     }
 
     pub async fn send_message(&self, dest: Ipv4Addr, message: IP){
         let bgp_state = self.bgp_state.lock().await;
-        if let Some(nexthop) = bgp_state.get_nexthop(dest).await{
+        if let Some(nexthop) = bgp_state.get_nexthop(message.src, dest).await{
             self.igp_state.lock().await.send_message(nexthop, message).await;
         }else{
             self.igp_state.lock().await.send_message(message.dest, message).await;
         }
     }
 
-    pub async fn send_ping(&self, dest: Ipv4Addr){
+    // the route is recorded when the replier waits for it, starting with the router itself
+    pub async fn send_ping(&mut self, dest: Ipv4Addr, replier: PingReplier){
         let info = self.router_info.lock().await;
         let src = info.ip.clone();
         let name = info.name.clone();
         drop(info);
+        let id = self.next_echo_id;
+        self.next_echo_id += 1;
+        let route = matches!(replier, PingReplier::Route(_)).then(|| vec![src]);
+        self.pending_pings.insert((dest, id), (0, SystemTime::now(), replier));
         self.logger.log(Source::PING, format!("Router {} sending ping message to {}", name, dest)).await;
-        self.send_message(dest, IP{src, dest, content: Content::Ping}).await;
+        self.send_message(dest, IP{src, dest, ttl: DEFAULT_TTL, content: Content::Ping(id, 0, route), visited: vec![]}).await;
     }
 
-    pub async fn receive_command(&mut self) -> bool{
-        match self.command_receiver.try_recv(){
-            Ok(command) => {
-                match command{
-                    Command::AddLink(receiver, sender, port, cost) => {
-                        let mut info = self.router_info.lock().await;
-                        self.logger.log(Source::DEBUG, format!("Router {} received adding link", info.name)).await;
-                        let receiver = Arc::new(Mutex::new(receiver));
-                        info.neighbors_links.insert(port, (receiver, sender));
-                        info.igp_links.insert(port, cost);
-                        false
-                    },
-                    Command::Quit => true,
-                    Command::StatePorts => panic!("Unsupported command"),
-                    Command::Ping(dest) => {
-                        self.send_ping(dest).await;
-                        false
-                    },
-                    Command::RoutingTable => {
-                        self.command_replier.send(Response::RoutingTable(self.igp_state.lock().await.routing_table.clone())).await.expect("Failed to send the routing table");
-                        false
-                    },
-                    Command::AddPeerLink(receiver, sender, port, med, other_ip) => {
-                        let mut info = self.router_info.lock().await;
-                        self.logger.log(Source::DEBUG, format!("Router {} received adding peer link", info.name)).await;
-                        let receiver = Arc::new(Mutex::new(receiver));
-                        info.neighbors_links.insert(port, (receiver, sender));
-                        info.bgp_links.insert(port, (100, med));
-                        let prefix = IPPrefix{ip: other_ip, prefix_len: 32};
-                        let mut igp_state = self.igp_state.lock().await;
-                        igp_state.routing_table.insert(prefix, (port, 1));
-                        igp_state.prefixes.insert(prefix, prefix);
-                        igp_state.direct_neighbors.insert((1, port, prefix));
-                        false
-                    },
-                    Command::AddProvider(receiver, sender, port, med, other_ip) => {
-                        let mut info = self.router_info.lock().await;
-                        self.logger.log(Source::DEBUG, format!("Router {} received adding provider link", info.name)).await;
-                        let receiver = Arc::new(Mutex::new(receiver));
-                        info.neighbors_links.insert(port, (receiver, sender));
-                        info.bgp_links.insert(port, (50, med));
-                        let prefix = IPPrefix{ip: other_ip, prefix_len: 32};
-                        let mut igp_state = self.igp_state.lock().await;
-                        igp_state.routing_table.insert(prefix, (port, 1));
-                        igp_state.prefixes.insert(prefix, prefix);
-                        igp_state.direct_neighbors.insert((1, port, prefix));
-                        false
-                    },
-                    Command::AddCustomer(receiver, sender, port, med, other_ip) => {
-                        let mut info = self.router_info.lock().await;
-                        self.logger.log(Source::DEBUG, format!("Router {} received adding customer link", info.name)).await;
-                        let receiver = Arc::new(Mutex::new(receiver));
-                        info.neighbors_links.insert(port, (receiver, sender));
-                        info.bgp_links.insert(port, (150, med));
-                        let prefix = IPPrefix{ip: other_ip, prefix_len: 32};
-                        let mut igp_state = self.igp_state.lock().await;
-                        igp_state.routing_table.insert(prefix, (port, 1));
-                        igp_state.prefixes.insert(prefix, prefix);
-                        igp_state.direct_neighbors.insert((1, port, prefix));
-                        false
-                    },
-                    Command::AnnouncePrefix => {
-                        self.bgp_state.lock().await.announce_prefix().await;
-                        false
-                    },
-                    Command::BGPRoutes => {
-                        let bgp_state = self.bgp_state.lock().await;
-                        let mut routes = HashMap::new();
-                        
-                        for (prefix, r) in bgp_state.routes.iter(){
-                            let best_route = bgp_state.decision_process(*prefix).await;
-                            routes.insert(*prefix, (best_route, r.clone()));
-                        }
-                        self.command_replier.send(Response::BGPRoutes(routes)).await.expect("Failed to send the routing table");
-                        false
-                    },
-                    Command::AddIBGP(peer_addr) => {
-                        let mut info = self.router_info.lock().await;
-                        self.logger.log(Source::DEBUG, format!("Router {} received adding ibp connection to {}", info.name, peer_addr)).await;
-                        info.ibgp_peers.push(peer_addr);
-                        false
-                    },
+    pub async fn start_ping_series(&mut self, dest: Ipv4Addr, count: u32, interval: Duration, replier: oneshot::Sender<PingReport>){
+        let id = self.next_echo_id;
+        self.next_echo_id += 1;
+        let series = PingSeries{dest, count, interval, started: Instant::now(), sent: vec![], answered: BTreeMap::new(), duplicates: 0, reordered: 0, replier};
+        self.ping_series.insert(id, series);
+        self.advance_ping_series().await;
+    }
+
+    // send the pings whose time came, then end the series whose last ping timed out
+    async fn advance_ping_series(&mut self){
+        let src = self.router_info.lock().await.ip;
+        let mut due = vec![];
+        let mut finished = vec![];
+        for (id, series) in self.ping_series.iter_mut(){
+            while (series.sent.len() as u32) < series.count && series.deadline() <= Instant::now(){
+                due.push((series.dest, *id, series.sent.len() as u32));
+                series.sent.push(SystemTime::now());
+            }
+            if series.sent.len() as u32 == series.count && series.deadline() <= Instant::now(){
+                finished.push(*id);
+            }
+        }
+        for (dest, id, seq) in due{
+            self.send_message(dest, IP{src, dest, ttl: DEFAULT_TTL, content: Content::Ping(id, seq, None), visited: vec![]}).await;
+        }
+        for id in finished{
+            self.finish_ping_series(id).await;
+        }
+    }
+
+    async fn finish_ping_series(&mut self, id: u32){
+        if let Some(series) = self.ping_series.remove(&id){
+            let dest = series.dest;
+            let report = series.report();
+            self.logger.log(Source::PING, format!("Router {} pinged {}: {}", self.router_info.lock().await.name, dest, report)).await;
+        }
+    }
+
+    pub async fn send_data(&mut self, dest: Ipv4Addr, payload: String){
+        let info = self.router_info.lock().await;
+        let src = info.ip;
+        self.logger.log(Source::IP, format!("Router {} sending data {:?} to {}", info.name, payload, dest)).await;
+        drop(info);
+        self.send_message(dest, IP{src, dest, ttl: DEFAULT_TTL, content: Content::Data(payload), visited: vec![]}).await;
+    }
+
+    pub async fn send_flow_probe(&mut self, dest: Ipv4Addr, flow: u64){
+        let src = self.router_info.lock().await.ip;
+        self.send_message(dest, IP{src, dest, ttl: DEFAULT_TTL, content: Content::Probe(flow, SystemTime::now()), visited: vec![]}).await;
+    }
+
+    pub async fn start_traceroute(&mut self, dest: Ipv4Addr, replier: oneshot::Sender<Vec<Ipv4Addr>>){
+        // a single trace at a time, the previous one returns the hops found so far
+        self.finish_traceroute().await;
+        let id = self.next_echo_id;
+        self.next_echo_id += 1;
+        self.traceroute = Some(Traceroute{dest, id, seq: 0, ttl: 0, sent: SystemTime::now(), hops: vec![], replier});
+        self.send_probe().await;
+    }
+
+    // send a ping with a ttl one higher than the previous probe
+    async fn send_probe(&mut self){
+        let src = self.router_info.lock().await.ip;
+        let traceroute = self.traceroute.as_mut().unwrap();
+        traceroute.seq += 1;
+        traceroute.ttl += 1;
+        traceroute.sent = SystemTime::now();
+        let (dest, id, seq, ttl) = (traceroute.dest, traceroute.id, traceroute.seq, traceroute.ttl);
+        self.logger.log(Source::PING, format!("Router {} sending traceroute probe to {} with ttl {}", self.router_info.lock().await.name, dest, ttl)).await;
+        self.send_message(dest, IP{src, dest, ttl, content: Content::Ping(id, seq, None), visited: vec![]}).await;
+    }
+
+    async fn finish_traceroute(&mut self){
+        if let Some(traceroute) = self.traceroute.take(){
+            let _ = traceroute.replier.send(traceroute.hops);
+        }
+    }
+
+    async fn check_ping_timeouts(&mut self){
+        let expired: Vec<(Ipv4Addr, u32)> = self.pending_pings.iter()
+            .filter(|(_, (_, sent, _))| sent.elapsed().unwrap_or_default() > PING_TIMEOUT)
+            .map(|(key, _)| *key)
+            .collect();
+        for (dest, id) in expired{
+            let (_, _, replier) = self.pending_pings.remove(&(dest, id)).unwrap();
+            self.logger.log(Source::PING, format!("Ping to {} timed out", dest)).await;
+            replier.fail(PingError::Timeout(dest));
+        }
+        if self.traceroute.as_ref().is_some_and(|t| t.sent.elapsed().unwrap_or_default() > PING_TIMEOUT){
+            // a hop didn't answer, return the hops found so far
+            self.finish_traceroute().await;
+        }
+    }
+
+    // the bgp ports are told by their sessions, opened as soon as the link is added, the other ports are igp links
+    async fn ports(&self) -> BTreeMap<u32, PortInfo>{
+        let info = self.router_info.lock().await;
+        let igp_links = info.igp_links.clone();
+        let linked: HashSet<u32> = info.neighbors_links.keys().copied().collect();
+        drop(info);
+        let mut ports = BTreeMap::new();
+
+        let igp_state = self.igp_state.lock().await;
+        for (port, cost) in igp_links{
+            let neighbor_ip = igp_state.direct_neighbors.iter()
+                .find(|(_, p, _)| *p == port)
+                .map(|(_, _, prefix)| igp_state.neighbor_address(port, prefix.ip));
+            ports.insert(port, PortInfo{kind: PortKind::Igp, cost_or_pref: cost, neighbor_ip});
+        }
+        drop(igp_state);
+
+        let bgp_state = self.bgp_state.lock().await;
+        // the session of a removed link is kept, idle, until a link is added on its port again
+        for session in bgp_state.sessions.values().filter(|session| linked.contains(&session.port)){
+            let (relationship, _) = session.link;
+            let kind = match relationship{
+                CUSTOMER_PREF => PortKind::Customer,
+                PEER_PREF => PortKind::Peer,
+                _ => PortKind::Provider,
+            };
+            // the pref set on the link or at runtime replaces the one of the relationship
+            let pref = bgp_state.local_prefs.get(&session.neighbor).map_or(relationship, |(_, pref)| *pref);
+            ports.insert(session.port, PortInfo{kind, cost_or_pref: pref, neighbor_ip: Some(session.neighbor)});
+        }
+        ports
+    }
+
+    async fn health(&self) -> RouterHealth{
+        let info = self.router_info.lock().await;
+        let name = info.name.clone();
+        let ports: Vec<u32> = info.neighbors_links.keys().copied().collect();
+        drop(info);
+        let mut anomalies = vec![];
+
+        let igp_state = self.igp_state.lock().await;
+        let interfaces = ports.into_iter().map(|port| {
+            let up = igp_state.direct_neighbors.iter().any(|(_, p, _)| *p == port) || igp_state.ebgp_neighbors.contains_key(&port);
+            (port, if up { InterfaceState::Up } else { InterfaceState::Waiting })
+        }).collect();
+        let mut ospf_neighbors = BTreeMap::new();
+        for ((_, neighbor), last) in igp_state.last_hello.iter(){
+            let elapsed = last.elapsed().as_millis() as u64;
+            let entry = ospf_neighbors.entry(neighbor.ip).or_insert(elapsed);
+            *entry = u64::min(*entry, elapsed);
+        }
+        let lsp_database = igp_state.topo.len();
+        let spf_runs = igp_state.spf_runs;
+        let routing_table = igp_state.routing_table.len();
+        let ospf_trie_nodes = igp_state.prefixes.node_count();
+        if igp_state.lsp_evictions > 0{
+            anomalies.push(format!("{} lsps evicted", igp_state.lsp_evictions));
+        }
+        drop(igp_state);
+
+        let bgp_state = self.bgp_state.lock().await;
+        let mut bgp_sessions = BTreeMap::new();
+        for session in bgp_state.sessions.values(){
+            let prefixes = bgp_state.routes.values().filter(|routes| routes.iter().any(|r| r.nexthop == session.neighbor)).count();
+            bgp_sessions.insert(session.neighbor, SessionHealth{state: session.state, prefixes});
+            if session.secret_mismatch{
+                anomalies.push(format!("secret mismatch with {}", session.neighbor));
+            }
+        }
+        if bgp_state.route_evictions > 0{
+            anomalies.push(format!("{} bgp routes evicted", bgp_state.route_evictions));
+        }
+        let bgp_trie_nodes = bgp_state.prefixes.node_count();
+        drop(bgp_state);
+
+        RouterHealth{
+            name,
+            interfaces,
+            ospf_neighbors,
+            lsp_database,
+            spf_runs,
+            routing_table,
+            ospf_trie_nodes,
+            bgp_trie_nodes,
+            bgp_sessions,
+            command_queue: self.command_receiver.len(),
+            link_queue: self.link_receiver.len(),
+            anomalies
+        }
+    }
+
+    // the routing table and the BGP routes didn't change for a few hello intervals and no message is waiting
+    async fn converged(&mut self) -> bool{
+        let fingerprint = self.routes_fingerprint().await;
+        if fingerprint != self.routes_fingerprint.0{
+            self.routes_fingerprint = (fingerprint, Instant::now());
+            return false;
+        }
+        self.routes_fingerprint.1.elapsed() >= self.timers.hello_interval * CONVERGENCE_INTERVALS && self.link_receiver.is_empty()
+    }
+
+    // the same whatever the order of the routes in their maps
+    async fn routes_fingerprint(&self) -> u64{
+        fn hash(value: impl Hash) -> u64{
+            let mut hasher = DefaultHasher::new();
+            value.hash(&mut hasher);
+            hasher.finish()
+        }
+        let igp_state = self.igp_state.lock().await;
+        let mut fingerprint = igp_state.routing_table.iter().fold(0u64, |total, entry| total.wrapping_add(hash(entry)));
+        drop(igp_state);
+        let bgp_state = self.bgp_state.lock().await;
+        for (prefix, routes) in bgp_state.routes.iter(){
+            fingerprint = routes.iter().fold(fingerprint, |total, route| total.wrapping_add(hash((prefix, route))));
+        }
+        fingerprint
+    }
+
+    pub async fn process_command(&mut self, command: Command) -> bool{
+        // a change of configuration restarts the quiet period of converged, the messages it sends
+        // may not have reached the neighbors yet when they are asked if they converged
+        if matches!(command, Command::AddLink(..) | Command::SetInterfaceAddress(..) | Command::RemoveLink(_) | Command::SetPortEnabled(..)
+            | Command::SetLinkCost(..) | Command::AddPeerLink(..) | Command::AddProvider(..) | Command::AddCustomer(..)
+            | Command::AddIBGP(_) | Command::AddIBGPClient(_) | Command::AnnouncePrefix(_) | Command::AnnouncePrefixCustom(_)
+            | Command::AnnouncePrefixWithCommunities(_) | Command::AnnouncePrefixWithOrigin(_) | Command::WithdrawPrefix
+            | Command::ClearRoutes | Command::ClearBGP | Command::AddConditionalAdvertisement(..) | Command::SetLocalPref(..)
+            | Command::SetIGPMed(..) | Command::SetMultipath(_) | Command::SetEcmp(_) | Command::SetPrepend(..)
+            | Command::AddCommunityPolicy(..) | Command::SetMaxPrefixes(..) | Command::SetImportPolicy(..)
+            | Command::SetExportPolicy(..) | Command::AddStaticRoute(..) | Command::SetOSPF(_)){
+            self.routes_fingerprint.1 = Instant::now();
+        }
+        match command{
+            Command::AddLink(receiver, sender, port, cost) => {
+                let link = self.add_link(receiver, port);
+                let mut info = self.router_info.lock().await;
+                self.logger.log(Source::DEBUG, format!("Router {} received adding link", info.name)).await;
+                info.neighbors_links.insert(port, (link, sender.counted(port, &self.stats)));
+                info.igp_links.insert(port, cost);
+                drop(info);
+                self.arp_state.lock().await.announce(port).await;
+                false
+            },
+            Command::SetInterfaceAddress(port, address, subnet) => {
+                let mut info = self.router_info.lock().await;
+                self.logger.log(Source::DEBUG, format!("Router {} has address {} on port {}", info.name, address, port)).await;
+                info.interface_addresses.insert(port, (address, subnet));
+                drop(info);
+                self.igp_state.lock().await.add_connected_subnet(subnet, port).await;
+                false
+            },
+            Command::RemoveLink(port) => {
+                let mut info = self.router_info.lock().await;
+                self.logger.log(Source::DEBUG, format!("Router {} removing link on port {}", info.name, port)).await;
+                // the messages still in flight on the link are dropped
+                info.neighbors_links.remove(&port);
+                info.igp_links.remove(&port);
+                info.bgp_links.remove(&port);
+                info.interface_addresses.remove(&port);
+                drop(info);
+                self.disabled_ports.remove(&port);
+                let mut bgp_state = self.bgp_state.lock().await;
+                bgp_state.close_session(port).await;
+                bgp_state.sessions.remove(&port);
+                bgp_state.prepends.remove(&port);
+                bgp_state.import_policies.remove(&port);
+                bgp_state.export_policies.remove(&port);
+                bgp_state.max_prefixes.remove(&port);
+                drop(bgp_state);
+                self.igp_state.lock().await.remove_neighbor(port).await;
+                false
+            },
+            Command::SetPortEnabled(port, enabled) => {
+                let info = self.router_info.lock().await;
+                let Some((_, sender)) = info.neighbors_links.get(&port) else {
+                    return false;
+                };
+                sender.set_enabled(enabled);
+                self.logger.log(Source::DEBUG, format!("Router {} {} port {}", info.name, if enabled { "enabled" } else { "shut down" }, port)).await;
+                drop(info);
+                if enabled{
+                    // the neighbors behind the port are found again by the next hellos
+                    self.disabled_ports.remove(&port);
+                }else if self.disabled_ports.insert(port){
+                    self.igp_state.lock().await.shut_down_port(port).await;
+                }
+                false
+            },
+            Command::SetLinkCost(port, cost) => {
+                let mut info = self.router_info.lock().await;
+                let Some(link_cost) = info.igp_links.get_mut(&port) else {
+                    return false;
+                };
+                *link_cost = cost;
+                self.logger.log(Source::DEBUG, format!("Router {} set the cost of port {} to {}", info.name, port, cost)).await;
+                drop(info);
+                self.igp_state.lock().await.set_link_cost(port, cost).await;
+                false
+            },
+            Command::Replay(messages, replier) => {
+                for (port, message) in messages{
+                    self.dispatch_message(port, message).await;
+                }
+                self.igp_state.lock().await.update_routes().await;
+                let _ = replier.send(());
+                false
+            },
+            Command::Quit => true,
+            #[cfg(test)]
+            Command::Panic => panic!("Router {} told to panic", self.router_info.lock().await.name),
+            Command::StatePorts(_) => panic!("Unsupported command"),
+            Command::MacTable(_) => panic!("Unsupported command"),
+            Command::SetMaxAge(_) => panic!("Unsupported command"),
+            Command::SetDeadInterval(dead_interval) => {
+                self.igp_state.lock().await.dead_interval = dead_interval;
+                false
+            },
+            Command::Ping(dest, replier) => {
+                self.send_ping(dest, PingReplier::Rtt(replier)).await;
+                false
+            },
+            Command::PingRecorded(dest, replier) => {
+                self.send_ping(dest, PingReplier::Route(replier)).await;
+                false
+            },
+            Command::Traceroute(dest, replier) => {
+                self.start_traceroute(dest, replier).await;
+                false
+            },
+            Command::PingSeries(dest, count, interval, replier) => {
+                self.start_ping_series(dest, count, interval, replier).await;
+                false
+            },
+            Command::SendData(dest, payload) => {
+                self.send_data(dest, payload).await;
+                false
+            },
+            Command::ReceivedData(replier) => {
+                let _ = replier.send(self.received_data.clone());
+                false
+            },
+            Command::SendProbe(dest, flow) => {
+                self.send_flow_probe(dest, flow).await;
+                false
+            },
+            Command::ReceivedProbes(flow, replier) => {
+                let _ = replier.send(self.received_probes.get(&flow).copied().unwrap_or_default());
+                false
+            },
+            Command::RoutingTable(replier) => {
+                let _ = replier.send(self.igp_state.lock().await.routing_table.clone());
+                false
+            },
+            Command::AddPeerLink(receiver, sender, port, med, other_ip, pref) => {
+                self.add_bgp_link(receiver, sender, port, (PEER_PREF, med), other_ip, pref).await;
+                false
+            },
+            Command::AddProvider(receiver, sender, port, med, other_ip, pref) => {
+                self.add_bgp_link(receiver, sender, port, (PROVIDER_PREF, med), other_ip, pref).await;
+                false
+            },
+            Command::AddCustomer(receiver, sender, port, med, other_ip, pref) => {
+                self.add_bgp_link(receiver, sender, port, (CUSTOMER_PREF, med), other_ip, pref).await;
+                false
+            },
+            Command::AnnouncePrefix(pref) => {
+                self.bgp_state.lock().await.announce_prefix(pref).await;
+                false
+            },
+            Command::AnnouncePrefixWithCommunities(communities) => {
+                self.bgp_state.lock().await.announce_prefix_with_communities(communities).await;
+                false
+            },
+            Command::AnnouncePrefixWithOrigin(origin) => {
+                self.bgp_state.lock().await.announce_prefix_with_origin(origin).await;
+                false
+            },
+            Command::AnnouncePrefixCustom(prefix) => {
+                self.bgp_state.lock().await.announce_custom_prefix(prefix).await;
+                false
+            },
+            Command::WithdrawPrefix => {
+                self.bgp_state.lock().await.withdraw_prefix().await;
+                false
+            },
+            Command::ClearRoutes => {
+                self.igp_state.lock().await.clear_routes().await;
+                // the routes installed by BGP were cleared too
+                self.bgp_state.lock().await.install_best_routes().await;
+                false
+            },
+            Command::ClearBGP => {
+                self.bgp_state.lock().await.clear_routes().await;
+                false
+            },
+            Command::AddConditionalAdvertisement(prefix, unless_present, port) => {
+                self.bgp_state.lock().await.add_conditional_advertisement(prefix, unless_present, port).await;
+                false
+            },
+            Command::SetLocalPref(port, pref) => {
+                self.bgp_state.lock().await.set_local_pref(port, pref).await;
+                false
+            },
+            Command::AddStaticRoute(prefix, port, nexthop) => {
+                self.igp_state.lock().await.add_static_route(prefix, port, nexthop).await;
+                false
+            },
+            Command::SetOSPF(enabled) => {
+                self.igp_state.lock().await.set_enabled(enabled).await;
+                false
+            },
+            Command::SetIGPMed(port, enabled) => {
+                self.bgp_state.lock().await.set_igp_med(port, enabled).await;
+                false
+            },
+            Command::SetPrepend(port, count) => {
+                self.bgp_state.lock().await.set_prepend(port, count).await;
+                false
+            },
+            Command::AddCommunityPolicy(community, action) => {
+                self.bgp_state.lock().await.add_community_policy(community, action).await;
+                false
+            },
+            Command::SetMaxPrefixes(port, limit, action) => {
+                self.bgp_state.lock().await.set_max_prefixes(port, limit, action).await;
+                false
+            },
+            Command::SetImportPolicy(port, policy) => {
+                self.bgp_state.lock().await.set_import_policy(port, policy).await;
+                false
+            },
+            Command::SetExportPolicy(port, policy) => {
+                self.bgp_state.lock().await.set_export_policy(port, policy).await;
+                false
+            },
+            Command::SetSessionSecret(neighbor, secret) => {
+                self.bgp_state.lock().await.set_secret(neighbor, secret);
+                false
+            },
+            Command::BGPSessions(replier) => {
+                let _ = replier.send(self.bgp_state.lock().await.sessions.values().cloned().collect());
+                false
+            },
+            Command::SetLimits(limits) => {
+                self.router_info.lock().await.limits = limits;
+                false
+            },
+            Command::ConvergenceState(replier) => {
+                let _ = replier.send(self.converged().await);
+                false
+            },
+            Command::Health(replier) => {
+                let _ = replier.send(self.health().await);
+                false
+            },
+            Command::RouterPorts(replier) => {
+                let _ = replier.send(self.ports().await);
+                false
+            },
+            Command::Stats(replier) => {
+                let _ = replier.send(self.stats.lock().unwrap().clone());
+                false
+            },
+            Command::MemoryStats(replier) => {
+                let igp_state = self.igp_state.lock().await;
+                let bgp_state = self.bgp_state.lock().await;
+                let stats = MemoryStats{
+                    received_lsp: igp_state.received_lsp.len(),
+                    bgp_routes: bgp_state.routes.values().map(|routes| routes.len()).sum(),
+                    lsp_evictions: igp_state.lsp_evictions,
+                    route_evictions: bgp_state.route_evictions,
+                    ospf_trie_nodes: igp_state.prefixes.node_count(),
+                    bgp_trie_nodes: bgp_state.prefixes.node_count()
+                };
+                let _ = replier.send(stats);
+                false
+            },
+            Command::MultipathRoutes(replier) => {
+                let _ = replier.send(self.bgp_state.lock().await.multipath_table().await);
+                false
+            },
+            Command::SetMultipath(enabled) => {
+                self.bgp_state.lock().await.set_multipath(enabled).await;
+                false
+            },
+            Command::Lsdb(replier) => {
+                let _ = replier.send(self.igp_state.lock().await.lsdb().await);
+                false
+            },
+            Command::EqualCostPorts(replier) => {
+                let _ = replier.send(self.igp_state.lock().await.equal_cost_ports.clone());
+                false
+            },
+            Command::SetEcmp(enabled) => {
+                self.igp_state.lock().await.set_ecmp(enabled).await;
+                false
+            },
+            Command::BGPRoutes(replier) => {
+                let bgp_state = self.bgp_state.lock().await;
+                let mut routes = HashMap::new();
+                
+                for (prefix, r) in bgp_state.routes.iter(){
+                    let best_route = bgp_state.decision_process(*prefix).await;
+                    routes.insert(*prefix, (best_route, r.clone()));
                 }
+                let _ = replier.send(routes);
+                false
+            },
+            Command::AddIBGP(peer_addr) => {
+                let mut info = self.router_info.lock().await;
+                self.logger.log(Source::DEBUG, format!("Router {} received adding ibp connection to {}", info.name, peer_addr)).await;
+                info.ibgp_peers.push(peer_addr);
+                false
+            },
+            Command::AddIBGPClient(client_addr) => {
+                let mut info = self.router_info.lock().await;
+                self.logger.log(Source::DEBUG, format!("Router {} received adding ibgp client {}", info.name, client_addr)).await;
+                info.ibgp_peers.push(client_addr);
+                info.rr_clients.push(client_addr);
+                false
             },
-            Err(_) => false,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,885 @@
+use std::{collections::{HashMap, HashSet}, time::Duration};
+
+use super::{config::{ActionsCfg, AnnounceCfg, BgpLinkCfg, BgpMed, ConfigFile, HostCfg, IbgpSessionCfg, LinkCfg, LinkEnd, MaxPrefixesCfg, PeerLinkCfg, PolicyCfg, RolesCfg, RouterCfg, SwitchCfg}, error::NetworkError, link::{LinkImpairment, LinkRate, RateLimit}, logger::Logger, state::{Difference, NetworkState}, topology::Topology, BgpLinkAttributes, Network};
+
+// time given to the IGP and to BGP to converge when running a scenario
+pub const IGP_CONVERGENCE: Duration = Duration::from_millis(1000);
+pub const BGP_CONVERGENCE: Duration = Duration::from_millis(2000);
+// time after which a scenario stops with an error if the routers didn't converge
+pub const CONVERGENCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn load_config(path: &str) -> Result<ConfigFile, NetworkError>{
+    ConfigFile::load(path)
+}
+
+fn routers(config: &ConfigFile) -> &[RouterCfg]{
+    &config.network.routers
+}
+
+fn generate_routers(network: &mut Network, config: &ConfigFile, steps: &mut Vec<String>) -> Result<(), NetworkError>{
+    for router in routers(config){
+        network.add_router(&router.name, router.id, router.router_as)?;
+        steps.push(format!("Added router {} with id {} in AS {}", router.name, router.id, router.router_as));
+    }
+    Ok(())
+}
+
+fn generate_switchs(network: &mut Network, config: &ConfigFile, steps: &mut Vec<String>){
+    for switch in &config.network.switches{
+        network.add_switch(&switch.name, switch.id);
+        steps.push(format!("Added switch {} with id {}", switch.name, switch.id));
+    }
+}
+
+fn generate_hosts(network: &mut Network, config: &ConfigFile, steps: &mut Vec<String>){
+    for host in &config.network.hosts{
+        network.add_host(&host.name, host.ip, host.gateway);
+        steps.push(format!("Added host {} with ip {} and gateway {}", host.name, host.ip, host.gateway));
+    }
+}
+
+fn describe_latency(device1: &str, device2: &str, latency: Duration, steps: &mut Vec<String>){
+    if !latency.is_zero(){
+        steps.push(format!("Link between {} and {} delays its messages by {}ms", device1, device2, latency.as_millis()));
+    }
+}
+
+fn generate_impairment(network: &Network, (device1, port1): (&str, u32), device2: &str, impairment: Option<LinkImpairment>, steps: &mut Vec<String>) -> Result<(), NetworkError>{
+    if let Some(i) = impairment{
+        network.set_link_impairment(device1, port1, Some(i))?;
+        steps.push(format!("Link between {} and {} has loss probability {}, reorder probability {} and duplicate probability {} (seed {})",
+            device1, device2, i.loss_prob, i.reorder_prob, i.duplicate_prob, i.seed));
+    }
+    Ok(())
+}
+
+fn generate_rate_limit(network: &Network, (device1, port1): (&str, u32), device2: &str, rate_limit: Option<RateLimit>, steps: &mut Vec<String>) -> Result<(), NetworkError>{
+    if let Some(r) = rate_limit{
+        network.set_link_rate_limit(device1, port1, Some(r))?;
+        let rate = match r.rate{
+            LinkRate::MessagesPerSec(rate) => format!("{} messages", rate),
+            LinkRate::BytesPerSec(rate) => format!("{} bytes", rate),
+        };
+        steps.push(format!("Link between {} and {} sends {} per second, with a queue of {} messages", device1, device2, rate, r.queue_depth));
+    }
+    Ok(())
+}
+
+async fn generate_igp_med(network: &Network, (router1, router2): (&str, &str), med: BgpMed) -> Result<(), NetworkError>{
+    if med == BgpMed::Igp{
+        network.set_igp_med(router1, router2, true).await?;
+        network.set_igp_med(router2, router1, true).await?;
+    }
+    Ok(())
+}
+
+// ports given by the internal links, the other links never get them even if they are added before
+fn explicit_ports(config: &ConfigFile) -> HashMap<&str, HashSet<u32>>{
+    let mut ports: HashMap<&str, HashSet<u32>> = HashMap::new();
+    for link in &config.network.links.internal{
+        for end in [&link.ends.0, &link.ends.1]{
+            if let Some(port) = end.port{
+                ports.entry(end.device.as_str()).or_default().insert(port);
+            }
+        }
+    }
+    ports
+}
+
+// the links of the config get the same ports whether its devices are started or only drawn
+trait FreePorts{
+    fn next_free_port_except(&self, device: &str, reserved: &HashSet<u32>) -> Result<u32, NetworkError>;
+}
+
+impl FreePorts for Network{
+    fn next_free_port_except(&self, device: &str, reserved: &HashSet<u32>) -> Result<u32, NetworkError>{
+        Network::next_free_port_except(self, device, reserved)
+    }
+}
+
+impl FreePorts for Topology{
+    fn next_free_port_except(&self, device: &str, reserved: &HashSet<u32>) -> Result<u32, NetworkError>{
+        Topology::next_free_port_except(self, device, reserved)
+    }
+}
+
+fn allocate_port(network: &impl FreePorts, device: &str, reserved: &HashMap<&str, HashSet<u32>>) -> Result<u32, NetworkError>{
+    network.next_free_port_except(device, reserved.get(device).unwrap_or(&HashSet::new()))
+}
+
+fn link_end_port(network: &impl FreePorts, end: &LinkEnd, reserved: &HashMap<&str, HashSet<u32>>) -> Result<u32, NetworkError>{
+    match end.port{
+        Some(port) => Ok(port),
+        None => allocate_port(network, &end.device, reserved),
+    }
+}
+
+async fn generate_links(network: &mut Network, config: &ConfigFile, steps: &mut Vec<String>) -> Result<(), NetworkError>{
+    let links = &config.network.links;
+    let reserved = explicit_ports(config);
+
+    for link in &links.internal{
+        let (r1, r2) = (link.ends.0.device.as_str(), link.ends.1.device.as_str());
+        let port1 = link_end_port(network, &link.ends.0, &reserved)?;
+        let port2 = link_end_port(network, &link.ends.1, &reserved)?;
+
+        network.add_link_with_latency(r1, port1, r2, port2, link.cost, link.latency).await?;
+        steps.push(format!("Link from {}:{} to {}:{} added with cost {}", r1, port1, r2, port2, link.cost));
+        describe_latency(r1, r2, link.latency, steps);
+        generate_impairment(network, (r1, port1), r2, link.impairment, steps)?;
+        generate_rate_limit(network, (r1, port1), r2, link.rate_limit, steps)?;
+        if let Some(address) = link.address{
+            network.set_interface_address(r1, port1, address.ip, address.subnet).await?;
+            steps.push(format!("Router {} has address {} on subnet {}", r1, address.ip, address.subnet));
+        }
+    }
+
+    for link in &links.bgp.provider_customer{
+        let (provider, customer) = (link.provider.as_str(), link.customer.as_str());
+        let (med, _) = link.med.med();
+        let port1 = allocate_port(network, provider, &reserved)?;
+        let port2 = allocate_port(network, customer, &reserved)?;
+
+        let attributes = BgpLinkAttributes{med, prefs: link.local_pref.clone().pair(), latency: link.latency};
+        network.add_provider_customer_link_with_attributes(provider, port1, customer, port2, attributes).await?;
+        generate_igp_med(network, (provider, customer), link.med).await?;
+        steps.push(format!("BGP link from provider {}:{} to customer {}:{} added with med {}", provider, port1, customer, port2, link.med));
+        describe_latency(provider, customer, link.latency, steps);
+        generate_impairment(network, (provider, port1), customer, link.impairment, steps)?;
+        generate_rate_limit(network, (provider, port1), customer, link.rate_limit, steps)?;
+        let ends = [(provider, port1), (customer, port2)];
+        generate_prepends(network, ends, link.prepend.clone().pair(), steps).await?;
+        generate_policies(network, ends, [link.import.clone().pair(), link.export.clone().pair()], steps).await?;
+        generate_max_prefixes(network, ends, link.max_prefixes.clone().pair(), steps).await?;
+    }
+
+    for link in &links.bgp.peer{
+        let (r1, r2) = (link.peers.0.as_str(), link.peers.1.as_str());
+        let (med, _) = link.med.med();
+        let port1 = allocate_port(network, r1, &reserved)?;
+        let port2 = allocate_port(network, r2, &reserved)?;
+
+        let attributes = BgpLinkAttributes{med, prefs: link.local_pref, latency: link.latency};
+        network.add_peer_link_with_attributes(r1, port1, r2, port2, attributes).await?;
+        generate_igp_med(network, (r1, r2), link.med).await?;
+        steps.push(format!("Peer link from {}:{} to {}:{} added with med {}", r1, port1, r2, port2, link.med));
+        describe_latency(r1, r2, link.latency, steps);
+        generate_impairment(network, (r1, port1), r2, link.impairment, steps)?;
+        generate_rate_limit(network, (r1, port1), r2, link.rate_limit, steps)?;
+        let ends = [(r1, port1), (r2, port2)];
+        generate_prepends(network, ends, link.prepend, steps).await?;
+        generate_policies(network, ends, [link.import.clone(), link.export.clone()], steps).await?;
+        generate_max_prefixes(network, ends, link.max_prefixes, steps).await?;
+    }
+
+    // the pairs of the full meshes that already have a session are skipped
+    let mut sessions = HashSet::new();
+    for session in &links.bgp.ibgp{
+        match session{
+            IbgpSessionCfg::RouteReflector{rr, client} => {
+                network.add_ibgp_client(rr, client).await?;
+                steps.push(format!("IBGP session added between route reflector {} and its client {}", rr, client));
+                sessions.insert(session_key(rr, client));
+            },
+            IbgpSessionCfg::Mesh(r1, r2) => {
+                network.add_ibgp_connection(r1, r2).await?;
+                steps.push(format!("IBGP session added between {} and {}", r1, r2));
+                sessions.insert(session_key(r1, r2));
+            },
+        }
+    }
+    for router_as in &links.bgp.ibgp_full_mesh{
+        let routers = network.routers_of_as(*router_as).to_vec();
+        if routers.is_empty(){
+            return Err(NetworkError::InvalidConfig(format!("AS {} of the iBGP full mesh has no routers", router_as)));
+        }
+        for (i, r1) in routers.iter().enumerate(){
+            for r2 in &routers[i + 1..]{
+                if sessions.insert(session_key(r1, r2)){
+                    network.add_ibgp_connection(r1, r2).await?;
+                    steps.push(format!("IBGP session added between {} and {}", r1, r2));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// the same for both orders of the routers
+fn session_key(r1: &str, r2: &str) -> (String, String){
+    (r1.min(r2).to_string(), r1.max(r2).to_string())
+}
+
+async fn generate_prepends(network: &Network, ends: [(&str, u32); 2], prepends: (u32, u32), steps: &mut Vec<String>) -> Result<(), NetworkError>{
+    for ((router, port), count) in ends.into_iter().zip([prepends.0, prepends.1]){
+        if count > 0{
+            network.set_prepend(router, port, count).await?;
+            steps.push(format!("Router {} prepends its AS {} more times on port {}", router, count, port));
+        }
+    }
+    Ok(())
+}
+
+// import and export policies of the two ends of a BGP link
+async fn generate_policies(network: &Network, ends: [(&str, u32); 2], policies: [(Option<PolicyCfg>, Option<PolicyCfg>); 2], steps: &mut Vec<String>) -> Result<(), NetworkError>{
+    let [imports, exports] = policies;
+    for ((router, port), (import, export)) in ends.into_iter().zip([(imports.0, exports.0), (imports.1, exports.1)]){
+        if let Some(PolicyCfg(policy)) = import{
+            steps.push(format!("Router {} applies the import policy {} on port {}", router, policy, port));
+            network.set_import_policy(router, port, policy).await?;
+        }
+        if let Some(PolicyCfg(policy)) = export{
+            steps.push(format!("Router {} applies the export policy {} on port {}", router, policy, port));
+            network.set_export_policy(router, port, policy).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn generate_max_prefixes(network: &Network, ends: [(&str, u32); 2], max_prefixes: (Option<MaxPrefixesCfg>, Option<MaxPrefixesCfg>), steps: &mut Vec<String>) -> Result<(), NetworkError>{
+    for ((router, port), max_prefixes) in ends.into_iter().zip([max_prefixes.0, max_prefixes.1]){
+        if let Some(MaxPrefixesCfg{limit, action}) = max_prefixes{
+            network.set_max_prefixes(router, port, limit, action).await?;
+            steps.push(format!("Router {} accepts at most {} prefixes on port {}", router, limit, port));
+        }
+    }
+    Ok(())
+}
+
+// the secrets are compared when the sessions are opened, they are set before the links are added
+async fn generate_session_secrets(network: &Network, config: &ConfigFile, steps: &mut Vec<String>) -> Result<(), NetworkError>{
+    for router in routers(config){
+        for (neighbor, secret) in &router.secrets{
+            network.set_session_secret(&router.name, neighbor, Some(secret)).await?;
+            steps.push(format!("Router {} expects a secret on its session with {}", router.name, neighbor));
+        }
+    }
+    Ok(())
+}
+
+async fn generate_conditional_advertisements(network: &Network, config: &ConfigFile, steps: &mut Vec<String>) -> Result<(), NetworkError>{
+    for router in routers(config){
+        for rule in &router.advertise{
+            network.add_conditional_advertisement(&router.name, rule.prefix, rule.unless_present, &rule.session).await?;
+            steps.push(format!("Router {} advertises {} to {} unless {} is present", router.name, rule.prefix, rule.session, rule.unless_present));
+        }
+    }
+    Ok(())
+}
+
+fn announcing_router(announce: &AnnounceCfg) -> Option<&str>{
+    match announce{
+        AnnounceCfg::As(_) => None,
+        AnnounceCfg::Router(router) | AnnounceCfg::WithPref{router, ..} | AnnounceCfg::Prefix{router, ..} => Some(router),
+    }
+}
+
+// names of the devices used by the bgp links, the ibgp sessions and the announces of the config
+fn bgp_devices(config: &ConfigFile) -> Vec<&str>{
+    let mut devices = vec![];
+    let bgp = &config.network.links.bgp;
+    for link in &bgp.provider_customer{
+        devices.extend([link.provider.as_str(), link.customer.as_str()]);
+    }
+    for link in &bgp.peer{
+        devices.extend([link.peers.0.as_str(), link.peers.1.as_str()]);
+    }
+    for session in &bgp.ibgp{
+        match session{
+            IbgpSessionCfg::Mesh(r1, r2) => devices.extend([r1.as_str(), r2.as_str()]),
+            IbgpSessionCfg::RouteReflector{rr, client} => devices.extend([rr.as_str(), client.as_str()]),
+        }
+    }
+    for announce in config.network.actions.iter().flat_map(|actions| &actions.announce_prefix){
+        devices.extend(announcing_router(announce));
+    }
+    devices
+}
+
+// a switch used in the bgp configuration is reported before any device is started
+fn check_bgp_devices(config: &ConfigFile) -> Result<(), NetworkError>{
+    let switches: Vec<&str> = config.network.switches.iter().map(|switch| switch.name.as_str()).collect();
+    match bgp_devices(config).into_iter().find(|device| switches.contains(device)){
+        Some(switch) => Err(NetworkError::SwitchInBGP(switch.to_string())),
+        None => Ok(()),
+    }
+}
+
+// OSPF is disabled before the links are added, so that the routers never exchange hellos
+async fn disable_ospf(network: &mut Network, config: &ConfigFile, steps: &mut Vec<String>) -> Result<(), NetworkError>{
+    for router in routers(config).iter().filter(|router| !router.ospf){
+        network.set_ospf(&router.name, false).await?;
+        steps.push(format!("OSPF disabled on router {}", router.name));
+    }
+    Ok(())
+}
+
+async fn generate_static_routes(network: &Network, config: &ConfigFile, steps: &mut Vec<String>) -> Result<(), NetworkError>{
+    for router in routers(config){
+        for route in &router.static_routes{
+            network.add_static_route(&router.name, route.prefix, route.nexthop).await?;
+            steps.push(format!("Static route to {} via {} added on router {}", route.prefix, route.nexthop, router.name));
+        }
+    }
+    Ok(())
+}
+
+// Checks the topology of the config without starting its devices: their names, the ends of the links and their ports
+pub fn validate(config: &ConfigFile) -> Result<(), NetworkError>{
+    check_bgp_devices(config)?;
+    let network = &config.network;
+    let routers: HashSet<&str> = network.routers.iter().map(|router| router.name.as_str()).collect();
+    let hosts: HashSet<&str> = network.hosts.iter().map(|host| host.name.as_str()).collect();
+    let names = network.routers.iter().map(|router| &router.name)
+        .chain(network.switches.iter().map(|switch| &switch.name))
+        .chain(network.hosts.iter().map(|host| &host.name));
+    let mut ports: HashMap<&str, HashSet<u32>> = HashMap::new();
+    for name in names{
+        if ports.insert(name, HashSet::new()).is_some(){
+            return Err(NetworkError::InvalidConfig(format!("Device {} is defined twice", name)));
+        }
+    }
+
+    // the ports are given as generate_links does
+    let reserved = explicit_ports(config);
+    let mut connect = |device: &str, port: Option<u32>| -> Result<(), NetworkError>{
+        let used = ports.get_mut(device).ok_or(NetworkError::UnknownDevice(device.to_string()))?;
+        let kept = reserved.get(device);
+        let port = port.unwrap_or_else(|| (1..).find(|p| !used.contains(p) && !kept.is_some_and(|kept| kept.contains(p))).unwrap());
+        if !used.insert(port){
+            return Err(NetworkError::PortInUse{device: device.to_string(), port});
+        }
+        if hosts.contains(device) && used.len() > 1{
+            return Err(NetworkError::HostAlreadyLinked(device.to_string()));
+        }
+        Ok(())
+    };
+    for link in &network.links.internal{
+        connect(&link.ends.0.device, link.ends.0.port)?;
+        connect(&link.ends.1.device, link.ends.1.port)?;
+    }
+    let bgp = &network.links.bgp;
+    let bgp_links = bgp.provider_customer.iter().map(|link| (&link.provider, &link.customer))
+        .chain(bgp.peer.iter().map(|link| (&link.peers.0, &link.peers.1)));
+    for (r1, r2) in bgp_links{
+        for router in [r1, r2]{
+            if hosts.contains(router.as_str()){
+                return Err(NetworkError::NotARouter(router.clone()));
+            }
+            connect(router, None)?;
+        }
+    }
+    let sessions = bgp.ibgp.iter().flat_map(|session| match session{
+        IbgpSessionCfg::Mesh(r1, r2) => [r1, r2],
+        IbgpSessionCfg::RouteReflector{rr, client} => [rr, client],
+    });
+    let announcing = network.actions.iter().flat_map(|actions| &actions.announce_prefix).filter_map(announcing_router);
+    for router in sessions.map(|router| router.as_str()).chain(announcing){
+        if !routers.contains(router){
+            return Err(NetworkError::UnknownDevice(router.to_string()));
+        }
+    }
+    if let Some(router_as) = bgp.ibgp_full_mesh.iter().find(|router_as| !network.routers.iter().any(|router| router.router_as == **router_as)){
+        return Err(NetworkError::InvalidConfig(format!("AS {} of the iBGP full mesh has no routers", router_as)));
+    }
+    Ok(())
+}
+
+// Builds the network of the config and returns a description of each step, for the caller to report them
+pub async fn build_network(network: &mut Network, config: &ConfigFile) -> Result<Vec<String>, NetworkError>{
+    let mut steps = vec![];
+    check_bgp_devices(config)?;
+    network.set_timers(config.network.config.timers.timer_config());
+    if let Some(dead_interval_ms) = config.network.config.timers.dead_interval_ms{
+        network.set_dead_interval(Duration::from_millis(dead_interval_ms)).await;
+    }
+    generate_routers(network, config, &mut steps)?;
+    generate_switchs(network, config, &mut steps);
+    generate_hosts(network, config, &mut steps);
+    generate_session_secrets(network, config, &mut steps).await?;
+    disable_ospf(network, config, &mut steps).await?;
+    generate_links(network, config, &mut steps).await?;
+    generate_static_routes(network, config, &mut steps).await?;
+    generate_conditional_advertisements(network, config, &mut steps).await?;
+    Ok(steps)
+}
+
+// The devices and links of the config with the ports build_network gives them, without starting the devices
+pub fn topology(config: &ConfigFile) -> Result<Topology, NetworkError>{
+    check_bgp_devices(config)?;
+    let mut topology = Topology::default();
+    for router in routers(config){
+        topology.add_router(&router.name, router.router_as);
+    }
+    for switch in &config.network.switches{
+        topology.add_switch(&switch.name);
+    }
+    for host in &config.network.hosts{
+        topology.add_host(&host.name);
+    }
+
+    let links = &config.network.links;
+    let reserved = explicit_ports(config);
+    for link in &links.internal{
+        let port1 = link_end_port(&topology, &link.ends.0, &reserved)?;
+        let port2 = link_end_port(&topology, &link.ends.1, &reserved)?;
+        topology.add_link(&link.ends.0.device, port1, &link.ends.1.device, port2, link.cost)?;
+    }
+    for link in &links.bgp.provider_customer{
+        let port1 = allocate_port(&topology, &link.provider, &reserved)?;
+        let port2 = allocate_port(&topology, &link.customer, &reserved)?;
+        topology.add_provider_customer_link(&link.provider, port1, &link.customer, port2)?;
+    }
+    for link in &links.bgp.peer{
+        let port1 = allocate_port(&topology, &link.peers.0, &reserved)?;
+        let port2 = allocate_port(&topology, &link.peers.1, &reserved)?;
+        topology.add_peer_link(&link.peers.0, port1, &link.peers.1, port2)?;
+    }
+    Ok(topology)
+}
+
+pub async fn announce(network: &Network, announce: &AnnounceCfg) -> Result<(), NetworkError>{
+    match announce{
+        AnnounceCfg::As(router_as) => network.announce_prefix_as(*router_as).await?,
+        AnnounceCfg::Router(router) => network.announce_prefix(router).await?,
+        AnnounceCfg::WithPref{router, pref} => network.announce_prefix_with_pref(router, *pref).await?,
+        AnnounceCfg::Prefix{router, prefix} => network.announce_custom_prefix(router, *prefix).await?,
+    }
+    Ok(())
+}
+
+pub async fn announce_prefixes(network: &Network, config: &ConfigFile) -> Result<(), NetworkError>{
+    for entry in config.network.actions.iter().flat_map(|actions: &ActionsCfg| &actions.announce_prefix){
+        announce(network, entry).await?;
+    }
+    Ok(())
+}
+
+// Builds the network described by the config, announces its prefixes and waits for the protocols to converge
+pub async fn run_to_convergence(config: &ConfigFile, logger: Logger) -> Result<Network, NetworkError>{
+    let network = Network::from_config(config, logger).await?;
+    network.wait_for_ospf(CONVERGENCE_TIMEOUT).await?;
+    announce_prefixes(&network, config).await?;
+    network.wait_for_bgp(CONVERGENCE_TIMEOUT).await?;
+    Ok(network)
+}
+
+impl Network{
+    // Builds the network of the config, without announcing its prefixes
+    pub async fn from_config(config: &ConfigFile, logger: Logger) -> Result<Network, NetworkError>{
+        let mut network = Network::new(logger);
+        build_network(&mut network, config).await?;
+        Ok(network)
+    }
+
+    // Config of the devices, links and iBGP sessions of the network, e.g. to save a network built in code
+    // The ports of the internal links are kept, those of the BGP links are allocated again when the config is loaded
+    pub fn to_config(&self) -> ConfigFile{
+        let mut config = ConfigFile::default();
+        let network = &mut config.network;
+        for (name, (_, ip)) in &self.routers{
+            let router = RouterCfg::new(name, ip.octets()[3] as u32, self.as_router[name]);
+            network.routers.push(RouterCfg{ospf: !self.ospf_disabled.contains(name), ..router});
+        }
+        for (name, (_, id)) in &self.switches{
+            network.switches.push(SwitchCfg{name: name.clone(), id: *id});
+        }
+        for (name, (_, ip, gateway)) in &self.hosts{
+            network.hosts.push(HostCfg{name: name.clone(), ip: *ip, gateway: *gateway});
+        }
+
+        // each link is stored by both of its devices, it is written once from the smallest end
+        let mut internal: Vec<LinkCfg> = self.internal_links.iter()
+            .flat_map(|(device1, links)| links.iter().map(move |(port1, device2, port2, cost)| ((device1, *port1), (device2, *port2), *cost)))
+            .filter(|(end1, end2, _)| end1 < end2)
+            .map(|((device1, port1), (device2, port2), cost)| {
+                LinkCfg::new((LinkEnd{device: device1.clone(), port: Some(port1)}, LinkEnd{device: device2.clone(), port: Some(port2)}), cost)
+            })
+            .collect();
+        internal.sort_by_key(|link| (link.ends.0.to_string(), link.ends.1.to_string()));
+        network.links.internal = internal;
+
+        let bgp = &mut network.links.bgp;
+        for (provider, _, customer, _, (med, prefs)) in &self.provider_customer{
+            let link = BgpLinkCfg::new(provider, customer, BgpMed::Value(*med));
+            bgp.provider_customer.push(BgpLinkCfg{local_pref: RolesCfg{provider: prefs.0, customer: prefs.1}, ..link});
+        }
+        for (r1, _, r2, _, (med, prefs)) in &self.peers{
+            bgp.peer.push(PeerLinkCfg{local_pref: *prefs, ..PeerLinkCfg::new(r1, r2, BgpMed::Value(*med))});
+        }
+        bgp.ibgp = self.ibgp_sessions.clone();
+        config
+    }
+}
+
+async fn converged_state(config: &ConfigFile) -> Result<NetworkState, NetworkError>{
+    let network = run_to_convergence(config, Logger::start_test()).await?;
+    let state = network.export_state().await;
+    network.quit().await;
+    state
+}
+
+// Runs both scenarios side by side, each in its own network, and returns how their final states differ
+pub async fn compare_scenarios(a: &ConfigFile, b: &ConfigFile) -> Result<Vec<Difference>, NetworkError>{
+    let (state_a, state_b) = tokio::join!(converged_state(a), converged_state(b));
+    Ok(state_a?.diff(&state_b?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{ip_prefix::IPPrefix, link::DEFAULT_QUEUE_DEPTH, rib::RouteOrigin, timers::TimerConfig};
+
+    const SCENARIO: &str = "
+network:
+  routers:
+    - {name: r1, id: 1, AS: 1}
+    - {name: r2, id: 2, AS: 2}
+    - {name: r3, id: 3, AS: 3}
+  links:
+    bgp:
+      provider-customer:
+        - {provider: r2, customer: r1, med: MED}
+        - {provider: r2, customer: r3}
+  actions:
+    announce_prefix: [r1]
+";
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_compare_med() {
+        let a: ConfigFile = SCENARIO.replace("MED", "0").parse().unwrap();
+        let b: ConfigFile = SCENARIO.replace("MED", "5").parse().unwrap();
+
+        assert_eq!(compare_scenarios(&a, &a).await.unwrap(), vec![]);
+
+        let differences = compare_scenarios(&a, &b).await.unwrap();
+        assert!(!differences.is_empty());
+        for difference in differences{
+            assert_eq!(difference.device(), "r2");
+            assert_eq!(difference.prefix(), Some("10.0.1.0/24".parse().unwrap()));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_unknown_device_in_scenario() {
+        let config: ConfigFile = SCENARIO.replace("MED", "0").replace("customer: r3", "customer: r4").parse().unwrap();
+        let mut network = Network::new(Logger::start_test());
+        assert_eq!(build_network(&mut network, &config).await.map(|_| ()), Err(NetworkError::UnknownDevice("r4".into())));
+        network.quit().await;
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = |replaced: &str, by: &str| SCENARIO.replace("MED", "0").replace(replaced, by).parse::<ConfigFile>().unwrap();
+        assert_eq!(validate(&config("", "")), Ok(()));
+        assert_eq!(validate(&load_config("examples/ospf-ports.yaml").unwrap()), Ok(()));
+
+        assert_eq!(validate(&config("customer: r3", "customer: r4")), Err(NetworkError::UnknownDevice("r4".into())));
+        assert_eq!(validate(&config("id: 3, AS: 3}", "id: 3, AS: 3}\n    - {name: r1, id: 4, AS: 4}")),
+            Err(NetworkError::InvalidConfig("Device r1 is defined twice".into())));
+        assert_eq!(validate(&config("announce_prefix: [r1]", "announce_prefix: [r5]")), Err(NetworkError::UnknownDevice("r5".into())));
+
+        // the first link skips the explicit port of r1, which is then given twice
+        let ports = "network: {routers: [{name: r1, id: 1, AS: 1}, {name: r2, id: 2, AS: 1}, {name: r3, id: 3, AS: 1}], links: {internal: [[r1, r2], [r1:1, r3], [r3, r1:1]]}}";
+        assert_eq!(validate(&ports.parse().unwrap()), Err(NetworkError::PortInUse{device: "r1".into(), port: 1}));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let error = SCENARIO.replace("med: MED", "med: high").parse::<ConfigFile>().unwrap_err().to_string();
+        assert!(error.contains("network.links.bgp.provider-customer[0].med: invalid value: string \"high\""), "{}", error);
+
+        let error = load_config("examples/missing.yaml").unwrap_err().to_string();
+        assert!(error.contains("cannot open examples/missing.yaml"), "{}", error);
+    }
+
+    const SWITCH_SCENARIO: &str = "
+network:
+  routers:
+    - {name: r1, id: 1, AS: 1}
+    - {name: r2, id: 2, AS: 2}
+  switches:
+    - {name: s1, id: 3}
+  links:
+    internal:
+      - [r1, s1]
+    bgp:
+      BGP_LINKS
+  actions:
+    announce_prefix: [ANNOUNCE]
+";
+
+    #[test]
+    fn test_load_timers() {
+        let config: ConfigFile = "
+network:
+  config:
+    timers: {hello_interval_ms: 20, arp_refresh_interval_ms: 50}
+".parse().unwrap();
+        let timers = config.network.config.timers.timer_config();
+        assert_eq!(timers.hello_interval, Duration::from_millis(20));
+        assert_eq!(timers.bpdu_interval, TimerConfig::default().bpdu_interval);
+        assert_eq!(timers.arp_refresh_interval, Duration::from_millis(50));
+        assert_eq!(SCENARIO.replace("MED", "0").parse::<ConfigFile>().unwrap().network.config.timers.timer_config(), TimerConfig::default());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_static_routes_scenario() {
+        let config: ConfigFile = "
+network:
+  routers:
+    - name: r1
+      id: 1
+      AS: 1
+      ospf: false
+      static_routes:
+        - {prefix: 0.0.0.0/0, nexthop: 10.0.1.2}
+    - name: r2
+      id: 2
+      AS: 1
+      ospf: false
+      static_routes:
+        - {prefix: 10.0.1.1/32, port: 1}
+  links:
+    internal:
+      - [r1, r2]
+".parse().unwrap();
+        let mut network = Network::new(Logger::start_test());
+        build_network(&mut network, &config).await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        network.ping("r1", "10.0.1.2".parse().unwrap()).await.unwrap();
+        let table = network.get_routing_table("r1").await.unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(table[&"0.0.0.0/0".parse().unwrap()], (1, 1, RouteOrigin::Static));
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_explicit_ports_scenario() {
+        let config = load_config("examples/ospf-ports.yaml").unwrap();
+        let mut network = Network::new(Logger::start_test());
+        let steps = build_network(&mut network, &config).await.unwrap();
+        assert_eq!(steps.iter().filter(|step| step.starts_with("Link from")).collect::<Vec<_>>(), vec![
+            "Link from r1:2 to r2:1 added with cost 1",
+            "Link from r1:1 to r3:5 added with cost 1",
+            "Link from r2:7 to r3:1 added with cost 3",
+        ]);
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        let prefix = |ip: &str| ip.parse().unwrap();
+        let table = network.get_routing_table("r1").await.unwrap();
+        assert_eq!((table[&prefix("10.0.1.2/32")].0, table[&prefix("10.0.1.3/32")].0), (2, 1));
+        let table = network.get_routing_table("r2").await.unwrap();
+        assert_eq!((table[&prefix("10.0.1.1/32")].0, table[&prefix("10.0.1.3/32")].0), (1, 1));
+        let table = network.get_routing_table("r3").await.unwrap();
+        assert_eq!((table[&prefix("10.0.1.1/32")].0, table[&prefix("10.0.1.2/32")].0), (5, 5));
+        network.quit().await;
+
+        // an explicit port already taken by another link of the device
+        let config: ConfigFile = "network: {routers: [{name: r1, id: 1, AS: 1}, {name: r2, id: 2, AS: 1}], links: {internal: [[r1:1, r2], [r1:1, r2]]}}".parse().unwrap();
+        let mut network = Network::new(Logger::start_test());
+        assert_eq!(build_network(&mut network, &config).await.map(|_| ()), Err(NetworkError::PortInUse{device: "r1".into(), port: 1}));
+        network.quit().await;
+    }
+
+    const IBGP_SCENARIO: &str = "
+network:
+  routers:
+    - {name: r1, id: 1, AS: 1}
+    - {name: r2, id: 2, AS: 1}
+    - {name: r3, id: 3, AS: 1}
+    - {name: r4, id: 4, AS: 2}
+    - {name: r5, id: 5, AS: 3}
+  links:
+    internal:
+      - [r1, r2]
+      - [r2, r3]
+      - [r1, r3]
+    bgp:
+      provider-customer:
+        - {provider: r4, customer: r1, med: 0}
+        - {provider: r3, customer: r5, med: 0}
+      IBGP
+  actions:
+    announce_prefix: [r4, r5]
+";
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_ibgp_full_mesh_scenario() {
+        let manual: ConfigFile = IBGP_SCENARIO.replace("IBGP", "ibgp: [[r1, r2], [r2, r3], [r1, r3]]").parse().unwrap();
+        let mesh: ConfigFile = IBGP_SCENARIO.replace("IBGP", "ibgp: [[r3, r1]]\n      ibgp_full_mesh: [1]").parse().unwrap();
+
+        let mut network = Network::new(Logger::start_test());
+        let steps = build_network(&mut network, &mesh).await.unwrap();
+        let sessions: Vec<&String> = steps.iter().filter(|step| step.starts_with("IBGP session")).collect();
+        assert_eq!(sessions, vec!["IBGP session added between r3 and r1", "IBGP session added between r1 and r2", "IBGP session added between r2 and r3"]);
+        network.quit().await;
+
+        // r2 learns the prefixes of r4 and r5 from r1 and r3 in both networks
+        let network = run_to_convergence(&mesh, Logger::start_test()).await.unwrap();
+        let routes = network.get_bgp_routes("r2").await.unwrap();
+        for prefix in ["10.0.2.0/24", "10.0.3.0/24"]{
+            assert!(routes[&prefix.parse().unwrap()].0.is_some(), "no route to {}", prefix);
+        }
+        // the routers of the other ASes are never advertised by OSPF, whenever their links were added
+        let table = network.get_routing_table("r2").await.unwrap();
+        for neighbor in ["10.0.2.4/32", "10.0.3.5/32"]{
+            assert_eq!(table.get(&neighbor.parse().unwrap()), None, "{} advertised by OSPF", neighbor);
+        }
+        network.quit().await;
+        assert_eq!(compare_scenarios(&manual, &mesh).await.unwrap(), vec![]);
+
+        let unknown: ConfigFile = IBGP_SCENARIO.replace("IBGP", "ibgp_full_mesh: [1, 7]").parse().unwrap();
+        let mut network = Network::new(Logger::start_test());
+        let error = NetworkError::InvalidConfig("AS 7 of the iBGP full mesh has no routers".into());
+        assert_eq!(build_network(&mut network, &unknown).await.map(|_| ()), Err(error));
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_hosts_scenario() {
+        let config: ConfigFile = "
+network:
+  routers:
+    - {name: r1, id: 1, AS: 1}
+  switches:
+    - {name: s1, id: 2}
+  hosts:
+    - {name: h1, ip: 10.1.1.2, gateway: 10.1.1.1}
+  links:
+    internal:
+      - [r1, s1, 1, 10.1.1.1/24]
+      - [h1, s1]
+".parse().unwrap();
+        let mut network = Network::new(Logger::start_test());
+        let steps = build_network(&mut network, &config).await.unwrap();
+        assert!(steps.contains(&"Router r1 has address 10.1.1.1 on subnet 10.1.1.0/24".to_string()));
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        network.ping("h1", "10.0.1.1".parse().unwrap()).await.unwrap();
+        network.ping("r1", "10.1.1.2".parse().unwrap()).await.unwrap();
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_latency_scenario() {
+        let scenario = SCENARIO.replace("med: MED}", "med: 0, latency_ms: 50}")
+            .replace("  links:\n", "  links:\n    internal:\n      - {devices: [r1, r3], cost: 2, latency_ms: 20}\n");
+        let config: ConfigFile = scenario.parse().unwrap();
+        let mut network = Network::new(Logger::start_test());
+        let steps = build_network(&mut network, &config).await.unwrap();
+        assert!(steps.contains(&"Link from r1:1 to r3:1 added with cost 2".to_string()));
+        assert!(steps.contains(&"Link between r1 and r3 delays its messages by 20ms".to_string()));
+        assert!(steps.contains(&"Link between r2 and r1 delays its messages by 50ms".to_string()));
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        assert!(network.ping("r2", "10.0.1.1".parse().unwrap()).await.unwrap() >= Duration::from_millis(100));
+        assert!(network.ping("r1", "10.0.3.3".parse().unwrap()).await.unwrap() >= Duration::from_millis(40));
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_impairment_scenario() {
+        let scenario = SCENARIO.replace("med: MED}", "med: 0, impairment: {duplicate_prob: 0.5}}")
+            .replace("  links:\n", "  links:\n    internal:\n      - {devices: [r1, r3], impairment: {loss_prob: 0.5, seed: 7}}\n");
+        let config: ConfigFile = scenario.parse().unwrap();
+        let mut network = Network::new(Logger::start_test());
+        let steps = build_network(&mut network, &config).await.unwrap();
+        assert!(steps.contains(&"Link between r1 and r3 has loss probability 0.5, reorder probability 0 and duplicate probability 0 (seed 7)".to_string()));
+        assert!(steps.contains(&"Link between r2 and r1 has loss probability 0, reorder probability 0 and duplicate probability 0.5 (seed 0)".to_string()));
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+        assert!(network.get_link_stats("r1", 1).unwrap().dropped > 0);
+        assert!(network.get_link_stats("r2", 1).unwrap().duplicated > 0);
+        network.quit().await;
+
+        let scenario = SCENARIO.replace("MED", "0").replace("  links:\n", "  links:\n    internal:\n      - {devices: [r1, r3], impairment: {loss_prob: 2}}\n");
+        let mut network = Network::new(Logger::start_test());
+        assert!(matches!(build_network(&mut network, &scenario.parse().unwrap()).await, Err(NetworkError::InvalidConfig(_))));
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_rate_limit_scenario() {
+        let scenario = SCENARIO.replace("med: MED}", "med: 0, rate_limit: {bytes_per_sec: 10000}}")
+            .replace("  links:\n", "  links:\n    internal:\n      - {devices: [r1, r3], rate_limit: {messages_per_sec: 5, queue_depth: 2}}\n");
+        let config: ConfigFile = scenario.parse().unwrap();
+        let mut network = Network::new(Logger::start_test());
+        let steps = build_network(&mut network, &config).await.unwrap();
+        assert!(steps.contains(&"Link between r1 and r3 sends 5 messages per second, with a queue of 2 messages".to_string()));
+        assert!(steps.contains(&format!("Link between r2 and r1 sends 10000 bytes per second, with a queue of {} messages", DEFAULT_QUEUE_DEPTH)));
+        network.quit().await;
+
+        let scenario = SCENARIO.replace("med: MED}", "med: 0, rate_limit: {bytes_per_sec: 10000, messages_per_sec: 5}}");
+        assert!(matches!(scenario.parse::<ConfigFile>(), Err(NetworkError::InvalidConfig(_))));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_prepend_scenario() {
+        let scenario = SCENARIO.replace("MED", "0")
+            .replace("{provider: r2, customer: r3}", "{provider: r2, customer: r3, prepend: {customer: 2}}\n      peer:\n        - {peers: [r1, r3], med: 0, prepend: [1, 0]}");
+        let config: ConfigFile = scenario.parse().unwrap();
+        let mut network = Network::new(Logger::start_test());
+        let steps = build_network(&mut network, &config).await.unwrap();
+        assert!(steps.contains(&"Router r3 prepends its AS 2 more times on port 1".to_string()));
+        assert!(steps.contains(&"Peer link from r1:2 to r3:2 added with med 0".to_string()));
+        assert!(steps.contains(&"Router r1 prepends its AS 1 more times on port 2".to_string()));
+        announce_prefixes(&network, &config).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        // r3 still prefers its peer to its provider, whatever the length of the path
+        let best = network.get_bgp_routes("r3").await.unwrap()[&"10.0.1.0/24".parse().unwrap()].0.clone().unwrap();
+        assert_eq!(best.as_path, vec![1, 1]);
+        network.quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn test_policy_scenario() {
+        let scenario = SCENARIO
+            .replace("med: MED}", "med: 0, import: {provider: [{prefix: 10.0.1.0/24, action: deny}]}}")
+            .replace("{provider: r2, customer: r3}", "{provider: r2, customer: r3}\n      peer:\n        - {peers: [r1, r3], export: [[{prefix: 10.0.0.0/16, le: 24, action: set_med, value: 4}]], max_prefixes: [{limit: 10}, {limit: 5, action: close}]}");
+        let config: ConfigFile = scenario.parse().unwrap();
+        let mut network = Network::new(Logger::start_test());
+        let steps = build_network(&mut network, &config).await.unwrap();
+        assert!(steps.contains(&"Router r2 applies the import policy [match 10.0.1.0/24 Deny] on port 1".to_string()));
+        assert!(steps.contains(&"Router r1 applies the export policy [match 10.0.0.0/16 le 24 SetMed(4)] on port 2".to_string()));
+        assert!(steps.contains(&"Router r3 accepts at most 5 prefixes on port 2".to_string()));
+        announce_prefixes(&network, &config).await.unwrap();
+        network.wait_for_bgp(CONVERGENCE_TIMEOUT).await.unwrap();
+
+        // r2 filters the prefix of r1, r3 still learns it from its peer
+        let prefix: IPPrefix = "10.0.1.0/24".parse().unwrap();
+        assert!(network.get_bgp_routes("r2").await.unwrap().get(&prefix).is_none_or(|(best, _)| best.is_none()));
+        let best = network.get_bgp_routes("r3").await.unwrap()[&prefix].0.clone().unwrap();
+        assert_eq!((best.as_path, best.med), (vec![1], 4));
+        network.quit().await;
+
+        let error = SCENARIO.replace("med: MED}", "import: {provider: [{prefix: 10.0.1.0/24, action: drop}]}}").parse::<ConfigFile>().unwrap_err().to_string();
+        assert!(error.contains("unknown variant `drop`, expected one of `accept`, `deny`, `set_med`, `set_pref`"), "{}", error);
+
+        let error = SCENARIO.replace("med: MED}", "max_prefixes: {customer: {limit: 2, action: reset}}}").parse::<ConfigFile>().unwrap_err().to_string();
+        assert!(error.contains("unknown variant `reset`, expected `drop` or `close`"), "{}", error);
+    }
+
+    async fn build_error(bgp_links: &str, announce: &str) -> Result<(), NetworkError>{
+        let config: ConfigFile = SWITCH_SCENARIO.replace("BGP_LINKS", bgp_links).replace("ANNOUNCE", announce).parse().unwrap();
+        let mut network = Network::new(Logger::start_test());
+        let result = build_network(&mut network, &config).await.map(|_| ());
+        network.quit().await;
+        result
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_switch_in_bgp_links() {
+        let error = Err(NetworkError::SwitchInBGP("s1".into()));
+        assert_eq!(build_error("provider-customer: [{provider: r2, customer: s1}]", "r1").await, error);
+        assert_eq!(build_error("provider-customer: [{provider: s1, customer: r2}]", "r1").await, error);
+        assert_eq!(build_error("peer: [[r2, s1]]", "r1").await, error);
+        assert_eq!(build_error("peer: [{peers: [r2, s1], prepend: [1, 0]}]", "r1").await, error);
+        assert_eq!(build_error("ibgp: [[s1, r1]]", "r1").await, error);
+        assert_eq!(build_error("peer: [[r1, r2]]", "s1").await, error);
+        assert_eq!(build_error("peer: [[r1, r2]]", "{router: s1, pref: 100}").await, error);
+        assert_eq!(build_error("peer: [[r1, r2]]", "r1").await, Ok(()));
+        assert_eq!(NetworkError::SwitchInBGP("s1".into()).to_string(), "Device 's1' is a switch and cannot participate in BGP links");
+    }
+}
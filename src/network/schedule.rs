@@ -0,0 +1,517 @@
+use std::{fmt::Display, fs, net::Ipv4Addr, str::FromStr, time::Duration};
+
+use serde::de::DeserializeOwned;
+use serde_yaml::Value;
+use strum::IntoEnumIterator;
+use tokio::time::Instant;
+
+use super::{config::{ActionsCfg, AnnounceCfg, BgpBestAssertCfg, ConfigFile, ForwardingGraphCfg, LinkCostCfg, PingAssertCfg, PingCfg, PingOutcome, PortCfg, RoutingTableAssertCfg, DEFAULT_PING_INTERVAL_MS}, error::NetworkError, ip_prefix::IPPrefix, logger::Source, protocols::bgp::SessionState, scenario::{self, CONVERGENCE_TIMEOUT}, Network};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action{
+    AnnouncePrefix(AnnounceCfg),
+    WithdrawPrefix(String),
+    ClearRoutes(String),
+    ClearBGP(String),
+    RemoveLink(String, String),
+    SetPortEnabled{device: String, port: u32, enabled: bool},
+    SetLinkCost{device: String, port: u32, cost: u32},
+    SetLocalPref{router: String, session: String, pref: Option<u32>}, // None restores the pref of the relationship
+    Ping(String, Ipv4Addr),
+    PingSeries{from: String, to: Ipv4Addr, count: u32, interval: Duration},
+    PrintRoutingTables,
+    PrintPortStates,
+    PrintBGPTables,
+    PrintReachability,
+    PrintBGPSessions,
+    PrintHealth(String),
+    PrintLsdb(String),
+    PrintStats,
+    PrintForwardingGraph(Ipv4Addr),
+    PrintSpanningTreeGraph,
+    ExpectSession{router: String, session: String, state: SessionState},
+    ExpectRoute{router: String, prefix: IPPrefix, learned: bool},
+    DotGraphFile(String),
+    StateFile(String),
+    AssertRoutingTable(RoutingTableAssertCfg),
+    AssertBGPBest(BgpBestAssertCfg),
+    AssertPing(PingAssertCfg),
+    Wait(Duration),
+    WaitForConvergence(Duration) // fails after the duration
+}
+
+impl Display for Action{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            Action::AnnouncePrefix(announce) => write!(f, "announce_prefix {}", announce),
+            Action::WithdrawPrefix(router) => write!(f, "withdraw_prefix {}", router),
+            Action::ClearRoutes(router) => write!(f, "clear_routes {}", router),
+            Action::ClearBGP(router) => write!(f, "clear_bgp {}", router),
+            Action::RemoveLink(device1, device2) => write!(f, "remove_link {} {}", device1, device2),
+            Action::SetPortEnabled{device, port, enabled} => write!(f, "{} {}:{}", if *enabled { "enable_port" } else { "disable_port" }, device, port),
+            Action::SetLinkCost{device, port, cost} => write!(f, "set_link_cost {}:{} {}", device, port, cost),
+            Action::SetLocalPref{router, session, pref: Some(pref)} => write!(f, "set_local_pref {} {} {}", router, session, pref),
+            Action::SetLocalPref{router, session, pref: None} => write!(f, "restore_local_pref {} {}", router, session),
+            Action::Ping(from, to) => write!(f, "ping {} {}", from, to),
+            Action::PingSeries{from, to, count, interval} => write!(f, "ping {} {} count {} interval {}ms", from, to, count, interval.as_millis()),
+            Action::PrintRoutingTables => write!(f, "print_routing_tables"),
+            Action::PrintPortStates => write!(f, "print_port_states"),
+            Action::PrintBGPTables => write!(f, "print_bgp_tables"),
+            Action::PrintReachability => write!(f, "print_reachability"),
+            Action::PrintBGPSessions => write!(f, "print_bgp_sessions"),
+            Action::PrintForwardingGraph(dest) => write!(f, "print_forwarding_graph {}", dest),
+            Action::PrintSpanningTreeGraph => write!(f, "print_spanning_tree_graph"),
+            Action::PrintHealth(router) => write!(f, "print_health {}", router),
+            Action::PrintLsdb(router) => write!(f, "print_lsdb {}", router),
+            Action::PrintStats => write!(f, "print_stats"),
+            Action::ExpectSession{router, session, state} => write!(f, "expect_session {} {} {}", router, session, state),
+            Action::ExpectRoute{router, prefix, learned} => write!(f, "expect_route {} {} {}", router, prefix, if *learned { "learned" } else { "not learned" }),
+            Action::DotGraphFile(file) => write!(f, "dot_graph_file {}", file),
+            Action::StateFile(file) => write!(f, "state_file {}", file),
+            Action::AssertRoutingTable(assert) => write!(f, "assert_routing_table {}", assert.router),
+            Action::AssertBGPBest(assert) => write!(f, "assert_bgp_best {} {} {:?}", assert.router, assert.prefix, assert.as_path),
+            Action::AssertPing(assert) => write!(f, "assert_ping {} {} {}", assert.from, assert.to, assert.expect),
+            Action::Wait(duration) => write!(f, "wait {}ms", duration.as_millis()),
+            Action::WaitForConvergence(timeout) => write!(f, "wait_for_convergence {}ms", timeout.as_millis()),
+        }
+    }
+}
+
+// action executed once time has elapsed since the start of the scenario
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event{
+    pub time: Duration,
+    pub action: Action
+}
+
+// accessors reporting the values of the events that don't have the expected type
+fn config_str<'a>(value: &'a Value, what: &str) -> Result<&'a str, NetworkError>{
+    value.as_str().ok_or(NetworkError::InvalidConfig(format!("{} should be a string", what)))
+}
+
+fn config_int(value: &Value, what: &str) -> Result<u64, NetworkError>{
+    value.as_u64().ok_or(NetworkError::InvalidConfig(format!("{} should be an integer", what)))
+}
+
+fn config_list<'a>(value: &'a Value, what: &str) -> Result<&'a Vec<Value>, NetworkError>{
+    value.as_sequence().ok_or(NetworkError::InvalidConfig(format!("{} should be a list", what)))
+}
+
+fn config_parse<T: FromStr>(value: &Value, what: &str) -> Result<T, NetworkError>{
+    let s = config_str(value, what)?;
+    s.parse().map_err(|_| NetworkError::InvalidConfig(format!("{} {} is invalid", what, s)))
+}
+
+// values of the actions that have the same form in the actions and in the events
+fn config_value<T: DeserializeOwned>(value: &Value, what: &str) -> Result<T, NetworkError>{
+    serde_yaml::from_value(value.clone()).map_err(|e| NetworkError::InvalidConfig(format!("{}: {}", what, e)))
+}
+
+fn router_name(value: &Value) -> Result<String, NetworkError>{
+    Ok(config_str(value, "Router name")?.to_string())
+}
+
+fn ping_action(ping: &PingCfg) -> Action{
+    match ping.count{
+        Some(count) => {
+            let interval = Duration::from_millis(ping.interval_ms.unwrap_or(DEFAULT_PING_INTERVAL_MS));
+            Action::PingSeries{from: ping.from.clone(), to: ping.to, count, interval}
+        },
+        None => Action::Ping(ping.from.clone(), ping.to),
+    }
+}
+
+fn parse_action(name: &str, value: &Value) -> Result<Action, NetworkError>{
+    let action = match name{
+        "announce_prefix" => Action::AnnouncePrefix(config_value(value, "Announce prefix")?),
+        "withdraw_prefix" => Action::WithdrawPrefix(router_name(value)?),
+        "clear_routes" => Action::ClearRoutes(router_name(value)?),
+        "clear_bgp" => Action::ClearBGP(router_name(value)?),
+        "remove_link" => {
+            let l = config_list(value, "Remove link")?;
+            if l.len() != 2{
+                return Err(NetworkError::InvalidConfig("Remove link should be a list of two devices".into()));
+            }
+            Action::RemoveLink(router_name(&l[0])?, router_name(&l[1])?)
+        },
+        "disable_port" | "enable_port" => {
+            let PortCfg{device, port} = config_value(value, "Port")?;
+            Action::SetPortEnabled{device, port, enabled: name == "enable_port"}
+        },
+        "set_link_cost" => {
+            let LinkCostCfg{device, port, cost} = config_value(value, "Link cost")?;
+            Action::SetLinkCost{device, port, cost}
+        },
+        "set_local_pref" | "restore_local_pref" => {
+            let pref = match name{
+                "set_local_pref" => Some(config_int(&value["value"], "Local pref")? as u32),
+                _ => None,
+            };
+            Action::SetLocalPref{router: router_name(&value["router"])?, session: router_name(&value["session"])?, pref}
+        },
+        "ping" => ping_action(&config_value(value, "Ping")?),
+        "print_routing_tables" => Action::PrintRoutingTables,
+        "print_port_states" => Action::PrintPortStates,
+        "print_bgp_tables" => Action::PrintBGPTables,
+        "print_reachability" => Action::PrintReachability,
+        "print_bgp_sessions" => Action::PrintBGPSessions,
+        "print_health" => Action::PrintHealth(router_name(value)?),
+        "print_lsdb" => Action::PrintLsdb(router_name(value)?),
+        "print_stats" => Action::PrintStats,
+        "print_spanning_tree_graph" => Action::PrintSpanningTreeGraph,
+        "print_forwarding_graph" => Action::PrintForwardingGraph(config_value::<ForwardingGraphCfg>(value, "Forwarding graph")?.dest),
+        "expect_session" => {
+            let state = config_str(&value["state"], "Session state")?;
+            let Some(state) = SessionState::iter().find(|s| s.to_string() == state) else {
+                let states: Vec<String> = SessionState::iter().map(|s| s.to_string()).collect();
+                return Err(NetworkError::InvalidConfig(format!("Unknown session state {}, supported states are [{}]", state, states.join(", "))));
+            };
+            Action::ExpectSession{router: router_name(&value["router"])?, session: router_name(&value["session"])?, state}
+        },
+        "expect_route" => {
+            let prefix = config_parse(&value["prefix"], "Prefix")?;
+            let learned = value["learned"].as_bool().unwrap_or(true);
+            Action::ExpectRoute{router: router_name(&value["router"])?, prefix, learned}
+        },
+        "dot_graph_file" => Action::DotGraphFile(config_str(value, "Dot filename")?.to_string()),
+        "state_file" => Action::StateFile(config_str(value, "State filename")?.to_string()),
+        "assert_routing_table" => Action::AssertRoutingTable(config_value(value, "Routing table assertion")?),
+        "assert_bgp_best" => Action::AssertBGPBest(config_value(value, "BGP best route assertion")?),
+        "assert_ping" => Action::AssertPing(config_value(value, "Ping assertion")?),
+        "wait" => Action::Wait(Duration::from_millis(config_int(value, "Wait")?)),
+        "wait_for_convergence" => Action::WaitForConvergence(Duration::from_millis(config_int(value, "Convergence timeout")?)),
+        name => return Err(NetworkError::InvalidConfig(format!("Unknown action {}", name))),
+    };
+    Ok(action)
+}
+
+// the former actions: announces once the IGP converged, the rest once BGP converged too
+// all of them happen at the start, the rounds are separated by waits for the convergence
+fn legacy_events(actions: &ActionsCfg) -> Vec<Event>{
+    let mut events = vec![];
+    let mut push = |action| events.push(Event{time: Duration::ZERO, action});
+    push(Action::WaitForConvergence(CONVERGENCE_TIMEOUT));
+
+    for announce in &actions.announce_prefix{
+        push(Action::AnnouncePrefix(announce.clone()));
+    }
+    for (printed, action) in [(actions.print_routing_tables, Action::PrintRoutingTables), (actions.print_port_states, Action::PrintPortStates), (actions.print_spanning_tree_graph, Action::PrintSpanningTreeGraph)]{
+        if printed{
+            push(action);
+        }
+    }
+    push(Action::WaitForConvergence(CONVERGENCE_TIMEOUT));
+    let mut changed = false;
+    for (routers, action) in [(&actions.withdraw_prefix, Action::WithdrawPrefix as fn(String) -> Action), (&actions.clear_routes, Action::ClearRoutes), (&actions.clear_bgp, Action::ClearBGP)]{
+        for router in routers{
+            push(action(router.clone()));
+            changed = true;
+        }
+    }
+    if changed{
+        // wait for the changes to propagate
+        push(Action::WaitForConvergence(CONVERGENCE_TIMEOUT));
+    }
+    for (printed, action) in [(actions.print_bgp_tables, Action::PrintBGPTables), (actions.print_reachability, Action::PrintReachability)]{
+        if printed{
+            push(action);
+        }
+    }
+    if let Some(graph) = actions.print_forwarding_graph{
+        push(Action::PrintForwardingGraph(graph.dest));
+    }
+    for ping in &actions.ping{
+        push(ping_action(ping));
+    }
+    for (file, action) in [(&actions.dot_graph_file, Action::DotGraphFile as fn(String) -> Action), (&actions.state_file, Action::StateFile)]{
+        if let Some(file) = file{
+            push(action(file.clone()));
+        }
+    }
+    // checked once everything else ran
+    for assert in &actions.assert_routing_table{
+        push(Action::AssertRoutingTable(assert.clone()));
+    }
+    for assert in &actions.assert_bgp_best{
+        push(Action::AssertBGPBest(assert.clone()));
+    }
+    for assert in &actions.assert_ping{
+        push(Action::AssertPing(assert.clone()));
+    }
+    events
+}
+
+// Events of the config, sorted by time. An event without time_ms happens at the time of the previous one
+pub fn load_events(config: &ConfigFile) -> Result<Vec<Event>, NetworkError>{
+    let mut events = config.network.actions.as_ref().map(legacy_events).unwrap_or_default();
+    let mut time = Duration::ZERO;
+    for entry in &config.network.events{
+        if let Some(t) = entry.get("time_ms"){
+            time = Duration::from_millis(config_int(t, "time_ms")?);
+        }
+        for (name, value) in entry.iter(){
+            let name = config_str(name, "Action name")?;
+            if name != "time_ms"{
+                events.push(Event{time, action: parse_action(name, value)?});
+            }
+        }
+    }
+    events.sort_by_key(|e| e.time);
+    Ok(events)
+}
+
+pub(super) async fn execute(network: &mut Network, action: &Action) -> Result<(), NetworkError>{
+    match action{
+        Action::AnnouncePrefix(announce) => scenario::announce(network, announce).await?,
+        Action::WithdrawPrefix(router) => network.withdraw_prefix(router).await?,
+        Action::ClearRoutes(router) => network.clear_routes(router).await?,
+        Action::ClearBGP(router) => network.clear_bgp(router).await?,
+        Action::RemoveLink(device1, device2) => {
+            let (port1, port2) = network.link_ports(device1, device2).ok_or(NetworkError::UnknownLink{device1: device1.clone(), port1: 0, device2: device2.clone(), port2: 0})?;
+            network.remove_link(device1, port1, device2, port2).await?;
+        },
+        Action::SetPortEnabled{device, port, enabled} => network.set_port_enabled(device, *port, *enabled).await?,
+        Action::SetLinkCost{device, port, cost} => network.set_link_cost(device, *port, *cost).await?,
+        Action::SetLocalPref{router, session, pref} => network.set_local_pref(router, session, *pref).await?,
+        Action::Ping(from, to) => match network.ping(from, *to).await{
+            Ok(rtt) => println!("Ping from {} to {}: answer received in {:?}", from, to, rtt),
+            Err(NetworkError::Ping(error)) => println!("Ping from {} to {} failed: {}", from, to, error),
+            Err(error) => return Err(error),
+        },
+        Action::PingSeries{from, to, count, interval} => {
+            let report = network.ping_n(from, *to, *count, *interval).await?;
+            println!("Ping from {} to {}: {}", from, to, report);
+        },
+        Action::PrintRoutingTables => {
+            println!("Routing tables:");
+            network.print_routing_tables().await?;
+            println!();
+        },
+        Action::PrintPortStates => {
+            println!("Switch port states:");
+            network.print_switch_states().await?;
+            println!();
+        },
+        Action::PrintBGPTables => {
+            println!("BGP tables:");
+            network.print_bgp_tables().await?;
+            println!();
+        },
+        Action::PrintReachability => {
+            println!("Reachability:");
+            network.print_reachability().await?;
+            println!();
+        },
+        Action::PrintBGPSessions => {
+            println!("BGP sessions:");
+            network.print_bgp_sessions().await?;
+            println!();
+        },
+        Action::PrintHealth(router) => {
+            println!("{}", network.health(router).await?);
+            println!();
+        },
+        Action::PrintLsdb(router) => {
+            print!("{}", network.get_lsdb(router).await?);
+            println!();
+        },
+        Action::PrintStats => {
+            println!("Message counters:");
+            network.print_stats().await?;
+            println!();
+        },
+        Action::PrintSpanningTreeGraph => {
+            println!("Spanning tree:");
+            print!("{}", network.dot_spanning_tree().await?);
+            println!();
+        },
+        Action::PrintForwardingGraph(dest) => {
+            println!("Forwarding graph towards {}:", dest);
+            print!("{}", network.dot_forwarding(*dest).await?);
+            println!();
+        },
+        Action::ExpectSession{router, session, state} => {
+            let sessions = network.get_bgp_sessions(router).await?;
+            let current = sessions.get(session).ok_or(NetworkError::UnknownSession{router: router.clone(), neighbor: session.clone()})?;
+            if current.state != *state{
+                return Err(NetworkError::ExpectationFailed(format!("session of {} with {} is {}, expected {}", router, session, current, state)));
+            }
+            println!("Expectation met: {}", action);
+        },
+        Action::ExpectRoute{router, prefix, learned} => {
+            let routes = network.get_bgp_routes(router).await?;
+            let present = routes.get(prefix).is_some_and(|(best, _)| best.is_some());
+            if present != *learned{
+                let verb = if present { "learned" } else { "didn't learn" };
+                return Err(NetworkError::ExpectationFailed(format!("{} {} a route to {}", router, verb, prefix)));
+            }
+            println!("Expectation met: {}", action);
+        },
+        Action::DotGraphFile(filename) => {
+            let dot_repr = network.dot_representation().await?;
+            fs::write(filename, dot_repr).map_err(|e| NetworkError::Io(format!("cannot write the DOT graph to {}: {}", filename, e)))?;
+        },
+        Action::StateFile(filename) => {
+            let state = network.export_state().await?;
+            fs::write(filename, state.to_json()).map_err(|e| NetworkError::Io(format!("cannot write the network state to {}: {}", filename, e)))?;
+        },
+        Action::AssertRoutingTable(assert) => {
+            let table = network.get_routing_table(&assert.router).await?;
+            let differences = assert.entries.iter().filter_map(|(prefix, expected)| match table.get(prefix){
+                Some((port, distance, _)) if expected.matches(*port, *distance) => None,
+                Some((port, distance, _)) => Some(format!("{}: expected {}, got port={}, distance={}", prefix, expected, port, distance)),
+                None => Some(format!("{}: expected {}, got no route", prefix, expected)),
+            }).collect();
+            check_assertion(action, differences)?;
+        },
+        Action::AssertBGPBest(assert) => {
+            let routes = network.get_bgp_routes(&assert.router).await?;
+            let difference = match routes.get(&assert.prefix).and_then(|(best, _)| best.as_ref()){
+                Some(best) if best.as_path == assert.as_path => None,
+                Some(best) => Some(format!("{}: expected as_path {:?}, got {:?}", assert.prefix, assert.as_path, best.as_path)),
+                None => Some(format!("{}: expected as_path {:?}, got no best route", assert.prefix, assert.as_path)),
+            };
+            check_assertion(action, difference.into_iter().collect())?;
+        },
+        Action::AssertPing(assert) => {
+            let difference = match (network.ping(&assert.from, assert.to).await, assert.expect){
+                (Ok(_), PingOutcome::Success) | (Err(NetworkError::Ping(_)), PingOutcome::Failure) => None,
+                (Ok(rtt), PingOutcome::Failure) => Some(format!("expected failure, got an answer in {:?}", rtt)),
+                (Err(NetworkError::Ping(error)), PingOutcome::Success) => Some(format!("expected success, got failure: {}", error)),
+                (Err(error), _) => return Err(error),
+            };
+            check_assertion(action, difference.into_iter().collect())?;
+        },
+        Action::Wait(duration) => tokio::time::sleep(*duration).await,
+        Action::WaitForConvergence(timeout) => {
+            let elapsed = network.wait_for_convergence(*timeout).await?;
+            network.logger.log(Source::DEBUG, format!("Network converged in {}ms", elapsed.as_millis())).await;
+        },
+    }
+    Ok(())
+}
+
+// prints the differences between the expected and the actual state, a failed assertion doesn't stop the scenario
+fn check_assertion(action: &Action, differences: Vec<String>) -> Result<(), NetworkError>{
+    if differences.is_empty(){
+        println!("Assertion passed: {}", action);
+        return Ok(());
+    }
+    println!("Assertion failed: {}", action);
+    for difference in differences.iter(){
+        println!("  {}", difference);
+    }
+    Err(NetworkError::AssertionsFailed(vec![format!("{}\n  {}", action, differences.join("\n  "))]))
+}
+
+// Runs the events in order, each one once its time has elapsed since the call
+// The failed assertions are reported together once all the events ran
+pub async fn run_events(network: &mut Network, events: &[Event]) -> Result<(), NetworkError>{
+    let start = Instant::now();
+    let mut failures = vec![];
+    for event in events{
+        tokio::time::sleep_until(start + event.time).await;
+        network.logger.log(Source::DEBUG, format!("Scenario event at {}ms: {}", event.time.as_millis(), event.action)).await;
+        match execute(network, &event.action).await{
+            Err(NetworkError::AssertionsFailed(failed)) => failures.extend(failed),
+            result => result?,
+        }
+    }
+    if !failures.is_empty(){
+        return Err(NetworkError::AssertionsFailed(failures));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::logger::Logger;
+
+    async fn run_scenario(config: &ConfigFile) -> Result<(), NetworkError>{
+        let mut network = Network::new(Logger::start_test());
+        scenario::build_network(&mut network, config).await?;
+        let result = run_events(&mut network, &load_events(config)?).await;
+        network.quit().await;
+        result
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_secret_mismatch_example() {
+        let config = scenario::load_config("examples/bgp-secret-mismatch.yaml").unwrap();
+        assert_eq!(run_scenario(&config).await, Ok(()));
+
+        // with the same secret on both ends, the session comes up and r2 learns the prefix of r1
+        let fixed = fs::read_to_string("examples/bgp-secret-mismatch.yaml").unwrap().replace("bleu", "blue");
+        let config: ConfigFile = fixed.parse().unwrap();
+        assert_eq!(
+            run_scenario(&config).await,
+            Err(NetworkError::ExpectationFailed("session of r2 with r1 is Established, expected Idle".into()))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_unwritable_file() {
+        let config: ConfigFile = "network: {routers: [{name: r1, id: 1, AS: 1}], events: [{dot_graph_file: missing/graph.dot}]}".parse().unwrap();
+        match run_scenario(&config).await{
+            Err(NetworkError::Io(reason)) => assert!(reason.starts_with("cannot write the DOT graph to missing/graph.dot: "), "{}", reason),
+            result => panic!("expected an io error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_load_events() {
+        let config: ConfigFile = "
+network:
+  actions:
+    announce_prefix: [r1]
+    print_bgp_tables: true
+  events:
+    - {time_ms: 4000, remove_link: [r1, r2]}
+    - {wait: 500}
+    - {disable_port: {device: s9, port: 1}}
+    - {time_ms: 4200, enable_port: {device: s9, port: 1}}
+    - {time_ms: 4300, set_link_cost: {device: r1, port: 2, cost: 10}}
+    - {time_ms: 0, print_routing_tables: true}
+    - {time_ms: 4500, print_forwarding_graph: {dest: 10.0.1.1}}
+    - {time_ms: 5000, set_local_pref: {router: r3, session: r7, value: 10}}
+    - {time_ms: 6000, restore_local_pref: {router: r3, session: r7}}
+    - {ping: {from: r1, to: 10.0.1.2}}
+    - {ping: {from: r1, to: 10.0.1.2, count: 5, interval_ms: 20}}
+    - {wait_for_convergence: 2000}
+    - assert_routing_table: {router: r1, entries: {10.0.1.2/32: {distance: 1}}}
+      assert_bgp_best: {router: r3, prefix: 10.0.1.0/24, as_path: [2, 1]}
+      assert_ping: {from: r3, to: 10.0.1.1, expect: failure}
+".parse().unwrap();
+        let events = load_events(&config).unwrap();
+        let actions: Vec<String> = events.iter().map(|e| format!("{}@{}", e.action, e.time.as_millis())).collect();
+        assert_eq!(actions, vec![
+            "wait_for_convergence 30000ms@0",
+            "announce_prefix r1@0",
+            "wait_for_convergence 30000ms@0",
+            "print_bgp_tables@0",
+            "print_routing_tables@0",
+            "remove_link r1 r2@4000",
+            "wait 500ms@4000",
+            "disable_port s9:1@4000",
+            "enable_port s9:1@4200",
+            "set_link_cost r1:2 10@4300",
+            "print_forwarding_graph 10.0.1.1@4500",
+            "set_local_pref r3 r7 10@5000",
+            "restore_local_pref r3 r7@6000",
+            "ping r1 10.0.1.2@6000",
+            "ping r1 10.0.1.2 count 5 interval 20ms@6000",
+            "wait_for_convergence 2000ms@6000",
+            "assert_routing_table r1@6000",
+            "assert_bgp_best r3 10.0.1.0/24 [2, 1]@6000",
+            "assert_ping r3 10.0.1.1 failure@6000",
+        ]);
+
+        let config: ConfigFile = "network: {events: [{time_ms: 0, reboot: r1}]}".parse().unwrap();
+        assert_eq!(load_events(&config), Err(NetworkError::InvalidConfig("Unknown action reboot".into())));
+        let config: ConfigFile = "network: {events: [{expect_session: {router: r1, session: r2, state: Up}}]}".parse().unwrap();
+        assert!(matches!(load_events(&config), Err(NetworkError::InvalidConfig(_))));
+        let config: ConfigFile = "network: {events: [{assert_ping: {from: r1}}]}".parse().unwrap();
+        assert_eq!(load_events(&config), Err(NetworkError::InvalidConfig("Ping assertion: missing field `to`".into())));
+    }
+}
@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde::Serialize;
+
+use super::{ip_prefix::IPPrefix, protocols::bgp::BGPRoute, rib::RIBEntry, switch::PortState};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BGPTableEntry{
+    pub best: Option<BGPRoute>,
+    pub routes: Vec<BGPRoute>
+}
+
+// Snapshot of the observable state of every device of a network
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct NetworkState{
+    pub routing_tables: BTreeMap<String, BTreeMap<IPPrefix, RIBEntry>>,
+    pub bgp_tables: BTreeMap<String, BTreeMap<IPPrefix, BGPTableEntry>>,
+    pub port_states: BTreeMap<String, BTreeMap<u32, PortState>>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference{
+    RoutingEntry{router: String, prefix: IPPrefix, a: Option<RIBEntry>, b: Option<RIBEntry>},
+    BGPRoutes{router: String, prefix: IPPrefix, only_a: Vec<BGPRoute>, only_b: Vec<BGPRoute>},
+    BestPath{router: String, prefix: IPPrefix, a: Option<BGPRoute>, b: Option<BGPRoute>},
+    PortState{switch: String, port: u32, a: Option<PortState>, b: Option<PortState>}
+}
+
+impl Difference{
+    pub fn device(&self) -> &str{
+        match self{
+            Difference::RoutingEntry{router, ..} => router,
+            Difference::BGPRoutes{router, ..} => router,
+            Difference::BestPath{router, ..} => router,
+            Difference::PortState{switch, ..} => switch,
+        }
+    }
+
+    pub fn prefix(&self) -> Option<IPPrefix>{
+        match self{
+            Difference::RoutingEntry{prefix, ..} => Some(*prefix),
+            Difference::BGPRoutes{prefix, ..} => Some(*prefix),
+            Difference::BestPath{prefix, ..} => Some(*prefix),
+            Difference::PortState{..} => None,
+        }
+    }
+}
+
+fn or_none<T: Display>(value: &Option<T>) -> String{
+    match value{
+        Some(v) => v.to_string(),
+        None => "none".into(),
+    }
+}
+
+fn routes_to_string(routes: &[BGPRoute]) -> String{
+    routes.iter().map(|r| format!("[{}]", r)).collect::<Vec<String>>().join(" ")
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            Difference::RoutingEntry{router, prefix, a, b} => {
+                let entry = |e: &Option<RIBEntry>| match e{
+                    Some((port, distance, origin)) => format!("port={}, distance={}, origin={}", port, distance, origin),
+                    None => "none".into(),
+                };
+                write!(f, "{} routing table {}: a=({}), b=({})", router, prefix, entry(a), entry(b))
+            },
+            Difference::BGPRoutes{router, prefix, only_a, only_b} =>
+                write!(f, "{} bgp routes {}: only in a={}, only in b={}", router, prefix, routes_to_string(only_a), routes_to_string(only_b)),
+            Difference::BestPath{router, prefix, a, b} =>
+                write!(f, "{} best path {}: a=({}), b=({})", router, prefix, or_none(a), or_none(b)),
+            Difference::PortState{switch, port, a, b} =>
+                write!(f, "{} port {}: a={}, b={}", switch, port, or_none(&a.as_ref().map(|s| format!("{:?}", s))), or_none(&b.as_ref().map(|s| format!("{:?}", s)))),
+        }
+    }
+}
+
+fn union_keys<'a, K: Ord + Clone, V1, V2>(a: Option<&'a BTreeMap<K, V1>>, b: Option<&'a BTreeMap<K, V2>>) -> Vec<K>{
+    let mut keys: Vec<K> = a.into_iter().flat_map(|m| m.keys().cloned()).chain(b.into_iter().flat_map(|m| m.keys().cloned())).collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+impl NetworkState{
+    pub fn to_json(&self) -> String{
+        serde_json::to_string_pretty(self).expect("Failed to serialize network state")
+    }
+
+    pub fn diff(&self, other: &NetworkState) -> Vec<Difference>{
+        let mut differences = vec![];
+
+        for router in union_keys(Some(&self.routing_tables), Some(&other.routing_tables)){
+            let (table_a, table_b) = (self.routing_tables.get(&router), other.routing_tables.get(&router));
+            for prefix in union_keys(table_a, table_b){
+                let a = table_a.and_then(|t| t.get(&prefix)).cloned();
+                let b = table_b.and_then(|t| t.get(&prefix)).cloned();
+                if a != b{
+                    differences.push(Difference::RoutingEntry{router: router.clone(), prefix, a, b});
+                }
+            }
+        }
+
+        for router in union_keys(Some(&self.bgp_tables), Some(&other.bgp_tables)){
+            let (table_a, table_b) = (self.bgp_tables.get(&router), other.bgp_tables.get(&router));
+            for prefix in union_keys(table_a, table_b){
+                let entry_a = table_a.and_then(|t| t.get(&prefix));
+                let entry_b = table_b.and_then(|t| t.get(&prefix));
+                let routes_a = entry_a.map(|e| e.routes.clone()).unwrap_or_default();
+                let routes_b = entry_b.map(|e| e.routes.clone()).unwrap_or_default();
+                let only_a: Vec<BGPRoute> = routes_a.iter().filter(|r| !routes_b.contains(r)).cloned().collect();
+                let only_b: Vec<BGPRoute> = routes_b.iter().filter(|r| !routes_a.contains(r)).cloned().collect();
+                if !only_a.is_empty() || !only_b.is_empty(){
+                    differences.push(Difference::BGPRoutes{router: router.clone(), prefix, only_a, only_b});
+                }
+                let best_a = entry_a.and_then(|e| e.best.clone());
+                let best_b = entry_b.and_then(|e| e.best.clone());
+                if best_a != best_b{
+                    differences.push(Difference::BestPath{router: router.clone(), prefix, a: best_a, b: best_b});
+                }
+            }
+        }
+
+        for switch in union_keys(Some(&self.port_states), Some(&other.port_states)){
+            let (ports_a, ports_b) = (self.port_states.get(&switch), other.port_states.get(&switch));
+            for port in union_keys(ports_a, ports_b){
+                let a = ports_a.and_then(|p| p.get(&port)).cloned();
+                let b = ports_b.and_then(|p| p.get(&port)).cloned();
+                if a != b{
+                    differences.push(Difference::PortState{switch: switch.clone(), port, a, b});
+                }
+            }
+        }
+
+        differences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::rib::RouteOrigin;
+
+    #[test]
+    fn test_diff_port_states_and_routes() {
+        let mut a = NetworkState::default();
+        a.routing_tables.insert("r1".into(), [("10.0.1.2/32".parse().unwrap(), (1, 1, RouteOrigin::Ospf))].into_iter().collect());
+        a.port_states.insert("s1".into(), [(1, PortState::Root), (2, PortState::Designated)].into_iter().collect());
+        let mut b = a.clone();
+        assert_eq!(a.diff(&b), vec![]);
+
+        b.routing_tables.get_mut("r1").unwrap().insert("10.0.1.3/32".parse().unwrap(), (2, 1, RouteOrigin::Ospf));
+        b.port_states.get_mut("s1").unwrap().insert(2, PortState::Blocked);
+
+        assert_eq!(a.diff(&b), vec![
+            Difference::RoutingEntry{router: "r1".into(), prefix: "10.0.1.3/32".parse().unwrap(), a: None, b: Some((2, 1, RouteOrigin::Ospf))},
+            Difference::PortState{switch: "s1".into(), port: 2, a: Some(PortState::Designated), b: Some(PortState::Blocked)},
+        ]);
+    }
+}
@@ -0,0 +1,87 @@
+use std::{collections::BTreeMap, fmt::Display, sync::{Arc, Mutex}};
+
+use super::{capture::Direction, messages::{ospf::OSPFMessage, Message}};
+
+// Kind of a message in the counters of a device. The OSPF messages are split, the lsps are
+// most of the traffic of a large network while the hellos only depend on its number of links
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MessageKind{
+    Bpdu,
+    OspfHello, // hellos and their replies
+    OspfLsp,
+    EthernetFrame,
+    Bgp,
+    Arp
+}
+
+impl From<&Message> for MessageKind{
+    fn from(message: &Message) -> Self{
+        match message{
+            Message::Bpdu(_) => MessageKind::Bpdu,
+            Message::Ospf(OSPFMessage::Lsp(_, _, _)) => MessageKind::OspfLsp,
+            Message::Ospf(_) => MessageKind::OspfHello,
+            Message::EthernetFrame(_, _, _) => MessageKind::EthernetFrame,
+            Message::Bgp(_) => MessageKind::Bgp,
+            Message::Arp(_) => MessageKind::Arp,
+        }
+    }
+}
+
+impl Display for MessageKind{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            MessageKind::Bpdu => write!(f, "BPDU"),
+            MessageKind::OspfHello => write!(f, "OSPF hello"),
+            MessageKind::OspfLsp => write!(f, "OSPF LSP"),
+            MessageKind::EthernetFrame => write!(f, "Ethernet frame"),
+            MessageKind::Bgp => write!(f, "BGP"),
+            MessageKind::Arp => write!(f, "ARP"),
+        }
+    }
+}
+
+// Messages sent and received by a device since it started, on each of its ports and by kind
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceStats{
+    pub sent: BTreeMap<(u32, MessageKind), u64>,
+    pub received: BTreeMap<(u32, MessageKind), u64>,
+    pub send_failures: BTreeMap<u32, u64>, // messages of each port dropped as their link was full or closed
+    pub loops_detected: u64 // packets dropped by a router as they came back to it
+}
+
+// counters of a device, also updated by the senders of its links
+pub(crate) type SharedStats = Arc<Mutex<DeviceStats>>;
+
+impl DeviceStats{
+    pub(crate) fn count(&mut self, direction: Direction, port: u32, message: &Message){
+        let counters = match direction{
+            Direction::Sent => &mut self.sent,
+            Direction::Received => &mut self.received,
+        };
+        *counters.entry((port, message.into())).or_default() += 1;
+    }
+
+    fn counters(&self, direction: Direction) -> &BTreeMap<(u32, MessageKind), u64>{
+        match direction{
+            Direction::Sent => &self.sent,
+            Direction::Received => &self.received,
+        }
+    }
+
+    pub fn on_port(&self, direction: Direction, port: u32, kind: MessageKind) -> u64{
+        self.counters(direction).get(&(port, kind)).copied().unwrap_or(0)
+    }
+
+    // on all the ports of the device
+    pub fn total(&self, direction: Direction, kind: MessageKind) -> u64{
+        self.counters(direction).iter().filter(|((_, k), _)| *k == kind).map(|(_, count)| count).sum()
+    }
+
+    // messages sent and received for each port and kind seen in either direction
+    pub fn rows(&self) -> Vec<(u32, MessageKind, u64, u64)>{
+        let mut keys: Vec<&(u32, MessageKind)> = self.sent.keys().chain(self.received.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        keys.into_iter().map(|(port, kind)| (*port, *kind, self.on_port(Direction::Sent, *port, *kind), self.on_port(Direction::Received, *port, *kind))).collect()
+    }
+}
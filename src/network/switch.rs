@@ -1,14 +1,16 @@
-use std::{cell::RefCell, collections::{BTreeMap, HashMap}, rc::Rc, sync::Arc, time::SystemTime};
-use tokio::sync::{mpsc::{channel, Receiver, Sender}, Mutex};
+use std::{collections::{BTreeMap, HashMap}, sync::Arc, time::{Duration, Instant}};
+use serde::Serialize;
+use tokio::{sync::mpsc::{channel, Receiver, Sender}, task::JoinHandle};
 
-use super::{logger::{Logger, Source}, messages::{bpdu::BPDU, Message}, utils::SharedState};
-use super::communicators::{SwitchCommunicator, Command, Response};
+use super::{capture::Direction, events::{self, EventSender, NetworkEvent}, link::LinkSender, logger::{Logger, Source}, messages::{bpdu::Bpdu, Message}, stats::SharedStats, timers::TimerConfig, utils::{forward_link, LinkMessage, MacAddress}};
+use super::communicators::{SwitchCommunicator, Command};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum PortState{
     Blocked,
     Designated,
-    Root
+    Root,
+    Disabled // shut down by the administrator, the port neither sends nor receives
 }
 
 impl ToString for PortState{
@@ -17,23 +19,36 @@ impl ToString for PortState{
             PortState::Blocked => "B".into(),
             PortState::Designated => "D".into(),
             PortState::Root => "R".into(),
+            PortState::Disabled => "X".into(),
         }
     }
 }
 
-type Neighbor = (u32, SharedState<Receiver<Message>>, Sender<Message>, u32); // port, receiver, sender, cost
+// a bpdu whose root information is older than this is forgotten
+pub const BPDU_MAX_AGE: Duration = Duration::from_secs(1);
+// added to the age of the bpdus at each hop, so that stale information circulating in a loop still expires
+const MESSAGE_AGE_INCREMENT: Duration = Duration::from_millis(1);
+
+type Neighbor = (u32, u64, LinkSender, u32); // port, id of the link, sender, cost
 
 #[derive(Debug)]
 pub struct Switch{
     pub name: String,
     pub id: u32,
     pub neighbors: Vec<Neighbor>, 
-    pub bpdu: BPDU,
+    pub bpdu: Bpdu,
     pub root_port: u32,
-    pub ports: HashMap<u32, (BPDU, u32)>,
+    pub ports: HashMap<u32, (Bpdu, u32, Instant)>, // best bpdu received on each port, its cost and arrival time
     pub ports_states: HashMap<u32, PortState>,
+    pub mac_table: HashMap<MacAddress, u32>, // port on which each address was last seen as source of a frame
+    pub max_age: Duration,
     pub command_receiver: Receiver<Command>,
-    pub command_replier: Sender<Response>,
+    pub link_sender: Sender<LinkMessage>,
+    pub link_receiver: Receiver<LinkMessage>, // messages of all the links
+    pub next_link: u64,
+    pub stats: SharedStats,
+    pub timers: TimerConfig,
+    pub events: EventSender,
     pub logger: Logger
 }
 
@@ -45,114 +60,279 @@ impl ToString for Switch{
 
 impl Switch{
 
-    pub fn start(name: String, id: u32, logger: Logger) -> SwitchCommunicator{
+    pub fn start(name: String, id: u32, max_age: Duration, timers: TimerConfig, events: EventSender, logger: Logger) -> (SwitchCommunicator, JoinHandle<()>){
         let (tx_command, rx_command) = channel(1024);
-        let (tx_response, rx_response) = channel(1024);
+        let (link_sender, link_receiver) = channel(1024);
         let mut switch = Switch{
             name, 
             id, 
             neighbors: vec![], 
             ports: HashMap::new(), 
             ports_states: HashMap::new(), 
+            mac_table: HashMap::new(),
+            max_age,
             root_port: 0, 
-            bpdu: BPDU{root: id, distance: 0, switch: id, port: 0}, 
+            bpdu: Bpdu{root: id, distance: 0, switch: id, port: 0, age: Duration::ZERO}, 
             command_receiver: rx_command,
-            command_replier: tx_response,
+            link_sender,
+            link_receiver,
+            next_link: 0,
+            stats: Arc::default(),
+            timers,
+            events,
             logger
         };
-        tokio::spawn(async move {
+        let task = tokio::spawn(async move {
             switch.run().await;
         });
-        SwitchCommunicator{command_sender: tx_command, response_receiver: Rc::new(RefCell::new(rx_response))}
+        (SwitchCommunicator{command_sender: tx_command}, task)
     }
 
     pub async fn run(&mut self){
         self.logger.log(Source::SPT, format!("Init BPDU for switch {} : {}", self.name, self.bpdu.to_string())).await;
-        let mut time = SystemTime::now();
+        let mut hello = tokio::time::interval(self.timers.bpdu_interval);
         loop{
-            if self.receive_command().await{
-                return;
-            }
-            self.receive_ports().await;
-            if time.elapsed().unwrap().as_millis() > 200{
-                // every 200ms, send my own bpdu
-                time = SystemTime::now();
-                self.send_bpdu().await;
+            tokio::select!{
+                command = self.command_receiver.recv() => match command{
+                    Some(command) => if self.process_command(command).await{
+                        return;
+                    },
+                    None => return,
+                },
+                Some((port, link, message)) = self.link_receiver.recv() => self.receive_message(port, link, message).await,
+                // forget the stale bpdus and send my own bpdu
+                _ = hello.tick() => {
+                    self.expire_bpdus().await;
+                    self.send_bpdu().await;
+                },
             }
-            
         }
     }
 
-    pub async fn receive_command(&mut self) -> bool{
-        match self.command_receiver.try_recv(){
-            Ok(command) => {
-                match command{
-                    Command::StatePorts => {
-                        let mut map = BTreeMap::new();
-                        for (port, state) in self.ports_states.iter(){
-                            map.insert(*port, state.clone());
-                        }
-                        self.command_replier.send(Response::StatePorts(map)).await.expect("Failed to send response to state port command");
-                        false
-                    },
-                    Command::AddLink(receiver, sender, port, cost) => {
-                        let receiver = Arc::new(Mutex::new(receiver));
-                        self.neighbors.push((port, receiver, sender, cost));
-                        self.ports_states.insert(port, PortState::Designated);
-                        false
-                    },
-                    Command::Quit => true,
-                    Command::Ping(_) => panic!("Ping not supported on switch"),
-                    Command::RoutingTable => panic!("RoutingTable not supported on switch"),
-                    Command::AddPeerLink(_, _, _, _, _) => panic!("Adding peer link not supported on switch"),
-                    Command::AddProvider(_, _, _, _, _) => panic!("Adding provider link not supported on switch"),
-                    Command::AddCustomer(_, _, _, _, _) => panic!("Adding customer link not supported on switch"),
-                    Command::AnnouncePrefix => panic!("Announcing prefix not supported on switch"),
-                    Command::BGPRoutes => panic!("BGPRoutes not supported on switch"),
-                    Command::AddIBGP(_) => panic!("AddIBGP not supported on switch"),
+    pub async fn process_command(&mut self, command: Command) -> bool{
+        match command{
+            Command::StatePorts(replier) => {
+                let mut map = BTreeMap::new();
+                for (port, state) in self.ports_states.iter(){
+                    map.insert(*port, state.clone());
+                }
+                // the caller may have stopped waiting for the answer
+                let _ = replier.send(map);
+                false
+            },
+            Command::MacTable(replier) => {
+                let _ = replier.send(self.mac_table.clone());
+                false
+            },
+            Command::AddLink(receiver, sender, port, cost) => {
+                let link = self.next_link;
+                self.next_link += 1;
+                forward_link(receiver, port, link, self.link_sender.clone());
+                self.neighbors.push((port, link, sender.counted(port, &self.stats), cost));
+                self.ports_states.insert(port, PortState::Designated);
+                false
+            },
+            Command::RemoveLink(port) => {
+                self.logger.log(Source::DEBUG, format!("Switch {} removing link on port {}", self.name, port)).await;
+                // the messages still in flight on the link are dropped
+                self.neighbors.retain(|(p, _, _, _)| *p != port);
+                self.ports.remove(&port);
+                self.ports_states.remove(&port);
+                self.mac_table.retain(|_, p| *p != port);
+                if port == self.root_port{
+                    self.recompute_root().await;
+                }
+                false
+            },
+            Command::Stats(replier) => {
+                let _ = replier.send(self.stats.lock().unwrap().clone());
+                false
+            },
+            Command::SetPortEnabled(port, enabled) => {
+                self.set_port_enabled(port, enabled).await;
+                false
+            },
+            Command::SetLinkCost(port, cost) => {
+                self.set_link_cost(port, cost).await;
+                false
+            },
+            Command::SetMaxAge(max_age) => {
+                self.max_age = max_age;
+                false
+            },
+            Command::Replay(messages, replier) => {
+                for (port, message) in messages{
+                    // the messages on a port without link are ignored, like those of a removed link
+                    let cost = self.neighbors.iter().find(|(p, _, _, _)| *p == port && !self.is_disabled(*p)).map(|(_, _, _, cost)| *cost);
+                    if let Some(cost) = cost{
+                        self.dispatch_message(port, cost, message).await;
+                    }
                 }
+                let _ = replier.send(());
+                false
             },
-            Err(_) => false,
+            Command::Quit => true,
+            #[cfg(test)]
+            Command::Panic => panic!("Switch told to panic"),
+            Command::Ping(_, _) => panic!("Ping not supported on switch"),
+            Command::PingRecorded(_, _) => panic!("Ping not supported on switch"),
+            Command::Traceroute(_, _) => panic!("Traceroute not supported on switch"),
+            Command::PingSeries(_, _, _, _) => panic!("Ping not supported on switch"),
+            Command::SendData(_, _) => panic!("Sending data not supported on switch"),
+            Command::ReceivedData(_) => panic!("ReceivedData not supported on switch"),
+            Command::SendProbe(_, _) => panic!("Sending probes not supported on switch"),
+            Command::ReceivedProbes(_, _) => panic!("ReceivedProbes not supported on switch"),
+            Command::RoutingTable(_) => panic!("RoutingTable not supported on switch"),
+            Command::AddPeerLink(_, _, _, _, _, _) => panic!("Adding peer link not supported on switch"),
+            Command::AddProvider(_, _, _, _, _, _) => panic!("Adding provider link not supported on switch"),
+            Command::AddCustomer(_, _, _, _, _, _) => panic!("Adding customer link not supported on switch"),
+            Command::AnnouncePrefix(_) => panic!("Announcing prefix not supported on switch"),
+            Command::AnnouncePrefixCustom(_) => panic!("Announcing prefix not supported on switch"),
+            Command::AnnouncePrefixWithCommunities(_) => panic!("Announcing prefix not supported on switch"),
+            Command::AnnouncePrefixWithOrigin(_) => panic!("Announcing prefix not supported on switch"),
+            Command::WithdrawPrefix => panic!("Withdrawing prefix not supported on switch"),
+            Command::ClearRoutes => panic!("Clearing routes not supported on switch"),
+            Command::ClearBGP => panic!("Clearing BGP not supported on switch"),
+            Command::AddConditionalAdvertisement(_, _, _) => panic!("Conditional advertisement not supported on switch"),
+            Command::SetLocalPref(_, _) => panic!("Local pref not supported on switch"),
+            Command::SetIGPMed(_, _) => panic!("IGP MED not supported on switch"),
+            Command::SetPrepend(_, _) => panic!("AS path prepending not supported on switch"),
+            Command::AddCommunityPolicy(_, _) => panic!("Community policies not supported on switch"),
+            Command::SetImportPolicy(_, _) | Command::SetExportPolicy(_, _) => panic!("Route policies not supported on switch"),
+            Command::SetMaxPrefixes(_, _, _) => panic!("Max prefixes not supported on switch"),
+            Command::AddStaticRoute(_, _, _) => panic!("Static routes not supported on switch"),
+            Command::SetOSPF(_) => panic!("OSPF not supported on switch"),
+            Command::SetLimits(_) => panic!("Limits not supported on switch"),
+            Command::SetDeadInterval(_) => panic!("Dead interval not supported on switch"),
+            Command::SetInterfaceAddress(_, _, _) => panic!("Interface addresses not supported on switch"),
+            Command::MemoryStats(_) => panic!("MemoryStats not supported on switch"),
+            Command::Health(_) => panic!("Health not supported on switch"),
+            Command::RouterPorts(_) => panic!("RouterPorts not supported on switch"),
+            Command::ConvergenceState(_) => panic!("ConvergenceState not supported on switch"),
+            Command::BGPRoutes(_) => panic!("BGPRoutes not supported on switch"),
+            Command::MultipathRoutes(_) => panic!("MultipathRoutes not supported on switch"),
+            Command::SetMultipath(_) => panic!("BGP multipath not supported on switch"),
+            Command::EqualCostPorts(_) => panic!("EqualCostPorts not supported on switch"),
+            Command::Lsdb(_) => panic!("Lsdb not supported on switch"),
+            Command::SetEcmp(_) => panic!("ECMP not supported on switch"),
+            Command::BGPSessions(_) => panic!("BGPSessions not supported on switch"),
+            Command::SetSessionSecret(_, _) => panic!("Session secret not supported on switch"),
+            Command::AddIBGP(_) => panic!("AddIBGP not supported on switch"),
+            Command::AddIBGPClient(_) => panic!("AddIBGPClient not supported on switch"),
         }
     }
 
-    pub async fn receive_ports(&mut self){
-        let mut received_bpdus = vec![];
-        let mut received_messages= vec![];
-        for (port, receiver, _, cost) in self.neighbors.iter(){
-            let mut receiver = receiver.lock().await;
-            match receiver.try_recv(){
-                Ok(Message::BPDU(bpdu)) => received_bpdus.push((bpdu, *port, *cost)),
-                Ok(message) => {
-                    if self.get_port_state(*port) != PortState::Blocked{
-                        received_messages.push((*port, message))
+    pub async fn receive_message(&mut self, port: u32, link: u64, message: Message){
+        let cost = match self.neighbors.iter().find(|(p, l, _, _)| *p == port && *l == link){
+            Some((_, _, _, cost)) if !self.is_disabled(port) => *cost,
+            _ => return, // the link was removed or its port shut down
+        };
+        self.stats.lock().unwrap().count(Direction::Received, port, &message);
+        self.dispatch_message(port, cost, message).await;
+    }
+
+    // handle a message received on port at the given cost, whichever link carried it
+    pub async fn dispatch_message(&mut self, port: u32, cost: u32, message: Message){
+        match message{
+            Message::Bpdu(bpdu) => self.receive_bpdu(bpdu, port, cost).await,
+            message => {
+                if !self.forwards(port){
+                    return;
+                }
+                if let Message::EthernetFrame(src, dest, _) = &message{
+                    if self.mac_table.insert(src.clone(), port) != Some(port){
+                        self.logger.log(Source::DEBUG, format!("Switch {} learned mac address {} on port {}", self.name, src.id, port)).await;
+                    }
+                    if let Some(out) = self.mac_table.get(dest).filter(|p| self.forwards(**p)){
+                        if *out != port{
+                            if let Some((_, _, sender, _)) = self.neighbors.iter().find(|(p, _, _, _)| p == out){
+                                sender.send(message.clone()).await;
+                            }
+                        }
+                        return;
+                    }
+                }
+                // unknown destinations and the other messages are flooded
+                for (p, _, sender, _) in self.neighbors.iter(){
+                    if port != *p && self.forwards(*p){
+                        sender.send(message.clone()).await;
                     }
                 }
-                Err(_) => continue,
             }
         }
-        for (bpdu, port, cost) in received_bpdus{
-            self.receive_bpdu(bpdu, port, cost).await;
+    }
+
+    // A disabled port is left out of the spanning tree as if its link was removed, the tree is computed again without it.
+    // Once enabled, it is designated until the bpdus of the other end tell otherwise, like a new link
+    async fn set_port_enabled(&mut self, port: u32, enabled: bool){
+        let Some((_, _, sender, _)) = self.neighbors.iter().find(|(p, _, _, _)| *p == port) else {
+            return;
+        };
+        if self.is_disabled(port) != enabled{
+            return;
         }
-        for (port, message) in received_messages{
-            for (p, _, sender, _) in self.neighbors.iter(){
-                if port != *p && self.get_port_state(*p) != PortState::Blocked{
-                    sender.send(message.clone()).await.expect("Failed to broadcast message");
-                }
-            }
+        sender.set_enabled(enabled);
+        self.logger.log(Source::SPT, format!("Switch {} {} port {}", self.name, if enabled { "enabled" } else { "shut down" }, port)).await;
+        if enabled{
+            self.set_port_state(port, PortState::Designated).await;
+            self.send_bpdu().await;
+            return;
+        }
+        self.ports.remove(&port);
+        self.set_port_state(port, PortState::Disabled).await;
+        if port == self.root_port{
+            self.recompute_root().await;
+        }
+    }
+
+    // the distance to the root through the port changes, it may not be the best port towards the root anymore
+    async fn set_link_cost(&mut self, port: u32, cost: u32){
+        let Some(neighbor) = self.neighbors.iter_mut().find(|(p, _, _, _)| *p == port) else {
+            return;
+        };
+        neighbor.3 = cost;
+        if let Some((_, port_cost, _)) = self.ports.get_mut(&port){
+            *port_cost = cost;
+        }
+        self.logger.log(Source::SPT, format!("Switch {} set the cost of port {} to {}", self.name, port, cost)).await;
+        if self.elect_root().await{
+            self.send_bpdu().await;
         }
     }
 
-    pub async fn receive_bpdu(&mut self, bpdu: BPDU, port: u32, distance: u32){
+    fn is_disabled(&self, port: u32) -> bool{
+        self.ports_states.get(&port) == Some(&PortState::Disabled)
+    }
+
+    // the frames are only forwarded on the ports of the spanning tree
+    fn forwards(&self, port: u32) -> bool{
+        matches!(self.get_port_state(port), PortState::Root | PortState::Designated)
+    }
+
+    pub async fn receive_bpdu(&mut self, bpdu: Bpdu, port: u32, distance: u32){
         self.logger.log(Source::SPT, format!("Switch {} received BPDU {} on port {}", self.name, bpdu.to_string(), port)).await;
+        if bpdu.age >= self.max_age{
+            return;
+        }
         let prev = self.ports.get(&port);
-        if let Some((prev_bpdu, _)) = prev{
+        if let Some((prev_bpdu, _, _)) = prev{
             if prev_bpdu < &bpdu{
+                if prev_bpdu.switch == bpdu.switch && prev_bpdu.port == bpdu.port && port == self.root_port{
+                    // the path to the root through our root port got worse, another port may be better now
+                    self.ports.insert(port, (bpdu, distance, Instant::now()));
+                    if self.elect_root().await{
+                        self.send_bpdu().await;
+                    }
+                }else if prev_bpdu.switch == bpdu.switch && prev_bpdu.port == bpdu.port{
+                    // the neighbor lost its root port, it may not be designated anymore on this link
+                    self.ports.insert(port, (bpdu, distance, Instant::now()));
+                    self.update_state_port(port).await;
+                }
                 return;
             }
         }
-        self.ports.insert(port, (bpdu.clone(), distance));
-        self.update_best(BPDU{root: bpdu.root, distance: bpdu.distance+distance, switch: bpdu.switch, port: bpdu.port}, port).await;
+        self.ports.insert(port, (bpdu.clone(), distance, Instant::now()));
+        self.update_best(Bpdu{root: bpdu.root, distance: bpdu.distance+distance, switch: bpdu.switch, port: bpdu.port, age: bpdu.age}, port).await;
         self.update_state_port(port).await;
         // updated root, resend my bpdu to all neighbors
         if self.root_port == port{
@@ -165,16 +345,33 @@ impl Switch{
         if bpdu.is_none(){
             return;
         }
-        let (bpdu, _) = bpdu.unwrap();
-        if port == self.root_port{
-            self.ports_states.insert(port, PortState::Root);
+        let (bpdu, _, _) = bpdu.unwrap();
+        let state = if port == self.root_port{
+            PortState::Root
         }else if bpdu < &self.bpdu{
             self.logger.log(Source::SPT, format!("BPDU received ({}) by {} on port {} was better than self bpdu ({}), port {} becomes blocked", bpdu.to_string(), self.name, port, self.bpdu.to_string(), port)).await;
-            self.ports_states.insert(port, PortState::Blocked);
+            PortState::Blocked
         }else{
             self.logger.log(Source::SPT, format!("BPDU received ({}) by {} on port {} was worse than self bpdu ({}), port {} becomes designated", bpdu.to_string(), self.name, port, self.bpdu.to_string(), port)).await;
-            self.ports_states.insert(port, PortState::Designated);
+            PortState::Designated
+        };
+        self.set_port_state(port, state).await;
+    }
+
+    // the stations may be behind other ports once the tree changed, they are learned again from their next frames
+    async fn set_port_state(&mut self, port: u32, state: PortState){
+        let old = self.ports_states.insert(port, state.clone());
+        if old.as_ref() == Some(&state){
+            return;
+        }
+        if let Some(old) = old{
+            events::emit(&self.events, NetworkEvent::StpPortStateChanged{switch: self.name.clone(), port, old, new: state});
         }
+        if self.mac_table.is_empty(){
+            return;
+        }
+        self.logger.log(Source::SPT, format!("Switch {} flushes its mac table, the state of port {} changed", self.name, port)).await;
+        self.mac_table.clear();
     }
 
     pub async fn send_bpdu(&self){
@@ -183,9 +380,39 @@ impl Switch{
                 // either we can't send a bpdu on this port, or it generated a cycle for rust borrows, no point to continue
                 continue;
             }
-            let bpdu = BPDU{root: self.bpdu.root, distance: self.bpdu.distance, switch: self.id, port: *port};
+            let bpdu = self.own_bpdu(*port);
             self.logger.log(Source::SPT, format!("Switch {} sending BPDU {} on port {}", self.name, bpdu.to_string(), port)).await;
-            sender.send(Message::BPDU(bpdu)).await.unwrap();
+            sender.send(Message::Bpdu(bpdu)).await;
+        }
+    }
+
+    // our bpdu as sent on port, aged by the time the root information spent reaching us
+    fn own_bpdu(&self, port: u32) -> Bpdu{
+        let age = match self.ports.get(&self.root_port){
+            Some((bpdu, _, arrival)) => bpdu.age + arrival.elapsed() + MESSAGE_AGE_INCREMENT,
+            _ => Duration::ZERO,
+        };
+        Bpdu{root: self.bpdu.root, distance: self.bpdu.distance, switch: self.id, port, age}
+    }
+
+    // forget the bpdus whose root information is too old, the root or the designated switch behind them may be dead
+    async fn expire_bpdus(&mut self){
+        let max_age = self.max_age;
+        let expired: Vec<u32> = self.ports.iter()
+            .filter(|(_, (bpdu, _, arrival))| bpdu.age + arrival.elapsed() >= max_age)
+            .map(|(port, _)| *port)
+            .collect();
+        if expired.is_empty(){
+            return;
+        }
+        for port in expired.iter(){
+            self.logger.log(Source::SPT, format!("BPDU of switch {} on port {} expired", self.name, port)).await;
+            self.ports.remove(port);
+            // nobody else claims the link, we become designated on it
+            self.set_port_state(*port, PortState::Designated).await;
+        }
+        if expired.contains(&self.root_port){
+            self.elect_root().await;
         }
     }
 
@@ -197,16 +424,16 @@ impl Switch{
         ports
     }
 
-    async fn update_best(&mut self, bpdu: BPDU, port: u32){
-        let default = (self.bpdu.clone(), 0);
-        let (previous_best, cost) = self.ports.get(&self.root_port).unwrap_or(&default);
+    async fn update_best(&mut self, bpdu: Bpdu, port: u32){
+        let default = (self.bpdu.clone(), 0, Instant::now());
+        let (previous_best, cost, _) = self.ports.get(&self.root_port).unwrap_or(&default);
         
-        let previous_best_distance_added = BPDU{root: previous_best.root, distance: previous_best.distance + cost, switch: previous_best.switch, port: previous_best.port};
+        let previous_best_distance_added = Bpdu{root: previous_best.root, distance: previous_best.distance + cost, switch: previous_best.switch, port: previous_best.port, age: previous_best.age};
         // if we received an update for the previous root port, recompute always the best bpdu
         // else, check if it is better than the previous root port
         let update = port == self.root_port || previous_best_distance_added > bpdu; 
         if update{
-            self.bpdu = BPDU{root: bpdu.root, distance: bpdu.distance, switch: self.id, port: 0};
+            self.bpdu = Bpdu{root: bpdu.root, distance: bpdu.distance, switch: self.id, port: 0, age: Duration::ZERO};
             self.root_port = port;
             self.logger.log(Source::SPT, format!("Updated BPDU of switch {} to {} and port {} became new root", self.name, self.bpdu.to_string(), port)).await;
             for port in self.get_ports(){
@@ -215,6 +442,37 @@ impl Switch{
         }
     }
 
+    // the root port disappeared, elect a new one from the bpdus received on the remaining ports
+    async fn recompute_root(&mut self){
+        self.elect_root().await;
+        // neighbors behind our non designated ports must also learn that our root changed
+        for (port, _, sender, _) in self.neighbors.iter(){
+            let bpdu = self.own_bpdu(*port);
+            sender.send(Message::Bpdu(bpdu)).await;
+        }
+    }
+
+    // choose the root port among all the bpdus received, returns whether our bpdu changed
+    async fn elect_root(&mut self) -> bool{
+        let previous = self.bpdu.clone();
+        let mut best = Bpdu{root: self.id, distance: 0, switch: self.id, port: 0, age: Duration::ZERO};
+        let mut root_port = 0;
+        for (port, (bpdu, cost, _)) in self.ports.iter(){
+            let candidate = Bpdu{root: bpdu.root, distance: bpdu.distance + cost, switch: bpdu.switch, port: bpdu.port, age: bpdu.age};
+            if candidate < best{
+                best = candidate;
+                root_port = *port;
+            }
+        }
+        self.bpdu = Bpdu{root: best.root, distance: best.distance, switch: self.id, port: 0, age: Duration::ZERO};
+        self.root_port = root_port;
+        self.logger.log(Source::SPT, format!("Switch {} elected BPDU {} and port {} as root", self.name, self.bpdu.to_string(), root_port)).await;
+        for port in self.get_ports(){
+            self.update_state_port(port).await;
+        }
+        previous != self.bpdu
+    }
+
     pub fn get_port_state(&self, port: u32) -> PortState{
         if self.root_port == port{
             PortState::Root
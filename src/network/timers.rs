@@ -0,0 +1,19 @@
+use std::time::Duration;
+
+use super::{protocols::ospf::LSP_REFRESH_INTERVAL, utils::HELLO_INTERVAL};
+
+// Periods of the timers of a device, the OSPF dead interval and the BPDU max age must stay a few periods long
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimerConfig{
+    pub hello_interval: Duration,       // OSPF hellos of a router
+    pub bpdu_interval: Duration,        // BPDUs of a switch
+    pub arp_refresh_interval: Duration, // resolution of the addresses of the neighbors of a router
+    pub keepalive_interval: Duration,   // BGP keepalives of a router, its sessions are closed after three periods without them
+    pub lsp_refresh_interval: Duration  // LSP flooded again by a router, the LSPs not refreshed for three periods are forgotten
+}
+
+impl Default for TimerConfig{
+    fn default() -> Self {
+        TimerConfig{hello_interval: HELLO_INTERVAL, bpdu_interval: HELLO_INTERVAL, arp_refresh_interval: HELLO_INTERVAL, keepalive_interval: HELLO_INTERVAL, lsp_refresh_interval: LSP_REFRESH_INTERVAL}
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use super::{error::NetworkError, graphviz::{EdgeOption, Graph, GraphOption, NodeOption}, health::PortInfo, switch::PortState, BgpLink, Highlight};
+
+// Devices and links of a network as drawn in its DOT graph, taken from a running network or from a config without starting its devices
+#[derive(Debug, Default, Clone)]
+pub struct Topology{
+    pub(super) router_as: HashMap<u32, Vec<String>>,
+    pub(super) as_router: HashMap<String, u32>,
+    pub(super) switches: BTreeSet<String>,
+    pub(super) hosts: BTreeSet<String>,
+    pub(super) used_port: BTreeMap<String, HashSet<u32>>,
+    pub(super) internal_links: HashMap<String, Vec<(u32, String, u32, u32)>>,
+    pub(super) provider_customer: Vec<BgpLink>,
+    pub(super) peers: Vec<BgpLink>,
+}
+
+impl Topology{
+    pub fn add_router(&mut self, name: &str, router_as: u32){
+        self.used_port.insert(name.to_string(), HashSet::new());
+        self.router_as.entry(router_as).or_default().push(name.to_string());
+        self.as_router.insert(name.to_string(), router_as);
+    }
+
+    pub fn add_switch(&mut self, name: &str){
+        self.used_port.insert(name.to_string(), HashSet::new());
+        self.switches.insert(name.to_string());
+    }
+
+    pub fn add_host(&mut self, name: &str){
+        self.used_port.insert(name.to_string(), HashSet::new());
+        self.hosts.insert(name.to_string());
+    }
+
+    pub fn add_link(&mut self, device1: &str, port1: u32, device2: &str, port2: u32, cost: u32) -> Result<(), NetworkError>{
+        self.reserve_ports(device1, port1, device2, port2)?;
+        self.internal_links.entry(device1.to_string()).or_default().push((port1, device2.to_string(), port2, cost));
+        self.internal_links.entry(device2.to_string()).or_default().push((port2, device1.to_string(), port1, cost));
+        Ok(())
+    }
+
+    pub fn add_provider_customer_link(&mut self, provider: &str, port1: u32, customer: &str, port2: u32) -> Result<(), NetworkError>{
+        self.reserve_ports(provider, port1, customer, port2)?;
+        self.provider_customer.push((provider.to_string(), port1, customer.to_string(), port2, Default::default()));
+        Ok(())
+    }
+
+    pub fn add_peer_link(&mut self, router1: &str, port1: u32, router2: &str, port2: u32) -> Result<(), NetworkError>{
+        self.reserve_ports(router1, port1, router2, port2)?;
+        self.peers.push((router1.to_string(), port1, router2.to_string(), port2, Default::default()));
+        Ok(())
+    }
+
+    fn reserve_ports(&mut self, device1: &str, port1: u32, device2: &str, port2: u32) -> Result<(), NetworkError>{
+        for (device, port) in [(device1, port1), (device2, port2)]{
+            let ports = self.used_port.get_mut(device).ok_or(NetworkError::UnknownDevice(device.to_string()))?;
+            if !ports.insert(port){
+                return Err(NetworkError::PortInUse{device: device.to_string(), port});
+            }
+        }
+        Ok(())
+    }
+
+    // lowest port of the device that is neither used nor kept for a link added later, like the one of the network
+    pub fn next_free_port_except(&self, device: &str, reserved: &HashSet<u32>) -> Result<u32, NetworkError>{
+        let ports = self.used_port.get(device).ok_or(NetworkError::UnknownDevice(device.to_string()))?;
+        let mut port = 1;
+        while ports.contains(&port) || reserved.contains(&port){
+            port += 1;
+        }
+        Ok(port)
+    }
+
+    // the graph without the port states of the switches nor the costs and prefs of the routers
+    pub fn dot_string(&self) -> String{
+        self.dot_graph(None, None, None)
+    }
+
+    fn switch_as(&self) -> (HashMap<u32, Vec<String>>, Vec<String>){
+        let mut switch_as = HashMap::new();
+        let mut others = vec![];
+        for switch in self.switches.iter(){
+            let mut affiliation = None;
+            let mut inserted_other = false;
+            for (_, neighbor, _, _) in self.internal_links.get(switch).into_iter().flatten(){
+                if !self.as_router.contains_key(neighbor) {
+                    continue;
+                }
+                let router_as = self.as_router.get(neighbor).unwrap();
+                match affiliation{
+                    Some(a) => {
+                        if a != router_as{
+                            others.push(switch.clone());
+                            inserted_other = true;
+                            break;
+                        }
+                    }
+                    None => affiliation = Some(router_as)
+                }
+            }
+            if !inserted_other{
+                if let Some(a) = affiliation{
+                    switch_as.entry(*a).or_insert(vec![]).push(switch.clone());
+                }else{
+                    others.push(switch.clone());
+                }
+            }
+        }
+        (switch_as, others)
+    }
+
+
+    // the links between two switches are labelled with the states of their ports when they are given
+    // routers grouped by AS with the switches linked only to it, internal links undirected with their cost,
+    // provider -> customer links directed and peer links dashed, the port of each end is written next to it
+    // with the cost or the pref of the router port when they are given
+    pub(super) fn dot_graph(&self, states: Option<&BTreeMap<String, BTreeMap<u32, PortState>>>, router_ports: Option<&BTreeMap<String, BTreeMap<u32, PortInfo>>>,
+        highlight: Option<&Highlight>) -> String {
+        let port_label = |device: &str, port: u32| match router_ports.and_then(|ports| ports.get(device)).and_then(|ports| ports.get(&port)){
+            Some(info) => format!("{} {}", port, info),
+            None => format!("{}", port),
+        };
+        let mut graph = Graph::new(vec![GraphOption::RankSep("1".to_string()), GraphOption::NodeSep("1".to_string())]);
+        let node = |device: &str, shape: &str| {
+            let mut options = vec![NodeOption::Shape(shape.to_string())];
+            match highlight {
+                Some(Highlight::Forwarding(view)) => {
+                    if view.destinations.contains(device) {
+                        options.extend([NodeOption::Style("filled".to_string()), NodeOption::FillColor("palegreen".to_string())]);
+                    }
+                    if view.unreachable.contains(device) {
+                        options.extend([NodeOption::Color("grey".to_string()), NodeOption::FontColor("grey".to_string())]);
+                    }
+                },
+                Some(Highlight::SpanningTree{root}) if root.as_deref() == Some(device) => options = vec![NodeOption::Shape("doublecircle".to_string())],
+                _ => {},
+            }
+            options
+        };
+        let edge = |graph: &mut Graph, (device1, p1): (&str, u32), (device2, p2): (&str, u32), mut options: Vec<EdgeOption>| {
+            match highlight {
+                // the links used towards the destination are directed the way the packets go
+                Some(Highlight::Forwarding(view)) => {
+                    let used = |device: &str, port: u32| view.egress.contains(&(device.to_string(), port));
+                    let dir = match (used(device1, p1), used(device2, p2)) {
+                        (false, false) => None,
+                        (true, false) => Some("forward"),
+                        (false, true) => Some("back"),
+                        (true, true) => Some("both"), // a forwarding loop
+                    };
+                    if let Some(dir) = dir {
+                        options.retain(|option| !matches!(option, EdgeOption::Arrowhead(_) | EdgeOption::Color(_)));
+                        options.extend([EdgeOption::Color("darkgreen".to_string()), EdgeOption::PenWidth("3".to_string()), EdgeOption::Dir(dir.to_string())]);
+                    }
+                },
+                // the links of the active tree, whose switch ports aren't blocked nor disabled, stand out from the others
+                Some(Highlight::SpanningTree{..}) => {
+                    let state = |device: &str, port: u32| states.and_then(|states| states.get(device)).and_then(|ports| ports.get(&port));
+                    let (state1, state2) = (state(device1, p1), state(device2, p2));
+                    if state1.is_some() || state2.is_some() {
+                        options.retain(|option| !matches!(option, EdgeOption::Color(_)));
+                        let inactive = |state: Option<&PortState>| matches!(state, Some(PortState::Blocked | PortState::Disabled));
+                        if inactive(state1) || inactive(state2) {
+                            options.extend([EdgeOption::Color("lightgrey".to_string()), EdgeOption::Style("dashed".to_string())]);
+                        } else {
+                            options.extend([EdgeOption::Color("darkgreen".to_string()), EdgeOption::PenWidth("2".to_string())]);
+                        }
+                    }
+                },
+                None => {},
+            }
+            graph.add_edge(device1, device2, options);
+        };
+
+        let (switch_as, others) = self.switch_as();
+        let mut ases: Vec<&u32> = self.router_as.keys().collect();
+        ases.sort();
+        for as_id in ases{
+            let group = format!("AS{as_id}");
+            graph.add_group(&group, &format!("AS {as_id}"));
+            for router in self.router_as[as_id].iter(){
+                graph.add_node_group(router, &group, node(router, "rect"));
+            }
+            for switch in switch_as.get(as_id).unwrap_or(&vec![]).iter(){
+                graph.add_node_group(switch, &group, node(switch, "diamond"));
+            }
+        }
+        for switch in others{
+            graph.add_node(&switch, node(&switch, "diamond"))
+        }
+        for host in self.hosts.iter(){
+            graph.add_node(host, node(host, "ellipse"))
+        }
+
+        let mut devices: Vec<&String> = self.internal_links.keys().collect();
+        devices.sort();
+        for device1 in devices {
+            for (p1, device2, p2, cost) in self.internal_links[device1].iter(){
+                if device1 > device2{
+                    continue;
+                }
+                let mut options = vec![EdgeOption::Arrowhead("none".to_string()), EdgeOption::Label(cost.to_string())];
+                if let Some(states) = states.filter(|_| self.switches.contains(device1) && self.switches.contains(device2)){
+                    options.push(EdgeOption::Headlabel(format!("{} {}", p2,
+                        states.get(device2).unwrap().get(p2).unwrap().to_string())));
+                    options.push(EdgeOption::Taillabel(format!("{} {}", p1,
+                        states.get(device1).unwrap().get(p1).unwrap().to_string())));
+                }else{
+                    options.push(EdgeOption::Headlabel(port_label(device2, *p2)));
+                    options.push(EdgeOption::Taillabel(port_label(device1, *p1)));
+                }
+                edge(&mut graph, (device1, *p1), (device2, *p2), options);
+            }
+        }
+
+        for (device1, p1, device2, p2, _) in self.provider_customer.iter(){
+            let options = vec![
+                EdgeOption::Label("$".to_string()), 
+                EdgeOption::Headlabel(port_label(device2, *p2)),
+                EdgeOption::Taillabel(port_label(device1, *p1)),
+                EdgeOption::Color("red".to_string()),
+                EdgeOption::FontColor("red".to_string())
+            ];
+            edge(&mut graph, (device1, *p1), (device2, *p2), options);
+        }
+        for (device1, p1, device2, p2, _) in self.peers.iter(){
+            let options = vec![
+                EdgeOption::Arrowhead("none".to_string()),
+                EdgeOption::Label("=".to_string()), 
+                EdgeOption::Headlabel(port_label(device2, *p2)),
+                EdgeOption::Taillabel(port_label(device1, *p1)),
+                EdgeOption::Color("blue".to_string()),
+                EdgeOption::FontColor("blue".to_string()),
+                EdgeOption::Style("dashed".to_string())
+            ];
+            edge(&mut graph, (device1, *p1), (device2, *p2), options);
+        }
+
+        format!("{}", graph)
+    }
+}
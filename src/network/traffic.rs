@@ -0,0 +1,108 @@
+use std::{collections::HashMap, fmt::Display, net::Ipv4Addr, sync::{atomic::{AtomicU64, Ordering}, Arc}, time::Duration};
+
+use serde::Serialize;
+use tokio::task::JoinHandle;
+
+use super::{communicators::RouterCommunicator, logger::{Logger, Source}};
+
+// Counters of a flow, the probes still in flight when the stats are taken are counted as lost
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlowStats{
+    pub sent: u64,
+    pub received: u64,
+    pub lost: u64,
+    pub average_latency: Duration, // of the probes received
+    pub finished: bool             // all the probes of the flow were sent
+}
+
+impl Display for FlowStats{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sent {}, received {}, lost {}, average latency {:?}{}", self.sent, self.received, self.lost, self.average_latency,
+            if self.finished { "" } else { " (running)" })
+    }
+}
+
+// Answers to a series of pings like ping -c, the round trip times are the ones of the answered pings
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PingReport{
+    pub sent: u32,
+    pub received: u32,
+    pub lost: u32,
+    pub duplicates: u32, // answers received twice, e.g. over a link duplicating the messages
+    pub reordered: u32,  // answers received after the one of a later ping
+    pub min_rtt: Duration,
+    pub avg_rtt: Duration,
+    pub max_rtt: Duration
+}
+
+impl PingReport{
+    pub fn loss_percent(&self) -> f64{
+        if self.sent == 0 { 0.0 } else { 100.0 * self.lost as f64 / self.sent as f64 }
+    }
+}
+
+impl Display for PingReport{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |rtt: Duration| rtt.as_secs_f64() * 1000.0;
+        write!(f, "{} packets transmitted, {} received, ", self.sent, self.received)?;
+        if self.duplicates > 0{
+            write!(f, "+{} duplicates, ", self.duplicates)?;
+        }
+        if self.reordered > 0{
+            write!(f, "{} reordered, ", self.reordered)?;
+        }
+        write!(f, "{:.0}% packet loss", self.loss_percent())?;
+        if self.received > 0{
+            write!(f, ", rtt min/avg/max = {:.3}/{:.3}/{:.3} ms", ms(self.min_rtt), ms(self.avg_rtt), ms(self.max_rtt))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Flow{
+    sent: Arc<AtomicU64>,
+    sender: JoinHandle<()>
+}
+
+// Flows of probes sent at a fixed rate, each router counts the probes it receives for each flow
+#[derive(Debug, Default)]
+pub(crate) struct TrafficGen{
+    flows: HashMap<u64, Flow>,
+    next_flow: u64
+}
+
+impl TrafficGen{
+    // send rate_pps probes per second from the router during duration, returns the id of the flow
+    pub fn start(&mut self, src: RouterCommunicator, name: &str, dest: Ipv4Addr, rate_pps: u32, duration: Duration, logger: Logger) -> u64{
+        let flow = self.next_flow;
+        self.next_flow += 1;
+        let sent = Arc::new(AtomicU64::new(0));
+        let count = (duration.as_secs_f64() * rate_pps as f64).round() as u64;
+        let (counter, name) = (Arc::clone(&sent), name.to_string());
+        let sender = tokio::spawn(async move {
+            if count > 0{
+                let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / rate_pps as f64));
+                for _ in 0..count{
+                    interval.tick().await;
+                    src.send_probe(dest, flow).await;
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            logger.log_from(Source::IP, &name, format!("Flow {} from {} to {} finished, {} probes sent", flow, name, dest, count)).await;
+        });
+        self.flows.insert(flow, Flow{sent, sender});
+        flow
+    }
+
+    // probes sent so far and whether the flow finished, none for an unknown flow
+    pub fn sent(&self, flow: u64) -> Option<(u64, bool)>{
+        self.flows.get(&flow).map(|f| (f.sent.load(Ordering::Relaxed), f.sender.is_finished()))
+    }
+
+    pub fn stop(&mut self){
+        for (_, flow) in self.flows.drain(){
+            flow.sender.abort();
+        }
+    }
+}
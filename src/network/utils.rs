@@ -1,9 +1,55 @@
-use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{mpsc::{Receiver, Sender}, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::messages::Message;
 
 pub type SharedState<V> = Arc<Mutex<V>>;
 
-#[derive(Debug, Clone, PartialEq)]
+pub type LinkMessage = (u32, u64, Message); // port, id of the link, message
+
+// time between two hellos of a device
+pub const HELLO_INTERVAL: Duration = Duration::from_millis(200);
+
+// splitmix64, enough to draw the faults of a link or a random topology
+#[derive(Debug)]
+pub(crate) struct Rng(pub u64); // seed
+
+impl Rng{
+    pub fn next_u64(&mut self) -> u64{
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_f64(&mut self) -> f64{
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // in 0..n
+    pub fn below(&mut self, n: usize) -> usize{
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct MacAddress{
     pub id: u32 // for simplicity, we simply use an int as an address
-}
\ No newline at end of file
+}
+
+// Forward the messages received on a link to its device, which only has to wait on a single channel.
+// The link is read until the other end has dropped its sender, so that a neighbor that hasn't
+// processed the removal of the link yet never sends into a closed channel
+pub fn forward_link(mut receiver: Receiver<Message>, port: u32, link: u64, device: Sender<LinkMessage>){
+    tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await{
+            // the device stopped, the link is closed so that the senders on it notice
+            if device.send((port, link, message)).await.is_err(){
+                break;
+            }
+        }
+    });
+}
@@ -0,0 +1,29 @@
+use std::process::{Command, Output};
+
+fn run(file: &str) -> Output{
+    Command::new(env!("CARGO_BIN_EXE_network-simulator")).args(["run", file]).output().expect("Failed to run the simulator")
+}
+
+#[test]
+fn test_assertions_exit_status() {
+    let output = run("examples/bgp-assertions.yaml");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(stdout.matches("Assertion passed").count(), 4, "{}", stdout);
+
+    // the wrong port and AS path are both reported, the pings still pass
+    let failing = std::fs::read_to_string("examples/bgp-assertions.yaml").unwrap()
+        .replace("{port: 1, distance: 1}", "{port: 2, distance: 1}")
+        .replace("as_path: [4, 1]", "as_path: [4, 2, 1]");
+    let file = std::env::temp_dir().join(format!("bgp-assertions-failing-{}.yaml", std::process::id()));
+    std::fs::write(&file, failing).unwrap();
+    let output = run(file.to_str().unwrap());
+    std::fs::remove_file(&file).unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains("Assertion failed: assert_routing_table r1\n  10.0.1.2/32: expected port=2, distance=1, got port=1, distance=1\n"), "{}", stdout);
+    assert!(stdout.contains("Assertion failed: assert_bgp_best r3 10.0.1.0/24 [4, 2, 1]\n  10.0.1.0/24: expected as_path [4, 2, 1], got [4, 1]\n"), "{}", stdout);
+    assert_eq!(stdout.matches("Assertion passed").count(), 2, "{}", stdout);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("2 assertions failed"));
+}
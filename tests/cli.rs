@@ -0,0 +1,120 @@
+use std::{io::Write, process::{Command, Output, Stdio}};
+
+fn simulator(args: &[&str]) -> Output{
+    Command::new(env!("CARGO_BIN_EXE_network-simulator")).args(args).output().expect("Failed to run the simulator")
+}
+
+fn stdout(output: &Output) -> String{
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+// writes a config in a temporary file, removed once the closure ran
+fn with_config<T>(name: &str, config: &str, f: impl FnOnce(&str) -> T) -> T{
+    let file = std::env::temp_dir().join(format!("{}-{}.yaml", name, std::process::id()));
+    std::fs::write(&file, config).unwrap();
+    let result = f(file.to_str().unwrap());
+    std::fs::remove_file(&file).unwrap();
+    result
+}
+
+#[test]
+fn test_run() {
+    let dot = std::env::temp_dir().join(format!("ospf-ports-{}.dot", std::process::id()));
+    let output = simulator(&["run", "examples/ospf-ports.yaml", "--log", "ospf,bgp", "--quiet", "--seed", "7", "--dot", dot.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = stdout(&output);
+    assert!(stdout.starts_with("Routing tables:"), "{}", stdout);
+    assert!(!stdout.contains("added with cost"));
+    let graph = std::fs::read_to_string(&dot).unwrap();
+    std::fs::remove_file(&dot).unwrap();
+    assert!(graph.starts_with("digraph{"));
+
+    let output = simulator(&["run", "examples/ospf-ports.yaml", "--log", "tcp"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown log source tcp"));
+
+    let output = simulator(&["run", "examples/missing.yaml"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_config_log_sources() {
+    // the sources of the config are printed whatever RUST_LOG is, and only them
+    let output = Command::new(env!("CARGO_BIN_EXE_network-simulator")).args(["run", "examples/ospf-square.yaml"])
+        .env("RUST_LOG", "error").output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Router r4 received ping back from 10.0.1.3"), "{}", stderr);
+    assert!(!stderr.contains("sending Hello"), "{}", stderr);
+
+    let output = simulator(&["run", "examples/ospf-square.yaml", "--quiet"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+#[test]
+fn test_interactive() {
+    let config = "network: {routers: [{name: r1, id: 1, AS: 1}, {name: r2, id: 2, AS: 2}], links: {bgp: {provider-customer: [{provider: r2, customer: r1}]}}}";
+    let commands = "show bgp r2\nannounce r1\nannounce r2\nwait\nshow bgp r2\nping r2 10.0.1.1\nping r9 10.0.1.1\nping r2 10.0.1\nquit\n";
+    let output = with_config("interactive", config, |file| {
+        let mut simulator = Command::new(env!("CARGO_BIN_EXE_network-simulator")).args(["run", file, "--interactive", "--quiet"])
+            .stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+        simulator.stdin.take().unwrap().write_all(commands.as_bytes()).unwrap();
+        simulator.wait_with_output().unwrap()
+    });
+    assert!(output.status.success());
+    let stdout = stdout(&output);
+    let lines: Vec<&str> = stdout.lines().collect();
+    let header = "   Network            Next Hop         Pref   MED  Port  Path";
+    assert_eq!(lines[..4], ["r2", header, "Prefix of r1 announced", "Prefix of r2 announced"], "{}", stdout);
+    assert!(lines[4].starts_with("Network converged in"));
+    assert_eq!(lines[5..7], ["r2", header]);
+    assert!(lines[7].starts_with(" > 10.0.1.0/24        10.0.1.1") && lines[7].ends_with("1  1 i"), "{}", stdout);
+    assert!(lines[9].starts_with("Ping from r2 to 10.0.1.1: answer received in"), "{}", stdout);
+    // the errors don't end the session
+    assert_eq!(lines[10..], ["Error: Unknown device r9", "Error: Invalid command: 10.0.1 is not a valid IP address"]);
+}
+
+#[test]
+fn test_validate() {
+    let output = simulator(&["validate", "examples/ospf-ports.yaml"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "examples/ospf-ports.yaml is valid: 3 routers, 0 switches, 0 hosts and 3 links\n");
+
+    let config = std::fs::read_to_string("examples/ospf-ports.yaml").unwrap().replace("\"r3:5\"", "\"r4:5\"");
+    let output = with_config("ospf-ports-unknown", &config, |file| simulator(&["validate", file]));
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("r4"), "{}", String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn test_graph() {
+    let output = simulator(&["graph", "examples/ospf-ports.yaml"]);
+    assert!(output.status.success());
+    let stdout = stdout(&output);
+    assert!(stdout.starts_with("digraph{"), "{}", stdout);
+    for router in ["r1", "r2", "r3"]{
+        assert!(stdout.contains(&format!("{}[shape=rect]", router)), "{}", stdout);
+    }
+    // the scenario doesn't run
+    assert!(!stdout.contains("Routing tables"));
+}
+
+#[test]
+fn test_compare() {
+    let output = simulator(&["compare", "examples/ospf-ports.yaml", "examples/ospf-ports.yaml", "--expect-identical"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("converge to identical states"));
+}
+
+#[test]
+fn test_schema_and_example() {
+    let output = simulator(&["schema"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("network:"));
+
+    let output = simulator(&["example", "ospf-square"]);
+    assert!(output.status.success());
+    let output = simulator(&["example", "unknown"]);
+    assert_eq!(output.status.code(), Some(2));
+}
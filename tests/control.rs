@@ -0,0 +1,63 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use network_simulator::prelude::*;
+use serde_json::{json, Value};
+use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines}, net::{tcp::{OwnedReadHalf, OwnedWriteHalf}, TcpStream}, sync::RwLock};
+
+struct Client{
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client{
+    async fn connect(addr: std::net::SocketAddr) -> Client{
+        let (reader, writer) = TcpStream::connect(addr).await.unwrap().into_split();
+        Client{lines: BufReader::new(reader).lines(), writer}
+    }
+
+    async fn request(&mut self, command: &str) -> Value{
+        self.writer.write_all(format!("{}\n", command).as_bytes()).await.unwrap();
+        let line = self.lines.next_line().await.unwrap().expect("Connection closed before the answer");
+        serde_json::from_str(&line).unwrap()
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_control_server() {
+    let mut network = Network::new(Logger::start_test());
+    for id in 1..=3{
+        network.add_router(&format!("r{}", id), id, 1).unwrap();
+    }
+    network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+    network.add_link("r2", 2, "r3", 1, 1).await.unwrap();
+    network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+
+    let expected: BTreeMap<IPPrefix, RIBEntry> = network.get_routing_table("r1").await.unwrap().into_iter().collect();
+    let network = Arc::new(RwLock::new(network));
+    let addr = Network::serve_control(network.clone(), "127.0.0.1:0").await.unwrap();
+
+    let mut client = Client::connect(addr).await;
+    let table = client.request("show route r1").await;
+    assert_eq!(table, json!({"routing_table": expected}));
+    assert_eq!(table["routing_table"]["10.0.1.3/32"], json!([1, 2, "Ospf"]));
+
+    // two clients querying different routers at the same time
+    let mut other = Client::connect(addr).await;
+    let (r2, r3) = tokio::join!(client.request("show route r2"), other.request("show route r3"));
+    assert_eq!(r2["routing_table"].as_object().unwrap().len(), 3);
+    assert_eq!(r3["routing_table"]["10.0.1.1/32"], json!([1, 2, "Ospf"]));
+
+    // the errors are answered and the connection stays open
+    assert_eq!(client.request("show route r9").await, json!({"error": "Unknown device r9"}));
+    assert_eq!(client.request("ping r1 10.0.1").await, json!({"error": "Invalid command: 10.0.1 is not a valid IP address"}));
+    assert!(client.request("ping r1 10.0.1.3").await["ping"]["rtt_us"].is_u64());
+
+    let done = client.request("fail-link r2 2").await;
+    assert_eq!(done, json!({"done": "Link from r2:2 to r3:1 removed"}));
+    assert_eq!(network.read().await.link_ports("r2", "r3"), None);
+    let done = client.request("add-link r1 r3 4").await;
+    assert_eq!(done, json!({"done": "Link from r1:2 to r3:1 added with cost 4"}));
+    assert_eq!(client.request("log ospf,bgp r1").await, json!({"done": "Logging the messages of OSPF, BGP from r1"}));
+    assert_eq!(client.request("log all").await, json!({"done": "Logging the messages of all sources from all devices"}));
+    assert_eq!(client.request("quit").await, json!({"done": "Bye"}));
+}
@@ -0,0 +1,58 @@
+use network_simulator::{network::scenario::{self, build_network}, prelude::*};
+
+// builds the topology of the example without running its scenario
+async fn dot_string(example: &str) -> String{
+    let config = load_config(&format!("examples/{}.yaml", example)).unwrap();
+    let mut network = Network::new(Logger::start_test());
+    build_network(&mut network, &config).await.unwrap();
+    let dot = network.dot_string();
+    network.quit().await;
+    dot
+}
+
+// the snapshots are regenerated with `cargo run -- graph examples/<example>.yaml > tests/snapshots/<example>.dot`
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_dot_snapshots() {
+    for example in ["bgp-example", "mixing-example"]{
+        let expected = std::fs::read_to_string(format!("tests/snapshots/{}.dot", example)).unwrap();
+        assert_eq!(dot_string(example).await, expected, "DOT graph of {} changed", example);
+        // the graph subcommand draws the config without starting the devices
+        let config = load_config(&format!("examples/{}.yaml", example)).unwrap();
+        assert_eq!(scenario::topology(&config).unwrap().dot_string(), expected, "DOT graph of the config of {} changed", example);
+    }
+}
+
+// a switch without links yet is drawn on its own, outside of the ASes
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_unlinked_switch_snapshot() {
+    let mut network = Network::new(Logger::start_test());
+    network.add_router("r1", 1, 1).unwrap();
+    network.add_router("r2", 2, 1).unwrap();
+    network.add_switch("s1", 3);
+    network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+
+    let dot = network.dot_string();
+    network.quit().await;
+    let expected = std::fs::read_to_string("tests/snapshots/unlinked-switch.dot").unwrap();
+    assert_eq!(dot, expected, "DOT graph with an unlinked switch changed");
+}
+
+// the topology of the spanning tree test of the network, s9 blocks its ports towards s3 and s4 and s6 the one towards s9
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_spanning_tree_snapshot() {
+    let mut network = Network::new(Logger::start_test());
+    for (switch, id) in [("s1", 1), ("s2", 2), ("s3", 3), ("s4", 4), ("s6", 6), ("s9", 9)]{
+        network.add_switch(switch, id);
+    }
+    for (device1, port1, device2, port2) in [("s1", 1, "s2", 1), ("s1", 2, "s4", 1), ("s2", 2, "s9", 1), ("s4", 2, "s9", 2), ("s4", 3, "s3", 1), ("s9", 3, "s3", 2), ("s9", 4, "s6", 1), ("s3", 3, "s6", 2)]{
+        network.add_link(device1, port1, device2, port2, 1).await.unwrap();
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+    let dot = network.dot_spanning_tree().await.unwrap();
+    network.quit().await;
+    let expected = std::fs::read_to_string("tests/snapshots/spanning-tree.dot").unwrap();
+    assert_eq!(dot, expected, "DOT graph of the spanning tree changed");
+    let greyed: Vec<&str> = dot.lines().filter(|line| line.contains("color=lightgrey")).map(|line| line.split('[').next().unwrap().trim()).collect();
+    assert_eq!(greyed, ["s3 -> s9", "s4 -> s9", "s6 -> s9"]);
+}
@@ -0,0 +1,55 @@
+use std::{collections::BTreeMap, net::Ipv4Addr, time::Duration};
+
+use network_simulator::prelude::*;
+
+fn unreachable_pairs(matrix: &BTreeMap<String, BTreeMap<String, bool>>) -> Vec<(String, String)>{
+    matrix.iter()
+        .flat_map(|(from, destinations)| destinations.iter().filter(|(_, r)| !**r).map(move |(to, _)| (from.clone(), to.clone())))
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 5)]
+async fn test_embedded_ring_failure() {
+    let mut network = Network::new(Logger::start_test());
+    for id in 1..=4{
+        network.add_router(&format!("r{}", id), id, 1).unwrap();
+    }
+    network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+    network.add_link("r2", 2, "r3", 1, 1).await.unwrap();
+    network.add_link("r3", 2, "r4", 1, 1).await.unwrap();
+    network.add_link("r4", 2, "r1", 2, 5).await.unwrap();
+
+    tokio::time::sleep(IGP_CONVERGENCE).await;
+    assert_eq!(unreachable_pairs(&network.reachability().await.unwrap()), vec![]);
+    assert_eq!(
+        network.traceroute("r1", Ipv4Addr::new(10, 0, 1, 3)).await.unwrap(),
+        vec![Ipv4Addr::new(10, 0, 1, 2), Ipv4Addr::new(10, 0, 1, 3)]
+    );
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    network.remove_link("r2", 2, "r3", 1).await.unwrap();
+    assert!(network.remove_link("r2", 2, "r3", 1).await.is_err());
+
+    tokio::time::sleep(IGP_CONVERGENCE).await;
+    assert_eq!(unreachable_pairs(&network.reachability().await.unwrap()), vec![]);
+    assert_eq!(
+        network.traceroute("r1", Ipv4Addr::new(10, 0, 1, 3)).await.unwrap(),
+        vec![Ipv4Addr::new(10, 0, 1, 4), Ipv4Addr::new(10, 0, 1, 3)]
+    );
+    assert!(network.ping("r1", Ipv4Addr::new(10, 0, 1, 3)).await.is_ok());
+
+    network.quit().await;
+}
+
+// the loggers don't install a global logger, a program can run several networks one after the other
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_networks_in_one_process() {
+    for logger in [Logger::start(), Logger::start_with_filters(vec![Source::PING]), Logger::start()]{
+        let mut network = Network::new(logger);
+        network.add_router("r1", 1, 1).unwrap();
+        network.add_router("r2", 2, 1).unwrap();
+        network.add_link("r1", 1, "r2", 1, 1).await.unwrap();
+        network.wait_for_ospf(CONVERGENCE_TIMEOUT).await.unwrap();
+        network.quit().await;
+    }
+}
@@ -0,0 +1,51 @@
+use network_simulator::{network::{scenario, schedule}, prelude::*};
+
+const SCENARIO: &str = "
+network:
+  routers:
+    - {name: r1, id: 1, AS: 1}
+    - {name: r2, id: 2, AS: 2}
+  links:
+    bgp:
+      provider-customer:
+        - {provider: r2, customer: r1}
+  events:
+    - {time_ms: 100, announce_prefix: r1}
+    - {time_ms: 600, ping: {from: r2, to: 10.0.1.1}}
+    - {time_ms: 700, remove_link: [r2, r1]}
+    - {wait: 200}
+    - {ping: {from: r2, to: 10.0.1.1}}
+    - {time_ms: 0, print_bgp_tables: true}
+";
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_timed_events_run_in_order() {
+    let config: ConfigFile = SCENARIO.parse().unwrap();
+    let (logger, logs) = Logger::start_capture();
+    let mut network = Network::new(logger);
+    scenario::build_network(&mut network, &config).await.unwrap();
+
+    let events = schedule::load_events(&config).unwrap();
+    schedule::run_events(&mut network, &events).await.unwrap();
+    network.quit().await;
+
+    let messages: Vec<String> = logs.messages().await.into_iter()
+        .filter(|(source, msg)| match source{
+            Source::DEBUG => msg.starts_with("Scenario event"),
+            Source::PING => msg.contains("received ping back"),
+            Source::BGP => msg.contains("announcing"),
+            _ => false,
+        })
+        .map(|(_, msg)| msg)
+        .collect();
+    assert_eq!(messages, vec![
+        "Scenario event at 0ms: print_bgp_tables",
+        "Scenario event at 100ms: announce_prefix r1",
+        "Router r1 announcing its prefix 10.0.1.1 with pref 1000",
+        "Scenario event at 600ms: ping r2 10.0.1.1",
+        "Router r2 received ping back from 10.0.1.1",
+        "Scenario event at 700ms: remove_link r2 r1",
+        "Scenario event at 700ms: wait 200ms",
+        "Scenario event at 700ms: ping r2 10.0.1.1",
+    ]);
+}
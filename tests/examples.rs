@@ -0,0 +1,94 @@
+use network_simulator::{network::{self, examples::EXAMPLES, scenario, schedule}, prelude::*};
+
+async fn run_example(name: &str, yaml: &str){
+    let config: ConfigFile = yaml.parse().unwrap_or_else(|e| panic!("Example {} doesn't parse: {}", name, e));
+    let mut network = Network::new(Logger::start_test());
+    scenario::build_network(&mut network, &config).await.unwrap();
+
+    // the files the example saves are written to the temp dir instead of the working directory
+    let output = |file: &str| std::env::temp_dir().join(format!("{}-{}", std::process::id(), file)).to_string_lossy().into_owned();
+    let events: Vec<schedule::Event> = schedule::load_events(&config).unwrap().into_iter().map(|event| {
+        let action = match event.action{
+            schedule::Action::DotGraphFile(file) => schedule::Action::DotGraphFile(output(&file)),
+            schedule::Action::StateFile(file) => schedule::Action::StateFile(output(&file)),
+            action => action,
+        };
+        schedule::Event{action, ..event}
+    }).collect();
+    if let Err(error) = schedule::run_events(&mut network, &events).await{
+        panic!("Example {} failed: {}", name, error);
+    }
+    // every router of the examples reaches all the others once they ran
+    for (from, destinations) in network.reachability().await.unwrap(){
+        for (to, reachable) in destinations{
+            assert!(reachable, "Example {}: {} has no route towards {}", name, from, to);
+        }
+    }
+    network.quit().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn test_builtin_examples_run() {
+    let runs: Vec<_> = EXAMPLES.iter().map(|(name, yaml)| tokio::spawn(run_example(name, yaml))).collect();
+    for run in runs{
+        run.await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_data_delivered_in_order() {
+    let config: ConfigFile = include_str!("../examples/bgp-example.yaml").parse().unwrap();
+    let network = scenario::run_to_convergence(&config, Logger::start_test()).await.unwrap();
+
+    // r1 reaches r3 through its peer r4, the payloads follow the same path and arrive in order
+    let payloads: Vec<String> = (0..10).map(|i| format!("message {}", i)).collect();
+    for payload in payloads.iter(){
+        network.send_data("r1", "10.0.3.3".parse().unwrap(), payload).await.unwrap();
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let received = network.received_data("r3").await.unwrap();
+    let expected: Vec<(std::net::Ipv4Addr, String)> = payloads.into_iter().map(|payload| ("10.0.1.1".parse().unwrap(), payload)).collect();
+    assert_eq!(received, expected);
+    assert_eq!(network.received_data("r2").await.unwrap(), vec![]);
+    network.quit().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_flow_delivery() {
+    let config: ConfigFile = include_str!("../examples/ospf-example.yaml").parse().unwrap();
+    let mut network = scenario::run_to_convergence(&config, Logger::start_test()).await.unwrap();
+
+    let flow = network.start_flow("r1", "10.0.1.4".parse().unwrap(), 100, std::time::Duration::from_secs(1)).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(1200)).await;
+
+    let stats = network.flow_stats(flow).await.unwrap();
+    assert!(stats.finished);
+    assert_eq!(stats.sent, 100);
+    assert!(stats.received >= 99, "flow {}", stats);
+    assert_eq!(stats.lost, stats.sent - stats.received);
+    assert_eq!(network.flow_stats(flow + 1).await, Err(NetworkError::UnknownFlow(flow + 1)));
+    network.quit().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_json_log_of_example() {
+    let path = std::env::temp_dir().join(format!("log-{}.jsonl", std::process::id()));
+    let path = path.to_str().unwrap();
+    let config: ConfigFile = include_str!("../examples/ibgp-example.yaml").parse().unwrap();
+    let logger = Logger::start_json(std::fs::File::create(path).unwrap());
+    let network = scenario::run_to_convergence(&config, logger).await.unwrap();
+    network.quit().await;
+
+    let records = network::logparse::load(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert!(records.iter().enumerate().all(|(i, record)| record.seq == i as u64));
+    assert!(records.windows(2).all(|pair| pair[0].wall_time_ms <= pair[1].wall_time_ms));
+
+    // iBGP is carried over the routes of OSPF, the routers of the AS find each other before exchanging their routes
+    let first = |source: Source, text: &str| records.iter().find(|r| r.source == source && r.message.contains(text)).map(|r| r.seq);
+    let hello_reply = first(Source::OSPF, "sending hello reply").expect("no OSPF hello reply logged");
+    let ibgp = first(Source::BGP, "iBGP message").expect("no iBGP message logged");
+    assert!(hello_reply < ibgp, "iBGP record {} before the first hello reply {}", ibgp, hello_reply);
+    assert!(records.iter().filter(|r| r.source == Source::BGP).all(|r| r.device.is_some()));
+}
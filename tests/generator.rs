@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+use network_simulator::{network::generator, prelude::*};
+
+// builds a random topology of routers of AS 1 and checks that each of them has a route towards all the others within timeout
+async fn check_reachability(n_routers: usize, seed: u64, timeout: Duration){
+    let config = generator::random_geometric(n_routers, 4.0, seed).unwrap();
+    let network = Network::from_config(&config, Logger::start_test()).await.unwrap();
+    network.wait_for_ospf(timeout).await.unwrap();
+
+    let addresses: Vec<IPPrefix> = (1..=n_routers).map(|id| format!("10.0.1.{}/32", id).parse().unwrap()).collect();
+    for router in network.routers(){
+        let routing_table = network.get_routing_table(&router).await.unwrap();
+        let missing: Vec<&IPPrefix> = addresses.iter().filter(|address| !routing_table.contains_key(address)).collect();
+        assert!(missing.is_empty(), "{} has no route towards {:?}", router, missing);
+    }
+    network.quit().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_random_topology_reachability() {
+    check_reachability(30, 42, Duration::from_secs(20)).await;
+}
+
+// 200 routers, run with `cargo test --release --test generator -- --ignored`
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+#[ignore]
+async fn test_large_random_topology_reachability() {
+    check_reachability(200, 42, Duration::from_secs(60)).await;
+}
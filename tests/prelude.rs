@@ -0,0 +1,15 @@
+use network_simulator::prelude::*;
+
+// stops compiling when an item disappears from the prelude
+#[test]
+fn test_prelude_exports() {
+    let _: Option<(Network, NetworkBuilder, NetworkError, PingError, IPPrefix, Limits, MemoryStats, LogHandle, Logger, Source, OutputMode)> = None;
+    let _: Option<(BGPRoute, BGPSession, RouteSource, SessionState, BGPTableEntry, Difference, NetworkState, PortState, MacAddress, FlowStats, LinkImpairment, LinkStats)> = None;
+    let _: Option<(LinkRate, RateLimit, Capture, CaptureRecord, Direction, Protocol, ConfigFile, PingReport, BgpLinkAttributes)> = None;
+    let _ = (compare_scenarios, load_config, run_to_convergence);
+    assert_eq!(DEFAULT_LOCAL_PREF, 1000);
+    assert!(IGP_CONVERGENCE < IGP_CONVERGENCE + BGP_CONVERGENCE);
+
+    // the prelude is also available from the root of the crate
+    let _: Option<(network_simulator::Network, network_simulator::PortState, network_simulator::RouteSource)> = None;
+}